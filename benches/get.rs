@@ -0,0 +1,142 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Point lookup benchmark. Run with `cargo bench --bench get`.
+//!
+//! The database is loaded so that most keys live in levels holding many files, with a
+//! layer of tombstones on top. Lookups for deleted keys stop at the tombstone, and
+//! lookups outside every file's key range never open a table. The `multi_get` runs
+//! look up the same keys in batches, sharing filter probes and block reads. The
+//! zipfian and hotspot runs concentrate on a few popular keys, as real workloads do.
+//! Single lookups also report their median and 99th percentile latency.
+
+use std::time::Instant;
+
+use rustydb::{
+    db::{db_impl::destroy_db, KeyRange, DB},
+    leveldb::options::{Options, ReadOptions, WriteOptions},
+    util::{
+        bloom::new_bloom_filter_policy,
+        distribution::{Hotspot, KeyDistribution, Zipfian, DEFAULT_ZIPFIAN_THETA},
+        histogram::Histogram,
+    },
+};
+
+const NUM_KEYS: usize = 100_000;
+const NUM_READS: usize = 200_000;
+
+fn key(i: usize) -> String { format!("key{:010}", i * 2) }
+
+fn bench(db: &DB, name: &str, make_key: impl Fn(usize) -> String, expect_found: bool) {
+    let options = ReadOptions::default();
+    let mut latencies = Histogram::new();
+    let start = Instant::now();
+    let mut found = 0;
+    for i in 0..NUM_READS {
+        // Spread the lookups over the key space with a multiplicative hash.
+        let k = make_key(i.wrapping_mul(2654435761) % NUM_KEYS);
+        let op_start = Instant::now();
+        if db.get(&options, k.as_bytes()).unwrap().is_some() {
+            found += 1;
+        }
+        latencies.add(op_start.elapsed().as_secs_f64() * 1e6);
+    }
+    let elapsed = start.elapsed();
+    assert_eq!(found > 0, expect_found);
+    println!(
+        "{:<17} : {:8.3} micros/op; {} of {} found; p50 {:.3} p99 {:.3} micros",
+        name,
+        elapsed.as_secs_f64() * 1e6 / NUM_READS as f64,
+        found,
+        NUM_READS,
+        latencies.median(),
+        latencies.p99()
+    );
+}
+
+fn bench_multi_get(db: &DB, name: &str, make_key: impl Fn(usize) -> String, batch_size: usize) {
+    let options = ReadOptions::default();
+    let start = Instant::now();
+    let mut found = 0;
+    for batch in 0..NUM_READS / batch_size {
+        let keys: Vec<String> = (batch * batch_size..(batch + 1) * batch_size)
+            .map(|i| make_key(i.wrapping_mul(2654435761) % NUM_KEYS))
+            .collect();
+        let refs: Vec<&[u8]> = keys.iter().map(|k| k.as_bytes()).collect();
+        found += db
+            .multi_get(&options, &refs)
+            .into_iter()
+            .filter(|r| r.as_ref().unwrap().is_some())
+            .count();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<17} : {:8.3} micros/op; {} of {} found (batches of {})",
+        name,
+        elapsed.as_secs_f64() * 1e6 / NUM_READS as f64,
+        found,
+        NUM_READS,
+        batch_size
+    );
+}
+
+fn main() {
+    let dbname = std::env::temp_dir()
+        .join("rustydb-bench-get")
+        .to_string_lossy()
+        .into_owned();
+    let options = Options {
+        create_if_missing: true,
+        filter_policy: Some(new_bloom_filter_policy(10)),
+        ..Options::default()
+    };
+    let _ = destroy_db(&dbname, &options);
+    let db = DB::open(options.clone(), &dbname).unwrap();
+
+    let wo = WriteOptions::default();
+    let value = vec![b'x'; 100];
+    for i in 0..NUM_KEYS {
+        db.put(&wo, key(i).as_bytes(), &value).unwrap();
+    }
+    db.compact_range(&KeyRange::all()).unwrap();
+    for i in (0..NUM_KEYS).step_by(10) {
+        db.delete(&wo, key(i).as_bytes()).unwrap();
+    }
+    // Reopen so that the tombstones are flushed to a table by log recovery.
+    drop(db);
+    let db = DB::open(options.clone(), &dbname).unwrap();
+
+    bench(&db, "hit", key, true);
+    let zipfian = Zipfian::scrambled(NUM_KEYS as u64, DEFAULT_ZIPFIAN_THETA, 301);
+    bench(&db, "hit zipfian", |_| key(zipfian.next() as usize), true);
+    let hotspot = Hotspot::new(NUM_KEYS as u64, 0.01, 0.9, 301);
+    bench(&db, "hit hotspot", |_| key(hotspot.next() as usize), true);
+    bench(&db, "deleted", |i| key(i - i % 10), false);
+    bench(&db, "miss in range", |i| format!("key{:010}", i * 2 + 1), false);
+    bench(&db, "miss out of range", |i| format!("zzz{:010}", i), false);
+    for batch_size in [10, 100, 1000] {
+        bench_multi_get(&db, "multi_get hit", key, batch_size);
+        bench_multi_get(&db, "multi_get miss", |i| format!("key{:010}", i * 2 + 1), batch_size);
+    }
+
+    drop(db);
+    let _ = destroy_db(&dbname, &options);
+}
@@ -0,0 +1,168 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! io_uring against pread benchmark. Run with
+//! `cargo bench --bench io_uring --features io_uring`.
+//!
+//! Compares `UringEnv` with `PosixEnv`, whose memory mapping of tables is turned off
+//! so that both read tables with system calls: random block-sized reads of a file
+//! from several threads, synced appends to a log file, and random lookups in a
+//! database larger than its block cache. Put the temporary directory on the device
+//! to measure, with `TMPDIR`, and use a file larger than the page cache to measure
+//! the device rather than the cache.
+
+use std::{sync::Arc, thread, time::Instant};
+
+use rustydb::{
+    db::{db_impl::destroy_db, KeyRange, DB},
+    leveldb::{
+        env::{Env, RandomAccessFile},
+        options::{new_block_cache, Options, ReadOptions, WriteOptions},
+    },
+    util::{
+        env_posix::{set_mmap_limit, PosixEnv},
+        env_uring::UringEnv,
+        random::Random,
+    },
+};
+
+const FILE_SIZE: u64 = 256 << 20;
+const BLOCK_SIZE: usize = 4096;
+const READS_PER_THREAD: usize = 50_000;
+const THREADS: usize = 8;
+const SYNCED_APPENDS: usize = 2_000;
+const NUM_KEYS: usize = 500_000;
+const NUM_LOOKUPS: usize = 200_000;
+
+fn report(name: &str, env: &str, ops: usize, start: Instant) {
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "{:<16} {:<6} : {:8.3} micros/op; {:10.0} ops/sec",
+        name,
+        env,
+        elapsed * 1e6 / ops as f64,
+        ops as f64 / elapsed
+    );
+}
+
+fn bench_random_reads(env: &Arc<dyn Env>, name: &str, fname: &str) {
+    let file: Arc<dyn RandomAccessFile> = env.new_random_access_file(fname).unwrap().into();
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for t in 0..THREADS {
+            let file = file.clone();
+            scope.spawn(move || {
+                let rnd = Random::new(301 + t as u32);
+                let mut buf = vec![0; BLOCK_SIZE];
+                let blocks = (FILE_SIZE / BLOCK_SIZE as u64) as u32;
+                for _ in 0..READS_PER_THREAD {
+                    let offset = rnd.uniform(blocks) as u64 * BLOCK_SIZE as u64;
+                    assert_eq!(file.read(offset, &mut buf).unwrap(), BLOCK_SIZE);
+                }
+            });
+        }
+    });
+    report("random reads", name, READS_PER_THREAD * THREADS, start);
+}
+
+fn bench_synced_appends(env: &Arc<dyn Env>, name: &str, dir: &str) {
+    let fname = format!("{}/000001.log", dir);
+    let mut file = env.new_writable_file(&fname).unwrap();
+    let record = vec![b'x'; 200];
+    let start = Instant::now();
+    for _ in 0..SYNCED_APPENDS {
+        file.append(&record).unwrap();
+        file.sync().unwrap();
+    }
+    report("synced appends", name, SYNCED_APPENDS, start);
+    file.close().unwrap();
+    env.remove_file(&fname).unwrap();
+}
+
+fn bench_lookups(env: &Arc<dyn Env>, name: &str, dir: &str) {
+    let dbname = format!("{}/db", dir);
+    let options = Options {
+        env: env.clone(),
+        create_if_missing: true,
+        block_cache: Some(new_block_cache(1 << 20)),
+        ..Options::default()
+    };
+    let _ = destroy_db(&dbname, &options);
+    let db = DB::open(options.clone(), &dbname).unwrap();
+    let wo = WriteOptions::default();
+    let value = vec![b'v'; 100];
+    for i in 0..NUM_KEYS {
+        db.put(&wo, format!("key{:010}", i).as_bytes(), &value).unwrap();
+    }
+    db.compact_range(&KeyRange::all()).unwrap();
+
+    let ro = ReadOptions::default();
+    let rnd = Random::new(301);
+    let start = Instant::now();
+    for _ in 0..NUM_LOOKUPS {
+        let key = format!("key{:010}", rnd.uniform(NUM_KEYS as u32));
+        assert!(db.get(&ro, key.as_bytes()).unwrap().is_some());
+    }
+    report("db lookups", name, NUM_LOOKUPS, start);
+    drop(db);
+    let _ = destroy_db(&dbname, &options);
+}
+
+fn main() {
+    let uring: Arc<dyn Env> = match UringEnv::new() {
+        Ok(env) => Arc::new(env),
+        Err(err) => {
+            println!("skipped: {}", err);
+            return;
+        }
+    };
+    let posix: Arc<dyn Env> = Arc::new(PosixEnv);
+    set_mmap_limit(0);
+
+    let dir = std::env::temp_dir()
+        .join("rustydb-bench-io-uring")
+        .to_string_lossy()
+        .into_owned();
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let fname = format!("{}/data", dir);
+    {
+        let mut file = posix.new_writable_file(&fname).unwrap();
+        let chunk = vec![b'x'; 1 << 20];
+        for _ in 0..FILE_SIZE / chunk.len() as u64 {
+            file.append(&chunk).unwrap();
+        }
+        file.close().unwrap();
+    }
+
+    for (env, name) in [(&posix, "pread"), (&uring, "uring")] {
+        bench_random_reads(env, name, &fname);
+    }
+    for (env, name) in [(&posix, "posix"), (&uring, "uring")] {
+        bench_synced_appends(env, name, &dir);
+    }
+    for (env, name) in [(&posix, "posix"), (&uring, "uring")] {
+        bench_lookups(env, name, &dir);
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
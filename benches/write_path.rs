@@ -0,0 +1,273 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Write path and point lookup benchmarks. Run with `cargo bench --bench write_path`.
+//!
+//! Each case runs its workload `ROUNDS` times after a warm-up round and reports the
+//! median, so that single slow rounds do not move the result. Keys and values come
+//! from a fixed seed, so every run measures the same work.
+//!
+//! To compare against an earlier run, save its results with
+//! `RUSTYDB_BENCH_SAVE=<file>` and pass the file as `RUSTYDB_BENCH_BASELINE=<file>`
+//! to a later run. Cases more than `REGRESSION_THRESHOLD` slower than the baseline
+//! are reported, and the run exits with a failure status.
+
+use std::{collections::HashMap, time::Instant};
+
+use rustydb::{
+    db::{
+        db_impl::destroy_db,
+        dbformat::{InternalKeyComparator, ValueType},
+        log_writer,
+        memtable::MemTable,
+        write_batch::WriteBatch,
+        KeyRange, DB,
+    },
+    leveldb::{
+        env::default_env,
+        options::{new_block_cache, Options, ReadOptions, WriteOptions},
+    },
+    table::block_builder::BlockBuilder,
+    util::{bloom::new_bloom_filter_policy, comparator::bytewise_comparator, random::Random},
+};
+
+const ROUNDS: usize = 5;
+const REGRESSION_THRESHOLD: f64 = 0.10;
+
+const KEY_SIZE: usize = 16;
+const VALUE_SIZE: usize = 100;
+const NUM_KEYS: usize = 100_000;
+
+/// Keys in a fixed pseudo-random order, and a value for each.
+struct Fixture {
+    keys: Vec<Vec<u8>>,
+    values: Vec<Vec<u8>>,
+}
+
+impl Fixture {
+    fn new(n: usize) -> Self {
+        let rnd = Random::new(301);
+        let keys = (0..n)
+            .map(|_| format!("{:0width$}", rnd.uniform(n as u32 * 10), width = KEY_SIZE).into_bytes())
+            .collect();
+        let values = (0..n)
+            .map(|_| (0..VALUE_SIZE).map(|_| b' ' + rnd.uniform(95) as u8).collect())
+            .collect();
+        Self { keys, values }
+    }
+
+    fn sorted_keys(&self) -> Vec<Vec<u8>> {
+        let mut keys = self.keys.clone();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+}
+
+struct Suite {
+    results: Vec<(String, f64)>,
+}
+
+impl Suite {
+    /// Time `round`, which performs `ops` operations, and record the median micros/op.
+    fn run(&mut self, name: &str, ops: usize, mut round: impl FnMut()) {
+        round();
+        let mut samples: Vec<f64> = (0..ROUNDS)
+            .map(|_| {
+                let start = Instant::now();
+                round();
+                start.elapsed().as_secs_f64() * 1e6 / ops as f64
+            })
+            .collect();
+        samples.sort_by(|a, b| a.total_cmp(b));
+        let median = samples[ROUNDS / 2];
+        println!("{:<24} : {:8.3} micros/op", name, median);
+        self.results.push((name.to_string(), median));
+    }
+
+    /// Save the results to and compare them with the files named by the environment.
+    /// Returns false if a case regressed.
+    fn finish(&self) -> bool {
+        if let Ok(path) = std::env::var("RUSTYDB_BENCH_SAVE") {
+            let contents: String = self.results.iter().map(|(name, v)| format!("{}\t{}\n", name, v)).collect();
+            std::fs::write(&path, contents).expect("save results");
+        }
+        let path = match std::env::var("RUSTYDB_BENCH_BASELINE") {
+            Ok(path) => path,
+            Err(_) => return true,
+        };
+        let contents = std::fs::read_to_string(&path).expect("read baseline");
+        let baseline: HashMap<&str, f64> = contents
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .filter_map(|(name, v)| Some((name, v.parse().ok()?)))
+            .collect();
+        let mut ok = true;
+        println!();
+        for (name, value) in self.results.iter() {
+            if let Some(&base) = baseline.get(name.as_str()) {
+                let change = value / base - 1.0;
+                let regressed = change > REGRESSION_THRESHOLD;
+                ok &= !regressed;
+                println!(
+                    "{:<24} : {:+7.1}% vs baseline{}",
+                    name,
+                    change * 100.0,
+                    if regressed { "  REGRESSION" } else { "" }
+                );
+            }
+        }
+        ok
+    }
+}
+
+fn bench_write_batch(suite: &mut Suite, fixture: &Fixture) {
+    const BATCH_SIZE: usize = 100;
+    suite.run("write_batch encode", NUM_KEYS, || {
+        for (keys, values) in fixture.keys.chunks(BATCH_SIZE).zip(fixture.values.chunks(BATCH_SIZE)) {
+            let mut batch = WriteBatch::new();
+            for (k, v) in keys.iter().zip(values) {
+                batch.put(k, v);
+            }
+            std::hint::black_box(batch.serialize());
+        }
+    });
+}
+
+fn bench_wal(suite: &mut Suite, fixture: &Fixture, dir: &str) {
+    let env = default_env();
+    let fname = format!("{}/bench.log", dir);
+    let mut record = Vec::new();
+    for (k, v) in fixture.keys.iter().zip(fixture.values.iter()).take(8) {
+        record.extend_from_slice(k);
+        record.extend_from_slice(v);
+    }
+
+    let records = 10_000;
+    suite.run("wal append", records, || {
+        let mut log = log_writer::Writer::new(env.new_writable_file(&fname).unwrap());
+        for _ in 0..records {
+            log.add_record(&record).unwrap();
+        }
+        log.close().unwrap();
+    });
+
+    let records = 200;
+    suite.run("wal append+sync", records, || {
+        let mut log = log_writer::Writer::new(env.new_writable_file(&fname).unwrap());
+        for _ in 0..records {
+            log.add_record(&record).unwrap();
+            log.sync().unwrap();
+        }
+        log.close().unwrap();
+    });
+    let _ = env.remove_file(&fname);
+}
+
+fn bench_memtable(suite: &mut Suite, fixture: &Fixture) {
+    suite.run("memtable insert", NUM_KEYS, || {
+        let mem = MemTable::new(InternalKeyComparator::new(bytewise_comparator()));
+        for (seq, (k, v)) in fixture.keys.iter().zip(fixture.values.iter()).enumerate() {
+            mem.add(seq as u64 + 1, ValueType::Value, k, v);
+        }
+        std::hint::black_box(mem.approximate_memory_usage());
+    });
+}
+
+fn bench_block_build(suite: &mut Suite, fixture: &Fixture) {
+    let keys = fixture.sorted_keys();
+    suite.run("block build", keys.len(), || {
+        let mut builder = BlockBuilder::new(16, bytewise_comparator());
+        for (k, v) in keys.iter().zip(fixture.values.iter()) {
+            builder.add(k, v);
+            if builder.current_size_estimate() >= 4096 {
+                std::hint::black_box(builder.finish());
+                builder.reset();
+            }
+        }
+        std::hint::black_box(builder.finish());
+    });
+}
+
+fn bench_get(suite: &mut Suite, fixture: &Fixture, dir: &str) {
+    let dbname = format!("{}/db", dir);
+    let load = |block_cache_entries: usize| {
+        let options = Options {
+            create_if_missing: true,
+            filter_policy: Some(new_bloom_filter_policy(10)),
+            block_cache: Some(new_block_cache(block_cache_entries)),
+            ..Options::default()
+        };
+        DB::open(options, &dbname).unwrap()
+    };
+
+    let _ = destroy_db(&dbname, &Options::default());
+    {
+        let db = load(1);
+        let wo = WriteOptions::default();
+        for (k, v) in fixture.keys.iter().zip(fixture.values.iter()) {
+            db.put(&wo, k, v).unwrap();
+        }
+        db.compact_range(&KeyRange::all()).unwrap();
+    }
+
+    let reads: usize = 100_000;
+    let read_options = ReadOptions::default();
+    let lookups = |db: &DB| {
+        for i in 0..reads {
+            let k = &fixture.keys[i.wrapping_mul(2654435761) % fixture.keys.len()];
+            assert!(db.get(&read_options, k).unwrap().is_some());
+        }
+    };
+
+    // Every block fits in the cache, and the warm-up round loads them all.
+    let db = load(1 << 16);
+    suite.run("get hot cache", reads, || lookups(&db));
+    drop(db);
+
+    // A single-entry cache: nearly every lookup reads its block from the file.
+    let db = load(1);
+    suite.run("get cold cache", reads, || lookups(&db));
+    drop(db);
+    let _ = destroy_db(&dbname, &Options::default());
+}
+
+fn main() {
+    let dir = std::env::temp_dir()
+        .join("rustydb-bench-write-path")
+        .to_string_lossy()
+        .into_owned();
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let fixture = Fixture::new(NUM_KEYS);
+    let mut suite = Suite { results: Vec::new() };
+    bench_write_batch(&mut suite, &fixture);
+    bench_wal(&mut suite, &fixture, &dir);
+    bench_memtable(&mut suite, &fixture);
+    bench_block_build(&mut suite, &fixture);
+    bench_get(&mut suite, &fixture, &dir);
+
+    let _ = std::fs::remove_dir_all(&dir);
+    if !suite.finish() {
+        std::process::exit(1);
+    }
+}
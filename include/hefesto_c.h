@@ -0,0 +1,156 @@
+/*
+ * MIT License
+ *
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+/* C API of hefestodb, modeled on the leveldb and rocksdb C APIs. Link against the
+ * cdylib built by cargo (librustydb.so).
+ *
+ * Objects are opaque pointers created and destroyed with the matching functions.
+ * A function that can fail takes a `char** errptr`: on failure it stores a malloc()ed
+ * error message there, freeing any message already stored, and leaves it alone on
+ * success. Strings and values returned by the API are malloc()ed and are released
+ * with hefesto_free(). A DB must outlive its iterators.
+ */
+
+#ifndef HEFESTO_C_H_
+#define HEFESTO_C_H_
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct hefesto_db_t hefesto_db_t;
+typedef struct hefesto_iterator_t hefesto_iterator_t;
+typedef struct hefesto_writebatch_t hefesto_writebatch_t;
+typedef struct hefesto_snapshot_t hefesto_snapshot_t;
+typedef struct hefesto_cache_t hefesto_cache_t;
+typedef struct hefesto_filterpolicy_t hefesto_filterpolicy_t;
+typedef struct hefesto_options_t hefesto_options_t;
+typedef struct hefesto_readoptions_t hefesto_readoptions_t;
+typedef struct hefesto_writeoptions_t hefesto_writeoptions_t;
+
+/* DB operations */
+
+hefesto_db_t* hefesto_open(const hefesto_options_t* options, const char* name, char** errptr);
+void hefesto_close(hefesto_db_t* db);
+void hefesto_put(hefesto_db_t* db, const hefesto_writeoptions_t* options, const char* key, size_t keylen,
+                 const char* val, size_t vallen, char** errptr);
+void hefesto_delete(hefesto_db_t* db, const hefesto_writeoptions_t* options, const char* key, size_t keylen,
+                    char** errptr);
+/* The batch keeps its updates and may be written again. */
+void hefesto_write(hefesto_db_t* db, const hefesto_writeoptions_t* options, hefesto_writebatch_t* batch,
+                   char** errptr);
+/* Returns NULL if not found or on error. Otherwise *vallen holds the length of the
+ * returned malloc()ed value. */
+char* hefesto_get(hefesto_db_t* db, const hefesto_readoptions_t* options, const char* key, size_t keylen,
+                  size_t* vallen, char** errptr);
+hefesto_iterator_t* hefesto_create_iterator(hefesto_db_t* db, const hefesto_readoptions_t* options);
+const hefesto_snapshot_t* hefesto_create_snapshot(hefesto_db_t* db);
+void hefesto_release_snapshot(hefesto_db_t* db, const hefesto_snapshot_t* snapshot);
+/* Returns NULL if the property is unknown. Otherwise returns a malloc()ed string. */
+char* hefesto_property_value(hefesto_db_t* db, const char* propname);
+/* A NULL key stands for the start or the end of the key space. */
+void hefesto_compact_range(hefesto_db_t* db, const char* start_key, size_t start_key_len, const char* limit_key,
+                           size_t limit_key_len, char** errptr);
+
+/* Management operations */
+
+void hefesto_destroy_db(const hefesto_options_t* options, const char* name, char** errptr);
+void hefesto_repair_db(const hefesto_options_t* options, const char* name, char** errptr);
+
+/* Iterator */
+
+void hefesto_iter_destroy(hefesto_iterator_t* iter);
+uint8_t hefesto_iter_valid(const hefesto_iterator_t* iter);
+void hefesto_iter_seek_to_first(hefesto_iterator_t* iter);
+void hefesto_iter_seek_to_last(hefesto_iterator_t* iter);
+void hefesto_iter_seek(hefesto_iterator_t* iter, const char* k, size_t klen);
+void hefesto_iter_next(hefesto_iterator_t* iter);
+void hefesto_iter_prev(hefesto_iterator_t* iter);
+/* Valid until the iterator is moved or destroyed. */
+const char* hefesto_iter_key(const hefesto_iterator_t* iter, size_t* klen);
+const char* hefesto_iter_value(const hefesto_iterator_t* iter, size_t* vlen);
+void hefesto_iter_get_error(const hefesto_iterator_t* iter, char** errptr);
+
+/* Write batch */
+
+hefesto_writebatch_t* hefesto_writebatch_create(void);
+void hefesto_writebatch_destroy(hefesto_writebatch_t* batch);
+void hefesto_writebatch_clear(hefesto_writebatch_t* batch);
+void hefesto_writebatch_put(hefesto_writebatch_t* batch, const char* key, size_t klen, const char* val, size_t vlen);
+void hefesto_writebatch_delete(hefesto_writebatch_t* batch, const char* key, size_t klen);
+
+/* Options */
+
+enum {
+  hefesto_no_compression = 0,
+  hefesto_lz4_compression = 4,
+  hefesto_zstd_compression = 7
+};
+
+hefesto_options_t* hefesto_options_create(void);
+void hefesto_options_destroy(hefesto_options_t* options);
+void hefesto_options_set_create_if_missing(hefesto_options_t* options, uint8_t v);
+void hefesto_options_set_error_if_exists(hefesto_options_t* options, uint8_t v);
+void hefesto_options_set_paranoid_checks(hefesto_options_t* options, uint8_t v);
+void hefesto_options_set_write_buffer_size(hefesto_options_t* options, size_t size);
+void hefesto_options_set_max_open_files(hefesto_options_t* options, int n);
+void hefesto_options_set_cache(hefesto_options_t* options, hefesto_cache_t* cache);
+void hefesto_options_set_block_size(hefesto_options_t* options, size_t size);
+void hefesto_options_set_block_restart_interval(hefesto_options_t* options, int n);
+void hefesto_options_set_max_file_size(hefesto_options_t* options, size_t size);
+/* Other values than the hefesto_*_compression ones leave the compression unchanged. */
+void hefesto_options_set_compression(hefesto_options_t* options, int compression);
+/* A NULL policy turns filters off. */
+void hefesto_options_set_filter_policy(hefesto_options_t* options, hefesto_filterpolicy_t* policy);
+
+hefesto_cache_t* hefesto_cache_create_lru(size_t capacity);
+void hefesto_cache_destroy(hefesto_cache_t* cache);
+
+hefesto_filterpolicy_t* hefesto_filterpolicy_create_bloom(int bits_per_key);
+void hefesto_filterpolicy_destroy(hefesto_filterpolicy_t* policy);
+
+hefesto_readoptions_t* hefesto_readoptions_create(void);
+void hefesto_readoptions_destroy(hefesto_readoptions_t* options);
+void hefesto_readoptions_set_verify_checksums(hefesto_readoptions_t* options, uint8_t v);
+void hefesto_readoptions_set_fill_cache(hefesto_readoptions_t* options, uint8_t v);
+/* A NULL snapshot reads the latest state. */
+void hefesto_readoptions_set_snapshot(hefesto_readoptions_t* options, const hefesto_snapshot_t* snapshot);
+
+hefesto_writeoptions_t* hefesto_writeoptions_create(void);
+void hefesto_writeoptions_destroy(hefesto_writeoptions_t* options);
+void hefesto_writeoptions_set_sync(hefesto_writeoptions_t* options, uint8_t v);
+
+/* Utility */
+
+void hefesto_free(void* ptr);
+int hefesto_major_version(void);
+int hefesto_minor_version(void);
+
+#ifdef __cplusplus
+} /* end extern "C" */
+#endif
+
+#endif /* HEFESTO_C_H_ */
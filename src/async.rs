@@ -0,0 +1,400 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+// An async front end for DB. Every call that may read or write files runs as a task on
+// a blocking thread pool supplied by the async runtime, so that disk I/O never blocks
+// the executor. The module depends on no runtime: with tokio, the spawner is
+// `|task| { tokio::task::spawn_blocking(task); }`.
+
+use std::{
+    collections::VecDeque,
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    db::{write_batch::WriteBatch, DB},
+    leveldb::{
+        options::{ReadOptions, WriteOptions},
+        result::Result,
+    },
+};
+
+// An AsyncIterator reads ahead this many entries, or this many bytes of keys and
+// values, with each blocking task.
+const ITERATOR_BATCH_ENTRIES: usize = 256;
+const ITERATOR_BATCH_BYTES: usize = 1 << 20;
+
+/// A unit of blocking work handed to a `BlockingSpawner`.
+pub type BlockingTask = Box<dyn FnOnce() + Send>;
+
+/// Runs blocking work outside of the async executor, usually on the blocking thread
+/// pool of the runtime. Closures taking a `BlockingTask` implement this trait.
+///
+/// A task that is dropped without being run, e.g. because the runtime shuts down,
+/// fails the call that spawned it with an `Aborted` error.
+pub trait BlockingSpawner: Send + Sync {
+    fn spawn_blocking(&self, task: BlockingTask);
+}
+
+impl<F: Fn(BlockingTask) + Send + Sync> BlockingSpawner for F {
+    fn spawn_blocking(&self, task: BlockingTask) { self(task) }
+}
+
+struct Slot<T> {
+    result: Option<Result<T>>,
+    waker: Option<Waker>,
+}
+
+// The sending half of a BlockingFuture, moved into the blocking task.
+struct Sender<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Sender<T> {
+    fn send(self, result: Result<T>) { self.slot.lock().unwrap().result = Some(result) }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut slot = self.slot.lock().unwrap();
+        if slot.result.is_none() {
+            slot.result = Some(LEVELDB_ERR!(Aborted, "blocking task dropped before it ran"));
+        }
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The result of a call running on a blocking task.
+pub struct BlockingFuture<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Future for BlockingFuture<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<T>> {
+        let mut slot = self.slot.lock().unwrap();
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn spawn<T, F>(spawner: &dyn BlockingSpawner, db: &Arc<DB>, f: F) -> BlockingFuture<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&DB) -> Result<T> + Send + 'static,
+{
+    let slot = Arc::new(Mutex::new(Slot {
+        result: None,
+        waker: None,
+    }));
+    let sender = Sender { slot: slot.clone() };
+    let db = db.clone();
+    spawner.spawn_blocking(Box::new(move || sender.send(f(&db))));
+    BlockingFuture { slot }
+}
+
+/// A DB with async methods. Cloning an AsyncDB is cheap; the clones share the DB.
+#[derive(Clone)]
+pub struct AsyncDB {
+    db: Arc<DB>,
+    spawner: Arc<dyn BlockingSpawner>,
+}
+
+impl AsyncDB {
+    pub fn new(db: Arc<DB>, spawner: impl BlockingSpawner + 'static) -> Self {
+        Self {
+            db,
+            spawner: Arc::new(spawner),
+        }
+    }
+
+    /// The underlying DB, for calls that have no async version.
+    pub fn db(&self) -> &Arc<DB> { &self.db }
+
+    /// Async version of `DB::get()`.
+    pub async fn get(&self, options: &ReadOptions, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let (options, key) = (options.clone(), key.to_vec());
+        spawn(self.spawner.as_ref(), &self.db, move |db| db.get(&options, &key)).await
+    }
+
+    /// Async version of `DB::put()`.
+    pub async fn put(&self, options: &WriteOptions, key: &[u8], value: &[u8]) -> Result<()> {
+        let (options, key, value) = (*options, key.to_vec(), value.to_vec());
+        spawn(self.spawner.as_ref(), &self.db, move |db| db.put(&options, &key, &value)).await
+    }
+
+    /// Async version of `DB::write()`.
+    pub async fn write(&self, options: &WriteOptions, updates: WriteBatch) -> Result<()> {
+        let options = *options;
+        spawn(self.spawner.as_ref(), &self.db, move |db| db.write(&options, updates)).await
+    }
+
+    /// Async version of `DB::multi_get()`. All keys are looked up by a single blocking
+    /// task. If that task is dropped, every key fails with its error.
+    pub async fn multi_get(&self, options: &ReadOptions, keys: &[&[u8]]) -> Vec<Result<Option<Vec<u8>>>> {
+        let options = options.clone();
+        let owned: Vec<Vec<u8>> = keys.iter().map(|key| key.to_vec()).collect();
+        let task = spawn(self.spawner.as_ref(), &self.db, move |db| {
+            let keys: Vec<&[u8]> = owned.iter().map(Vec::as_slice).collect();
+            Ok(db.multi_get(&options, &keys))
+        });
+        match task.await {
+            Ok(results) => results,
+            Err(err) => keys.iter().map(|_| Err(err.clone())).collect(),
+        }
+    }
+
+    /// Return an iterator over the contents of the database, starting at the first key.
+    /// Unless `options.snapshot` is set, the iterator reads from a snapshot taken now.
+    pub fn new_iterator(&self, options: &ReadOptions) -> AsyncIterator {
+        let mut options = options.clone();
+        if options.snapshot.is_none() {
+            options.snapshot = Some(self.db.get_snapshot());
+        }
+        AsyncIterator {
+            db: self.db.clone(),
+            spawner: self.spawner.clone(),
+            options,
+            start: None,
+            buffered: VecDeque::new(),
+            exhausted: false,
+            pending: None,
+        }
+    }
+}
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// Where the next batch of an AsyncIterator starts.
+#[derive(Clone)]
+enum Start {
+    At(Vec<u8>),
+    After(Vec<u8>),
+}
+
+/// A forward iterator over the entries of an AsyncDB. Entries are read in batches, each
+/// by one blocking task, and handed out from memory in between. `poll_next()` has the
+/// signature of `futures::Stream::poll_next()`, so the iterator can be wrapped into a
+/// stream for any runtime.
+pub struct AsyncIterator {
+    db: Arc<DB>,
+    spawner: Arc<dyn BlockingSpawner>,
+    options: ReadOptions,
+    start: Option<Start>,
+    buffered: VecDeque<Entry>,
+    exhausted: bool,
+    pending: Option<BlockingFuture<(Vec<Entry>, bool)>>,
+}
+
+impl AsyncIterator {
+    /// Position the iterator at the first key that is at or past `target`.
+    pub fn seek(&mut self, target: &[u8]) {
+        self.start = Some(Start::At(target.to_vec()));
+        self.buffered.clear();
+        self.exhausted = false;
+        self.pending = None;
+    }
+
+    /// Return the next entry, or `None` once the iterator has passed the last key. An
+    /// error ends the iteration.
+    pub async fn next(&mut self) -> Option<Result<Entry>> { poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await }
+
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Entry>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(entry) = this.buffered.pop_front() {
+                return Poll::Ready(Some(Ok(entry)));
+            }
+            if this.exhausted {
+                return Poll::Ready(None);
+            }
+            let pending = match this.pending.as_mut() {
+                Some(pending) => pending,
+                None => this.pending.insert(this.read_batch()),
+            };
+            let (entries, exhausted) = match Pin::new(pending).poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(result) => {
+                    this.pending = None;
+                    match result {
+                        Ok(batch) => batch,
+                        Err(err) => {
+                            this.exhausted = true;
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                    }
+                }
+            };
+            if let Some((key, _)) = entries.last() {
+                this.start = Some(Start::After(key.clone()));
+            }
+            this.buffered.extend(entries);
+            this.exhausted = exhausted;
+        }
+    }
+
+    fn read_batch(&self) -> BlockingFuture<(Vec<Entry>, bool)> {
+        let (options, start) = (self.options.clone(), self.start.clone());
+        spawn(self.spawner.as_ref(), &self.db, move |db| {
+            let mut iter = db.new_iterator(&options);
+            match &start {
+                None => iter.seek_to_first(),
+                Some(Start::At(target)) => iter.seek(target),
+                Some(Start::After(key)) => {
+                    iter.seek(key);
+                    if iter.valid() && iter.key() == key.as_slice() {
+                        iter.next();
+                    }
+                }
+            }
+            let (mut entries, mut bytes) = (Vec::new(), 0);
+            while iter.valid() && entries.len() < ITERATOR_BATCH_ENTRIES && bytes < ITERATOR_BATCH_BYTES {
+                bytes += iter.key().len() + iter.value().len();
+                entries.push((iter.key().to_vec(), iter.value().to_vec()));
+                iter.next();
+            }
+            iter.status()?;
+            Ok((entries, !iter.valid()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        task::Wake,
+        thread::{self, Thread},
+    };
+
+    use super::*;
+    use crate::{leveldb::options::Options, util::testutil::tmp_dir};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) { self.0.unpark() }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    fn open(dir: &str) -> AsyncDB {
+        let options = Options {
+            create_if_missing: true,
+            ..Default::default()
+        };
+        let db = DB::open(options, dir).unwrap();
+        AsyncDB::new(Arc::new(db), |task: BlockingTask| {
+            thread::spawn(task);
+        })
+    }
+
+    #[test]
+    fn reads_and_writes() {
+        let dir = tmp_dir("async_reads_and_writes");
+        let db = open(&dir);
+        // Multi-threaded runtimes require futures that can move between threads.
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&db.get(&ReadOptions::default(), b"a"));
+        assert_send(&db.new_iterator(&ReadOptions::default()).next());
+        block_on(async {
+            let wo = WriteOptions::default();
+            db.put(&wo, b"a", b"1").await.unwrap();
+            let mut batch = WriteBatch::new();
+            batch.put(b"b", b"2");
+            batch.delete(b"a");
+            db.write(&wo, batch).await.unwrap();
+
+            let ro = ReadOptions::default();
+            assert_eq!(db.get(&ro, b"a").await.unwrap(), None);
+            assert_eq!(db.get(&ro, b"b").await.unwrap(), Some(b"2".to_vec()));
+            let results = db.multi_get(&ro, &[b"a", b"b", b"c"]).await;
+            let results: Vec<_> = results.into_iter().map(Result::unwrap).collect();
+            assert_eq!(results, vec![None, Some(b"2".to_vec()), None]);
+        });
+    }
+
+    #[test]
+    fn iterator() {
+        let dir = tmp_dir("async_iterator");
+        let db = open(&dir);
+        let wo = WriteOptions::default();
+        for i in 0..1000 {
+            db.db().put(&wo, format!("{:04}", i).as_bytes(), b"v").unwrap();
+        }
+        let mut iter = db.new_iterator(&ReadOptions::default());
+        // Not visible: the iterator reads from a snapshot taken when it was created.
+        db.db().put(&wo, b"0500x", b"v").unwrap();
+        block_on(async {
+            let mut n = 0;
+            while let Some(entry) = iter.next().await {
+                assert_eq!(entry.unwrap().0, format!("{:04}", n).into_bytes());
+                n += 1;
+            }
+            assert_eq!(n, 1000);
+            assert!(iter.next().await.is_none());
+
+            iter.seek(b"0998");
+            assert_eq!(iter.next().await.unwrap().unwrap().0, b"0998");
+            assert_eq!(iter.next().await.unwrap().unwrap().0, b"0999");
+            assert!(iter.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn dropped_task() {
+        let dir = tmp_dir("async_dropped_task");
+        let db = DB::open(
+            Options {
+                create_if_missing: true,
+                ..Default::default()
+            },
+            &dir,
+        )
+        .unwrap();
+        let db = AsyncDB::new(Arc::new(db), |task: BlockingTask| drop(task));
+        let err = block_on(db.get(&ReadOptions::default(), b"a")).unwrap_err();
+        assert!(err.is_aborted());
+        let results = block_on(db.multi_get(&ReadOptions::default(), &[b"a", b"b"]));
+        assert!(results.iter().all(|result| result.as_ref().unwrap_err().is_aborted()));
+    }
+}
@@ -0,0 +1,367 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Command-line tool for inspecting table files and databases when debugging data
+//! issues. Run `hefesto-tool help` for usage.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    process,
+};
+
+use rustydb::{
+    db::{dbformat::parse_internal_key, DB},
+    leveldb::{
+        env::{default_env, read_file_to_string},
+        options::{Options, ReadOptions, WriteOptions},
+    },
+    table::{table::Table, verify::verify_table_bytes},
+};
+
+const USAGE: &str = "usage: hefesto-tool <command> [args] [flags]
+
+commands:
+  dump-sst <file> [--properties] [--blocks] [--kv]
+      Print the properties, data block locations and entries of a table file.
+      Prints all three unless some are selected.
+  scan <db> [--from=<key>] [--to=<key>] [--limit=<n>]
+      Print the key/value pairs of a database in [from, to).
+  scrub <db>
+      Verify the checksums and key order of every live table and log of a database,
+      opened read-only, and list the damaged files.
+  get <db> <key>
+  put <db> <key> <value> [--create_if_missing]
+  delete <db> <key>
+
+flags:
+  --hex    Read keys and values from the command line as hex, and print them as hex.
+";
+
+type ToolResult = Result<(), String>;
+
+/// Command-line flags, which may appear anywhere after the command.
+#[derive(Default)]
+struct Flags {
+    hex: bool,
+    properties: bool,
+    blocks: bool,
+    kv: bool,
+    create_if_missing: bool,
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<usize>,
+}
+
+fn parse_args(args: &[String]) -> Result<(Vec<String>, Flags), String> {
+    let mut positional = Vec::new();
+    let mut flags = Flags::default();
+    for arg in args {
+        let Some(flag) = arg.strip_prefix("--") else {
+            positional.push(arg.clone());
+            continue;
+        };
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (flag, None),
+        };
+        match (name, value) {
+            ("hex", None) => flags.hex = true,
+            ("properties", None) => flags.properties = true,
+            ("blocks", None) => flags.blocks = true,
+            ("kv", None) => flags.kv = true,
+            ("create_if_missing", None) => flags.create_if_missing = true,
+            ("from", Some(v)) => flags.from = Some(v),
+            ("to", Some(v)) => flags.to = Some(v),
+            ("limit", Some(v)) => flags.limit = Some(v.parse().map_err(|_| format!("bad --limit: {}", v))?),
+            _ => return Err(format!("unknown flag: {}", arg)),
+        }
+    }
+    Ok((positional, flags))
+}
+
+/// Format `data` for printing: as hex with `--hex`, otherwise with non-printable
+/// bytes escaped as `\xNN`.
+fn format_bytes(data: &[u8], hex: bool) -> String {
+    let mut s = String::new();
+    for &b in data {
+        if hex {
+            let _ = write!(s, "{:02x}", b);
+        } else if (b' '..=b'~').contains(&b) {
+            s.push(b as char);
+        } else {
+            let _ = write!(s, "\\x{:02x}", b);
+        }
+    }
+    s
+}
+
+/// Decode a key or value given on the command line.
+fn parse_bytes(arg: &str, hex: bool) -> Result<Vec<u8>, String> {
+    if !hex {
+        return Ok(arg.as_bytes().to_vec());
+    }
+    let digits = arg.strip_prefix("0x").unwrap_or(arg);
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits: {}", arg));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("bad hex: {}", arg)))
+        .collect()
+}
+
+/// Format an internal key as `'user_key' @ sequence : type`.
+fn format_internal_key(key: &[u8], hex: bool) -> String {
+    match parse_internal_key(key) {
+        Some(ikey) => format!(
+            "'{}' @ {} : {:?}",
+            format_bytes(ikey.user_key, hex),
+            ikey.sequence,
+            ikey.value_type
+        ),
+        None => format!("(bad){}", format_bytes(key, hex)),
+    }
+}
+
+fn dump_sst(path: &str, flags: &Flags, out: &mut dyn Write) -> ToolResult {
+    let env = default_env();
+    let all = !flags.properties && !flags.blocks && !flags.kv;
+    let data = read_file_to_string(env.as_ref(), path).map_err(|e| e.to_string())?;
+    let summary = verify_table_bytes(&data).map_err(|e| e.to_string())?;
+    let io = |e: io::Error| e.to_string();
+
+    if all || flags.properties {
+        writeln!(out, "Properties:").map_err(io)?;
+        writeln!(out, "  file size: {}", data.len()).map_err(io)?;
+        writeln!(out, "  entries: {}", summary.num_entries).map_err(io)?;
+        writeln!(out, "  data blocks: {}", summary.num_data_blocks).map_err(io)?;
+        writeln!(out, "  raw key size: {}", summary.raw_key_size).map_err(io)?;
+        writeln!(out, "  raw value size: {}", summary.raw_value_size).map_err(io)?;
+        writeln!(out, "  data size: {}", summary.data_size).map_err(io)?;
+        writeln!(out, "  index size: {}", summary.index_size).map_err(io)?;
+        writeln!(out, "  filter size: {}", summary.filter_size).map_err(io)?;
+        writeln!(out, "  meta blocks: {}", summary.meta_blocks.join(", ")).map_err(io)?;
+        writeln!(out, "  checksum: {:?}", summary.checksum_type).map_err(io)?;
+        if summary.num_entries > 0 {
+            writeln!(out, "  smallest key: {}", format_internal_key(&summary.smallest_key, flags.hex)).map_err(io)?;
+            writeln!(out, "  largest key: {}", format_internal_key(&summary.largest_key, flags.hex)).map_err(io)?;
+        }
+    }
+
+    if all || flags.blocks {
+        writeln!(out, "Data blocks:").map_err(io)?;
+        for (i, handle) in summary.data_blocks.iter().enumerate() {
+            writeln!(out, "  #{} offset: {} size: {}", i, handle.offset(), handle.size()).map_err(io)?;
+        }
+    }
+
+    if all || flags.kv {
+        writeln!(out, "Key/values:").map_err(io)?;
+        let file = env.new_random_access_file(path).map_err(|e| e.to_string())?;
+        let table = Table::open(&Options::default(), path, file, data.len() as u64).map_err(|e| e.to_string())?;
+        let mut iter = table.new_iterator(&ReadOptions::default());
+        iter.seek_to_first();
+        while iter.valid() {
+            writeln!(
+                out,
+                "  {} => '{}'",
+                format_internal_key(iter.key(), flags.hex),
+                format_bytes(iter.value(), flags.hex)
+            )
+            .map_err(io)?;
+            iter.next();
+        }
+        iter.status().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn open_db(path: &str, flags: &Flags) -> Result<DB, String> {
+    let options = Options {
+        create_if_missing: flags.create_if_missing,
+        ..Options::default()
+    };
+    DB::open(options, path).map_err(|e| e.to_string())
+}
+
+fn scan(path: &str, flags: &Flags, out: &mut dyn Write) -> ToolResult {
+    let db = open_db(path, flags)?;
+    let to = flags.to.as_deref().map(|k| parse_bytes(k, flags.hex)).transpose()?;
+    let mut iter = db.new_iterator(&ReadOptions::default());
+    match flags.from.as_deref() {
+        Some(from) => iter.seek(&parse_bytes(from, flags.hex)?),
+        None => iter.seek_to_first(),
+    }
+    let mut count = 0;
+    while iter.valid() && flags.limit.is_none_or(|limit| count < limit) {
+        if to.as_deref().is_some_and(|to| iter.key() >= to) {
+            break;
+        }
+        writeln!(out, "{} => {}", format_bytes(iter.key(), flags.hex), format_bytes(iter.value(), flags.hex))
+            .map_err(|e| e.to_string())?;
+        count += 1;
+        iter.next();
+    }
+    iter.status().map_err(|e| e.to_string())
+}
+
+fn scrub(path: &str, out: &mut dyn Write) -> ToolResult {
+    let db = DB::open_read_only(Options::default(), path, false).map_err(|e| e.to_string())?;
+    let report = db.scrub(&ReadOptions::default()).map_err(|e| e.to_string())?;
+    let io = |e: io::Error| e.to_string();
+    writeln!(out, "tables checked: {}", report.tables_checked).map_err(io)?;
+    writeln!(out, "logs checked: {}", report.logs_checked).map_err(io)?;
+    writeln!(out, "bytes checked: {}", report.bytes_checked).map_err(io)?;
+    for problem in report.problems.iter() {
+        writeln!(out, "  {}: {}", problem.file, problem.error).map_err(io)?;
+    }
+    match report.is_ok() {
+        true => writeln!(out, "OK").map_err(io),
+        false => Err(format!("found {} damaged files", report.problems.len())),
+    }
+}
+
+fn run(args: &[String], out: &mut dyn Write) -> ToolResult {
+    let (positional, flags) = parse_args(args)?;
+    let positional: Vec<&str> = positional.iter().map(String::as_str).collect();
+    match positional.as_slice() {
+        ["dump-sst", path] => dump_sst(path, &flags, out),
+        ["scan", path] => scan(path, &flags, out),
+        ["scrub", path] => scrub(path, out),
+        ["get", path, key] => {
+            let db = open_db(path, &flags)?;
+            match db.get(&ReadOptions::default(), &parse_bytes(key, flags.hex)?) {
+                Ok(Some(value)) => writeln!(out, "{}", format_bytes(&value, flags.hex)).map_err(|e| e.to_string()),
+                Ok(None) => Err("not found".to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        ["put", path, key, value] => {
+            let db = open_db(path, &flags)?;
+            let (key, value) = (parse_bytes(key, flags.hex)?, parse_bytes(value, flags.hex)?);
+            db.put(&WriteOptions::default(), &key, &value).map_err(|e| e.to_string())?;
+            writeln!(out, "OK").map_err(|e| e.to_string())
+        }
+        ["delete", path, key] => {
+            let db = open_db(path, &flags)?;
+            db.delete(&WriteOptions::default(), &parse_bytes(key, flags.hex)?)
+                .map_err(|e| e.to_string())?;
+            writeln!(out, "OK").map_err(|e| e.to_string())
+        }
+        ["help"] => write!(out, "{}", USAGE).map_err(|e| e.to_string()),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let stdout = io::stdout();
+    if let Err(e) = run(&args, &mut stdout.lock()) {
+        eprintln!("{}", e.trim_end());
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use rustydb::db::sst_file_writer::SstFileWriter;
+
+    use super::*;
+
+    fn tmp_dir(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("rustydb-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().into_owned()
+    }
+
+    fn run_tool(args: &[&str]) -> Result<String, String> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let mut out = Vec::new();
+        run(&args, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn dump_sst() {
+        let dir = tmp_dir("tool_dump_sst");
+        let path = format!("{}/data.sst", dir);
+        let mut writer = SstFileWriter::new(&Options::default());
+        writer.open(&path).unwrap();
+        writer.put(b"apple", b"red").unwrap();
+        writer.delete(b"banana").unwrap();
+        writer.put(b"c\x01", b"\xff").unwrap();
+        writer.finish().unwrap();
+
+        let out = run_tool(&["dump-sst", &path]).unwrap();
+        assert!(out.contains("  entries: 3\n"), "{}", out);
+        assert!(out.contains("  #0 offset: 0 size: "));
+        assert!(out.contains("  'apple' @ 0 : Value => 'red'\n"));
+        assert!(out.contains("  'banana' @ 0 : Deletion => ''\n"));
+        assert!(out.contains("  'c\\x01' @ 0 : Value => '\\xff'\n"));
+
+        let out = run_tool(&["dump-sst", &path, "--kv", "--hex"]).unwrap();
+        assert!(!out.contains("Properties:"));
+        assert!(out.contains("  '6170706c65' @ 0 : Value => '726564'\n"));
+    }
+
+    #[test]
+    fn db_commands() {
+        let dir = tmp_dir("tool_db_commands");
+        let db = format!("{}/db", dir);
+        assert!(run_tool(&["put", &db, "k1", "v1"]).is_err());
+        assert_eq!(run_tool(&["put", &db, "k1", "v1", "--create_if_missing"]).unwrap(), "OK\n");
+        run_tool(&["put", &db, "k2", "v2"]).unwrap();
+        run_tool(&["put", "--hex", &db, "6b33", "0x00ff"]).unwrap();
+        assert_eq!(run_tool(&["get", &db, "k3"]).unwrap(), "\\x00\\xff\n");
+        assert_eq!(run_tool(&["get", &db, "6b33", "--hex"]).unwrap(), "00ff\n");
+        run_tool(&["delete", &db, "k1"]).unwrap();
+        assert_eq!(run_tool(&["get", &db, "k1"]), Err("not found".to_string()));
+        assert_eq!(run_tool(&["scan", &db]).unwrap(), "k2 => v2\nk3 => \\x00\\xff\n");
+        assert_eq!(run_tool(&["scan", &db, "--from=k3"]).unwrap(), "k3 => \\x00\\xff\n");
+        assert_eq!(run_tool(&["scan", &db, "--to=k3"]).unwrap(), "k2 => v2\n");
+        assert_eq!(run_tool(&["scan", &db, "--limit=1"]).unwrap(), "k2 => v2\n");
+        assert!(run_tool(&["scan", &db, "--bogus"]).is_err());
+    }
+
+    #[test]
+    fn scrub() {
+        let dir = tmp_dir("tool_scrub");
+        let db = format!("{}/db", dir);
+        run_tool(&["put", &db, "k1", "v1", "--create_if_missing"]).unwrap();
+        let out = run_tool(&["scrub", &db]).unwrap();
+        assert!(out.contains("tables checked: 1\n"), "{}", out);
+        assert!(out.ends_with("OK\n"));
+
+        for entry in fs::read_dir(&db).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "ldb") {
+                let mut contents = fs::read(&path).unwrap();
+                contents[0] ^= 0x08;
+                fs::write(&path, contents).unwrap();
+            }
+        }
+        assert_eq!(run_tool(&["scrub", &db]), Err("found 1 damaged files".to_string()));
+    }
+}
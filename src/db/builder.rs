@@ -0,0 +1,89 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    db::{dbformat::InternalKey, filename::table_file_name, table_cache::TableCache, version_edit::FileMetaData},
+    leveldb::{
+        iterator::Iterator,
+        options::{Options, ReadOptions},
+        result::Result,
+    },
+    table::table_builder::TableBuilder,
+};
+
+/// Build a Table file from the contents of `iter`. The generated file will be named
+/// according to `meta.number`. On success, the rest of `meta` will be filled with
+/// metadata about the generated table. If no data is present in `iter`,
+/// `meta.file_size` will be set to zero, and no Table file will be produced.
+pub fn build_table(dbname: &str, options: &Options, table_cache: &TableCache, iter: &mut dyn Iterator, meta: &mut FileMetaData) -> Result<()> {
+    let env = options.env.clone();
+    meta.file_size = 0;
+    iter.seek_to_first();
+
+    let fname = table_file_name(dbname, meta.number);
+    let mut s = Ok(());
+    if iter.valid() {
+        let file = env.new_writable_file(&fname)?;
+        meta.creation_time = env.now_micros() / 1_000_000;
+        let mut builder = TableBuilder::new(options, file);
+        meta.smallest = InternalKey::decode_from(iter.key());
+        let mut last_key = Vec::new();
+        while iter.valid() {
+            last_key.clear();
+            last_key.extend_from_slice(iter.key());
+            builder.add(iter.key(), iter.value());
+            iter.next();
+        }
+        meta.largest = InternalKey::decode_from(&last_key);
+
+        // Finish and check for builder errors
+        s = builder.finish();
+        if s.is_ok() {
+            meta.file_size = builder.file_size();
+            assert!(meta.file_size > 0);
+        }
+
+        // Finish and check for file errors
+        let mut file = builder.into_file();
+        if s.is_ok() {
+            s = file.sync();
+        }
+        if s.is_ok() {
+            s = file.close();
+        }
+
+        if s.is_ok() {
+            // Verify that the table is usable
+            let it = table_cache.new_iterator(&ReadOptions::default(), meta.number, meta.file_size);
+            s = it.status();
+        }
+    }
+
+    // Check for input iterator errors
+    if let Err(e) = iter.status() {
+        s = Err(e);
+    }
+
+    if s.is_err() || meta.file_size == 0 {
+        let _ = env.remove_file(&fname);
+    }
+    s
+}
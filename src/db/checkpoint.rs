@@ -0,0 +1,149 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    db::{
+        db_impl::DB,
+        dbformat::config,
+        filename::{descriptor_file_name, log_file_name, set_current_file, table_file_name},
+    },
+    leveldb::{
+        env::{copy_file, link_or_copy_file, Env},
+        result::{Error, ErrorType, Result},
+    },
+};
+
+/// Creates checkpoints: consistent copies of a live database in another directory.
+/// A checkpoint is a complete database directory that `DB::open()` opens on its own,
+/// e.g. to take a backup or to seed a replica.
+pub struct Checkpoint;
+
+impl Checkpoint {
+    /// Create a checkpoint of `db` in `checkpoint_dir`, which must not exist yet.
+    ///
+    /// The memtable is flushed first, so the checkpoint holds every write made before
+    /// the call. Table files are hard-linked into the checkpoint where the `Env` and
+    /// file system allow it, which makes the checkpoint cheap; they are copied
+    /// otherwise. Table files are never modified once written, so the checkpoint and
+    /// `db` stay independent either way. The manifest and the log are copied.
+    pub fn create(db: &DB, checkpoint_dir: &str) -> Result<()> {
+        let env = db.env().as_ref();
+        if env.file_exists(checkpoint_dir) {
+            return Err(Error::new(ErrorType::InvalidArgument, "checkpoint directory already exists")
+                .with_detail(checkpoint_dir.to_string()));
+        }
+        // Build the checkpoint under a temporary name so that a failed attempt never
+        // leaves a directory that looks like a complete checkpoint.
+        let tmp_dir = format!("{}.tmp", checkpoint_dir);
+        remove_dir_all(env, &tmp_dir);
+        env.create_dir(&tmp_dir)?;
+        let result = Self::populate(db, &tmp_dir).and_then(|_| env.rename_file(&tmp_dir, checkpoint_dir));
+        if result.is_err() {
+            remove_dir_all(env, &tmp_dir);
+        }
+        result
+    }
+
+    fn populate(db: &DB, dir: &str) -> Result<()> {
+        let env = db.env().as_ref();
+        let live = db.live_files()?;
+        let mut result = Ok(());
+        'levels: for level in 0..config::NUM_LEVELS {
+            for f in live.version.files(level) {
+                let src = table_file_name(db.dbname(), f.number);
+                result = link_or_copy_file(env, &src, &table_file_name(dir, f.number), f.file_size);
+                if result.is_err() {
+                    break 'levels;
+                }
+            }
+        }
+        // Compactions may have made some of the captured tables obsolete meanwhile.
+        drop(live.version);
+        db.purge_obsolete_tables();
+        result?;
+
+        copy_file(
+            env,
+            &descriptor_file_name(db.dbname(), live.manifest_number),
+            &descriptor_file_name(dir, live.manifest_number),
+            live.manifest_size,
+        )?;
+        copy_file(
+            env,
+            &log_file_name(db.dbname(), live.log_number),
+            &log_file_name(dir, live.log_number),
+            live.log_size,
+        )?;
+        set_current_file(env, dir, live.manifest_number)
+    }
+}
+
+/// Remove `dir` and the files in it, ignoring errors.
+fn remove_dir_all(env: &dyn Env, dir: &str) {
+    if let Ok(children) = env.get_children(dir) {
+        for child in children {
+            let _ = env.remove_file(&format!("{}/{}", dir, child));
+        }
+    }
+    let _ = env.remove_dir(dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        leveldb::options::{Options, ReadOptions, WriteOptions},
+        util::testutil::tmp_dir,
+    };
+
+    fn get(db: &DB, key: &[u8]) -> Option<Vec<u8>> { db.get(&ReadOptions::default(), key).unwrap() }
+
+    #[test]
+    fn create() {
+        let dir = tmp_dir("checkpoint_src");
+        let checkpoint_dir = format!("{}/checkpoint", tmp_dir("checkpoint_dst"));
+        let options = Options {
+            create_if_missing: true,
+            write_buffer_size: 8 * 1024,
+            ..Options::default()
+        };
+        let db = DB::open(options.clone(), &dir).unwrap();
+        let wo = WriteOptions::default();
+        for i in 0..500 {
+            db.put(&wo, format!("key{:04}", i).as_bytes(), &[b'x'; 100]).unwrap();
+        }
+        db.put(&wo, b"last", b"before").unwrap();
+
+        Checkpoint::create(&db, &checkpoint_dir).unwrap();
+        assert_eq!(Checkpoint::create(&db, &checkpoint_dir).unwrap_err().ty(), ErrorType::InvalidArgument);
+        db.put(&wo, b"last", b"after").unwrap();
+        db.delete(&wo, b"key0000").unwrap();
+
+        let copy = DB::open(options, &checkpoint_dir).unwrap();
+        assert_eq!(get(&copy, b"last"), Some(b"before".to_vec()));
+        assert_eq!(get(&copy, b"key0000"), Some(vec![b'x'; 100]));
+        assert_eq!(get(&copy, b"key0499"), Some(vec![b'x'; 100]));
+        copy.put(&wo, b"only-in-copy", b"v").unwrap();
+        assert_eq!(get(&db, b"only-in-copy"), None);
+        assert_eq!(get(&db, b"last"), Some(b"after".to_vec()));
+        assert_eq!(get(&db, b"key0000"), None);
+    }
+}
@@ -0,0 +1,5764 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    fmt, mem,
+    ops::Bound,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    db::{
+        builder::build_table,
+        external_file::ExternalFile,
+        db_iter::new_db_iterator,
+        dbformat::{
+            config, extract_user_key, parse_internal_key, InternalFilterPolicy, InternalKey, InternalKeyComparator, InternalSliceTransform, LookupKey,
+            sequences_available, SequenceNumber, ValueType, MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK,
+        },
+        key_range::KeyRange,
+        filename::{
+            archival_directory, archived_log_file_name, current_file_name, descriptor_file_name, info_log_file_name, lock_file_name, log_file_name,
+            old_info_log_file_name, parse_file_name, set_current_file, table_file_name, FileType,
+        },
+        log_reader, log_writer,
+        memtable::MemTable,
+        merge::{merge_entries, new_compaction_merge_iterator},
+        metrics::{MetricsSnapshot, Registration, Statistics},
+        purge_queue::{PinnedIterator, PurgeQueue},
+        range_iter::RangeIter,
+        snapshot::{Snapshot, SnapshotList},
+        table_cache::TableCache,
+        tailing_iter::TailingIterator,
+        transaction_log::TransactionLogIterator,
+        version_edit::{FileMetaData, VersionEdit},
+        version_set::{total_file_size, Compaction, FileNumbers, GetStats, Version, VersionSet},
+        write_batch::WriteBatch,
+    },
+    leveldb::{
+        env::{copy_file, link_or_copy_file, read_file_to_string, Env, FileLock, InfoLogLevel, Logger, Priority, WritableFile},
+        iterator::Iterator,
+        compaction_filter::{CompactionFilter, CompactionFilterDecision},
+        listener::{
+            BackgroundJobInfo, BackgroundJobKind, CompactionDecision, CompactionJobInfo, FlushJobInfo, JobProgress,
+            TableFileCreationInfo, TableFileCreationReason,
+        },
+        options::{
+            new_block_cache, CompactRangeOptions, FlushOptions, IngestExternalFileOptions, Options, ReadOptions,
+            WaitForCompactOptions, WriteOptions,
+        },
+        pinnable_slice::PinnableSlice,
+        result::{Error, ErrorType, Result},
+        slice_transform::SliceTransform,
+    },
+    table::{
+        format::check_format_version, merger::new_merging_iterator, table_builder::TableBuilder,
+        verify::verify_table_bytes_with,
+    },
+    util::{arena::Arena, logger::RollingLogger},
+};
+
+// Number of open files reserved for files other than tables, out of
+// `Options::max_open_files`.
+const NUM_NON_TABLE_CACHE_FILES: usize = 10;
+
+// Capacity in bytes of the block cache created when the user does not supply one.
+const DEFAULT_BLOCK_CACHE_SIZE: usize = 8 << 20;
+
+// Number of table lookups multi_get() does between checks of ReadOptions::cancel.
+const MULTI_GET_CANCEL_INTERVAL: usize = 64;
+
+// Number of entries verify_checksums() reads between checks of ReadOptions::cancel.
+const VERIFY_CANCEL_INTERVAL: u64 = 1024;
+
+// Maximum size of the batches of a write group. A group led by a small write only
+// grows by WRITE_GROUP_SMALL_GROWTH, so as not to slow the small write down too much.
+const MAX_WRITE_GROUP_SIZE: usize = 1 << 20;
+const WRITE_GROUP_SMALL_GROWTH: usize = 128 << 10;
+
+// How long wait_for_compact() sleeps before offering a delayed compaction again.
+const DELAYED_COMPACTION_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Fix user-supplied options to be reasonable, and switch the comparator, filter policy
+/// and prefix extractor to their internal-key counterparts.
+pub(crate) fn sanitize_options(icmp: &InternalKeyComparator, src: &Options) -> Options {
+    let mut result = src.clone();
+    result.comparator = Arc::new(icmp.clone());
+    result.filter_policy = src
+        .filter_policy
+        .clone()
+        .map(|p| Arc::new(InternalFilterPolicy::new(p)) as Arc<_>);
+    result.prefix_extractor = src
+        .prefix_extractor
+        .clone()
+        .map(|t| Arc::new(InternalSliceTransform::new(t)) as Arc<_>);
+    if result.block_cache.is_none() {
+        result.block_cache = Some(new_block_cache(DEFAULT_BLOCK_CACHE_SIZE));
+    }
+    result.arena_block_size = src.arena_block_size.clamp(1 << 10, 1 << 30);
+    if result.max_open_files != -1 {
+        result.max_open_files = src.max_open_files.clamp(20, 50000);
+    }
+    result.level0_file_num_compaction_trigger = src.level0_file_num_compaction_trigger.max(1);
+    result.level0_slowdown_writes_trigger = src
+        .level0_slowdown_writes_trigger
+        .max(result.level0_file_num_compaction_trigger);
+    result.level0_stop_writes_trigger = src.level0_stop_writes_trigger.max(result.level0_slowdown_writes_trigger);
+    result
+}
+
+/// Number of tables the table cache keeps open under `options.max_open_files`, which
+/// must be sanitized.
+fn table_cache_size(options: &Options) -> usize {
+    match options.max_open_files {
+        -1 => usize::MAX,
+        n => n as usize - NUM_NON_TABLE_CACHE_FILES,
+    }
+}
+
+fn status_string(s: &Result<()>) -> String {
+    match s {
+        Ok(()) => "OK".to_string(),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Log reader reporter that drops damaged records, so that the records that can still
+/// be read are recovered. With `paranoid_checks` it also remembers the first
+/// corruption, which then fails recovery.
+struct LogReporter {
+    info_log: Option<Arc<dyn Logger>>,
+    fname: String,
+    status: Option<Arc<Mutex<Option<Error>>>>,
+}
+
+impl log_reader::Reporter for LogReporter {
+    fn corruption(&mut self, bytes: usize, reason: &Error) {
+        if let Some(info_log) = self.info_log.as_ref() {
+            info_log.warn(format_args!("{}: dropping {} bytes; {}", self.fname, bytes, reason));
+        }
+        if let Some(status) = self.status.as_ref() {
+            status.lock().unwrap().get_or_insert_with(|| reason.clone());
+        }
+    }
+}
+
+/// A log file and memtable created ahead of time by a background job, ready to
+/// replace the active ones.
+struct PreparedMemtable {
+    log_number: u64,
+    result: mpsc::Receiver<Result<(Box<dyn WritableFile>, MemTable)>>,
+}
+
+impl PreparedMemtable {
+    /// Wait for the background job and return what it created.
+    fn wait(self) -> Result<(Box<dyn WritableFile>, MemTable)> {
+        match self.result.recv() {
+            Ok(result) => result,
+            Err(_) => LEVELDB_ERR!(IOError, "memtable preparation job failed"),
+        }
+    }
+}
+
+/// A level-0 table being built from a memtable.
+struct FlushJob {
+    number: u64,
+    start_micros: u64,
+    memtable_bytes: u64,
+    progress: Arc<JobProgress>,
+}
+
+/// A flush of the immutable memtable running in a background job.
+struct BackgroundFlush {
+    job: FlushJob,
+    // The log holding the updates of the immutable memtable
+    old_log_number: u64,
+    // The log started when the memtable was switched out, which holds everything
+    // written since.
+    new_log_number: u64,
+    result: mpsc::Receiver<Result<FileMetaData>>,
+}
+
+/// Create a memtable that allocates from `arena`, one that takes concurrent inserts
+/// (with an arena of its own) if `concurrent` is set, or a vector memtable, which
+/// makes inserts cheaper but reads slower, for a bulk load.
+fn create_memtable(icmp: InternalKeyComparator, arena: Arena, concurrent: bool, bulk_load: bool) -> MemTable {
+    match (bulk_load, concurrent) {
+        (true, _) => MemTable::new_vector(icmp),
+        (false, true) => MemTable::new_concurrent(icmp),
+        (false, false) => MemTable::with_arena(icmp, arena),
+    }
+}
+
+/// Build a level-0 table numbered `number` from the contents of `mem`.
+fn build_level0_table(dbname: &str, options: &Options, table_cache: &TableCache, mem: &MemTable, number: u64) -> Result<FileMetaData> {
+    let mut meta = FileMetaData {
+        number,
+        ..FileMetaData::default()
+    };
+    let mut iter = mem.new_iterator();
+    build_table(dbname, options, table_cache, iter.as_mut(), &mut meta)?;
+    Ok(meta)
+}
+
+struct CompactionOutput {
+    meta: FileMetaData,
+    builder: TableBuilder,
+}
+
+/// Everything a compaction needs to write its output tables, so that it can run in a
+/// low priority background job without the DB mutex.
+struct CompactionContext {
+    dbname: String,
+    env: Arc<dyn Env>,
+    options: Options,
+    icmp: InternalKeyComparator,
+    table_cache: Arc<TableCache>,
+    file_numbers: FileNumbers,
+    // Sequence numbers older than the oldest snapshot can be collapsed.
+    smallest_snapshot: SequenceNumber,
+    compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    progress: Arc<JobProgress>,
+}
+
+impl CompactionContext {
+    /// Compact the inputs of `c` into new output files, in parallel subcompactions over
+    /// the key ranges that `boundaries` separate. The output files are removed again on
+    /// failure.
+    fn run(&self, c: &Compaction, boundaries: &[Vec<u8>]) -> Result<Vec<FileMetaData>> {
+        if boundaries.is_empty() {
+            return self.compact_key_range(&mut c.subcompaction(), &KeyRange::all());
+        }
+
+        // Compact disjoint parts of the key range on separate threads. Each part holds
+        // all entries of its user keys.
+        let ranges: Vec<KeyRange> = (0..=boundaries.len())
+            .map(|i| {
+                let start = i.checked_sub(1).map_or(Bound::Unbounded, |i| Bound::Included(&boundaries[i][..]));
+                let end = boundaries.get(i).map_or(Bound::Unbounded, |key| Bound::Excluded(&key[..]));
+                KeyRange::new(start, end)
+            })
+            .collect();
+        let results: Vec<Result<Vec<FileMetaData>>> = thread::scope(|scope| {
+            let handles: Vec<_> = ranges
+                .iter()
+                .map(|range| {
+                    let mut sub = c.subcompaction();
+                    scope.spawn(move || self.compact_key_range(&mut sub, range))
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        let mut outputs = Vec::new();
+        let mut status = Ok(());
+        for result in results {
+            match result {
+                Ok(sub_outputs) => outputs.extend(sub_outputs),
+                Err(e) => status = status.and(Err(e)),
+            }
+        }
+        if let Err(e) = status {
+            for out in outputs.iter() {
+                let _ = self.env.remove_file(&table_file_name(&self.dbname, out.number));
+            }
+            return Err(e);
+        }
+        Ok(outputs)
+    }
+
+    /// Compact the entries of the inputs of `c` whose user keys fall in `range` into new
+    /// output files, which are removed again on failure.
+    fn compact_key_range(&self, c: &mut Compaction, range: &KeyRange) -> Result<Vec<FileMetaData>> {
+        let smallest_snapshot = self.smallest_snapshot;
+        let compaction_filter = self.compaction_filter.as_deref();
+        let ucmp = self.icmp.user_comparator().clone();
+        let mut input = c.new_input_iterator(&self.options, &self.table_cache);
+        if let Some(operator) = self.options.merge_operator.clone() {
+            input = new_compaction_merge_iterator(input, ucmp.clone(), operator, smallest_snapshot);
+        }
+        match &range.start {
+            Bound::Included(start) => input.seek(InternalKey::new(start, MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK).encode()),
+            _ => input.seek_to_first(),
+        }
+        let mut outputs = Vec::new();
+        let mut output: Option<CompactionOutput> = None;
+        let mut current_user_key: Option<Vec<u8>> = None;
+        let mut last_sequence_for_key = MAX_SEQUENCE_NUMBER;
+        let mut status = Ok(());
+
+        while input.valid() {
+            let key = input.key();
+            if parse_internal_key(key).is_some_and(|ikey| range.is_after_end(ucmp.as_ref(), ikey.user_key)) {
+                break;
+            }
+            if c.should_stop_before(key) {
+                if let Some(out) = output.take() {
+                    match self.finish_compaction_output_file(out, input.as_ref()) {
+                        Ok(meta) => outputs.push(meta),
+                        Err(e) => {
+                            status = Err(e);
+                            break;
+                        }
+                    }
+                }
+            }
+
+            // Handle key/value, add to state, etc.
+            let mut replacement: Option<(Vec<u8>, Vec<u8>)> = None;
+            let drop = match parse_internal_key(key) {
+                None => {
+                    // Do not hide error keys
+                    current_user_key = None;
+                    last_sequence_for_key = MAX_SEQUENCE_NUMBER;
+                    false
+                }
+                Some(ikey) => {
+                    let first_occurrence = current_user_key
+                        .as_ref()
+                        .is_none_or(|k| ucmp.compare(ikey.user_key, k) != std::cmp::Ordering::Equal);
+                    if first_occurrence {
+                        // First occurrence of this user key
+                        current_user_key = Some(ikey.user_key.to_vec());
+                        last_sequence_for_key = MAX_SEQUENCE_NUMBER;
+                    }
+
+                    let mut drop = if last_sequence_for_key <= smallest_snapshot {
+                        // Hidden by an newer entry for same user key
+                        true
+                    } else {
+                        // For this user key:
+                        // (1) there is no data in higher levels
+                        // (2) data in lower levels will have larger sequence numbers
+                        // (3) data in layers that are being compacted here and have
+                        //     smaller sequence numbers will be dropped in the next few
+                        //     iterations of this loop (by rule (A) above).
+                        // Therefore this deletion marker is obsolete and can be dropped.
+                        ikey.value_type == ValueType::Deletion
+                            && ikey.sequence <= smallest_snapshot
+                            && c.is_base_level_for_key(ikey.user_key)
+                    };
+                    let filter = compaction_filter.filter(|_| !drop && ikey.value_type == ValueType::Value && ikey.sequence <= smallest_snapshot);
+                    if let Some(filter) = filter {
+                        match filter.filter(c.level(), ikey.user_key, input.value()) {
+                            CompactionFilterDecision::Keep => {}
+                            CompactionFilterDecision::Remove => {
+                                if c.is_base_level_for_key(ikey.user_key) {
+                                    drop = true;
+                                } else {
+                                    // Older values in deeper levels must stay hidden.
+                                    let deletion = InternalKey::new(ikey.user_key, ikey.sequence, ValueType::Deletion);
+                                    replacement = Some((deletion.encode().to_vec(), Vec::new()));
+                                }
+                            }
+                            CompactionFilterDecision::ChangeValue(value) => replacement = Some((key.to_vec(), value)),
+                        }
+                    }
+                    // A merge operand does not hide older entries: reads still need
+                    // them as the base of the merge.
+                    if ikey.value_type != ValueType::Merge {
+                        last_sequence_for_key = ikey.sequence;
+                    }
+                    drop
+                }
+            };
+
+            if !drop {
+                let (key, value) = match replacement.as_ref() {
+                    Some((key, value)) => (&key[..], &value[..]),
+                    None => (key, input.value()),
+                };
+                // Open output file if necessary
+                if output.is_none() {
+                    match self.open_compaction_output_file(c.output_level()) {
+                        Ok(out) => output = Some(out),
+                        Err(e) => {
+                            status = Err(e);
+                            break;
+                        }
+                    }
+                }
+                let out = output.as_mut().unwrap();
+                if out.builder.num_entries() == 0 {
+                    out.meta.smallest = InternalKey::decode_from(key);
+                }
+                out.meta.largest = InternalKey::decode_from(key);
+                out.builder.add(key, value);
+
+                // Close output file if it is big enough
+                if out.builder.file_size() >= c.max_output_file_size() {
+                    let out = output.take().unwrap();
+                    match self.finish_compaction_output_file(out, input.as_ref()) {
+                        Ok(meta) => outputs.push(meta),
+                        Err(e) => {
+                            status = Err(e);
+                            break;
+                        }
+                    }
+                }
+            }
+
+            self.progress.add_bytes_read((key.len() + input.value().len()) as u64);
+            input.next();
+        }
+
+        if let Some(mut out) = output.take() {
+            if status.is_ok() {
+                match self.finish_compaction_output_file(out, input.as_ref()) {
+                    Ok(meta) => outputs.push(meta),
+                    Err(e) => status = Err(e),
+                }
+            } else {
+                out.builder.abandon();
+                let _ = self.env.remove_file(&table_file_name(&self.dbname, out.meta.number));
+            }
+        }
+        if status.is_ok() {
+            status = input.status();
+        }
+
+        match status {
+            Ok(()) => Ok(outputs),
+            Err(e) => {
+                for out in outputs.iter() {
+                    let _ = self.env.remove_file(&table_file_name(&self.dbname, out.number));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn open_compaction_output_file(&self, output_level: usize) -> Result<CompactionOutput> {
+        let number = self.file_numbers.next();
+        let file = self.env.new_writable_file(&table_file_name(&self.dbname, number))?;
+        let options = Options {
+            compression: self.options.compression_for_level(output_level),
+            ..self.options.clone()
+        };
+        Ok(CompactionOutput {
+            meta: FileMetaData {
+                number,
+                creation_time: self.env.now_micros() / 1_000_000,
+                ..FileMetaData::default()
+            },
+            builder: TableBuilder::new(&options, file),
+        })
+    }
+
+    fn finish_compaction_output_file(&self, output: CompactionOutput, input: &dyn Iterator) -> Result<FileMetaData> {
+        let CompactionOutput { mut meta, mut builder } = output;
+        // Check for iterator errors
+        let mut s = input.status();
+        if s.is_ok() {
+            s = builder.finish();
+        } else {
+            builder.abandon();
+        }
+        meta.file_size = builder.file_size();
+        self.progress.add_bytes_written(meta.file_size);
+        let num_entries = builder.num_entries();
+
+        // Finish and check for file errors
+        let mut file = builder.into_file();
+        if s.is_ok() {
+            s = file.sync();
+        }
+        if s.is_ok() {
+            s = file.close();
+        }
+
+        if s.is_ok() && num_entries > 0 {
+            // Verify that the table is usable
+            let iter = self
+                .table_cache
+                .new_iterator(&ReadOptions::default(), meta.number, meta.file_size);
+            s = iter.status();
+        }
+        s.map(|_| meta)
+    }
+}
+
+/// The outputs of a compaction running in a background job. Unlike a channel, they can
+/// be waited for by any number of threads, none of which holds the DB mutex.
+#[derive(Default)]
+struct CompactionResult {
+    outputs: Mutex<Option<Result<Vec<FileMetaData>>>>,
+    done: Condvar,
+}
+
+impl CompactionResult {
+    fn set(&self, outputs: Result<Vec<FileMetaData>>) {
+        *self.outputs.lock().unwrap() = Some(outputs);
+        self.done.notify_all();
+    }
+
+    /// Wait until the outputs are set, or until `deadline`. Returns whether they are.
+    fn wait(&self, deadline: Option<Instant>) -> bool {
+        let mut outputs = self.outputs.lock().unwrap();
+        while outputs.is_none() {
+            match deadline {
+                None => outputs = self.done.wait(outputs).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    outputs = self.done.wait_timeout(outputs, deadline - now).unwrap().0;
+                }
+            }
+        }
+        true
+    }
+
+    fn take(&self) -> Option<Result<Vec<FileMetaData>>> { self.outputs.lock().unwrap().take() }
+}
+
+/// A compaction writing its outputs in a background job.
+struct BackgroundCompaction {
+    c: Compaction,
+    info: CompactionJobInfo,
+    progress: Arc<JobProgress>,
+    start_micros: u64,
+    result: Arc<CompactionResult>,
+}
+
+/// A write waiting in the write queue.
+struct QueuedWrite {
+    id: u64,
+    sync: bool,
+    // Taken by the leader that commits the write, or by the writer itself when it
+    // inserts its batch into the memtable concurrently with the rest of its group
+    batch: Option<WriteBatch>,
+    // Set by the leader when the writer should insert its batch into this memtable
+    insert: Option<Arc<MemTable>>,
+}
+
+/// Writers waiting to commit, in arrival order. The first one is the leader: it
+/// commits its own batch together with those of the writers queued behind it as one
+/// log record, and hands them the result.
+#[derive(Default)]
+struct WriteQueue {
+    writers: VecDeque<QueuedWrite>,
+    // Results of the writes committed by a leader on behalf of other writers
+    results: HashMap<u64, Result<()>>,
+    next_id: u64,
+    // Writers of the group being committed that still insert their batch, and the
+    // first error they hit
+    inserting: usize,
+    insert_error: Option<Error>,
+}
+
+impl WriteQueue {
+    fn push(&mut self, sync: bool, batch: WriteBatch) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.writers.push_back(QueuedWrite {
+            id,
+            sync,
+            batch: Some(batch),
+            insert: None,
+        });
+        id
+    }
+
+    fn get_mut(&mut self, id: u64) -> Option<&mut QueuedWrite> {
+        // Ids are consecutive from the front of the queue.
+        let first = self.writers.front()?.id;
+        self.writers.get_mut(id.checked_sub(first)? as usize)
+    }
+
+    /// Merge the batches of the leader and of the writers behind it, up to a size
+    /// limit. Returns the merged batch, whether it must be synced, and the id of the
+    /// last write of the group. With `keep_batches`, the writers keep their batches to
+    /// insert them into the memtable themselves.
+    /// REQUIRES: the queue is not empty and no group is being committed
+    fn take_group(&mut self, keep_batches: bool) -> (WriteBatch, bool, u64) {
+        let leader = self.writers.front_mut().unwrap();
+        let sync = leader.sync;
+        let mut last_id = leader.id;
+        let mut group = match keep_batches {
+            true => leader.batch.clone().unwrap(),
+            false => leader.batch.take().unwrap(),
+        };
+        let mut size = group.approximate_size();
+        let max_size = match size <= WRITE_GROUP_SMALL_GROWTH {
+            true => size + WRITE_GROUP_SMALL_GROWTH,
+            false => MAX_WRITE_GROUP_SIZE,
+        };
+        for w in self.writers.iter_mut().skip(1) {
+            if w.sync && !sync {
+                // Do not include a sync write into a group committed without sync.
+                break;
+            }
+            let batch = w.batch.as_ref().unwrap();
+            size += batch.approximate_size();
+            if size > max_size {
+                break;
+            }
+            group.append(batch);
+            if !keep_batches {
+                w.batch = None;
+            }
+            last_id = w.id;
+        }
+        (group, sync, last_id)
+    }
+
+    /// Remove the writes of the group ending at `last_id` and record their result for
+    /// their writers, except for the leader's own.
+    fn finish_group(&mut self, last_id: u64, result: &Result<()>) {
+        let leader = self.writers.pop_front().unwrap();
+        assert!(leader.id <= last_id);
+        while self.writers.front().is_some_and(|w| w.id <= last_id) {
+            let w = self.writers.pop_front().unwrap();
+            self.results.insert(w.id, result.clone());
+        }
+    }
+}
+
+/// State guarded by the DB mutex.
+struct DBState {
+    // Lock over the persistent DB state. Some iff successfully acquired.
+    db_lock: Option<Box<dyn FileLock>>,
+    mem: Arc<MemTable>,
+    // Memtable being flushed, which reads consult after `mem`.
+    imm: Option<Arc<MemTable>>,
+    // The background flush of `imm`, unless it failed.
+    flush: Option<BackgroundFlush>,
+    // The compaction running in the background, if any. Only one runs at a time.
+    compaction: Option<BackgroundCompaction>,
+    // Taken out by the write leader while it appends to the log without the mutex.
+    log: Option<log_writer::Writer>,
+    logfile_number: u64,
+    versions: VersionSet,
+
+    // The log file and memtable to switch to once `mem` is full, if their creation
+    // has started.
+    prepared: Option<PreparedMemtable>,
+
+    // Have we encountered a background error in paranoid mode?
+    bg_error: Result<()>,
+
+    // Compactions vetoed by a listener, identified by level and input file numbers.
+    vetoed_compactions: HashSet<(usize, Vec<u64>)>,
+
+    // Work done by flushes and compactions, by the level they wrote to.
+    stats: [CompactionStats; config::NUM_LEVELS],
+
+    // Files found at open that the recovered state does not account for.
+    unexpected_files: Vec<String>,
+
+    // Between prepare_for_bulk_load() and finish_bulk_load().
+    bulk_load: bool,
+
+    // For a secondary instance, the number of records of each log of the primary that
+    // have been replayed into `mem`.
+    replayed_records: BTreeMap<u64, usize>,
+
+    // Arena of the last flushed memtable, reset for the next memtable to reuse its
+    // blocks.
+    spare_arena: Option<Arena>,
+
+    // Obsolete logs kept while file deletions are disabled.
+    obsolete_logs: Vec<u64>,
+}
+
+/// Per level compaction stats. `stats[level]` stores the stats for compactions that
+/// produced data for the specified "level".
+#[derive(Clone, Copy, Default)]
+struct CompactionStats {
+    micros: u64,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+impl CompactionStats {
+    fn add(&mut self, micros: u64, bytes_read: u64, bytes_written: u64) {
+        self.micros += micros;
+        self.bytes_read += bytes_read;
+        self.bytes_written += bytes_written;
+    }
+}
+
+/// The files that make up a consistent state of the database, as captured by
+/// `DB::live_files()`. The table files of `version` stay on disk while it is alive.
+pub(crate) struct LiveFiles {
+    pub version: Arc<Version>,
+    pub manifest_number: u64,
+    pub manifest_size: u64,
+    pub log_number: u64,
+    pub log_size: u64,
+}
+
+/// The files returned by `DB::get_live_files()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveFileList {
+    /// Names relative to the database directory: CURRENT, the manifest and the table
+    /// files of the current version.
+    pub files: Vec<String>,
+    /// The valid prefix of the manifest. Edits appended later are not part of the
+    /// captured state.
+    pub manifest_file_size: u64,
+}
+
+/// A damaged file found by `DB::scrub()`.
+#[derive(Debug, Clone)]
+pub struct ScrubProblem {
+    /// Name of the file, relative to the database directory.
+    pub file: String,
+    /// The first problem found in the file.
+    pub error: Error,
+}
+
+/// The result of `DB::scrub()`.
+#[derive(Debug, Clone, Default)]
+pub struct ScrubReport {
+    pub tables_checked: usize,
+    pub logs_checked: usize,
+    /// Total size of the checked files.
+    pub bytes_checked: u64,
+    /// The damaged files, tables first.
+    pub problems: Vec<ScrubProblem>,
+}
+
+impl ScrubReport {
+    /// Return true if no damage was found.
+    pub fn is_ok(&self) -> bool { self.problems.is_empty() }
+}
+
+/// Log reader reporter that keeps the first corruption it is told about.
+struct FirstCorruption(Arc<Mutex<Option<Error>>>);
+
+impl log_reader::Reporter for FirstCorruption {
+    fn corruption(&mut self, _bytes: usize, reason: &Error) {
+        self.0.lock().unwrap().get_or_insert_with(|| reason.clone());
+    }
+}
+
+/// A DB is a persistent ordered map from keys to values. A DB is safe for concurrent
+/// access from multiple threads without any external synchronization.
+pub struct DB {
+    dbname: String,
+    env: Arc<dyn Env>,
+    internal_comparator: InternalKeyComparator,
+    // options.comparator == &internal_comparator
+    options: Options,
+    // The user's prefix extractor, which options.prefix_extractor wraps
+    prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    table_cache: Arc<TableCache>,
+    snapshots: Arc<SnapshotList>,
+    // Set by open_read_only() and open_as_secondary(): nothing is ever written to the
+    // DB directory.
+    read_only: bool,
+    // Set by open_as_secondary().
+    secondary: bool,
+    // Tables removed by compactions that readers of older versions may still use.
+    purge_queue: Arc<PurgeQueue>,
+    state: Mutex<DBState>,
+
+    // Writers waiting for their turn to commit, and the signal of their turn or result.
+    write_queue: Mutex<WriteQueue>,
+    write_queue_cv: Condvar,
+    // Held by the write leader while it appends to the log and inserts into the
+    // memtable without the DB mutex, and by everything else that uses the log or
+    // switches the memtable. Taken before the DB mutex.
+    write_lock: Mutex<()>,
+
+    // Flushes and compactions in progress, by job id. Guarded by its own lock so that
+    // progress can be queried while a job holds the DB mutex.
+    jobs: Mutex<BTreeMap<u64, Arc<JobProgress>>>,
+    next_job_id: AtomicU64,
+
+    statistics: Arc<Statistics>,
+    // Membership in options.metrics_registry, if any
+    metrics_registration: Option<Registration>,
+}
+
+impl DB {
+    fn new(raw_options: &Options, dbname: &str, read_only: bool) -> Self {
+        let internal_comparator = InternalKeyComparator::new(raw_options.comparator.clone());
+        let mut options = sanitize_options(&internal_comparator, raw_options);
+        // Without the `std` feature there may be no system clock to timestamp a LOG
+        // file with, so only a logger set in the options is used.
+        if options.info_log.is_none() && !read_only && cfg!(feature = "std") {
+            // Open a log file in the same directory as the db
+            let _ = options.env.create_dir(dbname); // In case it does not exist
+            options.info_log = RollingLogger::open(
+                options.env.clone(),
+                &info_log_file_name(dbname),
+                &old_info_log_file_name(dbname),
+                options.max_log_file_size,
+                options.info_log_level,
+            )
+            .ok()
+            .map(|logger| Arc::new(logger) as Arc<dyn Logger>);
+        }
+        let table_cache = Arc::new(TableCache::new(dbname, &options, table_cache_size(&options)));
+        let versions = VersionSet::new(dbname, &options, internal_comparator.clone());
+        let purge_queue = Arc::new(PurgeQueue::new(
+            dbname,
+            options.env.clone(),
+            table_cache.clone(),
+            options.listeners.clone(),
+        ));
+        Self {
+            dbname: dbname.to_string(),
+            env: options.env.clone(),
+            state: Mutex::new(DBState {
+                db_lock: None,
+                mem: Arc::new(create_memtable(
+                    internal_comparator.clone(),
+                    Arena::with_block_size(options.arena_block_size),
+                    options.allow_concurrent_memtable_write,
+                    false,
+                )),
+                imm: None,
+                flush: None,
+                compaction: None,
+                log: None,
+                logfile_number: 0,
+                versions,
+                prepared: None,
+                bg_error: Ok(()),
+                vetoed_compactions: HashSet::new(),
+                stats: Default::default(),
+                unexpected_files: Vec::new(),
+                bulk_load: false,
+                replayed_records: BTreeMap::new(),
+                spare_arena: None,
+                obsolete_logs: Vec::new(),
+            }),
+            internal_comparator,
+            prefix_extractor: raw_options.prefix_extractor.clone(),
+            options,
+            table_cache,
+            snapshots: SnapshotList::new(),
+            read_only,
+            secondary: false,
+            purge_queue,
+            write_queue: Mutex::new(WriteQueue::default()),
+            write_queue_cv: Condvar::new(),
+            write_lock: Mutex::new(()),
+            jobs: Mutex::new(BTreeMap::new()),
+            next_job_id: AtomicU64::new(1),
+            statistics: Arc::new(Statistics::default()),
+            metrics_registration: None,
+        }
+    }
+
+    /// Open the database with the specified `name`. Returns the opened database on
+    /// success, or an error otherwise. Fails with an `InvalidArgument` error if new
+    /// tables cannot be written in `options.format_version`.
+    pub fn open(options: Options, name: &str) -> Result<DB> {
+        check_format_version(options.format_version, options.checksum)?;
+        let mut db = DB::new(&options, name, false);
+        {
+            let mut state = db.lock();
+            let mut edit = VersionEdit::new();
+            let recovered_logs = db.recover(&mut state, &mut edit)?;
+
+            let new_log_number = state.versions.new_file_number();
+            let file = db.env.new_writable_file(&log_file_name(name, new_log_number))?;
+            edit.set_log_number(new_log_number);
+            state.log = Some(db.new_log_writer(file));
+            state.logfile_number = new_log_number;
+            state.versions.log_and_apply(&mut edit)?;
+            // The recovered logs are now obsolete: their contents live in tables.
+            for number in recovered_logs {
+                db.retire_log(&mut state, number);
+            }
+            db.maybe_compact(&mut state);
+            state.bg_error.clone()?;
+            if db.options.max_open_files == -1 {
+                db.load_table_readers(&state.versions.current());
+            }
+            db.update_level_metrics(&state);
+        }
+        db.register_metrics();
+        Ok(db)
+    }
+
+    /// Open the database with the specified `name` for reading only. The state is
+    /// recovered from the manifest and the logs are replayed into the memtable, but
+    /// nothing is written to the database directory, not even the LOG file, and the
+    /// database lock is not taken. This makes it possible to read a database that is
+    /// open in another process; the view is that of the moment it was opened.
+    ///
+    /// All writes, flushes and compactions fail with a `NotSupported` error. With
+    /// `error_if_wal_exists`, opening fails with an `InvalidArgument` error if a log
+    /// holds data that is not yet in a table.
+    pub fn open_read_only(options: Options, name: &str, error_if_wal_exists: bool) -> Result<DB> {
+        let mut db = DB::new(&options, name, true);
+        {
+            let mut state = db.lock();
+            db.recover_read_only(&mut state, error_if_wal_exists)?;
+            if db.options.max_open_files == -1 {
+                db.load_table_readers(&state.versions.current());
+            }
+            db.update_level_metrics(&state);
+        }
+        db.register_metrics();
+        Ok(db)
+    }
+
+    /// Open the database at `primary_path`, which another process owns, as a secondary
+    /// instance: a read-only instance that can follow the primary's writes by calling
+    /// `try_catch_up_with_primary()`. Its LOG file is kept in `secondary_path`.
+    ///
+    /// The secondary reads the primary's files in place. A table that the primary
+    /// deletes after a compaction remains readable only if the secondary has it open;
+    /// catch up regularly so that reads use the current tables.
+    pub fn open_as_secondary(options: Options, primary_path: &str, secondary_path: &str) -> Result<DB> {
+        let mut options = options;
+        if options.info_log.is_none() && cfg!(feature = "std") {
+            let env = options.env.clone();
+            let _ = env.create_dir(secondary_path);
+            let logger = RollingLogger::open(
+                env,
+                &info_log_file_name(secondary_path),
+                &old_info_log_file_name(secondary_path),
+                options.max_log_file_size,
+                options.info_log_level,
+            )?;
+            options.info_log = Some(Arc::new(logger));
+        }
+        let mut db = DB::new(&options, primary_path, true);
+        db.secondary = true;
+        {
+            let mut state = db.lock();
+            if !db.env.file_exists(&current_file_name(primary_path)) {
+                return LEVELDB_ERR!(InvalidArgument, "database does not exist");
+            }
+            db.catch_up(&mut state)?;
+            db.update_level_metrics(&state);
+        }
+        db.register_metrics();
+        Ok(db)
+    }
+
+    /// Bring a secondary instance up to date with its primary: read the current
+    /// manifest to pick up flushes and compactions, and replay the records appended to
+    /// the primary's logs since the last call. Reads through iterators and snapshots
+    /// created before keep their view. Fails with a `NotSupported` error if the
+    /// database was not opened with `open_as_secondary()`.
+    pub fn try_catch_up_with_primary(&self) -> Result<()> {
+        if !self.secondary {
+            return LEVELDB_ERR!(NotSupported, "not a secondary instance");
+        }
+        let mut state = self.lock();
+        self.catch_up(&mut state)?;
+        self.update_level_metrics(&state);
+        Ok(())
+    }
+
+    fn catch_up(&self, state: &mut DBState) -> Result<()> {
+        let mut versions = VersionSet::new(&self.dbname, &self.options, self.internal_comparator.clone());
+        versions.recover()?;
+        let filenames = self.env.get_children(&self.dbname)?;
+        let old_min_log = state.versions.log_number();
+        let old_last_sequence = state.versions.last_sequence();
+        state.versions = versions;
+        let logs = Self::logs_to_recover(state, &filenames);
+
+        // Once the primary has flushed a log, its records are in tables, so the
+        // memtable is rebuilt from the remaining logs.
+        let mut max_sequence = 0;
+        if state.versions.log_number() != old_min_log || state.replayed_records.is_empty() {
+            state.mem = Arc::new(self.new_memtable(state));
+            state.replayed_records.clear();
+        } else {
+            // The entries already in the memtable may be newer than the manifest says.
+            max_sequence = old_last_sequence;
+        }
+        let mut edit = VersionEdit::new();
+        for &number in logs.iter() {
+            let skip = state.replayed_records.get(&number).copied().unwrap_or(0);
+            let n = self.recover_log_file(state, number, skip, &mut edit, &mut max_sequence)?;
+            state.replayed_records.insert(number, n);
+        }
+        state.replayed_records.retain(|number, _| logs.contains(number));
+        if state.versions.last_sequence() < max_sequence {
+            state.versions.set_last_sequence(max_sequence);
+        }
+        Ok(())
+    }
+
+    fn register_metrics(&mut self) {
+        if let Some(registry) = self.options.metrics_registry.as_ref() {
+            let registration = registry.register(&self.dbname, self.options.metrics_labels.clone(), self.statistics.clone());
+            self.metrics_registration = Some(registration);
+        }
+    }
+
+    /// Fail with a `NotSupported` error if the database was opened read-only.
+    fn check_writable(&self) -> Result<()> {
+        match self.read_only {
+            true => LEVELDB_ERR!(NotSupported, "database opened read-only"),
+            false => Ok(()),
+        }
+    }
+
+    /// Open all the tables of `version`, for `max_open_files == -1`. Tables that fail
+    /// to open are left for the read path to report.
+    fn load_table_readers(&self, version: &Version) {
+        for f in (0..config::NUM_LEVELS).flat_map(|level| version.files(level).iter()) {
+            let _ = self.table_cache.find_table(f.number, f.file_size);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, DBState> { self.state.lock().unwrap_or_else(|e| e.into_inner()) }
+
+    fn lock_writes(&self) -> MutexGuard<'_, ()> { self.write_lock.lock().unwrap_or_else(|e| e.into_inner()) }
+
+    fn new_db(&self) -> Result<()> {
+        let mut new_db = VersionEdit::new();
+        new_db.set_comparator_name(self.internal_comparator.user_comparator().name());
+        new_db.set_log_number(0);
+        new_db.set_next_file(2);
+        new_db.set_last_sequence(0);
+
+        let manifest = descriptor_file_name(&self.dbname, 1);
+        let file = self.env.new_writable_file(&manifest)?;
+        let mut log = log_writer::Writer::new(file);
+        let mut record = Vec::new();
+        new_db.encode_to(&mut record);
+        let mut s = log.add_record(&record).and_then(|_| log.sync());
+        if s.is_ok() {
+            s = log.close();
+        }
+        if s.is_ok() {
+            // Make "CURRENT" file that points to the new manifest file.
+            s = set_current_file(self.env.as_ref(), &self.dbname, 1);
+        }
+        if s.is_err() {
+            let _ = self.env.remove_file(&manifest);
+        }
+        s
+    }
+
+    /// Recover the descriptor from persistent storage. May do a significant amount of
+    /// work to recover recently logged updates. Any changes to be made to the
+    /// descriptor are added to `edit`. Returns the numbers of the replayed log files.
+    fn recover(&self, state: &mut DBState, edit: &mut VersionEdit) -> Result<Vec<u64>> {
+        // Ignore error from create_dir since the creation of the DB is committed only
+        // when the descriptor is created, and this directory may already exist from a
+        // previous failed creation attempt.
+        let _ = self.env.create_dir(&self.dbname);
+        assert!(state.db_lock.is_none());
+        state.db_lock = Some(self.env.lock_file(&lock_file_name(&self.dbname))?);
+
+        if !self.env.file_exists(&current_file_name(&self.dbname)) {
+            if self.options.create_if_missing {
+                self.new_db()?;
+            } else {
+                return LEVELDB_ERR!(InvalidArgument, "database does not exist (create_if_missing is false)");
+            }
+        } else if self.options.error_if_exists {
+            return LEVELDB_ERR!(InvalidArgument, "database exists (error_if_exists is true)");
+        }
+
+        state.versions.recover()?;
+
+        // Recover from all newer log files than the ones named in the descriptor (new
+        // log files may have been added by the previous incarnation without
+        // registering them in the descriptor).
+        let filenames = self.env.get_children(&self.dbname)?;
+        state.unexpected_files = self.check_files(state, &filenames)?;
+        let logs = Self::logs_to_recover(state, &filenames);
+        let mut max_sequence = 0;
+        for &number in logs.iter() {
+            self.recover_log_file(state, number, 0, edit, &mut max_sequence)?;
+
+            // The previous incarnation may not have written any MANIFEST records after
+            // allocating this log number. So we manually update the file number
+            // allocation counter in VersionSet.
+            state.versions.mark_file_number_used(number);
+        }
+
+        if state.versions.last_sequence() < max_sequence {
+            state.versions.set_last_sequence(max_sequence);
+        }
+        Ok(logs)
+    }
+
+    /// Recover the state for `open_read_only()`: like `recover()`, but the logs are
+    /// replayed into the memtable instead of being written out as tables.
+    fn recover_read_only(&self, state: &mut DBState, error_if_wal_exists: bool) -> Result<()> {
+        if !self.env.file_exists(&current_file_name(&self.dbname)) {
+            return LEVELDB_ERR!(InvalidArgument, "database does not exist");
+        }
+        state.versions.recover()?;
+        let filenames = self.env.get_children(&self.dbname)?;
+        state.unexpected_files = self.check_files(state, &filenames)?;
+        let logs = Self::logs_to_recover(state, &filenames);
+        if error_if_wal_exists {
+            for &number in logs.iter() {
+                let fname = log_file_name(&self.dbname, number);
+                if self.env.get_file_size(&fname)? > 0 {
+                    return Err(Error::new(ErrorType::InvalidArgument, "write-ahead log file is not empty").with_detail(fname));
+                }
+            }
+        }
+        let mut edit = VersionEdit::new();
+        let mut max_sequence = 0;
+        for &number in logs.iter() {
+            self.recover_log_file(state, number, 0, &mut edit, &mut max_sequence)?;
+        }
+        if state.versions.last_sequence() < max_sequence {
+            state.versions.set_last_sequence(max_sequence);
+        }
+        Ok(())
+    }
+
+    /// Return the numbers of the logs in `filenames` that may hold updates missing
+    /// from the recovered version, oldest first.
+    fn logs_to_recover(state: &DBState, filenames: &[String]) -> Vec<u64> {
+        // Note that prev_log_number() is no longer used, but we pay attention to it in
+        // case we are recovering a database produced by an older version of leveldb.
+        let min_log = state.versions.log_number();
+        let prev_log = state.versions.prev_log_number();
+        let mut logs: Vec<u64> = filenames
+            .iter()
+            .filter_map(|name| parse_file_name(name))
+            .filter(|&(number, file_type)| {
+                file_type == FileType::LogFile && (number >= min_log || number == prev_log)
+            })
+            .map(|(number, _)| number)
+            .collect();
+
+        // Recover in the order in which the logs were generated
+        logs.sort_unstable();
+        logs
+    }
+
+    /// Cross-check the recovered version against `filenames`, the contents of the DB
+    /// directory. Fails with a `Corruption` error naming the first table file that is
+    /// missing or, unless `Options::skip_stats_update_on_db_open` is set, does not have
+    /// the size recorded in the MANIFEST. Returns the names of the DB files that the
+    /// recovered state does not refer to, e.g. tables left behind by a crash, and
+    /// writes them to the info log.
+    fn check_files(&self, state: &DBState, filenames: &[String]) -> Result<Vec<String>> {
+        let tables: BTreeSet<u64> = filenames
+            .iter()
+            .filter_map(|name| parse_file_name(name))
+            .filter(|&(_, file_type)| file_type == FileType::TableFile)
+            .map(|(number, _)| number)
+            .collect();
+        let current = state.versions.current();
+        let mut live = BTreeSet::new();
+        for level in 0..config::NUM_LEVELS {
+            for f in current.files(level) {
+                let fname = table_file_name(&self.dbname, f.number);
+                if !tables.contains(&f.number) {
+                    return Err(Error::new(ErrorType::Corruption, "missing table file").with_detail(fname));
+                }
+                if !self.options.skip_stats_update_on_db_open {
+                    match self.env.get_file_size(&fname) {
+                        Ok(size) if size == f.file_size => {}
+                        Ok(size) => {
+                            return Err(Error::new(ErrorType::Corruption, "table file has unexpected size").with_detail(
+                                format!("{}: expected {} bytes, found {}", fname, f.file_size, size),
+                            ))
+                        }
+                        Err(e) if e.is_not_found() => {
+                            return Err(Error::new(ErrorType::Corruption, "missing table file").with_detail(fname))
+                        }
+                        Err(e) => return Err(e.with_detail(fname)),
+                    }
+                }
+                live.insert(f.number);
+            }
+        }
+
+        let min_log = state.versions.log_number();
+        let prev_log = state.versions.prev_log_number();
+        let mut unexpected: Vec<String> = filenames
+            .iter()
+            .filter(|name| match parse_file_name(name) {
+                Some((number, FileType::TableFile)) => !live.contains(&number),
+                Some((number, FileType::LogFile)) => number < min_log && number != prev_log,
+                // Older descriptors are superseded rather than unexpected; a newer one was
+                // written by a crashed attempt to switch to it.
+                Some((number, FileType::DescriptorFile)) => number > state.versions.manifest_file_number(),
+                Some((_, FileType::TempFile)) => true,
+                _ => false,
+            })
+            .cloned()
+            .collect();
+        unexpected.sort();
+        for name in unexpected.iter() {
+            self.log(InfoLogLevel::Warn, format_args!("Unexpected file in DB directory: {}", name));
+        }
+        Ok(unexpected)
+    }
+
+    /// Replay the write batches of log file `log_number` and write them out as level-0
+    /// tables, recording the new tables in `edit`. A read-only database adds them to
+    /// its memtable instead. The first `skip` records are assumed to have been
+    /// replayed before. Returns the number of records in the log.
+    fn recover_log_file(
+        &self,
+        state: &mut DBState,
+        log_number: u64,
+        skip: usize,
+        edit: &mut VersionEdit,
+        max_sequence: &mut SequenceNumber,
+    ) -> Result<usize> {
+        // Open the log file
+        let fname = log_file_name(&self.dbname, log_number);
+        let file = self.env.new_sequential_file(&fname)?;
+        self.log(InfoLogLevel::Info, format_args!("Recovering log #{}", log_number));
+
+        // We intentionally make log::Reader do checksumming even if paranoid_checks is
+        // false so that corruptions cause entire commits to be skipped instead of
+        // propagating bad information (like overly large sequence numbers).
+        let status = Arc::new(Mutex::new(None));
+        let reporter = LogReporter {
+            info_log: self.options.info_log.clone(),
+            fname: fname.clone(),
+            status: self.options.paranoid_checks.then(|| status.clone()),
+        };
+        let mut reader = log_reader::Reader::new(file, Some(Box::new(reporter)), true);
+
+        // Read all the records and add to a memtable
+        let mut record = Vec::new();
+        let mut batch = WriteBatch::new();
+        let mut mem: Option<MemTable> = None;
+        let read_only_mem = self.read_only.then(|| state.mem.clone());
+        let mut num_records = 0;
+        while reader.read_record(&mut record) {
+            num_records += 1;
+            if num_records <= skip {
+                continue;
+            }
+            if record.len() < 12 {
+                // Log record too small; drop it like any other damaged record.
+                if self.options.paranoid_checks {
+                    return LEVELDB_ERR!(Corruption, "log record too small");
+                }
+                continue;
+            }
+            batch.set_contents(&record);
+
+            let mem_ref = match read_only_mem.as_deref() {
+                Some(mem) => mem,
+                None => mem.get_or_insert_with(|| self.new_memtable(state)),
+            };
+            batch.insert_into(mem_ref)?;
+            let last_seq = batch.sequence() + batch.count() as SequenceNumber - 1;
+            if last_seq > *max_sequence {
+                *max_sequence = last_seq;
+            }
+
+            if read_only_mem.is_none() && mem_ref.approximate_memory_usage() > self.options.write_buffer_size {
+                let full = mem.take().unwrap();
+                self.write_level0_table(state, &full, edit, None)?;
+                Self::retain_arena(state, full);
+            }
+        }
+
+        if let Some(e) = status.lock().unwrap().take() {
+            return Err(e);
+        }
+
+        if let Some(mem) = mem {
+            self.write_level0_table(state, &mem, edit, None)?;
+        }
+        Ok(num_records)
+    }
+
+    /// Register a new background job so that it is reported by `running_jobs()`.
+    fn start_job(&self, kind: BackgroundJobKind, level: usize, total_bytes: u64) -> Arc<JobProgress> {
+        let job_id = self.next_job_id.fetch_add(1, Ordering::Relaxed);
+        let progress = Arc::new(JobProgress::new(job_id, kind, level, total_bytes));
+        self.jobs.lock().unwrap().insert(job_id, progress.clone());
+        progress
+    }
+
+    fn finish_job(&self, progress: &JobProgress) { self.jobs.lock().unwrap().remove(&progress.info().job_id); }
+
+    fn record_background_error(&self, state: &mut DBState, s: &Result<()>) {
+        if state.bg_error.is_ok() {
+            if let Err(e) = s {
+                state.bg_error = Err(e.clone());
+                self.log(InfoLogLevel::Error, format_args!("Background error: {}", e));
+                for listener in self.options.listeners.iter() {
+                    listener.on_background_error(e);
+                }
+            }
+        }
+    }
+
+    fn log(&self, level: InfoLogLevel, args: fmt::Arguments<'_>) {
+        if let Some(info_log) = self.options.info_log.as_ref() {
+            info_log.logv(level, args);
+        }
+    }
+
+    fn notify_table_file_created(&self, number: u64, file_size: u64, level: usize, reason: TableFileCreationReason) {
+        if self.options.listeners.is_empty() {
+            return;
+        }
+        let info = TableFileCreationInfo {
+            file_number: number,
+            file_path: table_file_name(&self.dbname, number),
+            file_size,
+            level,
+            reason,
+        };
+        for listener in self.options.listeners.iter() {
+            listener.on_table_file_created(&info);
+        }
+    }
+
+    fn write_level0_table(
+        &self,
+        state: &mut DBState,
+        mem: &MemTable,
+        edit: &mut VersionEdit,
+        base: Option<&Version>,
+    ) -> Result<FlushJobInfo> {
+        let job = self.start_flush_job(state, mem);
+        let result = build_level0_table(&self.dbname, &self.flush_options(), &self.table_cache, mem, job.number);
+        self.finish_flush_job(state, job, result, edit, base)
+    }
+
+    /// Options of the tables written by flushes. The level is only picked once the
+    /// table is built, so flushes always use the compression of level 0.
+    fn flush_options(&self) -> Options {
+        Options {
+            compression: self.options.compression_for_level(0),
+            ..self.options.clone()
+        }
+    }
+
+    fn start_flush_job(&self, state: &mut DBState, mem: &MemTable) -> FlushJob {
+        let memtable_bytes = mem.approximate_memory_usage() as u64;
+        let job = FlushJob {
+            number: state.versions.new_file_number(),
+            start_micros: self.env.now_micros(),
+            memtable_bytes,
+            progress: self.start_job(BackgroundJobKind::Flush, 0, memtable_bytes),
+        };
+        self.log(InfoLogLevel::Info, format_args!("Level-0 table #{}: started", job.number));
+        job
+    }
+
+    /// Account for the table built by `job` and add it to `edit`, at the level picked
+    /// against `base`.
+    fn finish_flush_job(
+        &self,
+        state: &mut DBState,
+        job: FlushJob,
+        result: Result<FileMetaData>,
+        edit: &mut VersionEdit,
+        base: Option<&Version>,
+    ) -> Result<FlushJobInfo> {
+        match result.as_ref() {
+            Ok(meta) => self.log(
+                InfoLogLevel::Info,
+                format_args!("Level-0 table #{}: {} bytes OK", job.number, meta.file_size),
+            ),
+            Err(e) => self.log(InfoLogLevel::Error, format_args!("Level-0 table #{}: {}", job.number, e)),
+        }
+        let file_size = result.as_ref().map_or(0, |meta| meta.file_size);
+        job.progress.add_bytes_read(job.memtable_bytes);
+        job.progress.add_bytes_written(file_size);
+        self.finish_job(&job.progress);
+        let meta = match result {
+            Ok(meta) => meta,
+            Err(e) => {
+                state.versions.reuse_file_number(job.number);
+                return Err(e);
+            }
+        };
+
+        // Note that if file_size is zero, the file has been deleted and should not be
+        // added to the manifest.
+        let mut level = 0;
+        if meta.file_size > 0 {
+            if let Some(base) = base {
+                level =
+                    base.pick_level_for_memtable_output(&self.options, meta.smallest.user_key(), meta.largest.user_key());
+            }
+            edit.add_file_meta(level, &meta);
+        }
+        state.stats[level].add(self.env.now_micros() - job.start_micros, 0, meta.file_size);
+        Ok(FlushJobInfo {
+            job_id: job.progress.info().job_id,
+            file_number: meta.number,
+            file_size: meta.file_size,
+            level,
+        })
+    }
+
+    /// Write the contents of `mem` to a new table and record in the manifest that all
+    /// logs before `log_number` are no longer needed.
+    fn compact_memtable(&self, state: &mut DBState, mem: &MemTable, log_number: u64) -> Result<()> {
+        // Save the contents of the memtable as a new Table
+        let mut edit = VersionEdit::new();
+        let base = state.versions.current();
+        let info = self.write_level0_table(state, mem, &mut edit, Some(&base))?;
+        self.install_flush(state, edit, &info, log_number)
+    }
+
+    /// Apply `edit`, which adds the table of a flush, and record that all logs before
+    /// `log_number` are no longer needed.
+    fn install_flush(&self, state: &mut DBState, mut edit: VersionEdit, info: &FlushJobInfo, log_number: u64) -> Result<()> {
+        // Replace immutable memtable with the generated Table
+        edit.set_prev_log_number(0);
+        edit.set_log_number(log_number); // Earlier logs no longer needed
+        state.versions.log_and_apply(&mut edit)?;
+        self.statistics.record_flush(state.mem.approximate_memory_usage() as u64);
+        self.update_level_metrics(state);
+        if info.file_size > 0 {
+            self.notify_table_file_created(info.file_number, info.file_size, info.level, TableFileCreationReason::Flush);
+        }
+        for listener in self.options.listeners.iter() {
+            listener.on_flush_completed(info);
+        }
+        Ok(())
+    }
+
+    /// Publish the number and size of the files at each level of the current version.
+    fn update_level_metrics(&self, state: &DBState) {
+        for level in 0..config::NUM_LEVELS {
+            self.statistics.set_level(
+                level,
+                state.versions.num_level_files(level) as u64,
+                state.versions.num_level_bytes(level),
+            );
+        }
+    }
+
+    /// Start creating the next log file and memtable in a high priority background
+    /// job, so that `switch_memtable()` does not have to wait for file creation and
+    /// allocation.
+    fn prepare_next_memtable(&self, state: &mut DBState) {
+        let log_number = state.versions.new_file_number();
+        let env = self.env.clone();
+        let fname = log_file_name(&self.dbname, log_number);
+        let icmp = self.internal_comparator.clone();
+        let arena = self.new_arena(state);
+        let concurrent = self.options.allow_concurrent_memtable_write;
+        let bulk_load = state.bulk_load;
+        let (tx, result) = mpsc::sync_channel(1);
+        self.env.schedule(
+            Box::new(move || {
+                let prepared = env
+                    .new_writable_file(&fname)
+                    .map(|file| (file, create_memtable(icmp, arena, concurrent, bulk_load)));
+                let _ = tx.send(prepared);
+            }),
+            Priority::High,
+        );
+        state.prepared = Some(PreparedMemtable { log_number, result });
+    }
+
+    /// Discard the prepared log file and memtable, if any.
+    fn discard_prepared_memtable(&self, state: &mut DBState) {
+        if let Some(prepared) = state.prepared.take() {
+            let log_number = prepared.log_number;
+            if prepared.wait().is_ok() {
+                let _ = self.env.remove_file(&log_file_name(&self.dbname, log_number));
+            }
+        }
+    }
+
+    /// Start a fresh log file and memtable and flush the old memtable to a table,
+    /// waiting for the flush to finish.
+    fn switch_memtable(&self, state: &mut DBState) -> Result<()> {
+        self.start_memtable_switch(state)?;
+        self.finish_flush(state, true).map(|_| ())
+    }
+
+    /// Start a fresh log file and memtable, and flush the old memtable to a table in a
+    /// background job while writes go to the new one. Only one memtable can be
+    /// immutable at a time, so this waits for the flush of the previous one first.
+    fn start_memtable_switch(&self, state: &mut DBState) -> Result<()> {
+        self.finish_flush(state, true)?;
+        let (new_log_number, file, new_mem) = match state.prepared.take() {
+            // Usually the preparation finished long ago and this does not block.
+            Some(prepared) => {
+                let log_number = prepared.log_number;
+                let (file, mem) = prepared.wait()?;
+                (log_number, file, mem)
+            }
+            None => {
+                let new_log_number = state.versions.new_file_number();
+                match self.env.new_writable_file(&log_file_name(&self.dbname, new_log_number)) {
+                    Ok(file) => (new_log_number, file, self.new_memtable(state)),
+                    Err(e) => {
+                        // Avoid chewing through file number space in a tight loop.
+                        state.versions.reuse_file_number(new_log_number);
+                        return Err(e);
+                    }
+                }
+            }
+        };
+        let old_log_number = state.logfile_number;
+        state.log = Some(self.new_log_writer(file));
+        state.logfile_number = new_log_number;
+        let imm = mem::replace(&mut state.mem, Arc::new(new_mem));
+
+        let job = self.start_flush_job(state, &imm);
+        let (tx, result) = mpsc::sync_channel(1);
+        let dbname = self.dbname.clone();
+        let options = self.flush_options();
+        let table_cache = self.table_cache.clone();
+        let mem = imm.clone();
+        let number = job.number;
+        self.env.schedule(
+            Box::new(move || {
+                let result = build_level0_table(&dbname, &options, &table_cache, &mem, number);
+                // Let the flushed memtable's arena be reused as soon as it is installed.
+                drop(mem);
+                let _ = tx.send(result);
+            }),
+            Priority::High,
+        );
+        state.imm = Some(imm);
+        state.flush = Some(BackgroundFlush {
+            job,
+            old_log_number,
+            new_log_number,
+            result,
+        });
+        Ok(())
+    }
+
+    /// Install the table of the background flush, if one is running, once it is built.
+    /// Unless `wait` is set, only a flush that already finished is installed. Returns
+    /// whether a flush was installed.
+    fn finish_flush(&self, state: &mut DBState, wait: bool) -> Result<bool> {
+        let received = match state.flush.as_ref() {
+            None => return Ok(false),
+            Some(flush) if wait => flush.result.recv().ok(),
+            Some(flush) => match flush.result.try_recv() {
+                Err(mpsc::TryRecvError::Empty) => return Ok(false),
+                result => result.ok(),
+            },
+        };
+        let flush = state.flush.take().unwrap();
+        let result = received.unwrap_or_else(|| LEVELDB_ERR!(IOError, "flush job failed"));
+        let mut edit = VersionEdit::new();
+        let base = state.versions.current();
+        let s = self
+            .finish_flush_job(state, flush.job, result, &mut edit, Some(&base))
+            .and_then(|info| self.install_flush(state, edit, &info, flush.new_log_number));
+        match s {
+            Ok(()) => {
+                self.retire_log(state, flush.old_log_number);
+                // Readers that still refer to the flushed memtable keep its arena alive.
+                if let Some(Ok(imm)) = state.imm.take().map(Arc::try_unwrap) {
+                    Self::retain_arena(state, imm);
+                }
+                Ok(true)
+            }
+            Err(e) => {
+                // The memtable stays readable, and its log is recovered on the next open.
+                let s = Err(e);
+                self.record_background_error(state, &s);
+                s.map(|_| false)
+            }
+        }
+    }
+
+    /// Return an arena for a new memtable, reusing the blocks of the last flushed
+    /// memtable if they were retained.
+    fn new_arena(&self, state: &mut DBState) -> Arena {
+        state
+            .spare_arena
+            .take()
+            .unwrap_or_else(|| Arena::with_block_size(self.options.arena_block_size))
+    }
+
+    fn new_log_writer(&self, mut file: Box<dyn WritableFile>) -> log_writer::Writer {
+        file.set_preallocation_block_size(self.options.wal_preallocate_block_size);
+        file.set_bytes_per_sync(self.options.wal_bytes_per_sync);
+        let mut log = log_writer::Writer::new(file);
+        log.set_manual_flush(self.options.manual_wal_flush);
+        log
+    }
+
+    fn new_memtable(&self, state: &mut DBState) -> MemTable {
+        create_memtable(
+            self.internal_comparator.clone(),
+            self.new_arena(state),
+            self.options.allow_concurrent_memtable_write,
+            state.bulk_load,
+        )
+    }
+
+    /// Keep the arena of a flushed memtable for the next memtable, unless an iterator
+    /// still refers to the memtable's contents.
+    fn retain_arena(state: &mut DBState, mem: MemTable) {
+        if let Some(mut arena) = mem.into_arena() {
+            arena.reset();
+            state.spare_arena = Some(arena);
+        }
+    }
+
+    /// Have the writers of the group at the front of the write queue, which ends at
+    /// `last_id`, insert their batches into `mem` in parallel, numbering them from
+    /// `sequence` in queue order. Waits until they are all done. Called by the write
+    /// leader without the DB mutex, so reads and background work go on meanwhile.
+    fn insert_group_concurrently(&self, mem: &Arc<MemTable>, mut sequence: SequenceNumber, last_id: u64) -> Result<()> {
+        let mut queue = self.write_queue.lock().unwrap();
+        let mut own = None;
+        let mut remaining = 0;
+        for (i, w) in queue.writers.iter_mut().take_while(|w| w.id <= last_id).enumerate() {
+            let batch = w.batch.as_mut().unwrap();
+            batch.set_sequence(sequence);
+            sequence += batch.count() as SequenceNumber;
+            if i == 0 {
+                own = w.batch.take();
+            } else {
+                w.insert = Some(mem.clone());
+                remaining += 1;
+            }
+        }
+        queue.inserting = remaining;
+        drop(queue);
+        self.write_queue_cv.notify_all();
+
+        let s = own.unwrap().insert_into(mem);
+        let mut queue = self.write_queue.lock().unwrap();
+        while queue.inserting > 0 {
+            queue = self.write_queue_cv.wait(queue).unwrap();
+        }
+        // Take the error of the other writers even if ours failed too, so that it does
+        // not leak into the next group.
+        let error = queue.insert_error.take();
+        s?;
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Hold back a write while level-0 has too many files: delay it once past
+    /// `level0_slowdown_writes_trigger`, and wait for compactions to bring level-0 under
+    /// `level0_stop_writes_trigger`. Compactions that a listener delays are offered
+    /// again while waiting. Fails with an `Aborted` error if no compaction can run to
+    /// bring level-0 down, e.g. because a listener vetoed them.
+    fn throttle_writes<'a>(&'a self, mut state: MutexGuard<'a, DBState>) -> Result<MutexGuard<'a, DBState>> {
+        let mut delayed = false;
+        let mut stopped = false;
+        loop {
+            state.bg_error.clone()?;
+            let level0 = state.versions.num_level_files(0);
+            if state.bulk_load || level0 < self.options.level0_slowdown_writes_trigger {
+                return Ok(state);
+            }
+            if level0 < self.options.level0_stop_writes_trigger {
+                if delayed {
+                    return Ok(state);
+                }
+                // Yield some of the write throughput to compactions rather than stall
+                // a single write for a long time once the stop trigger is reached.
+                drop(state);
+                self.env.sleep_for_microseconds(1000);
+                delayed = true;
+            } else {
+                if !stopped {
+                    self.log(InfoLogLevel::Info, format_args!("Too many L0 files; waiting..."));
+                    stopped = true;
+                }
+                let delayed = self.maybe_compact(&mut state);
+                if state.versions.num_level_files(0) < self.options.level0_stop_writes_trigger {
+                    continue;
+                }
+                state.bg_error.clone()?;
+                if state.compaction.is_some() {
+                    state = self.wait_for_compaction(state);
+                    continue;
+                }
+                if !delayed {
+                    // Vetoed compactions are never offered again, so waiting would
+                    // never end.
+                    return LEVELDB_ERR!(Aborted, "too many level-0 files and no compaction allowed to reduce them");
+                }
+                drop(state);
+                self.env.sleep_for_microseconds(DELAYED_COMPACTION_RETRY_INTERVAL.as_micros() as u64);
+            }
+            state = self.lock();
+        }
+    }
+
+    fn make_room_for_write(&self, state: &mut DBState) -> Result<()> {
+        state.bg_error.clone()?;
+        // Install the table of a flush that finished in the background
+        let mut flushed = self.finish_flush(state, false)?;
+        let usage = state.mem.approximate_memory_usage();
+        let write_buffer_size = match state.bulk_load {
+            true => self.options.write_buffer_size.max(self.options.bulk_load_write_buffer_size),
+            false => self.options.write_buffer_size,
+        };
+        if usage <= write_buffer_size {
+            // There is room in current memtable. Once it is mostly full, get the next
+            // log file and memtable ready so that switching to them is cheap.
+            if state.prepared.is_none() && usage >= write_buffer_size / 4 * 3 {
+                self.prepare_next_memtable(state);
+            }
+        } else {
+            // The previous memtable is still being flushed, so writes stall until it
+            // is done.
+            flushed |= self.finish_flush(state, true)?;
+            self.start_memtable_switch(state)?;
+        }
+        if flushed || state.compaction.is_some() {
+            self.maybe_compact(state);
+        }
+        state.bg_error.clone()
+    }
+
+    /// Install the outputs of the background compaction once it is done, and start the
+    /// next compaction if a level needs one. Does not start any during a bulk load.
+    /// Returns true iff a listener delayed the next compaction.
+    fn maybe_compact(&self, state: &mut DBState) -> bool {
+        loop {
+            let _ = self.finish_compaction(state);
+            if state.compaction.is_some()
+                || state.bg_error.is_err()
+                || state.bulk_load
+                || !state.versions.needs_compaction()
+            {
+                return false;
+            }
+            let c = match state.versions.pick_compaction() {
+                Some(c) => c,
+                None => return false,
+            };
+            match self.approve_compaction(state, &c, false) {
+                Ok(info) => self.start_compaction(state, c, info),
+                // Picking again would yield the same compaction; retry on the next
+                // call instead.
+                Err(decision) => return decision == CompactionDecision::Delay,
+            }
+        }
+    }
+
+    /// Describe `c` to the registered listeners and ask whether it may run. Returns
+    /// the description of the compaction if it may.
+    fn approve_compaction(
+        &self,
+        state: &mut DBState,
+        c: &Compaction,
+        manual: bool,
+    ) -> std::result::Result<CompactionJobInfo, CompactionDecision> {
+        let mut input_files = Vec::new();
+        let mut input_bytes = 0;
+        for which in 0..2 {
+            for i in 0..c.num_input_files(which) {
+                input_files.push(c.input(which, i).number);
+                input_bytes += c.input(which, i).file_size;
+            }
+        }
+        let info = CompactionJobInfo {
+            job_id: self.next_job_id.fetch_add(1, Ordering::Relaxed),
+            level: c.level(),
+            output_level: c.output_level(),
+            input_files,
+            input_bytes,
+            manual,
+            trivial_move: c.is_trivial_move(),
+        };
+        if self.options.listeners.is_empty() {
+            return Ok(info);
+        }
+
+        // Forget vetoes for files that have since moved out of their level.
+        let current = state.versions.current();
+        state.vetoed_compactions.retain(|(level, files)| {
+            files
+                .iter()
+                .any(|number| current.files(*level).iter().any(|f| f.number == *number))
+        });
+        let key = (info.level, info.input_files.clone());
+        if state.vetoed_compactions.contains(&key) {
+            return Err(CompactionDecision::Veto);
+        }
+
+        for listener in self.options.listeners.iter() {
+            match listener.on_compaction_begin(&info) {
+                CompactionDecision::Proceed => {}
+                CompactionDecision::Delay => return Err(CompactionDecision::Delay),
+                CompactionDecision::Veto => {
+                    state.vetoed_compactions.insert(key);
+                    return Err(CompactionDecision::Veto);
+                }
+            }
+        }
+        Ok(info)
+    }
+
+    /// Start `c`, which the listeners approved as `info`. A trivial move is applied right
+    /// away. Any other compaction writes its outputs in a low priority background job,
+    /// and `finish_compaction()` installs them once it is done.
+    /// REQUIRES: no compaction is running
+    fn start_compaction(&self, state: &mut DBState, mut c: Compaction, info: CompactionJobInfo) {
+        let progress = Arc::new(JobProgress::new(
+            info.job_id,
+            BackgroundJobKind::Compaction,
+            info.level,
+            info.input_bytes,
+        ));
+        self.jobs.lock().unwrap().insert(info.job_id, progress.clone());
+        if c.is_trivial_move() {
+            // Move file to next level
+            assert_eq!(c.num_input_files(0), 1);
+            let f = c.input(0, 0).clone();
+            let level = c.level();
+            c.edit().remove_file(level, f.number);
+            c.edit().add_file_meta(level + 1, &f);
+            let s = state.versions.log_and_apply(c.edit());
+            self.log(
+                InfoLogLevel::Info,
+                format_args!("Moved #{} to level-{} {} bytes {}", f.number, level + 1, f.file_size, status_string(&s)),
+            );
+            self.finish_compaction_job(state, &info, &progress, &s);
+            return;
+        }
+
+        self.log(
+            InfoLogLevel::Info,
+            format_args!(
+                "Compacting {}@{} + {}@{} files",
+                c.num_input_files(0),
+                c.level(),
+                c.num_input_files(1),
+                c.output_level()
+            ),
+        );
+        let compaction_filter: Option<Arc<dyn CompactionFilter>> = match (
+            self.options.compaction_filter.as_ref(),
+            self.options.compaction_filter_factory.as_ref(),
+        ) {
+            (Some(filter), _) => Some(filter.clone()),
+            (None, Some(factory)) => Some(factory.create_compaction_filter(&info).into()),
+            (None, None) => None,
+        };
+        let boundaries = match c.level() {
+            0 => c.subcompaction_boundaries(self.options.max_subcompactions),
+            _ => Vec::new(),
+        };
+        let context = CompactionContext {
+            dbname: self.dbname.clone(),
+            env: self.env.clone(),
+            options: self.options.clone(),
+            icmp: self.internal_comparator.clone(),
+            table_cache: self.table_cache.clone(),
+            file_numbers: state.versions.file_numbers(),
+            smallest_snapshot: self
+                .snapshots
+                .oldest()
+                .unwrap_or_else(|| state.versions.last_sequence()),
+            compaction_filter,
+            progress: progress.clone(),
+        };
+        let start_micros = self.env.now_micros();
+        let result = Arc::new(CompactionResult::default());
+        let work = c.subcompaction();
+        let done = result.clone();
+        self.env.schedule(
+            Box::new(move || {
+                let outputs = panic::catch_unwind(AssertUnwindSafe(|| context.run(&work, &boundaries)))
+                    .unwrap_or_else(|_| LEVELDB_ERR!(IOError, "compaction job failed"));
+                // Let the inputs be deleted as soon as the outputs are installed.
+                drop(work);
+                done.set(outputs);
+            }),
+            Priority::Low,
+        );
+        state.compaction = Some(BackgroundCompaction {
+            c,
+            info,
+            progress,
+            start_micros,
+            result,
+        });
+    }
+
+    /// Install the outputs of the background compaction, if one is running and done.
+    /// Returns whether one was installed.
+    fn finish_compaction(&self, state: &mut DBState) -> Result<bool> {
+        let outputs = match state.compaction.as_ref().and_then(|bg| bg.result.take()) {
+            Some(outputs) => outputs,
+            None => return Ok(false),
+        };
+        let bg = state.compaction.take().unwrap();
+        let s = match outputs {
+            Ok(outputs) => self.install_compaction(state, bg.c, &outputs, bg.start_micros),
+            Err(e) => {
+                self.log(InfoLogLevel::Error, format_args!("Compaction error: {}", e));
+                Err(e)
+            }
+        };
+        self.finish_compaction_job(state, &bg.info, &bg.progress, &s);
+        s.map(|_| true)
+    }
+
+    /// Wait for the background compaction, if one is running, and install its outputs.
+    /// The DB mutex is released while waiting. A failed compaction is recorded as the
+    /// background error.
+    fn wait_for_compaction<'a>(&'a self, mut state: MutexGuard<'a, DBState>) -> MutexGuard<'a, DBState> {
+        while let Some(result) = state.compaction.as_ref().map(|bg| bg.result.clone()) {
+            drop(state);
+            result.wait(None);
+            state = self.lock();
+            let _ = self.finish_compaction(&mut state);
+        }
+        state
+    }
+
+    fn install_compaction(&self, state: &mut DBState, mut c: Compaction, outputs: &[FileMetaData], start_micros: u64) -> Result<()> {
+        let bytes_read = (0..2)
+            .flat_map(|which| (0..c.num_input_files(which)).map(move |i| (which, i)))
+            .map(|(which, i)| c.input(which, i).file_size)
+            .sum();
+        let bytes_written = outputs.iter().map(|out| out.file_size).sum();
+        state.stats[c.output_level()].add(self.env.now_micros() - start_micros, bytes_read, bytes_written);
+        let s = self.install_compaction_results(state, &mut c, outputs);
+        self.log(
+            InfoLogLevel::Info,
+            format_args!(
+                "Compacted {}@{} + {}@{} files => {} bytes {}",
+                c.num_input_files(0),
+                c.level(),
+                c.num_input_files(1),
+                c.output_level(),
+                bytes_written,
+                status_string(&s)
+            ),
+        );
+        if s.is_ok() {
+            for out in outputs.iter().filter(|out| out.file_size > 0) {
+                self.notify_table_file_created(out.number, out.file_size, c.output_level(), TableFileCreationReason::Compaction);
+            }
+            // The inputs are no longer referenced by the current version, but may still
+            // be read through older versions.
+            for which in 0..2 {
+                for i in 0..c.num_input_files(which) {
+                    self.purge_queue.add(c.input(which, i).clone());
+                }
+            }
+            drop(c);
+            self.purge_queue.purge();
+        } else {
+            for out in outputs.iter() {
+                let _ = self.env.remove_file(&table_file_name(&self.dbname, out.number));
+            }
+        }
+        s
+    }
+
+    fn install_compaction_results(&self, state: &mut DBState, c: &mut Compaction, outputs: &[FileMetaData]) -> Result<()> {
+        // Add compaction outputs
+        c.add_input_deletions();
+        let level = c.output_level();
+        for out in outputs.iter() {
+            c.edit().add_file_meta(level, out);
+        }
+        state.versions.log_and_apply(c.edit())
+    }
+
+    /// Account for the end of the compaction job described by `info`, with status `s`.
+    fn finish_compaction_job(&self, state: &mut DBState, info: &CompactionJobInfo, progress: &JobProgress, s: &Result<()>) {
+        self.finish_job(progress);
+        if s.is_ok() {
+            let info = progress.info();
+            self.statistics.record_compaction(info.bytes_read, info.bytes_written);
+            self.update_level_metrics(state);
+        }
+        for listener in self.options.listeners.iter() {
+            listener.on_compaction_completed(info, s.is_ok());
+        }
+        self.record_background_error(state, s);
+    }
+
+    /// Set the database entry for `key` to `value`. Returns an error on failure.
+    ///
+    /// Note: consider setting `options.sync = true`.
+    pub fn put(&self, options: &WriteOptions, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write(options, batch)
+    }
+
+    /// Remove the database entry (if any) for `key`. Returns an error on failure. It
+    /// is not an error if `key` did not exist in the database.
+    ///
+    /// Note: consider setting `options.sync = true`.
+    pub fn delete(&self, options: &WriteOptions, key: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(options, batch)
+    }
+
+    /// Add `operand` to the merge operands of `key`. Reads combine the operands with the
+    /// existing value of `key` through `Options::merge_operator`. Fails with a
+    /// `NotSupported` error if the database has no merge operator.
+    ///
+    /// Note: consider setting `options.sync = true`.
+    pub fn merge(&self, options: &WriteOptions, key: &[u8], operand: &[u8]) -> Result<()> {
+        if self.options.merge_operator.is_none() {
+            return LEVELDB_ERR!(NotSupported, "no merge operator configured");
+        }
+        let mut batch = WriteBatch::new();
+        batch.merge(key, operand);
+        self.write(options, batch)
+    }
+
+    /// Apply the specified updates to the database. Returns an error on failure. A batch
+    /// holding a key or value above `Options::max_key_size` or `Options::max_value_size`
+    /// is rejected as a whole with an `InvalidArgument` error.
+    ///
+    /// Note: consider setting `options.sync = true`.
+    pub fn write(&self, options: &WriteOptions, updates: WriteBatch) -> Result<()> {
+        let start_micros = self.env.now_micros();
+        let s = self.write_impl(options, updates);
+        self.statistics.record_write_micros(self.env.now_micros().saturating_sub(start_micros));
+        s
+    }
+
+    fn write_impl(&self, options: &WriteOptions, updates: WriteBatch) -> Result<()> {
+        self.check_writable()?;
+        updates.check_entry_sizes(self.options.max_key_size, self.options.max_value_size)?;
+
+        // Wait until a leader committed the write, or until it is first in line and
+        // commits itself the writes queued behind it.
+        let mut queue = self.write_queue.lock().unwrap();
+        let id = queue.push(options.sync, updates);
+        loop {
+            if let Some(s) = queue.results.remove(&id) {
+                return s;
+            }
+            if queue.writers.front().map(|w| w.id) == Some(id) {
+                break;
+            }
+            if let Some(mem) = queue.get_mut(id).and_then(|w| w.insert.take()) {
+                // The leader logged the group: insert our part of it.
+                let batch = queue.get_mut(id).unwrap().batch.take().unwrap();
+                drop(queue);
+                let s = batch.insert_into(&mem);
+                drop(mem);
+                queue = self.write_queue.lock().unwrap();
+                queue.inserting -= 1;
+                if let Err(e) = s {
+                    queue.insert_error.get_or_insert(e);
+                }
+                self.write_queue_cv.notify_all();
+                continue;
+            }
+            queue = self.write_queue_cv.wait(queue).unwrap();
+        }
+        let concurrent = self.options.allow_concurrent_memtable_write;
+        let (group, sync, last_id) = queue.take_group(concurrent);
+        drop(queue);
+
+        let s = self.write_group(sync, group, (concurrent && id < last_id).then_some(last_id));
+        self.write_queue.lock().unwrap().finish_group(last_id, &s);
+        // Wake up the writers of the group, and the leader of the next one.
+        self.write_queue_cv.notify_all();
+        s
+    }
+
+    /// Apply the merged batches of a write group to the log and the memtable. If
+    /// `concurrent_until` is set, each writer of the group up to that id inserts its
+    /// own batch into the memtable instead.
+    fn write_group(&self, sync: bool, mut updates: WriteBatch, concurrent_until: Option<u64>) -> Result<()> {
+        let _writes = self.lock_writes();
+        let mut state = self.throttle_writes(self.lock())?;
+        self.make_room_for_write(&mut state)?;
+
+        let last_sequence = state.versions.last_sequence();
+        if !sequences_available(last_sequence, updates.count() as u64) {
+            // Wrapping around would break the ordering of internal keys, so the database
+            // stops accepting writes instead.
+            return LEVELDB_ERR!(NotSupported, "sequence numbers exhausted");
+        }
+        updates.set_sequence(last_sequence + 1);
+        let last_sequence = last_sequence + updates.count() as SequenceNumber;
+
+        // Add to log and apply to memtable. The write lock keeps the log and memtable
+        // in place, so the mutex is released meanwhile: reads, flushes and compactions
+        // go on, and the new entries stay invisible until the last sequence is
+        // published.
+        let mut log = state.log.take().unwrap();
+        let mem = state.mem.clone();
+        drop(state);
+        let mut s = log.add_record(updates.contents());
+        if s.is_ok() && sync {
+            s = log.sync();
+        }
+        let logged = s.is_ok();
+        if logged {
+            s = match concurrent_until {
+                Some(last_id) => self.insert_group_concurrently(&mem, updates.sequence(), last_id),
+                None => updates.insert_into(&mem),
+            };
+        }
+        let mut state = self.lock();
+        state.log = Some(log);
+        if logged {
+            // The group is in the log, and maybe partly in the memtable, even if the
+            // insert failed: its sequence numbers must never be handed out again.
+            state.versions.set_last_sequence(last_sequence);
+        }
+        if s.is_err() {
+            // The state of the log file is indeterminate: the log record we just added
+            // may or may not show up when the DB is re-opened. The memtable may miss
+            // part of the group. So we force the DB into a mode where all future writes
+            // fail.
+            self.record_background_error(&mut state, &s);
+            return s;
+        }
+        self.statistics.record_write(
+            updates.count() as u64,
+            updates.contents().len() as u64,
+            mem.approximate_memory_usage() as u64,
+        );
+        Ok(())
+    }
+
+    /// Return an iterator over the write batches that hold updates with sequence
+    /// numbers at or after `sequence`, read from the live logs and the archived ones
+    /// (see `Options::wal_archive_size_limit`), e.g. to replicate the database or
+    /// capture its changes. The first batch may start before `sequence`. Fails with a
+    /// `NotFound` error if some of the requested updates are no longer in any log.
+    ///
+    /// Entries added by `ingest_external_file()` are not written to the log, so they
+    /// are not returned.
+    pub fn get_updates_since(&self, sequence: SequenceNumber) -> Result<TransactionLogIterator> {
+        // Hold the lock while listing, so that no log moves to the archive in between.
+        let state = self.lock();
+        let mut logs = self.archived_logs();
+        logs.extend(
+            self.env
+                .get_children(&self.dbname)?
+                .iter()
+                .filter_map(|name| parse_file_name(name))
+                .filter(|&(_, file_type)| file_type == FileType::LogFile)
+                .map(|(number, _)| number),
+        );
+        let last_sequence = state.versions.last_sequence();
+        drop(state);
+        TransactionLogIterator::new(self.env.clone(), &self.dbname, logs, sequence, last_sequence)
+    }
+
+    /// Apply a batch produced by `WriteBatch::serialize`, e.g. one received from
+    /// another process. The batch is validated in full before any of it is applied, and
+    /// is assigned fresh sequence numbers from this database.
+    pub fn write_serialized_batch(&self, options: &WriteOptions, data: &[u8]) -> Result<()> {
+        let batch = WriteBatch::deserialize(data)?;
+        self.write(options, batch)
+    }
+
+    /// If the database contains an entry for `key` returns its value. If there is no
+    /// entry for `key`, returns `Ok(None)`. May return an error status on failure.
+    pub fn get(&self, options: &ReadOptions, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_pinned(options, key).map(|value| value.map(PinnableSlice::into_vec))
+    }
+
+    /// Like `get()`, but the value refers to the memtable entry or the cached data
+    /// block that holds it instead of being copied. The memtable or block stays in
+    /// memory until the value is dropped.
+    pub fn get_pinned(&self, options: &ReadOptions, key: &[u8]) -> Result<Option<PinnableSlice>> {
+        let start_micros = self.env.now_micros();
+        let (snapshot, mem, imm, current) = {
+            let state = self.lock();
+            let snapshot = match options.snapshot.as_ref() {
+                Some(s) => s.sequence_number(),
+                None => state.versions.last_sequence(),
+            };
+            (snapshot, state.mem.clone(), state.imm.clone(), state.versions.current())
+        };
+
+        self.statistics.record_reads(1);
+
+        // Unlock while reading from files and memtables
+        let lkey = LookupKey::new(key, snapshot);
+        let result = match mem.get_pinned(&lkey).or_else(|| imm.as_ref().and_then(|imm| imm.get_pinned(&lkey))) {
+            // Done
+            Some(Ok(value)) => Ok(Some(value)),
+            Some(Err(e)) if e.is_not_found() => Ok(None),
+            Some(Err(e)) => Err(e),
+            None => current.get(options, &lkey, &self.table_cache, &mut GetStats::default()),
+        };
+        let result = match result {
+            Err(e) if e.is_merge_in_progress() => self
+                .get_merged(options, &lkey, &mem, imm.as_deref(), &current)
+                .map(|value| value.map(PinnableSlice::from_vec)),
+            result => result,
+        };
+        self.check_read_result(&result);
+        self.statistics.record_get_micros(self.env.now_micros().saturating_sub(start_micros));
+        result
+    }
+
+    /// Resolve a key whose newest visible entry is a merge operand by combining the
+    /// operands with the older entries found across the memtable and all tables.
+    fn get_merged(
+        &self,
+        options: &ReadOptions,
+        lkey: &LookupKey,
+        mem: &MemTable,
+        imm: Option<&MemTable>,
+        current: &Version,
+    ) -> Result<Option<Vec<u8>>> {
+        let mut list = vec![mem.new_iterator()];
+        list.extend(imm.map(MemTable::new_iterator));
+        current.add_iterators(options, &self.table_cache, &mut list);
+        let mut iter = new_merging_iterator(Arc::new(self.internal_comparator.clone()), list);
+        iter.seek(lkey.internal_key());
+        let ucmp = self.internal_comparator.user_comparator();
+        merge_entries(ucmp.as_ref(), self.options.merge_operator.as_deref(), iter.as_mut(), lkey.user_key()).map(Some)
+    }
+
+    /// Look up many keys against the same snapshot. `result[i]` is what `get()` would
+    /// return for `keys[i]`. Keys missing from the memtable are searched in sorted
+    /// order, so that keys stored in the same table are probed against its filter and
+    /// data blocks together.
+    pub fn multi_get(&self, options: &ReadOptions, keys: &[&[u8]]) -> Vec<Result<Option<Vec<u8>>>> {
+        let (snapshot, mem, imm, current) = {
+            let state = self.lock();
+            let snapshot = match options.snapshot.as_ref() {
+                Some(s) => s.sequence_number(),
+                None => state.versions.last_sequence(),
+            };
+            (snapshot, state.mem.clone(), state.imm.clone(), state.versions.current())
+        };
+
+        self.statistics.record_reads(keys.len() as u64);
+
+        // Unlock while reading from files and memtables
+        let mut results: Vec<Option<Result<Option<Vec<u8>>>>> = Vec::with_capacity(keys.len());
+        let mut remaining = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            let lkey = LookupKey::new(key, snapshot);
+            results.push(match mem.get(&lkey).or_else(|| imm.as_ref().and_then(|imm| imm.get(&lkey))) {
+                Some(Ok(value)) => Some(Ok(Some(value))),
+                Some(Err(e)) if e.is_not_found() => Some(Ok(None)),
+                Some(Err(e)) => Some(Err(e)),
+                None => {
+                    remaining.push((i, lkey));
+                    None
+                }
+            });
+        }
+
+        let ucmp = self.internal_comparator.user_comparator();
+        remaining.sort_by(|(_, a), (_, b)| ucmp.compare(a.user_key(), b.user_key()));
+        let (indexes, lkeys): (Vec<usize>, Vec<LookupKey>) = remaining.into_iter().unzip();
+        let batch_size = match options.cancel {
+            Some(_) => MULTI_GET_CANCEL_INTERVAL,
+            None => lkeys.len().max(1),
+        };
+        for (indexes, lkeys) in indexes.chunks(batch_size).zip(lkeys.chunks(batch_size)) {
+            if let Some(Err(e)) = options.cancel.as_ref().map(|token| token.check()) {
+                for &i in indexes {
+                    results[i] = Some(Err(e.clone()));
+                }
+                continue;
+            }
+            let found = current.multi_get(options, lkeys, &self.table_cache, &mut GetStats::default());
+            for (&i, result) in indexes.iter().zip(found) {
+                results[i] = Some(result);
+            }
+        }
+        results
+            .into_iter()
+            .enumerate()
+            .map(|(i, result)| {
+                let result = match result.expect("every key is resolved") {
+                    Err(e) if e.is_merge_in_progress() => {
+                        self.get_merged(options, &LookupKey::new(keys[i], snapshot), &mem, imm.as_deref(), &current)
+                    }
+                    result => result,
+                };
+                self.check_read_result(&result);
+                result
+            })
+            .collect()
+    }
+
+    /// For each range in `ranges`, return the approximate file system space used by
+    /// keys in that range, estimated from the index blocks of the tables in the current
+    /// version.
+    ///
+    /// Note that the returned sizes measure file system space usage, so if the user data
+    /// compresses by a factor of ten, the returned sizes will be one-tenth the size of
+    /// the corresponding user data size. The results may not include the sizes of
+    /// recently written data.
+    pub fn get_approximate_sizes(&self, ranges: &[KeyRange]) -> Vec<u64> {
+        let current = self.lock().versions.current();
+        // Convert user keys into corresponding internal keys: the first possible entry
+        // for keys that start a stretch of the range, the last one for keys that end it.
+        let first_entry = |key: &[u8]| InternalKey::new(key, MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK);
+        let last_entry = |key: &[u8]| InternalKey::new(key, 0, ValueType::Deletion);
+        let offset_of = |ikey: InternalKey| current.approximate_offset_of(&ikey, &self.table_cache);
+        ranges
+            .iter()
+            .map(|range| {
+                let start = match &range.start {
+                    Bound::Included(k) => offset_of(first_entry(k)),
+                    Bound::Excluded(k) => offset_of(last_entry(k)),
+                    Bound::Unbounded => 0,
+                };
+                let limit = match &range.end {
+                    Bound::Included(k) => offset_of(last_entry(k)),
+                    Bound::Excluded(k) => offset_of(first_entry(k)),
+                    Bound::Unbounded => (0..config::NUM_LEVELS).map(|l| total_file_size(current.files(l))).sum(),
+                };
+                limit.saturating_sub(start)
+            })
+            .collect()
+    }
+
+    /// With paranoid checks, a read that hits corruption stops further writes.
+    fn check_read_result<T>(&self, result: &Result<T>) {
+        if self.options.paranoid_checks {
+            if let Err(e) = result.as_ref() {
+                if e.is_corruption() {
+                    self.record_background_error(&mut self.lock(), &Err(e.clone()));
+                }
+            }
+        }
+    }
+
+    /// Return an iterator over the contents of the database. The result of
+    /// `new_iterator()` is initially invalid (caller must call one of the seek methods
+    /// on the iterator before using it).
+    pub fn new_iterator(&self, options: &ReadOptions) -> Box<dyn Iterator> {
+        let (sequence, mem, imm, current) = {
+            let state = self.lock();
+            let sequence = match options.snapshot.as_ref() {
+                Some(s) => s.sequence_number(),
+                None => state.versions.last_sequence(),
+            };
+            (sequence, state.mem.clone(), state.imm.clone(), state.versions.current())
+        };
+
+        // Collect together all needed child iterators
+        let mut list = vec![mem.new_iterator()];
+        list.extend(imm.map(|imm| imm.new_iterator()));
+        current.add_iterators(options, &self.table_cache, &mut list);
+        let internal_iter = new_merging_iterator(Arc::new(self.internal_comparator.clone()), list);
+        let prefix_extractor = match options.prefix_same_as_start {
+            true => self.prefix_extractor.clone(),
+            false => None,
+        };
+        let iter = new_db_iterator(
+            self.internal_comparator.user_comparator().clone(),
+            internal_iter,
+            sequence,
+            prefix_extractor,
+            self.options.merge_operator.clone(),
+        );
+        Box::new(PinnedIterator::new(iter, current, self.purge_queue.clone()))
+    }
+
+    /// Return an iterator that is not bound to a snapshot: each seek picks up the
+    /// writes committed since the previous one. Like `new_iterator()`, it is initially
+    /// invalid. `options.snapshot` is ignored.
+    pub fn new_tailing_iterator(&self, options: &ReadOptions) -> TailingIterator<'_> { TailingIterator::new(self, options) }
+
+    /// Return a `std::iter::Iterator` over the entries of the database in `range`.
+    pub fn iter_range(&self, options: &ReadOptions, range: &KeyRange) -> RangeIter {
+        // Both ends of the range read the same snapshot.
+        let mut options = options.clone();
+        if options.snapshot.is_none() {
+            options.snapshot = Some(self.get_snapshot());
+        }
+        RangeIter::new(
+            self.new_iterator(&options),
+            self.new_iterator(&options),
+            range.clone(),
+            self.internal_comparator.user_comparator().clone(),
+        )
+    }
+
+    /// Return a `std::iter::Iterator` over the entries of the database whose key starts
+    /// with `prefix`. Requires a comparator that orders keys bytewise.
+    pub fn iter_prefix(&self, options: &ReadOptions, prefix: &[u8]) -> RangeIter {
+        self.iter_range(options, &KeyRange::prefix(prefix))
+    }
+
+    /// Return the numbers of the table files that compactions have made obsolete but
+    /// that cannot be deleted yet because a live iterator may still read them. They
+    /// are deleted as soon as the last such iterator is dropped.
+    ///
+    /// Snapshots do not hold on to files: compactions keep the entries a snapshot can
+    /// see.
+    pub fn pending_purge(&self) -> Vec<u64> { self.purge_queue.pending() }
+
+    /// Flush the memtable and capture the files that a copy of the database needs: the
+    /// tables of the current version, and the manifest and log up to their current
+    /// sizes. Records appended to either later are not part of the captured state.
+    pub(crate) fn live_files(&self) -> Result<LiveFiles> {
+        self.check_writable()?;
+        let _writes = self.lock_writes();
+        let mut state = self.lock();
+        state.bg_error.clone()?;
+        self.finish_flush(&mut state, true)?;
+        if !state.mem.is_empty() {
+            self.switch_memtable(&mut state)?;
+        }
+        let manifest_number = state.versions.manifest_file_number();
+        let manifest_size = self
+            .env
+            .get_file_size(&descriptor_file_name(&self.dbname, manifest_number))?;
+        let log_number = state.logfile_number;
+        let log_size = self.env.get_file_size(&log_file_name(&self.dbname, log_number))?;
+        Ok(LiveFiles {
+            version: state.versions.current(),
+            manifest_number,
+            manifest_size,
+            log_number,
+            log_size,
+        })
+    }
+
+    /// Delete the tables that only a released `LiveFiles` still kept alive.
+    pub(crate) fn purge_obsolete_tables(&self) { self.purge_queue.purge(); }
+
+    /// Keep obsolete table and log files on disk until a matching
+    /// `enable_file_deletions()` call, so that the files returned by
+    /// `get_live_files()` can be copied while the database keeps running. Calls nest.
+    pub fn disable_file_deletions(&self) {
+        let _state = self.lock();
+        self.purge_queue.disable_deletions();
+    }
+
+    /// Undo a `disable_file_deletions()` call. Once every call has been undone, the
+    /// files that became obsolete in the meantime are deleted.
+    pub fn enable_file_deletions(&self) {
+        let mut state = self.lock();
+        if !self.purge_queue.enable_deletions() {
+            return;
+        }
+        for number in mem::take(&mut state.obsolete_logs) {
+            self.retire_log(&mut state, number);
+        }
+        drop(state);
+        self.purge_queue.purge();
+    }
+
+    /// Flush the memtable and return the files that make up the current state of the
+    /// database. Call `disable_file_deletions()` first, or compactions may delete the
+    /// returned tables before they are copied.
+    pub fn get_live_files(&self) -> Result<LiveFileList> {
+        let live = self.live_files()?;
+        let relative = |fname: String| fname[self.dbname.len() + 1..].to_string();
+        let mut files = vec![
+            relative(current_file_name(&self.dbname)),
+            relative(descriptor_file_name(&self.dbname, live.manifest_number)),
+        ];
+        for level in 0..config::NUM_LEVELS {
+            for f in live.version.files(level) {
+                files.push(relative(table_file_name(&self.dbname, f.number)));
+            }
+        }
+        drop(live.version);
+        self.purge_obsolete_tables();
+        Ok(LiveFileList {
+            files,
+            manifest_file_size: live.manifest_size,
+        })
+    }
+
+    pub(crate) fn dbname(&self) -> &str { &self.dbname }
+
+    pub(crate) fn env(&self) -> &Arc<dyn Env> { &self.env }
+
+    /// Return a handle to the current DB state. Iterators created with this handle will
+    /// all observe a stable snapshot of the current DB state. The snapshot is released
+    /// when the returned handle and all of its clones are dropped.
+    pub fn get_snapshot(&self) -> Snapshot {
+        let state = self.lock();
+        self.snapshots.new_snapshot(state.versions.last_sequence())
+    }
+
+    /// Return the sequence number of the most recent write.
+    pub fn latest_sequence_number(&self) -> SequenceNumber { self.lock().versions.last_sequence() }
+
+    /// DB implementations can export properties about their state via this method. If
+    /// `property` is a valid property understood by this DB implementation, returns its
+    /// current value. Otherwise returns `None`.
+    ///
+    /// Valid property names include:
+    ///
+    /// * `"hefesto.num-files-at-level<N>"` - return the number of files at level <N>,
+    ///   where <N> is an ASCII representation of a level number (e.g. "0").
+    /// * `"hefesto.stats"` - returns a multi-line string that describes statistics
+    ///   about the internal operation of the DB.
+    /// * `"hefesto.sstables"` - returns a multi-line string that describes all of the
+    ///   sstables that make up the db contents.
+    /// * `"hefesto.approximate-memory-usage"` - returns the approximate number of bytes
+    ///   of memory in use by the DB's memtables.
+    /// * `"hefesto.block-cache-stats"` - returns the hits, misses, insertions and
+    ///   evictions of the block cache, its usage and capacity in bytes, and its hit
+    ///   ratio, one `name: value` pair per line.
+    /// * `"hefesto.sequence-headroom"` - returns the number of sequence numbers that can
+    ///   still be assigned. Writes fail once a batch no longer fits in the headroom.
+    /// * `"hefesto.unexpected-files"` - returns the names of the files, one per line,
+    ///   that were in the DB directory at open but that the recovered state does not
+    ///   refer to, e.g. tables left behind by a crash during a compaction.
+    pub fn get_property(&self, property: &str) -> Option<String> {
+        let state = self.lock();
+        let property = property.strip_prefix("hefesto.")?;
+        if let Some(level) = property.strip_prefix("num-files-at-level") {
+            return match level.parse::<usize>() {
+                Ok(level) if level < config::NUM_LEVELS => Some(state.versions.num_level_files(level).to_string()),
+                _ => None,
+            };
+        }
+        match property {
+            "stats" => {
+                let mut value = String::from(
+                    "                               Compactions\n\
+                     Level  Files Size(MB) Time(sec) Read(MB) Write(MB)\n\
+                     --------------------------------------------------\n",
+                );
+                for (level, stats) in state.stats.iter().enumerate() {
+                    let files = state.versions.num_level_files(level);
+                    if stats.micros > 0 || files > 0 {
+                        value.push_str(&format!(
+                            "{:3} {:8} {:8.0} {:9.0} {:8.0} {:9.0}\n",
+                            level,
+                            files,
+                            state.versions.num_level_bytes(level) as f64 / 1048576.0,
+                            stats.micros as f64 / 1e6,
+                            stats.bytes_read as f64 / 1048576.0,
+                            stats.bytes_written as f64 / 1048576.0
+                        ));
+                    }
+                }
+                Some(value)
+            }
+            "sstables" => Some(state.versions.current().debug_string()),
+            "approximate-memory-usage" => {
+                let imm_usage = state.imm.as_ref().map_or(0, |imm| imm.approximate_memory_usage());
+                Some((state.mem.approximate_memory_usage() + imm_usage).to_string())
+            }
+            "block-cache-stats" => {
+                let stats = self.options.block_cache.as_ref()?.stats();
+                Some(format!(
+                    "hits: {}\nmisses: {}\ninsertions: {}\nevictions: {}\nevicted bytes: {}\nusage: {}\ncapacity: {}\nhit ratio: {:.4}\n",
+                    stats.hits,
+                    stats.misses,
+                    stats.insertions,
+                    stats.evictions,
+                    stats.evicted_charge,
+                    stats.usage,
+                    stats.capacity,
+                    stats.hit_ratio()
+                ))
+            }
+            "sequence-headroom" => Some((MAX_SEQUENCE_NUMBER - state.versions.last_sequence()).to_string()),
+            "unexpected-files" => Some(state.unexpected_files.iter().map(|name| format!("{}\n", name)).collect()),
+            _ => None,
+        }
+    }
+
+    /// Read every table of the current version in full, verifying the checksum of
+    /// each block. Returns the first error found, naming the table it was found in.
+    /// Fails with an `Aborted` error once `options.cancel` is cancelled.
+    pub fn verify_checksums(&self, options: &ReadOptions) -> Result<()> {
+        let check_cancel = || options.cancel.as_ref().map_or(Ok(()), |token| token.check());
+        let current = self.lock().versions.current();
+        let read_options = ReadOptions {
+            verify_checksums: true,
+            fill_cache: false,
+            ..options.clone()
+        };
+        for level in 0..config::NUM_LEVELS {
+            for f in current.files(level) {
+                check_cancel()?;
+                let mut iter = self.table_cache.new_iterator(&read_options, f.number, f.file_size);
+                iter.seek_to_first();
+                let mut entries: u64 = 0;
+                while iter.valid() {
+                    entries += 1;
+                    if entries.is_multiple_of(VERIFY_CANCEL_INTERVAL) {
+                        check_cancel()?;
+                    }
+                    iter.next();
+                }
+                iter.status()
+                    .map_err(|e| e.with_detail(table_file_name(&self.dbname, f.number)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check every live table and log file of the database without stopping at the
+    /// first problem, e.g. as a periodic scrub job. Every block of each table is read
+    /// and its checksum verified, as are the key order within the table and the
+    /// structure of its index and filter blocks. Every record of each log that may
+    /// hold updates not yet in a table is read and its checksum verified. Files are
+    /// not deleted while the scrub runs.
+    ///
+    /// Damage is returned in the report rather than as an error. Fails with an
+    /// `Aborted` error once `options.cancel` is cancelled.
+    pub fn scrub(&self, options: &ReadOptions) -> Result<ScrubReport> {
+        self.disable_file_deletions();
+        let result = self.scrub_files(options);
+        self.enable_file_deletions();
+        result
+    }
+
+    fn scrub_files(&self, options: &ReadOptions) -> Result<ScrubReport> {
+        let check_cancel = || options.cancel.as_ref().map_or(Ok(()), |token| token.check());
+        let (current, logs) = {
+            let state = self.lock();
+            let filenames = self.env.get_children(&self.dbname)?;
+            (state.versions.current(), Self::logs_to_recover(&state, &filenames))
+        };
+        let relative = |fname: &str| fname[self.dbname.len() + 1..].to_string();
+        let mut report = ScrubReport::default();
+
+        for level in 0..config::NUM_LEVELS {
+            for f in current.files(level) {
+                check_cancel()?;
+                let fname = table_file_name(&self.dbname, f.number);
+                let result = read_file_to_string(self.env.as_ref(), &fname).and_then(|data| {
+                    report.bytes_checked += data.len() as u64;
+                    if data.len() as u64 != f.file_size {
+                        return Err(Error::new(ErrorType::Corruption, "table file has unexpected size")
+                            .with_detail(format!("expected {} bytes, found {}", f.file_size, data.len())));
+                    }
+                    verify_table_bytes_with(&self.internal_comparator, &data).map(|_| ())
+                });
+                report.tables_checked += 1;
+                if let Err(error) = result {
+                    report.problems.push(ScrubProblem { file: relative(&fname), error });
+                }
+            }
+        }
+
+        for number in logs {
+            check_cancel()?;
+            let fname = log_file_name(&self.dbname, number);
+            let file = match self.env.new_sequential_file(&fname) {
+                Ok(file) => file,
+                // Deleted after the directory was listed: its updates are in a table
+                Err(e) if e.is_not_found() => continue,
+                Err(error) => {
+                    report.problems.push(ScrubProblem { file: relative(&fname), error });
+                    continue;
+                }
+            };
+            let corruption = Arc::new(Mutex::new(None));
+            let mut reader = log_reader::Reader::new(file, Some(Box::new(FirstCorruption(corruption.clone()))), true);
+            let mut record = Vec::new();
+            while reader.read_record(&mut record) {
+                if record.len() < 12 {
+                    corruption.lock().unwrap().get_or_insert_with(|| Error::corruption("log record too small"));
+                }
+            }
+            report.logs_checked += 1;
+            report.bytes_checked += self.env.get_file_size(&fname).unwrap_or(0);
+            let error = corruption.lock().unwrap().take();
+            if let Some(error) = error {
+                report.problems.push(ScrubProblem { file: relative(&fname), error });
+            }
+        }
+        Ok(report)
+    }
+
+    /// Return the current values of the DB's statistics. They are also reported through
+    /// `Options::metrics_registry` when one is set.
+    pub fn metrics(&self) -> MetricsSnapshot { self.statistics.snapshot() }
+
+    /// Return the flushes and compactions that are currently running, with their
+    /// progress. May be called while a job is running on another thread.
+    pub fn running_jobs(&self) -> Vec<BackgroundJobInfo> {
+        self.jobs.lock().unwrap().values().map(|job| job.info()).collect()
+    }
+
+    /// Compact the underlying storage for the key range `range`. In particular, deleted
+    /// and overwritten versions are discarded, and the data is rearranged to reduce the
+    /// cost of operations needed to access the data.
+    ///
+    /// The following call will compact the entire database:
+    ///    `db.compact_range(&KeyRange::all())`
+    ///
+    /// Compactions delayed or vetoed by a listener are skipped.
+    pub fn compact_range(&self, range: &KeyRange) -> Result<()> {
+        self.compact_range_with_options(&CompactRangeOptions::default(), range)
+    }
+
+    /// Like `compact_range()`, but controlled by `options`, e.g. to allow cancelling a
+    /// long compaction of a large range.
+    pub fn compact_range_with_options(&self, options: &CompactRangeOptions, range: &KeyRange) -> Result<()> {
+        self.check_writable()?;
+        let check_cancel = || options.cancel.as_ref().map_or(Ok(()), |token| token.check());
+        let writes = self.lock_writes();
+        let mut state = self.lock();
+        state.bg_error.clone()?;
+
+        // Flush the memtable first
+        check_cancel()?;
+        self.finish_flush(&mut state, true)?;
+        if !state.mem.is_empty() {
+            self.switch_memtable(&mut state)?;
+        }
+        drop(writes);
+
+        state = self.wait_for_compaction(state);
+        state.bg_error.clone()?;
+        let mut max_level_with_files = 1;
+        {
+            let base = state.versions.current();
+            for level in 1..config::NUM_LEVELS {
+                if base.overlap_in_level(level, range) {
+                    max_level_with_files = level;
+                }
+            }
+        }
+        for level in 0..max_level_with_files {
+            state = self.manual_compaction(state, level, range, &check_cancel)?;
+        }
+        self.maybe_compact(&mut state);
+        state.bg_error.clone()
+    }
+
+    /// Write the contents of the memtable to a level-0 table, so that they no longer
+    /// depend on the log. Does nothing if the memtable is empty. Without
+    /// `FlushOptions::wait`, the table is installed by a later write or flush.
+    pub fn flush(&self, options: &FlushOptions) -> Result<()> {
+        self.check_writable()?;
+        let _writes = self.lock_writes();
+        let mut state = self.lock();
+        state.bg_error.clone()?;
+        if !state.mem.is_empty() {
+            self.start_memtable_switch(&mut state)?;
+        }
+        if options.wait && self.finish_flush(&mut state, true)? {
+            self.maybe_compact(&mut state);
+        }
+        state.bg_error.clone()
+    }
+
+    /// Flush the writes buffered in the log to the operating system, and also persist
+    /// them to stable storage if `sync` is true. Only needed with
+    /// `Options::manual_wal_flush`, unless `sync` is set.
+    pub fn flush_wal(&self, sync: bool) -> Result<()> {
+        self.check_writable()?;
+        let _writes = self.lock_writes();
+        let mut state = self.lock();
+        state.bg_error.clone()?;
+        let log = state.log.as_mut().unwrap();
+        let s = if sync { log.sync() } else { log.flush() };
+        // As for a failed write, the log may now end in a partial record.
+        self.record_background_error(&mut state, &s);
+        s
+    }
+
+    /// Persist the writes in the log to stable storage, as a sync write would.
+    pub fn sync_wal(&self) -> Result<()> { self.flush_wal(true) }
+
+    /// Block until no level needs a compaction anymore, running the pending ones. With
+    /// `WaitForCompactOptions::flush`, the memtable is flushed first. Compactions that a
+    /// listener delays are offered again until they run, and vetoed ones are skipped.
+    /// Returns right away during a bulk load, which runs no compactions.
+    pub fn wait_for_compact(&self, options: &WaitForCompactOptions) -> Result<()> {
+        self.check_writable()?;
+        let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+        let writes = self.lock_writes();
+        let mut state = self.lock();
+        state.bg_error.clone()?;
+        if options.flush && !state.mem.is_empty() {
+            self.start_memtable_switch(&mut state)?;
+        }
+        drop(writes);
+        self.finish_flush(&mut state, true)?;
+        loop {
+            let delayed = self.maybe_compact(&mut state);
+            let running = state.compaction.as_ref().map(|bg| bg.result.clone());
+            if running.is_none() && !delayed {
+                break;
+            }
+            drop(state);
+            match running {
+                Some(result) => {
+                    if !result.wait(deadline) {
+                        return LEVELDB_ERR!(TimedOut, "compactions still pending");
+                    }
+                }
+                None => {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return LEVELDB_ERR!(TimedOut, "compactions still pending");
+                    }
+                    self.env.sleep_for_microseconds(DELAYED_COMPACTION_RETRY_INTERVAL.as_micros() as u64);
+                }
+            }
+            state = self.lock();
+        }
+        state.bg_error.clone()
+    }
+
+    /// Switch to a mode suited to loading a large amount of data: memtables are vectors
+    /// that grow to `Options::bulk_load_write_buffer_size` and are flushed as they fill
+    /// up, but no automatic compaction runs, however many level-0 files pile up. The
+    /// current memtable is kept until it fills up. Reads stay
+    /// correct but get slower as level-0 grows, so the mode is meant for loading data
+    /// before serving reads from it. Loading keys in sorted order lets most flushes go
+    /// straight to deeper levels.
+    ///
+    /// Call `finish_bulk_load()` once done.
+    pub fn prepare_for_bulk_load(&self) { self.set_bulk_load(true) }
+
+    /// Leave the bulk load mode entered by `prepare_for_bulk_load()`, restoring the
+    /// normal write buffer size and automatic compactions, and compact the whole
+    /// database so that it is fully organized into levels again.
+    pub fn finish_bulk_load(&self) -> Result<()> {
+        self.set_bulk_load(false);
+        self.compact_range(&KeyRange::all())
+    }
+
+    fn set_bulk_load(&self, bulk_load: bool) {
+        let mut state = self.lock();
+        if state.bulk_load != bulk_load {
+            state.bulk_load = bulk_load;
+            // The next memtable has to be of the kind the mode calls for.
+            self.discard_prepared_memtable(&mut state);
+        }
+    }
+
+    /// Add the table files at `paths`, written by `SstFileWriter`, to the database
+    /// without going through the log and memtable. The files must not overlap each
+    /// other. The memtable is flushed first if it overlaps any of them. Each file then
+    /// goes to the deepest level such that neither that level nor any level above it
+    /// holds keys in the range of the file.
+    ///
+    /// The ingested entries are assigned the next sequence number, so they replace the
+    /// values their keys had before and are invisible to existing snapshots. A file
+    /// whose range holds no data at all keeps sequence number zero and is linked (or
+    /// copied, see `IngestExternalFileOptions::copy_files`) as is; it is then also
+    /// visible to existing snapshots. A file that overlaps data in deeper levels is
+    /// rewritten with the assigned sequence number. Either all files are ingested or
+    /// none.
+    pub fn ingest_external_file(&self, paths: &[&str], options: &IngestExternalFileOptions) -> Result<()> {
+        self.check_writable()?;
+        let mut files = paths
+            .iter()
+            .map(|path| ExternalFile::open(&self.options, path))
+            .collect::<Result<Vec<_>>>()?;
+        let ucmp = self.internal_comparator.user_comparator().clone();
+        files.sort_by(|a, b| ucmp.compare(a.smallest.user_key(), b.smallest.user_key()));
+        for pair in files.windows(2) {
+            if ucmp.compare(pair[0].largest.user_key(), pair[1].smallest.user_key()) != std::cmp::Ordering::Less {
+                return Err(Error::new(ErrorType::InvalidArgument, "external files overlap")
+                    .with_detail(format!("{} and {}", pair[0].path, pair[1].path)));
+            }
+        }
+
+        // The levels of the files are picked against a version that no compaction is
+        // about to change.
+        let _writes = self.lock_writes();
+        let mut state = self.wait_for_compaction(self.lock());
+        state.bg_error.clone()?;
+        // The entries of the immutable memtable are older than the ingested ones, so
+        // its table must be in place before the level of the files is picked.
+        self.finish_flush(&mut state, true)?;
+        let mem_overlaps = files.iter().any(|f| {
+            let mut iter = state.mem.new_iterator();
+            iter.seek(InternalKey::new(f.smallest.user_key(), MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK).encode());
+            iter.valid() && ucmp.compare(extract_user_key(iter.key()), f.largest.user_key()) != std::cmp::Ordering::Greater
+        });
+        if mem_overlaps {
+            self.switch_memtable(&mut state)?;
+        }
+        let last_sequence = state.versions.last_sequence();
+        if !sequences_available(last_sequence, 1) {
+            return LEVELDB_ERR!(NotSupported, "sequence numbers exhausted");
+        }
+        let sequence = last_sequence + 1;
+
+        let current = state.versions.current();
+        let mut edit = VersionEdit::new();
+        let mut added = Vec::new();
+        let mut created = Vec::new();
+        let mut s = Ok(());
+        let mut sequence_used = false;
+        for f in files.iter() {
+            let range = KeyRange::inclusive(f.smallest.user_key(), f.largest.user_key());
+            let first_overlap = (0..config::NUM_LEVELS).find(|&level| current.overlap_in_level(level, &range));
+            let level = first_overlap.map_or(config::NUM_LEVELS - 1, |level| level.saturating_sub(1));
+            let mut meta = FileMetaData {
+                number: state.versions.new_file_number(),
+                ..FileMetaData::default()
+            };
+            let fname = table_file_name(&self.dbname, meta.number);
+            added.push(fname.clone());
+            if first_overlap.is_some() {
+                // Older values of these keys live in deeper levels, so the entries need
+                // a sequence number above theirs.
+                sequence_used = true;
+                let options = Options {
+                    compression: self.options.compression_for_level(level),
+                    ..self.options.clone()
+                };
+                let mut iter = f.iter_with_sequence(sequence);
+                s = build_table(&self.dbname, &options, &self.table_cache, iter.as_mut(), &mut meta);
+            } else {
+                s = match options.copy_files {
+                    true => copy_file(self.env.as_ref(), &f.path, &fname, f.file_size),
+                    false => link_or_copy_file(self.env.as_ref(), &f.path, &fname, f.file_size),
+                };
+                meta.file_size = f.file_size;
+                meta.smallest = f.smallest.clone();
+                meta.largest = f.largest.clone();
+                meta.creation_time = self.env.now_micros() / 1_000_000;
+            }
+            if s.is_err() {
+                break;
+            }
+            edit.add_file_meta(level, &meta);
+            created.push((meta.number, meta.file_size, level));
+        }
+        if s.is_ok() {
+            if sequence_used {
+                state.versions.set_last_sequence(sequence);
+            }
+            s = state.versions.log_and_apply(&mut edit);
+        }
+        if s.is_err() {
+            // An unused sequence number is harmless, so last_sequence stays advanced.
+            for fname in added {
+                let _ = self.env.remove_file(&fname);
+            }
+            return s;
+        }
+        self.update_level_metrics(&state);
+        for (number, file_size, level) in created {
+            self.notify_table_file_created(number, file_size, level, TableFileCreationReason::Ingestion);
+        }
+        self.maybe_compact(&mut state);
+        state.bg_error.clone()
+    }
+
+    /// Compact the files of `level` that overlap `range` into the next level, one
+    /// compaction at a time. Each one runs in the background like any other, and the
+    /// DB mutex is released while waiting for it.
+    fn manual_compaction<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, DBState>,
+        level: usize,
+        range: &KeyRange,
+        check_cancel: &dyn Fn() -> Result<()>,
+    ) -> Result<MutexGuard<'a, DBState>> {
+        assert!(level + 1 < config::NUM_LEVELS);
+        let mut range = range.clone();
+        loop {
+            // An automatic compaction may have started while the mutex was released.
+            state = self.wait_for_compaction(state);
+            state.bg_error.clone()?;
+            let Some(c) = state.versions.compact_range(level, &range) else { break };
+            check_cancel()?;
+            let info = match self.approve_compaction(&mut state, &c, true) {
+                Ok(info) => info,
+                Err(_) => break,
+            };
+            let manual_end = c.input(0, c.num_input_files(0) - 1).largest.user_key().to_vec();
+            self.start_compaction(&mut state, c, info);
+            range.start = Bound::Included(manual_end);
+        }
+        Ok(state)
+    }
+}
+
+impl Drop for DB {
+    fn drop(&mut self) {
+        let mut state = self.wait_for_compaction(self.lock());
+        self.shutdown(&mut state);
+        self.purge_queue.purge();
+        if let Some(lock) = state.db_lock.take() {
+            let _ = self.env.unlock_file(lock);
+        }
+    }
+}
+
+impl DB {
+    /// Simulate a crash: nothing is flushed, and only the LOCK file is released, as
+    /// it would be for a process that exits.
+    #[cfg(test)]
+    pub(crate) fn crash(self) {
+        let lock = self.lock().db_lock.take();
+        mem::forget(self);
+        drop(lock);
+    }
+
+    /// Persist the memtable and retire the current log file, if possible.
+    fn shutdown(&self, state: &mut DBState) {
+        if state.log.is_none() {
+            // Open failed before the DB was usable
+            return;
+        }
+        let _ = self.finish_flush(state, true);
+        self.discard_prepared_memtable(state);
+        if let Some(mut log) = state.log.take() {
+            let _ = log.close();
+        }
+        let old_log_number = state.logfile_number;
+        if state.bg_error.is_ok() && !state.mem.is_empty() {
+            // Persist the memtable so that the log becomes obsolete. Log numbers below
+            // a fresh number are all considered obsolete by the manifest.
+            let log_number = state.versions.new_file_number();
+            let new_mem = Arc::new(self.new_memtable(state));
+            let imm = mem::replace(&mut state.mem, new_mem);
+            if self.compact_memtable(state, &imm, log_number).is_err() {
+                return;
+            }
+        } else if !state.mem.is_empty() {
+            return;
+        }
+        self.retire_log(state, old_log_number);
+    }
+
+    /// Delete log `number`, which recovery no longer needs, or move it to the archive
+    /// if `Options::wal_archive_size_limit` is set. While file deletions are disabled,
+    /// the log stays in place until they are enabled again.
+    fn retire_log(&self, state: &mut DBState, number: u64) {
+        if self.purge_queue.deletions_disabled() {
+            state.obsolete_logs.push(number);
+            return;
+        }
+        let fname = log_file_name(&self.dbname, number);
+        let limit = self.options.wal_archive_size_limit;
+        if limit == 0 {
+            let _ = self.env.remove_file(&fname);
+            return;
+        }
+        let archive = archival_directory(&self.dbname);
+        let _ = self.env.create_dir(&archive);
+        if self.env.rename_file(&fname, &archived_log_file_name(&self.dbname, number)).is_err() {
+            let _ = self.env.remove_file(&fname);
+        }
+
+        // Delete the oldest archived logs until the rest fit in the limit.
+        let mut archived: Vec<(u64, u64)> = self
+            .archived_logs()
+            .into_iter()
+            .map(|number| {
+                let size = self
+                    .env
+                    .get_file_size(&archived_log_file_name(&self.dbname, number))
+                    .unwrap_or(0);
+                (number, size)
+            })
+            .collect();
+        let mut total: u64 = archived.iter().map(|&(_, size)| size).sum();
+        archived.reverse();
+        while total > limit {
+            let Some((number, size)) = archived.pop() else { break };
+            if self.env.remove_file(&archived_log_file_name(&self.dbname, number)).is_ok() {
+                total -= size;
+            }
+        }
+    }
+
+    /// Return the numbers of the archived logs, oldest first.
+    fn archived_logs(&self) -> Vec<u64> {
+        let mut logs: Vec<u64> = self
+            .env
+            .get_children(&archival_directory(&self.dbname))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|name| parse_file_name(name))
+            .filter(|&(_, file_type)| file_type == FileType::LogFile)
+            .map(|(number, _)| number)
+            .collect();
+        logs.sort_unstable();
+        logs
+    }
+}
+
+/// Destroy the contents of the specified database. Be very careful using this method.
+pub fn destroy_db(dbname: &str, options: &Options) -> Result<()> {
+    let env = options.env.clone();
+    let filenames = match env.get_children(dbname) {
+        Ok(names) => names,
+        // Ignore error in case directory does not exist
+        Err(_) => return Ok(()),
+    };
+    let lockname = lock_file_name(dbname);
+    let lock = env.lock_file(&lockname)?;
+    let mut result = Ok(());
+    let archive = archival_directory(dbname);
+    if let Ok(names) = env.get_children(&archive) {
+        for name in names {
+            let s = env.remove_file(&format!("{}/{}", archive, name));
+            if result.is_ok() && s.is_err() {
+                result = s;
+            }
+        }
+        let _ = env.remove_dir(&archive);
+    }
+    for name in filenames {
+        if format!("{}/{}", dbname, name) == archive {
+            continue;
+        }
+        if matches!(parse_file_name(&name), Some((_, FileType::DBLockFile))) {
+            // Lock file will be deleted at end
+            continue;
+        }
+        let s = env.remove_file(&format!("{}/{}", dbname, name));
+        if result.is_ok() && s.is_err() {
+            result = s;
+        }
+    }
+    let _ = env.unlock_file(lock); // Ignore error since state is already gone
+    let _ = env.remove_file(&lockname);
+    let _ = env.remove_dir(dbname); // Ignore error in case dir contains other files
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+    use crate::{
+        db::{metrics::MetricsRegistry, sst_file_writer::SstFileWriter},
+        leveldb::{
+            compaction_filter::CompactionFilterFactory,
+            env::{default_env, read_file_to_string, write_string_to_file},
+            options::{CancellationToken, ChecksumType, CompressionType},
+        },
+        util::{
+            bloom::new_bloom_filter_policy, merge_operators::new_string_append_operator,
+            slice_transform::new_fixed_prefix_transform,
+            slow_env::{IoProfile, Latency, SlowEnv, SlowEnvOptions},
+            testutil::tmp_dir,
+        },
+    };
+
+    fn open(dir: &str, options: Option<Options>) -> DB {
+        let mut options = options.unwrap_or_default();
+        options.create_if_missing = true;
+        DB::open(options, dir).expect("open")
+    }
+
+    fn get(db: &DB, key: &str) -> Option<String> {
+        db.get(&ReadOptions::default(), key.as_bytes())
+            .expect("get")
+            .map(|v| String::from_utf8(v).unwrap())
+    }
+
+    fn contents(db: &DB, options: &ReadOptions) -> Vec<(String, String)> {
+        let mut iter = db.new_iterator(options);
+        let mut result = Vec::new();
+        iter.seek_to_first();
+        while iter.valid() {
+            result.push((
+                String::from_utf8(iter.key().to_vec()).unwrap(),
+                String::from_utf8(iter.value().to_vec()).unwrap(),
+            ));
+            iter.next();
+        }
+        assert!(iter.status().is_ok());
+        result
+    }
+
+    #[test]
+    fn empty() {
+        let dir = tmp_dir("db_empty");
+        let db = open(&dir, None);
+        assert_eq!(get(&db, "foo"), None);
+        assert!(contents(&db, &ReadOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn missing_db() {
+        let dir = tmp_dir("db_missing");
+        let err = DB::open(Options::default(), &dir).err().expect("open should fail");
+        assert_eq!(err.ty(), ErrorType::InvalidArgument);
+    }
+
+    #[test]
+    fn format_version() {
+        let dir = tmp_dir("db_format_version");
+        let unwritable = Options {
+            create_if_missing: true,
+            checksum: ChecksumType::XxHash64,
+            format_version: 1,
+            ..Options::default()
+        };
+        assert_eq!(DB::open(unwritable, &dir).err().unwrap().ty(), ErrorType::InvalidArgument);
+
+        // Tables of an older version stay readable after switching to the latest one
+        let db = open(&dir, Some(Options { format_version: 0, ..Options::default() }));
+        db.put(&WriteOptions::default(), b"foo", b"v1").unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
+        drop(db);
+        let db = open(&dir, None);
+        db.put(&WriteOptions::default(), b"bar", b"v2").unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
+        assert_eq!(get(&db, "foo").as_deref(), Some("v1"));
+        assert_eq!(get(&db, "bar").as_deref(), Some("v2"));
+    }
+
+    #[test]
+    fn put_delete_get() {
+        let dir = tmp_dir("db_put_delete_get");
+        let db = open(&dir, None);
+        let wo = WriteOptions::default();
+        db.put(&wo, b"foo", b"v1").unwrap();
+        assert_eq!(get(&db, "foo").as_deref(), Some("v1"));
+        db.put(&wo, b"foo", b"v2").unwrap();
+        assert_eq!(get(&db, "foo").as_deref(), Some("v2"));
+        db.delete(&wo, b"foo").unwrap();
+        assert_eq!(get(&db, "foo"), None);
+    }
+
+    #[test]
+    fn iterate_with_snapshot() {
+        let dir = tmp_dir("db_snapshot");
+        let db = open(&dir, None);
+        let wo = WriteOptions::default();
+        db.put(&wo, b"a", b"1").unwrap();
+        db.put(&wo, b"b", b"2").unwrap();
+        let snapshot = db.get_snapshot();
+        db.put(&wo, b"a", b"3").unwrap();
+        db.delete(&wo, b"b").unwrap();
+        db.put(&wo, b"c", b"4").unwrap();
+
+        let current = contents(&db, &ReadOptions::default());
+        assert_eq!(current, [("a".into(), "3".into()), ("c".into(), "4".into())]);
+
+        let at_snapshot = ReadOptions {
+            snapshot: Some(snapshot),
+            ..ReadOptions::default()
+        };
+        let old = contents(&db, &at_snapshot);
+        assert_eq!(old, [("a".into(), "1".into()), ("b".into(), "2".into())]);
+        assert_eq!(db.get(&at_snapshot, b"b").unwrap(), Some(b"2".to_vec()));
+
+        let mut iter = db.new_iterator(&ReadOptions::default());
+        iter.seek(b"b");
+        assert_eq!(iter.key(), b"c");
+    }
+
+    #[test]
+    fn iterate_backwards() {
+        let dir = tmp_dir("db_iterate_backwards");
+        let options = Options {
+            merge_operator: Some(new_string_append_operator(b',')),
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        let wo = WriteOptions::default();
+        db.put(&wo, b"a", b"1").unwrap();
+        db.put(&wo, b"b", b"2").unwrap();
+        db.put(&wo, b"c", b"3").unwrap();
+        db.merge(&wo, b"d", b"x").unwrap();
+        db.switch_memtable(&mut db.lock()).unwrap();
+        let snapshot = db.get_snapshot();
+        db.put(&wo, b"a", b"4").unwrap();
+        db.delete(&wo, b"b").unwrap();
+        db.merge(&wo, b"d", b"y").unwrap();
+        db.put(&wo, b"e", b"5").unwrap();
+
+        let backwards = |options: &ReadOptions| {
+            let mut iter = db.new_iterator(options);
+            let mut result = Vec::new();
+            iter.seek_to_last();
+            while iter.valid() {
+                result.push(format!("{}={}", String::from_utf8_lossy(iter.key()), String::from_utf8_lossy(iter.value())));
+                iter.prev();
+            }
+            assert!(iter.status().is_ok());
+            result
+        };
+        assert_eq!(backwards(&ReadOptions::default()), ["e=5", "d=x,y", "c=3", "a=4"]);
+        let at_snapshot = ReadOptions {
+            snapshot: Some(snapshot),
+            ..ReadOptions::default()
+        };
+        assert_eq!(backwards(&at_snapshot), ["d=x", "c=3", "b=2", "a=1"]);
+
+        // Switching directions
+        let mut iter = db.new_iterator(&ReadOptions::default());
+        iter.seek(b"c");
+        iter.prev();
+        assert_eq!((iter.key(), iter.value()), (&b"a"[..], &b"4"[..]));
+        iter.next();
+        assert_eq!(iter.key(), b"c");
+        iter.next();
+        assert_eq!((iter.key(), iter.value()), (&b"d"[..], &b"x,y"[..]));
+        iter.prev();
+        assert_eq!(iter.key(), b"c");
+        iter.next();
+        iter.next();
+        assert_eq!(iter.key(), b"e");
+        iter.prev();
+        assert_eq!((iter.key(), iter.value()), (&b"d"[..], &b"x,y"[..]));
+        iter.prev();
+        iter.prev();
+        assert_eq!(iter.key(), b"a");
+        iter.prev();
+        assert!(!iter.valid());
+
+        iter.seek_for_prev(b"b");
+        assert_eq!(iter.key(), b"a");
+        iter.seek_for_prev(b"d");
+        assert_eq!(iter.value(), b"x,y");
+        iter.seek_for_prev(b"z");
+        assert_eq!(iter.key(), b"e");
+        iter.seek_for_prev(b"0");
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn reopen_keeps_data() {
+        let dir = tmp_dir("db_reopen");
+        {
+            let db = open(&dir, None);
+            db.put(&WriteOptions::default(), b"foo", b"bar").unwrap();
+            db.put(&WriteOptions::default(), b"baz", b"qux").unwrap();
+        }
+        let db = open(&dir, None);
+        assert_eq!(get(&db, "foo").as_deref(), Some("bar"));
+        assert_eq!(get(&db, "baz").as_deref(), Some("qux"));
+        drop(db);
+
+        let options = Options {
+            error_if_exists: true,
+            ..Options::default()
+        };
+        assert!(DB::open(options, &dir).is_err());
+    }
+
+    #[test]
+    fn recover_from_log() {
+        let dir = tmp_dir("db_recover_log");
+        let wo = WriteOptions { sync: true };
+        let db = open(&dir, None);
+        db.put(&wo, b"foo", b"v1").unwrap();
+        db.put(&wo, b"bar", b"v2").unwrap();
+        db.delete(&wo, b"bar").unwrap();
+        // Simulate a crash: nothing is flushed on the way out.
+        db.crash();
+
+        let options = Options {
+            write_buffer_size: 1024,
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options.clone()));
+        assert_eq!(get(&db, "foo").as_deref(), Some("v1"));
+        assert_eq!(get(&db, "bar"), None);
+
+        // Writes after recovery get sequence numbers above the recovered ones, and a
+        // log larger than the write buffer is replayed into several tables.
+        for i in 0..200 {
+            db.put(&wo, format!("key{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        db.put(&wo, b"foo", b"v3").unwrap();
+        db.crash();
+
+        let db = open(&dir, Some(options));
+        assert_eq!(get(&db, "foo").as_deref(), Some("v3"));
+        assert_eq!(contents(&db, &ReadOptions::default()).len(), 201);
+        let logs = db
+            .env
+            .get_children(&dir)
+            .unwrap()
+            .iter()
+            .filter(|name| matches!(parse_file_name(name), Some((_, FileType::LogFile))))
+            .count();
+        assert_eq!(logs, 1);
+    }
+
+    #[test]
+    fn prepared_memtable_switch() {
+        let dir = tmp_dir("db_prepared_memtable");
+        let options = Options {
+            write_buffer_size: 64 * 1024,
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options.clone()));
+        let wo = WriteOptions::default();
+        let mut i = 0;
+        let put = |i: usize| db.put(&wo, format!("key{:06}", i).as_bytes(), &[b'v'; 100]).unwrap();
+        while db.lock().prepared.is_none() {
+            put(i);
+            i += 1;
+        }
+        let prepared = db.lock().prepared.as_ref().unwrap().log_number;
+        let logfile_number = db.lock().logfile_number;
+        while db.lock().logfile_number == logfile_number {
+            put(i);
+            i += 1;
+        }
+        assert_eq!(db.lock().logfile_number, prepared);
+        assert_eq!(get(&db, "key000000").as_deref(), Some(&"v".repeat(100)[..]));
+        assert_eq!(contents(&db, &ReadOptions::default()).len(), i);
+
+        // Reopening removes the unused prepared log file.
+        while db.lock().prepared.is_none() {
+            put(i);
+            i += 1;
+        }
+        let prepared = db.lock().prepared.as_ref().unwrap().log_number;
+        drop(db);
+        assert!(!options.env.file_exists(&log_file_name(&dir, prepared)));
+        let db = open(&dir, Some(options));
+        assert_eq!(contents(&db, &ReadOptions::default()).len(), i);
+    }
+
+    #[test]
+    fn background_flush() {
+        let dir = tmp_dir("db_background_flush");
+        let db = open(&dir, None);
+        let wo = WriteOptions::default();
+        db.put(&wo, b"a", b"1").unwrap();
+        let old_log = db.lock().logfile_number;
+        db.start_memtable_switch(&mut db.lock()).unwrap();
+
+        // Until its table is installed, the flushed memtable is read after the new one.
+        assert!(db.lock().imm.is_some());
+        assert_eq!(get(&db, "a").as_deref(), Some("1"));
+        db.put(&wo, b"b", b"2").unwrap();
+        db.delete(&wo, b"a").unwrap();
+        assert_eq!(get(&db, "a"), None);
+        assert_eq!(contents(&db, &ReadOptions::default()), vec![("b".to_string(), "2".to_string())]);
+        let results = db.multi_get(&ReadOptions::default(), &[b"a", b"b"]);
+        assert_eq!(results[1].as_ref().unwrap().as_deref(), Some(&b"2"[..]));
+
+        // Installing the table retires the old log.
+        assert!(db.finish_flush(&mut db.lock(), true).is_ok());
+        assert!(db.lock().imm.is_none());
+        assert_eq!((0..config::NUM_LEVELS).map(|level| db.lock().versions.num_level_files(level)).sum::<usize>(), 1);
+        assert!(!db.env.file_exists(&log_file_name(&dir, old_log)));
+        assert_eq!(get(&db, "a"), None);
+        assert_eq!(get(&db, "b").as_deref(), Some("2"));
+
+        // A flush still running at close is finished first.
+        db.start_memtable_switch(&mut db.lock()).unwrap();
+        drop(db);
+        let db = open(&dir, None);
+        assert_eq!(contents(&db, &ReadOptions::default()), vec![("b".to_string(), "2".to_string())]);
+    }
+
+    #[test]
+    fn write_group() {
+        let batch = |key: &[u8], value_size: usize| {
+            let mut batch = WriteBatch::new();
+            batch.put(key, &vec![b'v'; value_size]);
+            batch
+        };
+        let mut queue = WriteQueue::default();
+        queue.push(false, batch(b"a", 10));
+        queue.push(false, batch(b"b", 10));
+        queue.push(true, batch(b"c", 10));
+        queue.push(false, batch(b"d", 10));
+
+        // A group committed without sync stops before a sync write.
+        let (group, sync, last_id) = queue.take_group(false);
+        assert_eq!((group.count(), sync, last_id), (2, false, 1));
+        queue.finish_group(last_id, &Ok(()));
+        assert_eq!(queue.writers.len(), 2);
+        assert!(queue.results.contains_key(&1) && !queue.results.contains_key(&0));
+
+        // A sync leader takes the writes without sync along.
+        let (group, sync, last_id) = queue.take_group(false);
+        assert_eq!((group.count(), sync, last_id), (2, true, 3));
+        queue.finish_group(last_id, &LEVELDB_ERR!(IOError, "disk full"));
+        assert!(queue.writers.is_empty());
+        assert!(queue.results[&3].is_err());
+
+        // A small write only takes a limited amount of data along.
+        queue.push(false, batch(b"e", 10));
+        queue.push(false, batch(b"f", 100 << 10));
+        queue.push(false, batch(b"g", 100 << 10));
+        let (group, _, last_id) = queue.take_group(true);
+        assert_eq!((group.count(), last_id), (2, 5));
+
+        // Writers inserting their own batch keep it.
+        assert!(queue.writers.iter().all(|w| w.batch.is_some()));
+    }
+
+    #[test]
+    fn concurrent_writes() {
+        for concurrent in [false, true] {
+            let dir = tmp_dir(&format!("db_concurrent_writes_{concurrent}"));
+            let options = Options {
+                allow_concurrent_memtable_write: concurrent,
+                ..Options::default()
+            };
+            let db = open(&dir, Some(options.clone()));
+            std::thread::scope(|s| {
+                for t in 0..8 {
+                    let db = &db;
+                    s.spawn(move || {
+                        for i in 0..200 {
+                            let key = format!("{t}.{i:03}");
+                            db.put(&WriteOptions::default(), key.as_bytes(), b"v").unwrap();
+                        }
+                    });
+                }
+            });
+            assert_eq!(contents(&db, &ReadOptions::default()).len(), 8 * 200);
+            assert_eq!(db.lock().versions.last_sequence(), 8 * 200);
+            let queue = db.write_queue.lock().unwrap();
+            assert!(queue.writers.is_empty() && queue.results.is_empty() && queue.inserting == 0);
+            drop(queue);
+            drop(db);
+            let db = open(&dir, Some(options));
+            assert_eq!(contents(&db, &ReadOptions::default()).len(), 8 * 200);
+        }
+    }
+
+    #[test]
+    fn reads_during_log_write() {
+        for concurrent in [false, true] {
+            let dir = tmp_dir(&format!("db_reads_during_log_write_{concurrent}"));
+            let latency = Duration::from_millis(300);
+            let env = Arc::new(SlowEnv::new(
+                default_env(),
+                SlowEnvOptions {
+                    wal: IoProfile {
+                        latency: Latency::Fixed(latency),
+                        ..IoProfile::default()
+                    },
+                    ..SlowEnvOptions::default()
+                },
+            ));
+            let db = open(
+                &dir,
+                Some(Options {
+                    env,
+                    allow_concurrent_memtable_write: concurrent,
+                    ..Options::default()
+                }),
+            );
+            db.put(&WriteOptions::default(), b"foo", b"v1").unwrap();
+            thread::scope(|scope| {
+                let writer = scope.spawn(|| db.put(&WriteOptions { sync: true }, b"foo", b"v2"));
+                thread::sleep(latency / 4);
+                // The write is appending to the log, and is not visible yet. Reads do not
+                // wait for it.
+                let start = Instant::now();
+                assert_eq!(get(&db, "foo"), Some("v1".to_string()));
+                assert!(start.elapsed() < latency / 2);
+                writer.join().unwrap().unwrap();
+            });
+            assert_eq!(get(&db, "foo"), Some("v2".to_string()));
+        }
+    }
+
+    #[test]
+    fn memtable_insert_failure() {
+        let dir = tmp_dir("db_memtable_insert_failure");
+        let db = open(&dir, None);
+        let wo = WriteOptions::default();
+        db.put(&wo, b"a", b"v1").unwrap();
+        db.lock().mem.fail_inserts();
+        assert!(db.put(&wo, b"b", b"v2").unwrap_err().is_io_error());
+        // The failed write was logged, so its sequence number is used up, and later
+        // writes fail instead of reusing it.
+        assert_eq!(db.lock().versions.last_sequence(), 2);
+        assert!(db.put(&wo, b"c", b"v3").unwrap_err().is_io_error());
+        assert_eq!(db.lock().versions.last_sequence(), 2);
+        drop(db);
+
+        // The logged write is recovered.
+        let db = open(&dir, None);
+        assert_eq!(get(&db, "b"), Some("v2".to_string()));
+        assert_eq!(get(&db, "c"), None);
+        db.put(&wo, b"a", b"v4").unwrap();
+        assert_eq!(get(&db, "a"), Some("v4".to_string()));
+    }
+
+    #[test]
+    fn concurrent_memtable_insert_failure() {
+        let dir = tmp_dir("db_concurrent_memtable_insert_failure");
+        let latency = Duration::from_millis(200);
+        let env = Arc::new(SlowEnv::new(
+            default_env(),
+            SlowEnvOptions {
+                wal: IoProfile {
+                    latency: Latency::Fixed(latency),
+                    ..IoProfile::default()
+                },
+                ..SlowEnvOptions::default()
+            },
+        ));
+        let options = Options {
+            env,
+            allow_concurrent_memtable_write: true,
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options.clone()));
+        let wo = WriteOptions::default();
+        thread::scope(|scope| {
+            let first = scope.spawn(|| db.put(&wo, b"k0", b"v"));
+            // Queue up a group behind the first write while it is in the log, and make
+            // the memtable fail while the group is being logged in turn.
+            thread::sleep(latency / 4);
+            let group: Vec<_> = (1..5)
+                .map(|i| {
+                    let db = &db;
+                    scope.spawn(move || db.put(&wo, format!("k{i}").as_bytes(), b"v"))
+                })
+                .collect();
+            first.join().unwrap().unwrap();
+            thread::sleep(latency / 4);
+            db.lock().mem.fail_inserts();
+            for writer in group {
+                assert!(writer.join().unwrap().unwrap_err().is_io_error());
+            }
+        });
+        assert_eq!(db.lock().versions.last_sequence(), 5);
+        assert!(db.put(&wo, b"k5", b"v").is_err());
+        assert_eq!(db.write_queue.lock().unwrap().insert_error.as_ref().map(Error::msg), None);
+        drop(db);
+
+        let db = open(&dir, Some(options));
+        assert_eq!(contents(&db, &ReadOptions::default()).len(), 5);
+    }
+
+    #[test]
+    fn memtable_arena_reuse() {
+        let dir = tmp_dir("db_memtable_arena_reuse");
+        let options = Options {
+            write_buffer_size: 64 * 1024,
+            arena_block_size: 16 * 1024,
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        let wo = WriteOptions::default();
+        for i in 0..500 {
+            db.put(&wo, format!("key{:06}", i).as_bytes(), &[b'v'; 100]).unwrap();
+        }
+        db.switch_memtable(&mut db.lock()).unwrap();
+        {
+            let state = db.lock();
+            let arena = state.spare_arena.as_ref().expect("arena retained");
+            assert_eq!(arena.block_size(), 16 * 1024);
+            assert_eq!(arena.memory_usage(), 0);
+        }
+
+        // The next memtable takes over the retained blocks.
+        db.prepare_next_memtable(&mut db.lock());
+        assert!(db.lock().spare_arena.is_none());
+        for i in 500..1000 {
+            db.put(&wo, format!("key{:06}", i).as_bytes(), &[b'w'; 100]).unwrap();
+        }
+        assert_eq!(get(&db, "key000000").as_deref(), Some(&"v".repeat(100)[..]));
+        assert_eq!(get(&db, "key000999").as_deref(), Some(&"w".repeat(100)[..]));
+
+        // An iterator over the flushed memtable keeps its arena alive.
+        db.lock().spare_arena = None;
+        let iter = db.new_iterator(&ReadOptions::default());
+        db.switch_memtable(&mut db.lock()).unwrap();
+        assert!(db.lock().spare_arena.is_none());
+        drop(iter);
+        assert_eq!(contents(&db, &ReadOptions::default()).len(), 1000);
+    }
+
+    /// An Env that counts the table files opened for random access.
+    struct CountingEnv {
+        base: Arc<dyn Env>,
+        tables_opened: AtomicU64,
+    }
+
+    impl Env for CountingEnv {
+        fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn crate::leveldb::env::SequentialFile>> {
+            self.base.new_sequential_file(fname)
+        }
+
+        fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn crate::leveldb::env::RandomAccessFile>> {
+            if fname.ends_with(".ldb") {
+                self.tables_opened.fetch_add(1, Ordering::Relaxed);
+            }
+            self.base.new_random_access_file(fname)
+        }
+
+        fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> { self.base.new_writable_file(fname) }
+
+        fn file_exists(&self, fname: &str) -> bool { self.base.file_exists(fname) }
+
+        fn get_children(&self, dir: &str) -> Result<Vec<String>> { self.base.get_children(dir) }
+
+        fn remove_file(&self, fname: &str) -> Result<()> { self.base.remove_file(fname) }
+
+        fn create_dir(&self, dirname: &str) -> Result<()> { self.base.create_dir(dirname) }
+
+        fn remove_dir(&self, dirname: &str) -> Result<()> { self.base.remove_dir(dirname) }
+
+        fn get_file_size(&self, fname: &str) -> Result<u64> { self.base.get_file_size(fname) }
+
+        fn rename_file(&self, src: &str, target: &str) -> Result<()> { self.base.rename_file(src, target) }
+
+        fn lock_file(&self, fname: &str) -> Result<Box<dyn FileLock>> { self.base.lock_file(fname) }
+
+        fn now_micros(&self) -> u64 { self.base.now_micros() }
+    }
+
+    #[test]
+    fn lazy_table_loading() {
+        let dir = tmp_dir("db_lazy_tables");
+        let env = Arc::new(CountingEnv {
+            base: crate::leveldb::env::default_env(),
+            tables_opened: AtomicU64::new(0),
+        });
+        let mut options = Options {
+            env: env.clone(),
+            write_buffer_size: 4096,
+            ..Options::default()
+        };
+        {
+            let db = open(&dir, Some(options.clone()));
+            for i in 0..500 {
+                db.put(&WriteOptions::default(), format!("key{:04}", i).as_bytes(), b"value")
+                    .unwrap();
+            }
+        }
+        let files = {
+            let db = open(&dir, Some(options.clone()));
+            let state = db.lock();
+            (0..config::NUM_LEVELS).map(|l| state.versions.num_level_files(l) as u64).sum::<u64>()
+        };
+        assert!(files > 1);
+
+        for skip_stats in [false, true] {
+            options.skip_stats_update_on_db_open = skip_stats;
+            env.tables_opened.store(0, Ordering::Relaxed);
+            let db = open(&dir, Some(options.clone()));
+            assert_eq!(env.tables_opened.load(Ordering::Relaxed), 0);
+            assert_eq!(get(&db, "key0123").as_deref(), Some("value"));
+            assert_eq!(env.tables_opened.load(Ordering::Relaxed), 1);
+        }
+
+        // Only with an unbounded table cache are all the tables opened up front.
+        options.max_open_files = -1;
+        env.tables_opened.store(0, Ordering::Relaxed);
+        drop(open(&dir, Some(options)));
+        assert_eq!(env.tables_opened.load(Ordering::Relaxed), files);
+    }
+
+    #[test]
+    fn read_deadline() {
+        let dir = tmp_dir("db_read_deadline");
+        let db = open(&dir, None);
+        let wo = WriteOptions::default();
+        db.put(&wo, b"flushed", b"v1").unwrap();
+        db.compact_range(&KeyRange::all()).unwrap();
+        db.put(&wo, b"buffered", b"v2").unwrap();
+
+        let expired = ReadOptions {
+            deadline: Some(std::time::Instant::now()),
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+        // The memtable is served without touching the disk.
+        assert_eq!(db.get(&expired, b"buffered").unwrap().as_deref(), Some(&b"v2"[..]));
+        assert!(db.get(&expired, b"flushed").unwrap_err().is_timed_out());
+        let mut iter = db.new_iterator(&expired);
+        iter.seek_to_first();
+        while iter.valid() {
+            iter.next();
+        }
+        assert!(iter.status().unwrap_err().is_timed_out());
+        assert_eq!(get(&db, "flushed").as_deref(), Some("v1"));
+    }
+
+    #[test]
+    fn flush_and_compact() {
+        let dir = tmp_dir("db_compact");
+        let options = Options {
+            write_buffer_size: 16 * 1024,
+            max_file_size: 32 * 1024,
+            filter_policy: Some(new_bloom_filter_policy(10)),
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options.clone()));
+        let wo = WriteOptions::default();
+        let n: usize = 4000;
+        for i in 0..n {
+            db.put(&wo, format!("key{:06}", i).as_bytes(), format!("value{:06}", i).as_bytes())
+                .unwrap();
+        }
+        for i in (0..n).step_by(3) {
+            db.delete(&wo, format!("key{:06}", i).as_bytes()).unwrap();
+        }
+        let files: usize = {
+            let state = db.lock();
+            (0..config::NUM_LEVELS).map(|l| state.versions.num_level_files(l)).sum()
+        };
+        assert!(files > 0);
+
+        db.compact_range(&KeyRange::all()).unwrap();
+        {
+            let state = db.lock();
+            assert_eq!(state.versions.num_level_files(0), 0);
+        }
+
+        for i in 0..n {
+            let expected = if i % 3 == 0 { None } else { Some(format!("value{:06}", i)) };
+            assert_eq!(get(&db, &format!("key{:06}", i)), expected);
+        }
+        assert_eq!(contents(&db, &ReadOptions::default()).len(), n - n.div_ceil(3));
+        drop(db);
+
+        let db = open(&dir, Some(options));
+        assert_eq!(get(&db, "key000001").as_deref(), Some("value000001"));
+        assert_eq!(get(&db, "key000003"), None);
+    }
+
+    #[test]
+    fn get_probes() {
+        let dir = tmp_dir("db_get_probes");
+        let options = Options {
+            write_buffer_size: 16 * 1024,
+            max_file_size: 8 * 1024,
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        let wo = WriteOptions::default();
+        for i in 0..2000 {
+            db.put(&wo, format!("key{:06}", i * 2).as_bytes(), format!("value{:06}", i).as_bytes())
+                .unwrap();
+        }
+        db.compact_range(&KeyRange::all()).unwrap();
+
+        let probe = |key: &str, sequence: SequenceNumber| {
+            let current = db.lock().versions.current();
+            let mut stats = GetStats::default();
+            let value = current
+                .get(&ReadOptions::default(), &LookupKey::new(key.as_bytes(), sequence), &db.table_cache, &mut stats)
+                .unwrap()
+                .map(|v| String::from_utf8(v.into_vec()).unwrap());
+            (value, stats.files_probed)
+        };
+        let before_delete = db.lock().versions.last_sequence();
+        {
+            let current = db.lock().versions.current();
+            assert_eq!(current.num_files(0), 0);
+            assert!((1..config::NUM_LEVELS).any(|l| current.num_files(l) > 1));
+        }
+
+        // A level holding many files is searched with a single probe, and keys falling
+        // outside every file's range are never probed at all.
+        assert_eq!(probe("key000200", before_delete), (Some("value000100".to_string()), 1));
+        assert_eq!(probe("key003997", before_delete), (None, 1));
+        assert_eq!(probe("a", before_delete), (None, 0));
+        assert_eq!(probe("zzz", before_delete), (None, 0));
+
+        // A tombstone in a newer level ends the search without reaching the value.
+        db.delete(&wo, b"key000200").unwrap();
+        db.switch_memtable(&mut db.lock()).unwrap();
+        let last = db.lock().versions.last_sequence();
+        assert_eq!(probe("key000200", last), (None, 1));
+        assert_eq!(get(&db, "key000200"), None);
+
+        // At an older sequence the tombstone is invisible and the search continues. The
+        // tombstone was pushed below level 0, where the binary search over internal keys
+        // passes over a file that only holds newer entries for the key.
+        assert_eq!(db.lock().versions.num_level_files(0), 0);
+        assert_eq!(probe("key000200", before_delete), (Some("value000100".to_string()), 1));
+        assert_eq!(probe("key000202", last).0, Some("value000101".to_string()));
+    }
+
+    #[test]
+    fn get_pinned() {
+        let dir = tmp_dir("db_get_pinned");
+        let db = open(&dir, None);
+        let wo = WriteOptions::default();
+        let ro = ReadOptions::default();
+        let value = vec![b'x'; 100 << 10];
+        db.put(&wo, b"a", &value).unwrap();
+        let from_mem = db.get_pinned(&ro, b"a").unwrap().unwrap();
+        assert!(from_mem.is_pinned());
+        assert_eq!(from_mem[..], value[..]);
+
+        db.switch_memtable(&mut db.lock()).unwrap();
+        let from_table = db.get_pinned(&ro, b"a").unwrap().unwrap();
+        assert!(from_table.is_pinned());
+        assert_eq!(from_table[..], value[..]);
+
+        // The values outlive the memtable and the table they were read from.
+        db.delete(&wo, b"a").unwrap();
+        db.compact_range(&KeyRange::all()).unwrap();
+        assert!(db.get_pinned(&ro, b"a").unwrap().is_none());
+        assert_eq!(from_mem, from_table);
+        assert_eq!(from_table.into_vec(), value);
+    }
+
+    #[test]
+    fn multi_get() {
+        let dir = tmp_dir("db_multi_get");
+        let options = Options {
+            write_buffer_size: 16 * 1024,
+            max_file_size: 8 * 1024,
+            filter_policy: Some(new_bloom_filter_policy(10)),
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        let wo = WriteOptions::default();
+        for i in 0..2000 {
+            db.put(&wo, format!("key{:06}", i).as_bytes(), format!("value{:06}", i).as_bytes())
+                .unwrap();
+        }
+        db.compact_range(&KeyRange::all()).unwrap();
+        // Newer values and tombstones in level 0 and the memtable shadow the old ones.
+        for i in (0..2000).step_by(5) {
+            db.put(&wo, format!("key{:06}", i).as_bytes(), b"new").unwrap();
+        }
+        for i in (0..2000).step_by(7) {
+            db.delete(&wo, format!("key{:06}", i).as_bytes()).unwrap();
+        }
+        let snapshot = db.get_snapshot();
+        db.put(&wo, b"key000001", b"after snapshot").unwrap();
+
+        let mut keys: Vec<String> = (0..2100).rev().step_by(3).map(|i| format!("key{:06}", i)).collect();
+        keys.extend(["key000001".to_string(), "a".to_string(), "key000001".to_string()]);
+        let refs: Vec<&[u8]> = keys.iter().map(|k| k.as_bytes()).collect();
+        for options in [
+            ReadOptions::default(),
+            ReadOptions {
+                snapshot: Some(snapshot.clone()),
+                ..ReadOptions::default()
+            },
+        ] {
+            let results = db.multi_get(&options, &refs);
+            assert_eq!(results.len(), refs.len());
+            for (key, result) in refs.iter().zip(results) {
+                assert_eq!(result.unwrap(), db.get(&options, key).unwrap());
+            }
+        }
+        assert_eq!(get(&db, "key000001").as_deref(), Some("after snapshot"));
+        assert!(db.multi_get(&ReadOptions::default(), &[]).is_empty());
+    }
+
+    #[test]
+    fn approximate_sizes() {
+        let dir = tmp_dir("db_approximate_sizes");
+        let options = Options {
+            write_buffer_size: 100_000_000, // Keep everything in the memtable at first
+            max_file_size: 64 * 1024,
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        let key = |i: usize| format!("key{:06}", i);
+        let size = |start: &str, limit: &str| db.get_approximate_sizes(&[KeyRange::half_open(start.as_bytes(), limit.as_bytes())])[0];
+
+        // Random-ish values so that compression does not shrink them.
+        let n = 80;
+        let wo = WriteOptions::default();
+        for i in 0..n {
+            let value: Vec<u8> = (0..10_000u32).map(|j| (j * 7919 + i as u32 * 104729).to_le_bytes()[1]).collect();
+            db.put(&wo, key(i).as_bytes(), &value).unwrap();
+        }
+        // Data in the memtable is not counted.
+        assert_eq!(size("", "xyz"), 0);
+
+        db.compact_range(&KeyRange::all()).unwrap();
+        assert_eq!(db.lock().versions.num_level_files(0), 0);
+        let total = size("", "xyz");
+        assert!((800_000..900_000).contains(&total), "{}", total);
+        for step in [1, 10, 20] {
+            for i in (0..n).step_by(step) {
+                let s = size(&key(i), &key(i + step));
+                let expected = 10_000 * step as u64;
+                assert!(s + 12_000 >= expected && s <= expected + 12_000, "{} {} {}", i, step, s);
+            }
+        }
+        assert_eq!(size(&key(40), &key(20)), 0);
+        assert_eq!(size("", "a"), 0);
+        assert_eq!(size("zzz", "zzzz"), 0);
+
+        let sizes = db.get_approximate_sizes(&[
+            KeyRange::new(Bound::Unbounded, Bound::Excluded(b"key000040")),
+            KeyRange::new(Bound::Included(b"key000040"), Bound::Unbounded),
+            KeyRange::all(),
+            KeyRange::prefix(b"key00001"),
+            KeyRange::new(Bound::Excluded(b"key000010"), Bound::Included(b"key000019")),
+        ]);
+        assert_eq!(sizes.len(), 5);
+        assert!(sizes[0] + sizes[1] <= sizes[2]);
+        assert!(sizes[0] + sizes[1] + 12_000 >= sizes[2]);
+        assert!(sizes[2] >= total);
+        // The prefix holds 10 values, one more than the exclusive range.
+        assert!(sizes[3] + 12_000 >= 100_000 && sizes[3] <= 112_000, "{}", sizes[3]);
+        assert!(sizes[4] + 12_000 >= 90_000 && sizes[4] <= 102_000, "{}", sizes[4]);
+    }
+
+    #[test]
+    fn mixed_compression() {
+        let dir = tmp_dir("db_mixed_compression");
+        let compressions = [
+            CompressionType::Lz4,
+            CompressionType::Zstd { level: 3 },
+            CompressionType::NoCompression,
+            CompressionType::Lz4,
+        ];
+        let wo = WriteOptions::default();
+        for (round, &compression) in compressions.iter().enumerate() {
+            let options = Options {
+                write_buffer_size: 16 * 1024,
+                compression,
+                ..Options::default()
+            };
+            let db = open(&dir, Some(options));
+            for i in 0..1000 {
+                let value = format!("value{:06}-{}", i, round).repeat(4);
+                db.put(&wo, format!("key{:06}", i * 4 + round).as_bytes(), value.as_bytes())
+                    .unwrap();
+            }
+            // Every table written so far stays readable, whatever compression it used.
+            if round == 2 {
+                db.compact_range(&KeyRange::all()).unwrap();
+            }
+            for r in 0..=round {
+                for i in (0..1000).step_by(97) {
+                    let expected = format!("value{:06}-{}", i, r).repeat(4);
+                    assert_eq!(get(&db, &format!("key{:06}", i * 4 + r)), Some(expected));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compression_per_level() {
+        let dir = tmp_dir("db_compression_per_level");
+        let options = Options {
+            compression_per_level: vec![CompressionType::NoCompression, CompressionType::Zstd { level: 1 }],
+            ..Options::default()
+        };
+        assert_eq!(options.compression_for_level(0), CompressionType::NoCompression);
+        assert_eq!(options.compression_for_level(5), CompressionType::Zstd { level: 1 });
+        assert_eq!(Options::default().compression_for_level(3), CompressionType::NoCompression);
+
+        let db = open(&dir, Some(options));
+        let wo = WriteOptions::default();
+        let value = "x".repeat(100);
+        let level_bytes = |db: &DB| -> Vec<u64> {
+            let state = db.lock();
+            (0..config::NUM_LEVELS).map(|l| state.versions.num_level_bytes(l)).collect()
+        };
+        for round in 0..2 {
+            for i in 0..2000 {
+                db.put(&wo, format!("key{:06}", i).as_bytes(), format!("{}{}", value, round).as_bytes())
+                    .unwrap();
+            }
+            db.compact_range(&KeyRange::all()).unwrap();
+        }
+        let bytes = level_bytes(&db);
+        // Flushed tables are uncompressed and hold about 220KB; the compacted output
+        // holding the same number of entries is far smaller.
+        assert_eq!(bytes[0], 0);
+        assert!(bytes.iter().sum::<u64>() < 100 * 1024, "{:?}", bytes);
+        assert_eq!(get(&db, "key001234"), Some(format!("{}1", value)));
+    }
+
+    #[test]
+    fn get_property() {
+        let dir = tmp_dir("db_get_property");
+        let options = Options {
+            write_buffer_size: 16 * 1024,
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        assert_eq!(db.get_property("hefesto.num-files-at-level0").as_deref(), Some("0"));
+        assert_eq!(db.get_property("hefesto.num-files-at-level7"), None);
+        assert_eq!(db.get_property("hefesto.num-files-at-levelx"), None);
+        assert_eq!(db.get_property("leveldb.stats"), None);
+        assert_eq!(db.get_property("hefesto.nonexistent"), None);
+        let usage: usize = db.get_property("hefesto.approximate-memory-usage").unwrap().parse().unwrap();
+
+        let wo = WriteOptions::default();
+        for i in 0..1000 {
+            db.put(&wo, format!("key{:06}", i).as_bytes(), format!("value{:06}", i).as_bytes())
+                .unwrap();
+        }
+        let grown: usize = db.get_property("hefesto.approximate-memory-usage").unwrap().parse().unwrap();
+        assert!(grown > usage);
+        db.compact_range(&KeyRange::all()).unwrap();
+
+        let files: Vec<usize> = (0..config::NUM_LEVELS)
+            .map(|l| db.get_property(&format!("hefesto.num-files-at-level{}", l)).unwrap().parse().unwrap())
+            .collect();
+        let total: usize = files.iter().sum();
+        assert!(total > 0);
+
+        let sstables = db.get_property("hefesto.sstables").unwrap();
+        assert!(sstables.starts_with("--- level 0 ---\n"));
+        assert_eq!(sstables.lines().filter(|l| !l.starts_with("---")).count(), total);
+        assert!(sstables.contains("'key000000' @ 1 : 1"));
+
+        let stats = db.get_property("hefesto.stats").unwrap();
+        assert!(stats.contains("Level  Files Size(MB) Time(sec) Read(MB) Write(MB)"));
+        // At least one row for every level holding files.
+        let rows = stats.lines().skip(3).count();
+        assert!(rows >= files.iter().filter(|&&n| n > 0).count());
+
+        // Reading the same key twice misses the block cache once, then hits it.
+        assert_eq!(get(&db, "key000500").as_deref(), Some("value000500"));
+        assert_eq!(get(&db, "key000500").as_deref(), Some("value000500"));
+        let cache_stats = db.get_property("hefesto.block-cache-stats").unwrap();
+        let stat = |name: &str| -> u64 {
+            let line = cache_stats.lines().find(|l| l.starts_with(&format!("{}: ", name))).unwrap();
+            line[name.len() + 2..].parse().unwrap()
+        };
+        assert!(stat("hits") >= 1);
+        assert!(stat("misses") >= 1);
+        assert!(stat("usage") > 0);
+        assert_eq!(stat("capacity"), DEFAULT_BLOCK_CACHE_SIZE as u64);
+    }
+
+    #[test]
+    fn sequence_number_limit() {
+        let dir = tmp_dir("db_sequence_limit");
+        let db = open(&dir, None);
+        let wo = WriteOptions::default();
+        db.put(&wo, b"a", b"1").unwrap();
+        let headroom = |db: &DB| db.get_property("hefesto.sequence-headroom").unwrap();
+        assert_eq!(headroom(&db), (MAX_SEQUENCE_NUMBER - 1).to_string());
+        assert_eq!(db.get_property("hefesto.unknown"), None);
+
+        db.lock().versions.set_last_sequence(MAX_SEQUENCE_NUMBER - 2);
+        let mut batch = WriteBatch::new();
+        batch.put(b"b", b"2");
+        batch.put(b"c", b"3");
+        batch.put(b"d", b"4");
+        let err = db.write(&wo, batch).unwrap_err();
+        assert_eq!(err.ty(), ErrorType::NotSupported);
+        assert_eq!(headroom(&db), "2");
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"b", b"2");
+        batch.delete(b"a");
+        db.write(&wo, batch).unwrap();
+        assert_eq!(headroom(&db), "0");
+        assert_eq!(get(&db, "a"), None);
+        assert_eq!(get(&db, "b").as_deref(), Some("2"));
+        assert!(db.put(&wo, b"e", b"5").is_err());
+        drop(db);
+
+        // The exhausted sequence survives recovery from the log.
+        let db = open(&dir, None);
+        assert_eq!(headroom(&db), "0");
+        assert_eq!(get(&db, "b").as_deref(), Some("2"));
+        assert!(db.put(&wo, b"e", b"5").is_err());
+    }
+
+    /// Flip a bit in the byte at `offset` of every file of `dir` with the given suffix.
+    fn corrupt_files(dir: &str, suffix: &str, offset: usize) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.to_str().unwrap().ends_with(suffix) {
+                let mut contents = std::fs::read(&path).unwrap();
+                contents[offset] ^= 0x08;
+                std::fs::write(&path, contents).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn paranoid_checks() {
+        let dir = tmp_dir("db_paranoid_checks");
+        let wo = WriteOptions::default();
+        {
+            let db = open(&dir, None);
+            for i in 0..100 {
+                db.put(&wo, format!("key{:06}", i).as_bytes(), format!("value{:06}", i).as_bytes())
+                    .unwrap();
+            }
+            db.compact_range(&KeyRange::all()).unwrap();
+        }
+        // Damage a value in the first data block of the table.
+        corrupt_files(&dir, ".ldb", 20);
+
+        let db = open(&dir, None);
+        let no_cache = ReadOptions {
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+        assert!(db.get(&no_cache, b"key000000").is_ok());
+        let verify = ReadOptions {
+            verify_checksums: true,
+            ..ReadOptions::default()
+        };
+        assert!(db.get(&verify, b"key000000").unwrap_err().is_corruption());
+        db.put(&wo, b"other", b"v").unwrap();
+        drop(db);
+
+        let paranoid = Options {
+            paranoid_checks: true,
+            ..Options::default()
+        };
+        let db = open(&dir, Some(paranoid.clone()));
+        assert!(db.get(&ReadOptions::default(), b"key000000").unwrap_err().is_corruption());
+        // The corruption stops writes.
+        assert!(db.put(&wo, b"other", b"v2").unwrap_err().is_corruption());
+        drop(db);
+
+        // Damaged log records are skipped normally, but fail a paranoid recovery.
+        let dir = tmp_dir("db_paranoid_log");
+        {
+            let db = open(&dir, None);
+            db.put(&wo, b"a", b"1").unwrap();
+            db.put(&wo, b"b", b"2").unwrap();
+            db.crash();
+        }
+        corrupt_files(&dir, ".log", 10);
+        let paranoid = Options {
+            create_if_missing: true,
+            ..paranoid
+        };
+        assert!(DB::open(paranoid, &dir).err().unwrap().is_corruption());
+        // The reader drops the rest of the damaged log block, which holds both records.
+        let db = open(&dir, None);
+        assert_eq!(get(&db, "a"), None);
+        assert_eq!(get(&db, "b"), None);
+        db.put(&wo, b"c", b"3").unwrap();
+    }
+
+    struct FreezeListener {
+        decision: Mutex<CompactionDecision>,
+        begun: Mutex<Vec<CompactionJobInfo>>,
+        completed: AtomicU64,
+    }
+
+    impl crate::leveldb::listener::EventListener for FreezeListener {
+        fn on_compaction_begin(&self, info: &CompactionJobInfo) -> CompactionDecision {
+            self.begun.lock().unwrap().push(info.clone());
+            *self.decision.lock().unwrap()
+        }
+
+        fn on_compaction_completed(&self, _info: &CompactionJobInfo, succeeded: bool) {
+            assert!(succeeded);
+            self.completed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn compaction_listener() {
+        let dir = tmp_dir("db_compaction_listener");
+        let listener = Arc::new(FreezeListener {
+            decision: Mutex::new(CompactionDecision::Veto),
+            begun: Mutex::new(Vec::new()),
+            completed: AtomicU64::new(0),
+        });
+        let options = Options {
+            write_buffer_size: 16 * 1024,
+            listeners: vec![listener.clone()],
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        let wo = WriteOptions::default();
+        for i in 0..4000 {
+            db.put(&wo, format!("key{:06}", i % 500).as_bytes(), b"value").unwrap();
+        }
+        assert!(db.lock().versions.num_level_files(0) >= db.options.level0_file_num_compaction_trigger);
+        assert!(!listener.begun.lock().unwrap().is_empty());
+        assert_eq!(listener.completed.load(Ordering::Relaxed), 0);
+
+        // A vetoed compaction is not offered again.
+        let begun = listener.begun.lock().unwrap().len();
+        db.maybe_compact(&mut db.lock());
+        assert_eq!(listener.begun.lock().unwrap().len(), begun);
+
+        // A delayed compaction is offered again. Flush a new level-0 file so that
+        // the picked compaction is not the vetoed one.
+        *listener.decision.lock().unwrap() = CompactionDecision::Delay;
+        db.switch_memtable(&mut db.lock()).unwrap();
+        let begun = listener.begun.lock().unwrap().len();
+        db.maybe_compact(&mut db.lock());
+        db.maybe_compact(&mut db.lock());
+        assert_eq!(listener.begun.lock().unwrap().len(), begun + 2);
+        assert_eq!(listener.completed.load(Ordering::Relaxed), 0);
+
+        *listener.decision.lock().unwrap() = CompactionDecision::Proceed;
+        db.compact_range(&KeyRange::all()).unwrap();
+        assert_eq!(db.lock().versions.num_level_files(0), 0);
+        assert!(listener.completed.load(Ordering::Relaxed) > 0);
+        let info = listener.begun.lock().unwrap().last().unwrap().clone();
+        assert!(info.manual);
+        assert!(!info.input_files.is_empty());
+        assert!(db.running_jobs().is_empty());
+    }
+
+    #[test]
+    fn level0_triggers() {
+        let dir = tmp_dir("db_level0_triggers");
+        let listener = Arc::new(FreezeListener {
+            decision: Mutex::new(CompactionDecision::Delay),
+            begun: Mutex::new(Vec::new()),
+            completed: AtomicU64::new(0),
+        });
+        let options = Options {
+            write_buffer_size: 16 * 1024,
+            level0_file_num_compaction_trigger: 2,
+            level0_slowdown_writes_trigger: 3,
+            level0_stop_writes_trigger: 4,
+            listeners: vec![listener.clone()],
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..8000 {
+                    db.put(&WriteOptions::default(), format!("key{:06}", i % 500).as_bytes(), b"value").unwrap();
+                }
+                done.store(true, Ordering::Release);
+            });
+            // Writes stop once level-0 reaches the stop trigger, as its compactions are
+            // delayed.
+            while db.lock().versions.num_level_files(0) < 4 {
+                assert!(!done.load(Ordering::Acquire));
+                thread::sleep(Duration::from_millis(1));
+            }
+            thread::sleep(Duration::from_millis(50));
+            assert!(!done.load(Ordering::Acquire));
+            assert!(db.lock().versions.num_level_files(0) <= 5);
+            *listener.decision.lock().unwrap() = CompactionDecision::Proceed;
+        });
+        db.wait_for_compact(&WaitForCompactOptions::default()).unwrap();
+        assert!(listener.completed.load(Ordering::Relaxed) > 0);
+        // Level-0 is compacted from two files on.
+        assert!(db.lock().versions.num_level_files(0) < 2);
+    }
+
+    #[test]
+    fn level0_stop_with_vetoed_compactions() {
+        let dir = tmp_dir("db_level0_stop_vetoed");
+        let listener = Arc::new(FreezeListener {
+            decision: Mutex::new(CompactionDecision::Veto),
+            begun: Mutex::new(Vec::new()),
+            completed: AtomicU64::new(0),
+        });
+        let options = Options {
+            write_buffer_size: 16 * 1024,
+            level0_file_num_compaction_trigger: 2,
+            level0_slowdown_writes_trigger: 3,
+            level0_stop_writes_trigger: 4,
+            listeners: vec![listener.clone()],
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        // Writes fail rather than wait forever for compactions that never run.
+        let e = (0..8000)
+            .map(|i| db.put(&WriteOptions::default(), format!("key{:06}", i % 500).as_bytes(), b"value"))
+            .find_map(Result::err)
+            .unwrap();
+        assert!(e.is_aborted());
+        assert!(db.lock().versions.num_level_files(0) >= 4);
+        assert_eq!(listener.completed.load(Ordering::Relaxed), 0);
+        // Reads still work.
+        assert_eq!(get(&db, "key000001"), Some("value".to_string()));
+    }
+
+    #[test]
+    fn manual_wal_flush() {
+        let dir = tmp_dir("db_manual_wal_flush");
+        let db = open(
+            &dir,
+            Some(Options {
+                manual_wal_flush: true,
+                ..Options::default()
+            }),
+        );
+        let log_size = |db: &DB| {
+            let number = db.lock().logfile_number;
+            db.env.get_file_size(&log_file_name(&dir, number)).unwrap()
+        };
+        db.put(&WriteOptions::default(), b"foo", b"v1").unwrap();
+        assert_eq!(log_size(&db), 0);
+        db.flush_wal(false).unwrap();
+        let size = log_size(&db);
+        assert!(size > 0);
+
+        db.put(&WriteOptions::default(), b"bar", b"v2").unwrap();
+        assert_eq!(log_size(&db), size);
+        db.sync_wal().unwrap();
+        assert!(log_size(&db) > size);
+
+        // Sync writes reach the file right away.
+        let size = log_size(&db);
+        db.put(&WriteOptions { sync: true }, b"baz", b"v3").unwrap();
+        assert!(log_size(&db) > size);
+
+        // Recovery sees the flushed writes.
+        db.crash();
+        let db = open(&dir, None);
+        assert_eq!(get(&db, "foo"), Some("v1".to_string()));
+        assert_eq!(get(&db, "baz"), Some("v3".to_string()));
+    }
+
+    #[test]
+    fn wait_for_compact() {
+        let dir = tmp_dir("db_wait_for_compact");
+        let listener = Arc::new(FreezeListener {
+            decision: Mutex::new(CompactionDecision::Delay),
+            begun: Mutex::new(Vec::new()),
+            completed: AtomicU64::new(0),
+        });
+        let options = Options {
+            write_buffer_size: 16 * 1024,
+            listeners: vec![listener.clone()],
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        let wo = WriteOptions::default();
+        for i in 0..4000 {
+            db.put(&wo, format!("key{:06}", i % 500).as_bytes(), b"value").unwrap();
+        }
+        assert!(db.lock().versions.needs_compaction());
+
+        let options = WaitForCompactOptions {
+            flush: true,
+            timeout: Some(Duration::from_millis(30)),
+        };
+        assert!(db.wait_for_compact(&options).unwrap_err().is_timed_out());
+        assert_eq!(listener.completed.load(Ordering::Relaxed), 0);
+
+        // The call keeps waiting until the listener lets the compactions run.
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                *listener.decision.lock().unwrap() = CompactionDecision::Proceed;
+            });
+            db.wait_for_compact(&WaitForCompactOptions::default()).unwrap();
+        });
+        assert!(!db.lock().versions.needs_compaction());
+        assert!(listener.completed.load(Ordering::Relaxed) > 0);
+        assert!(db.lock().mem.is_empty());
+        assert_eq!(get(&db, "key000123").as_deref(), Some("value"));
+    }
+
+    #[test]
+    fn write_serialized_batch() {
+        let dir = tmp_dir("db_serialized_batch");
+        let db = open(&dir, None);
+        let mut batch = WriteBatch::new();
+        batch.put(b"k1", b"v1");
+        batch.put(b"k2", b"v2");
+        batch.delete(b"k1");
+        let data = batch.serialize();
+
+        db.write_serialized_batch(&WriteOptions::default(), &data).unwrap();
+        assert_eq!(get(&db, "k1"), None);
+        assert_eq!(get(&db, "k2").as_deref(), Some("v2"));
+
+        // Damaged input is rejected without applying anything
+        let mut damaged = data.clone();
+        damaged[3] ^= 0x1;
+        assert!(db
+            .write_serialized_batch(&WriteOptions::default(), &damaged)
+            .unwrap_err()
+            .is_corruption());
+        assert_eq!(get(&db, "k2").as_deref(), Some("v2"));
+    }
+
+    #[test]
+    fn destroy() {
+        let dir = tmp_dir("db_destroy");
+        {
+            let db = open(&dir, None);
+            db.put(&WriteOptions::default(), b"foo", b"bar").unwrap();
+        }
+        destroy_db(&dir, &Options::default()).unwrap();
+        assert!(DB::open(Options::default(), &dir).is_err());
+    }
+
+    #[test]
+    fn lock_file() {
+        let dir = tmp_dir("db_lock_file");
+        let db = open(&dir, None);
+        assert!(db.env.file_exists(&lock_file_name(&dir)));
+
+        // The database cannot be opened or destroyed while it is open.
+        let err = DB::open(Options::default(), &dir).err().unwrap();
+        assert_eq!(err.ty(), ErrorType::IOError);
+        assert_eq!(destroy_db(&dir, &Options::default()).unwrap_err().ty(), ErrorType::IOError);
+        db.put(&WriteOptions::default(), b"foo", b"bar").unwrap();
+
+        drop(db);
+        let db = open(&dir, None);
+        assert_eq!(get(&db, "foo").as_deref(), Some("bar"));
+        drop(db);
+        destroy_db(&dir, &Options::default()).unwrap();
+        assert!(!Options::default().env.file_exists(&dir));
+    }
+
+    #[test]
+    fn pending_purge() {
+        let dir = tmp_dir("db_pending_purge");
+        let db = open(
+            &dir,
+            Some(Options {
+                write_buffer_size: 4096,
+                ..Options::default()
+            }),
+        );
+        for i in 0..500 {
+            db.put(&WriteOptions::default(), format!("key{:04}", i).as_bytes(), b"v1")
+                .unwrap();
+        }
+        let table_files = || {
+            let mut numbers: Vec<u64> = db
+                .env
+                .get_children(&dir)
+                .unwrap()
+                .iter()
+                .filter_map(|name| parse_file_name(name))
+                .filter(|(_, t)| *t == FileType::TableFile)
+                .map(|(number, _)| number)
+                .collect();
+            numbers.sort();
+            numbers
+        };
+
+        // An iterator keeps the files of its version alive across compactions.
+        let mut iter = db.new_iterator(&ReadOptions::default());
+        iter.seek_to_first();
+        for i in 0..500 {
+            db.put(&WriteOptions::default(), format!("key{:04}", i).as_bytes(), b"v2")
+                .unwrap();
+        }
+        db.compact_range(&KeyRange::all()).unwrap();
+        let pending = db.pending_purge();
+        assert!(!pending.is_empty());
+        let files = table_files();
+        assert!(pending.iter().all(|number| files.contains(number)));
+
+        let mut count = 0;
+        while iter.valid() {
+            assert_eq!(iter.value(), b"v1");
+            count += 1;
+            iter.next();
+        }
+        assert!(iter.status().is_ok());
+        assert_eq!(count, 500);
+
+        // Dropping the last reader deletes the obsolete files.
+        drop(iter);
+        assert!(db.pending_purge().is_empty());
+        let files = table_files();
+        assert!(pending.iter().all(|number| !files.contains(number)));
+        assert_eq!(get(&db, "key0000"), Some("v2".to_string()));
+
+        // Without readers, compactions delete their inputs right away.
+        db.compact_range(&KeyRange::all()).unwrap();
+        assert!(db.pending_purge().is_empty());
+    }
+
+    #[test]
+    fn file_deletions() {
+        let dir = tmp_dir("db_file_deletions");
+        let db = open(&dir, None);
+        let children = || {
+            let mut names = db.env.get_children(&dir).unwrap();
+            names.sort();
+            names
+        };
+        db.put(&WriteOptions::default(), b"foo", b"v1").unwrap();
+        db.compact_range(&KeyRange::all()).unwrap();
+
+        db.disable_file_deletions();
+        db.disable_file_deletions();
+        let live = db.get_live_files().unwrap();
+        assert!(live.files.contains(&"CURRENT".to_string()));
+        assert!(live.files.iter().any(|name| name.starts_with("MANIFEST-")));
+        assert!(live.files.iter().any(|name| name.ends_with(".ldb")));
+        assert!(live.manifest_file_size > 0);
+        let before = children();
+
+        // Neither compactions nor flushes delete files while deletions are disabled.
+        db.put(&WriteOptions::default(), b"foo", b"v2").unwrap();
+        db.compact_range(&KeyRange::all()).unwrap();
+        let during = children();
+        assert!(before.iter().all(|name| during.contains(name)));
+        assert!(during.iter().filter(|name| name.ends_with(".log")).count() > 1);
+        assert!(live
+            .files
+            .iter()
+            .all(|name| db.env.file_exists(&format!("{}/{}", dir, name))));
+
+        // Deletions stay disabled until every call has been undone.
+        db.enable_file_deletions();
+        assert_eq!(children(), during);
+        db.enable_file_deletions();
+        let after = children();
+        assert!(live.files.iter().any(|name| name.ends_with(".ldb") && !after.contains(name)));
+        assert_eq!(after.iter().filter(|name| name.ends_with(".log")).count(), 1);
+        assert_eq!(get(&db, "foo"), Some("v2".to_string()));
+    }
+
+    #[test]
+    fn flush() {
+        let dir = tmp_dir("db_flush");
+        let db = open(&dir, None);
+        // Flushed tables may be placed below level 0 when they overlap nothing.
+        let table_count = |db: &DB| {
+            let version = db.lock().versions.current();
+            (0..config::NUM_LEVELS).map(|level| version.files(level).len()).sum::<usize>()
+        };
+        db.flush(&FlushOptions::default()).unwrap();
+        assert_eq!(table_count(&db), 0);
+
+        db.put(&WriteOptions::default(), b"foo", b"v1").unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
+        assert_eq!(table_count(&db), 1);
+        assert!(db.lock().mem.is_empty());
+        assert_eq!(get(&db, "foo"), Some("v1".to_string()));
+
+        // A flush that does not wait is installed by the next one.
+        db.put(&WriteOptions::default(), b"bar", b"v2").unwrap();
+        db.flush(&FlushOptions { wait: false }).unwrap();
+        assert_eq!(get(&db, "bar"), Some("v2".to_string()));
+        db.flush(&FlushOptions::default()).unwrap();
+        assert_eq!(table_count(&db), 2);
+        drop(db);
+
+        let db = open(&dir, None);
+        assert_eq!(get(&db, "foo"), Some("v1".to_string()));
+        assert_eq!(get(&db, "bar"), Some("v2".to_string()));
+    }
+
+    #[test]
+    fn dynamic_level_bytes() {
+        let dir = tmp_dir("db_dynamic_level_bytes");
+        let db = open(
+            &dir,
+            Some(Options {
+                write_buffer_size: 16 * 1024,
+                level_compaction_dynamic_level_bytes: true,
+                ..Options::default()
+            }),
+        );
+        let wo = WriteOptions::default();
+        for i in 0..4000 {
+            db.put(&wo, format!("key{:06}", i % 1000).as_bytes(), format!("value{}", i).as_bytes())
+                .unwrap();
+        }
+        // The database is smaller than the base level size, so compactions move
+        // everything past level 0 to the last level.
+        db.wait_for_compact(&WaitForCompactOptions::default()).unwrap();
+        let state = db.lock();
+        assert!((1..config::NUM_LEVELS - 1).all(|level| state.versions.num_level_files(level) == 0));
+        assert!(state.versions.num_level_files(config::NUM_LEVELS - 1) > 0);
+        drop(state);
+        assert_eq!(get(&db, "key000123"), Some("value3123".to_string()));
+    }
+
+    // Records the threads that compactions filter entries on.
+    struct ThreadRecorder {
+        threads: Mutex<HashSet<thread::ThreadId>>,
+    }
+
+    impl CompactionFilter for ThreadRecorder {
+        fn name(&self) -> &str { "ThreadRecorder" }
+
+        fn filter(&self, _level: usize, _key: &[u8], _value: &[u8]) -> CompactionFilterDecision {
+            self.threads.lock().unwrap().insert(thread::current().id());
+            CompactionFilterDecision::Keep
+        }
+    }
+
+    #[test]
+    fn subcompactions() {
+        let dir = tmp_dir("db_subcompactions");
+        let recorder = Arc::new(ThreadRecorder {
+            threads: Mutex::new(HashSet::new()),
+        });
+        let options = Options {
+            write_buffer_size: 16 * 1024,
+            max_subcompactions: 4,
+            merge_operator: Some(new_string_append_operator(b',')),
+            compaction_filter: Some(recorder.clone()),
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        let wo = WriteOptions::default();
+        let mut model = BTreeMap::new();
+        for i in 0..6000 {
+            let key = format!("key{:06}", i * 7919 % 2000);
+            match i % 5 {
+                0 => {
+                    db.delete(&wo, key.as_bytes()).unwrap();
+                    model.remove(&key);
+                }
+                1 => {
+                    db.merge(&wo, key.as_bytes(), b"m").unwrap();
+                    let value: &mut String = model.entry(key).or_default();
+                    if !value.is_empty() {
+                        value.push(',');
+                    }
+                    value.push('m');
+                }
+                _ => {
+                    let value = format!("v{}", i);
+                    db.put(&wo, key.as_bytes(), value.as_bytes()).unwrap();
+                    model.insert(key, value);
+                }
+            }
+        }
+        db.compact_range(&KeyRange::all()).unwrap();
+        assert!(recorder.threads.lock().unwrap().len() > 1);
+        assert!(model.into_iter().eq(contents(&db, &ReadOptions::default())));
+    }
+
+    // Holds compactions in their filter until opened.
+    struct GateFilter {
+        entered: AtomicBool,
+        open: Mutex<bool>,
+        opened: Condvar,
+    }
+
+    impl GateFilter {
+        fn open(&self) {
+            *self.open.lock().unwrap() = true;
+            self.opened.notify_all();
+        }
+    }
+
+    impl CompactionFilter for GateFilter {
+        fn name(&self) -> &str { "GateFilter" }
+
+        fn filter(&self, _level: usize, _key: &[u8], _value: &[u8]) -> CompactionFilterDecision {
+            self.entered.store(true, Ordering::Release);
+            let mut open = self.open.lock().unwrap();
+            while !*open {
+                open = self.opened.wait(open).unwrap();
+            }
+            CompactionFilterDecision::Keep
+        }
+    }
+
+    #[test]
+    fn flush_during_compaction() {
+        let dir = tmp_dir("db_flush_during_compaction");
+        let gate = Arc::new(GateFilter {
+            entered: AtomicBool::new(false),
+            open: Mutex::new(false),
+            opened: Condvar::new(),
+        });
+        let db = open(
+            &dir,
+            Some(Options {
+                compaction_filter: Some(gate.clone()),
+                ..Options::default()
+            }),
+        );
+        let wo = WriteOptions::default();
+        // Flush level-0 files until a compaction starts and blocks in the filter.
+        let mut i = 0;
+        while !gate.entered.load(Ordering::Acquire) && i < 100 {
+            for j in 0..100 {
+                db.put(&wo, format!("key{:03}", j).as_bytes(), format!("v{}", i).as_bytes()).unwrap();
+            }
+            db.flush(&FlushOptions::default()).unwrap();
+            i += 1;
+            thread::sleep(Duration::from_millis(1));
+        }
+        let level0 = db.lock().versions.num_level_files(0);
+
+        // A flush neither waits for the compaction nor for the DB mutex it held.
+        db.put(&wo, b"key050", b"late").unwrap();
+        db.flush(&FlushOptions::default()).unwrap();
+        let flushed = db.lock().versions.num_level_files(0) == level0 + 1;
+        let compacting = db.running_jobs().iter().any(|job| job.kind == BackgroundJobKind::Compaction);
+        gate.open();
+        assert!(gate.entered.load(Ordering::Acquire));
+        assert!(flushed);
+        assert!(compacting);
+
+        db.wait_for_compact(&WaitForCompactOptions::default()).unwrap();
+        assert!(db.lock().versions.num_level_files(0) < db.options.level0_file_num_compaction_trigger);
+        assert_eq!(get(&db, "key050"), Some("late".to_string()));
+        assert_eq!(get(&db, "key042"), Some(format!("v{}", i - 1)));
+    }
+
+    // An Env whose clock can be moved forward.
+    struct ClockEnv {
+        base: Arc<dyn Env>,
+        offset_micros: AtomicU64,
+    }
+
+    impl Env for ClockEnv {
+        fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn crate::leveldb::env::SequentialFile>> {
+            self.base.new_sequential_file(fname)
+        }
+
+        fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn crate::leveldb::env::RandomAccessFile>> {
+            self.base.new_random_access_file(fname)
+        }
+
+        fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> { self.base.new_writable_file(fname) }
+
+        fn file_exists(&self, fname: &str) -> bool { self.base.file_exists(fname) }
+
+        fn get_children(&self, dir: &str) -> Result<Vec<String>> { self.base.get_children(dir) }
+
+        fn remove_file(&self, fname: &str) -> Result<()> { self.base.remove_file(fname) }
+
+        fn create_dir(&self, dirname: &str) -> Result<()> { self.base.create_dir(dirname) }
+
+        fn remove_dir(&self, dirname: &str) -> Result<()> { self.base.remove_dir(dirname) }
+
+        fn get_file_size(&self, fname: &str) -> Result<u64> { self.base.get_file_size(fname) }
+
+        fn rename_file(&self, src: &str, target: &str) -> Result<()> { self.base.rename_file(src, target) }
+
+        fn lock_file(&self, fname: &str) -> Result<Box<dyn FileLock>> { self.base.lock_file(fname) }
+
+        fn now_micros(&self) -> u64 { self.base.now_micros() + self.offset_micros.load(Ordering::Relaxed) }
+    }
+
+    // Counts the entries that compactions pass to the filter.
+    struct CountingFilter {
+        count: AtomicU64,
+    }
+
+    impl CompactionFilter for CountingFilter {
+        fn name(&self) -> &str { "CountingFilter" }
+
+        fn filter(&self, _level: usize, _key: &[u8], _value: &[u8]) -> CompactionFilterDecision {
+            self.count.fetch_add(1, Ordering::Relaxed);
+            CompactionFilterDecision::Keep
+        }
+    }
+
+    #[test]
+    fn periodic_compaction() {
+        let dir = tmp_dir("db_periodic_compaction");
+        let env = Arc::new(ClockEnv {
+            base: crate::leveldb::env::default_env(),
+            offset_micros: AtomicU64::new(0),
+        });
+        let filter = Arc::new(CountingFilter {
+            count: AtomicU64::new(0),
+        });
+        let options = Options {
+            env: env.clone(),
+            write_buffer_size: 16 * 1024,
+            level_compaction_dynamic_level_bytes: true,
+            periodic_compaction_seconds: 3600,
+            compaction_filter: Some(filter.clone()),
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options.clone()));
+        let wo = WriteOptions::default();
+        for i in 0..1000 {
+            db.put(&wo, format!("key{:06}", i).as_bytes(), b"value").unwrap();
+        }
+        db.compact_range(&KeyRange::all()).unwrap();
+        let tables = |db: &DB| -> Vec<(usize, Arc<FileMetaData>)> {
+            let version = db.lock().versions.current();
+            (0..config::NUM_LEVELS)
+                .flat_map(|level| version.files(level).iter().map(move |f| (level, f.clone())))
+                .collect()
+        };
+        let before = tables(&db);
+        assert!(before.iter().all(|(level, f)| *level == config::NUM_LEVELS - 1 && f.creation_time > 0));
+
+        // Nothing happens until the files are old enough.
+        let count = filter.count.load(Ordering::Relaxed);
+        db.wait_for_compact(&WaitForCompactOptions::default()).unwrap();
+        assert_eq!(filter.count.load(Ordering::Relaxed), count);
+
+        // Files of the last level are rewritten in place, and the filter sees every
+        // entry again.
+        env.offset_micros.store(7200 * 1_000_000, Ordering::Relaxed);
+        db.wait_for_compact(&WaitForCompactOptions::default()).unwrap();
+        assert!(filter.count.load(Ordering::Relaxed) >= count + 1000);
+        let after = tables(&db);
+        assert!(after.iter().all(|(level, _)| *level == config::NUM_LEVELS - 1));
+        assert!(after
+            .iter()
+            .all(|(_, f)| before.iter().all(|(_, old)| old.number != f.number)));
+        assert_eq!(get(&db, "key000123"), Some("value".to_string()));
+
+        // Creation times survive a reopen.
+        drop(db);
+        let db = open(&dir, Some(options));
+        let reopened: Vec<u64> = tables(&db).iter().map(|(_, f)| f.creation_time).collect();
+        assert_eq!(reopened, after.iter().map(|(_, f)| f.creation_time).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn check_files_at_open() {
+        let dir = tmp_dir("db_check_files");
+        let env = Options::default().env;
+        let table = {
+            let db = open(&dir, None);
+            db.put(&WriteOptions::default(), b"foo", b"bar").unwrap();
+            db.compact_range(&KeyRange::all()).unwrap();
+            let current = db.lock().versions.current();
+            let f = (0..config::NUM_LEVELS).flat_map(|level| current.files(level)).next().unwrap().clone();
+            table_file_name(&dir, f.number)
+        };
+
+        // Stray files are reported but do not prevent opening.
+        let stray = table_file_name(&dir, 999_999);
+        write_string_to_file(env.as_ref(), b"junk", &stray).unwrap();
+        {
+            let db = open(&dir, None);
+            assert_eq!(get(&db, "foo"), Some("bar".to_string()));
+            assert_eq!(db.get_property("hefesto.unexpected-files").unwrap(), "999999.ldb\n");
+        }
+        let log = read_file_to_string(env.as_ref(), &info_log_file_name(&dir)).unwrap();
+        let log = String::from_utf8(log).unwrap();
+        assert!(log.contains(" WARN Unexpected file in DB directory: 999999.ldb"), "{}", log);
+        env.remove_file(&stray).unwrap();
+
+        // A table of the wrong size is named in the error, unless sizes are not checked.
+        let contents = read_file_to_string(env.as_ref(), &table).unwrap();
+        write_string_to_file(env.as_ref(), &contents[..contents.len() - 1], &table).unwrap();
+        let e = DB::open(Options::default(), &dir).err().unwrap();
+        assert!(e.is_corruption());
+        assert!(e.detail().unwrap().starts_with(&table), "{}", e);
+        let skip_stats = Options {
+            skip_stats_update_on_db_open: true,
+            ..Options::default()
+        };
+        drop(open(&dir, Some(skip_stats.clone())));
+
+        env.remove_file(&table).unwrap();
+        for options in [Options::default(), skip_stats] {
+            let e = DB::open(options, &dir).err().unwrap();
+            assert!(e.is_corruption());
+            assert_eq!(e.msg(), "missing table file");
+            assert_eq!(e.detail(), Some(table.as_str()));
+        }
+    }
+
+    #[test]
+    fn bulk_load() {
+        let dir = tmp_dir("db_bulk_load");
+        let db = open(
+            &dir,
+            Some(Options {
+                write_buffer_size: 4096,
+                bulk_load_write_buffer_size: 16 * 1024,
+                ..Options::default()
+            }),
+        );
+        db.prepare_for_bulk_load();
+        // Loading in shuffled order makes every flush overlap level 0.
+        for i in 0..2000 {
+            db.put(&WriteOptions::default(), format!("key{:04}", i * 7919 % 2000).as_bytes(), &[b'v'; 100])
+                .unwrap();
+        }
+        let level0 = db.lock().versions.num_level_files(0);
+        assert!(level0 > db.options.level0_stop_writes_trigger, "{}", level0);
+        // Memtables used the larger bulk load write buffer, and were vectors.
+        assert!(level0 < 2000 * 110 / 4096, "{}", level0);
+        assert!(db.lock().mem.is_vector());
+        assert_eq!(get(&db, "key0123").unwrap().len(), 100);
+
+        db.finish_bulk_load().unwrap();
+        let state = db.lock();
+        assert!(!state.mem.is_vector());
+        assert_eq!(state.versions.num_level_files(0), 0);
+        let levels = (1..config::NUM_LEVELS)
+            .filter(|&level| state.versions.num_level_files(level) > 0)
+            .count();
+        assert_eq!(levels, 1);
+        drop(state);
+        assert_eq!(contents(&db, &ReadOptions::default()).len(), 2000);
+    }
+
+    #[test]
+    fn max_open_files() {
+        let dir = tmp_dir("db_max_open_files");
+        let options = Options {
+            write_buffer_size: 4096,
+            max_open_files: 20,
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options.clone()));
+        for i in 0..2000 {
+            db.put(&WriteOptions::default(), format!("key{:04}", i).as_bytes(), &[b'v'; 100]).unwrap();
+        }
+        db.flush(&FlushOptions::default()).unwrap();
+        let num_files: usize = (0..config::NUM_LEVELS).map(|l| db.lock().versions.num_level_files(l)).sum();
+        assert!(num_files > 20, "{}", num_files);
+        drop(db);
+
+        // Tables are opened as reads need them. Ten of the files are kept for other uses.
+        let db = open(&dir, Some(options.clone()));
+        assert_eq!(db.table_cache.num_open_tables(), 0);
+        for i in (0..2000).step_by(7) {
+            assert_eq!(get(&db, &format!("key{:04}", i)).unwrap().len(), 100);
+        }
+        assert_eq!(db.table_cache.num_open_tables(), 10);
+        drop(db);
+
+        let db = open(
+            &dir,
+            Some(Options {
+                max_open_files: -1,
+                ..options
+            }),
+        );
+        assert_eq!(db.table_cache.num_open_tables(), num_files);
+    }
+
+    #[test]
+    fn builtin_comparators() {
+        use crate::util::comparator::{case_insensitive_comparator, reverse_bytewise_comparator};
+
+        let dir = tmp_dir("db_reverse_comparator");
+        let reverse = Options {
+            comparator: reverse_bytewise_comparator(),
+            write_buffer_size: 4096,
+            ..Options::default()
+        };
+        {
+            let db = open(&dir, Some(reverse.clone()));
+            for i in 0..200 {
+                db.put(&WriteOptions::default(), format!("key{:03}", i).as_bytes(), b"v")
+                    .unwrap();
+            }
+            db.compact_range(&KeyRange::all()).unwrap();
+            let keys: Vec<String> = contents(&db, &ReadOptions::default()).into_iter().map(|(k, _)| k).collect();
+            assert_eq!(keys.len(), 200);
+            assert_eq!(keys[0], "key199");
+            assert!(keys.windows(2).all(|w| w[0] > w[1]));
+        }
+        let e = DB::open(Options::default(), &dir).err().unwrap();
+        assert_eq!(e.ty(), ErrorType::InvalidArgument);
+        assert_eq!(
+            e.detail(),
+            Some("database uses hefesto.ReverseBytewiseComparator, opened with leveldb.BytewiseComparator")
+        );
+        assert_eq!(get(&open(&dir, Some(reverse)), "key007"), Some("v".to_string()));
+
+        let dir = tmp_dir("db_case_insensitive_comparator");
+        let db = open(
+            &dir,
+            Some(Options {
+                comparator: case_insensitive_comparator(),
+                ..Options::default()
+            }),
+        );
+        db.put(&WriteOptions::default(), b"Hello", b"world").unwrap();
+        assert_eq!(get(&db, "HELLO"), Some("world".to_string()));
+        db.delete(&WriteOptions::default(), b"hello").unwrap();
+        assert_eq!(get(&db, "Hello"), None);
+    }
+
+    #[test]
+    fn max_entry_sizes() {
+        let dir = tmp_dir("max_entry_sizes");
+        let db = open(
+            &dir,
+            Some(Options {
+                max_key_size: 16,
+                max_value_size: 100,
+                ..Options::default()
+            }),
+        );
+        let wo = WriteOptions::default();
+        db.put(&wo, &[b'k'; 16], &[b'v'; 100]).unwrap();
+
+        let err = db.put(&wo, &[b'k'; 17], b"v").unwrap_err();
+        assert_eq!(err.ty(), ErrorType::InvalidArgument);
+        assert_eq!(err.detail(), Some("key is 17 bytes, limit is 16"));
+        let err = db.put(&wo, b"k", &[b'v'; 101]).unwrap_err();
+        assert_eq!(err.ty(), ErrorType::InvalidArgument);
+        assert_eq!(err.detail(), Some("value is 101 bytes, limit is 100"));
+        assert!(db.delete(&wo, &[b'k'; 17]).is_err());
+
+        // A batch with one oversized entry is rejected as a whole.
+        let mut batch = WriteBatch::new();
+        batch.put(b"a", b"va");
+        batch.put(b"b", &[b'v'; 101]);
+        assert!(db.write(&wo, batch).is_err());
+        assert_eq!(get(&db, "a"), None);
+        assert_eq!(get(&db, "b"), None);
+    }
+
+    #[test]
+    fn prefix_same_as_start() {
+        let dir = tmp_dir("prefix_same_as_start");
+        let db = open(
+            &dir,
+            Some(Options {
+                filter_policy: Some(new_bloom_filter_policy(10)),
+                prefix_extractor: Some(new_fixed_prefix_transform(4)),
+                ..Options::default()
+            }),
+        );
+        let wo = WriteOptions::default();
+        // One table per tenant, plus a few entries left in the memtable
+        for tenant in 0..4 {
+            for i in 0..20 {
+                db.put(&wo, format!("t{:03}{:04}", tenant * 2, i).as_bytes(), b"v").unwrap();
+            }
+            db.switch_memtable(&mut db.lock()).unwrap();
+        }
+        db.put(&wo, b"t0020000", b"mem").unwrap();
+        db.delete(&wo, b"t0020001").unwrap();
+
+        let options = ReadOptions {
+            prefix_same_as_start: true,
+            ..ReadOptions::default()
+        };
+        let mut iter = db.new_iterator(&options);
+        iter.seek(b"t0020010");
+        let mut keys = Vec::new();
+        while iter.valid() {
+            keys.push(String::from_utf8(iter.key().to_vec()).unwrap());
+            iter.next();
+        }
+        assert_eq!(keys.len(), 10);
+        assert!(keys.iter().all(|k| k.starts_with("t002")));
+
+        iter.seek(b"t002");
+        assert_eq!(iter.key(), b"t0020000");
+        assert_eq!(iter.value(), b"mem");
+        iter.next();
+        assert_eq!(iter.key(), b"t0020002");
+
+        // No key has this prefix, although later keys exist
+        iter.seek(b"t003");
+        assert!(!iter.valid());
+        // seek_to_first() is not bound to a prefix
+        iter.seek_to_first();
+        let mut n = 0;
+        while iter.valid() {
+            n += 1;
+            iter.next();
+        }
+        assert_eq!(n, 79);
+
+        let mut iter = db.new_iterator(&ReadOptions::default());
+        iter.seek(b"t003");
+        assert_eq!(iter.key(), b"t0040000");
+    }
+
+    #[test]
+    fn metrics_registry() {
+        let registry = Arc::new(MetricsRegistry::new());
+        let options = |shard: &str| Options {
+            metrics_registry: Some(registry.clone()),
+            metrics_labels: vec![("shard".to_string(), shard.to_string())],
+            ..Options::default()
+        };
+        let dir_a = tmp_dir("metrics_registry_a");
+        let dir_b = tmp_dir("metrics_registry_b");
+        let a = open(&dir_a, Some(options("a")));
+        let b = open(&dir_b, Some(options("b")));
+        let wo = WriteOptions::default();
+        for i in 0..10 {
+            a.put(&wo, format!("key{}", i).as_bytes(), b"value").unwrap();
+        }
+        let mut batch = WriteBatch::new();
+        batch.put(b"x", b"1");
+        batch.delete(b"y");
+        b.write(&wo, batch).unwrap();
+        get(&a, "key1");
+        a.multi_get(&ReadOptions::default(), &[b"key2", b"missing"]);
+        a.switch_memtable(&mut a.lock()).unwrap();
+
+        let dbs = registry.collect();
+        assert_eq!(dbs.len(), 2);
+        assert_eq!(dbs[0].name, dir_a);
+        assert_eq!(dbs[0].labels, vec![("shard".to_string(), "a".to_string())]);
+        assert_eq!(dbs[0].metrics, a.metrics());
+        assert_eq!(dbs[0].metrics.keys_written, 10);
+        assert_eq!(dbs[0].metrics.keys_read, 3);
+        assert_eq!(dbs[0].metrics.flushes, 1);
+        assert_eq!(dbs[0].metrics.level_files.iter().sum::<u64>(), 1);
+        assert_eq!(dbs[0].metrics.get_micros.count(), 1);
+        assert_eq!(dbs[0].metrics.write_micros.count(), 10);
+        assert_eq!(dbs[1].metrics.keys_written, 2);
+        assert!(dbs[1].metrics.memtable_bytes > 0);
+        assert!(registry
+            .render_text()
+            .contains(&format!("hefesto_keys_written_total{{db=\"{}\",shard=\"b\"}} 2\n", dir_b)));
+
+        drop(a);
+        let dbs = registry.collect();
+        assert_eq!(dbs.len(), 1);
+        assert_eq!(dbs[0].name, dir_b);
+
+        // Databases opened without a registry report nothing
+        drop(b);
+        let _c = open(&tmp_dir("metrics_registry_c"), None);
+        assert!(registry.collect().is_empty());
+    }
+
+    #[test]
+    fn cancellation() {
+        let dir = tmp_dir("db_cancellation");
+        let db = open(&dir, None);
+        let wo = WriteOptions::default();
+        for i in 0..3000 {
+            db.put(&wo, format!("key{:06}", i).as_bytes(), b"value").unwrap();
+        }
+        db.switch_memtable(&mut db.lock()).unwrap();
+        db.put(&wo, b"mem", b"v").unwrap();
+
+        let token = CancellationToken::new();
+        let options = ReadOptions {
+            cancel: Some(token.clone()),
+            ..ReadOptions::default()
+        };
+        let keys: Vec<String> = (0..200).map(|i| format!("key{:06}", i * 10)).collect();
+        let mut refs: Vec<&[u8]> = keys.iter().map(|k| k.as_bytes()).collect();
+        refs.push(b"mem");
+        assert!(db.multi_get(&options, &refs).iter().all(|r| r.as_ref().unwrap().is_some()));
+        db.verify_checksums(&options).unwrap();
+
+        token.cancel();
+        let results = db.multi_get(&options, &refs);
+        assert!(results[..200].iter().all(|r| r.as_ref().unwrap_err().is_aborted()));
+        // The memtable is consulted first and is not interrupted.
+        assert_eq!(results[200].as_ref().unwrap().as_deref(), Some(&b"v"[..]));
+        assert!(db.verify_checksums(&options).unwrap_err().is_aborted());
+
+        let files = |db: &DB| (0..config::NUM_LEVELS).map(|l| db.lock().versions.num_level_files(l)).sum::<usize>();
+        let before = files(&db);
+        let compact_options = CompactRangeOptions { cancel: Some(token) };
+        assert!(db.compact_range_with_options(&compact_options, &KeyRange::all()).unwrap_err().is_aborted());
+        assert_eq!(files(&db), before);
+        assert_eq!(get(&db, "mem").as_deref(), Some("v"));
+
+        // Cancelling does not put the database in an error state.
+        db.put(&wo, b"after", b"v").unwrap();
+        db.compact_range_with_options(&CompactRangeOptions::default(), &KeyRange::all()).unwrap();
+        assert_eq!(get(&db, "key000010").as_deref(), Some("value"));
+
+        // Checksum errors name the damaged table.
+        drop(db);
+        corrupt_files(&dir, ".ldb", 20);
+        let db = open(&dir, None);
+        let err = db.verify_checksums(&ReadOptions::default()).unwrap_err();
+        assert!(err.is_corruption());
+        assert!(err.detail().unwrap().ends_with(".ldb"));
+    }
+
+    #[test]
+    fn scrub() {
+        let dir = tmp_dir("db_scrub");
+        let db = open(&dir, None);
+        let wo = WriteOptions::default();
+        for i in 0..100 {
+            db.put(&wo, format!("key{:06}", i).as_bytes(), b"value").unwrap();
+        }
+        db.flush(&FlushOptions::default()).unwrap();
+        db.put(&wo, b"unflushed", b"value").unwrap();
+
+        let report = db.scrub(&ReadOptions::default()).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.tables_checked, 1);
+        assert_eq!(report.logs_checked, 1);
+        assert!(report.bytes_checked > 0);
+
+        // Every damaged file is reported, not just the first
+        corrupt_files(&dir, ".ldb", 20);
+        corrupt_files(&dir, ".log", 20);
+        let report = db.scrub(&ReadOptions::default()).unwrap();
+        assert_eq!(report.problems.len(), 2);
+        assert!(report.problems[0].file.ends_with(".ldb"));
+        assert!(report.problems[1].file.ends_with(".log"));
+        assert!(report.problems.iter().all(|p| p.error.is_corruption()));
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let options = ReadOptions {
+            cancel: Some(cancel),
+            ..ReadOptions::default()
+        };
+        assert!(db.scrub(&options).unwrap_err().is_aborted());
+    }
+
+    #[test]
+    fn merge_operator() {
+        let dir = tmp_dir("db_merge");
+        let db = open(&dir, None);
+        let wo = WriteOptions::default();
+        assert_eq!(db.merge(&wo, b"k", b"v").unwrap_err().ty(), ErrorType::NotSupported);
+        drop(db);
+
+        let options = Options {
+            merge_operator: Some(new_string_append_operator(b',')),
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options.clone()));
+        // The number of internal entries stored for `key` in the tables.
+        let table_entries = |db: &DB, key: &[u8]| {
+            let mut list = Vec::new();
+            db.lock().versions.current().add_iterators(&ReadOptions::default(), &db.table_cache, &mut list);
+            let mut iter = new_merging_iterator(Arc::new(db.internal_comparator.clone()), list);
+            iter.seek_to_first();
+            let mut n = 0;
+            while iter.valid() {
+                n += (parse_internal_key(iter.key()).unwrap().user_key == key) as usize;
+                iter.next();
+            }
+            n
+        };
+
+        db.merge(&wo, b"list", b"a").unwrap();
+        db.merge(&wo, b"list", b"b").unwrap();
+        assert_eq!(get(&db, "list").as_deref(), Some("a,b"));
+        db.put(&wo, b"list", b"x").unwrap();
+        db.merge(&wo, b"list", b"c").unwrap();
+        assert_eq!(get(&db, "list").as_deref(), Some("x,c"));
+
+        // Operands in the memtable combine with values in tables.
+        db.switch_memtable(&mut db.lock()).unwrap();
+        db.merge(&wo, b"list", b"d").unwrap();
+        let snapshot = db.get_snapshot();
+        db.merge(&wo, b"list", b"e").unwrap();
+        db.delete(&wo, b"gone").unwrap();
+        db.merge(&wo, b"gone", b"z").unwrap();
+        assert_eq!(get(&db, "list").as_deref(), Some("x,c,d,e"));
+        assert_eq!(get(&db, "gone").as_deref(), Some("z"));
+        let at_snapshot = ReadOptions {
+            snapshot: Some(snapshot.clone()),
+            ..ReadOptions::default()
+        };
+        assert_eq!(db.get(&at_snapshot, b"list").unwrap().unwrap(), b"x,c,d");
+        let keys: [&[u8]; 3] = [b"gone", b"list", b"none"];
+        let found: Vec<_> = db.multi_get(&ReadOptions::default(), &keys).into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(found, vec![Some(b"z".to_vec()), Some(b"x,c,d,e".to_vec()), None]);
+        assert_eq!(
+            contents(&db, &ReadOptions::default()),
+            vec![("gone".to_string(), "z".to_string()), ("list".to_string(), "x,c,d,e".to_string())]
+        );
+        assert_eq!(contents(&db, &at_snapshot), vec![("list".to_string(), "x,c,d".to_string())]);
+
+        // Compaction keeps what the snapshot sees, then collapses the operands once it
+        // is released.
+        db.compact_range(&KeyRange::all()).unwrap();
+        assert_eq!(get(&db, "list").as_deref(), Some("x,c,d,e"));
+        assert_eq!(db.get(&at_snapshot, b"list").unwrap().unwrap(), b"x,c,d");
+        assert!(table_entries(&db, b"list") > 1);
+        drop(at_snapshot);
+        drop(snapshot);
+        db.merge(&wo, b"list", b"f").unwrap();
+        db.compact_range(&KeyRange::all()).unwrap();
+        assert_eq!(table_entries(&db, b"list"), 1);
+        assert_eq!(get(&db, "list").as_deref(), Some("x,c,d,e,f"));
+        assert_eq!(get(&db, "gone").as_deref(), Some("z"));
+        drop(db);
+
+        let db = open(&dir, Some(options));
+        db.merge(&wo, b"list", b"g").unwrap();
+        assert_eq!(get(&db, "list").as_deref(), Some("x,c,d,e,f,g"));
+    }
+
+    #[test]
+    fn ingest_external_file() {
+        let dir = tmp_dir("db_ingest");
+        let ext_dir = tmp_dir("db_ingest_ext");
+        let options = Options {
+            filter_policy: Some(new_bloom_filter_policy(10)),
+            ..Options::default()
+        };
+        let write_file = |name: &str, entries: &[(&str, Option<&str>)]| {
+            let path = format!("{}/{}", ext_dir, name);
+            let mut writer = SstFileWriter::new(&options);
+            writer.open(&path).unwrap();
+            for (k, v) in entries {
+                match v {
+                    Some(v) => writer.put(k.as_bytes(), v.as_bytes()).unwrap(),
+                    None => writer.delete(k.as_bytes()).unwrap(),
+                }
+            }
+            writer.finish().unwrap();
+            path
+        };
+        let db = open(&dir, Some(options.clone()));
+        let wo = WriteOptions::default();
+        for k in ["a", "b", "c"] {
+            db.put(&wo, k.as_bytes(), b"old").unwrap();
+        }
+        db.compact_range(&KeyRange::all()).unwrap();
+        db.put(&wo, b"m", b"old").unwrap();
+        let snapshot = db.get_snapshot();
+        let io = IngestExternalFileOptions::default();
+
+        // Files must not overlap each other, and must come from SstFileWriter.
+        let f1 = write_file("1.sst", &[("x", Some("1")), ("y", Some("1"))]);
+        let f2 = write_file("2.sst", &[("y", Some("2")), ("z", Some("2"))]);
+        assert_eq!(db.ingest_external_file(&[&f1, &f2], &io).unwrap_err().ty(), ErrorType::InvalidArgument);
+        let current = db.lock().versions.current();
+        let table = (0..config::NUM_LEVELS).flat_map(|level| current.files(level)).next().unwrap();
+        let table = table_file_name(&dir, table.number);
+        assert_eq!(db.ingest_external_file(&[&table], &io).unwrap_err().ty(), ErrorType::InvalidArgument);
+        assert_eq!(get(&db, "x"), None);
+
+        // A range without data is linked as is; overlapping ones are rewritten.
+        let f3 = write_file("3.sst", &[("b", Some("new")), ("c", None)]);
+        let f4 = write_file("4.sst", &[("m", Some("new"))]);
+        let sequence = db.lock().versions.last_sequence();
+        db.ingest_external_file(&[&f1, &f3, &f4], &io).unwrap();
+        assert_eq!(db.lock().versions.last_sequence(), sequence + 1);
+        assert!(db.lock().mem.is_empty());
+        assert_eq!(
+            contents(&db, &ReadOptions::default()),
+            vec![
+                ("a".to_string(), "old".to_string()),
+                ("b".to_string(), "new".to_string()),
+                ("m".to_string(), "new".to_string()),
+                ("x".to_string(), "1".to_string()),
+                ("y".to_string(), "1".to_string()),
+            ]
+        );
+        let at_snapshot = ReadOptions {
+            snapshot: Some(snapshot),
+            ..ReadOptions::default()
+        };
+        assert_eq!(db.get(&at_snapshot, b"b").unwrap().unwrap(), b"old");
+        assert_eq!(db.get(&at_snapshot, b"c").unwrap().unwrap(), b"old");
+        assert_eq!(db.get(&at_snapshot, b"m").unwrap().unwrap(), b"old");
+        drop(at_snapshot);
+
+        db.compact_range(&KeyRange::all()).unwrap();
+        drop(db);
+        let db = open(&dir, Some(options.clone()));
+        assert_eq!(get(&db, "b").as_deref(), Some("new"));
+        assert_eq!(get(&db, "c"), None);
+        assert_eq!(get(&db, "x").as_deref(), Some("1"));
+        db.put(&wo, b"x", b"2").unwrap();
+        assert_eq!(get(&db, "x").as_deref(), Some("2"));
+    }
+
+    struct RetentionFilter {
+        enabled: AtomicBool,
+    }
+
+    impl CompactionFilter for RetentionFilter {
+        fn name(&self) -> &str { "RetentionFilter" }
+
+        fn filter(&self, _level: usize, _key: &[u8], value: &[u8]) -> CompactionFilterDecision {
+            if !self.enabled.load(Ordering::Relaxed) {
+                return CompactionFilterDecision::Keep;
+            }
+            match value {
+                b"expired" => CompactionFilterDecision::Remove,
+                _ => match value.strip_prefix(b"upper:") {
+                    Some(rest) => CompactionFilterDecision::ChangeValue(rest.to_ascii_uppercase()),
+                    None => CompactionFilterDecision::Keep,
+                },
+            }
+        }
+    }
+
+    struct RetentionFilterFactory {
+        created: AtomicU64,
+    }
+
+    impl CompactionFilterFactory for RetentionFilterFactory {
+        fn name(&self) -> &str { "RetentionFilterFactory" }
+
+        fn create_compaction_filter(&self, _info: &CompactionJobInfo) -> Box<dyn CompactionFilter> {
+            self.created.fetch_add(1, Ordering::Relaxed);
+            Box::new(RetentionFilter {
+                enabled: AtomicBool::new(true),
+            })
+        }
+    }
+
+    #[test]
+    fn compaction_filter() {
+        let dir = tmp_dir("db_compaction_filter");
+        let filter = Arc::new(RetentionFilter {
+            enabled: AtomicBool::new(false),
+        });
+        let options = Options {
+            compaction_filter: Some(filter.clone()),
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        let wo = WriteOptions::default();
+        db.put(&wo, b"a", b"old").unwrap();
+        db.put(&wo, b"b", b"keep").unwrap();
+        db.compact_range(&KeyRange::all()).unwrap();
+
+        // Removing the newer value of "a" must not bring back the older one.
+        db.put(&wo, b"a", b"expired").unwrap();
+        db.put(&wo, b"c", b"upper:abc").unwrap();
+        db.put(&wo, b"d", b"expired").unwrap();
+        let snapshot = db.get_snapshot();
+        db.put(&wo, b"e", b"expired").unwrap();
+        filter.enabled.store(true, Ordering::Relaxed);
+        db.compact_range(&KeyRange::all()).unwrap();
+        assert_eq!(
+            contents(&db, &ReadOptions::default()),
+            vec![
+                ("b".to_string(), "keep".to_string()),
+                ("c".to_string(), "ABC".to_string()),
+                ("e".to_string(), "expired".to_string()),
+            ]
+        );
+        drop(snapshot);
+        db.put(&wo, b"d", b"x").unwrap();
+        db.compact_range(&KeyRange::all()).unwrap();
+        assert_eq!(get(&db, "e"), None);
+        drop(db);
+
+        let factory = Arc::new(RetentionFilterFactory {
+            created: AtomicU64::new(0),
+        });
+        let options = Options {
+            compaction_filter_factory: Some(factory.clone()),
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        db.put(&wo, b"c", b"upper:xyz").unwrap();
+        db.compact_range(&KeyRange::all()).unwrap();
+        assert!(factory.created.load(Ordering::Relaxed) > 0);
+        assert_eq!(get(&db, "c").as_deref(), Some("XYZ"));
+    }
+
+    #[derive(Default)]
+    struct RecordingListener {
+        flushes: Mutex<Vec<FlushJobInfo>>,
+        created: Mutex<Vec<TableFileCreationInfo>>,
+        deleted: Mutex<Vec<u64>>,
+    }
+
+    impl crate::leveldb::listener::EventListener for RecordingListener {
+        fn on_flush_completed(&self, info: &FlushJobInfo) { self.flushes.lock().unwrap().push(info.clone()); }
+
+        fn on_table_file_created(&self, info: &TableFileCreationInfo) { self.created.lock().unwrap().push(info.clone()); }
+
+        fn on_table_file_deleted(&self, info: &crate::leveldb::listener::TableFileDeletionInfo) {
+            assert!(info.status.is_ok());
+            self.deleted.lock().unwrap().push(info.file_number);
+        }
+    }
+
+    #[test]
+    fn table_file_listener() {
+        let dir = tmp_dir("db_table_file_listener");
+        let listener = Arc::new(RecordingListener::default());
+        let options = Options {
+            listeners: vec![listener.clone()],
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        let wo = WriteOptions::default();
+        db.put(&wo, b"a", b"va").unwrap();
+        db.put(&wo, b"c", b"vc").unwrap();
+        db.switch_memtable(&mut db.lock()).unwrap();
+        db.put(&wo, b"b", b"vb").unwrap();
+        db.switch_memtable(&mut db.lock()).unwrap();
+
+        let flushes = listener.flushes.lock().unwrap().clone();
+        assert_eq!(flushes.len(), 2);
+        let created = listener.created.lock().unwrap().clone();
+        assert_eq!(created.len(), 2);
+        assert!(created.iter().all(|f| f.reason == TableFileCreationReason::Flush));
+        assert_eq!(created[0].file_number, flushes[0].file_number);
+        assert_eq!(created[0].file_size, flushes[0].file_size);
+        assert!(db.env().file_exists(&created[1].file_path));
+
+        db.compact_range(&KeyRange::all()).unwrap();
+        let created = listener.created.lock().unwrap().clone();
+        assert!(created[2..].iter().all(|f| f.reason == TableFileCreationReason::Compaction));
+        assert!(created.len() > 2);
+        let mut deleted = listener.deleted.lock().unwrap().clone();
+        deleted.sort_unstable();
+        assert_eq!(deleted, vec![flushes[0].file_number, flushes[1].file_number]);
+        assert_eq!(contents(&db, &ReadOptions::default()).len(), 3);
+    }
+
+    #[test]
+    fn info_log() {
+        let dir = tmp_dir("db_info_log");
+        let db = open(&dir, None);
+        db.put(&WriteOptions::default(), b"k", b"v").unwrap();
+        db.switch_memtable(&mut db.lock()).unwrap();
+        db.put(&WriteOptions::default(), b"k2", b"v2").unwrap();
+        db.crash();
+        let read_log = |fname: &str| String::from_utf8(read_file_to_string(default_env().as_ref(), fname).unwrap()).unwrap();
+        let log = read_log(&info_log_file_name(&dir));
+        assert!(log.contains(" INFO Level-0 table #"), "{}", log);
+
+        // Reopening moves the LOG file aside and replays the remaining log.
+        let db = open(&dir, None);
+        assert_eq!(read_log(&old_info_log_file_name(&dir)), log);
+        drop(db);
+        let log = read_log(&info_log_file_name(&dir));
+        assert!(log.contains(" INFO Recovering log #"));
+
+        // A supplied logger replaces the LOG file.
+        #[derive(Default)]
+        struct Lines(Mutex<Vec<String>>);
+        impl Logger for Lines {
+            fn logv(&self, level: InfoLogLevel, args: fmt::Arguments<'_>) {
+                self.0.lock().unwrap().push(format!("{} {}", level.as_str(), args));
+            }
+        }
+        let lines = Arc::new(Lines::default());
+        let options = Options {
+            info_log: Some(lines.clone()),
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options));
+        db.put(&WriteOptions::default(), b"k3", b"v3").unwrap();
+        db.switch_memtable(&mut db.lock()).unwrap();
+        drop(db);
+        assert!(lines.0.lock().unwrap().iter().any(|l| l.starts_with("INFO Level-0 table #")));
+        assert_eq!(read_log(&info_log_file_name(&dir)), log);
+    }
+
+    #[test]
+    fn open_read_only() {
+        let dir = tmp_dir("db_open_read_only");
+        let db = open(&dir, None);
+        let wo = WriteOptions::default();
+        db.put(&wo, b"flushed", b"v1").unwrap();
+        db.switch_memtable(&mut db.lock()).unwrap();
+        db.put(&wo, b"logged", b"v2").unwrap();
+        let files = |dir: &str| {
+            let mut names = default_env().get_children(dir).unwrap();
+            names.sort();
+            names
+        };
+        let before = files(&dir);
+
+        // The writer still holds the lock and has data only in its log.
+        let reader = DB::open_read_only(Options::default(), &dir, false).unwrap();
+        assert_eq!(get(&reader, "flushed").as_deref(), Some("v1"));
+        assert_eq!(get(&reader, "logged").as_deref(), Some("v2"));
+        assert_eq!(reader.put(&wo, b"k", b"v").unwrap_err().ty(), ErrorType::NotSupported);
+        assert_eq!(reader.delete(&wo, b"logged").unwrap_err().ty(), ErrorType::NotSupported);
+        assert_eq!(reader.compact_range(&KeyRange::all()).unwrap_err().ty(), ErrorType::NotSupported);
+        let e = DB::open_read_only(Options::default(), &dir, true).err().unwrap();
+        assert_eq!(e.ty(), ErrorType::InvalidArgument);
+
+        // Later writes are not visible to the reader.
+        db.put(&wo, b"later", b"v3").unwrap();
+        assert_eq!(get(&reader, "later"), None);
+        drop(reader);
+        assert_eq!(files(&dir), before);
+
+        drop(db);
+        let reader = DB::open_read_only(Options::default(), &dir, true).unwrap();
+        assert_eq!(get(&reader, "later").as_deref(), Some("v3"));
+        let missing = format!("{}/missing", dir);
+        assert!(DB::open_read_only(Options::default(), &missing, false).is_err());
+        assert!(!default_env().file_exists(&missing));
+    }
+
+    #[test]
+    fn secondary_instance() {
+        let dir = tmp_dir("db_secondary_instance");
+        let secondary_dir = format!("{}/secondary", dir);
+        let primary_dir = format!("{}/primary", dir);
+        let primary = open(&primary_dir, None);
+        let wo = WriteOptions::default();
+        primary.put(&wo, b"a", b"v1").unwrap();
+
+        let secondary = DB::open_as_secondary(Options::default(), &primary_dir, &secondary_dir).unwrap();
+        assert_eq!(get(&secondary, "a").as_deref(), Some("v1"));
+        assert!(default_env().file_exists(&info_log_file_name(&secondary_dir)));
+        assert_eq!(secondary.put(&wo, b"k", b"v").unwrap_err().ty(), ErrorType::NotSupported);
+
+        // Records appended to the log since the last catch-up.
+        primary.put(&wo, b"b", b"v2").unwrap();
+        primary.delete(&wo, b"a").unwrap();
+        assert_eq!(get(&secondary, "b"), None);
+        let snapshot = secondary.get_snapshot();
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(get(&secondary, "a"), None);
+        assert_eq!(get(&secondary, "b").as_deref(), Some("v2"));
+        let at_snapshot = ReadOptions {
+            snapshot: Some(snapshot),
+            ..ReadOptions::default()
+        };
+        assert_eq!(secondary.get(&at_snapshot, b"a").unwrap().as_deref(), Some(&b"v1"[..]));
+
+        // Flushes and compactions that retire the log.
+        primary.put(&wo, b"c", b"v3").unwrap();
+        primary.compact_range(&KeyRange::all()).unwrap();
+        primary.put(&wo, b"d", b"v4").unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(
+            contents(&secondary, &ReadOptions::default()),
+            vec![
+                ("b".to_string(), "v2".to_string()),
+                ("c".to_string(), "v3".to_string()),
+                ("d".to_string(), "v4".to_string()),
+            ]
+        );
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(contents(&secondary, &ReadOptions::default()).len(), 3);
+        assert_eq!(primary.try_catch_up_with_primary().unwrap_err().ty(), ErrorType::NotSupported);
+    }
+
+    #[test]
+    fn get_updates_since() {
+        let dir = tmp_dir("db_get_updates_since");
+        let options = Options {
+            wal_archive_size_limit: 1 << 20,
+            ..Options::default()
+        };
+        let db = open(&dir, Some(options.clone()));
+        let wo = WriteOptions::default();
+        db.put(&wo, b"a", b"v1").unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"b", b"v2");
+        batch.delete(b"a");
+        db.write(&wo, batch).unwrap();
+        db.switch_memtable(&mut db.lock()).unwrap();
+        db.put(&wo, b"c", b"v3").unwrap();
+        assert!(!db.archived_logs().is_empty());
+
+        let sequences = |start| {
+            db.get_updates_since(start)
+                .unwrap()
+                .map(|batch| {
+                    let batch = batch.unwrap();
+                    (batch.sequence(), batch.count())
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sequences(0), vec![(1, 1), (2, 2), (4, 1)]);
+        assert_eq!(sequences(3), vec![(2, 2), (4, 1)]);
+        assert_eq!(sequences(4), vec![(4, 1)]);
+        assert_eq!(sequences(5), vec![]);
+        db.put(&wo, b"d", b"v4").unwrap();
+        assert_eq!(sequences(5), vec![(5, 1)]);
+        drop(db);
+        destroy_db(&dir, &Options::default()).unwrap();
+        assert!(!default_env().file_exists(&dir));
+
+        // Without an archive, the flushed updates are gone.
+        let db = open(&dir, None);
+        db.put(&wo, b"a", b"v1").unwrap();
+        db.switch_memtable(&mut db.lock()).unwrap();
+        db.put(&wo, b"b", b"v2").unwrap();
+        assert_eq!(db.get_updates_since(1).err().unwrap().ty(), ErrorType::NotFound);
+        assert_eq!(db.get_updates_since(2).unwrap().count(), 1);
+    }
+}
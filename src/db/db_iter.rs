@@ -0,0 +1,404 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::{
+    db::{
+        dbformat::{
+            append_internal_key, extract_user_key, parse_internal_key, ParsedInternalKey, SequenceNumber, ValueType, MAX_SEQUENCE_NUMBER,
+            VALUE_TYPE_FOR_SEEK,
+        },
+        merge::merge_entries,
+    },
+    leveldb::{
+        comparator::Comparator,
+        iterator::Iterator,
+        merge_operator::MergeOperator,
+        result::{Error, ErrorType, Result},
+        slice_transform::SliceTransform,
+    },
+};
+
+/// Which direction is the iterator currently moving?
+/// (1) When moving forward, the internal iterator is positioned at the exact entry
+///     that yields key(), value(), or past the entries of key() if the value was
+///     combined from merge operands.
+/// (2) When moving backwards, the internal iterator is positioned just before all
+///     entries whose user key == key().
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// Memtables and sstables that make the DB representation contain (userkey,seq,type)
+/// => uservalue entries. DBIter combines multiple entries for the same userkey found in
+/// the DB representation into a single entry while accounting for sequence numbers,
+/// deletion markers, overwrites, etc.
+struct DBIter {
+    user_comparator: Arc<dyn Comparator>,
+    iter: Box<dyn Iterator>,
+    sequence: SequenceNumber,
+    status: Result<()>,
+    // Current key when the iterator is positioned on an entry
+    saved_key: Vec<u8>,
+    // Current value when moving backwards
+    saved_value: Vec<u8>,
+    direction: Direction,
+    valid: bool,
+    // Set for iterators that stay within the prefix of their seek target.
+    prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    // Prefix of the last seek target, which all yielded keys must share.
+    prefix: Option<Vec<u8>>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    // Value of the current entry when it was combined from merge operands, in which
+    // case `iter` is already positioned past the entries of the current key.
+    merged_value: Option<Vec<u8>>,
+}
+
+impl DBIter {
+    fn parse_key(&mut self) -> Option<(Vec<u8>, SequenceNumber, ValueType)> {
+        match parse_internal_key(self.iter.key()) {
+            Some(ikey) => Some((ikey.user_key.to_vec(), ikey.sequence, ikey.value_type)),
+            None => {
+                self.status = Err(Error::new(ErrorType::Corruption, "corrupted internal key in DBIter"));
+                None
+            }
+        }
+    }
+
+    /// Advance the underlying iterator to the next visible entry, skipping entries
+    /// for `skip` (if set) and anything hidden by a deletion or newer than the
+    /// iterator's sequence number.
+    fn find_next_user_entry(&mut self, mut skipping: bool) {
+        // Loop until we hit an acceptable entry to yield
+        assert!(self.iter.valid());
+        loop {
+            if let Some((user_key, sequence, value_type)) = self.parse_key() {
+                if !self.in_prefix(&user_key) {
+                    // Keys with the same prefix are adjacent, so no later entry matches.
+                    break;
+                }
+                if sequence <= self.sequence {
+                    match value_type {
+                        ValueType::Deletion => {
+                            // Arrange to skip all upcoming entries for this key since
+                            // they are hidden by this deletion.
+                            self.saved_key = user_key;
+                            skipping = true;
+                        }
+                        ValueType::Value => {
+                            if skipping
+                                && self.user_comparator.compare(&user_key, &self.saved_key) != Ordering::Greater
+                            {
+                                // Entry hidden
+                            } else {
+                                self.saved_key = user_key;
+                                self.valid = true;
+                                return;
+                            }
+                        }
+                        ValueType::Merge => {
+                            if skipping
+                                && self.user_comparator.compare(&user_key, &self.saved_key) != Ordering::Greater
+                            {
+                                // Entry hidden
+                            } else {
+                                let operator = self.merge_operator.as_deref();
+                                match merge_entries(self.user_comparator.as_ref(), operator, self.iter.as_mut(), &user_key) {
+                                    Ok(value) => {
+                                        self.saved_key = user_key;
+                                        self.merged_value = Some(value);
+                                        self.valid = true;
+                                        return;
+                                    }
+                                    Err(e) => {
+                                        self.status = Err(e);
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            self.iter.next();
+            if !self.iter.valid() {
+                break;
+            }
+        }
+        self.saved_key.clear();
+        self.valid = false;
+    }
+
+    /// Move the underlying iterator backwards to the entries of the previous visible
+    /// user key, and make it the current entry.
+    fn find_prev_user_entry(&mut self) {
+        assert!(self.direction == Direction::Reverse);
+        let mut value_type = ValueType::Deletion;
+        while self.iter.valid() {
+            if let Some((user_key, sequence, entry_type)) = self.parse_key() {
+                if !self.in_prefix(&user_key) {
+                    // Keys with the same prefix are adjacent, so no earlier entry matches.
+                    break;
+                }
+                if sequence <= self.sequence {
+                    if value_type != ValueType::Deletion
+                        && self.user_comparator.compare(&user_key, &self.saved_key) == Ordering::Less
+                    {
+                        // We encountered a non-deleted value in entries for previous keys.
+                        break;
+                    }
+                    value_type = entry_type;
+                    self.saved_value.clear();
+                    match value_type {
+                        ValueType::Deletion => self.saved_key.clear(),
+                        ValueType::Value | ValueType::Merge => {
+                            self.saved_value.extend_from_slice(self.iter.value());
+                            self.saved_key = user_key;
+                        }
+                    }
+                }
+            }
+            self.iter.prev();
+        }
+
+        match value_type {
+            ValueType::Deletion => {
+                // End
+                self.valid = false;
+                self.saved_key.clear();
+                self.saved_value.clear();
+                self.direction = Direction::Forward;
+            }
+            ValueType::Value => self.valid = true,
+            ValueType::Merge => self.merge_backward(),
+        }
+    }
+
+    /// Combine the merge operands of the current key, whose newest visible entry is a
+    /// merge operand. The operands are read forward from that entry, after which the
+    /// underlying iterator returns to just before the entries of the key.
+    fn merge_backward(&mut self) {
+        let user_key = std::mem::take(&mut self.saved_key);
+        let mut seek_key = Vec::new();
+        append_internal_key(&mut seek_key, &ParsedInternalKey::new(&user_key, self.sequence, VALUE_TYPE_FOR_SEEK));
+        self.iter.seek(&seek_key);
+        let operator = self.merge_operator.as_deref();
+        let merged = merge_entries(self.user_comparator.as_ref(), operator, self.iter.as_mut(), &user_key);
+
+        seek_key.clear();
+        append_internal_key(&mut seek_key, &ParsedInternalKey::new(&user_key, MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK));
+        self.iter.seek(&seek_key);
+        if self.iter.valid() {
+            self.iter.prev();
+        } else {
+            self.iter.seek_to_last();
+        }
+
+        match merged {
+            Ok(value) => {
+                self.saved_key = user_key;
+                self.saved_value = value;
+                self.valid = true;
+            }
+            Err(e) => {
+                self.status = Err(e);
+                self.saved_value.clear();
+                self.valid = false;
+            }
+        }
+    }
+
+    fn in_prefix(&self, user_key: &[u8]) -> bool {
+        match (self.prefix.as_ref(), self.prefix_extractor.as_ref()) {
+            (Some(prefix), Some(t)) => t.in_domain(user_key) && t.transform(user_key)[..] == prefix[..],
+            _ => true,
+        }
+    }
+}
+
+impl Iterator for DBIter {
+    fn valid(&self) -> bool { self.valid }
+
+    fn seek_to_first(&mut self) {
+        self.direction = Direction::Forward;
+        self.prefix = None;
+        self.merged_value = None;
+        self.saved_key.clear();
+        self.saved_value.clear();
+        self.iter.seek_to_first();
+        if self.iter.valid() {
+            self.find_next_user_entry(false);
+        } else {
+            self.valid = false;
+        }
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.prefix = match self.prefix_extractor.as_ref() {
+            Some(t) if t.in_domain(target) => Some(t.transform(target).into_owned()),
+            _ => None,
+        };
+        self.direction = Direction::Forward;
+        self.merged_value = None;
+        self.saved_value.clear();
+        self.saved_key.clear();
+        append_internal_key(
+            &mut self.saved_key,
+            &ParsedInternalKey::new(target, self.sequence, VALUE_TYPE_FOR_SEEK),
+        );
+        let seek_key = std::mem::take(&mut self.saved_key);
+        self.iter.seek(&seek_key);
+        if self.iter.valid() {
+            self.find_next_user_entry(false);
+        } else {
+            self.valid = false;
+        }
+    }
+
+    fn seek_to_last(&mut self) {
+        self.direction = Direction::Reverse;
+        self.prefix = None;
+        self.merged_value = None;
+        self.saved_key.clear();
+        self.saved_value.clear();
+        self.iter.seek_to_last();
+        self.find_prev_user_entry();
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        self.prefix = match self.prefix_extractor.as_ref() {
+            Some(t) if t.in_domain(target) => Some(t.transform(target).into_owned()),
+            _ => None,
+        };
+        self.direction = Direction::Reverse;
+        self.merged_value = None;
+        self.saved_value.clear();
+        self.saved_key.clear();
+        // The last internal key for target has the smallest sequence number and type.
+        append_internal_key(
+            &mut self.saved_key,
+            &ParsedInternalKey::new(target, 0, ValueType::Deletion),
+        );
+        let seek_key = std::mem::take(&mut self.saved_key);
+        self.iter.seek_for_prev(&seek_key);
+        self.find_prev_user_entry();
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid);
+        if self.direction == Direction::Reverse {
+            // iter is pointing just before the entries for key(), so advance into the
+            // range of entries for key() and then use the normal skipping code below.
+            self.direction = Direction::Forward;
+            self.saved_value.clear();
+            if self.iter.valid() {
+                self.iter.next();
+            } else {
+                self.iter.seek_to_first();
+            }
+        } else if self.merged_value.take().is_none() {
+            // saved_key already holds the current user key; skip its older entries.
+            self.iter.next();
+        }
+        if !self.iter.valid() {
+            self.valid = false;
+            self.saved_key.clear();
+            return;
+        }
+        self.find_next_user_entry(true);
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid);
+        if self.direction == Direction::Forward {
+            // iter is pointing at the current entry, or past the entries of key() after
+            // a merge. Scan backwards until the key changes so we can use the normal
+            // reverse scanning code.
+            if self.merged_value.take().is_some() && !self.iter.valid() {
+                self.iter.seek_to_last();
+            } else {
+                self.iter.prev();
+            }
+            loop {
+                if !self.iter.valid() {
+                    self.valid = false;
+                    self.saved_key.clear();
+                    return;
+                }
+                if self.user_comparator.compare(extract_user_key(self.iter.key()), &self.saved_key) == Ordering::Less {
+                    break;
+                }
+                self.iter.prev();
+            }
+            self.direction = Direction::Reverse;
+        }
+        self.find_prev_user_entry();
+    }
+
+    fn key(&self) -> &[u8] {
+        assert!(self.valid);
+        &self.saved_key
+    }
+
+    fn value(&self) -> &[u8] {
+        assert!(self.valid);
+        match (self.direction, self.merged_value.as_ref()) {
+            (Direction::Reverse, _) => &self.saved_value,
+            (Direction::Forward, Some(value)) => value,
+            (Direction::Forward, None) => self.iter.value(),
+        }
+    }
+
+    fn status(&self) -> Result<()> {
+        self.status.clone()?;
+        self.iter.status()
+    }
+}
+
+/// Return a new iterator that converts internal keys (yielded by `internal_iter`) that
+/// were live at the specified sequence number into appropriate user keys. With a
+/// `prefix_extractor`, the iterator stops at the first key whose prefix differs from
+/// that of the last seek target. Merge operands are combined with `merge_operator`.
+pub fn new_db_iterator(
+    user_comparator: Arc<dyn Comparator>,
+    internal_iter: Box<dyn Iterator>,
+    sequence: SequenceNumber,
+    prefix_extractor: Option<Arc<dyn SliceTransform>>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+) -> Box<dyn Iterator> {
+    Box::new(DBIter {
+        user_comparator,
+        iter: internal_iter,
+        sequence,
+        status: Ok(()),
+        saved_key: Vec::new(),
+        saved_value: Vec::new(),
+        direction: Direction::Forward,
+        valid: false,
+        prefix_extractor,
+        prefix: None,
+        merge_operator,
+        merged_value: None,
+    })
+}
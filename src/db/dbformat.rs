@@ -0,0 +1,474 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{borrow::Cow, cmp::Ordering, fmt, sync::Arc};
+
+use crate::{
+    leveldb::{comparator::Comparator, filter_policy::FilterPolicy, slice_transform::SliceTransform},
+    util::coding::{decode_fixed_64, encode_fixed_64, encode_varint_32_vec},
+};
+
+/// Grouping of constants. We may want to make some of these parameters set via options.
+pub mod config {
+    pub const NUM_LEVELS: usize = 7;
+
+    /// Maximum level to which a new compacted memtable is pushed if it does not create
+    /// overlap. We try to push to level 2 to avoid the relatively expensive level 0=>1
+    /// compactions and to avoid some expensive manifest file operations. We do not push
+    /// all the way to the largest level since that can generate a lot of wasted disk
+    /// space if the same key space is being repeatedly overwritten.
+    pub const MAX_MEM_COMPACT_LEVEL: usize = 2;
+
+    /// Largest combined size of a key and its value. Lengths are encoded as varint32
+    /// in write batches, memtable entries and blocks, and a block holding a single
+    /// entry must still be addressable with 32-bit offsets; the remaining headroom
+    /// covers the internal key tag, entry headers, restart array and block trailer.
+    pub const MAX_ENTRY_SIZE: usize = u32::MAX as usize - 1024;
+}
+
+pub type SequenceNumber = u64;
+
+/// We leave eight bits empty at the bottom so a type and sequence# can be packed
+/// together into 64-bits.
+pub const MAX_SEQUENCE_NUMBER: SequenceNumber = (1u64 << 56) - 1;
+
+/// Value types encoded as the last component of internal keys.
+///
+/// DO NOT CHANGE THESE ENUM VALUES: they are embedded in the on-disk data structures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Deletion = 0x0,
+    Value = 0x1,
+    Merge = 0x2,
+}
+
+impl ValueType {
+    pub fn from_u8(v: u8) -> Option<ValueType> {
+        match v {
+            0x0 => Some(ValueType::Deletion),
+            0x1 => Some(ValueType::Value),
+            0x2 => Some(ValueType::Merge),
+            _ => None,
+        }
+    }
+}
+
+/// `VALUE_TYPE_FOR_SEEK` defines the `ValueType` that should be passed when constructing
+/// a `ParsedInternalKey` object for seeking to a particular sequence number (since we
+/// sort sequence numbers in decreasing order and the value type is embedded as the
+/// low 8 bits in the sequence number in internal keys, we need to use the highest-
+/// numbered ValueType, not the lowest).
+pub const VALUE_TYPE_FOR_SEEK: ValueType = ValueType::Merge;
+
+/// Pack a sequence number and a value type into the 64-bit tag of an internal key.
+///
+/// REQUIRES: `seq <= MAX_SEQUENCE_NUMBER`. A larger sequence number would spill into
+/// the type byte and break the ordering of internal keys, so it is never truncated.
+/// Writers check `sequences_available()` before assigning sequence numbers.
+pub fn pack_sequence_and_type(seq: SequenceNumber, t: ValueType) -> u64 {
+    assert!(seq <= MAX_SEQUENCE_NUMBER, "sequence number {} does not fit in 56 bits", seq);
+    (seq << 8) | t as u64
+}
+
+/// Returns true iff `count` more sequence numbers can be assigned after `last`.
+pub fn sequences_available(last: SequenceNumber, count: u64) -> bool {
+    last <= MAX_SEQUENCE_NUMBER && count <= MAX_SEQUENCE_NUMBER - last
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedInternalKey<'a> {
+    pub user_key: &'a [u8],
+    pub sequence: SequenceNumber,
+    pub value_type: ValueType,
+}
+
+impl<'a> ParsedInternalKey<'a> {
+    pub fn new(user_key: &'a [u8], sequence: SequenceNumber, value_type: ValueType) -> Self {
+        Self {
+            user_key,
+            sequence,
+            value_type,
+        }
+    }
+}
+
+impl fmt::Display for ParsedInternalKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "'{}' @ {} : {}",
+            String::from_utf8_lossy(self.user_key),
+            self.sequence,
+            self.value_type as u8
+        )
+    }
+}
+
+/// Return the length of the encoding of `key`.
+pub fn internal_key_encoding_length(key: &ParsedInternalKey) -> usize { key.user_key.len() + 8 }
+
+/// Append the serialization of `key` to `result`.
+pub fn append_internal_key(result: &mut Vec<u8>, key: &ParsedInternalKey) {
+    result.extend_from_slice(key.user_key);
+    let mut buf = [0u8; 8];
+    encode_fixed_64(&mut buf, pack_sequence_and_type(key.sequence, key.value_type));
+    result.extend_from_slice(&buf);
+}
+
+/// Attempt to parse an internal key from `internal_key`. On success, returns the
+/// parsed data. On error, returns `None`.
+pub fn parse_internal_key(internal_key: &[u8]) -> Option<ParsedInternalKey<'_>> {
+    let n = internal_key.len();
+    if n < 8 {
+        return None;
+    }
+    let num = decode_fixed_64(&internal_key[n - 8..]);
+    let c = (num & 0xff) as u8;
+    ValueType::from_u8(c).map(|value_type| ParsedInternalKey {
+        user_key: &internal_key[..n - 8],
+        sequence: num >> 8,
+        value_type,
+    })
+}
+
+/// Returns the user key portion of an internal key.
+#[inline]
+pub fn extract_user_key(internal_key: &[u8]) -> &[u8] {
+    assert!(internal_key.len() >= 8);
+    &internal_key[..internal_key.len() - 8]
+}
+
+/// A comparator for internal keys that uses a specified comparator for the user key
+/// portion and breaks ties by decreasing sequence number.
+#[derive(Clone)]
+pub struct InternalKeyComparator {
+    user_comparator: Arc<dyn Comparator>,
+}
+
+impl InternalKeyComparator {
+    pub fn new(user_comparator: Arc<dyn Comparator>) -> Self { Self { user_comparator } }
+
+    #[inline]
+    pub fn user_comparator(&self) -> &Arc<dyn Comparator> { &self.user_comparator }
+
+    pub fn compare_internal_key(&self, a: &InternalKey, b: &InternalKey) -> Ordering {
+        self.compare(a.encode(), b.encode())
+    }
+}
+
+impl Comparator for InternalKeyComparator {
+    fn compare(&self, akey: &[u8], bkey: &[u8]) -> Ordering {
+        // Order by:
+        //    increasing user key (according to user-supplied comparator)
+        //    decreasing sequence number
+        //    decreasing type (though sequence# should be enough to disambiguate)
+        match self
+            .user_comparator
+            .compare(extract_user_key(akey), extract_user_key(bkey))
+        {
+            Ordering::Equal => {
+                let anum = decode_fixed_64(&akey[akey.len() - 8..]);
+                let bnum = decode_fixed_64(&bkey[bkey.len() - 8..]);
+                bnum.cmp(&anum)
+            }
+            r => r,
+        }
+    }
+
+    fn name(&self) -> &'static str { "leveldb.InternalKeyComparator" }
+
+    fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &[u8]) {
+        // Attempt to shorten the user portion of the key
+        let user_start = extract_user_key(start);
+        let user_limit = extract_user_key(limit);
+        let mut tmp = user_start.to_vec();
+        self.user_comparator.find_shortest_separator(&mut tmp, user_limit);
+        if tmp.len() < user_start.len()
+            && self.user_comparator.compare(user_start, &tmp) == Ordering::Less
+        {
+            // User key has become shorter physically, but larger logically.
+            // Tack on the earliest possible number to the shortened user key.
+            let mut buf = [0u8; 8];
+            encode_fixed_64(&mut buf, pack_sequence_and_type(MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK));
+            tmp.extend_from_slice(&buf);
+            assert_eq!(self.compare(start, &tmp), Ordering::Less);
+            assert_eq!(self.compare(&tmp, limit), Ordering::Less);
+            *start = tmp;
+        }
+    }
+
+    fn find_short_successor(&self, key: &mut Vec<u8>) {
+        let user_key = extract_user_key(key);
+        let mut tmp = user_key.to_vec();
+        self.user_comparator.find_short_successor(&mut tmp);
+        if tmp.len() < user_key.len()
+            && self.user_comparator.compare(user_key, &tmp) == Ordering::Less
+        {
+            // User key has become shorter physically, but larger logically.
+            // Tack on the earliest possible number to the shortened user key.
+            let mut buf = [0u8; 8];
+            encode_fixed_64(&mut buf, pack_sequence_and_type(MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK));
+            tmp.extend_from_slice(&buf);
+            assert_eq!(self.compare(key, &tmp), Ordering::Less);
+            *key = tmp;
+        }
+    }
+}
+
+/// Filter policy wrapper that converts from internal keys to user keys
+pub struct InternalFilterPolicy {
+    user_policy: Arc<dyn FilterPolicy>,
+}
+
+impl InternalFilterPolicy {
+    pub fn new(user_policy: Arc<dyn FilterPolicy>) -> Self { Self { user_policy } }
+}
+
+impl FilterPolicy for InternalFilterPolicy {
+    fn name(&self) -> &'static str { self.user_policy.name() }
+
+    fn create_filter(&self, keys: &[&[u8]], dst: &mut Vec<u8>) {
+        // We rely on the fact that the code in table.rs does not mind us adjusting keys.
+        let user_keys: Vec<&[u8]> = keys.iter().map(|k| extract_user_key(k)).collect();
+        self.user_policy.create_filter(&user_keys, dst);
+    }
+
+    fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool {
+        self.user_policy.key_may_match(extract_user_key(key), filter)
+    }
+
+    fn keys_may_match(&self, keys: &[&[u8]], filter: &[u8], results: &mut [bool]) {
+        let user_keys: Vec<&[u8]> = keys.iter().map(|k| extract_user_key(k)).collect();
+        self.user_policy.keys_may_match(&user_keys, filter, results);
+    }
+}
+
+/// Prefix extractor wrapper which applies a user prefix extractor to the user key of
+/// an internal key. The prefix is returned as an internal key itself, so that the
+/// prefix filters built from it go through `InternalFilterPolicy` like whole keys do.
+pub struct InternalSliceTransform {
+    user_transform: Arc<dyn SliceTransform>,
+}
+
+impl InternalSliceTransform {
+    pub fn new(user_transform: Arc<dyn SliceTransform>) -> Self { Self { user_transform } }
+}
+
+impl SliceTransform for InternalSliceTransform {
+    fn name(&self) -> &str { self.user_transform.name() }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        let prefix = self.user_transform.transform(extract_user_key(key));
+        let mut result = Vec::with_capacity(prefix.len() + 8);
+        append_internal_key(&mut result, &ParsedInternalKey::new(&prefix, MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK));
+        Cow::Owned(result)
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool { self.user_transform.in_domain(extract_user_key(key)) }
+}
+
+/// Modules in this directory should keep internal keys wrapped inside the following
+/// type instead of plain byte vectors so that we do not incorrectly use plain vector
+/// comparisons instead of an `InternalKeyComparator`.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct InternalKey {
+    rep: Vec<u8>,
+}
+
+impl InternalKey {
+    pub fn new(user_key: &[u8], s: SequenceNumber, t: ValueType) -> Self {
+        let mut rep = Vec::with_capacity(user_key.len() + 8);
+        append_internal_key(&mut rep, &ParsedInternalKey::new(user_key, s, t));
+        Self { rep }
+    }
+
+    /// Wrap an already encoded internal key.
+    pub fn decode_from(s: &[u8]) -> Self { Self { rep: s.to_vec() } }
+
+    /// Returns false iff the stored bytes do not look like an internal key.
+    pub fn is_valid(&self) -> bool { parse_internal_key(&self.rep).is_some() }
+
+    #[inline]
+    pub fn encode(&self) -> &[u8] {
+        assert!(!self.rep.is_empty());
+        &self.rep
+    }
+
+    #[inline]
+    pub fn user_key(&self) -> &[u8] { extract_user_key(&self.rep) }
+
+    pub fn is_empty(&self) -> bool { self.rep.is_empty() }
+
+    pub fn clear(&mut self) { self.rep.clear() }
+}
+
+impl fmt::Debug for InternalKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match parse_internal_key(&self.rep) {
+            Some(parsed) => write!(f, "{}", parsed),
+            None => write!(f, "(bad){}", String::from_utf8_lossy(&self.rep)),
+        }
+    }
+}
+
+/// A helper type useful for `DB::get()`
+pub struct LookupKey {
+    // We construct a byte vector of the form:
+    //    klength  varint32               <-- 0
+    //    userkey  u8[klength]            <-- kstart
+    //    tag      u64
+    data: Vec<u8>,
+    kstart: usize,
+}
+
+impl LookupKey {
+    /// Initialize for looking up `user_key` at a snapshot with the specified sequence
+    /// number.
+    pub fn new(user_key: &[u8], s: SequenceNumber) -> Self {
+        let mut data = Vec::with_capacity(user_key.len() + 13);
+        encode_varint_32_vec(&mut data, (user_key.len() + 8) as u32);
+        let kstart = data.len();
+        append_internal_key(&mut data, &ParsedInternalKey::new(user_key, s, VALUE_TYPE_FOR_SEEK));
+        Self { data, kstart }
+    }
+
+    /// Return a key suitable for lookup in a MemTable.
+    #[inline]
+    pub fn memtable_key(&self) -> &[u8] { &self.data }
+
+    /// Return an internal key (suitable for passing to an internal iterator)
+    #[inline]
+    pub fn internal_key(&self) -> &[u8] { &self.data[self.kstart..] }
+
+    /// Return the user key
+    #[inline]
+    pub fn user_key(&self) -> &[u8] { &self.data[self.kstart..self.data.len() - 8] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::comparator::bytewise_comparator;
+
+    fn ikey(user_key: &[u8], seq: u64, vt: ValueType) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        append_internal_key(&mut encoded, &ParsedInternalKey::new(user_key, seq, vt));
+        encoded
+    }
+
+    fn shorten(s: &[u8], l: &[u8]) -> Vec<u8> {
+        let mut result = s.to_vec();
+        InternalKeyComparator::new(bytewise_comparator()).find_shortest_separator(&mut result, l);
+        result
+    }
+
+    fn short_successor(s: &[u8]) -> Vec<u8> {
+        let mut result = s.to_vec();
+        InternalKeyComparator::new(bytewise_comparator()).find_short_successor(&mut result);
+        result
+    }
+
+    fn test_key(key: &[u8], seq: u64, vt: ValueType) {
+        let encoded = ikey(key, seq, vt);
+        let decoded = parse_internal_key(&encoded).expect("parse");
+        assert_eq!(key, decoded.user_key);
+        assert_eq!(seq, decoded.sequence);
+        assert_eq!(vt, decoded.value_type);
+        assert!(parse_internal_key(b"bar").is_none());
+    }
+
+    #[test]
+    fn sequence_limits() {
+        assert_eq!(
+            pack_sequence_and_type(MAX_SEQUENCE_NUMBER, ValueType::Value),
+            u64::MAX - 0xff + ValueType::Value as u64
+        );
+        assert!(sequences_available(0, MAX_SEQUENCE_NUMBER));
+        assert!(!sequences_available(1, MAX_SEQUENCE_NUMBER));
+        assert!(sequences_available(MAX_SEQUENCE_NUMBER - 3, 3));
+        assert!(!sequences_available(MAX_SEQUENCE_NUMBER - 3, 4));
+        assert!(sequences_available(MAX_SEQUENCE_NUMBER, 0));
+        assert!(!sequences_available(MAX_SEQUENCE_NUMBER + 1, 0));
+    }
+
+    #[test]
+    fn internal_key_encode_decode() {
+        let keys: [&[u8]; 4] = [b"", b"k", b"hello", b"longggggggggggggggggggggg"];
+        let seqs: [u64; 12] = [
+            1,
+            2,
+            3,
+            (1 << 8) - 1,
+            1 << 8,
+            (1 << 8) + 1,
+            (1 << 16) - 1,
+            1 << 16,
+            (1 << 16) + 1,
+            (1 << 32) - 1,
+            1 << 32,
+            (1 << 32) + 1,
+        ];
+        for k in keys.iter() {
+            for s in seqs.iter() {
+                test_key(k, *s, ValueType::Value);
+                test_key(b"hello", 1, ValueType::Deletion);
+            }
+        }
+    }
+
+    #[test]
+    fn internal_key_short_separator() {
+        // When user keys are same
+        assert_eq!(ikey(b"foo", 100, ValueType::Value), shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foo", 99, ValueType::Value)));
+        assert_eq!(ikey(b"foo", 100, ValueType::Value), shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foo", 101, ValueType::Value)));
+        assert_eq!(ikey(b"foo", 100, ValueType::Value), shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foo", 100, ValueType::Deletion)));
+
+        // When user keys are misordered
+        assert_eq!(ikey(b"foo", 100, ValueType::Value), shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"bar", 99, ValueType::Value)));
+
+        // When user keys are different, but correctly ordered
+        assert_eq!(
+            ikey(b"g", MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK),
+            shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"hello", 200, ValueType::Value))
+        );
+
+        // When start user key is prefix of limit user key
+        assert_eq!(ikey(b"foo", 100, ValueType::Value), shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foobar", 200, ValueType::Value)));
+
+        // When limit user key is prefix of start user key
+        assert_eq!(ikey(b"foobar", 100, ValueType::Value), shorten(&ikey(b"foobar", 100, ValueType::Value), &ikey(b"foo", 200, ValueType::Value)));
+    }
+
+    #[test]
+    fn internal_key_shortest_successor() {
+        assert_eq!(ikey(b"g", MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK), short_successor(&ikey(b"foo", 100, ValueType::Value)));
+        assert_eq!(ikey(b"\xff\xff", 100, ValueType::Value), short_successor(&ikey(b"\xff\xff", 100, ValueType::Value)));
+    }
+
+    #[test]
+    fn lookup_key() {
+        let k = LookupKey::new(b"foo", 7);
+        assert_eq!(k.user_key(), b"foo");
+        assert_eq!(k.internal_key(), &ikey(b"foo", 7, VALUE_TYPE_FOR_SEEK)[..]);
+        assert_eq!(k.memtable_key()[0] as usize, 11);
+        assert_eq!(&k.memtable_key()[1..], k.internal_key());
+    }
+}
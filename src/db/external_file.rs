@@ -0,0 +1,148 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::sync::Arc;
+
+use crate::{
+    db::dbformat::{append_internal_key, parse_internal_key, InternalKey, ParsedInternalKey, SequenceNumber},
+    leveldb::{
+        iterator::Iterator,
+        options::{Options, ReadOptions},
+        result::{Error, ErrorType, Result},
+    },
+    table::table::Table,
+};
+
+/// A table file written by `SstFileWriter`, validated for ingestion.
+pub(crate) struct ExternalFile {
+    pub path: String,
+    pub file_size: u64,
+    pub smallest: InternalKey,
+    pub largest: InternalKey,
+    pub table: Arc<Table>,
+}
+
+impl ExternalFile {
+    /// Open the table at `path` and check that it holds at least one entry and that
+    /// every entry has sequence number zero, as `SstFileWriter` writes them.
+    pub fn open(options: &Options, path: &str) -> Result<Self> {
+        let env = options.env.as_ref();
+        let file_size = env.get_file_size(path)?;
+        let table = Table::open(options, path, env.new_random_access_file(path)?, file_size)?;
+        let not_external = || {
+            Err(Error::new(ErrorType::InvalidArgument, "not an external sst file").with_detail(path.to_string()))
+        };
+
+        let mut iter = table.new_iterator(&ReadOptions::default());
+        iter.seek_to_first();
+        let mut smallest = None;
+        let mut largest = Vec::new();
+        while iter.valid() {
+            match parse_internal_key(iter.key()) {
+                Some(ikey) if ikey.sequence == 0 => {}
+                _ => return not_external(),
+            }
+            if smallest.is_none() {
+                smallest = Some(InternalKey::decode_from(iter.key()));
+            }
+            largest.clear();
+            largest.extend_from_slice(iter.key());
+            iter.next();
+        }
+        iter.status()?;
+        let Some(smallest) = smallest else {
+            return not_external();
+        };
+        Ok(Self {
+            path: path.to_string(),
+            file_size,
+            smallest,
+            largest: InternalKey::decode_from(&largest),
+            table,
+        })
+    }
+
+    /// Return an iterator over the entries of the file that carry `sequence` instead of
+    /// zero.
+    pub fn iter_with_sequence(&self, sequence: SequenceNumber) -> Box<dyn Iterator> {
+        Box::new(SequenceAssigningIterator {
+            iter: self.table.new_iterator(&ReadOptions::default()),
+            sequence,
+            key: Vec::new(),
+        })
+    }
+}
+
+struct SequenceAssigningIterator {
+    iter: Box<dyn Iterator>,
+    sequence: SequenceNumber,
+    // The current key with its sequence number replaced
+    key: Vec<u8>,
+}
+
+impl SequenceAssigningIterator {
+    fn update_key(&mut self) {
+        self.key.clear();
+        if self.iter.valid() {
+            // ExternalFile::open() checked that every key parses.
+            let ikey = parse_internal_key(self.iter.key()).expect("validated external file key");
+            append_internal_key(
+                &mut self.key,
+                &ParsedInternalKey::new(ikey.user_key, self.sequence, ikey.value_type),
+            );
+        }
+    }
+}
+
+impl Iterator for SequenceAssigningIterator {
+    fn valid(&self) -> bool { self.iter.valid() }
+
+    fn seek_to_first(&mut self) {
+        self.iter.seek_to_first();
+        self.update_key();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.iter.seek_to_last();
+        self.update_key();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.iter.seek(target);
+        self.update_key();
+    }
+
+    fn next(&mut self) {
+        self.iter.next();
+        self.update_key();
+    }
+
+    fn prev(&mut self) {
+        self.iter.prev();
+        self.update_key();
+    }
+
+    fn key(&self) -> &[u8] { &self.key }
+
+    fn value(&self) -> &[u8] { self.iter.value() }
+
+    fn status(&self) -> Result<()> { self.iter.status() }
+}
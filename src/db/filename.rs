@@ -0,0 +1,245 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// File names used by DB code
+
+use crate::leveldb::{
+    env::{write_string_to_file_sync, Env},
+    result::Result,
+};
+
+/// The kinds of files found in a database directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    LogFile,
+    TableFile,
+    DescriptorFile,
+    CurrentFile,
+    DBLockFile,
+    TempFile,
+    // Either the current one, or an old one
+    InfoLogFile,
+}
+
+fn make_file_name(dbname: &str, number: u64, suffix: &str) -> String {
+    format!("{}/{:06}.{}", dbname, number, suffix)
+}
+
+/// Return the name of the log file with the specified number in the db named by
+/// `dbname`.
+pub fn log_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(dbname, number, "log")
+}
+
+/// Return the name of the directory that holds the log files archived by the db named
+/// by `dbname`.
+pub fn archival_directory(dbname: &str) -> String { format!("{}/archive", dbname) }
+
+/// Return the name of the archived log file with the specified number in the db named
+/// by `dbname`.
+pub fn archived_log_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(&archival_directory(dbname), number, "log")
+}
+
+/// Return the name of the sstable with the specified number in the db named by
+/// `dbname`.
+pub fn table_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(dbname, number, "ldb")
+}
+
+/// Return the name of the descriptor file for the db named by `dbname` and the
+/// specified incarnation number.
+pub fn descriptor_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    format!("{}/MANIFEST-{:06}", dbname, number)
+}
+
+/// Return the name of the current file. This file contains the name of the current
+/// manifest file.
+pub fn current_file_name(dbname: &str) -> String { format!("{}/CURRENT", dbname) }
+
+/// Return the name of the lock file for the db named by `dbname`. The result will be
+/// prefixed with `dbname`.
+pub fn lock_file_name(dbname: &str) -> String { format!("{}/LOCK", dbname) }
+
+/// Return the name of the info log file for `dbname`.
+pub fn info_log_file_name(dbname: &str) -> String { format!("{}/LOG", dbname) }
+
+/// Return the name of the old info log file for `dbname`.
+pub fn old_info_log_file_name(dbname: &str) -> String { format!("{}/LOG.old", dbname) }
+
+/// Return the name of a temporary file owned by the db named `dbname`.
+pub fn temp_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(dbname, number, "dbtmp")
+}
+
+/// If `filename` is a leveldb file, return the number encoded in the filename (zero
+/// for CURRENT, LOCK and LOG files) and the type of the file. The filename must not contain the directory.
+///
+/// Owned filenames have the form:
+///    dbname/CURRENT
+///    dbname/LOCK
+///    dbname/LOG
+///    dbname/LOG.old
+///    dbname/MANIFEST-[0-9]+
+///    dbname/[0-9]+.(log|sst|ldb|dbtmp)
+pub fn parse_file_name(filename: &str) -> Option<(u64, FileType)> {
+    if filename == "CURRENT" {
+        return Some((0, FileType::CurrentFile));
+    }
+    if filename == "LOCK" {
+        return Some((0, FileType::DBLockFile));
+    }
+    if filename == "LOG" || filename == "LOG.old" {
+        return Some((0, FileType::InfoLogFile));
+    }
+    if let Some(rest) = filename.strip_prefix("MANIFEST-") {
+        return parse_number(rest).map(|number| (number, FileType::DescriptorFile));
+    }
+    let (number, suffix) = filename.split_once('.')?;
+    let number = parse_number(number)?;
+    let file_type = match suffix {
+        "log" => FileType::LogFile,
+        "sst" | "ldb" => FileType::TableFile,
+        "dbtmp" => FileType::TempFile,
+        _ => return None,
+    };
+    Some((number, file_type))
+}
+
+/// Parse a non-empty string of decimal digits that fits in a u64.
+fn parse_number(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Make the CURRENT file point to the descriptor file with the specified number.
+///
+/// The new contents are written to a temporary file that is then renamed over
+/// CURRENT, so a crash leaves either the old or the new CURRENT file, never a partial
+/// one.
+pub fn set_current_file(env: &dyn Env, dbname: &str, descriptor_number: u64) -> Result<()> {
+    // Remove leading "dbname/" and add newline to manifest file name
+    let manifest = descriptor_file_name(dbname, descriptor_number);
+    let contents = &manifest[dbname.len() + 1..];
+    let tmp = temp_file_name(dbname, descriptor_number);
+    let mut s = write_string_to_file_sync(env, format!("{}\n", contents).as_bytes(), &tmp);
+    if s.is_ok() {
+        s = env.rename_file(&tmp, &current_file_name(dbname));
+    }
+    if s.is_err() {
+        let _ = env.remove_file(&tmp);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        leveldb::env::{default_env, read_file_to_string},
+        util::testutil::tmp_dir,
+    };
+
+    #[test]
+    fn construction() {
+        assert_eq!(log_file_name("foo", 192), "foo/000192.log");
+        assert_eq!(archived_log_file_name("foo", 192), "foo/archive/000192.log");
+        assert_eq!(table_file_name("bar", 200), "bar/000200.ldb");
+        assert_eq!(descriptor_file_name("bar", 100), "bar/MANIFEST-000100");
+        assert_eq!(current_file_name("foo"), "foo/CURRENT");
+        assert_eq!(lock_file_name("foo"), "foo/LOCK");
+        assert_eq!(info_log_file_name("foo"), "foo/LOG");
+        assert_eq!(old_info_log_file_name("foo"), "foo/LOG.old");
+        assert_eq!(temp_file_name("tmp", 999), "tmp/000999.dbtmp");
+    }
+
+    #[test]
+    fn parse() {
+        let cases = [
+            ("100.log", 100, FileType::LogFile),
+            ("0.log", 0, FileType::LogFile),
+            ("0.sst", 0, FileType::TableFile),
+            ("0.ldb", 0, FileType::TableFile),
+            ("CURRENT", 0, FileType::CurrentFile),
+            ("LOCK", 0, FileType::DBLockFile),
+            ("LOG", 0, FileType::InfoLogFile),
+            ("LOG.old", 0, FileType::InfoLogFile),
+            ("MANIFEST-2", 2, FileType::DescriptorFile),
+            ("MANIFEST-7", 7, FileType::DescriptorFile),
+            ("18446744073709551615.log", 18446744073709551615, FileType::LogFile),
+            ("000999.dbtmp", 999, FileType::TempFile),
+        ];
+        for (name, number, file_type) in cases {
+            assert_eq!(parse_file_name(name), Some((number, file_type)), "{}", name);
+        }
+
+        let errors = [
+            "",
+            "foo",
+            "foo-dx-100.log",
+            ".log",
+            "manifest",
+            "CURREN",
+            "CURRENTX",
+            "LOC",
+            "LOCKX",
+            "LO",
+            "LOGX",
+            "LOG.older",
+            "MANIFES",
+            "MANIFEST",
+            "MANIFEST-",
+            "XMANIFEST-3",
+            "MANIFEST-3x",
+            "184467440737095516150.log",
+            "100",
+            "100.",
+            "100.lop",
+        ];
+        for name in errors {
+            assert_eq!(parse_file_name(name), None, "{}", name);
+        }
+
+        for name in [log_file_name("foo", 192), table_file_name("bar", 200), descriptor_file_name("bar", 100)] {
+            let (_, base) = name.split_once('/').unwrap();
+            assert!(parse_file_name(base).is_some());
+        }
+    }
+
+    #[test]
+    fn current_file() {
+        let env = default_env();
+        let dbname = tmp_dir("filename_current");
+        set_current_file(env.as_ref(), &dbname, 5).unwrap();
+        set_current_file(env.as_ref(), &dbname, 12).unwrap();
+        let contents = read_file_to_string(env.as_ref(), &current_file_name(&dbname)).unwrap();
+        assert_eq!(contents, b"MANIFEST-000012\n");
+        // The temporary files were renamed away.
+        assert_eq!(env.get_children(&dbname).unwrap(), vec!["CURRENT".to_string()]);
+    }
+}
@@ -0,0 +1,244 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{cmp::Ordering, ops::Bound};
+
+use crate::{db::version_edit::FileMetaData, leveldb::comparator::Comparator};
+
+/// A range of user keys. Each end may be inclusive, exclusive or unbounded.
+///
+/// Ranges carry no comparator; methods that compare keys take the user comparator of
+/// the database the range is applied to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyRange {
+    pub start: Bound<Vec<u8>>,
+    pub end: Bound<Vec<u8>>,
+}
+
+impl KeyRange {
+    pub fn new(start: Bound<&[u8]>, end: Bound<&[u8]>) -> Self {
+        Self {
+            start: start.map(<[u8]>::to_vec),
+            end: end.map(<[u8]>::to_vec),
+        }
+    }
+
+    /// The range of all keys.
+    pub fn all() -> Self { Self::new(Bound::Unbounded, Bound::Unbounded) }
+
+    /// The range `[start, end]`.
+    pub fn inclusive(start: &[u8], end: &[u8]) -> Self { Self::new(Bound::Included(start), Bound::Included(end)) }
+
+    /// The range `[start, limit)`.
+    pub fn half_open(start: &[u8], limit: &[u8]) -> Self { Self::new(Bound::Included(start), Bound::Excluded(limit)) }
+
+    /// The range `[begin, end]`, where a `None` end is unbounded.
+    pub fn from_options(begin: Option<&[u8]>, end: Option<&[u8]>) -> Self {
+        Self::new(
+            begin.map_or(Bound::Unbounded, Bound::Included),
+            end.map_or(Bound::Unbounded, Bound::Included),
+        )
+    }
+
+    /// The range of all keys starting with `prefix`. Only meaningful for comparators
+    /// that order keys bytewise.
+    pub fn prefix(prefix: &[u8]) -> Self {
+        // The first key past the prefix increments the last byte that is not 0xff.
+        let mut limit = prefix.to_vec();
+        while limit.last() == Some(&0xff) {
+            limit.pop();
+        }
+        let end = match limit.last_mut() {
+            Some(last) => {
+                *last += 1;
+                Bound::Excluded(limit)
+            }
+            // Every key starting with a run of 0xffs sorts last.
+            None => Bound::Unbounded,
+        };
+        Self {
+            start: Bound::Included(prefix.to_vec()),
+            end,
+        }
+    }
+
+    /// Returns true iff `key` sorts before every key in the range.
+    pub fn is_before_start(&self, ucmp: &dyn Comparator, key: &[u8]) -> bool {
+        match &self.start {
+            Bound::Included(start) => ucmp.compare(key, start) == Ordering::Less,
+            Bound::Excluded(start) => ucmp.compare(key, start) != Ordering::Greater,
+            Bound::Unbounded => false,
+        }
+    }
+
+    /// Returns true iff `key` sorts after every key in the range.
+    pub fn is_after_end(&self, ucmp: &dyn Comparator, key: &[u8]) -> bool {
+        match &self.end {
+            Bound::Included(end) => ucmp.compare(key, end) == Ordering::Greater,
+            Bound::Excluded(end) => ucmp.compare(key, end) != Ordering::Less,
+            Bound::Unbounded => false,
+        }
+    }
+
+    pub fn contains(&self, ucmp: &dyn Comparator, key: &[u8]) -> bool {
+        !self.is_before_start(ucmp, key) && !self.is_after_end(ucmp, key)
+    }
+
+    /// Returns true iff the range holds no keys. Exclusive bounds are assumed to have
+    /// keys between them, since the comparator cannot tell otherwise.
+    pub fn is_empty(&self, ucmp: &dyn Comparator) -> bool {
+        match (&self.start, &self.end) {
+            (Bound::Included(start), Bound::Included(end)) => ucmp.compare(start, end) == Ordering::Greater,
+            (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end) | Bound::Excluded(end)) => {
+                ucmp.compare(start, end) != Ordering::Less
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true iff the range shares a key with `[smallest, largest]`.
+    pub fn overlaps(&self, ucmp: &dyn Comparator, smallest: &[u8], largest: &[u8]) -> bool {
+        !self.is_before_start(ucmp, largest) && !self.is_after_end(ucmp, smallest)
+    }
+
+    /// Returns true iff the range shares a user key with the keys of file `f`.
+    pub fn overlaps_file(&self, ucmp: &dyn Comparator, f: &FileMetaData) -> bool {
+        self.overlaps(ucmp, f.smallest.user_key(), f.largest.user_key())
+    }
+
+    /// Return the keys that are in both `self` and `other`.
+    pub fn intersect(&self, ucmp: &dyn Comparator, other: &KeyRange) -> KeyRange {
+        // The later start and the earlier end win. On equal keys, exclusive is tighter.
+        let start = match (&self.start, &other.start) {
+            (Bound::Unbounded, b) | (b, Bound::Unbounded) => b.clone(),
+            (a, b) => match ucmp.compare(bound_key(a), bound_key(b)) {
+                Ordering::Greater => a.clone(),
+                Ordering::Less => b.clone(),
+                Ordering::Equal if matches!(a, Bound::Excluded(_)) => a.clone(),
+                Ordering::Equal => b.clone(),
+            },
+        };
+        let end = match (&self.end, &other.end) {
+            (Bound::Unbounded, b) | (b, Bound::Unbounded) => b.clone(),
+            (a, b) => match ucmp.compare(bound_key(a), bound_key(b)) {
+                Ordering::Less => a.clone(),
+                Ordering::Greater => b.clone(),
+                Ordering::Equal if matches!(a, Bound::Excluded(_)) => a.clone(),
+                Ordering::Equal => b.clone(),
+            },
+        };
+        KeyRange { start, end }
+    }
+
+    /// Widen the range so that it includes `[smallest, largest]`. Returns true iff the
+    /// range changed.
+    pub fn extend_to(&mut self, ucmp: &dyn Comparator, smallest: &[u8], largest: &[u8]) -> bool {
+        let mut changed = false;
+        if self.is_before_start(ucmp, smallest) {
+            self.start = Bound::Included(smallest.to_vec());
+            changed = true;
+        }
+        if self.is_after_end(ucmp, largest) {
+            self.end = Bound::Included(largest.to_vec());
+            changed = true;
+        }
+        changed
+    }
+}
+
+fn bound_key(bound: &Bound<Vec<u8>>) -> &[u8] {
+    match bound {
+        Bound::Included(key) | Bound::Excluded(key) => key,
+        Bound::Unbounded => unreachable!("unbounded ends have no key"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::dbformat::{InternalKey, ValueType},
+        util::comparator::bytewise_comparator,
+    };
+
+    #[test]
+    fn bounds() {
+        let ucmp = bytewise_comparator();
+        let ucmp = ucmp.as_ref();
+        let r = KeyRange::half_open(b"b", b"d");
+        assert!(!r.contains(ucmp, b"a"));
+        assert!(r.contains(ucmp, b"b"));
+        assert!(r.contains(ucmp, b"c"));
+        assert!(!r.contains(ucmp, b"d"));
+        let r = KeyRange::new(Bound::Excluded(b"b"), Bound::Included(b"d"));
+        assert!(!r.contains(ucmp, b"b"));
+        assert!(r.contains(ucmp, b"d"));
+        assert!(KeyRange::all().contains(ucmp, b""));
+        assert_eq!(KeyRange::from_options(None, Some(b"x")), KeyRange::new(Bound::Unbounded, Bound::Included(b"x")));
+
+        assert!(!KeyRange::inclusive(b"a", b"a").is_empty(ucmp));
+        assert!(KeyRange::half_open(b"a", b"a").is_empty(ucmp));
+        assert!(KeyRange::inclusive(b"b", b"a").is_empty(ucmp));
+        assert!(!KeyRange::all().is_empty(ucmp));
+    }
+
+    #[test]
+    fn prefix() {
+        let ucmp = bytewise_comparator();
+        let ucmp = ucmp.as_ref();
+        let r = KeyRange::prefix(b"ab");
+        assert_eq!(r, KeyRange::half_open(b"ab", b"ac"));
+        assert!(r.contains(ucmp, b"ab\xff\xff"));
+        assert!(!r.contains(ucmp, b"aa\xff"));
+        assert_eq!(KeyRange::prefix(b"a\xff\xff"), KeyRange::half_open(b"a\xff\xff", b"b"));
+        assert_eq!(KeyRange::prefix(b"\xff"), KeyRange::new(Bound::Included(b"\xff"), Bound::Unbounded));
+        assert_eq!(KeyRange::prefix(b""), KeyRange::new(Bound::Included(b""), Bound::Unbounded));
+    }
+
+    #[test]
+    fn overlap_and_intersection() {
+        let ucmp = bytewise_comparator();
+        let ucmp = ucmp.as_ref();
+        let file = FileMetaData {
+            number: 1,
+            file_size: 0,
+            smallest: InternalKey::new(b"c", 10, ValueType::Value),
+            largest: InternalKey::new(b"e", 10, ValueType::Value),
+            creation_time: 0,
+        };
+        assert!(KeyRange::half_open(b"a", b"d").overlaps_file(ucmp, &file));
+        assert!(!KeyRange::half_open(b"a", b"c").overlaps_file(ucmp, &file));
+        assert!(KeyRange::inclusive(b"a", b"c").overlaps_file(ucmp, &file));
+        assert!(!KeyRange::new(Bound::Excluded(b"e"), Bound::Unbounded).overlaps_file(ucmp, &file));
+        assert!(KeyRange::all().overlaps_file(ucmp, &file));
+
+        let a = KeyRange::half_open(b"b", b"f");
+        let b = KeyRange::new(Bound::Excluded(b"b"), Bound::Included(b"f"));
+        assert_eq!(a.intersect(ucmp, &b), KeyRange::new(Bound::Excluded(b"b"), Bound::Excluded(b"f")));
+        assert_eq!(a.intersect(ucmp, &KeyRange::all()), a);
+        assert!(a.intersect(ucmp, &KeyRange::inclusive(b"g", b"h")).is_empty(ucmp));
+
+        let mut r = KeyRange::inclusive(b"c", b"d");
+        assert!(!r.extend_to(ucmp, b"c", b"d"));
+        assert!(r.extend_to(ucmp, b"b", b"c"));
+        assert_eq!(r, KeyRange::inclusive(b"b", b"d"));
+    }
+}
@@ -0,0 +1,64 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Log format information shared by reader and writer.
+//!
+//! The log file contents are a sequence of 32KB blocks. Each record is split into one
+//! or more fragments that never cross a block boundary. Each fragment has a header:
+//!
+//! ```text
+//!    checksum: u32   // crc32c of type and data[] ; little-endian
+//!    length: u16     // little-endian
+//!    type: u8        // One of FULL, FIRST, MIDDLE, LAST
+//!    data: u8[length]
+//! ```
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    /// Zero is reserved for preallocated files
+    Zero = 0,
+    Full = 1,
+
+    // For fragments
+    First = 2,
+    Middle = 3,
+    Last = 4,
+}
+
+impl RecordType {
+    pub fn from_u8(v: u8) -> Option<RecordType> {
+        match v {
+            0 => Some(RecordType::Zero),
+            1 => Some(RecordType::Full),
+            2 => Some(RecordType::First),
+            3 => Some(RecordType::Middle),
+            4 => Some(RecordType::Last),
+            _ => None,
+        }
+    }
+}
+
+pub const MAX_RECORD_TYPE: u8 = RecordType::Last as u8;
+
+pub const BLOCK_SIZE: usize = 32768;
+
+/// Header is checksum (4 bytes), length (2 bytes), type (1 byte).
+pub const HEADER_SIZE: usize = 4 + 2 + 1;
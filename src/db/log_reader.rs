@@ -0,0 +1,464 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    db::log_format::{RecordType, BLOCK_SIZE, HEADER_SIZE},
+    leveldb::{
+        env::SequentialFile,
+        result::{Error, ErrorType},
+    },
+    util::{coding::decode_fixed_32, crc32c},
+};
+
+/// Interface for reporting errors.
+pub trait Reporter: Send {
+    /// Some corruption was detected. `bytes` is the approximate number of bytes dropped
+    /// due to the corruption.
+    fn corruption(&mut self, bytes: usize, reason: &Error);
+}
+
+enum PhysicalRecord {
+    Record(RecordType, usize, usize),
+    Eof,
+    // Returned whenever we find an invalid physical record. Currently there are three
+    // situations in which this happens:
+    // * The record has an invalid CRC (read_physical_record reports a drop)
+    // * The record is a 0-length record (No drop is reported)
+    // * The record is below constructor's initial_offset (No drop is reported)
+    BadRecord,
+}
+
+pub struct Reader {
+    file: Box<dyn SequentialFile>,
+    reporter: Option<Box<dyn Reporter>>,
+    checksum: bool,
+    backing_store: Vec<u8>,
+    // Unconsumed part of backing_store: [buffer_start, buffer_end)
+    buffer_start: usize,
+    buffer_end: usize,
+    // Last read() indicated EOF by returning < BLOCK_SIZE
+    eof: bool,
+
+    // Offset of the last record returned by read_record.
+    last_record_offset: u64,
+    // Offset of the first location past the end of buffer.
+    end_of_buffer_offset: u64,
+}
+
+impl Reader {
+    /// Create a reader that will return log records from `file`.
+    ///
+    /// If `reporter` is non-null, it is notified whenever some data is dropped due to a
+    /// detected corruption.
+    ///
+    /// If `checksum` is true, verify checksums if available.
+    pub fn new(file: Box<dyn SequentialFile>, reporter: Option<Box<dyn Reporter>>, checksum: bool) -> Self {
+        Self {
+            file,
+            reporter,
+            checksum,
+            backing_store: vec![0; BLOCK_SIZE],
+            buffer_start: 0,
+            buffer_end: 0,
+            eof: false,
+            last_record_offset: 0,
+            end_of_buffer_offset: 0,
+        }
+    }
+
+    /// Read the next record into `record`. Returns true if read successfully, false if
+    /// we hit end of the input.
+    pub fn read_record(&mut self, record: &mut Vec<u8>) -> bool {
+        record.clear();
+        let mut in_fragmented_record = false;
+        // Record offset of the logical record that we're reading
+        let mut prospective_record_offset = 0;
+
+        loop {
+            let record_type = self.read_physical_record();
+
+            // read_physical_record may have only had an empty trailer remaining in its
+            // internal buffer. Calculate the offset of the next physical record now that
+            // it has returned, properly accounting for its header size.
+            let buffer_size = (self.buffer_end - self.buffer_start) as u64;
+            match record_type {
+                PhysicalRecord::Record(t, start, end) => {
+                    let physical_record_offset =
+                        self.end_of_buffer_offset - buffer_size - HEADER_SIZE as u64 - (end - start) as u64;
+                    match t {
+                        RecordType::Full => {
+                            if in_fragmented_record && !record.is_empty() {
+                                self.report_corruption(record.len(), "partial record without end(1)");
+                            }
+                            record.clear();
+                            record.extend_from_slice(&self.backing_store[start..end]);
+                            self.last_record_offset = physical_record_offset;
+                            return true;
+                        }
+                        RecordType::First => {
+                            if in_fragmented_record && !record.is_empty() {
+                                self.report_corruption(record.len(), "partial record without end(2)");
+                            }
+                            prospective_record_offset = physical_record_offset;
+                            record.clear();
+                            record.extend_from_slice(&self.backing_store[start..end]);
+                            in_fragmented_record = true;
+                        }
+                        RecordType::Middle => {
+                            if !in_fragmented_record {
+                                let n = end - start;
+                                self.report_corruption(n, "missing start of fragmented record(1)");
+                            } else {
+                                record.extend_from_slice(&self.backing_store[start..end]);
+                            }
+                        }
+                        RecordType::Last => {
+                            if !in_fragmented_record {
+                                let n = end - start;
+                                self.report_corruption(n, "missing start of fragmented record(2)");
+                            } else {
+                                record.extend_from_slice(&self.backing_store[start..end]);
+                                self.last_record_offset = prospective_record_offset;
+                                return true;
+                            }
+                        }
+                        RecordType::Zero => {
+                            let n = end - start + if in_fragmented_record { record.len() } else { 0 };
+                            self.report_corruption(n, "unknown record type");
+                            in_fragmented_record = false;
+                            record.clear();
+                        }
+                    }
+                }
+                PhysicalRecord::Eof => {
+                    // This can be caused by the writer dying immediately after writing a
+                    // physical record but before completing the next; don't treat it as
+                    // a corruption, just ignore the entire logical record.
+                    record.clear();
+                    return false;
+                }
+                PhysicalRecord::BadRecord => {
+                    if in_fragmented_record {
+                        self.report_corruption(record.len(), "error in middle of record");
+                        in_fragmented_record = false;
+                        record.clear();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the physical offset of the last record returned by `read_record`.
+    ///
+    /// Undefined before the first call to `read_record`.
+    pub fn last_record_offset(&self) -> u64 { self.last_record_offset }
+
+    fn report_corruption(&mut self, bytes: usize, reason: &'static str) {
+        self.report_drop(bytes, &Error::new(ErrorType::Corruption, reason));
+    }
+
+    fn report_drop(&mut self, bytes: usize, reason: &Error) {
+        if let Some(reporter) = self.reporter.as_mut() {
+            reporter.corruption(bytes, reason);
+        }
+    }
+
+    /// Return the type of the next physical record along with the location of its
+    /// payload in `backing_store`.
+    fn read_physical_record(&mut self) -> PhysicalRecord {
+        loop {
+            if self.buffer_end - self.buffer_start < HEADER_SIZE {
+                if !self.eof {
+                    // Last read was a full read, so this is a trailer to skip
+                    self.buffer_start = 0;
+                    self.buffer_end = 0;
+                    match self.read_block() {
+                        Ok(n) => {
+                            self.buffer_end = n;
+                            self.end_of_buffer_offset += n as u64;
+                            if n < BLOCK_SIZE {
+                                self.eof = true;
+                            }
+                        }
+                        Err(e) => {
+                            self.report_drop(BLOCK_SIZE, &e);
+                            self.eof = true;
+                            return PhysicalRecord::Eof;
+                        }
+                    }
+                    continue;
+                } else {
+                    // Note that if buffer is non-empty, we have a truncated header at the
+                    // end of the file, which can be caused by the writer crashing in the
+                    // middle of writing the header. Instead of considering this an error,
+                    // just report EOF.
+                    self.buffer_start = self.buffer_end;
+                    return PhysicalRecord::Eof;
+                }
+            }
+
+            // Parse the header
+            let header = &self.backing_store[self.buffer_start..self.buffer_start + HEADER_SIZE];
+            let a = header[4] as usize;
+            let b = header[5] as usize;
+            let t = header[6];
+            let length = a | (b << 8);
+            if HEADER_SIZE + length > self.buffer_end - self.buffer_start {
+                let drop_size = self.buffer_end - self.buffer_start;
+                self.buffer_start = self.buffer_end;
+                if !self.eof {
+                    self.report_corruption(drop_size, "bad record length");
+                    return PhysicalRecord::BadRecord;
+                }
+                // If the end of the file has been reached without reading |length| bytes
+                // of payload, assume the writer died in the middle of writing the record.
+                // Don't report a corruption.
+                return PhysicalRecord::Eof;
+            }
+
+            if t == RecordType::Zero as u8 && length == 0 {
+                // Skip zero length record without reporting any drops since such records
+                // are produced by the mmap based writing code that preallocates file
+                // regions.
+                self.buffer_start = self.buffer_end;
+                return PhysicalRecord::BadRecord;
+            }
+
+            // Check crc
+            if self.checksum {
+                let expected_crc = crc32c::unmask(decode_fixed_32(header));
+                let start = self.buffer_start + HEADER_SIZE - 1;
+                let actual_crc = crc32c::value(&self.backing_store[start..start + 1 + length]);
+                if actual_crc != expected_crc {
+                    // Drop the rest of the buffer since "length" itself may have been
+                    // corrupted and if we trust it, we could find some fragment of a real
+                    // log record that just happens to look like a valid log record.
+                    let drop_size = self.buffer_end - self.buffer_start;
+                    self.buffer_start = self.buffer_end;
+                    self.report_corruption(drop_size, "checksum mismatch");
+                    return PhysicalRecord::BadRecord;
+                }
+            }
+
+            let start = self.buffer_start + HEADER_SIZE;
+            self.buffer_start += HEADER_SIZE + length;
+            return match RecordType::from_u8(t) {
+                Some(record_type) => PhysicalRecord::Record(record_type, start, start + length),
+                None => {
+                    self.report_corruption(length, "unknown record type");
+                    PhysicalRecord::BadRecord
+                }
+            };
+        }
+    }
+
+    /// Fill `backing_store` with up to one block of data, returning the number of bytes
+    /// read. Short reads only happen at the end of the file.
+    fn read_block(&mut self) -> crate::leveldb::result::Result<usize> {
+        let mut n = 0;
+        while n < BLOCK_SIZE {
+            let read = self.file.read(&mut self.backing_store[n..])?;
+            if read == 0 {
+                break;
+            }
+            n += read;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::log_writer::Writer,
+        leveldb::{
+            env::{SequentialFile, WritableFile},
+            result::Result,
+        },
+        util::random::Random,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct StringDest {
+        contents: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl WritableFile for StringDest {
+        fn append(&mut self, data: &[u8]) -> Result<()> {
+            self.contents.lock().unwrap().extend_from_slice(data);
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result<()> { Ok(()) }
+
+        fn flush(&mut self) -> Result<()> { Ok(()) }
+
+        fn sync(&mut self) -> Result<()> { Ok(()) }
+    }
+
+    struct StringSource {
+        contents: Vec<u8>,
+        pos: usize,
+    }
+
+    impl SequentialFile for StringSource {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let n = buf.len().min(self.contents.len() - self.pos);
+            buf[..n].copy_from_slice(&self.contents[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+
+        fn skip(&mut self, n: u64) -> Result<()> {
+            self.pos = (self.pos + n as usize).min(self.contents.len());
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct ReportCollector {
+        dropped_bytes: Arc<Mutex<usize>>,
+    }
+
+    impl Reporter for ReportCollector {
+        fn corruption(&mut self, bytes: usize, _reason: &Error) { *self.dropped_bytes.lock().unwrap() += bytes; }
+    }
+
+    struct LogTest {
+        dest: StringDest,
+        writer: Writer,
+        reporter: ReportCollector,
+    }
+
+    impl LogTest {
+        fn new() -> Self {
+            let dest = StringDest::default();
+            Self {
+                writer: Writer::new(Box::new(dest.clone())),
+                dest,
+                reporter: ReportCollector::default(),
+            }
+        }
+
+        fn write(&mut self, msg: &[u8]) { self.writer.add_record(msg).expect("add_record") }
+
+        fn contents(&self) -> Vec<u8> { self.dest.contents.lock().unwrap().clone() }
+
+        fn reader(&self) -> Reader {
+            let source = StringSource {
+                contents: self.contents(),
+                pos: 0,
+            };
+            Reader::new(Box::new(source), Some(Box::new(self.reporter.clone())), true)
+        }
+
+        fn read_all(&self) -> Vec<Vec<u8>> {
+            let mut reader = self.reader();
+            let mut records = Vec::new();
+            let mut record = Vec::new();
+            while reader.read_record(&mut record) {
+                records.push(record.clone());
+            }
+            records
+        }
+
+        fn dropped_bytes(&self) -> usize { *self.reporter.dropped_bytes.lock().unwrap() }
+    }
+
+    fn big_string(partial: &str, n: usize) -> Vec<u8> { partial.as_bytes().iter().cycle().take(n).cloned().collect() }
+
+    #[test]
+    fn empty() {
+        let t = LogTest::new();
+        assert!(t.read_all().is_empty());
+    }
+
+    #[test]
+    fn read_write() {
+        let mut t = LogTest::new();
+        t.write(b"foo");
+        t.write(b"bar");
+        t.write(b"");
+        t.write(b"xxxx");
+        assert_eq!(t.read_all(), vec![b"foo".to_vec(), b"bar".to_vec(), b"".to_vec(), b"xxxx".to_vec()]);
+    }
+
+    #[test]
+    fn fragmentation() {
+        let mut t = LogTest::new();
+        let medium = big_string("medium", 50000);
+        let large = big_string("large", 100000);
+        t.write(b"small");
+        t.write(&medium);
+        t.write(&large);
+        assert_eq!(t.read_all(), vec![b"small".to_vec(), medium, large]);
+    }
+
+    #[test]
+    fn marginal_trailer() {
+        // Make a trailer that is exactly the same length as an empty record.
+        let mut t = LogTest::new();
+        let n = BLOCK_SIZE - 2 * HEADER_SIZE;
+        t.write(&big_string("foo", n));
+        assert_eq!(t.contents().len(), BLOCK_SIZE - HEADER_SIZE);
+        t.write(b"");
+        t.write(b"bar");
+        assert_eq!(t.read_all(), vec![big_string("foo", n), b"".to_vec(), b"bar".to_vec()]);
+    }
+
+    #[test]
+    fn random_read() {
+        const N: u32 = 500;
+        let mut t = LogTest::new();
+        let write_rnd = Random::new(301);
+        let mut expected = Vec::new();
+        for i in 0..N {
+            let record = big_string(&i.to_string(), write_rnd.skewed(17) as usize);
+            t.write(&record);
+            expected.push(record);
+        }
+        assert_eq!(t.read_all(), expected);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut t = LogTest::new();
+        t.write(b"foo");
+        let mut contents = t.contents();
+        contents[HEADER_SIZE] ^= 0x01;
+        t.dest.contents.lock().unwrap().clone_from(&contents);
+        assert!(t.read_all().is_empty());
+        assert_eq!(t.dropped_bytes(), 10);
+    }
+
+    #[test]
+    fn truncated_trailing_record_is_ignored() {
+        let mut t = LogTest::new();
+        t.write(b"foo");
+        let len = t.contents().len();
+        t.dest.contents.lock().unwrap().truncate(len - 1);
+        assert!(t.read_all().is_empty());
+        // Truncated last record is ignored, not treated as an error.
+        assert_eq!(t.dropped_bytes(), 0);
+    }
+}
@@ -0,0 +1,138 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    db::log_format::{RecordType, BLOCK_SIZE, HEADER_SIZE, MAX_RECORD_TYPE},
+    leveldb::{env::WritableFile, result::Result},
+    util::{coding::encode_fixed_32, crc32c},
+};
+
+pub struct Writer {
+    dest: Box<dyn WritableFile>,
+    // Current offset in block
+    block_offset: usize,
+    // If true, records stay buffered until flush() is called.
+    manual_flush: bool,
+
+    // crc32c values for all supported record types. These are pre-computed to reduce
+    // the overhead of computing the crc of the record type stored in the header.
+    type_crc: [u32; MAX_RECORD_TYPE as usize + 1],
+}
+
+impl Writer {
+    /// Create a writer that will append data to `dest`.
+    /// `dest` must be initially empty.
+    pub fn new(dest: Box<dyn WritableFile>) -> Self { Self::new_with_offset(dest, 0) }
+
+    /// Create a writer that will append data to `dest`.
+    /// `dest` must have initial length `dest_length`.
+    pub fn new_with_offset(dest: Box<dyn WritableFile>, dest_length: u64) -> Self {
+        let mut type_crc = [0u32; MAX_RECORD_TYPE as usize + 1];
+        for (i, crc) in type_crc.iter_mut().enumerate() {
+            *crc = crc32c::value(&[i as u8]);
+        }
+        Self {
+            dest,
+            block_offset: (dest_length % BLOCK_SIZE as u64) as usize,
+            manual_flush: false,
+            type_crc,
+        }
+    }
+
+    /// If `manual_flush` is true, added records are no longer flushed to the operating
+    /// system one by one, but only by `flush()`, `sync()` and `close()`.
+    pub fn set_manual_flush(&mut self, manual_flush: bool) { self.manual_flush = manual_flush; }
+
+    pub fn add_record(&mut self, slice: &[u8]) -> Result<()> {
+        let mut left = slice.len();
+        let mut ptr = 0;
+
+        // Fragment the record if necessary and emit it. Note that if slice is empty, we
+        // still want to iterate once to emit a single zero-length record
+        let mut begin = true;
+        loop {
+            let leftover = BLOCK_SIZE - self.block_offset;
+            if leftover < HEADER_SIZE {
+                // Switch to a new block
+                if leftover > 0 {
+                    // Fill the trailer (literal below relies on HEADER_SIZE being 7)
+                    self.dest.append(&[0u8; HEADER_SIZE - 1][..leftover])?;
+                }
+                self.block_offset = 0;
+            }
+
+            // Invariant: we never leave < HEADER_SIZE bytes in a block.
+            assert!(BLOCK_SIZE - self.block_offset >= HEADER_SIZE);
+
+            let avail = BLOCK_SIZE - self.block_offset - HEADER_SIZE;
+            let fragment_length = left.min(avail);
+
+            let end = left == fragment_length;
+            let record_type = match (begin, end) {
+                (true, true) => RecordType::Full,
+                (true, false) => RecordType::First,
+                (false, true) => RecordType::Last,
+                (false, false) => RecordType::Middle,
+            };
+
+            self.emit_physical_record(record_type, &slice[ptr..ptr + fragment_length])?;
+            ptr += fragment_length;
+            left -= fragment_length;
+            begin = false;
+            if left == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush buffered records to the operating system.
+    pub fn flush(&mut self) -> Result<()> { self.dest.flush() }
+
+    /// Flush and persist the log contents to stable storage.
+    pub fn sync(&mut self) -> Result<()> { self.dest.sync() }
+
+    /// Close the underlying file.
+    pub fn close(&mut self) -> Result<()> { self.dest.close() }
+
+    fn emit_physical_record(&mut self, t: RecordType, data: &[u8]) -> Result<()> {
+        let length = data.len();
+        assert!(length <= 0xffff); // Must fit in two bytes
+        assert!(self.block_offset + HEADER_SIZE + length <= BLOCK_SIZE);
+
+        // Format the header
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[4] = (length & 0xff) as u8;
+        buf[5] = (length >> 8) as u8;
+        buf[6] = t as u8;
+
+        // Compute the crc of the record type and the payload.
+        let crc = crc32c::extend(self.type_crc[t as usize], data);
+        encode_fixed_32(&mut buf, crc32c::mask(crc)); // Adjust for storage
+
+        // Write the header and the payload
+        self.dest.append(&buf)?;
+        self.dest.append(data)?;
+        let result = if self.manual_flush { Ok(()) } else { self.dest.flush() };
+        self.block_offset += HEADER_SIZE + length;
+        result
+    }
+}
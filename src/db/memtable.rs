@@ -0,0 +1,462 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::{
+    db::{
+        dbformat::{InternalKeyComparator, LookupKey, SequenceNumber, ValueType},
+        skiplist::{self, KeyComparator, SkipList},
+        vector_rep::{self, VectorRep},
+    },
+    leveldb::{
+        comparator::Comparator,
+        iterator::Iterator,
+        pinnable_slice::PinnableSlice,
+        result::{Error, ErrorType, Result},
+    },
+    util::{
+        arena::Arena,
+        coding::{decode_fixed_64, decode_varint_32, encode_varint_32, encode_varint_32_vec, varint_length},
+    },
+};
+
+/// Decode the varint32 length-prefixed slice at the start of `data`.
+fn get_length_prefixed_slice(data: &[u8]) -> &[u8] {
+    let (len, n) = decode_varint_32(data).expect("malformed memtable entry");
+    &data[n..n + len as usize]
+}
+
+pub struct MemTableKeyComparator {
+    comparator: InternalKeyComparator,
+}
+
+impl KeyComparator for MemTableKeyComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        // Internal keys are encoded as length-prefixed strings.
+        let a = get_length_prefixed_slice(a);
+        let b = get_length_prefixed_slice(b);
+        self.comparator.compare(a, b)
+    }
+}
+
+type Table = SkipList<MemTableKeyComparator>;
+type Vector = VectorRep<MemTableKeyComparator>;
+
+/// How the entries of a memtable are stored.
+#[derive(Clone)]
+enum Rep {
+    // Kept sorted by internal key in a skiplist whose nodes and entry data live in an
+    // arena owned by the table.
+    SkipList(Arc<Table>),
+    // Appended to a vector that is sorted when read.
+    Vector(Arc<Vector>),
+}
+
+/// The in-memory write buffer. Entries are kept in a skiplist, or in a vector for
+/// memtables created with `new_vector()`.
+pub struct MemTable {
+    rep: Rep,
+    // Set by tests to make inserts into the memtable fail.
+    #[cfg(test)]
+    fail_inserts: std::sync::atomic::AtomicBool,
+}
+
+impl MemTable {
+    pub fn new(comparator: InternalKeyComparator) -> Self { Self::with_arena(comparator, Arena::new()) }
+
+    /// Create a memtable that allocates its entries in `arena`, e.g. one that was
+    /// `reset()` after a previous memtable was flushed.
+    pub fn with_arena(comparator: InternalKeyComparator, arena: Arena) -> Self {
+        Self::from_rep(Rep::SkipList(Arc::new(SkipList::with_arena(MemTableKeyComparator { comparator }, arena))))
+    }
+
+    /// Create a memtable that several threads can add entries to at the same time.
+    pub fn new_concurrent(comparator: InternalKeyComparator) -> Self {
+        Self::from_rep(Rep::SkipList(Arc::new(SkipList::new_concurrent(MemTableKeyComparator { comparator }))))
+    }
+
+    /// Create a memtable that appends entries to a vector and sorts them only when
+    /// they are read: inserts are cheap, but reads after inserts are not. Several
+    /// threads can add entries at the same time.
+    pub fn new_vector(comparator: InternalKeyComparator) -> Self {
+        Self::from_rep(Rep::Vector(Arc::new(VectorRep::new(MemTableKeyComparator { comparator }))))
+    }
+
+    fn from_rep(rep: Rep) -> Self {
+        Self {
+            rep,
+            #[cfg(test)]
+            fail_inserts: Default::default(),
+        }
+    }
+
+    /// Make `WriteBatch::insert_into()` fail for this memtable from now on.
+    #[cfg(test)]
+    pub(crate) fn fail_inserts(&self) { self.fail_inserts.store(true, std::sync::atomic::Ordering::Relaxed) }
+
+    #[cfg(test)]
+    pub(crate) fn inserts_fail(&self) -> bool { self.fail_inserts.load(std::sync::atomic::Ordering::Relaxed) }
+
+    /// Returns true iff the memtable was created with `new_vector()`.
+    pub fn is_vector(&self) -> bool { matches!(self.rep, Rep::Vector(_)) }
+
+    /// Destroy the memtable and return its arena for reuse, unless an iterator still
+    /// refers to its contents or the memtable was created with `new_concurrent()` or
+    /// `new_vector()`.
+    pub fn into_arena(self) -> Option<Arena> {
+        match self.rep {
+            Rep::SkipList(table) => Arc::try_unwrap(table).ok().and_then(SkipList::into_arena),
+            Rep::Vector(_) => None,
+        }
+    }
+
+    /// Returns an estimate of the number of bytes of data in use by this data
+    /// structure. It is safe to call when MemTable is being modified.
+    pub fn approximate_memory_usage(&self) -> usize {
+        match &self.rep {
+            Rep::SkipList(table) => table.memory_usage(),
+            Rep::Vector(vector) => vector.memory_usage(),
+        }
+    }
+
+    /// Returns true iff no entry has been added to the memtable.
+    pub fn is_empty(&self) -> bool {
+        match &self.rep {
+            Rep::SkipList(table) => {
+                let mut iter = skiplist::Iter::new(table.clone());
+                iter.seek_to_first();
+                !iter.valid()
+            }
+            Rep::Vector(vector) => vector.is_empty(),
+        }
+    }
+
+    /// Return an iterator that yields the contents of the memtable.
+    ///
+    /// The keys returned by this iterator are internal keys encoded by
+    /// `append_internal_key` in the db/dbformat module. The iterator keeps the memtable
+    /// data alive for as long as it exists.
+    pub fn new_iterator(&self) -> Box<dyn Iterator> {
+        Box::new(MemTableIterator {
+            iter: self.rep_iter(),
+            tmp: Vec::new(),
+        })
+    }
+
+    /// Add an entry into memtable that maps key to value at the specified sequence
+    /// number and with the specified type. Typically value will be empty if
+    /// `t == ValueType::Deletion`.
+    pub fn add(&self, s: SequenceNumber, t: ValueType, key: &[u8], value: &[u8]) {
+        // Format of an entry is concatenation of:
+        //  key_size     : varint32 of internal_key.size()
+        //  key bytes    : u8[internal_key.size()]
+        //  tag          : u64((sequence << 8) | type)
+        //  value_size   : varint32 of value.size()
+        //  value bytes  : u8[value.size()]
+        let key_size = key.len();
+        let val_size = value.len();
+        let internal_key_size = key_size + 8;
+        let encoded_len = varint_length(internal_key_size as u64)
+            + internal_key_size
+            + varint_length(val_size as u64)
+            + val_size;
+        let encode = |buf: &mut [u8]| {
+            let mut p = encode_varint_32(buf, internal_key_size as u32);
+            buf[p..p + key_size].copy_from_slice(key);
+            p += key_size;
+            let tag = crate::db::dbformat::pack_sequence_and_type(s, t);
+            buf[p..p + 8].copy_from_slice(&tag.to_le_bytes());
+            p += 8;
+            p += encode_varint_32(&mut buf[p..], val_size as u32);
+            buf[p..p + val_size].copy_from_slice(value);
+            assert_eq!(p + val_size, encoded_len);
+        };
+        match &self.rep {
+            Rep::SkipList(table) => table.insert_with(encoded_len, encode),
+            Rep::Vector(vector) => vector.insert_with(encoded_len, encode),
+        }
+    }
+
+    /// If memtable contains a value for key, returns it.
+    /// If memtable contains a deletion for key, returns a `NotFound` error.
+    /// If the newest entry for key is a merge operand, returns a `MergeInProgress`
+    /// error: the caller has to combine it with the older entries.
+    /// Else, returns `None`.
+    pub fn get(&self, key: &LookupKey) -> Option<Result<Vec<u8>>> { self.lookup(key, <[u8]>::to_vec) }
+
+    /// Like `get()`, but the value refers to the memtable entry, which it keeps alive.
+    pub fn get_pinned(&self, key: &LookupKey) -> Option<Result<PinnableSlice>> {
+        // The entry lives in the arena of the table, or in an allocation of its own that
+        // the vector holds on to, for as long as the representation does.
+        self.lookup(key, |v| unsafe { PinnableSlice::pinned(v, Box::new(self.rep.clone())) })
+    }
+
+    fn lookup<T>(&self, key: &LookupKey, value: impl FnOnce(&[u8]) -> T) -> Option<Result<T>> {
+        let memkey = key.memtable_key();
+        let mut iter = self.rep_iter();
+        iter.seek(memkey);
+        if iter.valid() {
+            // entry format is:
+            //    klength  varint32
+            //    userkey  u8[klength - 8]
+            //    tag      u64
+            //    vlength  varint32
+            //    value    u8[vlength]
+            // Check that it belongs to same user key. We do not check the sequence
+            // number since the seek() call above should have skipped all entries with
+            // overly large sequence numbers.
+            let entry = iter.key();
+            let (key_length, n) = decode_varint_32(entry).expect("malformed memtable entry");
+            let key_length = key_length as usize;
+            let ikey = &entry[n..n + key_length];
+            if self
+                .table_comparator()
+                .user_comparator()
+                .compare(&ikey[..key_length - 8], key.user_key())
+                == Ordering::Equal
+            {
+                // Correct user key
+                let tag = decode_fixed_64(&ikey[key_length - 8..]);
+                match ValueType::from_u8((tag & 0xff) as u8) {
+                    Some(ValueType::Value) => {
+                        let v = get_length_prefixed_slice(&entry[n + key_length..]);
+                        return Some(Ok(value(v)));
+                    }
+                    Some(ValueType::Deletion) => {
+                        return Some(Err(Error::new(ErrorType::NotFound, "")));
+                    }
+                    Some(ValueType::Merge) => {
+                        return Some(Err(Error::new(ErrorType::MergeInProgress, "")));
+                    }
+                    None => {}
+                }
+            }
+        }
+        None
+    }
+
+    fn table_comparator(&self) -> &InternalKeyComparator {
+        match &self.rep {
+            Rep::SkipList(table) => &table.comparator().comparator,
+            Rep::Vector(vector) => &vector.comparator().comparator,
+        }
+    }
+
+    fn rep_iter(&self) -> RepIter {
+        match &self.rep {
+            Rep::SkipList(table) => RepIter::SkipList(skiplist::Iter::new(table.clone())),
+            Rep::Vector(vector) => RepIter::Vector(vector_rep::Iter::new(vector.clone())),
+        }
+    }
+}
+
+/// An iterator over the entries of either representation.
+enum RepIter {
+    SkipList(skiplist::Iter<MemTableKeyComparator>),
+    Vector(vector_rep::Iter<MemTableKeyComparator>),
+}
+
+macro_rules! dispatch {
+    ($iter:expr, $i:ident => $e:expr) => {
+        match $iter {
+            RepIter::SkipList($i) => $e,
+            RepIter::Vector($i) => $e,
+        }
+    };
+}
+
+impl RepIter {
+    fn valid(&self) -> bool { dispatch!(self, i => i.valid()) }
+
+    fn key(&self) -> &[u8] { dispatch!(self, i => i.key()) }
+
+    fn next(&mut self) { dispatch!(self, i => i.next()) }
+
+    fn prev(&mut self) { dispatch!(self, i => i.prev()) }
+
+    fn seek(&mut self, target: &[u8]) { dispatch!(self, i => i.seek(target)) }
+
+    fn seek_to_first(&mut self) { dispatch!(self, i => i.seek_to_first()) }
+
+    fn seek_to_last(&mut self) { dispatch!(self, i => i.seek_to_last()) }
+}
+
+struct MemTableIterator {
+    iter: RepIter,
+    // For passing to encode_key
+    tmp: Vec<u8>,
+}
+
+impl Iterator for MemTableIterator {
+    fn valid(&self) -> bool { self.iter.valid() }
+
+    fn seek_to_first(&mut self) { self.iter.seek_to_first() }
+
+    fn seek_to_last(&mut self) { self.iter.seek_to_last() }
+
+    fn seek(&mut self, k: &[u8]) {
+        // Encode a suitable internal key target for `k` and seek to it.
+        self.tmp.clear();
+        encode_varint_32_vec(&mut self.tmp, k.len() as u32);
+        self.tmp.extend_from_slice(k);
+        self.iter.seek(&self.tmp)
+    }
+
+    fn next(&mut self) { self.iter.next() }
+
+    fn prev(&mut self) { self.iter.prev() }
+
+    fn key(&self) -> &[u8] { get_length_prefixed_slice(self.iter.key()) }
+
+    fn value(&self) -> &[u8] {
+        let entry = self.iter.key();
+        let key_slice = get_length_prefixed_slice(entry);
+        let offset = varint_length(key_slice.len() as u64) + key_slice.len();
+        get_length_prefixed_slice(&entry[offset..])
+    }
+
+    fn status(&self) -> Result<()> { Ok(()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::dbformat::parse_internal_key, util::comparator::bytewise_comparator};
+
+    fn new_memtable() -> MemTable { MemTable::new(InternalKeyComparator::new(bytewise_comparator())) }
+
+    #[test]
+    fn add_get() {
+        let mem = new_memtable();
+        mem.add(1, ValueType::Value, b"foo", b"v1");
+        mem.add(2, ValueType::Value, b"bar", b"v2");
+        mem.add(3, ValueType::Deletion, b"foo", b"");
+        mem.add(4, ValueType::Value, b"baz", b"");
+
+        assert_eq!(mem.get(&LookupKey::new(b"foo", 2)).unwrap().unwrap(), b"v1");
+        assert!(mem.get(&LookupKey::new(b"foo", 3)).unwrap().unwrap_err().is_not_found());
+        assert!(mem.get(&LookupKey::new(b"bar", 1)).is_none());
+        assert_eq!(mem.get(&LookupKey::new(b"bar", 5)).unwrap().unwrap(), b"v2");
+        assert_eq!(mem.get(&LookupKey::new(b"baz", 5)).unwrap().unwrap(), b"");
+        assert!(mem.get(&LookupKey::new(b"qux", 5)).is_none());
+        mem.add(5, ValueType::Merge, b"bar", b"m");
+        assert!(mem.get(&LookupKey::new(b"bar", 5)).unwrap().unwrap_err().is_merge_in_progress());
+        assert_eq!(mem.get(&LookupKey::new(b"bar", 4)).unwrap().unwrap(), b"v2");
+        assert!(mem.approximate_memory_usage() > 0);
+    }
+
+    #[test]
+    fn iterate() {
+        let mem = new_memtable();
+        mem.add(1, ValueType::Value, b"b", b"vb");
+        mem.add(2, ValueType::Value, b"a", b"va");
+        mem.add(3, ValueType::Value, b"b", b"vb2");
+
+        let mut iter = mem.new_iterator();
+        iter.seek_to_first();
+        let mut entries = Vec::new();
+        while iter.valid() {
+            let parsed = parse_internal_key(iter.key()).unwrap();
+            entries.push((parsed.user_key.to_vec(), parsed.sequence, iter.value().to_vec()));
+            iter.next();
+        }
+        assert_eq!(
+            entries,
+            vec![
+                (b"a".to_vec(), 2, b"va".to_vec()),
+                (b"b".to_vec(), 3, b"vb2".to_vec()),
+                (b"b".to_vec(), 1, b"vb".to_vec()),
+            ]
+        );
+
+        let target = LookupKey::new(b"b", 2);
+        iter.seek(target.internal_key());
+        assert!(iter.valid());
+        assert_eq!(iter.value(), b"vb");
+    }
+
+    #[test]
+    fn concurrent_add() {
+        let mem = MemTable::new_concurrent(InternalKeyComparator::new(bytewise_comparator()));
+        std::thread::scope(|s| {
+            for t in 0..4u64 {
+                let mem = &mem;
+                s.spawn(move || {
+                    for i in 0..100u64 {
+                        mem.add(t * 100 + i + 1, ValueType::Value, format!("{t}.{i:02}").as_bytes(), b"v");
+                    }
+                });
+            }
+        });
+        assert_eq!(mem.get(&LookupKey::new(b"3.99", 400)).unwrap().unwrap(), b"v");
+        let mut iter = mem.new_iterator();
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+        assert_eq!(count, 400);
+        drop(iter);
+        assert!(mem.into_arena().is_none());
+    }
+
+    #[test]
+    fn vector() {
+        let mem = MemTable::new_vector(InternalKeyComparator::new(bytewise_comparator()));
+        assert!(mem.is_vector());
+        assert!(mem.is_empty());
+        mem.add(1, ValueType::Value, b"b", b"vb");
+        mem.add(2, ValueType::Value, b"a", b"va");
+        assert!(!mem.is_empty());
+        let pinned = mem.get_pinned(&LookupKey::new(b"b", 5)).unwrap().unwrap();
+        let mut iter = mem.new_iterator();
+
+        // Reads see the entries added after the previous read, iterators do not.
+        mem.add(3, ValueType::Deletion, b"b", b"");
+        mem.add(4, ValueType::Value, b"c", b"vc");
+        assert!(mem.get(&LookupKey::new(b"b", 5)).unwrap().unwrap_err().is_not_found());
+        assert_eq!(mem.get(&LookupKey::new(b"b", 2)).unwrap().unwrap(), b"vb");
+        assert_eq!(mem.get(&LookupKey::new(b"c", 5)).unwrap().unwrap(), b"vc");
+        assert!(mem.get(&LookupKey::new(b"d", 5)).is_none());
+        assert_eq!(&*pinned, b"vb");
+        assert!(pinned.is_pinned());
+        iter.seek_to_last();
+        assert_eq!(parse_internal_key(iter.key()).unwrap().user_key, b"b");
+        iter.prev();
+        assert_eq!(iter.value(), b"va");
+
+        let mut iter = mem.new_iterator();
+        iter.seek(LookupKey::new(b"b", 5).internal_key());
+        let parsed = parse_internal_key(iter.key()).unwrap();
+        assert_eq!((parsed.user_key, parsed.sequence), (&b"b"[..], 3));
+        iter.next();
+        assert_eq!(iter.value(), b"vb");
+        iter.next();
+        assert_eq!(iter.value(), b"vc");
+        iter.next();
+        assert!(!iter.valid());
+        assert!(mem.approximate_memory_usage() > 0);
+        drop(iter);
+        assert!(mem.into_arena().is_none());
+    }
+}
@@ -0,0 +1,286 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{cmp::Ordering, collections::VecDeque, sync::Arc};
+
+use crate::{
+    db::dbformat::{append_internal_key, parse_internal_key, ParsedInternalKey, SequenceNumber, ValueType},
+    leveldb::{
+        comparator::Comparator,
+        iterator::Iterator,
+        merge_operator::MergeOperator,
+        result::{Error, ErrorType, Result},
+    },
+};
+
+/// Combine the merge operands for `user_key` that `iter` yields from its current
+/// position, newest first, with the value or deletion below them. On return `iter` is
+/// positioned past every entry that was consumed.
+///
+/// REQUIRES: `iter` is positioned at a merge operand for `user_key`
+pub(crate) fn merge_entries(
+    ucmp: &dyn Comparator,
+    operator: Option<&dyn MergeOperator>,
+    iter: &mut dyn Iterator,
+    user_key: &[u8],
+) -> Result<Vec<u8>> {
+    let Some(operator) = operator else {
+        return LEVELDB_ERR!(NotSupported, "merge operand found but no merge operator configured");
+    };
+    let mut operands = Vec::new();
+    let mut base = None;
+    while iter.valid() {
+        let ikey = match parse_internal_key(iter.key()) {
+            Some(ikey) if ucmp.compare(ikey.user_key, user_key) == Ordering::Equal => ikey,
+            Some(_) => break,
+            None => return LEVELDB_ERR!(Corruption, "corrupted internal key in merge"),
+        };
+        let value_type = ikey.value_type;
+        match value_type {
+            ValueType::Merge => operands.push(iter.value().to_vec()),
+            ValueType::Value => base = Some(iter.value().to_vec()),
+            ValueType::Deletion => {}
+        }
+        iter.next();
+        if value_type != ValueType::Merge {
+            break;
+        }
+    }
+    iter.status()?;
+    full_merge(operator, user_key, base.as_deref(), &operands)
+}
+
+/// Apply `operator` to `operands`, which are ordered newest first.
+fn full_merge(operator: &dyn MergeOperator, user_key: &[u8], base: Option<&[u8]>, operands: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let operands: Vec<&[u8]> = operands.iter().rev().map(|op| op.as_slice()).collect();
+    match operator.full_merge(user_key, base, &operands) {
+        Some(value) => Ok(value),
+        None => Err(Error::new(ErrorType::Corruption, "merge operator failed")
+            .with_detail(format!("operator {}", operator.name()))),
+    }
+}
+
+/// Wraps the input of a compaction and collapses each run of merge operands that no
+/// snapshot can see into fewer entries: into a plain value when the run ends in a
+/// value or deletion, else into one operand when `partial_merge` combines them all.
+/// Runs the operator cannot combine are passed through unchanged.
+struct CompactionMergeIterator {
+    iter: Box<dyn Iterator>,
+    ucmp: Arc<dyn Comparator>,
+    operator: Arc<dyn MergeOperator>,
+    smallest_snapshot: SequenceNumber,
+    // Entries produced from the last collapsed run, yielded before `iter` moves on.
+    pending: VecDeque<(Vec<u8>, Vec<u8>)>,
+}
+
+impl CompactionMergeIterator {
+    fn prepare(&mut self) {
+        if !self.pending.is_empty() || !self.iter.valid() {
+            return;
+        }
+        match parse_internal_key(self.iter.key()) {
+            Some(ikey) if ikey.value_type == ValueType::Merge && ikey.sequence <= self.smallest_snapshot => {
+                let user_key = ikey.user_key.to_vec();
+                let sequence = ikey.sequence;
+                self.collapse(user_key, sequence);
+            }
+            _ => {}
+        }
+    }
+
+    fn collapse(&mut self, user_key: Vec<u8>, sequence: SequenceNumber) {
+        // Every entry of the run is older than the first one, so no snapshot sees
+        // any of them separately.
+        let mut originals = Vec::new();
+        let mut operands = Vec::new();
+        let mut base = None;
+        while self.iter.valid() {
+            let value_type = match parse_internal_key(self.iter.key()) {
+                Some(ikey) if self.ucmp.compare(ikey.user_key, &user_key) == Ordering::Equal => ikey.value_type,
+                _ => break,
+            };
+            originals.push((self.iter.key().to_vec(), self.iter.value().to_vec()));
+            match value_type {
+                ValueType::Merge => operands.push(self.iter.value().to_vec()),
+                ValueType::Value => base = Some(Some(self.iter.value().to_vec())),
+                ValueType::Deletion => base = Some(None),
+            }
+            self.iter.next();
+            if base.is_some() {
+                break;
+            }
+        }
+
+        let merged = match base {
+            Some(base) => full_merge(self.operator.as_ref(), &user_key, base.as_deref(), &operands)
+                .ok()
+                .map(|value| (ValueType::Value, value)),
+            None if operands.len() > 1 => {
+                // Fold from the oldest operand towards the newest.
+                let mut acc = operands.last().cloned();
+                for op in operands.iter().rev().skip(1) {
+                    acc = acc.and_then(|left| self.operator.partial_merge(&user_key, &left, op));
+                }
+                acc.map(|value| (ValueType::Merge, value))
+            }
+            None => None,
+        };
+        match merged {
+            Some((value_type, value)) => {
+                let mut key = Vec::new();
+                append_internal_key(&mut key, &ParsedInternalKey::new(&user_key, sequence, value_type));
+                self.pending.push_back((key, value));
+            }
+            None => self.pending.extend(originals),
+        }
+    }
+}
+
+impl Iterator for CompactionMergeIterator {
+    fn valid(&self) -> bool { !self.pending.is_empty() || self.iter.valid() }
+
+    fn seek_to_first(&mut self) {
+        self.pending.clear();
+        self.iter.seek_to_first();
+        self.prepare();
+    }
+
+    fn seek_to_last(&mut self) { panic!("compaction merge iterators only move forward") }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.pending.clear();
+        self.iter.seek(target);
+        self.prepare();
+    }
+
+    fn next(&mut self) {
+        if self.pending.pop_front().is_none() {
+            self.iter.next();
+        }
+        self.prepare();
+    }
+
+    fn prev(&mut self) { panic!("compaction merge iterators only move forward") }
+
+    fn key(&self) -> &[u8] {
+        match self.pending.front() {
+            Some((key, _)) => key,
+            None => self.iter.key(),
+        }
+    }
+
+    fn value(&self) -> &[u8] {
+        match self.pending.front() {
+            Some((_, value)) => value,
+            None => self.iter.value(),
+        }
+    }
+
+    fn status(&self) -> Result<()> { self.iter.status() }
+}
+
+/// Return an iterator over the entries of `iter` that collapses runs of merge operands
+/// at or below `smallest_snapshot` with `operator`.
+pub(crate) fn new_compaction_merge_iterator(
+    iter: Box<dyn Iterator>,
+    ucmp: Arc<dyn Comparator>,
+    operator: Arc<dyn MergeOperator>,
+    smallest_snapshot: SequenceNumber,
+) -> Box<dyn Iterator> {
+    Box::new(CompactionMergeIterator {
+        iter,
+        ucmp,
+        operator,
+        smallest_snapshot,
+        pending: VecDeque::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{
+            dbformat::{InternalKeyComparator, LookupKey},
+            memtable::MemTable,
+        },
+        util::{comparator::bytewise_comparator, merge_operators::new_u64_add_operator},
+    };
+
+    fn collapsed(mem: &MemTable, smallest_snapshot: SequenceNumber) -> Vec<(Vec<u8>, SequenceNumber, ValueType, u64)> {
+        let mut iter = new_compaction_merge_iterator(
+            mem.new_iterator(),
+            bytewise_comparator(),
+            new_u64_add_operator(),
+            smallest_snapshot,
+        );
+        let mut result = Vec::new();
+        iter.seek_to_first();
+        while iter.valid() {
+            let ikey = parse_internal_key(iter.key()).unwrap();
+            let value = u64::from_le_bytes(iter.value().try_into().unwrap());
+            result.push((ikey.user_key.to_vec(), ikey.sequence, ikey.value_type, value));
+            iter.next();
+        }
+        result
+    }
+
+    #[test]
+    fn compaction_collapse() {
+        let mem = MemTable::new(InternalKeyComparator::new(bytewise_comparator()));
+        let n = |v: u64| v.to_le_bytes();
+        mem.add(1, ValueType::Value, b"a", &n(10));
+        mem.add(2, ValueType::Merge, b"a", &n(1));
+        mem.add(3, ValueType::Merge, b"a", &n(2));
+        mem.add(4, ValueType::Merge, b"b", &n(3));
+        mem.add(5, ValueType::Merge, b"b", &n(4));
+        mem.add(6, ValueType::Merge, b"c", &n(5));
+
+        use ValueType::{Merge, Value};
+        assert_eq!(
+            collapsed(&mem, 10),
+            vec![
+                (b"a".to_vec(), 3, Value, 13),
+                (b"b".to_vec(), 5, Merge, 7),
+                (b"c".to_vec(), 6, Merge, 5),
+            ]
+        );
+        // Operands newer than the oldest snapshot stay separate.
+        assert_eq!(
+            collapsed(&mem, 2),
+            vec![
+                (b"a".to_vec(), 3, Merge, 2),
+                (b"a".to_vec(), 2, Value, 11),
+                (b"b".to_vec(), 5, Merge, 4),
+                (b"b".to_vec(), 4, Merge, 3),
+                (b"c".to_vec(), 6, Merge, 5),
+            ]
+        );
+
+        let mut iter = mem.new_iterator();
+        iter.seek(LookupKey::new(b"b", 10).internal_key());
+        let operator = new_u64_add_operator();
+        let merged = merge_entries(bytewise_comparator().as_ref(), Some(operator.as_ref()), iter.as_mut(), b"b").unwrap();
+        assert_eq!(merged, n(7));
+        assert_eq!(parse_internal_key(iter.key()).unwrap().user_key, b"c");
+        assert!(merge_entries(bytewise_comparator().as_ref(), None, iter.as_mut(), b"c").is_err());
+    }
+}
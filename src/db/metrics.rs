@@ -0,0 +1,321 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use crate::{db::dbformat::config, util::histogram::Histogram};
+
+/// Counters, gauges and latency histograms that a DB keeps about its own operation.
+/// Updating the counters and gauges never takes a lock and the histograms only take a
+/// short one of their own, so they are maintained whether or not the DB is registered
+/// with a `MetricsRegistry`.
+#[derive(Default)]
+pub struct Statistics {
+    keys_written: AtomicU64,
+    bytes_written: AtomicU64,
+    keys_read: AtomicU64,
+    flushes: AtomicU64,
+    compactions: AtomicU64,
+    compaction_bytes_read: AtomicU64,
+    compaction_bytes_written: AtomicU64,
+    memtable_bytes: AtomicU64,
+    level_files: [AtomicU64; config::NUM_LEVELS],
+    level_bytes: [AtomicU64; config::NUM_LEVELS],
+    get_micros: Mutex<Histogram>,
+    write_micros: Mutex<Histogram>,
+}
+
+impl Statistics {
+    pub(crate) fn record_write(&self, keys: u64, bytes: u64, memtable_bytes: u64) {
+        self.keys_written.fetch_add(keys, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+        self.memtable_bytes.store(memtable_bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_reads(&self, keys: u64) { self.keys_read.fetch_add(keys, Ordering::Relaxed); }
+
+    pub(crate) fn record_get_micros(&self, micros: u64) { self.get_micros.lock().unwrap().add(micros as f64); }
+
+    pub(crate) fn record_write_micros(&self, micros: u64) { self.write_micros.lock().unwrap().add(micros as f64); }
+
+    pub(crate) fn record_flush(&self, memtable_bytes: u64) {
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+        self.memtable_bytes.store(memtable_bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_compaction(&self, bytes_read: u64, bytes_written: u64) {
+        self.compactions.fetch_add(1, Ordering::Relaxed);
+        self.compaction_bytes_read.fetch_add(bytes_read, Ordering::Relaxed);
+        self.compaction_bytes_written.fetch_add(bytes_written, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_level(&self, level: usize, files: u64, bytes: u64) {
+        self.level_files[level].store(files, Ordering::Relaxed);
+        self.level_bytes[level].store(bytes, Ordering::Relaxed);
+    }
+
+    /// Return the current values.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let load = |v: &AtomicU64| v.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            keys_written: load(&self.keys_written),
+            bytes_written: load(&self.bytes_written),
+            keys_read: load(&self.keys_read),
+            flushes: load(&self.flushes),
+            compactions: load(&self.compactions),
+            compaction_bytes_read: load(&self.compaction_bytes_read),
+            compaction_bytes_written: load(&self.compaction_bytes_written),
+            memtable_bytes: load(&self.memtable_bytes),
+            level_files: self.level_files.iter().map(load).collect(),
+            level_bytes: self.level_bytes.iter().map(load).collect(),
+            get_micros: self.get_micros.lock().unwrap().clone(),
+            write_micros: self.write_micros.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// The values of a DB's `Statistics` at one point in time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Keys put or deleted through `DB::write()`.
+    pub keys_written: u64,
+    /// Size of the write batches applied, in bytes.
+    pub bytes_written: u64,
+    /// Keys looked up through `DB::get()` and `DB::multi_get()`.
+    pub keys_read: u64,
+    /// Memtables written out to level-0 tables.
+    pub flushes: u64,
+    /// Compactions run, including trivial moves.
+    pub compactions: u64,
+    pub compaction_bytes_read: u64,
+    pub compaction_bytes_written: u64,
+    /// Approximate memory used by the current memtable.
+    pub memtable_bytes: u64,
+    /// Number of table files at each level.
+    pub level_files: Vec<u64>,
+    /// Total size of the table files at each level.
+    pub level_bytes: Vec<u64>,
+    /// Latency of `DB::get()` calls, in microseconds.
+    pub get_micros: Histogram,
+    /// Latency of `DB::write()` calls, in microseconds.
+    pub write_micros: Histogram,
+}
+
+impl MetricsSnapshot {
+    /// Name, type and value of each scalar metric, for exporters.
+    fn scalars(&self) -> [(&'static str, &'static str, u64); 8] {
+        [
+            ("hefesto_keys_written_total", "counter", self.keys_written),
+            ("hefesto_bytes_written_total", "counter", self.bytes_written),
+            ("hefesto_keys_read_total", "counter", self.keys_read),
+            ("hefesto_flushes_total", "counter", self.flushes),
+            ("hefesto_compactions_total", "counter", self.compactions),
+            ("hefesto_compaction_bytes_read_total", "counter", self.compaction_bytes_read),
+            ("hefesto_compaction_bytes_written_total", "counter", self.compaction_bytes_written),
+            ("hefesto_memtable_bytes", "gauge", self.memtable_bytes),
+        ]
+    }
+
+    /// Name and values of each per-level gauge, for exporters.
+    fn per_level(&self) -> [(&'static str, &[u64]); 2] {
+        [("hefesto_level_files", &self.level_files), ("hefesto_level_bytes", &self.level_bytes)]
+    }
+
+    /// Name and values of each latency summary, for exporters.
+    fn latencies(&self) -> [(&'static str, &Histogram); 2] {
+        [("hefesto_get_micros", &self.get_micros), ("hefesto_write_micros", &self.write_micros)]
+    }
+}
+
+/// The metrics of one registered DB.
+#[derive(Clone, Debug)]
+pub struct DbMetrics {
+    /// The name the DB was opened with.
+    pub name: String,
+    /// The labels from `Options::metrics_labels`.
+    pub labels: Vec<(String, String)>,
+    pub metrics: MetricsSnapshot,
+}
+
+struct Entry {
+    id: u64,
+    name: String,
+    labels: Vec<(String, String)>,
+    statistics: Arc<Statistics>,
+}
+
+/// A set of DBs whose metrics are reported together, e.g. by a process that opens
+/// many shard databases and exports their metrics from one endpoint. A DB opened with
+/// `Options::metrics_registry` set registers itself, and leaves the registry when it
+/// is dropped.
+#[derive(Default)]
+pub struct MetricsRegistry {
+    next_id: AtomicU64,
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> Self { Self::default() }
+
+    /// Return the process-wide registry. DBs only join it when opened with
+    /// `Options::metrics_registry` set to it.
+    pub fn global() -> Arc<MetricsRegistry> {
+        static GLOBAL: OnceLock<Arc<MetricsRegistry>> = OnceLock::new();
+        GLOBAL.get_or_init(|| Arc::new(MetricsRegistry::new())).clone()
+    }
+
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        name: &str,
+        labels: Vec<(String, String)>,
+        statistics: Arc<Statistics>,
+    ) -> Registration {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.entries.lock().unwrap().push(Entry {
+            id,
+            name: name.to_string(),
+            labels,
+            statistics,
+        });
+        Registration {
+            registry: self.clone(),
+            id,
+        }
+    }
+
+    /// Return the current metrics of every registered DB, in registration order.
+    pub fn collect(&self) -> Vec<DbMetrics> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| DbMetrics {
+                name: e.name.clone(),
+                labels: e.labels.clone(),
+                metrics: e.statistics.snapshot(),
+            })
+            .collect()
+    }
+
+    /// Return the metrics of every registered DB in the Prometheus text format. Each
+    /// sample is labeled with `db`, the DB's name, and the DB's own labels.
+    pub fn render_text(&self) -> String {
+        let dbs = self.collect();
+        let labels: Vec<String> = dbs.iter().map(|db| format_labels(&db.name, &db.labels)).collect();
+        let mut out = String::new();
+        let scalars = MetricsSnapshot::default().scalars();
+        for (i, (name, ty, _)) in scalars.iter().enumerate() {
+            let _ = writeln!(out, "# TYPE {} {}", name, ty);
+            for (db, labels) in dbs.iter().zip(labels.iter()) {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels, db.metrics.scalars()[i].2);
+            }
+        }
+        for (i, (name, _)) in MetricsSnapshot::default().per_level().iter().enumerate() {
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            for (db, labels) in dbs.iter().zip(labels.iter()) {
+                for (level, value) in db.metrics.per_level()[i].1.iter().enumerate() {
+                    let _ = writeln!(out, "{}{{{},level=\"{}\"}} {}", name, labels, level, value);
+                }
+            }
+        }
+        for (i, (name, _)) in MetricsSnapshot::default().latencies().iter().enumerate() {
+            let _ = writeln!(out, "# TYPE {} summary", name);
+            for (db, labels) in dbs.iter().zip(labels.iter()) {
+                let histogram = db.metrics.latencies()[i].1;
+                for quantile in [0.5, 0.95, 0.99] {
+                    let value = histogram.percentile(quantile * 100.0);
+                    let _ = writeln!(out, "{}{{{},quantile=\"{}\"}} {}", name, labels, quantile, value);
+                }
+                let sum = histogram.average() * histogram.count() as f64;
+                let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, sum);
+                let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count());
+            }
+        }
+        out
+    }
+}
+
+fn format_labels(name: &str, labels: &[(String, String)]) -> String {
+    let escape = |v: &str| v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    let mut out = format!("db=\"{}\"", escape(name));
+    for (key, value) in labels {
+        let _ = write!(out, ",{}=\"{}\"", key, escape(value));
+    }
+    out
+}
+
+/// A DB's membership in a `MetricsRegistry`, which ends when this is dropped.
+pub(crate) struct Registration {
+    registry: Arc<MetricsRegistry>,
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) { self.registry.entries.lock().unwrap().retain(|e| e.id != self.id); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry() {
+        let registry = Arc::new(MetricsRegistry::new());
+        let a = Arc::new(Statistics::default());
+        let b = Arc::new(Statistics::default());
+        let ra = registry.register("/db/a", vec![("shard".to_string(), "1".to_string())], a.clone());
+        let rb = registry.register("/db/\"b\"", Vec::new(), b.clone());
+        a.record_write(3, 100, 4096);
+        a.set_level(1, 2, 2000);
+        b.record_reads(5);
+        b.record_get_micros(10);
+        b.record_get_micros(30);
+
+        let dbs = registry.collect();
+        assert_eq!(dbs.len(), 2);
+        assert_eq!(dbs[0].name, "/db/a");
+        assert_eq!(dbs[0].metrics.keys_written, 3);
+        assert_eq!(dbs[0].metrics.memtable_bytes, 4096);
+        assert_eq!(dbs[0].metrics.level_files[1], 2);
+        assert_eq!(dbs[1].metrics.keys_read, 5);
+
+        let text = registry.render_text();
+        assert!(text.contains("# TYPE hefesto_keys_written_total counter\n"));
+        assert!(text.contains("hefesto_keys_written_total{db=\"/db/a\",shard=\"1\"} 3\n"));
+        assert!(text.contains("hefesto_keys_read_total{db=\"/db/\\\"b\\\"\"} 5\n"));
+        assert!(text.contains("hefesto_level_bytes{db=\"/db/a\",shard=\"1\",level=\"1\"} 2000\n"));
+        assert!(text.contains("# TYPE hefesto_get_micros summary\n"));
+        assert!(text.contains("hefesto_get_micros_sum{db=\"/db/\\\"b\\\"\"} 40\n"));
+        assert!(text.contains("hefesto_get_micros_count{db=\"/db/\\\"b\\\"\"} 2\n"));
+        assert!(text.contains("hefesto_write_micros_count{db=\"/db/a\",shard=\"1\"} 0\n"));
+
+        drop(ra);
+        assert_eq!(registry.collect().len(), 1);
+        drop(rb);
+        assert!(registry.render_text().lines().all(|l| l.starts_with('#')));
+    }
+}
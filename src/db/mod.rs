@@ -0,0 +1,52 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+pub mod builder;
+pub mod checkpoint;
+pub mod db_impl;
+pub mod db_iter;
+pub mod dbformat;
+pub mod external_file;
+pub mod filename;
+pub mod key_range;
+pub mod log_format;
+pub mod log_reader;
+pub mod log_writer;
+pub mod memtable;
+pub mod merge;
+pub mod metrics;
+#[cfg(test)]
+mod model_test;
+pub mod purge_queue;
+pub mod range_iter;
+pub mod repair;
+pub mod skiplist;
+pub mod snapshot;
+pub mod sst_file_writer;
+pub mod table_cache;
+pub mod tailing_iter;
+pub mod transaction_log;
+pub mod vector_rep;
+pub mod version_edit;
+pub mod version_set;
+pub mod write_batch;
+
+pub use self::{db_impl::DB, key_range::KeyRange};
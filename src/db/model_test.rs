@@ -0,0 +1,249 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Differential tests that run randomized operation sequences against both a DB and a
+// simple in-memory model, and check that every read agrees. Snapshots are modeled as
+// clones of the model taken when the snapshot was created.
+
+use std::{collections::BTreeMap, ops::Bound};
+
+use crate::{
+    db::{db_impl::DB, key_range::KeyRange, snapshot::Snapshot, write_batch::WriteBatch},
+    leveldb::options::{Options, ReadOptions, WriteOptions},
+    util::{random::Random, testutil::tmp_dir},
+};
+
+type Model = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Number of distinct keys used by the operations. Small enough that keys are often
+/// overwritten and deleted.
+const KEY_SPACE: u32 = 200;
+
+fn random_key(rnd: &Random) -> Vec<u8> {
+    // Skew towards a few hot keys so that keys accumulate many versions.
+    let k = if rnd.one_in(2) { rnd.skewed(4) } else { rnd.uniform(KEY_SPACE) };
+    format!("key{:04}", k).into_bytes()
+}
+
+fn random_value(rnd: &Random, step: usize) -> Vec<u8> {
+    let len = rnd.skewed(10) as usize;
+    let mut value = format!("v{}:", step).into_bytes();
+    value.extend((0..len).map(|_| b'a' + rnd.uniform(26) as u8));
+    value
+}
+
+fn read_options(snapshot: Option<&Snapshot>) -> ReadOptions {
+    ReadOptions {
+        snapshot: snapshot.cloned(),
+        ..ReadOptions::default()
+    }
+}
+
+struct Harness {
+    dbname: String,
+    options: Options,
+    db: Option<DB>,
+    model: Model,
+    snapshots: Vec<(Snapshot, Model)>,
+    context: String,
+}
+
+impl Harness {
+    fn new(name: &str, options: Options) -> Self {
+        let dbname = tmp_dir(name);
+        let options = Options {
+            create_if_missing: true,
+            ..options
+        };
+        let db = DB::open(options.clone(), &dbname).expect("open");
+        Self {
+            dbname,
+            options,
+            db: Some(db),
+            model: Model::new(),
+            snapshots: Vec::new(),
+            context: String::new(),
+        }
+    }
+
+    fn db(&self) -> &DB { self.db.as_ref().unwrap() }
+
+    fn reopen(&mut self) {
+        // Snapshots do not survive a reopen.
+        self.snapshots.clear();
+        self.db = None;
+        self.db = Some(DB::open(self.options.clone(), &self.dbname).expect("reopen"));
+    }
+
+    /// Check that point lookups agree with `model` for every key of the key space.
+    fn check_gets(&self, snapshot: Option<&Snapshot>, model: &Model) {
+        let options = read_options(snapshot);
+        for k in 0..KEY_SPACE {
+            let key = format!("key{:04}", k).into_bytes();
+            let got = self.db().get(&options, &key).expect("get");
+            assert_eq!(got.as_ref(), model.get(&key), "{}: get {:?}", self.context, String::from_utf8_lossy(&key));
+        }
+    }
+
+    /// Check that a scan starting at `start` (or at the first key) yields up to
+    /// `limit` entries equal to those of `model`.
+    fn check_scan(&self, snapshot: Option<&Snapshot>, model: &Model, start: Option<&[u8]>, limit: usize) {
+        let mut iter = self.db().new_iterator(&read_options(snapshot));
+        let lower = match start {
+            Some(key) => {
+                iter.seek(key);
+                Bound::Included(key.to_vec())
+            }
+            None => {
+                iter.seek_to_first();
+                Bound::Unbounded
+            }
+        };
+        let mut expected = model.range((lower, Bound::Unbounded));
+        for _ in 0..limit {
+            match expected.next() {
+                Some((k, v)) => {
+                    assert!(iter.valid(), "{}: iterator ended early before {:?}", self.context, k);
+                    assert_eq!(iter.key(), &k[..], "{}: scan key", self.context);
+                    assert_eq!(iter.value(), &v[..], "{}: scan value", self.context);
+                    iter.next();
+                }
+                None => {
+                    assert!(!iter.valid(), "{}: iterator yields extra key {:?}", self.context, iter.key());
+                    break;
+                }
+            }
+        }
+        assert!(iter.status().is_ok(), "{}: iterator status", self.context);
+    }
+
+    fn check_all(&self, rnd: &Random) {
+        self.check_gets(None, &self.model);
+        self.check_scan(None, &self.model, None, usize::MAX);
+        for (snapshot, model) in self.snapshots.iter() {
+            self.check_gets(Some(snapshot), model);
+            self.check_scan(Some(snapshot), model, Some(&random_key(rnd)), 20);
+        }
+    }
+
+    fn step(&mut self, rnd: &Random, step: usize) {
+        let wo = WriteOptions::default();
+        match rnd.uniform(100) {
+            0..=44 => {
+                let (key, value) = (random_key(rnd), random_value(rnd, step));
+                self.context = format!("step {}: put", step);
+                self.db().put(&wo, &key, &value).expect("put");
+                self.model.insert(key, value);
+            }
+            45..=59 => {
+                let key = random_key(rnd);
+                self.context = format!("step {}: delete", step);
+                self.db().delete(&wo, &key).expect("delete");
+                self.model.remove(&key);
+            }
+            60..=69 => {
+                // Later operations in a batch override earlier ones for the same key.
+                let mut batch = WriteBatch::new();
+                let mut model = self.model.clone();
+                for _ in 0..rnd.uniform(10) {
+                    let key = random_key(rnd);
+                    if rnd.one_in(3) {
+                        batch.delete(&key);
+                        model.remove(&key);
+                    } else {
+                        let value = random_value(rnd, step);
+                        batch.put(&key, &value);
+                        model.insert(key, value);
+                    }
+                }
+                self.context = format!("step {}: write batch", step);
+                self.db().write(&wo, batch).expect("write");
+                self.model = model;
+            }
+            70..=79 => {
+                self.context = format!("step {}: get", step);
+                let key = random_key(rnd);
+                let got = self.db().get(&ReadOptions::default(), &key).expect("get");
+                assert_eq!(got.as_ref(), self.model.get(&key), "{}", self.context);
+            }
+            80..=87 => {
+                self.context = format!("step {}: scan", step);
+                let start = random_key(rnd);
+                self.check_scan(None, &self.model, Some(&start), rnd.uniform(30) as usize);
+            }
+            88..=92 => {
+                if self.snapshots.len() < 4 {
+                    let snapshot = self.db().get_snapshot();
+                    self.snapshots.push((snapshot, self.model.clone()));
+                } else {
+                    let i = rnd.uniform(self.snapshots.len() as u32) as usize;
+                    self.snapshots.remove(i);
+                }
+            }
+            93..=96 => {
+                let (a, b) = (random_key(rnd), random_key(rnd));
+                let (begin, end) = if a <= b { (a, b) } else { (b, a) };
+                self.context = format!("step {}: compact_range", step);
+                self.db().compact_range(&KeyRange::inclusive(&begin, &end)).expect("compact_range");
+            }
+            _ => {
+                self.context = format!("step {}: reopen", step);
+                self.reopen();
+            }
+        }
+    }
+}
+
+fn run(name: &str, seed: u32, steps: usize, options: Options) {
+    let mut harness = Harness::new(name, options);
+    let rnd = Random::new(seed);
+    for step in 0..steps {
+        harness.step(&rnd, step);
+        if step % 100 == 99 {
+            harness.context = format!("seed {} after step {}", seed, step);
+            harness.check_all(&rnd);
+        }
+    }
+    harness.context = format!("seed {} at end", seed);
+    harness.check_all(&rnd);
+}
+
+#[test]
+fn random_operations_default_options() {
+    for seed in 1..=3 {
+        run(&format!("model_default_{}", seed), seed, 600, Options::default());
+    }
+}
+
+#[test]
+fn random_operations_small_buffers() {
+    // Tiny write buffers and files force frequent flushes and compactions, so reads
+    // go through many tables and levels.
+    let options = Options {
+        write_buffer_size: 2 * 1024,
+        max_file_size: 4 * 1024,
+        block_size: 256,
+        ..Options::default()
+    };
+    for seed in 301..=303 {
+        run(&format!("model_small_{}", seed), seed, 1500, options.clone());
+    }
+}
@@ -0,0 +1,161 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    db::{filename::table_file_name, table_cache::TableCache, version_edit::FileMetaData, version_set::Version},
+    leveldb::{
+        env::Env,
+        iterator::{new_empty_iterator, Iterator},
+        listener::{EventListener, TableFileDeletionInfo},
+        result::Result,
+    },
+};
+
+/// Table files that compactions removed from the current version but that may still be
+/// read through an older version. Versions are reference counted and share the
+/// `FileMetaData` of their files, so a queued file is unreferenced once the queue holds
+/// the only reference to its metadata. Only then is it deleted.
+pub struct PurgeQueue {
+    dbname: String,
+    env: Arc<dyn Env>,
+    table_cache: Arc<TableCache>,
+    files: Mutex<Vec<Arc<FileMetaData>>>,
+    // Number of outstanding disable_deletions() calls. Held while files are deleted.
+    deletions_disabled: Mutex<usize>,
+    // Told about each deleted file.
+    listeners: Vec<Arc<dyn EventListener>>,
+}
+
+impl PurgeQueue {
+    pub fn new(dbname: &str, env: Arc<dyn Env>, table_cache: Arc<TableCache>, listeners: Vec<Arc<dyn EventListener>>) -> Self {
+        Self {
+            dbname: dbname.to_string(),
+            env,
+            table_cache,
+            files: Mutex::new(Vec::new()),
+            deletions_disabled: Mutex::new(0),
+            listeners,
+        }
+    }
+
+    /// Queue `f`, which is no longer part of the current version, for deletion.
+    pub fn add(&self, f: Arc<FileMetaData>) { self.files.lock().unwrap().push(f); }
+
+    /// Keep queued files on disk until a matching `enable_deletions()` call. Returns
+    /// once no purge is deleting files anymore.
+    pub fn disable_deletions(&self) { *self.deletions_disabled.lock().unwrap() += 1; }
+
+    /// Undo a `disable_deletions()` call. Returns true iff deletions are enabled again.
+    pub fn enable_deletions(&self) -> bool {
+        let mut disabled = self.deletions_disabled.lock().unwrap();
+        *disabled = disabled.saturating_sub(1);
+        *disabled == 0
+    }
+
+    pub fn deletions_disabled(&self) -> bool { *self.deletions_disabled.lock().unwrap() > 0 }
+
+    /// Delete the queued files that no version references anymore, unless deletions
+    /// are disabled. Returns the number of files deleted.
+    pub fn purge(&self) -> usize {
+        let disabled = self.deletions_disabled.lock().unwrap();
+        if *disabled > 0 {
+            return 0;
+        }
+        let mut files = self.files.lock().unwrap();
+        // A reference can only be copied from an existing one, so a count of one
+        // cannot go up while the queue is locked.
+        let (obsolete, pending): (Vec<_>, Vec<_>) = files.drain(..).partition(|f| Arc::strong_count(f) == 1);
+        *files = pending;
+        drop(files);
+
+        for f in obsolete.iter() {
+            self.table_cache.evict(f.number);
+            let file_path = table_file_name(&self.dbname, f.number);
+            let status = self.env.remove_file(&file_path);
+            if !self.listeners.is_empty() {
+                let info = TableFileDeletionInfo {
+                    file_number: f.number,
+                    file_path,
+                    status,
+                };
+                for listener in self.listeners.iter() {
+                    listener.on_table_file_deleted(&info);
+                }
+            }
+        }
+        drop(disabled);
+        obsolete.len()
+    }
+
+    /// Numbers of the queued files, in the order they were queued.
+    pub fn pending(&self) -> Vec<u64> { self.files.lock().unwrap().iter().map(|f| f.number).collect() }
+}
+
+/// An iterator that keeps the version it reads from, and so that version's files,
+/// alive. Dropping it purges whatever it was the last reader of.
+pub struct PinnedIterator {
+    iter: Box<dyn Iterator>,
+    version: Option<Arc<Version>>,
+    purge_queue: Arc<PurgeQueue>,
+}
+
+impl PinnedIterator {
+    pub fn new(iter: Box<dyn Iterator>, version: Arc<Version>, purge_queue: Arc<PurgeQueue>) -> Self {
+        Self {
+            iter,
+            version: Some(version),
+            purge_queue,
+        }
+    }
+}
+
+impl Iterator for PinnedIterator {
+    fn valid(&self) -> bool { self.iter.valid() }
+
+    fn seek_to_first(&mut self) { self.iter.seek_to_first() }
+
+    fn seek_to_last(&mut self) { self.iter.seek_to_last() }
+
+    fn seek(&mut self, target: &[u8]) { self.iter.seek(target) }
+
+    fn seek_for_prev(&mut self, target: &[u8]) { self.iter.seek_for_prev(target) }
+
+    fn next(&mut self) { self.iter.next() }
+
+    fn prev(&mut self) { self.iter.prev() }
+
+    fn key(&self) -> &[u8] { self.iter.key() }
+
+    fn value(&self) -> &[u8] { self.iter.value() }
+
+    fn status(&self) -> Result<()> { self.iter.status() }
+}
+
+impl Drop for PinnedIterator {
+    fn drop(&mut self) {
+        // Release the version first so that its files can be purged.
+        self.iter = new_empty_iterator();
+        self.version = None;
+        self.purge_queue.purge();
+    }
+}
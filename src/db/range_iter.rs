@@ -0,0 +1,186 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{cmp::Ordering, ops::Bound, sync::Arc};
+
+use crate::{
+    db::key_range::KeyRange,
+    leveldb::{comparator::Comparator, iterator::Iterator, result::Result},
+};
+
+/// A key and its value, as yielded by `RangeIter`.
+pub type Entry = (Box<[u8]>, Box<[u8]>);
+
+/// A `std::iter::Iterator` over the entries of a DB iterator that fall in a key range,
+/// as returned by `DB::iter_range()` and `DB::iter_prefix()`. An error of the
+/// underlying iterator is yielded once and ends the iteration.
+///
+/// The range can be consumed from both ends (e.g. with `rev()`): each end has an
+/// iterator of its own, and the iteration ends where they meet.
+pub struct RangeIter {
+    front: Box<dyn Iterator>,
+    back: Box<dyn Iterator>,
+    range: KeyRange,
+    comparator: Arc<dyn Comparator>,
+    // back is positioned at the end of the range on the first next_back()
+    back_positioned: bool,
+    // Keys last yielded at each end
+    front_key: Option<Box<[u8]>>,
+    back_key: Option<Box<[u8]>>,
+    done: bool,
+}
+
+impl RangeIter {
+    /// Iterate over `range` with `front` and `back`, which yield user keys ordered by
+    /// `comparator` and must observe the same state of the database.
+    pub fn new(mut front: Box<dyn Iterator>, back: Box<dyn Iterator>, range: KeyRange, comparator: Arc<dyn Comparator>) -> Self {
+        match &range.start {
+            Bound::Included(start) | Bound::Excluded(start) => front.seek(start),
+            Bound::Unbounded => front.seek_to_first(),
+        }
+        if front.valid() && range.is_before_start(comparator.as_ref(), front.key()) {
+            // The excluded start key itself
+            front.next();
+        }
+        Self {
+            front,
+            back,
+            range,
+            comparator,
+            back_positioned: false,
+            front_key: None,
+            back_key: None,
+            done: false,
+        }
+    }
+
+    fn position_back(&mut self) {
+        match &self.range.end {
+            Bound::Included(end) | Bound::Excluded(end) => self.back.seek_for_prev(end),
+            Bound::Unbounded => self.back.seek_to_last(),
+        }
+        if self.back.valid() && self.range.is_after_end(self.comparator.as_ref(), self.back.key()) {
+            // The excluded end key itself
+            self.back.prev();
+        }
+        self.back_positioned = true;
+    }
+
+    fn finish(&mut self, iter_status: Result<()>) -> Option<Result<Entry>> {
+        self.done = true;
+        iter_status.err().map(Err)
+    }
+}
+
+impl std::iter::Iterator for RangeIter {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let cmp = self.comparator.as_ref();
+        if !self.front.valid()
+            || self.range.is_after_end(cmp, self.front.key())
+            || self.back_key.as_ref().is_some_and(|back| cmp.compare(self.front.key(), back) != Ordering::Less)
+        {
+            return self.finish(self.front.status());
+        }
+        let entry: Entry = (Box::from(self.front.key()), Box::from(self.front.value()));
+        self.front_key = Some(entry.0.clone());
+        self.front.next();
+        Some(Ok(entry))
+    }
+}
+
+impl DoubleEndedIterator for RangeIter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if !self.back_positioned {
+            self.position_back();
+        }
+        let cmp = self.comparator.as_ref();
+        if !self.back.valid()
+            || self.range.is_before_start(cmp, self.back.key())
+            || self.front_key.as_ref().is_some_and(|front| cmp.compare(self.back.key(), front) != Ordering::Greater)
+        {
+            return self.finish(self.back.status());
+        }
+        let entry: Entry = (Box::from(self.back.key()), Box::from(self.back.value()));
+        self.back_key = Some(entry.0.clone());
+        self.back.prev();
+        Some(Ok(entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::DB,
+        leveldb::options::{Options, ReadOptions, WriteOptions},
+        util::testutil::tmp_dir,
+    };
+
+    fn keys(iter: impl std::iter::Iterator<Item = Result<Entry>>) -> Vec<String> {
+        iter.map(|entry| String::from_utf8(entry.unwrap().0.into_vec()).unwrap()).collect()
+    }
+
+    #[test]
+    fn ranges() {
+        let dir = tmp_dir("range_iter");
+        let options = Options {
+            create_if_missing: true,
+            ..Options::default()
+        };
+        let db = DB::open(options, &dir).unwrap();
+        let wo = WriteOptions::default();
+        for key in ["a", "ab", "abc", "b", "ba", "c"] {
+            db.put(&wo, key.as_bytes(), key.to_uppercase().as_bytes()).unwrap();
+        }
+        db.delete(&wo, b"ba").unwrap();
+        let ro = ReadOptions::default();
+
+        assert_eq!(keys(db.iter_range(&ro, &KeyRange::all())), ["a", "ab", "abc", "b", "c"]);
+        assert_eq!(keys(db.iter_range(&ro, &KeyRange::half_open(b"ab", b"b"))), ["ab", "abc"]);
+        let range = KeyRange::new(Bound::Excluded(b"ab"), Bound::Included(b"b"));
+        assert_eq!(keys(db.iter_range(&ro, &range)), ["abc", "b"]);
+        assert_eq!(keys(db.iter_prefix(&ro, b"ab")), ["ab", "abc"]);
+        assert!(db.iter_prefix(&ro, b"x").next().is_none());
+
+        // Iterating from both ends
+        assert_eq!(keys(db.iter_prefix(&ro, b"a").rev()), ["abc", "ab", "a"]);
+        let mut iter = db.iter_range(&ro, &KeyRange::all());
+        assert_eq!(&*iter.next().unwrap().unwrap().0, b"a");
+        assert_eq!(&*iter.next_back().unwrap().unwrap().1, b"C");
+        assert_eq!(keys(iter), ["ab", "abc", "b"]);
+
+        let values: Vec<_> = db
+            .iter_range(&ro, &KeyRange::all())
+            .filter_map(|entry| entry.ok())
+            .filter(|(key, _)| key.len() == 1)
+            .map(|(_, value)| value)
+            .collect();
+        assert_eq!(values, [&b"A"[..], b"B", b"C"].map(Box::from));
+    }
+}
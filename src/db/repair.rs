@@ -0,0 +1,462 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// We recover the contents of the descriptor from the other files we find.
+// (1) Any log files are first converted to tables
+// (2) We scan every table to compute
+//     (a) smallest/largest for the table
+//     (b) largest sequence number in the table
+// (3) We generate descriptor contents:
+//      - log number is set to zero
+//      - next-file-number is set to 1 + largest file number we found
+//      - last-sequence-number is set to largest sequence# found across all tables
+//        (see 2b)
+//      - compaction pointers are cleared
+//      - every table file is added at level 0
+//
+// Possible optimization 1:
+//   (a) Compute total size and use to pick appropriate max-level M
+//   (b) Sort tables by largest sequence# in the table
+//   (c) For each table: if it overlaps earlier table, place in level-0, else place in
+//       level-M.
+// Possible optimization 2:
+//   Store per-table metadata (smallest, largest, largest-seq#, ...) in the table's
+//   meta section to speed up ScanTable.
+
+use std::sync::Arc;
+
+use crate::{
+    db::{
+        builder::build_table,
+        db_impl::sanitize_options,
+        dbformat::{parse_internal_key, InternalKey, InternalKeyComparator, SequenceNumber},
+        filename::{descriptor_file_name, log_file_name, parse_file_name, set_current_file, table_file_name, temp_file_name, FileType},
+        log_reader, log_writer,
+        memtable::MemTable,
+        table_cache::TableCache,
+        version_edit::{FileMetaData, VersionEdit},
+        write_batch::WriteBatch,
+    },
+    leveldb::{
+        env::Env,
+        options::{Options, ReadOptions},
+        result::{Error, Result},
+    },
+    table::table_builder::TableBuilder,
+};
+
+// Number of open files that can be used by the table cache of the repairer.
+const TABLE_CACHE_SIZE: usize = 10;
+
+struct TableInfo {
+    meta: FileMetaData,
+    max_sequence: SequenceNumber,
+}
+
+struct Repairer {
+    dbname: String,
+    env: Arc<dyn Env>,
+    icmp: InternalKeyComparator,
+    options: Options,
+    table_cache: TableCache,
+    edit: VersionEdit,
+
+    manifests: Vec<String>,
+    table_numbers: Vec<u64>,
+    logs: Vec<u64>,
+    tables: Vec<TableInfo>,
+    next_file_number: u64,
+}
+
+impl Repairer {
+    fn new(dbname: &str, options: &Options) -> Self {
+        let icmp = InternalKeyComparator::new(options.comparator.clone());
+        let options = sanitize_options(&icmp, options);
+        Self {
+            dbname: dbname.to_string(),
+            env: options.env.clone(),
+            table_cache: TableCache::new(dbname, &options, TABLE_CACHE_SIZE),
+            icmp,
+            options,
+            edit: VersionEdit::new(),
+            manifests: Vec::new(),
+            table_numbers: Vec::new(),
+            logs: Vec::new(),
+            tables: Vec::new(),
+            next_file_number: 1,
+        }
+    }
+
+    fn run(&mut self) -> Result<()> {
+        self.find_files()?;
+        self.convert_log_files_to_tables();
+        self.extract_meta_data();
+        self.write_descriptor()
+    }
+
+    fn find_files(&mut self) -> Result<()> {
+        let filenames = self.env.get_children(&self.dbname)?;
+        if filenames.is_empty() {
+            return LEVELDB_ERR!(IOError, "repair found no files");
+        }
+
+        for name in filenames {
+            if let Some((number, file_type)) = parse_file_name(&name) {
+                if file_type == FileType::DescriptorFile {
+                    self.manifests.push(name);
+                } else {
+                    if number + 1 > self.next_file_number {
+                        self.next_file_number = number + 1;
+                    }
+                    match file_type {
+                        FileType::LogFile => self.logs.push(number),
+                        FileType::TableFile => self.table_numbers.push(number),
+                        // Ignore other files
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn convert_log_files_to_tables(&mut self) {
+        let mut logs = std::mem::take(&mut self.logs);
+        logs.sort_unstable();
+        for log in logs {
+            let logname = log_file_name(&self.dbname, log);
+            // Whatever happens, the log is archived: its readable contents have been
+            // salvaged into a table.
+            let _ = self.convert_log_to_table(log);
+            self.archive_file(&logname);
+        }
+    }
+
+    fn convert_log_to_table(&mut self, log: u64) -> Result<()> {
+        // Open the log file
+        let logname = log_file_name(&self.dbname, log);
+        let file = self.env.new_sequential_file(&logname)?;
+
+        // Create the log reader. We intentionally make log::Reader do checksumming so
+        // that corruptions cause entire commits to be skipped instead of propagating
+        // bad information (like overly large sequence numbers).
+        let mut reader = log_reader::Reader::new(file, Some(Box::new(LogReporter)), true);
+
+        // Read all the records and add to a memtable
+        let mut record = Vec::new();
+        let mut batch = WriteBatch::new();
+        let mem = MemTable::new(self.icmp.clone());
+        while reader.read_record(&mut record) {
+            if record.len() < 12 {
+                // Log record too small
+                continue;
+            }
+            batch.set_contents(&record);
+            // A batch that fails to apply is skipped; the memtable keeps whatever
+            // entries were inserted before the bad record.
+            let _ = batch.insert_into(&mem);
+        }
+
+        // Do not record a version edit for this conversion to a Table since
+        // extract_meta_data() will also generate edits.
+        let mut meta = FileMetaData {
+            number: self.next_file_number,
+            ..FileMetaData::default()
+        };
+        self.next_file_number += 1;
+        let mut iter = mem.new_iterator();
+        build_table(&self.dbname, &self.options, &self.table_cache, iter.as_mut(), &mut meta)?;
+        if meta.file_size > 0 {
+            self.table_numbers.push(meta.number);
+        }
+        Ok(())
+    }
+
+    fn extract_meta_data(&mut self) {
+        for number in std::mem::take(&mut self.table_numbers) {
+            self.scan_table(number);
+        }
+    }
+
+    fn scan_table(&mut self, number: u64) {
+        let mut fname = table_file_name(&self.dbname, number);
+        let mut file_size = self.env.get_file_size(&fname);
+        if file_size.is_err() {
+            // Try alternate file name.
+            fname = format!("{}/{:06}.sst", self.dbname, number);
+            file_size = self.env.get_file_size(&fname);
+        }
+        let file_size = match file_size {
+            Ok(size) => size,
+            Err(_) => {
+                self.archive_file(&table_file_name(&self.dbname, number));
+                self.archive_file(&fname);
+                return;
+            }
+        };
+        if !fname.ends_with(".ldb") {
+            // Tables are always opened through their ".ldb" name.
+            let target = table_file_name(&self.dbname, number);
+            if self.env.rename_file(&fname, &target).is_err() {
+                self.archive_file(&fname);
+                return;
+            }
+        }
+
+        // Extract metadata by scanning through table.
+        let mut table = TableInfo {
+            meta: FileMetaData {
+                number,
+                file_size,
+                ..FileMetaData::default()
+            },
+            max_sequence: 0,
+        };
+        let mut iter = self.table_cache.new_iterator(&ReadOptions::default(), number, file_size);
+        let mut empty = true;
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = iter.key();
+            if let Some(parsed) = parse_internal_key(key) {
+                if empty {
+                    empty = false;
+                    table.meta.smallest = InternalKey::decode_from(key);
+                }
+                table.meta.largest = InternalKey::decode_from(key);
+                if parsed.sequence > table.max_sequence {
+                    table.max_sequence = parsed.sequence;
+                }
+            }
+            iter.next();
+        }
+        let status = iter.status();
+        drop(iter);
+
+        if status.is_err() {
+            self.repair_table(table);
+        } else if empty {
+            // Nothing readable in the table
+            self.archive_file(&table_file_name(&self.dbname, number));
+        } else {
+            self.tables.push(table);
+        }
+    }
+
+    /// Copy the readable entries of a damaged table into a new table that replaces it.
+    fn repair_table(&mut self, mut table: TableInfo) {
+        let src = table_file_name(&self.dbname, table.meta.number);
+        let copy = table_file_name(&self.dbname, self.next_file_number);
+        self.next_file_number += 1;
+        let file = match self.env.new_writable_file(&copy) {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+        let mut builder = TableBuilder::new(&self.options, file);
+
+        // Copy data.
+        let mut iter = self
+            .table_cache
+            .new_iterator(&ReadOptions::default(), table.meta.number, table.meta.file_size);
+        iter.seek_to_first();
+        while iter.valid() {
+            builder.add(iter.key(), iter.value());
+            iter.next();
+        }
+        drop(iter);
+
+        self.archive_file(&src);
+        self.table_cache.evict(table.meta.number);
+        let mut s = Ok(());
+        if builder.num_entries() == 0 {
+            builder.abandon();
+        } else {
+            s = builder.finish();
+            if s.is_ok() {
+                table.meta.file_size = builder.file_size();
+            }
+        }
+        let num_entries = builder.num_entries();
+        let mut file = builder.into_file();
+        if s.is_ok() {
+            s = file.close();
+        }
+
+        if s.is_ok() && num_entries > 0 {
+            let orig = table_file_name(&self.dbname, table.meta.number);
+            s = self.env.rename_file(&copy, &orig);
+            if s.is_ok() {
+                self.tables.push(table);
+            }
+        }
+        if s.is_err() || num_entries == 0 {
+            let _ = self.env.remove_file(&copy);
+        }
+    }
+
+    fn write_descriptor(&mut self) -> Result<()> {
+        let tmp = temp_file_name(&self.dbname, 1);
+        let file = self.env.new_writable_file(&tmp)?;
+
+        let max_sequence = self.tables.iter().map(|t| t.max_sequence).max().unwrap_or(0);
+
+        self.edit.set_comparator_name(self.icmp.user_comparator().name());
+        self.edit.set_log_number(0);
+        self.edit.set_next_file(self.next_file_number);
+        self.edit.set_last_sequence(max_sequence);
+
+        for t in self.tables.iter() {
+            // TODO(opt): separate out into multiple levels
+            self.edit
+                .add_file(0, t.meta.number, t.meta.file_size, &t.meta.smallest, &t.meta.largest);
+        }
+
+        let mut status = {
+            let mut log = log_writer::Writer::new(file);
+            let mut record = Vec::new();
+            self.edit.encode_to(&mut record);
+            log.add_record(&record).and_then(|_| log.close())
+        };
+
+        if status.is_err() {
+            let _ = self.env.remove_file(&tmp);
+        } else {
+            // Discard older manifests
+            for manifest in std::mem::take(&mut self.manifests) {
+                self.archive_file(&format!("{}/{}", self.dbname, manifest));
+            }
+
+            // Install new manifest
+            status = self.env.rename_file(&tmp, &descriptor_file_name(&self.dbname, 1));
+            if status.is_ok() {
+                status = set_current_file(self.env.as_ref(), &self.dbname, 1);
+            } else {
+                let _ = self.env.remove_file(&tmp);
+            }
+        }
+        status
+    }
+
+    /// Move `fname` into a "lost" subdirectory of the database so that it stays out of
+    /// the way without being destroyed.
+    fn archive_file(&self, fname: &str) {
+        // Move into another directory. E.g., for
+        //    dir/foo
+        // rename to
+        //    dir/lost/foo
+        let (dir, base) = match fname.rfind('/') {
+            Some(slash) => (&fname[..slash], &fname[slash + 1..]),
+            None => ("", fname),
+        };
+        let lost_dir = format!("{}/lost", dir);
+        let _ = self.env.create_dir(&lost_dir); // Ignore error
+        let _ = self.env.rename_file(fname, &format!("{}/{}", lost_dir, base));
+    }
+}
+
+/// Log reader reporter that lets the repairer keep going past damaged records.
+struct LogReporter;
+
+impl log_reader::Reporter for LogReporter {
+    fn corruption(&mut self, _bytes: usize, _reason: &Error) {}
+}
+
+/// If a DB cannot be opened, you may attempt to call this method to resurrect as much
+/// of the contents of the database as possible. Some data may be lost, so be careful
+/// when calling this function on a database that contains important information.
+///
+/// Files that cannot be salvaged are moved into a `lost` subdirectory of the database
+/// rather than deleted.
+pub fn repair_db(dbname: &str, options: &Options) -> Result<()> { Repairer::new(dbname, options).run() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{db_impl::DB, filename::current_file_name, key_range::KeyRange},
+        leveldb::{env::write_string_to_file, options::WriteOptions},
+        util::testutil::tmp_dir,
+    };
+
+    fn options() -> Options {
+        Options {
+            create_if_missing: true,
+            write_buffer_size: 4096,
+            ..Options::default()
+        }
+    }
+
+    fn fill(dbname: &str, n: usize) {
+        let db = DB::open(options(), dbname).unwrap();
+        for i in 0..n {
+            db.put(&WriteOptions::default(), format!("key{:04}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn repair_lost_manifest() {
+        let dbname = tmp_dir("repair_manifest");
+        fill(&dbname, 500);
+
+        // Destroy the descriptor
+        let env = options().env;
+        write_string_to_file(env.as_ref(), b"garbage", &current_file_name(&dbname)).unwrap();
+        for name in env.get_children(&dbname).unwrap() {
+            if name.starts_with("MANIFEST") {
+                write_string_to_file(env.as_ref(), b"garbage", &format!("{}/{}", dbname, name)).unwrap();
+            }
+        }
+        assert!(DB::open(options(), &dbname).is_err());
+
+        repair_db(&dbname, &options()).unwrap();
+        let db = DB::open(options(), &dbname).unwrap();
+        for i in 0..500 {
+            let value = db.get(&ReadOptions::default(), format!("key{:04}", i).as_bytes()).unwrap();
+            assert_eq!(value.as_deref(), Some(&b"value"[..]));
+        }
+        assert!(env.file_exists(&format!("{}/lost", dbname)));
+    }
+
+    #[test]
+    fn repair_recovers_logs_and_archives_garbage() {
+        let dbname = tmp_dir("repair_logs");
+        {
+            let db = DB::open(options(), &dbname).unwrap();
+            db.put(&WriteOptions { sync: true }, b"foo", b"bar").unwrap();
+            // Leave the write in the log only.
+            db.crash();
+        }
+        let env = options().env;
+        let garbage = table_file_name(&dbname, 999);
+        write_string_to_file(env.as_ref(), b"not a table", &garbage).unwrap();
+
+        repair_db(&dbname, &options()).unwrap();
+        assert!(!env.file_exists(&garbage));
+        assert!(env.file_exists(&format!("{}/lost/000999.ldb", dbname)));
+
+        let db = DB::open(options(), &dbname).unwrap();
+        assert_eq!(db.get(&ReadOptions::default(), b"foo").unwrap().as_deref(), Some(&b"bar"[..]));
+        // Newly allocated file numbers do not collide with the ones found by repair.
+        db.put(&WriteOptions::default(), b"baz", b"qux").unwrap();
+        db.compact_range(&KeyRange::all()).unwrap();
+        assert_eq!(db.get(&ReadOptions::default(), b"foo").unwrap().as_deref(), Some(&b"bar"[..]));
+    }
+}
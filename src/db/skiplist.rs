@@ -0,0 +1,696 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Thread safety
+// -------------
+//
+// Writes are serialized internally by a mutex that also guards the arena, unless the
+// list was created with `new_concurrent()`: then writers link their nodes in with
+// compare-and-swap and allocate from a `ConcurrentArena`, so that they proceed in
+// parallel. Reads require a guarantee that the SkipList will not be destroyed while
+// the read is in progress (which `Iter` provides by holding an `Arc`). Apart from
+// that, reads progress without any internal locking or synchronization.
+//
+// Invariants:
+//
+// (1) Allocated nodes are never deleted until the SkipList is destroyed. This is
+// trivially guaranteed by the code since we never delete any skip list nodes.
+//
+// (2) The contents of a Node except for the next/prev pointers are immutable after the
+// Node has been linked into the SkipList. Only `insert()` modifies the list, and it is
+// careful to initialize a node and use release-stores (or compare-and-swaps) to publish
+// the nodes in one or more lists.
+
+use std::{
+    alloc::Layout,
+    cmp::Ordering,
+    mem, ptr,
+    sync::{
+        atomic::{AtomicPtr, AtomicUsize, Ordering as AtomicOrdering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    leveldb::slice::Slice,
+    util::{arena::Arena, concurrent_arena::ConcurrentArena, random::Random},
+};
+
+const MAX_HEIGHT: usize = 12;
+const BRANCHING: u32 = 4;
+
+/// Orders the keys stored in a `SkipList` or a `VectorRep`.
+pub trait KeyComparator: Send + Sync {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+}
+
+#[repr(C)]
+struct Node {
+    key: Slice,
+    // Array of length equal to the node height. next[0] is lowest level link.
+    next: [AtomicPtr<Node>; 1],
+}
+
+impl Node {
+    #[inline]
+    unsafe fn slot(node: *mut Node, n: usize) -> *const AtomicPtr<Node> {
+        (ptr::addr_of_mut!((*node).next) as *const AtomicPtr<Node>).add(n)
+    }
+
+    /// Accessors/mutators for links. Wrapped in methods so we can add the appropriate
+    /// barriers as necessary.
+    #[inline]
+    unsafe fn next(node: *mut Node, n: usize) -> *mut Node {
+        // Use an 'acquire load' so that we observe a fully initialized version of the
+        // returned Node.
+        (*Node::slot(node, n)).load(AtomicOrdering::Acquire)
+    }
+
+    #[inline]
+    unsafe fn set_next(node: *mut Node, n: usize, x: *mut Node) {
+        // Use a 'release store' so that anybody who reads through this pointer observes
+        // a fully initialized version of the inserted node.
+        (*Node::slot(node, n)).store(x, AtomicOrdering::Release)
+    }
+
+    /// No-barrier variants that can be safely used in a few locations.
+    #[inline]
+    unsafe fn no_barrier_next(node: *mut Node, n: usize) -> *mut Node {
+        (*Node::slot(node, n)).load(AtomicOrdering::Relaxed)
+    }
+
+    #[inline]
+    unsafe fn no_barrier_set_next(node: *mut Node, n: usize, x: *mut Node) {
+        (*Node::slot(node, n)).store(x, AtomicOrdering::Relaxed)
+    }
+
+    /// Replace the link at level `n` with `x` if it still points to `expected`, with
+    /// the barriers of `set_next()` and `next()`.
+    #[inline]
+    unsafe fn cas_next(node: *mut Node, n: usize, expected: *mut Node, x: *mut Node) -> bool {
+        (*Node::slot(node, n))
+            .compare_exchange(expected, x, AtomicOrdering::AcqRel, AtomicOrdering::Acquire)
+            .is_ok()
+    }
+
+    fn layout(height: usize) -> Layout {
+        let size = mem::size_of::<Node>() + mem::size_of::<AtomicPtr<Node>>() * (height - 1);
+        Layout::from_size_align(size, mem::align_of::<Node>()).expect("invalid node layout")
+    }
+
+    /// Initialize a node of `height` levels in `mem`, allocated with `layout(height)`.
+    unsafe fn init(mem: *mut u8, key: Slice, height: usize) -> *mut Node {
+        let node = mem as *mut Node;
+        ptr::addr_of_mut!((*node).key).write(key);
+        for i in 0..height {
+            (Node::slot(node, i) as *mut AtomicPtr<Node>).write(AtomicPtr::new(ptr::null_mut()));
+        }
+        node
+    }
+
+    #[inline]
+    unsafe fn key<'a>(node: *mut Node) -> &'a [u8] { (*node).key.data() }
+}
+
+struct Writer {
+    arena: Arena,
+    rnd: Random,
+}
+
+impl Writer {
+    fn new_node(&mut self, key: Slice, height: usize) -> *mut Node {
+        // Every field is written by init(), so the memory need not be zeroed.
+        unsafe { Node::init(self.arena.alloc_layout(Node::layout(height)), key, height) }
+    }
+}
+
+fn random_height(rnd: &Random) -> usize {
+    // Increase height with probability 1 in BRANCHING
+    let mut height = 1;
+    while height < MAX_HEIGHT && rnd.one_in(BRANCHING) {
+        height += 1;
+    }
+    assert!(height > 0);
+    assert!(height <= MAX_HEIGHT);
+    height
+}
+
+/// Where the nodes and keys of a `SkipList` are allocated.
+enum Allocator {
+    // Writes are serialized by the mutex.
+    Serial(Mutex<Writer>),
+    // Writes proceed in parallel.
+    Concurrent(ConcurrentArena),
+}
+
+pub struct SkipList<C: KeyComparator> {
+    // Immutable after construction
+    compare: C,
+    head: *mut Node,
+
+    allocator: Allocator,
+
+    // Height of the entire list. Modified only by `insert()`. Read racily by readers,
+    // but stale values are ok.
+    max_height: AtomicUsize,
+}
+
+unsafe impl<C: KeyComparator> Send for SkipList<C> {}
+unsafe impl<C: KeyComparator> Sync for SkipList<C> {}
+
+impl<C: KeyComparator> SkipList<C> {
+    /// Create a new SkipList object that will use `cmp` for comparing keys, and will
+    /// allocate memory using its own arena.
+    pub fn new(cmp: C) -> Self { Self::with_arena(cmp, Arena::new()) }
+
+    /// Create a new SkipList object that will use `cmp` for comparing keys, and will
+    /// allocate memory using `arena`.
+    pub fn with_arena(cmp: C, arena: Arena) -> Self {
+        let mut writer = Writer {
+            arena,
+            rnd: Random::new(0xdeadbeef),
+        };
+        let head = writer.new_node(Slice::new_empty(), MAX_HEIGHT);
+        Self {
+            compare: cmp,
+            head,
+            allocator: Allocator::Serial(Mutex::new(writer)),
+            max_height: AtomicUsize::new(1),
+        }
+    }
+
+    /// Create a new SkipList object that will use `cmp` for comparing keys, and whose
+    /// inserts from several threads proceed in parallel instead of taking turns.
+    pub fn new_concurrent(cmp: C) -> Self {
+        let arena = ConcurrentArena::new();
+        let head = unsafe { Node::init(arena.alloc_aligned(Node::layout(MAX_HEIGHT).size()), Slice::new_empty(), MAX_HEIGHT) };
+        Self {
+            compare: cmp,
+            head,
+            allocator: Allocator::Concurrent(arena),
+            max_height: AtomicUsize::new(1),
+        }
+    }
+
+    /// Insert a copy of `key` into the list.
+    /// REQUIRES: nothing that compares equal to key is currently in the list.
+    pub fn insert(&self, key: &[u8]) { self.insert_with(key.len(), |buf| buf.copy_from_slice(key)) }
+
+    /// Allocate `len` bytes in the list's arena, let `encode` fill them in and insert the
+    /// result as a key. `encode` must write all `len` bytes.
+    /// REQUIRES: nothing that compares equal to the encoded key is currently in the list.
+    pub fn insert_with<F: FnOnce(&mut [u8])>(&self, len: usize, encode: F) {
+        let mut writer = match &self.allocator {
+            Allocator::Serial(writer) => writer.lock().unwrap(),
+            Allocator::Concurrent(arena) => return self.insert_concurrently(arena, len, encode),
+        };
+        let key = if len == 0 {
+            Slice::new_empty()
+        } else {
+            // `encode` overwrites every byte, so the buffer need not be zeroed first.
+            let buf = writer.arena.alloc_uninit(len);
+            encode(unsafe { std::slice::from_raw_parts_mut(buf, len) });
+            Slice::new(buf, len)
+        };
+
+        let mut prev = [ptr::null_mut(); MAX_HEIGHT];
+        let x = self.find_greater_or_equal(key.data(), Some(&mut prev));
+
+        // Our data structure does not allow duplicate insertion
+        assert!(x.is_null() || !self.equal(key.data(), unsafe { Node::key(x) }));
+
+        let height = random_height(&writer.rnd);
+        let max_height = self.get_max_height();
+        if height > max_height {
+            for p in prev.iter_mut().take(height).skip(max_height) {
+                *p = self.head;
+            }
+            // It is ok to mutate max_height without any synchronization with concurrent
+            // readers. A concurrent reader that observes the new value of max_height
+            // will see either the old value of new level pointers from head (null), or a
+            // new value set in the loop below. In the former case the reader will
+            // immediately drop to the next level since null sorts after all keys. In the
+            // latter case the reader will use the new node.
+            self.max_height.store(height, AtomicOrdering::Relaxed);
+        }
+
+        let x = writer.new_node(key, height);
+        for (i, p) in prev.iter().enumerate().take(height) {
+            unsafe {
+                // no_barrier_set_next() suffices since we will add a barrier when we
+                // publish a pointer to "x" in prev[i].
+                Node::no_barrier_set_next(x, i, Node::no_barrier_next(*p, i));
+                Node::set_next(*p, i, x);
+            }
+        }
+    }
+
+    fn insert_concurrently<F: FnOnce(&mut [u8])>(&self, arena: &ConcurrentArena, len: usize, encode: F) {
+        static NEXT_SEED: AtomicUsize = AtomicUsize::new(0xdeadbeef);
+        thread_local! {
+            static RND: Random = Random::new(NEXT_SEED.fetch_add(1, AtomicOrdering::Relaxed) as u32);
+        }
+        let key = if len == 0 {
+            Slice::new_empty()
+        } else {
+            let buf = arena.alloc_uninit(len);
+            encode(unsafe { std::slice::from_raw_parts_mut(buf, len) });
+            Slice::new(buf, len)
+        };
+
+        // Raise the height of the list first, so that the search below finds the
+        // predecessors of the new node at every one of its levels.
+        let height = RND.with(random_height);
+        let mut max_height = self.get_max_height();
+        while height > max_height {
+            match self.max_height.compare_exchange_weak(max_height, height, AtomicOrdering::Relaxed, AtomicOrdering::Relaxed) {
+                Ok(_) => max_height = height,
+                Err(current) => max_height = current,
+            }
+        }
+
+        let mut prev = [self.head; MAX_HEIGHT];
+        let mut next = [ptr::null_mut(); MAX_HEIGHT];
+        let mut x = self.head;
+        for level in (0..max_height).rev() {
+            (prev[level], next[level]) = self.find_splice_for_level(key.data(), x, level);
+            x = prev[level];
+        }
+
+        // Our data structure does not allow duplicate insertion
+        assert!(next[0].is_null() || !self.equal(key.data(), unsafe { Node::key(next[0]) }));
+
+        let x = unsafe { Node::init(arena.alloc_aligned(Node::layout(height).size()), key, height) };
+        // Link the node in from the bottom up, so that it is in the level 0 list, which
+        // defines the contents of the list, before it can be found through any other.
+        for level in 0..height {
+            loop {
+                unsafe {
+                    Node::no_barrier_set_next(x, level, next[level]);
+                    if Node::cas_next(prev[level], level, next[level], x) {
+                        break;
+                    }
+                }
+                // Another writer linked a node in between, so search again from the
+                // predecessor we had found, which stays before the key.
+                (prev[level], next[level]) = self.find_splice_for_level(unsafe { Node::key(x) }, prev[level], level);
+            }
+        }
+    }
+
+    /// Return the last node at `level`, starting from `before`, whose key is less than
+    /// `key`, and the node that follows it.
+    /// REQUIRES: `before` is the head or its key is less than `key`
+    fn find_splice_for_level(&self, key: &[u8], mut before: *mut Node, level: usize) -> (*mut Node, *mut Node) {
+        loop {
+            let next = unsafe { Node::next(before, level) };
+            if self.key_is_after_node(key, next) {
+                before = next;
+            } else {
+                return (before, next);
+            }
+        }
+    }
+
+    /// Return the comparator used to order the keys of this list.
+    pub fn comparator(&self) -> &C { &self.compare }
+
+    /// Returns true iff an entry that compares equal to key is in the list.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        let x = self.find_greater_or_equal(key, None);
+        !x.is_null() && self.equal(key, unsafe { Node::key(x) })
+    }
+
+    /// Destroy the list and return its arena, e.g. to `reset()` it for another list.
+    /// Returns `None` for lists created with `new_concurrent()`.
+    pub fn into_arena(self) -> Option<Arena> {
+        match self.allocator {
+            Allocator::Serial(writer) => Some(writer.into_inner().unwrap().arena),
+            Allocator::Concurrent(_) => None,
+        }
+    }
+
+    /// Return the number of bytes allocated by the list's arena.
+    pub fn memory_usage(&self) -> usize {
+        match &self.allocator {
+            Allocator::Serial(writer) => writer.lock().unwrap().arena.memory_usage() as usize,
+            Allocator::Concurrent(arena) => arena.memory_usage() as usize,
+        }
+    }
+
+    #[inline]
+    fn get_max_height(&self) -> usize { self.max_height.load(AtomicOrdering::Relaxed) }
+
+    #[inline]
+    fn equal(&self, a: &[u8], b: &[u8]) -> bool { self.compare.compare(a, b) == Ordering::Equal }
+
+    /// Return true if key is greater than the data stored in `n`
+    #[inline]
+    fn key_is_after_node(&self, key: &[u8], n: *mut Node) -> bool {
+        // null n is considered infinite
+        !n.is_null() && self.compare.compare(unsafe { Node::key(n) }, key) == Ordering::Less
+    }
+
+    /// Return the earliest node that comes at or after key.
+    /// Return null if there is no such node.
+    ///
+    /// If prev is non-null, fills prev[level] with pointer to previous node at "level"
+    /// for every level in [0..max_height-1].
+    fn find_greater_or_equal(&self, key: &[u8], mut prev: Option<&mut [*mut Node; MAX_HEIGHT]>) -> *mut Node {
+        let mut x = self.head;
+        let mut level = self.get_max_height() - 1;
+        loop {
+            let next = unsafe { Node::next(x, level) };
+            if self.key_is_after_node(key, next) {
+                // Keep searching in this list
+                x = next;
+            } else {
+                if let Some(p) = prev.as_mut() {
+                    p[level] = x;
+                }
+                if level == 0 {
+                    return next;
+                }
+                // Switch to next list
+                level -= 1;
+            }
+        }
+    }
+
+    /// Return the latest node with a key < key.
+    /// Return head if there is no such node.
+    fn find_less_than(&self, key: &[u8]) -> *mut Node {
+        let mut x = self.head;
+        let mut level = self.get_max_height() - 1;
+        loop {
+            assert!(x == self.head || self.compare.compare(unsafe { Node::key(x) }, key) == Ordering::Less);
+            let next = unsafe { Node::next(x, level) };
+            if next.is_null() || self.compare.compare(unsafe { Node::key(next) }, key) != Ordering::Less {
+                if level == 0 {
+                    return x;
+                }
+                // Switch to next list
+                level -= 1;
+            } else {
+                x = next;
+            }
+        }
+    }
+
+    /// Return the last node in the list.
+    /// Return head if list is empty.
+    fn find_last(&self) -> *mut Node {
+        let mut x = self.head;
+        let mut level = self.get_max_height() - 1;
+        loop {
+            let next = unsafe { Node::next(x, level) };
+            if next.is_null() {
+                if level == 0 {
+                    return x;
+                }
+                // Switch to next list
+                level -= 1;
+            } else {
+                x = next;
+            }
+        }
+    }
+}
+
+/// Iteration over the contents of a skip list
+pub struct Iter<C: KeyComparator> {
+    list: Arc<SkipList<C>>,
+    node: *mut Node,
+}
+
+unsafe impl<C: KeyComparator> Send for Iter<C> {}
+
+impl<C: KeyComparator> Iter<C> {
+    /// Initialize an iterator over the specified list.
+    /// The returned iterator is not valid.
+    pub fn new(list: Arc<SkipList<C>>) -> Self {
+        Self {
+            list,
+            node: ptr::null_mut(),
+        }
+    }
+
+    /// Returns true iff the iterator is positioned at a valid node.
+    #[inline]
+    pub fn valid(&self) -> bool { !self.node.is_null() }
+
+    /// Returns the key at the current position.
+    /// REQUIRES: `valid()`
+    #[inline]
+    pub fn key(&self) -> &[u8] {
+        assert!(self.valid());
+        unsafe { Node::key(self.node) }
+    }
+
+    /// Advances to the next position.
+    /// REQUIRES: `valid()`
+    pub fn next(&mut self) {
+        assert!(self.valid());
+        self.node = unsafe { Node::next(self.node, 0) };
+    }
+
+    /// Advances to the previous position.
+    /// REQUIRES: `valid()`
+    pub fn prev(&mut self) {
+        // Instead of using explicit "prev" links, we just search for the last node that
+        // falls before key.
+        assert!(self.valid());
+        self.node = self.list.find_less_than(self.key());
+        if self.node == self.list.head {
+            self.node = ptr::null_mut();
+        }
+    }
+
+    /// Advance to the first entry with a key >= target
+    pub fn seek(&mut self, target: &[u8]) { self.node = self.list.find_greater_or_equal(target, None); }
+
+    /// Position at the first entry in list.
+    /// Final state of iterator is `valid()` iff list is not empty.
+    pub fn seek_to_first(&mut self) { self.node = unsafe { Node::next(self.list.head, 0) }; }
+
+    /// Position at the last entry in list.
+    /// Final state of iterator is `valid()` iff list is not empty.
+    pub fn seek_to_last(&mut self) {
+        self.node = self.list.find_last();
+        if self.node == self.list.head {
+            self.node = ptr::null_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::coding::{decode_fixed_64, encode_fixed_64};
+    use std::collections::BTreeSet;
+
+    struct TestComparator;
+
+    impl KeyComparator for TestComparator {
+        fn compare(&self, a: &[u8], b: &[u8]) -> Ordering { decode_fixed_64(a).cmp(&decode_fixed_64(b)) }
+    }
+
+    fn encode(k: u64) -> [u8; 8] {
+        let mut buf = [0u8; 8];
+        encode_fixed_64(&mut buf, k);
+        buf
+    }
+
+    #[test]
+    fn empty() {
+        let list = Arc::new(SkipList::new(TestComparator));
+        assert!(!list.contains(&encode(10)));
+
+        let mut iter = Iter::new(list);
+        assert!(!iter.valid());
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        iter.seek(&encode(100));
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn insert_and_lookup() {
+        const N: u32 = 2000;
+        const R: u32 = 5000;
+        let rnd = Random::new(1000);
+        let mut keys = BTreeSet::new();
+        let list = Arc::new(SkipList::new(TestComparator));
+        for _ in 0..N {
+            let key = (rnd.next() % R) as u64;
+            if keys.insert(key) {
+                list.insert(&encode(key));
+            }
+        }
+
+        for i in 0..R as u64 {
+            assert_eq!(list.contains(&encode(i)), keys.contains(&i));
+        }
+
+        // Simple iterator tests
+        {
+            let mut iter = Iter::new(list.clone());
+            assert!(!iter.valid());
+
+            iter.seek(&encode(0));
+            assert!(iter.valid());
+            assert_eq!(*keys.iter().next().unwrap(), decode_fixed_64(iter.key()));
+
+            iter.seek_to_first();
+            assert!(iter.valid());
+            assert_eq!(*keys.iter().next().unwrap(), decode_fixed_64(iter.key()));
+
+            iter.seek_to_last();
+            assert!(iter.valid());
+            assert_eq!(*keys.iter().next_back().unwrap(), decode_fixed_64(iter.key()));
+        }
+
+        // Forward iteration test
+        for i in 0..R as u64 {
+            let mut iter = Iter::new(list.clone());
+            iter.seek(&encode(i));
+
+            // Compare against model iterator
+            let mut model = keys.range(i..);
+            for _ in 0..3 {
+                match model.next() {
+                    None => {
+                        assert!(!iter.valid());
+                        break;
+                    }
+                    Some(k) => {
+                        assert!(iter.valid());
+                        assert_eq!(*k, decode_fixed_64(iter.key()));
+                        iter.next();
+                    }
+                }
+            }
+        }
+
+        // Backward iteration test
+        {
+            let mut iter = Iter::new(list.clone());
+            iter.seek_to_last();
+
+            // Compare against model iterator
+            for k in keys.iter().rev() {
+                assert!(iter.valid());
+                assert_eq!(*k, decode_fixed_64(iter.key()));
+                iter.prev();
+            }
+            assert!(!iter.valid());
+        }
+    }
+
+    #[test]
+    fn concurrent_readers() {
+        // One writer inserting increasing keys while readers scan; every reader must
+        // observe a sorted prefix of the inserted keys.
+        let list = Arc::new(SkipList::new(TestComparator));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let list = list.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..200 {
+                        let mut iter = Iter::new(list.clone());
+                        iter.seek_to_first();
+                        let mut expected = 0;
+                        while iter.valid() {
+                            assert_eq!(decode_fixed_64(iter.key()), expected);
+                            expected += 1;
+                            iter.next();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for i in 0..5000u64 {
+            list.insert(&encode(i));
+        }
+        for r in readers {
+            r.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        // Writers insert interleaved keys while readers scan; readers must always see
+        // sorted keys, and every key must be present in the end.
+        const WRITERS: u64 = 4;
+        const N: u64 = 5000;
+        let list = Arc::new(SkipList::new_concurrent(TestComparator));
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let list = list.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        let mut iter = Iter::new(list.clone());
+                        iter.seek_to_first();
+                        let mut last = None;
+                        while iter.valid() {
+                            let k = decode_fixed_64(iter.key());
+                            assert!(last < Some(k));
+                            last = Some(k);
+                            iter.next();
+                        }
+                    }
+                })
+            })
+            .collect();
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|w| {
+                let list = list.clone();
+                std::thread::spawn(move || {
+                    for i in 0..N {
+                        list.insert(&encode(i * WRITERS + w));
+                    }
+                })
+            })
+            .collect();
+        for t in writers.into_iter().chain(readers) {
+            t.join().unwrap();
+        }
+
+        let mut iter = Iter::new(list.clone());
+        iter.seek_to_first();
+        for k in 0..N * WRITERS {
+            assert!(iter.valid());
+            assert_eq!(decode_fixed_64(iter.key()), k);
+            iter.next();
+        }
+        assert!(!iter.valid());
+        iter.seek(&encode(777));
+        assert_eq!(decode_fixed_64(iter.key()), 777);
+        iter.prev();
+        assert_eq!(decode_fixed_64(iter.key()), 776);
+        assert!(list.contains(&encode(N * WRITERS - 1)));
+        assert!(list.memory_usage() > 0);
+        drop(iter);
+        assert!(Arc::try_unwrap(list).ok().unwrap().into_arena().is_none());
+    }
+}
@@ -0,0 +1,116 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::db::dbformat::SequenceNumber;
+
+/// The set of sequence numbers pinned by live snapshots of a DB. A sequence number may
+/// be pinned by several snapshots at once, so the list keeps a count per number.
+#[derive(Default)]
+pub struct SnapshotList {
+    live: Mutex<BTreeMap<SequenceNumber, usize>>,
+}
+
+impl SnapshotList {
+    pub fn new() -> Arc<Self> { Arc::new(Self::default()) }
+
+    pub fn is_empty(&self) -> bool { self.live.lock().unwrap().is_empty() }
+
+    /// The sequence number of the oldest live snapshot, if any.
+    pub fn oldest(&self) -> Option<SequenceNumber> { self.live.lock().unwrap().keys().next().copied() }
+
+    /// The sequence number of the newest live snapshot, if any.
+    pub fn newest(&self) -> Option<SequenceNumber> {
+        self.live.lock().unwrap().keys().next_back().copied()
+    }
+
+    /// Pin `sequence` until the returned snapshot (and all of its clones) are dropped.
+    pub fn new_snapshot(self: &Arc<Self>, sequence: SequenceNumber) -> Snapshot {
+        *self.live.lock().unwrap().entry(sequence).or_insert(0) += 1;
+        Snapshot {
+            inner: Arc::new(SnapshotImpl {
+                sequence,
+                list: self.clone(),
+            }),
+        }
+    }
+
+    fn release(&self, sequence: SequenceNumber) {
+        let mut live = self.live.lock().unwrap();
+        if let Some(count) = live.get_mut(&sequence) {
+            *count -= 1;
+            if *count == 0 {
+                live.remove(&sequence);
+            }
+        }
+    }
+}
+
+struct SnapshotImpl {
+    sequence: SequenceNumber,
+    list: Arc<SnapshotList>,
+}
+
+impl Drop for SnapshotImpl {
+    fn drop(&mut self) { self.list.release(self.sequence) }
+}
+
+/// Abstract handle to particular state of a DB. A Snapshot is an immutable object and
+/// can therefore be safely accessed from multiple threads without any external
+/// synchronization. The snapshot is released once the last clone of it is dropped.
+#[derive(Clone)]
+pub struct Snapshot {
+    inner: Arc<SnapshotImpl>,
+}
+
+impl Snapshot {
+    /// The sequence number this snapshot reads at.
+    pub fn sequence_number(&self) -> SequenceNumber { self.inner.sequence }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_on_drop() {
+        let list = SnapshotList::new();
+        assert!(list.is_empty());
+        let s1 = list.new_snapshot(10);
+        let s2 = list.new_snapshot(20);
+        let s3 = s1.clone();
+        let s4 = list.new_snapshot(10);
+        assert_eq!(list.oldest(), Some(10));
+        assert_eq!(list.newest(), Some(20));
+        drop(s1);
+        drop(s4);
+        assert_eq!(list.oldest(), Some(10));
+        drop(s3);
+        assert_eq!(list.oldest(), Some(20));
+        assert_eq!(s2.sequence_number(), 20);
+        drop(s2);
+        assert!(list.is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::{
+    db::{
+        db_impl::sanitize_options,
+        dbformat::{append_internal_key, InternalKeyComparator, ParsedInternalKey, ValueType},
+    },
+    leveldb::{
+        comparator::Comparator,
+        options::Options,
+        result::{Error, ErrorType, Result},
+    },
+    table::table_builder::TableBuilder,
+};
+
+/// Description of a file written by `SstFileWriter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalSstFileInfo {
+    pub file_path: String,
+    /// Smallest and largest user key in the file.
+    pub smallest_key: Vec<u8>,
+    pub largest_key: Vec<u8>,
+    pub num_entries: u64,
+    pub file_size: u64,
+}
+
+/// SstFileWriter writes a standalone table file outside of any database, e.g. from an
+/// offline ETL job, in the same format as the tables of a database. The file can later
+/// be added to a database with `DB::ingest_external_file()`.
+///
+/// Entries are stored with sequence number zero; ingestion decides which sequence
+/// number they get in the database.
+pub struct SstFileWriter {
+    // Options with the comparator and filter policy switched to their internal-key
+    // counterparts, as for the tables of a database.
+    options: Options,
+    user_comparator: Arc<dyn Comparator>,
+    builder: Option<TableBuilder>,
+    file_path: String,
+    smallest_key: Vec<u8>,
+    last_key: Option<Vec<u8>>,
+    ikey: Vec<u8>,
+}
+
+impl SstFileWriter {
+    /// Create a writer for tables of a database opened with `options`. The comparator,
+    /// filter policy, prefix extractor, block size and compression are taken from it.
+    pub fn new(options: &Options) -> Self {
+        let icmp = InternalKeyComparator::new(options.comparator.clone());
+        Self {
+            options: sanitize_options(&icmp, options),
+            user_comparator: options.comparator.clone(),
+            builder: None,
+            file_path: String::new(),
+            smallest_key: Vec::new(),
+            last_key: None,
+            ikey: Vec::new(),
+        }
+    }
+
+    /// Start writing a new file at `path`, replacing any existing file. A file that is
+    /// still open is abandoned.
+    pub fn open(&mut self, path: &str) -> Result<()> {
+        self.abandon();
+        let file = self.options.env.new_writable_file(path)?;
+        // External files do not belong to a level; use the compression of level 0.
+        let mut options = self.options.clone();
+        options.compression = options.compression_for_level(0);
+        self.builder = Some(TableBuilder::new(&options, file));
+        self.file_path = path.to_string();
+        self.smallest_key.clear();
+        self.last_key = None;
+        Ok(())
+    }
+
+    /// Add a mapping for `key`.
+    ///
+    /// REQUIRES: `key` is after every key added since `open()` in the order of the
+    /// comparator
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> { self.add(key, ValueType::Value, value) }
+
+    /// Add a deletion of `key`, which hides older values of `key` once the file is
+    /// ingested.
+    ///
+    /// REQUIRES: `key` is after every key added since `open()` in the order of the
+    /// comparator
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> { self.add(key, ValueType::Deletion, &[]) }
+
+    fn add(&mut self, key: &[u8], value_type: ValueType, value: &[u8]) -> Result<()> {
+        let Some(builder) = self.builder.as_mut() else {
+            return LEVELDB_ERR!(InvalidArgument, "no file is open");
+        };
+        if let Some(last) = self.last_key.as_ref() {
+            if self.user_comparator.compare(key, last) != Ordering::Greater {
+                return LEVELDB_ERR!(InvalidArgument, "keys must be added in strictly increasing order");
+            }
+        }
+        if key.len() > self.options.max_key_size || value.len() > self.options.max_value_size {
+            return Err(Error::new(ErrorType::InvalidArgument, "entry too large")
+                .with_detail(format!("key is {} bytes, value is {} bytes", key.len(), value.len())));
+        }
+        self.ikey.clear();
+        append_internal_key(&mut self.ikey, &ParsedInternalKey::new(key, 0, value_type));
+        builder.add(&self.ikey, value);
+        builder.status()?;
+        if self.last_key.is_none() {
+            self.smallest_key = key.to_vec();
+        }
+        self.last_key = Some(key.to_vec());
+        Ok(())
+    }
+
+    /// Finish the open file, sync it and return its description. Fails if no entry
+    /// was added, since an empty table cannot be ingested.
+    pub fn finish(&mut self) -> Result<ExternalSstFileInfo> {
+        let Some(mut builder) = self.builder.take() else {
+            return LEVELDB_ERR!(InvalidArgument, "no file is open");
+        };
+        let Some(largest_key) = self.last_key.take() else {
+            builder.abandon();
+            let _ = self.options.env.remove_file(&self.file_path);
+            return LEVELDB_ERR!(InvalidArgument, "cannot create an empty sst file");
+        };
+        let mut s = builder.finish();
+        let num_entries = builder.num_entries();
+        let file_size = builder.file_size();
+        let mut file = builder.into_file();
+        if s.is_ok() {
+            s = file.sync();
+        }
+        if s.is_ok() {
+            s = file.close();
+        }
+        if let Err(e) = s {
+            let _ = self.options.env.remove_file(&self.file_path);
+            return Err(e);
+        }
+        Ok(ExternalSstFileInfo {
+            file_path: std::mem::take(&mut self.file_path),
+            smallest_key: std::mem::take(&mut self.smallest_key),
+            largest_key,
+            num_entries,
+            file_size,
+        })
+    }
+
+    /// Stop writing the open file, if any, and delete it.
+    pub fn abandon(&mut self) {
+        if let Some(mut builder) = self.builder.take() {
+            builder.abandon();
+            let _ = self.options.env.remove_file(&self.file_path);
+        }
+    }
+}
+
+impl Drop for SstFileWriter {
+    fn drop(&mut self) { self.abandon() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::dbformat::parse_internal_key,
+        leveldb::options::ReadOptions,
+        table::table::Table,
+        util::{bloom::new_bloom_filter_policy, testutil::tmp_dir},
+    };
+
+    #[test]
+    fn write_and_read() {
+        let dir = tmp_dir("sst_file_writer");
+        let path = format!("{}/external.sst", dir);
+        let options = Options {
+            filter_policy: Some(new_bloom_filter_policy(10)),
+            ..Options::default()
+        };
+        let mut writer = SstFileWriter::new(&options);
+        assert!(writer.put(b"a", b"1").is_err());
+        writer.open(&path).unwrap();
+        for i in 0..100 {
+            writer.put(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        writer.delete(b"key100").unwrap();
+        assert_eq!(writer.put(b"key050", b"x").unwrap_err().ty(), ErrorType::InvalidArgument);
+        let info = writer.finish().unwrap();
+        assert_eq!(info.smallest_key, b"key000");
+        assert_eq!(info.largest_key, b"key100");
+        assert_eq!(info.num_entries, 101);
+        assert_eq!(info.file_size, options.env.get_file_size(&path).unwrap());
+
+        let file = options.env.new_random_access_file(&path).unwrap();
+        let table = Table::open(&writer.options, &path, file, info.file_size).unwrap();
+        let mut iter = table.new_iterator(&ReadOptions::default());
+        iter.seek_to_first();
+        let mut n = 0;
+        while iter.valid() {
+            let ikey = parse_internal_key(iter.key()).unwrap();
+            assert_eq!(ikey.sequence, 0);
+            n += 1;
+            iter.next();
+        }
+        assert_eq!(n, 101);
+
+        // An empty file is not kept.
+        writer.open(&path).unwrap();
+        assert!(writer.finish().is_err());
+        assert!(!options.env.file_exists(&path));
+    }
+}
@@ -0,0 +1,117 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Thread-safe (provides internal synchronization)
+
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    db::filename::table_file_name,
+    leveldb::{
+        iterator::{new_error_iterator, Iterator},
+        options::{Options, ReadOptions},
+        pinnable_slice::PinnableSlice,
+        result::Result,
+    },
+    table::table::{KeyValue, Table},
+    util::{
+        cache::{Cache, CacheKey},
+        coding::encode_fixed_64,
+    },
+};
+
+fn cache_key(file_number: u64) -> CacheKey {
+    let mut key: CacheKey = [0; 16];
+    encode_fixed_64(&mut key[..8], file_number);
+    key
+}
+
+pub struct TableCache {
+    dbname: String,
+    options: Options,
+    cache: Mutex<Cache<Arc<Table>>>,
+}
+
+impl TableCache {
+    pub fn new(dbname: &str, options: &Options, entries: usize) -> Self {
+        Self {
+            dbname: dbname.to_string(),
+            options: options.clone(),
+            cache: Mutex::new(Cache::new(entries)),
+        }
+    }
+
+    /// Return the table for the specified file number (the corresponding file length
+    /// must be exactly `file_size` bytes), opening it if it is not already cached.
+    pub fn find_table(&self, file_number: u64, file_size: u64) -> Result<Arc<Table>> {
+        let key = cache_key(file_number);
+        if let Some(table) = self.cache.lock().unwrap().get(&key) {
+            return Ok(table.clone());
+        }
+
+        let fname = table_file_name(&self.dbname, file_number);
+        let file = self.options.env.new_random_access_file(&fname)?;
+        // We do not cache error results so that if the error is transient, or somebody
+        // repairs the file, we recover automatically.
+        let table = Table::open(&self.options, &fname, file, file_size)?;
+        // Tables are charged one each: the cache bounds the number of open files. An
+        // evicted table closes its file once the last iterator over it is dropped.
+        let mut cache = self.cache.lock().unwrap();
+        let handle = cache.insert(&key, table.clone(), 1);
+        cache.release(handle);
+        Ok(table)
+    }
+
+    /// Return an iterator for the specified file number (the corresponding file length
+    /// must be exactly `file_size` bytes). The iterator keeps the table open for as long
+    /// as it is alive.
+    pub fn new_iterator(&self, options: &ReadOptions, file_number: u64, file_size: u64) -> Box<dyn Iterator> {
+        match self.find_table(file_number, file_size) {
+            Ok(table) => table.new_iterator(options),
+            Err(e) => new_error_iterator(e),
+        }
+    }
+
+    /// Seek to the first entry at or after internal key `k` in the specified file and
+    /// return it, if there is one. The value keeps its data block pinned.
+    pub fn get(&self, options: &ReadOptions, file_number: u64, file_size: u64, k: &[u8]) -> Result<Option<(Vec<u8>, PinnableSlice)>> {
+        let table = self.find_table(file_number, file_size)?;
+        table.internal_get_pinned(options, k)
+    }
+
+    /// Batched form of `get()` for internal keys `keys`, which must be sorted.
+    pub fn multi_get(&self, options: &ReadOptions, file_number: u64, file_size: u64, keys: &[&[u8]]) -> Result<Vec<Option<KeyValue>>> {
+        let table = self.find_table(file_number, file_size)?;
+        table.internal_multi_get(options, keys)
+    }
+
+    /// Number of tables held open by the cache.
+    pub fn num_open_tables(&self) -> usize { self.cache.lock().unwrap().count() }
+
+    /// Evict any entry for the specified file number, along with its blocks in the
+    /// block cache.
+    pub fn evict(&self, file_number: u64) {
+        let table = self.cache.lock().unwrap().remove(&cache_key(file_number));
+        if let Some(table) = table {
+            table.evict_blocks();
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    db::{dbformat::SequenceNumber, DB},
+    leveldb::{iterator::Iterator, options::ReadOptions, result::Result},
+};
+
+/// An iterator that is not bound to a snapshot: every seek observes the writes
+/// committed up to that point. Consumers that follow the tail of the keyspace re-seek
+/// once the iterator runs out. A seek rebuilds the underlying iterator only if
+/// something was written since the last one.
+pub struct TailingIterator<'a> {
+    db: &'a DB,
+    options: ReadOptions,
+    // Last sequence number visible to `iter`
+    sequence: SequenceNumber,
+    iter: Box<dyn Iterator>,
+}
+
+impl<'a> TailingIterator<'a> {
+    pub(crate) fn new(db: &'a DB, options: &ReadOptions) -> Self {
+        let options = ReadOptions {
+            snapshot: None,
+            ..options.clone()
+        };
+        let sequence = db.latest_sequence_number();
+        let iter = db.new_iterator(&options);
+        Self {
+            db,
+            options,
+            sequence,
+            iter,
+        }
+    }
+
+    fn refresh(&mut self) {
+        let sequence = self.db.latest_sequence_number();
+        if sequence != self.sequence {
+            self.iter = self.db.new_iterator(&self.options);
+            self.sequence = sequence;
+        }
+    }
+}
+
+impl Iterator for TailingIterator<'_> {
+    fn valid(&self) -> bool { self.iter.valid() }
+
+    fn seek_to_first(&mut self) {
+        self.refresh();
+        self.iter.seek_to_first()
+    }
+
+    fn seek_to_last(&mut self) {
+        self.refresh();
+        self.iter.seek_to_last()
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.refresh();
+        self.iter.seek(target)
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        self.refresh();
+        self.iter.seek_for_prev(target)
+    }
+
+    fn next(&mut self) { self.iter.next() }
+
+    fn prev(&mut self) { self.iter.prev() }
+
+    fn key(&self) -> &[u8] { self.iter.key() }
+
+    fn value(&self) -> &[u8] { self.iter.value() }
+
+    fn status(&self) -> Result<()> { self.iter.status() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::KeyRange,
+        leveldb::options::{Options, WriteOptions},
+        util::testutil::tmp_dir,
+    };
+
+    #[test]
+    fn follows_writes() {
+        let dir = tmp_dir("tailing_iter");
+        let options = Options {
+            create_if_missing: true,
+            ..Options::default()
+        };
+        let db = DB::open(options, &dir).unwrap();
+        let wo = WriteOptions::default();
+        db.put(&wo, b"0001", b"a").unwrap();
+
+        let mut iter = db.new_tailing_iterator(&ReadOptions::default());
+        iter.seek(b"0001");
+        assert!(iter.valid());
+        iter.next();
+        assert!(!iter.valid());
+
+        // New writes show up after a seek, even across a flush.
+        db.put(&wo, b"0002", b"b").unwrap();
+        db.compact_range(&KeyRange::all()).unwrap();
+        db.put(&wo, b"0003", b"c").unwrap();
+        iter.seek(b"0002");
+        assert_eq!(iter.key(), b"0002");
+        let old = iter.sequence;
+        iter.next();
+        assert_eq!(iter.value(), b"c");
+
+        // Without new writes, the iterator is reused.
+        iter.seek(b"0001");
+        assert_eq!(iter.sequence, old);
+        assert_eq!(iter.key(), b"0001");
+    }
+}
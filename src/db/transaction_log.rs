@@ -0,0 +1,197 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    db::{
+        dbformat::SequenceNumber,
+        filename::{archived_log_file_name, log_file_name},
+        log_reader,
+        write_batch::WriteBatch,
+    },
+    leveldb::{
+        env::Env,
+        result::{Error, ErrorType, Result},
+    },
+};
+
+/// Remembers the first corruption found while reading a log.
+struct Reporter {
+    status: Arc<Mutex<Option<Error>>>,
+}
+
+impl log_reader::Reporter for Reporter {
+    fn corruption(&mut self, _bytes: usize, reason: &Error) {
+        self.status.lock().unwrap().get_or_insert_with(|| reason.clone());
+    }
+}
+
+/// An iterator over the write batches recorded in the logs of a database, in sequence
+/// number order, as returned by `DB::get_updates_since()`. Each item is a batch whose
+/// `sequence()` is that of its first update.
+///
+/// The iterator ends at the end of the newest log. Batches written after that are not
+/// returned; call `DB::get_updates_since()` again to resume from the sequence number
+/// following the last batch seen.
+pub struct TransactionLogIterator {
+    env: Arc<dyn Env>,
+    dbname: String,
+    // Numbers of the logs still to read, oldest first
+    logs: VecDeque<u64>,
+    reader: Option<log_reader::Reader>,
+    status: Arc<Mutex<Option<Error>>>,
+    // Batches that end before this sequence number are skipped.
+    start: SequenceNumber,
+    done: bool,
+}
+
+impl TransactionLogIterator {
+    /// Return an iterator over the batches of `logs` that hold updates with sequence
+    /// numbers at or after `start`. `last_sequence` is the last sequence number used
+    /// by the database; if an update between `start` and it is no longer in any of
+    /// the logs, fails with a `NotFound` error.
+    pub(crate) fn new(
+        env: Arc<dyn Env>,
+        dbname: &str,
+        mut logs: Vec<u64>,
+        start: SequenceNumber,
+        last_sequence: SequenceNumber,
+    ) -> Result<Self> {
+        // No update has sequence number zero.
+        let start = start.max(1);
+        logs.sort_unstable();
+        let mut iter = Self {
+            env,
+            dbname: dbname.to_string(),
+            logs: VecDeque::new(),
+            reader: None,
+            status: Arc::new(Mutex::new(None)),
+            start,
+            done: false,
+        };
+
+        // Skip the logs that only hold updates before `start`: those followed by a log
+        // that starts at or before it.
+        let mut first_sequences = Vec::new();
+        for &number in logs.iter() {
+            if let Some(sequence) = iter.first_sequence(number)? {
+                first_sequences.push((number, sequence));
+            }
+        }
+        let begin = first_sequences
+            .iter()
+            .rposition(|&(_, sequence)| sequence <= start)
+            .unwrap_or(0);
+        let oldest = first_sequences.get(begin).map(|&(_, sequence)| sequence);
+        if start <= last_sequence && oldest.is_none_or(|oldest| oldest > start) {
+            return Err(Error::new(ErrorType::NotFound, "updates are no longer in the logs")
+                .with_detail(format!("sequence {}", start)));
+        }
+        if let Some(&(number, _)) = first_sequences.get(begin) {
+            iter.logs = logs.into_iter().filter(|&n| n >= number).collect();
+        }
+        Ok(iter)
+    }
+
+    /// Open log `number`, which may have been archived since it was listed.
+    fn open_log(&self, number: u64) -> Result<log_reader::Reader> {
+        let file = match self.env.new_sequential_file(&log_file_name(&self.dbname, number)) {
+            Ok(file) => file,
+            Err(e) if e.is_not_found() => self
+                .env
+                .new_sequential_file(&archived_log_file_name(&self.dbname, number))?,
+            Err(e) => return Err(e),
+        };
+        let reporter = Reporter {
+            status: self.status.clone(),
+        };
+        Ok(log_reader::Reader::new(file, Some(Box::new(reporter)), true))
+    }
+
+    /// Return the sequence number of the first batch of log `number`, if it has any.
+    fn first_sequence(&self, number: u64) -> Result<Option<SequenceNumber>> {
+        let mut reader = self.open_log(number)?;
+        let mut record = Vec::new();
+        while reader.read_record(&mut record) {
+            if record.len() >= 12 {
+                let mut batch = WriteBatch::new();
+                batch.set_contents(&record);
+                return Ok(Some(batch.sequence()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn take_status(&self) -> Result<()> {
+        match self.status.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::iter::Iterator for TransactionLogIterator {
+    type Item = Result<WriteBatch>;
+
+    fn next(&mut self) -> Option<Result<WriteBatch>> {
+        if self.done {
+            return None;
+        }
+        let mut record = Vec::new();
+        loop {
+            let reader = match self.reader.as_mut() {
+                Some(reader) => reader,
+                None => {
+                    let number = self.logs.pop_front()?;
+                    match self.open_log(number) {
+                        Ok(reader) => self.reader.insert(reader),
+                        Err(e) => {
+                            self.done = true;
+                            return Some(Err(e));
+                        }
+                    }
+                }
+            };
+            let found = reader.read_record(&mut record);
+            if let Err(e) = self.take_status() {
+                self.done = true;
+                return Some(Err(e));
+            }
+            if !found {
+                self.reader = None;
+                continue;
+            }
+            if record.len() < 12 {
+                self.done = true;
+                return Some(Err(Error::new(ErrorType::Corruption, "log record too small")));
+            }
+            let mut batch = WriteBatch::new();
+            batch.set_contents(&record);
+            if batch.sequence() + batch.count() as SequenceNumber > self.start {
+                return Some(Ok(batch));
+            }
+        }
+    }
+}
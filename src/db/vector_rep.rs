@@ -0,0 +1,276 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// A write buffer representation that appends entries to a vector and only sorts them
+// when they are read. Inserts are cheaper than with a `SkipList`, but every read that
+// follows a batch of inserts has to sort them first, so it suits workloads that write
+// a lot and read the buffer rarely, such as bulk loads.
+//
+// Thread safety
+// -------------
+//
+// Inserts and the sorting done on behalf of readers take turns on an internal mutex.
+// Readers iterate over a sorted snapshot that they share with the representation
+// (through an `Arc`), so entries added after an iterator was created are not visible
+// to it. Entries are never removed and never move in memory, so a key stays valid for
+// as long as the `VectorRep` is alive.
+
+use std::{
+    cmp::Ordering,
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc, Mutex,
+    },
+};
+
+use crate::db::skiplist::KeyComparator;
+
+type Entry = Arc<[u8]>;
+
+#[derive(Default)]
+struct Entries {
+    // Entries added since the last sort, in insertion order.
+    unsorted: Vec<Entry>,
+    // All other entries, in key order. Shared with the iterators created since.
+    sorted: Arc<Vec<Entry>>,
+}
+
+pub struct VectorRep<C: KeyComparator> {
+    compare: C,
+    entries: Mutex<Entries>,
+    memory_usage: AtomicUsize,
+}
+
+impl<C: KeyComparator> VectorRep<C> {
+    /// Create a new, empty representation that will use `cmp` for comparing keys.
+    pub fn new(cmp: C) -> Self {
+        Self {
+            compare: cmp,
+            entries: Mutex::new(Entries::default()),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    /// Insert a copy of `key`.
+    /// REQUIRES: nothing that compares equal to key is currently stored.
+    pub fn insert(&self, key: &[u8]) { self.insert_with(key.len(), |buf| buf.copy_from_slice(key)) }
+
+    /// Allocate `len` bytes, let `encode` fill them in and insert the result as a key.
+    /// `encode` must write all `len` bytes. Several threads may insert at the same time.
+    /// REQUIRES: nothing that compares equal to the encoded key is currently stored.
+    pub fn insert_with<F: FnOnce(&mut [u8])>(&self, len: usize, encode: F) {
+        let mut buf = vec![0u8; len];
+        encode(&mut buf);
+        let entry: Entry = buf.into();
+        self.memory_usage.fetch_add(len + mem::size_of::<Entry>() + 2 * mem::size_of::<usize>(), AtomicOrdering::Relaxed);
+        self.entries.lock().unwrap().unsorted.push(entry);
+    }
+
+    /// Return the comparator used to order the keys.
+    pub fn comparator(&self) -> &C { &self.compare }
+
+    /// Returns true iff nothing has been inserted.
+    pub fn is_empty(&self) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries.unsorted.is_empty() && entries.sorted.is_empty()
+    }
+
+    /// Return the number of bytes taken by the entries and their bookkeeping.
+    pub fn memory_usage(&self) -> usize { self.memory_usage.load(AtomicOrdering::Relaxed) }
+
+    /// Return all the entries in key order, sorting the ones inserted since the last call.
+    fn sorted(&self) -> Arc<Vec<Entry>> {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.unsorted.is_empty() {
+            let mut unsorted = mem::take(&mut entries.unsorted);
+            unsorted.sort_unstable_by(|a, b| self.compare.compare(a, b));
+            let sorted = &entries.sorted;
+            let mut merged = Vec::with_capacity(sorted.len() + unsorted.len());
+            let (mut i, mut j) = (0, 0);
+            while i < sorted.len() && j < unsorted.len() {
+                if self.compare.compare(&sorted[i], &unsorted[j]) == Ordering::Less {
+                    merged.push(sorted[i].clone());
+                    i += 1;
+                } else {
+                    merged.push(unsorted[j].clone());
+                    j += 1;
+                }
+            }
+            merged.extend_from_slice(&sorted[i..]);
+            merged.extend_from_slice(&unsorted[j..]);
+            entries.sorted = Arc::new(merged);
+        }
+        entries.sorted.clone()
+    }
+}
+
+/// Iteration over the entries of a vector representation, as they were when the
+/// iterator was created.
+pub struct Iter<C: KeyComparator> {
+    rep: Arc<VectorRep<C>>,
+    entries: Arc<Vec<Entry>>,
+    // entries.len() when not positioned at an entry.
+    pos: usize,
+}
+
+impl<C: KeyComparator> Iter<C> {
+    /// Initialize an iterator over the entries of `rep`, sorting them if needed.
+    /// The returned iterator is not valid.
+    pub fn new(rep: Arc<VectorRep<C>>) -> Self {
+        let entries = rep.sorted();
+        let pos = entries.len();
+        Self { rep, entries, pos }
+    }
+
+    /// Returns true iff the iterator is positioned at a valid entry.
+    #[inline]
+    pub fn valid(&self) -> bool { self.pos < self.entries.len() }
+
+    /// Returns the key at the current position.
+    /// REQUIRES: `valid()`
+    #[inline]
+    pub fn key(&self) -> &[u8] { &self.entries[self.pos] }
+
+    /// Advances to the next position.
+    /// REQUIRES: `valid()`
+    pub fn next(&mut self) {
+        assert!(self.valid());
+        self.pos += 1;
+    }
+
+    /// Advances to the previous position.
+    /// REQUIRES: `valid()`
+    pub fn prev(&mut self) {
+        assert!(self.valid());
+        self.pos = self.pos.checked_sub(1).unwrap_or(self.entries.len());
+    }
+
+    /// Advance to the first entry with a key >= target
+    pub fn seek(&mut self, target: &[u8]) {
+        let compare = self.rep.comparator();
+        self.pos = self.entries.partition_point(|e| compare.compare(e, target) == Ordering::Less);
+    }
+
+    /// Position at the first entry.
+    /// Final state of iterator is `valid()` iff there are entries.
+    pub fn seek_to_first(&mut self) { self.pos = 0; }
+
+    /// Position at the last entry.
+    /// Final state of iterator is `valid()` iff there are entries.
+    pub fn seek_to_last(&mut self) { self.pos = self.entries.len().saturating_sub(1); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::coding::{decode_fixed_64, encode_fixed_64};
+    use std::collections::BTreeSet;
+
+    struct TestComparator;
+
+    impl KeyComparator for TestComparator {
+        fn compare(&self, a: &[u8], b: &[u8]) -> Ordering { decode_fixed_64(a).cmp(&decode_fixed_64(b)) }
+    }
+
+    fn encode(k: u64) -> [u8; 8] {
+        let mut buf = [0u8; 8];
+        encode_fixed_64(&mut buf, k);
+        buf
+    }
+
+    fn keys(iter: &mut Iter<TestComparator>) -> Vec<u64> {
+        let mut keys = Vec::new();
+        iter.seek_to_first();
+        while iter.valid() {
+            keys.push(decode_fixed_64(iter.key()));
+            iter.next();
+        }
+        keys
+    }
+
+    #[test]
+    fn empty() {
+        let rep = Arc::new(VectorRep::new(TestComparator));
+        assert!(rep.is_empty());
+        let mut iter = Iter::new(rep);
+        assert!(!iter.valid());
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        iter.seek(&encode(100));
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn insert_and_iterate() {
+        let rep = Arc::new(VectorRep::new(TestComparator));
+        let mut expected = BTreeSet::new();
+        for k in [50u64, 10, 40, 20, 30] {
+            rep.insert(&encode(k));
+            expected.insert(k);
+        }
+        assert!(!rep.is_empty());
+        assert!(rep.memory_usage() >= 5 * 8);
+        let mut before = Iter::new(rep.clone());
+
+        // Entries inserted after a sort are merged into the sorted ones.
+        for k in [45u64, 5, 25] {
+            rep.insert(&encode(k));
+            expected.insert(k);
+        }
+        let mut iter = Iter::new(rep.clone());
+        assert_eq!(keys(&mut iter), expected.iter().copied().collect::<Vec<_>>());
+        // Older iterators keep the entries they started with.
+        assert_eq!(keys(&mut before), vec![10, 20, 30, 40, 50]);
+
+        iter.seek(&encode(26));
+        assert_eq!(decode_fixed_64(iter.key()), 30);
+        iter.prev();
+        assert_eq!(decode_fixed_64(iter.key()), 25);
+        iter.seek(&encode(51));
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        assert_eq!(decode_fixed_64(iter.key()), 50);
+        iter.seek_to_first();
+        assert_eq!(decode_fixed_64(iter.key()), 5);
+        iter.prev();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn concurrent_insert() {
+        let rep = VectorRep::new(TestComparator);
+        std::thread::scope(|s| {
+            for t in 0..4u64 {
+                let rep = &rep;
+                s.spawn(move || {
+                    for i in 0..100u64 {
+                        rep.insert(&encode(i * 4 + t));
+                    }
+                });
+            }
+        });
+        let mut iter = Iter::new(Arc::new(rep));
+        assert_eq!(keys(&mut iter), (0..400).collect::<Vec<_>>());
+    }
+}
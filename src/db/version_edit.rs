@@ -0,0 +1,369 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    db::dbformat::{config, InternalKey, SequenceNumber},
+    leveldb::{
+        result::{Error, ErrorType, Result},
+        slice::BorrowedSlice,
+    },
+    util::coding::{encode_length_prefixed_slice, encode_varint_32_vec, encode_varint_64_vec},
+};
+
+// Tag numbers for serialized VersionEdit. These numbers are written to disk and should
+// not be changed.
+const COMPARATOR: u32 = 1;
+const LOG_NUMBER: u32 = 2;
+const NEXT_FILE_NUMBER: u32 = 3;
+const LAST_SEQUENCE: u32 = 4;
+const COMPACT_POINTER: u32 = 5;
+const DELETED_FILE: u32 = 6;
+const NEW_FILE: u32 = 7;
+// 8 was used for large value refs
+const PREV_LOG_NUMBER: u32 = 9;
+// A new file followed by its creation time
+const NEW_FILE_WITH_TIME: u32 = 10;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileMetaData {
+    pub number: u64,
+    // File size in bytes
+    pub file_size: u64,
+    // Smallest internal key served by table
+    pub smallest: InternalKey,
+    // Largest internal key served by table
+    pub largest: InternalKey,
+    // Seconds since the epoch at which the table was written, or 0 if unknown
+    pub creation_time: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionEdit {
+    pub(crate) comparator: Option<String>,
+    pub(crate) log_number: Option<u64>,
+    pub(crate) prev_log_number: Option<u64>,
+    pub(crate) next_file_number: Option<u64>,
+    pub(crate) last_sequence: Option<SequenceNumber>,
+
+    pub(crate) compact_pointers: Vec<(usize, InternalKey)>,
+    pub(crate) deleted_files: BTreeSet<(usize, u64)>,
+    pub(crate) new_files: Vec<(usize, FileMetaData)>,
+}
+
+impl VersionEdit {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn clear(&mut self) { *self = Self::default(); }
+
+    pub fn set_comparator_name(&mut self, name: &str) { self.comparator = Some(name.to_string()); }
+
+    pub fn set_log_number(&mut self, num: u64) { self.log_number = Some(num); }
+
+    pub fn set_prev_log_number(&mut self, num: u64) { self.prev_log_number = Some(num); }
+
+    pub fn set_next_file(&mut self, num: u64) { self.next_file_number = Some(num); }
+
+    pub fn set_last_sequence(&mut self, seq: SequenceNumber) { self.last_sequence = Some(seq); }
+
+    pub fn set_compact_pointer(&mut self, level: usize, key: &InternalKey) {
+        self.compact_pointers.push((level, key.clone()));
+    }
+
+    /// Add the specified file at the specified number.
+    ///
+    /// REQUIRES: This version has not been saved (see `VersionSet::save_to`)
+    /// REQUIRES: `smallest` and `largest` are smallest and largest keys in file
+    pub fn add_file(&mut self, level: usize, file: u64, file_size: u64, smallest: &InternalKey, largest: &InternalKey) {
+        self.new_files.push((
+            level,
+            FileMetaData {
+                number: file,
+                file_size,
+                smallest: smallest.clone(),
+                largest: largest.clone(),
+                creation_time: 0,
+            },
+        ));
+    }
+
+    /// Add the file described by `f` at the specified level, keeping all of its
+    /// metadata such as its creation time.
+    pub fn add_file_meta(&mut self, level: usize, f: &FileMetaData) { self.new_files.push((level, f.clone())); }
+
+    /// Delete the specified "file" from the specified "level".
+    pub fn remove_file(&mut self, level: usize, file: u64) { self.deleted_files.insert((level, file)); }
+
+    pub fn encode_to(&self, dst: &mut Vec<u8>) {
+        if let Some(comparator) = self.comparator.as_ref() {
+            encode_varint_32_vec(dst, COMPARATOR);
+            encode_length_prefixed_slice(dst, comparator.as_bytes());
+        }
+        if let Some(log_number) = self.log_number {
+            encode_varint_32_vec(dst, LOG_NUMBER);
+            encode_varint_64_vec(dst, log_number);
+        }
+        if let Some(prev_log_number) = self.prev_log_number {
+            encode_varint_32_vec(dst, PREV_LOG_NUMBER);
+            encode_varint_64_vec(dst, prev_log_number);
+        }
+        if let Some(next_file_number) = self.next_file_number {
+            encode_varint_32_vec(dst, NEXT_FILE_NUMBER);
+            encode_varint_64_vec(dst, next_file_number);
+        }
+        if let Some(last_sequence) = self.last_sequence {
+            encode_varint_32_vec(dst, LAST_SEQUENCE);
+            encode_varint_64_vec(dst, last_sequence);
+        }
+
+        for (level, key) in self.compact_pointers.iter() {
+            encode_varint_32_vec(dst, COMPACT_POINTER);
+            encode_varint_32_vec(dst, *level as u32); // level
+            encode_length_prefixed_slice(dst, key.encode());
+        }
+
+        for (level, number) in self.deleted_files.iter() {
+            encode_varint_32_vec(dst, DELETED_FILE);
+            encode_varint_32_vec(dst, *level as u32); // level
+            encode_varint_64_vec(dst, *number);
+        }
+
+        for (level, f) in self.new_files.iter() {
+            // Files without a creation time keep the original encoding.
+            encode_varint_32_vec(dst, if f.creation_time == 0 { NEW_FILE } else { NEW_FILE_WITH_TIME });
+            encode_varint_32_vec(dst, *level as u32); // level
+            encode_varint_64_vec(dst, f.number);
+            encode_varint_64_vec(dst, f.file_size);
+            encode_length_prefixed_slice(dst, f.smallest.encode());
+            encode_length_prefixed_slice(dst, f.largest.encode());
+            if f.creation_time != 0 {
+                encode_varint_64_vec(dst, f.creation_time);
+            }
+        }
+    }
+
+    pub fn decode_from(src: &[u8]) -> Result<VersionEdit> {
+        let mut edit = VersionEdit::new();
+        let mut input = BorrowedSlice::from(src);
+
+        fn get_level(input: &mut BorrowedSlice) -> Option<usize> {
+            match input.get_varint32() {
+                Ok(v) if (v as usize) < config::NUM_LEVELS => Some(v as usize),
+                _ => None,
+            }
+        }
+
+        fn get_internal_key(input: &mut BorrowedSlice) -> Option<InternalKey> {
+            input.get_length_prefixed()
+                .ok()
+                .map(|s| InternalKey::decode_from(s.data()))
+        }
+
+        while !input.is_empty() {
+            let tag = match input.get_varint32() {
+                Ok(tag) => tag,
+                Err(_) => break,
+            };
+            let msg = match tag {
+                COMPARATOR => match input.get_length_prefixed() {
+                    Ok(s) => {
+                        edit.comparator = Some(String::from_utf8_lossy(s.data()).into_owned());
+                        None
+                    }
+                    Err(_) => Some("VersionEdit: comparator name"),
+                },
+                LOG_NUMBER => match input.get_varint64() {
+                    Ok(n) => {
+                        edit.log_number = Some(n);
+                        None
+                    }
+                    Err(_) => Some("VersionEdit: log number"),
+                },
+                PREV_LOG_NUMBER => match input.get_varint64() {
+                    Ok(n) => {
+                        edit.prev_log_number = Some(n);
+                        None
+                    }
+                    Err(_) => Some("VersionEdit: previous log number"),
+                },
+                NEXT_FILE_NUMBER => match input.get_varint64() {
+                    Ok(n) => {
+                        edit.next_file_number = Some(n);
+                        None
+                    }
+                    Err(_) => Some("VersionEdit: next file number"),
+                },
+                LAST_SEQUENCE => match input.get_varint64() {
+                    Ok(n) => {
+                        edit.last_sequence = Some(n);
+                        None
+                    }
+                    Err(_) => Some("VersionEdit: last sequence number"),
+                },
+                COMPACT_POINTER => match (get_level(&mut input), get_internal_key(&mut input)) {
+                    (Some(level), Some(key)) => {
+                        edit.compact_pointers.push((level, key));
+                        None
+                    }
+                    _ => Some("VersionEdit: compaction pointer"),
+                },
+                DELETED_FILE => match (get_level(&mut input), input.get_varint64()) {
+                    (Some(level), Ok(number)) => {
+                        edit.deleted_files.insert((level, number));
+                        None
+                    }
+                    _ => Some("VersionEdit: deleted file"),
+                },
+                NEW_FILE | NEW_FILE_WITH_TIME => {
+                    let level = get_level(&mut input);
+                    let number = input.get_varint64();
+                    let file_size = input.get_varint64();
+                    let smallest = get_internal_key(&mut input);
+                    let largest = get_internal_key(&mut input);
+                    let creation_time = match tag {
+                        NEW_FILE => Ok(0),
+                        _ => input.get_varint64(),
+                    };
+                    match (level, number, file_size, smallest, largest, creation_time) {
+                        (Some(level), Ok(number), Ok(file_size), Some(smallest), Some(largest), Ok(creation_time)) => {
+                            edit.new_files.push((
+                                level,
+                                FileMetaData {
+                                    number,
+                                    file_size,
+                                    smallest,
+                                    largest,
+                                    creation_time,
+                                },
+                            ));
+                            None
+                        }
+                        _ => Some("VersionEdit: new-file entry"),
+                    }
+                }
+                _ => Some("VersionEdit: unknown tag"),
+            };
+            if let Some(msg) = msg {
+                return Err(Error::new(ErrorType::Corruption, msg));
+            }
+        }
+        Ok(edit)
+    }
+}
+
+impl fmt::Display for VersionEdit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VersionEdit {{")?;
+        if let Some(comparator) = self.comparator.as_ref() {
+            write!(f, "\n  Comparator: {}", comparator)?;
+        }
+        if let Some(n) = self.log_number {
+            write!(f, "\n  LogNumber: {}", n)?;
+        }
+        if let Some(n) = self.prev_log_number {
+            write!(f, "\n  PrevLogNumber: {}", n)?;
+        }
+        if let Some(n) = self.next_file_number {
+            write!(f, "\n  NextFile: {}", n)?;
+        }
+        if let Some(n) = self.last_sequence {
+            write!(f, "\n  LastSeq: {}", n)?;
+        }
+        for (level, key) in self.compact_pointers.iter() {
+            write!(f, "\n  CompactPointer: {} {:?}", level, key)?;
+        }
+        for (level, number) in self.deleted_files.iter() {
+            write!(f, "\n  RemoveFile: {} {}", level, number)?;
+        }
+        for (level, file) in self.new_files.iter() {
+            write!(
+                f,
+                "\n  AddFile: {} {} {} {:?} .. {:?}",
+                level, file.number, file.file_size, file.smallest, file.largest
+            )?;
+            if file.creation_time != 0 {
+                write!(f, " created {}", file.creation_time)?;
+            }
+        }
+        write!(f, "\n}}\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::dbformat::ValueType;
+
+    fn test_encode_decode(edit: &VersionEdit) {
+        let mut encoded = Vec::new();
+        edit.encode_to(&mut encoded);
+        let parsed = VersionEdit::decode_from(&encoded).expect("decode");
+        let mut encoded2 = Vec::new();
+        parsed.encode_to(&mut encoded2);
+        assert_eq!(encoded, encoded2);
+        assert_eq!(*edit, parsed);
+    }
+
+    #[test]
+    fn encode_decode() {
+        const BIG: u64 = 1u64 << 50;
+
+        let mut edit = VersionEdit::new();
+        for i in 0..4u64 {
+            test_encode_decode(&edit);
+            edit.add_file(
+                3,
+                BIG + 300 + i,
+                BIG + 400 + i,
+                &InternalKey::new(b"foo", BIG + 500 + i, ValueType::Value),
+                &InternalKey::new(b"zoo", BIG + 600 + i, ValueType::Deletion),
+            );
+            edit.add_file_meta(
+                5,
+                &FileMetaData {
+                    number: BIG + 800 + i,
+                    file_size: 1000,
+                    smallest: InternalKey::new(b"bar", BIG + 500 + i, ValueType::Value),
+                    largest: InternalKey::new(b"baz", BIG + 600 + i, ValueType::Value),
+                    creation_time: 1_700_000_000 + i,
+                },
+            );
+            edit.remove_file(4, BIG + 700 + i);
+            edit.set_compact_pointer(i as usize, &InternalKey::new(b"x", BIG + 900 + i, ValueType::Value));
+        }
+
+        edit.set_comparator_name("foo");
+        edit.set_log_number(BIG + 100);
+        edit.set_next_file(BIG + 200);
+        edit.set_last_sequence(BIG + 1000);
+        test_encode_decode(&edit);
+    }
+
+    #[test]
+    fn rejects_bad_level() {
+        let mut encoded = Vec::new();
+        encode_varint_32_vec(&mut encoded, DELETED_FILE);
+        encode_varint_32_vec(&mut encoded, config::NUM_LEVELS as u32);
+        encode_varint_64_vec(&mut encoded, 1);
+        assert!(VersionEdit::decode_from(&encoded).unwrap_err().is_corruption());
+    }
+}
@@ -0,0 +1,1586 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// The representation of a DBImpl consists of a set of Versions. The newest version is
+// called "current". Older versions may be kept around to provide a consistent view to
+// live iterators.
+//
+// Each Version keeps track of a set of Table files per level. The entire set of
+// versions is maintained in a VersionSet.
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeSet,
+    sync::{
+        atomic::{self, AtomicU64},
+        Arc,
+    },
+};
+
+use crate::{
+    db::{
+        dbformat::{
+            config, parse_internal_key, InternalKey, InternalKeyComparator, LookupKey, SequenceNumber, ValueType,
+            MAX_SEQUENCE_NUMBER,
+        },
+        filename::{current_file_name, descriptor_file_name, set_current_file},
+        log_reader, log_writer,
+        key_range::KeyRange,
+        table_cache::TableCache,
+        version_edit::{FileMetaData, VersionEdit},
+    },
+    leveldb::{
+        comparator::Comparator,
+        env::read_file_to_string,
+        iterator::{new_error_iterator, Iterator},
+        options::{Options, ReadOptions},
+        pinnable_slice::PinnableSlice,
+        result::{Error, ErrorType, Result},
+    },
+    table::{merger::new_merging_iterator, two_level_iterator::new_two_level_iterator},
+    util::coding::{decode_fixed_64, encode_fixed_64},
+};
+
+// Bytes read ahead of the data block that a compaction reads from an input file.
+const COMPACTION_READAHEAD_SIZE: usize = 2 << 20;
+
+fn target_file_size(options: &Options) -> u64 { options.max_file_size as u64 }
+
+// Maximum bytes of overlaps in grandparent (i.e., level+2) before we stop building a
+// single file in a level->level+1 compaction.
+fn max_grand_parent_overlap_bytes(options: &Options) -> u64 { 10 * target_file_size(options) }
+
+// Maximum number of bytes in all compacted files. We avoid expanding the lower level
+// file set of a compaction if it would make the total compaction cover more than this
+// many bytes.
+fn expanded_compaction_byte_size_limit(options: &Options) -> u64 { 25 * target_file_size(options) }
+
+fn max_bytes_for_level(level: usize) -> f64 {
+    // Note: the result for level zero is not really used since we set the level-0
+    // compaction threshold based on number of files.
+
+    // Result for both level-0 and level-1
+    let mut result = 10. * 1048576.0;
+    let mut level = level;
+    while level > 1 {
+        result *= 10.;
+        level -= 1;
+    }
+    result
+}
+
+// Size limits of the levels with `Options::level_compaction_dynamic_level_bytes`, given
+// the size of the last level. The base level is the deepest one that would hold at most
+// 10MB. Levels above it get no room, so that their files are pushed on down to it. The
+// limit of the last level is not used.
+fn dynamic_max_bytes_for_levels(last_level_size: u64) -> [f64; config::NUM_LEVELS] {
+    let base_bytes = max_bytes_for_level(1);
+    let mut base_level = config::NUM_LEVELS - 1;
+    let mut size = last_level_size as f64;
+    while base_level > 1 && size > base_bytes {
+        base_level -= 1;
+        size /= 10.;
+    }
+    let mut result = [0.; config::NUM_LEVELS];
+    for max_bytes in result.iter_mut().skip(base_level) {
+        *max_bytes = size.max(base_bytes);
+        size *= 10.;
+    }
+    result
+}
+
+fn max_file_size_for_level(options: &Options, _level: usize) -> u64 {
+    // We could vary per level to reduce number of files?
+    target_file_size(options)
+}
+
+pub fn total_file_size(files: &[Arc<FileMetaData>]) -> u64 { files.iter().map(|f| f.file_size).sum() }
+
+/// Return the smallest index i such that `files[i].largest >= key`. Return
+/// `files.len()` if there is no such file.
+///
+/// REQUIRES: `files` contains a sorted list of non-overlapping files.
+pub fn find_file(icmp: &InternalKeyComparator, files: &[Arc<FileMetaData>], key: &[u8]) -> usize {
+    files.partition_point(|f| icmp.compare(f.largest.encode(), key) == Ordering::Less)
+}
+
+/// Returns true iff some file in `files` overlaps the user key range `range`.
+pub fn some_file_overlaps_range(icmp: &InternalKeyComparator, files: &[Arc<FileMetaData>], range: &KeyRange) -> bool {
+    let ucmp = icmp.user_comparator().as_ref();
+    files.iter().any(|f| range.overlaps_file(ucmp, f))
+}
+
+/// The user key range `[smallest, largest]` spanned by internal keys `smallest` and
+/// `largest`.
+fn user_key_range(smallest: &InternalKey, largest: &InternalKey) -> KeyRange {
+    KeyRange::inclusive(smallest.user_key(), largest.user_key())
+}
+
+/// An internal iterator. For a given version/level pair, yields information about the
+/// files in the level. For a given entry, key() is the largest key that occurs in the
+/// file, and value() is a 16-byte value containing the file number and file size, both
+/// encoded using `encode_fixed_64`.
+struct LevelFileNumIterator {
+    icmp: InternalKeyComparator,
+    flist: Vec<Arc<FileMetaData>>,
+    index: usize,
+    // Backing store for value(). Holds the file number and size.
+    value_buf: [u8; 16],
+}
+
+impl LevelFileNumIterator {
+    fn new(icmp: InternalKeyComparator, flist: Vec<Arc<FileMetaData>>) -> Self {
+        let index = flist.len(); // Marks as invalid
+        Self {
+            icmp,
+            flist,
+            index,
+            value_buf: [0; 16],
+        }
+    }
+
+    fn fill_value(&mut self) {
+        if self.valid() {
+            let f = &self.flist[self.index];
+            encode_fixed_64(&mut self.value_buf[..8], f.number);
+            encode_fixed_64(&mut self.value_buf[8..], f.file_size);
+        }
+    }
+}
+
+impl Iterator for LevelFileNumIterator {
+    fn valid(&self) -> bool { self.index < self.flist.len() }
+
+    fn seek_to_first(&mut self) {
+        self.index = 0;
+        self.fill_value();
+    }
+
+    fn seek_to_last(&mut self) {
+        // An empty list leaves the iterator invalid.
+        self.index = self.flist.len().checked_sub(1).unwrap_or(self.flist.len());
+        self.fill_value();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.index = find_file(&self.icmp, &self.flist, target);
+        self.fill_value();
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        self.index += 1;
+        self.fill_value();
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid());
+        self.index = match self.index {
+            0 => self.flist.len(), // Marks as invalid
+            index => index - 1,
+        };
+        self.fill_value();
+    }
+
+    fn key(&self) -> &[u8] {
+        assert!(self.valid());
+        self.flist[self.index].largest.encode()
+    }
+
+    fn value(&self) -> &[u8] {
+        assert!(self.valid());
+        &self.value_buf
+    }
+
+    fn status(&self) -> Result<()> { Ok(()) }
+}
+
+/// Return an iterator that yields the contents of the sorted, non-overlapping `files`
+/// one after the other, opening each table only when it is reached.
+fn new_concatenating_iterator(
+    icmp: &InternalKeyComparator,
+    files: &[Arc<FileMetaData>],
+    options: &ReadOptions,
+    table_cache: &Arc<TableCache>,
+) -> Box<dyn Iterator> {
+    let table_cache = table_cache.clone();
+    new_two_level_iterator(
+        Box::new(LevelFileNumIterator::new(icmp.clone(), files.to_vec())),
+        Box::new(move |options, file_value| {
+            if file_value.len() != 16 {
+                return new_error_iterator(Error::new(
+                    ErrorType::Corruption,
+                    "FileReader invoked with unexpected value",
+                ));
+            }
+            table_cache.new_iterator(options, decode_fixed_64(file_value), decode_fixed_64(&file_value[8..]))
+        }),
+        options,
+    )
+}
+
+pub struct Version {
+    icmp: InternalKeyComparator,
+    // List of files per level
+    files: [Vec<Arc<FileMetaData>>; config::NUM_LEVELS],
+
+    // Level that should be compacted next and its compaction score. Score < 1 means
+    // compaction is not strictly needed. These fields are initialized by finalize().
+    compaction_score: f64,
+    compaction_level: Option<usize>,
+
+    // With Options::periodic_compaction_seconds, the oldest file and its level and
+    // creation time. Initialized by finalize().
+    oldest_file: Option<(usize, Arc<FileMetaData>, u64)>,
+}
+
+impl Version {
+    fn new(icmp: InternalKeyComparator) -> Self {
+        Self {
+            icmp,
+            files: Default::default(),
+            compaction_score: -1.,
+            compaction_level: None,
+            oldest_file: None,
+        }
+    }
+
+    pub fn files(&self, level: usize) -> &[Arc<FileMetaData>] { &self.files[level] }
+
+    pub fn num_files(&self, level: usize) -> usize { self.files[level].len() }
+
+    /// Return a human readable string that describes this version's contents.
+    pub fn debug_string(&self) -> String {
+        let mut r = String::new();
+        for (level, files) in self.files.iter().enumerate() {
+            // E.g.,
+            //   --- level 1 ---
+            //   17:123['a' @ 1 : 1 .. 'd' @ 2 : 1]
+            //   20:43['e' @ 3 : 1 .. 'g' @ 4 : 1]
+            r.push_str(&format!("--- level {} ---\n", level));
+            for f in files.iter() {
+                r.push_str(&format!(" {}:{}[{:?} .. {:?}]\n", f.number, f.file_size, f.smallest, f.largest));
+            }
+        }
+        r
+    }
+
+    /// Append to `iters` a sequence of iterators that will yield the contents of this
+    /// Version when merged together.
+    pub fn add_iterators(&self, options: &ReadOptions, table_cache: &Arc<TableCache>, iters: &mut Vec<Box<dyn Iterator>>) {
+        // Merge all level zero files together since they may overlap
+        for f in self.files[0].iter() {
+            iters.push(table_cache.new_iterator(options, f.number, f.file_size));
+        }
+
+        // For levels > 0, we can use a concatenating iterator that sequentially walks
+        // through the non-overlapping files in the level, opening them lazily.
+        for level in 1..config::NUM_LEVELS {
+            if !self.files[level].is_empty() {
+                iters.push(new_concatenating_iterator(&self.icmp, &self.files[level], options, table_cache));
+            }
+        }
+    }
+
+    /// Lookup the value for key. If found, returns `Ok(Some(value))`. Returns `Ok(None)`
+    /// if the key is not present or has been deleted. Returns an error status if a file
+    /// could not be read, and a `MergeInProgress` error if the newest entry for key is
+    /// a merge operand. The number of table files consulted is added to `stats`. The
+    /// value keeps the data block it was read from pinned.
+    pub fn get(&self, options: &ReadOptions, k: &LookupKey, table_cache: &TableCache, stats: &mut GetStats) -> Result<Option<PinnableSlice>> {
+        let ikey = k.internal_key();
+        let user_key = k.user_key();
+        let ucmp = self.icmp.user_comparator();
+
+        // We can search level-by-level since entries never hop across levels. Hence we
+        // are guaranteed that if we find data in a smaller level, later levels are
+        // irrelevant.
+        for level in 0..config::NUM_LEVELS {
+            let files = &self.files[level];
+            if files.is_empty() {
+                continue;
+            }
+
+            let candidates: Vec<&Arc<FileMetaData>> = if level == 0 {
+                // Level-0 files may overlap each other. Find all files that overlap
+                // user_key and process them in order from newest to oldest.
+                let mut candidates: Vec<_> = files
+                    .iter()
+                    .filter(|f| {
+                        ucmp.compare(user_key, f.smallest.user_key()) != Ordering::Less
+                            && ucmp.compare(user_key, f.largest.user_key()) != Ordering::Greater
+                    })
+                    .collect();
+                candidates.sort_by_key(|f| std::cmp::Reverse(f.number));
+                candidates
+            } else {
+                // Binary search to find the only file that may contain the key.
+                let index = find_file(&self.icmp, files, ikey);
+                match files.get(index) {
+                    Some(f) if ucmp.compare(user_key, f.smallest.user_key()) != Ordering::Less => vec![f],
+                    // All of "f" is past any data for user_key
+                    _ => continue,
+                }
+            };
+
+            for f in candidates {
+                stats.files_probed += 1;
+                if let Some((found_key, value)) = table_cache.get(options, f.number, f.file_size, ikey)? {
+                    match parse_internal_key(&found_key) {
+                        None => return LEVELDB_ERR!(Corruption, "corrupted key for get"),
+                        Some(parsed) => {
+                            // The seek skipped every entry newer than the lookup's
+                            // sequence, so the first entry for user_key decides the
+                            // result, whether it is a value or a tombstone.
+                            if ucmp.compare(parsed.user_key, user_key) == Ordering::Equal {
+                                return match parsed.value_type {
+                                    ValueType::Value => Ok(Some(value)),
+                                    ValueType::Deletion => Ok(None),
+                                    ValueType::Merge => LEVELDB_ERR!(MergeInProgress, ""),
+                                };
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Batched form of `get()`: `result[i]` is what `get()` returns for `keys[i]`. Keys
+    /// that map to the same table file are looked up in a single call, so that they
+    /// share the index seek, filter probe and block read.
+    ///
+    /// REQUIRES: `keys` is sorted by user key.
+    pub fn multi_get(&self, options: &ReadOptions, keys: &[LookupKey], table_cache: &TableCache, stats: &mut GetStats) -> Vec<Result<Option<Vec<u8>>>> {
+        let ucmp = self.icmp.user_comparator();
+        let mut results: Vec<Option<Result<Option<Vec<u8>>>>> = (0..keys.len()).map(|_| None).collect();
+        // Indexes of the keys that have not been resolved yet, in key order.
+        let mut pending: Vec<usize> = (0..keys.len()).collect();
+        let mut search = |f: &FileMetaData, batch: &[usize], results: &mut [Option<Result<Option<Vec<u8>>>>]| {
+            stats.files_probed += 1;
+            for (i, result) in self.multi_get_from_file(options, keys, f, batch, table_cache) {
+                results[i] = Some(result);
+            }
+        };
+
+        for level in 0..config::NUM_LEVELS {
+            let files = &self.files[level];
+            if pending.is_empty() {
+                break;
+            }
+            if files.is_empty() {
+                continue;
+            }
+
+            if level == 0 {
+                // Level-0 files may overlap each other. Search them from newest to
+                // oldest, dropping keys as soon as a file resolves them.
+                let mut newest_first: Vec<&Arc<FileMetaData>> = files.iter().collect();
+                newest_first.sort_by_key(|f| std::cmp::Reverse(f.number));
+                for f in newest_first {
+                    let batch: Vec<usize> = pending
+                        .iter()
+                        .copied()
+                        .filter(|&i| {
+                            ucmp.compare(keys[i].user_key(), f.smallest.user_key()) != Ordering::Less
+                                && ucmp.compare(keys[i].user_key(), f.largest.user_key()) != Ordering::Greater
+                        })
+                        .collect();
+                    if !batch.is_empty() {
+                        search(f, &batch, &mut results);
+                        pending.retain(|&i| results[i].is_none());
+                    }
+                }
+            } else {
+                // Each key can only be in the file found by binary search. Since the
+                // keys are sorted, keys sharing a file are adjacent.
+                let mut batch: Vec<usize> = Vec::new();
+                let mut batch_file = None;
+                for &i in pending.iter() {
+                    let index = find_file(&self.icmp, files, keys[i].internal_key());
+                    match files.get(index) {
+                        Some(f) if ucmp.compare(keys[i].user_key(), f.smallest.user_key()) != Ordering::Less => {
+                            if batch_file != Some(index) {
+                                if let Some(b) = batch_file {
+                                    search(&files[b], &batch, &mut results);
+                                }
+                                batch.clear();
+                                batch_file = Some(index);
+                            }
+                            batch.push(i);
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some(b) = batch_file {
+                    search(&files[b], &batch, &mut results);
+                }
+                pending.retain(|&i| results[i].is_none());
+            }
+        }
+        results.into_iter().map(|r| r.unwrap_or(Ok(None))).collect()
+    }
+
+    /// Look up `keys[i]` for every `i` in `batch` in file `f`. Returns the result of
+    /// each key whose newest visible entry is found there.
+    fn multi_get_from_file(
+        &self,
+        options: &ReadOptions,
+        keys: &[LookupKey],
+        f: &FileMetaData,
+        batch: &[usize],
+        table_cache: &TableCache,
+    ) -> Vec<(usize, Result<Option<Vec<u8>>>)> {
+        let ikeys: Vec<&[u8]> = batch.iter().map(|&i| keys[i].internal_key()).collect();
+        let found = match table_cache.multi_get(options, f.number, f.file_size, &ikeys) {
+            Ok(found) => found,
+            Err(e) => return batch.iter().map(|&i| (i, Err(e.clone()))).collect(),
+        };
+        let ucmp = self.icmp.user_comparator();
+        let mut resolved = Vec::new();
+        for (&i, entry) in batch.iter().zip(found) {
+            let Some((found_key, value)) = entry else { continue };
+            match parse_internal_key(&found_key) {
+                None => resolved.push((i, LEVELDB_ERR!(Corruption, "corrupted key for get"))),
+                Some(parsed) if ucmp.compare(parsed.user_key, keys[i].user_key()) == Ordering::Equal => {
+                    let value = match parsed.value_type {
+                        ValueType::Value => Ok(Some(value)),
+                        ValueType::Deletion => Ok(None),
+                        ValueType::Merge => LEVELDB_ERR!(MergeInProgress, ""),
+                    };
+                    resolved.push((i, value));
+                }
+                Some(_) => {}
+            }
+        }
+        resolved
+    }
+
+    /// Return the approximate offset in the database of the data for `ikey` as of this
+    /// version: the size of all files before it plus its offset within the files that
+    /// span it.
+    pub fn approximate_offset_of(&self, ikey: &InternalKey, table_cache: &TableCache) -> u64 {
+        let mut result = 0;
+        for (level, files) in self.files.iter().enumerate() {
+            for f in files.iter() {
+                if self.icmp.compare_internal_key(&f.largest, ikey) != Ordering::Greater {
+                    // Entire file is before "ikey", so just add the file size
+                    result += f.file_size;
+                } else if self.icmp.compare_internal_key(&f.smallest, ikey) == Ordering::Greater {
+                    // Entire file is after "ikey", so ignore
+                    if level > 0 {
+                        // Files other than level 0 are sorted by smallest, so no further
+                        // files in this level will contain data for "ikey".
+                        break;
+                    }
+                } else if let Ok(table) = table_cache.find_table(f.number, f.file_size) {
+                    // "ikey" falls in the range for this table. Add the approximate
+                    // offset of "ikey" within the table.
+                    result += table.approximate_offset_of(ikey.encode());
+                }
+            }
+        }
+        result
+    }
+
+    pub fn overlap_in_level(&self, level: usize, range: &KeyRange) -> bool {
+        some_file_overlaps_range(&self.icmp, &self.files[level], range)
+    }
+
+    /// Return the level at which we should place a new memtable compaction result that
+    /// covers the range `[smallest_user_key, largest_user_key]`.
+    pub fn pick_level_for_memtable_output(&self, options: &Options, smallest_user_key: &[u8], largest_user_key: &[u8]) -> usize {
+        let mut level = 0;
+        let range = KeyRange::inclusive(smallest_user_key, largest_user_key);
+        if !self.overlap_in_level(0, &range) {
+            // Push to next level if there is no overlap in next level, and the #bytes
+            // overlapping in the level after that are limited.
+            while level < config::MAX_MEM_COMPACT_LEVEL {
+                if self.overlap_in_level(level + 1, &range) {
+                    break;
+                }
+                if level + 2 < config::NUM_LEVELS {
+                    // Check that file does not overlap too many grandparent bytes.
+                    let overlaps = self.get_overlapping_inputs(level + 2, &range);
+                    let sum = total_file_size(&overlaps);
+                    if sum > max_grand_parent_overlap_bytes(options) {
+                        break;
+                    }
+                }
+                level += 1;
+            }
+        }
+        level
+    }
+
+    /// Return all files in `level` that overlap `range`.
+    pub fn get_overlapping_inputs(&self, level: usize, range: &KeyRange) -> Vec<Arc<FileMetaData>> {
+        assert!(level < config::NUM_LEVELS);
+        let mut inputs = Vec::new();
+        let mut range = range.clone();
+        let ucmp = self.icmp.user_comparator().as_ref();
+        let mut i = 0;
+        while i < self.files[level].len() {
+            let f = &self.files[level][i];
+            i += 1;
+            if !range.overlaps_file(ucmp, f) {
+                // "f" is completely before or after the range; skip it
+                continue;
+            }
+            inputs.push(f.clone());
+            // Level-0 files may overlap each other. So check if the newly added file has
+            // expanded the range. If so, restart search.
+            if level == 0 && range.extend_to(ucmp, f.smallest.user_key(), f.largest.user_key()) {
+                inputs.clear();
+                i = 0;
+            }
+        }
+        inputs
+    }
+}
+
+/// Lookup statistics gathered by `Version::get`.
+#[derive(Debug, Default)]
+pub struct GetStats {
+    /// Number of table files searched for the key.
+    pub files_probed: usize,
+}
+
+/// Accumulates a sequence of edits on top of a base version without creating all of
+/// the intermediate versions.
+struct Builder {
+    icmp: InternalKeyComparator,
+    base: Arc<Version>,
+    deleted_files: [BTreeSet<u64>; config::NUM_LEVELS],
+    added_files: [Vec<Arc<FileMetaData>>; config::NUM_LEVELS],
+}
+
+impl Builder {
+    fn new(icmp: InternalKeyComparator, base: Arc<Version>) -> Self {
+        Self {
+            icmp,
+            base,
+            deleted_files: Default::default(),
+            added_files: Default::default(),
+        }
+    }
+
+    /// Apply all of the edits in `edit` to the current state.
+    fn apply(&mut self, edit: &VersionEdit, compact_pointer: &mut [Vec<u8>; config::NUM_LEVELS]) {
+        // Update compaction pointers
+        for (level, key) in edit.compact_pointers.iter() {
+            compact_pointer[*level] = key.encode().to_vec();
+        }
+
+        // Delete files
+        for (level, number) in edit.deleted_files.iter() {
+            self.deleted_files[*level].insert(*number);
+        }
+
+        // Add new files
+        for (level, f) in edit.new_files.iter() {
+            self.deleted_files[*level].remove(&f.number);
+            self.added_files[*level].push(Arc::new(f.clone()));
+        }
+    }
+
+    /// Merge the set of added files with the set of pre-existing files of `level`,
+    /// dropping any deleted files. The result is sorted by smallest key.
+    fn level_files(&self, level: usize) -> Vec<Arc<FileMetaData>> {
+        let mut files: Vec<Arc<FileMetaData>> = self.base.files[level]
+            .iter()
+            .chain(self.added_files[level].iter())
+            .filter(|f| !self.deleted_files[level].contains(&f.number))
+            .cloned()
+            .collect();
+        files.sort_by(|a, b| match self.icmp.compare_internal_key(&a.smallest, &b.smallest) {
+            // Break ties by file number
+            Ordering::Equal => a.number.cmp(&b.number),
+            r => r,
+        });
+        files
+    }
+
+    /// Check that the accumulated state describes a valid version: every file has a
+    /// sane key range and number, and files in levels > 0 do not overlap. `save_to()`
+    /// treats a violation as a bug, so damaged manifests are caught here instead.
+    fn validate(&self, next_file: u64) -> Result<()> {
+        for level in 0..config::NUM_LEVELS {
+            let files = self.level_files(level);
+            for f in files.iter() {
+                if f.number >= next_file {
+                    return LEVELDB_ERR!(Corruption, "file number beyond meta-nextfile in descriptor");
+                }
+                if self.icmp.compare_internal_key(&f.smallest, &f.largest) == Ordering::Greater {
+                    return LEVELDB_ERR!(Corruption, "file with inverted key range in descriptor");
+                }
+            }
+            if level > 0
+                && files
+                    .windows(2)
+                    .any(|pair| self.icmp.compare_internal_key(&pair[0].largest, &pair[1].smallest) != Ordering::Less)
+            {
+                return LEVELDB_ERR!(Corruption, "overlapping ranges in same level in descriptor");
+            }
+        }
+        Ok(())
+    }
+
+    /// Save the current state in `v`.
+    fn save_to(&self, v: &mut Version) {
+        for level in 0..config::NUM_LEVELS {
+            let files = self.level_files(level);
+
+            // Make sure there is no overlap in levels > 0
+            if level > 0 {
+                for pair in files.windows(2) {
+                    assert_eq!(
+                        self.icmp.compare_internal_key(&pair[0].largest, &pair[1].smallest),
+                        Ordering::Less,
+                        "overlapping ranges in same level"
+                    );
+                }
+            }
+            v.files[level] = files;
+        }
+    }
+}
+
+/// The next file number to allocate. Shared with the background jobs that create
+/// files, so that they do not need the DB mutex to number them.
+#[derive(Clone)]
+pub struct FileNumbers(Arc<AtomicU64>);
+
+impl FileNumbers {
+    fn new(next: u64) -> Self { Self(Arc::new(AtomicU64::new(next))) }
+
+    /// Allocate and return a new file number
+    pub fn next(&self) -> u64 { self.0.fetch_add(1, atomic::Ordering::Relaxed) }
+
+    fn peek(&self) -> u64 { self.0.load(atomic::Ordering::Relaxed) }
+
+    fn set(&self, next: u64) { self.0.store(next, atomic::Ordering::Relaxed) }
+}
+
+pub struct VersionSet {
+    dbname: String,
+    options: Options,
+    icmp: InternalKeyComparator,
+    next_file_number: FileNumbers,
+    manifest_file_number: u64,
+    last_sequence: SequenceNumber,
+    log_number: u64,
+    // 0 or backing store for memtable being compacted
+    prev_log_number: u64,
+
+    // Opened lazily
+    descriptor_log: Option<log_writer::Writer>,
+    current: Arc<Version>,
+
+    // Per-level key at which the next compaction at that level should start. Either an
+    // empty string, or a valid InternalKey.
+    compact_pointer: [Vec<u8>; config::NUM_LEVELS],
+
+    // Seconds since the epoch at which the database was opened
+    open_time: u64,
+}
+
+impl VersionSet {
+    pub fn new(dbname: &str, options: &Options, icmp: InternalKeyComparator) -> Self {
+        Self {
+            dbname: dbname.to_string(),
+            options: options.clone(),
+            current: Arc::new(Version::new(icmp.clone())),
+            icmp,
+            next_file_number: FileNumbers::new(2),
+            manifest_file_number: 0, // Filled by recover()
+            last_sequence: 0,
+            log_number: 0,
+            prev_log_number: 0,
+            descriptor_log: None,
+            compact_pointer: Default::default(),
+            open_time: options.env.now_micros() / 1_000_000,
+        }
+    }
+
+    /// Return the current version.
+    pub fn current(&self) -> Arc<Version> { self.current.clone() }
+
+    /// Return the current manifest file number
+    pub fn manifest_file_number(&self) -> u64 { self.manifest_file_number }
+
+    /// Allocate and return a new file number
+    pub fn new_file_number(&mut self) -> u64 { self.next_file_number.next() }
+
+    /// Return the allocator of file numbers, for use without the VersionSet.
+    pub fn file_numbers(&self) -> FileNumbers { self.next_file_number.clone() }
+
+    /// Arrange to reuse `file_number` unless a newer file number has already been
+    /// allocated.
+    ///
+    /// REQUIRES: `file_number` was returned by a call to `new_file_number()`.
+    pub fn reuse_file_number(&mut self, file_number: u64) {
+        let _ = self.next_file_number.0.compare_exchange(
+            file_number + 1,
+            file_number,
+            atomic::Ordering::Relaxed,
+            atomic::Ordering::Relaxed,
+        );
+    }
+
+    /// Mark the specified file number as used.
+    pub fn mark_file_number_used(&mut self, number: u64) {
+        self.next_file_number.0.fetch_max(number + 1, atomic::Ordering::Relaxed);
+    }
+
+    /// Return the number of Table files at the specified level.
+    pub fn num_level_files(&self, level: usize) -> usize { self.current.files[level].len() }
+
+    /// Return the combined file size of all files at the specified level.
+    pub fn num_level_bytes(&self, level: usize) -> u64 { total_file_size(&self.current.files[level]) }
+
+    /// Return the last sequence number.
+    pub fn last_sequence(&self) -> SequenceNumber { self.last_sequence }
+
+    /// Set the last sequence number to `s`.
+    pub fn set_last_sequence(&mut self, s: SequenceNumber) {
+        assert!(s >= self.last_sequence);
+        assert!(s <= MAX_SEQUENCE_NUMBER);
+        self.last_sequence = s;
+    }
+
+    /// Return the current log file number.
+    pub fn log_number(&self) -> u64 { self.log_number }
+
+    /// Return the log file number for the log file that is currently being compacted,
+    /// or zero if there is no such log file.
+    pub fn prev_log_number(&self) -> u64 { self.prev_log_number }
+
+    /// Apply `edit` to the current version to form a new descriptor that is both saved
+    /// to persistent state and installed as the new current version.
+    pub fn log_and_apply(&mut self, edit: &mut VersionEdit) -> Result<()> {
+        match edit.log_number {
+            Some(n) => {
+                assert!(n >= self.log_number);
+                assert!(n < self.next_file_number.peek());
+            }
+            None => edit.set_log_number(self.log_number),
+        }
+        if edit.prev_log_number.is_none() {
+            edit.set_prev_log_number(self.prev_log_number);
+        }
+        edit.set_next_file(self.next_file_number.peek());
+        edit.set_last_sequence(self.last_sequence);
+
+        let mut v = Version::new(self.icmp.clone());
+        {
+            let mut builder = Builder::new(self.icmp.clone(), self.current.clone());
+            builder.apply(edit, &mut self.compact_pointer);
+            builder.save_to(&mut v);
+        }
+        self.finalize(&mut v);
+
+        // Initialize new descriptor log file if necessary by creating a temporary file
+        // that contains a snapshot of the current version.
+        let mut new_manifest_file = None;
+        if self.descriptor_log.is_none() {
+            let fname = descriptor_file_name(&self.dbname, self.manifest_file_number);
+            let file = self.options.env.new_writable_file(&fname)?;
+            let mut log = log_writer::Writer::new(file);
+            let written = self.write_snapshot(&mut log);
+            if let Err(e) = written {
+                let _ = self.options.env.remove_file(&fname);
+                return Err(e);
+            }
+            self.descriptor_log = Some(log);
+            new_manifest_file = Some(fname);
+        }
+
+        // Write new record to MANIFEST log
+        let mut record = Vec::new();
+        edit.encode_to(&mut record);
+        let log = self.descriptor_log.as_mut().unwrap();
+        let mut s = log.add_record(&record).and_then(|_| log.sync());
+
+        // If we just created a new descriptor file, install it by writing a new CURRENT
+        // file that points to it.
+        if s.is_ok() && new_manifest_file.is_some() {
+            s = set_current_file(self.options.env.as_ref(), &self.dbname, self.manifest_file_number);
+        }
+
+        match s {
+            Ok(()) => {
+                // Install the new version
+                self.current = Arc::new(v);
+                self.log_number = edit.log_number.unwrap();
+                self.prev_log_number = edit.prev_log_number.unwrap();
+                Ok(())
+            }
+            Err(e) => {
+                if let Some(fname) = new_manifest_file {
+                    self.descriptor_log = None;
+                    let _ = self.options.env.remove_file(&fname);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Recover the last saved descriptor from persistent storage.
+    pub fn recover(&mut self) -> Result<()> {
+        // Read "CURRENT" file, which contains a pointer to the current manifest file
+        let current = read_file_to_string(self.options.env.as_ref(), &current_file_name(&self.dbname))?;
+        if current.last() != Some(&b'\n') {
+            return LEVELDB_ERR!(Corruption, "CURRENT file does not end with newline");
+        }
+        let current = String::from_utf8_lossy(&current[..current.len() - 1]).into_owned();
+
+        let dscname = format!("{}/{}", self.dbname, current);
+        let file = match self.options.env.new_sequential_file(&dscname) {
+            Ok(file) => file,
+            Err(e) if e.is_not_found() => {
+                return LEVELDB_ERR!(Corruption, "CURRENT points to a non-existent file");
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut have_log_number = false;
+        let mut have_prev_log_number = false;
+        let mut have_next_file = false;
+        let mut have_last_sequence = false;
+        let mut next_file = 0;
+        let mut last_sequence = 0;
+        let mut log_number = 0;
+        let mut prev_log_number = 0;
+        let mut builder = Builder::new(self.icmp.clone(), self.current.clone());
+        let mut compact_pointer = self.compact_pointer.clone();
+
+        {
+            let reporter = LogReporter::default();
+            let status = reporter.status.clone();
+            let mut reader = log_reader::Reader::new(file, Some(Box::new(reporter)), true);
+            let mut record = Vec::new();
+            while reader.read_record(&mut record) {
+                let edit = VersionEdit::decode_from(&record)?;
+                if let Some(name) = edit.comparator.as_ref() {
+                    let given = self.icmp.user_comparator().name();
+                    if name != given {
+                        return Err(
+                            Error::new(ErrorType::InvalidArgument, "comparator does not match existing comparator")
+                                .with_detail(format!("database uses {}, opened with {}", name, given)),
+                        );
+                    }
+                }
+
+                builder.apply(&edit, &mut compact_pointer);
+
+                if let Some(n) = edit.log_number {
+                    log_number = n;
+                    have_log_number = true;
+                }
+                if let Some(n) = edit.prev_log_number {
+                    prev_log_number = n;
+                    have_prev_log_number = true;
+                }
+                if let Some(n) = edit.next_file_number {
+                    next_file = n;
+                    have_next_file = true;
+                }
+                if let Some(n) = edit.last_sequence {
+                    last_sequence = n;
+                    have_last_sequence = true;
+                }
+            }
+            let error = status.lock().unwrap().take();
+            if let Some(e) = error {
+                return Err(e);
+            }
+        }
+
+        if !have_next_file {
+            return LEVELDB_ERR!(Corruption, "no meta-nextfile entry in descriptor");
+        } else if !have_log_number {
+            return LEVELDB_ERR!(Corruption, "no meta-lognumber entry in descriptor");
+        } else if !have_last_sequence {
+            return LEVELDB_ERR!(Corruption, "no last-sequence-number entry in descriptor");
+        }
+
+        if self.options.paranoid_checks {
+            builder.validate(next_file)?;
+        }
+
+        if last_sequence > MAX_SEQUENCE_NUMBER {
+            return LEVELDB_ERR!(Corruption, "last-sequence-number exceeds the 56-bit limit");
+        }
+
+        if !have_prev_log_number {
+            prev_log_number = 0;
+        }
+
+        self.mark_file_number_used(prev_log_number);
+        self.mark_file_number_used(log_number);
+
+        let mut v = Version::new(self.icmp.clone());
+        builder.save_to(&mut v);
+        // Install recovered version
+        self.finalize(&mut v);
+        self.current = Arc::new(v);
+        self.compact_pointer = compact_pointer;
+        self.manifest_file_number = next_file;
+        self.next_file_number.set(next_file + 1);
+        self.last_sequence = last_sequence;
+        self.log_number = log_number;
+        self.prev_log_number = prev_log_number;
+        Ok(())
+    }
+
+    fn finalize(&self, v: &mut Version) {
+        // Precomputed best level for next compaction
+        let mut best_level = None;
+        let mut best_score = -1.;
+        let max_bytes = match self.options.level_compaction_dynamic_level_bytes {
+            true => dynamic_max_bytes_for_levels(total_file_size(&v.files[config::NUM_LEVELS - 1])),
+            false => std::array::from_fn(max_bytes_for_level),
+        };
+
+        for (level, &level_max_bytes) in max_bytes.iter().enumerate().take(config::NUM_LEVELS - 1) {
+            let score = if level == 0 {
+                // We treat level-0 specially by bounding the number of files instead of
+                // number of bytes for two reasons:
+                //
+                // (1) With larger write-buffer sizes, it is nice not to do too many
+                // level-0 compactions.
+                //
+                // (2) The files in level-0 are merged on every read and therefore we
+                // wish to avoid too many files when the individual file size is small
+                // (perhaps because of a small write-buffer setting, or very high
+                // compression ratios, or lots of overwrites/deletions).
+                v.files[level].len() as f64 / self.options.level0_file_num_compaction_trigger as f64
+            } else if level_max_bytes == 0. {
+                // A level above the base level, which passes its files on right away.
+                if v.files[level].is_empty() { 0. } else { f64::INFINITY }
+            } else {
+                // Compute the ratio of current size to size limit.
+                total_file_size(&v.files[level]) as f64 / level_max_bytes
+            };
+
+            if score > best_score {
+                best_level = Some(level);
+                best_score = score;
+            }
+        }
+
+        v.compaction_level = best_level;
+        v.compaction_score = best_score;
+
+        if self.options.periodic_compaction_seconds > 0 {
+            v.oldest_file = v
+                .files
+                .iter()
+                .enumerate()
+                .flat_map(|(level, files)| files.iter().map(move |f| (level, f)))
+                .map(|(level, f)| (level, f.clone(), self.creation_time(f)))
+                .min_by_key(|&(_, _, creation_time)| creation_time);
+        }
+    }
+
+    /// Return the creation time of `f`, counting files of unknown age from when the
+    /// database was opened.
+    fn creation_time(&self, f: &FileMetaData) -> u64 {
+        match f.creation_time {
+            0 => self.open_time,
+            creation_time => creation_time,
+        }
+    }
+
+    /// Return the oldest file and its level if it is due for a periodic compaction.
+    fn expired_file(&self) -> Option<(usize, Arc<FileMetaData>)> {
+        let (level, f, creation_time) = self.current.oldest_file.as_ref()?;
+        let now = self.options.env.now_micros() / 1_000_000;
+        let expired = creation_time.saturating_add(self.options.periodic_compaction_seconds) <= now;
+        expired.then(|| (*level, f.clone()))
+    }
+
+    /// Save current contents to `log`
+    fn write_snapshot(&self, log: &mut log_writer::Writer) -> Result<()> {
+        // Save metadata
+        let mut edit = VersionEdit::new();
+        edit.set_comparator_name(self.icmp.user_comparator().name());
+
+        // Save compaction pointers
+        for (level, pointer) in self.compact_pointer.iter().enumerate() {
+            if !pointer.is_empty() {
+                edit.set_compact_pointer(level, &InternalKey::decode_from(pointer));
+            }
+        }
+
+        // Save files
+        for level in 0..config::NUM_LEVELS {
+            for f in self.current.files[level].iter() {
+                edit.add_file_meta(level, f);
+            }
+        }
+
+        let mut record = Vec::new();
+        edit.encode_to(&mut record);
+        log.add_record(&record)
+    }
+
+    /// Returns true iff some level needs a compaction, or some file is due for a
+    /// periodic compaction.
+    pub fn needs_compaction(&self) -> bool { self.current.compaction_score >= 1. || self.expired_file().is_some() }
+
+    /// Add all files listed in the current version to `live`.
+    pub fn add_live_files(&self, live: &mut BTreeSet<u64>) {
+        for level in 0..config::NUM_LEVELS {
+            for f in self.current.files[level].iter() {
+                live.insert(f.number);
+            }
+        }
+    }
+
+    /// Pick level and inputs for a new compaction. Returns `None` if there is no
+    /// compaction to be done.
+    pub fn pick_compaction(&mut self) -> Option<Compaction> {
+        // We prefer compactions triggered by too much data in a level over the
+        // compactions triggered by the age of files.
+        if self.current.compaction_score < 1. {
+            return self.pick_periodic_compaction();
+        }
+        let level = self.current.compaction_level?;
+        assert!(level + 1 < config::NUM_LEVELS);
+        let mut c = Compaction::new(&self.options, level, self.current.clone());
+
+        // Pick the first file that comes after compact_pointer[level]
+        for f in self.current.files[level].iter() {
+            if self.compact_pointer[level].is_empty()
+                || self.icmp.compare(f.largest.encode(), &self.compact_pointer[level]) == Ordering::Greater
+            {
+                c.inputs[0].push(f.clone());
+                break;
+            }
+        }
+        if c.inputs[0].is_empty() {
+            // Wrap-around to the beginning of the key space
+            c.inputs[0].push(self.current.files[level][0].clone());
+        }
+
+        self.expand_level0_inputs(&mut c);
+        self.setup_other_inputs(&mut c);
+        Some(c)
+    }
+
+    /// Pick a compaction that rewrites the oldest file, if it is older than
+    /// `Options::periodic_compaction_seconds`. A file of the last level is compacted
+    /// into that same level.
+    fn pick_periodic_compaction(&mut self) -> Option<Compaction> {
+        let (level, f) = self.expired_file()?;
+        let mut c = Compaction::new(&self.options, level, self.current.clone());
+        c.periodic = true;
+        c.inputs[0].push(f);
+        self.expand_level0_inputs(&mut c);
+        if level + 1 == config::NUM_LEVELS {
+            c.output_level = level;
+        } else {
+            self.setup_other_inputs(&mut c);
+        }
+        Some(c)
+    }
+
+    /// Files in level 0 may overlap each other, so pick up all files that overlap the
+    /// inputs of a level-0 compaction.
+    fn expand_level0_inputs(&self, c: &mut Compaction) {
+        if c.level == 0 {
+            let (smallest, largest) = self.get_range(&c.inputs[0]);
+            // Note that the next call will discard the file we placed in c.inputs[0]
+            // earlier and replace it with an overlapping set which will include the
+            // picked file.
+            c.inputs[0] = self.current.get_overlapping_inputs(0, &user_key_range(&smallest, &largest));
+            assert!(!c.inputs[0].is_empty());
+        }
+    }
+
+    /// Return a compaction object for compacting `range` in the specified level.
+    /// Returns `None` if there is nothing in that level that overlaps the specified
+    /// range.
+    pub fn compact_range(&mut self, level: usize, range: &KeyRange) -> Option<Compaction> {
+        let mut inputs = self.current.get_overlapping_inputs(level, range);
+        if inputs.is_empty() {
+            return None;
+        }
+
+        // Avoid compacting too much in one shot in case the range is large. But we
+        // cannot do this for level-0 since level-0 files can overlap and we must not
+        // pick one file and drop another older file if the two files overlap.
+        if level > 0 {
+            let limit = max_file_size_for_level(&self.options, level);
+            let mut total = 0;
+            for i in 0..inputs.len() {
+                total += inputs[i].file_size;
+                if total >= limit {
+                    inputs.truncate(i + 1);
+                    break;
+                }
+            }
+        }
+
+        let mut c = Compaction::new(&self.options, level, self.current.clone());
+        c.inputs[0] = inputs;
+        self.setup_other_inputs(&mut c);
+        Some(c)
+    }
+
+    /// Return the smallest and largest internal keys of all entries in `inputs`.
+    ///
+    /// REQUIRES: `inputs` is not empty
+    fn get_range(&self, inputs: &[Arc<FileMetaData>]) -> (InternalKey, InternalKey) {
+        assert!(!inputs.is_empty());
+        let mut smallest = &inputs[0].smallest;
+        let mut largest = &inputs[0].largest;
+        for f in inputs.iter().skip(1) {
+            if self.icmp.compare_internal_key(&f.smallest, smallest) == Ordering::Less {
+                smallest = &f.smallest;
+            }
+            if self.icmp.compare_internal_key(&f.largest, largest) == Ordering::Greater {
+                largest = &f.largest;
+            }
+        }
+        (smallest.clone(), largest.clone())
+    }
+
+    /// Return the smallest and largest internal keys of all entries in `inputs1` and
+    /// `inputs2`.
+    fn get_range2(&self, inputs1: &[Arc<FileMetaData>], inputs2: &[Arc<FileMetaData>]) -> (InternalKey, InternalKey) {
+        let all: Vec<Arc<FileMetaData>> = inputs1.iter().chain(inputs2.iter()).cloned().collect();
+        self.get_range(&all)
+    }
+
+    fn setup_other_inputs(&mut self, c: &mut Compaction) {
+        let level = c.level;
+        let (smallest, mut largest) = self.get_range(&c.inputs[0]);
+
+        c.inputs[1] = self
+            .current
+            .get_overlapping_inputs(level + 1, &user_key_range(&smallest, &largest));
+
+        // Get entire range covered by compaction
+        let (mut all_start, mut all_limit) = self.get_range2(&c.inputs[0], &c.inputs[1]);
+
+        // See if we can grow the number of inputs in "level" without changing the
+        // number of "level+1" files we pick up.
+        if !c.inputs[1].is_empty() {
+            let expanded0 = self
+                .current
+                .get_overlapping_inputs(level, &user_key_range(&all_start, &all_limit));
+            let inputs1_size = total_file_size(&c.inputs[1]);
+            let expanded0_size = total_file_size(&expanded0);
+            if expanded0.len() > c.inputs[0].len()
+                && inputs1_size + expanded0_size < expanded_compaction_byte_size_limit(&self.options)
+            {
+                let (new_start, new_limit) = self.get_range(&expanded0);
+                let expanded1 = self
+                    .current
+                    .get_overlapping_inputs(level + 1, &user_key_range(&new_start, &new_limit));
+                if expanded1.len() == c.inputs[1].len() {
+                    largest = new_limit;
+                    c.inputs[0] = expanded0;
+                    c.inputs[1] = expanded1;
+                    let (s, l) = self.get_range2(&c.inputs[0], &c.inputs[1]);
+                    all_start = s;
+                    all_limit = l;
+                }
+            }
+        }
+
+        // Compute the set of grandparent files that overlap this compaction
+        // (parent == level+1; grandparent == level+2)
+        if level + 2 < config::NUM_LEVELS {
+            c.grandparents = self
+                .current
+                .get_overlapping_inputs(level + 2, &user_key_range(&all_start, &all_limit));
+        }
+
+        // Update the place where we will do the next compaction for this level. We
+        // update this immediately instead of waiting for the VersionEdit to be applied
+        // so that if the compaction fails, we will try a different key range next time.
+        self.compact_pointer[level] = largest.encode().to_vec();
+        c.edit.set_compact_pointer(level, &largest);
+    }
+}
+
+/// Collects the first corruption reported while reading the manifest.
+#[derive(Default)]
+struct LogReporter {
+    status: Arc<std::sync::Mutex<Option<Error>>>,
+}
+
+impl log_reader::Reporter for LogReporter {
+    fn corruption(&mut self, _bytes: usize, error: &Error) {
+        let mut status = self.status.lock().unwrap();
+        if status.is_none() {
+            *status = Some(error.clone());
+        }
+    }
+}
+
+/// A Compaction encapsulates information about a compaction.
+pub struct Compaction {
+    level: usize,
+    // level + 1, except for periodic compactions of the last level
+    output_level: usize,
+    // Picked because of the age of its input
+    periodic: bool,
+    max_output_file_size: u64,
+    max_grand_parent_overlap_bytes: u64,
+    input_version: Arc<Version>,
+    edit: VersionEdit,
+
+    // Each compaction reads inputs from "level" and "level+1"
+    inputs: [Vec<Arc<FileMetaData>>; 2],
+
+    // State used to check for number of overlapping grandparent files
+    // (parent == level+1; grandparent == level+2)
+    grandparents: Vec<Arc<FileMetaData>>,
+    // Index in grandparents
+    grandparent_index: usize,
+    // Some output key has been seen
+    seen_key: bool,
+    // Bytes of overlap between current output and grandparent files
+    overlapped_bytes: u64,
+
+    // State for implementing is_base_level_for_key
+
+    // level_ptrs holds indices into input_version.files: our state is that we are
+    // positioned at one of the file ranges for each higher level than the ones
+    // involved in this compaction (i.e. for all L >= level + 2).
+    level_ptrs: [usize; config::NUM_LEVELS],
+}
+
+impl Compaction {
+    fn new(options: &Options, level: usize, input_version: Arc<Version>) -> Self {
+        Self {
+            level,
+            output_level: level + 1,
+            periodic: false,
+            max_output_file_size: max_file_size_for_level(options, level),
+            max_grand_parent_overlap_bytes: max_grand_parent_overlap_bytes(options),
+            input_version,
+            edit: VersionEdit::new(),
+            inputs: Default::default(),
+            grandparents: Vec::new(),
+            grandparent_index: 0,
+            seen_key: false,
+            overlapped_bytes: 0,
+            level_ptrs: [0; config::NUM_LEVELS],
+        }
+    }
+
+    /// Return the level that is being compacted. Inputs from "level" and "level+1" will
+    /// be merged to produce a set of "level+1" files.
+    pub fn level(&self) -> usize { self.level }
+
+    /// Return the level that the outputs are written to: "level+1", or "level" for a
+    /// periodic compaction of the last level.
+    pub fn output_level(&self) -> usize { self.output_level }
+
+    /// Return the object that holds the edits to the descriptor done by this
+    /// compaction.
+    pub fn edit(&mut self) -> &mut VersionEdit { &mut self.edit }
+
+    /// "which" must be either 0 or 1
+    pub fn num_input_files(&self, which: usize) -> usize { self.inputs[which].len() }
+
+    /// Return the ith input file at "level()+which" ("which" must be 0 or 1).
+    pub fn input(&self, which: usize, i: usize) -> &Arc<FileMetaData> { &self.inputs[which][i] }
+
+    /// Maximum size of files to build during this compaction.
+    pub fn max_output_file_size(&self) -> u64 { self.max_output_file_size }
+
+    /// Is this a trivial compaction that can be implemented by just moving a single
+    /// input file to the next level (no merging or splitting)
+    pub fn is_trivial_move(&self) -> bool {
+        // Avoid a move if there is lots of overlapping grandparent data. Otherwise, the
+        // move could create a parent file that will require a very expensive merge
+        // later on. A periodic compaction has to rewrite its input.
+        !self.periodic
+            && self.num_input_files(0) == 1
+            && self.num_input_files(1) == 0
+            && total_file_size(&self.grandparents) <= self.max_grand_parent_overlap_bytes
+    }
+
+    /// Create an iterator that reads over the inputs, from the tables of `table_cache`.
+    pub fn new_input_iterator(&self, options: &Options, table_cache: &Arc<TableCache>) -> Box<dyn Iterator> {
+        let options = ReadOptions {
+            verify_checksums: options.paranoid_checks,
+            fill_cache: false,
+            readahead_size: COMPACTION_READAHEAD_SIZE,
+            ..ReadOptions::default()
+        };
+        // Level-0 files have to be merged together. For other levels, we will make a
+        // concatenating iterator per level.
+        let icmp = &self.input_version.icmp;
+        let mut list = Vec::new();
+        for which in 0..2 {
+            if self.inputs[which].is_empty() {
+                continue;
+            }
+            if self.level() + which == 0 {
+                for f in self.inputs[which].iter() {
+                    list.push(table_cache.new_iterator(&options, f.number, f.file_size));
+                }
+            } else {
+                list.push(new_concatenating_iterator(icmp, &self.inputs[which], &options, table_cache));
+            }
+        }
+        new_merging_iterator(Arc::new(icmp.clone()), list)
+    }
+
+    /// Return a compaction over the same inputs, with its own output state and an empty
+    /// edit, that a subcompaction uses to write part of the key range.
+    pub fn subcompaction(&self) -> Compaction {
+        Self {
+            level: self.level,
+            output_level: self.output_level,
+            periodic: self.periodic,
+            max_output_file_size: self.max_output_file_size,
+            max_grand_parent_overlap_bytes: self.max_grand_parent_overlap_bytes,
+            input_version: self.input_version.clone(),
+            edit: VersionEdit::new(),
+            inputs: self.inputs.clone(),
+            grandparents: self.grandparents.clone(),
+            grandparent_index: 0,
+            seen_key: false,
+            overlapped_bytes: 0,
+            level_ptrs: [0; config::NUM_LEVELS],
+        }
+    }
+
+    /// Split the key range of the inputs into at most `max` parts at the boundaries of
+    /// the input files, to be compacted by separate subcompactions. Returns the user
+    /// keys that start the second and following parts.
+    pub fn subcompaction_boundaries(&self, max: usize) -> Vec<Vec<u8>> {
+        let ucmp = self.input_version.icmp.user_comparator();
+        let mut keys: Vec<&[u8]> = self.inputs[0]
+            .iter()
+            .chain(self.inputs[1].iter())
+            .flat_map(|f| [f.smallest.user_key(), f.largest.user_key()])
+            .collect();
+        keys.sort_by(|a, b| ucmp.compare(a, b));
+        keys.dedup_by(|a, b| ucmp.compare(a, b) == Ordering::Equal);
+        // The smallest key cannot start a part.
+        let candidates = keys.get(1..).unwrap_or_default();
+        let parts = max.min(candidates.len() + 1);
+        (1..parts)
+            .map(|i| candidates[i * candidates.len() / parts].to_vec())
+            .collect()
+    }
+
+    /// Add all inputs to this compaction as delete operations to `edit`.
+    pub fn add_input_deletions(&mut self) {
+        for which in 0..2 {
+            for f in self.inputs[which].iter() {
+                self.edit.remove_file(self.level + which, f.number);
+            }
+        }
+    }
+
+    /// Returns true if the information we have available guarantees that the
+    /// compaction is producing data in "level+1" for which no data exists in levels
+    /// greater than "level+1".
+    pub fn is_base_level_for_key(&mut self, user_key: &[u8]) -> bool {
+        // Maybe use binary search to find right entry instead of linear search?
+        let ucmp = self.input_version.icmp.user_comparator().clone();
+        for lvl in self.level + 2..config::NUM_LEVELS {
+            let files = &self.input_version.files[lvl];
+            while self.level_ptrs[lvl] < files.len() {
+                let f = &files[self.level_ptrs[lvl]];
+                if ucmp.compare(user_key, f.largest.user_key()) != Ordering::Greater {
+                    // We've advanced far enough
+                    if ucmp.compare(user_key, f.smallest.user_key()) != Ordering::Less {
+                        // Key falls in this file's range, so definitely not base level
+                        return false;
+                    }
+                    break;
+                }
+                self.level_ptrs[lvl] += 1;
+            }
+        }
+        true
+    }
+
+    /// Returns true iff we should stop building the current output before processing
+    /// `internal_key`.
+    pub fn should_stop_before(&mut self, internal_key: &[u8]) -> bool {
+        let icmp = &self.input_version.icmp;
+        // Scan to find earliest grandparent file that contains key.
+        while self.grandparent_index < self.grandparents.len()
+            && icmp.compare(
+                internal_key,
+                self.grandparents[self.grandparent_index].largest.encode(),
+            ) == Ordering::Greater
+        {
+            if self.seen_key {
+                self.overlapped_bytes += self.grandparents[self.grandparent_index].file_size;
+            }
+            self.grandparent_index += 1;
+        }
+        self.seen_key = true;
+
+        if self.overlapped_bytes > self.max_grand_parent_overlap_bytes {
+            // Too much overlap for current output; start new output
+            self.overlapped_bytes = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::comparator::bytewise_comparator;
+
+    fn file(number: u64, smallest: &[u8], largest: &[u8]) -> Arc<FileMetaData> {
+        Arc::new(FileMetaData {
+            number,
+            file_size: 0,
+            smallest: InternalKey::new(smallest, 100, ValueType::Value),
+            largest: InternalKey::new(largest, 100, ValueType::Value),
+            creation_time: 0,
+        })
+    }
+
+    #[test]
+    fn find_overlaps() {
+        let icmp = InternalKeyComparator::new(bytewise_comparator());
+        let files = vec![file(1, b"150", b"200"), file(2, b"200", b"250"), file(3, b"300", b"350")];
+        let overlaps = |s: Option<&[u8]>, l: Option<&[u8]>| {
+            some_file_overlaps_range(&icmp, &files, &KeyRange::from_options(s, l))
+        };
+
+        assert!(!overlaps(Some(b"100"), Some(b"149")));
+        assert!(!overlaps(Some(b"251"), Some(b"299")));
+        assert!(!overlaps(Some(b"351"), Some(b"400")));
+        assert!(overlaps(Some(b"100"), Some(b"150")));
+        assert!(overlaps(Some(b"200"), Some(b"200")));
+        assert!(overlaps(Some(b"240"), Some(b"310")));
+        assert!(overlaps(None, Some(b"150")));
+        assert!(!overlaps(None, Some(b"149")));
+        assert!(overlaps(Some(b"350"), None));
+        assert!(!overlaps(Some(b"351"), None));
+        assert!(overlaps(None, None));
+        assert!(!some_file_overlaps_range(&icmp, &[], &KeyRange::all()));
+        // Exclusive bounds do not overlap files that only touch them.
+        assert!(!some_file_overlaps_range(&icmp, &files, &KeyRange::half_open(b"100", b"150")));
+        assert!(some_file_overlaps_range(&icmp, &files, &KeyRange::prefix(b"3")));
+    }
+
+    #[test]
+    fn find_file_and_level_iterator() {
+        let icmp = InternalKeyComparator::new(bytewise_comparator());
+        let files = vec![file(1, b"150", b"200"), file(2, b"200", b"250"), file(3, b"300", b"350")];
+        let find = |key: &[u8]| find_file(&icmp, &files, InternalKey::new(key, 100, ValueType::Value).encode());
+        assert_eq!(find(b"100"), 0);
+        assert_eq!(find(b"200"), 0);
+        assert_eq!(find(b"201"), 1);
+        assert_eq!(find(b"251"), 2);
+        assert_eq!(find(b"350"), 2);
+        assert_eq!(find(b"351"), 3);
+        assert_eq!(find_file(&icmp, &[], b"foo"), 0);
+
+        let mut iter = LevelFileNumIterator::new(icmp.clone(), files.clone());
+        assert!(!iter.valid());
+        iter.seek(InternalKey::new(b"220", 100, ValueType::Value).encode());
+        assert_eq!(decode_fixed_64(iter.value()), 2);
+        assert_eq!(iter.key(), files[1].largest.encode());
+        iter.next();
+        assert_eq!(decode_fixed_64(iter.value()), 3);
+        iter.next();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn builder_validate() {
+        let icmp = InternalKeyComparator::new(bytewise_comparator());
+        let validate = |files: &[(usize, Arc<FileMetaData>)], next_file: u64| {
+            let mut edit = VersionEdit::new();
+            for (level, f) in files {
+                edit.add_file(*level, f.number, f.file_size, &f.smallest, &f.largest);
+            }
+            let mut builder = Builder::new(icmp.clone(), Arc::new(Version::new(icmp.clone())));
+            builder.apply(&edit, &mut Default::default());
+            builder.validate(next_file)
+        };
+
+        let overlap_l0 = [(0, file(1, b"100", b"200")), (0, file(2, b"150", b"250"))];
+        assert!(validate(&overlap_l0, 10).is_ok());
+        let overlap_l1 = [(1, file(1, b"100", b"200")), (1, file(2, b"150", b"250"))];
+        assert!(validate(&overlap_l1, 10).unwrap_err().is_corruption());
+        let disjoint_l1 = [(1, file(1, b"100", b"200")), (1, file(2, b"201", b"250"))];
+        assert!(validate(&disjoint_l1, 10).is_ok());
+        assert!(validate(&disjoint_l1, 2).unwrap_err().is_corruption());
+        assert!(validate(&[(2, file(3, b"300", b"200"))], 10).unwrap_err().is_corruption());
+    }
+
+    #[test]
+    fn overlapping_inputs_expand_on_level0() {
+        let icmp = InternalKeyComparator::new(bytewise_comparator());
+        let mut v = Version::new(icmp);
+        v.files[0] = vec![file(1, b"a", b"c"), file(2, b"b", b"e"), file(3, b"x", b"z")];
+        let inputs = v.get_overlapping_inputs(0, &KeyRange::inclusive(b"d", b"d"));
+        let mut numbers: Vec<u64> = inputs.iter().map(|f| f.number).collect();
+        numbers.sort();
+        assert_eq!(numbers, [1, 2]);
+    }
+
+    #[test]
+    fn subcompaction_boundaries() {
+        let icmp = InternalKeyComparator::new(bytewise_comparator());
+        let mut c = Compaction::new(&Options::default(), 0, Arc::new(Version::new(icmp)));
+        c.inputs[0] = vec![file(1, b"a", b"f"), file(2, b"c", b"k")];
+        c.inputs[1] = vec![file(3, b"b", b"d"), file(4, b"e", b"f")];
+        let boundaries = |max| c.subcompaction_boundaries(max);
+        assert!(boundaries(1).is_empty());
+        assert_eq!(boundaries(2), [b"e".to_vec()]);
+        assert_eq!(boundaries(3), [b"d".to_vec(), b"f".to_vec()]);
+        // There are no more parts than distinct file boundaries.
+        assert_eq!(boundaries(10).len(), 6);
+    }
+
+    #[test]
+    fn dynamic_level_bytes() {
+        const MB: f64 = 1048576.;
+        // A small database goes straight to the last level.
+        let max_bytes = dynamic_max_bytes_for_levels(5 << 20);
+        assert_eq!(max_bytes[..6], [0.; 6]);
+
+        // Each level below the base level holds ten times the one above.
+        let max_bytes = dynamic_max_bytes_for_levels(500 << 20);
+        assert_eq!(max_bytes[..4], [0.; 4]);
+        assert_eq!(max_bytes[4..], [10. * MB, 50. * MB, 500. * MB]);
+
+        let max_bytes = dynamic_max_bytes_for_levels(1 << 50);
+        assert_eq!(max_bytes[0], 0.);
+        assert_eq!(max_bytes[5], (1u64 << 50) as f64 / 10.);
+    }
+}
@@ -0,0 +1,557 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// WriteBatch::rep :=
+//    sequence: fixed64
+//    count: fixed32
+//    data: record[count]
+// record :=
+//    ValueType::Value varstring varstring         |
+//    ValueType::Deletion varstring                |
+//    ValueType::Merge varstring varstring
+// varstring :=
+//    len: varint32
+//    data: u8[len]
+//
+// Serialized form (see `WriteBatch::serialize`) :=
+//    version: u8
+//    len: varint32
+//    rep: u8[len]
+//    checksum: fixed32 (masked crc32c of the preceding bytes)
+
+use crate::{
+    db::{
+        dbformat::{config, sequences_available, SequenceNumber, ValueType},
+        memtable::MemTable,
+    },
+    leveldb::{
+        result::{Error, ErrorType, Result},
+        slice::BorrowedSlice,
+    },
+    util::{
+        coding::{
+            decode_fixed_32, decode_fixed_64, decode_varint_32, encode_fixed_32,
+            encode_fixed_32_vec, encode_fixed_64, encode_length_prefixed_slice, encode_varint_32_vec,
+        },
+        crc32c,
+    },
+};
+
+/// WriteBatch header has an 8-byte sequence number followed by a 4-byte count.
+const HEADER: usize = 12;
+
+/// Version byte written in front of a serialized batch. Bump it whenever the serialized
+/// layout changes; `deserialize` rejects versions it does not understand.
+const SERIALIZED_FORMAT_VERSION: u8 = 1;
+
+/// Support for iterating over the contents of a batch.
+pub trait Handler {
+    fn put(&mut self, key: &[u8], value: &[u8]);
+    fn delete(&mut self, key: &[u8]);
+    fn merge(&mut self, key: &[u8], value: &[u8]);
+}
+
+/// WriteBatch holds a collection of updates to apply atomically to a DB.
+///
+/// The updates are applied in the order in which they are added to the WriteBatch. For
+/// example, the value of "key" will be "v3" after the following batch is written:
+///
+/// ```ignore
+/// batch.put(b"key", b"v1");
+/// batch.delete(b"key");
+/// batch.put(b"key", b"v2");
+/// batch.put(b"key", b"v3");
+/// ```
+///
+/// Multiple threads can invoke const methods on a WriteBatch without external
+/// synchronization, but if any of the threads may call a non-const method, all threads
+/// accessing the same WriteBatch must use external synchronization.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteBatch {
+    rep: Vec<u8>,
+}
+
+impl Default for WriteBatch {
+    fn default() -> Self { Self::new() }
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self {
+            rep: vec![0; HEADER],
+        }
+    }
+
+    /// Store the mapping "key->value" in the database.
+    ///
+    /// Panics if `key` or `value` is 4GB or longer, which the batch format cannot
+    /// represent. `DB::write()` enforces the tighter `Options::max_key_size` and
+    /// `Options::max_value_size` limits.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        assert!(key.len() <= u32::MAX as usize, "key too large for a WriteBatch");
+        assert!(value.len() <= u32::MAX as usize, "value too large for a WriteBatch");
+        self.set_count(self.count() + 1);
+        self.rep.push(ValueType::Value as u8);
+        encode_length_prefixed_slice(&mut self.rep, key);
+        encode_length_prefixed_slice(&mut self.rep, value);
+    }
+
+    /// If the database contains a mapping for "key", erase it. Else do nothing.
+    pub fn delete(&mut self, key: &[u8]) {
+        assert!(key.len() <= u32::MAX as usize, "key too large for a WriteBatch");
+        self.set_count(self.count() + 1);
+        self.rep.push(ValueType::Deletion as u8);
+        encode_length_prefixed_slice(&mut self.rep, key);
+    }
+
+    /// Add `value` as a merge operand for "key". The operands are combined with the
+    /// existing value by the database's `Options::merge_operator` when "key" is read
+    /// or compacted.
+    pub fn merge(&mut self, key: &[u8], value: &[u8]) {
+        assert!(key.len() <= u32::MAX as usize, "key too large for a WriteBatch");
+        assert!(value.len() <= u32::MAX as usize, "value too large for a WriteBatch");
+        self.set_count(self.count() + 1);
+        self.rep.push(ValueType::Merge as u8);
+        encode_length_prefixed_slice(&mut self.rep, key);
+        encode_length_prefixed_slice(&mut self.rep, value);
+    }
+
+    /// Clear all updates buffered in this batch.
+    pub fn clear(&mut self) {
+        self.rep.clear();
+        self.rep.resize(HEADER, 0);
+    }
+
+    /// The size of the database changes caused by this batch.
+    ///
+    /// This number is tied to implementation details, and may change across releases. It
+    /// is intended for LevelDB usage metrics.
+    pub fn approximate_size(&self) -> usize { self.rep.len() }
+
+    /// Copies the operations in `source` to this batch.
+    ///
+    /// This runs in O(source size) time. However, the constant factor is better than
+    /// calling `iterate()` over the source batch with a Handler that replicates the
+    /// operations into this batch.
+    pub fn append(&mut self, source: &WriteBatch) {
+        self.set_count(self.count() + source.count());
+        assert!(source.rep.len() >= HEADER);
+        self.rep.extend_from_slice(&source.rep[HEADER..]);
+    }
+
+    /// Support for iterating over the contents of a batch.
+    pub fn iterate(&self, handler: &mut dyn Handler) -> Result<()> {
+        if self.rep.len() < HEADER {
+            return LEVELDB_ERR!(Corruption, "malformed WriteBatch (too small)");
+        }
+
+        let mut input = BorrowedSlice::from(&self.rep[HEADER..]);
+        let mut found = 0;
+        while !input.is_empty() {
+            found += 1;
+            let tag = input[0];
+            input.skip(1);
+            match ValueType::from_u8(tag) {
+                Some(ValueType::Value) => {
+                    let key = input.get_length_prefixed();
+                    let value = input.get_length_prefixed();
+                    match (key, value) {
+                        (Ok(key), Ok(value)) => handler.put(key.data(), value.data()),
+                        _ => return LEVELDB_ERR!(Corruption, "bad WriteBatch Put"),
+                    }
+                }
+                Some(ValueType::Deletion) => match input.get_length_prefixed() {
+                    Ok(key) => handler.delete(key.data()),
+                    Err(_) => return LEVELDB_ERR!(Corruption, "bad WriteBatch Delete"),
+                },
+                Some(ValueType::Merge) => {
+                    let key = input.get_length_prefixed();
+                    let value = input.get_length_prefixed();
+                    match (key, value) {
+                        (Ok(key), Ok(value)) => handler.merge(key.data(), value.data()),
+                        _ => return LEVELDB_ERR!(Corruption, "bad WriteBatch Merge"),
+                    }
+                }
+                None => return LEVELDB_ERR!(Corruption, "unknown WriteBatch tag"),
+            }
+        }
+        if found != self.count() {
+            LEVELDB_ERR!(Corruption, "WriteBatch has wrong count")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Return the number of entries in the batch.
+    pub fn count(&self) -> u32 { decode_fixed_32(&self.rep[8..]) }
+
+    /// Set the count for the number of entries in the batch.
+    pub(crate) fn set_count(&mut self, n: u32) { encode_fixed_32(&mut self.rep[8..], n) }
+
+    /// Return the sequence number for the start of this batch. Batches that have not
+    /// been written to a database carry sequence number zero.
+    pub fn sequence(&self) -> SequenceNumber { decode_fixed_64(&self.rep) }
+
+    /// Store the specified number as the sequence number for the start of this batch.
+    pub(crate) fn set_sequence(&mut self, seq: SequenceNumber) { encode_fixed_64(&mut self.rep, seq) }
+
+    /// Return the raw contents of the batch, as written to the log.
+    pub(crate) fn contents(&self) -> &[u8] { &self.rep }
+
+    /// Replace the contents of the batch with `contents`, e.g. a record read back from
+    /// the log.
+    pub(crate) fn set_contents(&mut self, contents: &[u8]) {
+        assert!(contents.len() >= HEADER);
+        self.rep.clear();
+        self.rep.extend_from_slice(contents);
+    }
+
+    /// Apply the updates in this batch to `memtable`, numbering them starting from the
+    /// batch's sequence number.
+    pub(crate) fn insert_into(&self, memtable: &MemTable) -> Result<()> {
+        if self.count() > 0 && !sequences_available(self.sequence(), self.count() as u64 - 1) {
+            return LEVELDB_ERR!(Corruption, "batch sequence numbers exceed the 56-bit limit");
+        }
+        #[cfg(test)]
+        if memtable.inserts_fail() {
+            return LEVELDB_ERR!(IOError, "injected memtable insert failure");
+        }
+        let mut inserter = MemTableInserter {
+            sequence: self.sequence(),
+            mem: memtable,
+        };
+        self.iterate(&mut inserter)
+    }
+
+    /// Check every key and value in the batch against the given limits and the format
+    /// limit `config::MAX_ENTRY_SIZE`, returning an `InvalidArgument` error naming the
+    /// first offending size.
+    pub(crate) fn check_entry_sizes(&self, max_key_size: usize, max_value_size: usize) -> Result<()> {
+        let mut checker = SizeChecker {
+            max_key_size,
+            max_value_size,
+            status: Ok(()),
+        };
+        self.iterate(&mut checker)?;
+        checker.status
+    }
+
+    /// Encode this batch into a self-describing buffer suitable for shipping to another
+    /// process (e.g. for replication). The result carries a format version and a
+    /// checksum so that `deserialize` can reject truncated or damaged input. The sequence
+    /// number stored in the batch is preserved.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut dst = Vec::with_capacity(self.rep.len() + 10);
+        dst.push(SERIALIZED_FORMAT_VERSION);
+        encode_varint_32_vec(&mut dst, self.rep.len() as u32);
+        dst.extend_from_slice(&self.rep);
+        let crc = crc32c::mask(crc32c::value(&dst));
+        encode_fixed_32_vec(&mut dst, crc);
+        dst
+    }
+
+    /// Decode a batch produced by `serialize`. The checksum, version, length and every
+    /// record of the batch are validated before the batch is returned.
+    pub fn deserialize(data: &[u8]) -> Result<WriteBatch> {
+        if data.len() < 1 + 1 + HEADER + 4 {
+            return LEVELDB_ERR!(Corruption, "serialized WriteBatch too small");
+        }
+        let (body, trailer) = data.split_at(data.len() - 4);
+        if crc32c::unmask(decode_fixed_32(trailer)) != crc32c::value(body) {
+            return LEVELDB_ERR!(Corruption, "serialized WriteBatch checksum mismatch");
+        }
+        if body[0] != SERIALIZED_FORMAT_VERSION {
+            return LEVELDB_ERR!(NotSupported, "unknown serialized WriteBatch version");
+        }
+        let (len, n) = decode_varint_32(&body[1..])?;
+        let rep = &body[1 + n..];
+        if rep.len() != len as usize || rep.len() < HEADER {
+            return LEVELDB_ERR!(Corruption, "serialized WriteBatch has bad length");
+        }
+
+        let mut batch = WriteBatch::new();
+        batch.set_contents(rep);
+        // Walk the records so that a malformed batch is rejected here rather than
+        // half-applied later.
+        batch.iterate(&mut CountingHandler)?;
+        Ok(batch)
+    }
+}
+
+struct CountingHandler;
+
+impl Handler for CountingHandler {
+    fn put(&mut self, _key: &[u8], _value: &[u8]) {}
+
+    fn delete(&mut self, _key: &[u8]) {}
+
+    fn merge(&mut self, _key: &[u8], _value: &[u8]) {}
+}
+
+struct SizeChecker {
+    max_key_size: usize,
+    max_value_size: usize,
+    status: Result<()>,
+}
+
+impl SizeChecker {
+    fn check(&mut self, key: &[u8], value: &[u8]) {
+        if self.status.is_err() {
+            return;
+        }
+        if key.len() > self.max_key_size {
+            self.status = Err(Error::new(ErrorType::InvalidArgument, "key too large")
+                .with_detail(format!("key is {} bytes, limit is {}", key.len(), self.max_key_size)));
+        } else if value.len() > self.max_value_size {
+            self.status = Err(Error::new(ErrorType::InvalidArgument, "value too large")
+                .with_detail(format!("value is {} bytes, limit is {}", value.len(), self.max_value_size)));
+        } else if key.len() + value.len() > config::MAX_ENTRY_SIZE {
+            self.status = Err(Error::new(ErrorType::InvalidArgument, "entry too large").with_detail(format!(
+                "key and value are {} bytes, limit is {}",
+                key.len() + value.len(),
+                config::MAX_ENTRY_SIZE
+            )));
+        }
+    }
+}
+
+impl Handler for SizeChecker {
+    fn put(&mut self, key: &[u8], value: &[u8]) { self.check(key, value) }
+
+    fn delete(&mut self, key: &[u8]) { self.check(key, &[]) }
+
+    fn merge(&mut self, key: &[u8], value: &[u8]) { self.check(key, value) }
+}
+
+struct MemTableInserter<'a> {
+    sequence: SequenceNumber,
+    mem: &'a MemTable,
+}
+
+impl Handler for MemTableInserter<'_> {
+    fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.mem.add(self.sequence, ValueType::Value, key, value);
+        self.sequence += 1;
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.mem.add(self.sequence, ValueType::Deletion, key, &[]);
+        self.sequence += 1;
+    }
+
+    fn merge(&mut self, key: &[u8], value: &[u8]) {
+        self.mem.add(self.sequence, ValueType::Merge, key, value);
+        self.sequence += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::leveldb::result::ErrorType;
+    use crate::{
+        db::dbformat::{parse_internal_key, InternalKeyComparator, MAX_SEQUENCE_NUMBER},
+        util::comparator::bytewise_comparator,
+    };
+
+    fn print_contents(b: &WriteBatch) -> String {
+        let mem = MemTable::new(InternalKeyComparator::new(bytewise_comparator()));
+        let s = b.insert_into(&mem);
+        let mut state = String::new();
+        let mut count = 0;
+        let mut iter = mem.new_iterator();
+        iter.seek_to_first();
+        while iter.valid() {
+            let ikey = parse_internal_key(iter.key()).unwrap();
+            match ikey.value_type {
+                ValueType::Value => {
+                    state.push_str(&format!(
+                        "Put({}, {})",
+                        String::from_utf8_lossy(ikey.user_key),
+                        String::from_utf8_lossy(iter.value())
+                    ));
+                }
+                ValueType::Deletion => {
+                    state.push_str(&format!("Delete({})", String::from_utf8_lossy(ikey.user_key)));
+                }
+                ValueType::Merge => {
+                    state.push_str(&format!(
+                        "Merge({}, {})",
+                        String::from_utf8_lossy(ikey.user_key),
+                        String::from_utf8_lossy(iter.value())
+                    ));
+                }
+            }
+            count += 1;
+            state.push_str(&format!("@{}", ikey.sequence));
+            iter.next();
+        }
+        if s.is_err() {
+            state.push_str("ParseError()");
+        } else if count != b.count() {
+            state.push_str("CountMismatch()");
+        }
+        state
+    }
+
+    #[test]
+    fn empty() {
+        let batch = WriteBatch::new();
+        assert_eq!("", print_contents(&batch));
+        assert_eq!(0, batch.count());
+    }
+
+    #[test]
+    fn sequence_overflow() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"foo", b"bar");
+        batch.delete(b"box");
+        batch.set_sequence(MAX_SEQUENCE_NUMBER - 1);
+        assert_eq!(
+            format!("Delete(box)@{}Put(foo, bar)@{}", MAX_SEQUENCE_NUMBER, MAX_SEQUENCE_NUMBER - 1),
+            print_contents(&batch)
+        );
+        batch.set_sequence(MAX_SEQUENCE_NUMBER);
+        assert_eq!("ParseError()", print_contents(&batch));
+    }
+
+    #[test]
+    fn multiple() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"foo", b"bar");
+        batch.delete(b"box");
+        batch.put(b"baz", b"boo");
+        batch.set_sequence(100);
+        assert_eq!(100, batch.sequence());
+        assert_eq!(3, batch.count());
+        assert_eq!("Put(baz, boo)@102Delete(box)@101Put(foo, bar)@100", print_contents(&batch));
+    }
+
+    #[test]
+    fn merge() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"foo", b"bar");
+        batch.merge(b"foo", b"baz");
+        batch.merge(b"box", b"");
+        batch.set_sequence(10);
+        assert_eq!(3, batch.count());
+        assert_eq!("Merge(box, )@12Merge(foo, baz)@11Put(foo, bar)@10", print_contents(&batch));
+    }
+
+    #[test]
+    fn corruption() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"foo", b"bar");
+        batch.delete(b"box");
+        batch.set_sequence(200);
+        let contents = batch.contents().to_vec();
+        batch.set_contents(&contents[..contents.len() - 1]);
+        assert_eq!("Put(foo, bar)@200ParseError()", print_contents(&batch));
+    }
+
+    #[test]
+    fn append() {
+        let mut b1 = WriteBatch::new();
+        let mut b2 = WriteBatch::new();
+        b1.set_sequence(200);
+        b2.set_sequence(300);
+        b1.append(&b2);
+        assert_eq!("", print_contents(&b1));
+        b2.put(b"a", b"va");
+        b1.append(&b2);
+        assert_eq!("Put(a, va)@200", print_contents(&b1));
+        b2.clear();
+        b2.put(b"b", b"vb");
+        b1.append(&b2);
+        assert_eq!("Put(a, va)@200Put(b, vb)@201", print_contents(&b1));
+        b2.delete(b"foo");
+        b1.append(&b2);
+        assert_eq!("Put(a, va)@200Put(b, vb)@202Put(b, vb)@201Delete(foo)@203", print_contents(&b1));
+    }
+
+    #[test]
+    fn approximate_size() {
+        let mut batch = WriteBatch::new();
+        let empty_size = batch.approximate_size();
+
+        batch.put(b"foo", b"bar");
+        let one_key_size = batch.approximate_size();
+        assert!(empty_size < one_key_size);
+
+        batch.put(b"baz", b"boo");
+        let two_keys_size = batch.approximate_size();
+        assert!(one_key_size < two_keys_size);
+
+        batch.delete(b"box");
+        let post_delete_size = batch.approximate_size();
+        assert!(two_keys_size < post_delete_size);
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"foo", b"bar");
+        batch.delete(b"box");
+        batch.put(b"", b"");
+        batch.set_sequence(42);
+
+        let data = batch.serialize();
+        let decoded = WriteBatch::deserialize(&data).expect("deserialize");
+        assert_eq!(decoded, batch);
+        assert_eq!(decoded.sequence(), 42);
+        assert_eq!(print_contents(&decoded), print_contents(&batch));
+
+        let empty = WriteBatch::deserialize(&WriteBatch::new().serialize()).expect("deserialize");
+        assert_eq!(empty.count(), 0);
+    }
+
+    #[test]
+    fn deserialize_rejects_damage() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"foo", b"bar");
+        let data = batch.serialize();
+
+        // Truncated input
+        for len in 0..data.len() {
+            assert!(WriteBatch::deserialize(&data[..len]).unwrap_err().is_corruption());
+        }
+
+        // Flipped bits anywhere are caught by the checksum
+        for i in 0..data.len() {
+            let mut damaged = data.clone();
+            damaged[i] ^= 0x10;
+            assert!(WriteBatch::deserialize(&damaged).unwrap_err().is_corruption());
+        }
+
+        // Unknown versions are reported as such even with a valid checksum
+        let mut future = data[..data.len() - 4].to_vec();
+        future[0] = SERIALIZED_FORMAT_VERSION + 1;
+        let crc = crc32c::mask(crc32c::value(&future));
+        encode_fixed_32_vec(&mut future, crc);
+        assert_eq!(WriteBatch::deserialize(&future).unwrap_err().ty(), ErrorType::NotSupported);
+
+        // A well-formed envelope around a malformed batch is rejected too
+        let mut bad = WriteBatch::new();
+        bad.put(b"foo", b"bar");
+        bad.set_count(2);
+        assert!(WriteBatch::deserialize(&bad.serialize()).unwrap_err().is_corruption());
+    }
+}
@@ -0,0 +1,605 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+// A C API modeled on the leveldb and rocksdb C APIs; include/hefesto_c.h declares it.
+// Objects are opaque pointers that the caller creates and destroys with the matching
+// functions. A function that can fail takes a `char** errptr`: on failure it stores a
+// malloc()ed error message there, freeing any message already stored, and leaves it
+// alone on success. Strings and values returned to the caller are malloc()ed too and
+// are released with hefesto_free() or free().
+//
+// Safety: every pointer argument must be NULL only where a function says so, object
+// pointers must come from this API and not yet be destroyed, and a key or value
+// pointer must point to at least its length in bytes. A DB must outlive its iterators.
+
+#![allow(non_camel_case_types, clippy::missing_safety_doc)]
+
+use std::{
+    ffi::{c_char, c_int, c_uchar, c_void, CStr},
+    ptr, slice,
+    sync::Arc,
+};
+
+use crate::{
+    db::{db_impl::destroy_db, repair::repair_db, snapshot::Snapshot, write_batch::WriteBatch, KeyRange, DB},
+    leveldb::{
+        filter_policy::FilterPolicy,
+        iterator::Iterator,
+        options::{new_block_cache, BlockCache, CompressionType, Options, ReadOptions, WriteOptions, ZSTD_DEFAULT_LEVEL},
+        result::Result,
+    },
+    util::bloom::new_bloom_filter_policy,
+};
+
+pub const HEFESTO_MAJOR_VERSION: c_int = 0;
+pub const HEFESTO_MINOR_VERSION: c_int = 1;
+
+/// Values of `hefesto_options_set_compression()`, numbered as in the rocksdb C API.
+pub const HEFESTO_NO_COMPRESSION: c_int = 0;
+pub const HEFESTO_LZ4_COMPRESSION: c_int = 4;
+pub const HEFESTO_ZSTD_COMPRESSION: c_int = 7;
+
+pub struct hefesto_db_t {
+    db: DB,
+}
+
+pub struct hefesto_iterator_t {
+    iter: Box<dyn Iterator>,
+}
+
+pub struct hefesto_writebatch_t {
+    batch: WriteBatch,
+}
+
+pub struct hefesto_snapshot_t {
+    snapshot: Snapshot,
+}
+
+pub struct hefesto_cache_t {
+    cache: Arc<BlockCache>,
+}
+
+pub struct hefesto_filterpolicy_t {
+    policy: Arc<dyn FilterPolicy>,
+}
+
+pub struct hefesto_options_t {
+    options: Options,
+}
+
+pub struct hefesto_readoptions_t {
+    options: ReadOptions,
+}
+
+pub struct hefesto_writeoptions_t {
+    options: WriteOptions,
+}
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+// Return a malloc()ed copy of `data`, followed by a NUL so that strings can be used as
+// C strings.
+fn copy_out(data: &[u8]) -> *mut c_char {
+    // SAFETY: The allocation has room for `data` and the NUL.
+    unsafe {
+        let result = malloc(data.len() + 1) as *mut u8;
+        if result.is_null() {
+            return ptr::null_mut();
+        }
+        ptr::copy_nonoverlapping(data.as_ptr(), result, data.len());
+        *result.add(data.len()) = 0;
+        result as *mut c_char
+    }
+}
+
+// Store the error of `result`, if any, in `errptr`. Returns the value on success.
+unsafe fn save_error<T>(errptr: *mut *mut c_char, result: Result<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            if !errptr.is_null() {
+                free(*errptr as *mut c_void);
+                *errptr = copy_out(err.to_string().as_bytes());
+            }
+            None
+        }
+    }
+}
+
+// The `len` bytes at `data`, which may be null if `len` is zero.
+unsafe fn bytes<'a>(data: *const c_char, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data as *const u8, len)
+    }
+}
+
+unsafe fn string(name: *const c_char) -> String { CStr::from_ptr(name).to_string_lossy().into_owned() }
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_open(
+    options: *const hefesto_options_t,
+    name: *const c_char,
+    errptr: *mut *mut c_char,
+) -> *mut hefesto_db_t {
+    match save_error(errptr, DB::open((*options).options.clone(), &string(name))) {
+        Some(db) => Box::into_raw(Box::new(hefesto_db_t { db })),
+        None => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_close(db: *mut hefesto_db_t) { drop(Box::from_raw(db)) }
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_put(
+    db: *mut hefesto_db_t,
+    options: *const hefesto_writeoptions_t,
+    key: *const c_char,
+    keylen: usize,
+    val: *const c_char,
+    vallen: usize,
+    errptr: *mut *mut c_char,
+) {
+    let result = (*db).db.put(&(*options).options, bytes(key, keylen), bytes(val, vallen));
+    save_error(errptr, result);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_delete(
+    db: *mut hefesto_db_t,
+    options: *const hefesto_writeoptions_t,
+    key: *const c_char,
+    keylen: usize,
+    errptr: *mut *mut c_char,
+) {
+    save_error(errptr, (*db).db.delete(&(*options).options, bytes(key, keylen)));
+}
+
+/// Apply the updates of `batch`, which keeps them and may be written again.
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_write(
+    db: *mut hefesto_db_t,
+    options: *const hefesto_writeoptions_t,
+    batch: *mut hefesto_writebatch_t,
+    errptr: *mut *mut c_char,
+) {
+    save_error(errptr, (*db).db.write(&(*options).options, (*batch).batch.clone()));
+}
+
+/// Returns NULL if the key is not found or an error occurs. Otherwise returns a
+/// malloc()ed copy of the value and stores its length in `*vallen`.
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_get(
+    db: *mut hefesto_db_t,
+    options: *const hefesto_readoptions_t,
+    key: *const c_char,
+    keylen: usize,
+    vallen: *mut usize,
+    errptr: *mut *mut c_char,
+) -> *mut c_char {
+    *vallen = 0;
+    match save_error(errptr, (*db).db.get(&(*options).options, bytes(key, keylen))) {
+        Some(Some(value)) => {
+            *vallen = value.len();
+            copy_out(&value)
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_create_iterator(
+    db: *mut hefesto_db_t,
+    options: *const hefesto_readoptions_t,
+) -> *mut hefesto_iterator_t {
+    let iter = (*db).db.new_iterator(&(*options).options);
+    Box::into_raw(Box::new(hefesto_iterator_t { iter }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_create_snapshot(db: *mut hefesto_db_t) -> *const hefesto_snapshot_t {
+    Box::into_raw(Box::new(hefesto_snapshot_t {
+        snapshot: (*db).db.get_snapshot(),
+    }))
+}
+
+/// Release `snapshot`. Read options it was set on keep it alive until they are
+/// destroyed or given another snapshot.
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_release_snapshot(_db: *mut hefesto_db_t, snapshot: *const hefesto_snapshot_t) {
+    drop(Box::from_raw(snapshot as *mut hefesto_snapshot_t))
+}
+
+/// Returns NULL if `propname` is not a property understood by the DB. Otherwise
+/// returns a malloc()ed NUL-terminated value.
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_property_value(db: *mut hefesto_db_t, propname: *const c_char) -> *mut c_char {
+    match (*db).db.get_property(&string(propname)) {
+        Some(value) => copy_out(value.as_bytes()),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Compact the keys in `[start_key, limit_key]`. A NULL key stands for the start or
+/// the end of the key space.
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_compact_range(
+    db: *mut hefesto_db_t,
+    start_key: *const c_char,
+    start_key_len: usize,
+    limit_key: *const c_char,
+    limit_key_len: usize,
+    errptr: *mut *mut c_char,
+) {
+    let start = (!start_key.is_null()).then(|| bytes(start_key, start_key_len));
+    let limit = (!limit_key.is_null()).then(|| bytes(limit_key, limit_key_len));
+    save_error(errptr, (*db).db.compact_range(&KeyRange::from_options(start, limit)));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_destroy_db(
+    options: *const hefesto_options_t,
+    name: *const c_char,
+    errptr: *mut *mut c_char,
+) {
+    save_error(errptr, destroy_db(&string(name), &(*options).options));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_repair_db(
+    options: *const hefesto_options_t,
+    name: *const c_char,
+    errptr: *mut *mut c_char,
+) {
+    save_error(errptr, repair_db(&string(name), &(*options).options));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_iter_destroy(iter: *mut hefesto_iterator_t) { drop(Box::from_raw(iter)) }
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_iter_valid(iter: *const hefesto_iterator_t) -> c_uchar { (*iter).iter.valid() as c_uchar }
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_iter_seek_to_first(iter: *mut hefesto_iterator_t) { (*iter).iter.seek_to_first() }
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_iter_seek_to_last(iter: *mut hefesto_iterator_t) { (*iter).iter.seek_to_last() }
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_iter_seek(iter: *mut hefesto_iterator_t, k: *const c_char, klen: usize) {
+    (*iter).iter.seek(bytes(k, klen))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_iter_next(iter: *mut hefesto_iterator_t) { (*iter).iter.next() }
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_iter_prev(iter: *mut hefesto_iterator_t) { (*iter).iter.prev() }
+
+/// The key at the current position, valid until the iterator is moved or destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_iter_key(iter: *const hefesto_iterator_t, klen: *mut usize) -> *const c_char {
+    let key = (*iter).iter.key();
+    *klen = key.len();
+    key.as_ptr() as *const c_char
+}
+
+/// The value at the current position, valid until the iterator is moved or destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_iter_value(iter: *const hefesto_iterator_t, vlen: *mut usize) -> *const c_char {
+    let value = (*iter).iter.value();
+    *vlen = value.len();
+    value.as_ptr() as *const c_char
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_iter_get_error(iter: *const hefesto_iterator_t, errptr: *mut *mut c_char) {
+    save_error(errptr, (*iter).iter.status());
+}
+
+#[no_mangle]
+pub extern "C" fn hefesto_writebatch_create() -> *mut hefesto_writebatch_t {
+    Box::into_raw(Box::new(hefesto_writebatch_t { batch: WriteBatch::new() }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_writebatch_destroy(batch: *mut hefesto_writebatch_t) { drop(Box::from_raw(batch)) }
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_writebatch_clear(batch: *mut hefesto_writebatch_t) { (*batch).batch.clear() }
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_writebatch_put(
+    batch: *mut hefesto_writebatch_t,
+    key: *const c_char,
+    klen: usize,
+    val: *const c_char,
+    vlen: usize,
+) {
+    (*batch).batch.put(bytes(key, klen), bytes(val, vlen))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_writebatch_delete(batch: *mut hefesto_writebatch_t, key: *const c_char, klen: usize) {
+    (*batch).batch.delete(bytes(key, klen))
+}
+
+#[no_mangle]
+pub extern "C" fn hefesto_options_create() -> *mut hefesto_options_t {
+    Box::into_raw(Box::new(hefesto_options_t { options: Options::default() }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_destroy(options: *mut hefesto_options_t) { drop(Box::from_raw(options)) }
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_set_create_if_missing(options: *mut hefesto_options_t, v: c_uchar) {
+    (*options).options.create_if_missing = v != 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_set_error_if_exists(options: *mut hefesto_options_t, v: c_uchar) {
+    (*options).options.error_if_exists = v != 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_set_paranoid_checks(options: *mut hefesto_options_t, v: c_uchar) {
+    (*options).options.paranoid_checks = v != 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_set_write_buffer_size(options: *mut hefesto_options_t, size: usize) {
+    (*options).options.write_buffer_size = size;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_set_max_open_files(options: *mut hefesto_options_t, n: c_int) {
+    (*options).options.max_open_files = n;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_set_cache(options: *mut hefesto_options_t, cache: *mut hefesto_cache_t) {
+    (*options).options.block_cache = Some((*cache).cache.clone());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_set_block_size(options: *mut hefesto_options_t, size: usize) {
+    (*options).options.block_size = size;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_set_block_restart_interval(options: *mut hefesto_options_t, n: c_int) {
+    (*options).options.block_restart_interval = n.max(1) as usize;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_set_max_file_size(options: *mut hefesto_options_t, size: usize) {
+    (*options).options.max_file_size = size;
+}
+
+/// Set the compression of table blocks to one of the `HEFESTO_*_COMPRESSION` values.
+/// Other values leave the compression unchanged.
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_set_compression(options: *mut hefesto_options_t, compression: c_int) {
+    let compression = match compression {
+        HEFESTO_NO_COMPRESSION => CompressionType::NoCompression,
+        HEFESTO_LZ4_COMPRESSION => CompressionType::Lz4,
+        HEFESTO_ZSTD_COMPRESSION => CompressionType::Zstd { level: ZSTD_DEFAULT_LEVEL },
+        _ => return,
+    };
+    (*options).options.compression = compression;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_options_set_filter_policy(
+    options: *mut hefesto_options_t,
+    policy: *mut hefesto_filterpolicy_t,
+) {
+    (*options).options.filter_policy = (!policy.is_null()).then(|| (*policy).policy.clone());
+}
+
+#[no_mangle]
+pub extern "C" fn hefesto_cache_create_lru(capacity: usize) -> *mut hefesto_cache_t {
+    Box::into_raw(Box::new(hefesto_cache_t {
+        cache: new_block_cache(capacity),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_cache_destroy(cache: *mut hefesto_cache_t) { drop(Box::from_raw(cache)) }
+
+#[no_mangle]
+pub extern "C" fn hefesto_filterpolicy_create_bloom(bits_per_key: c_int) -> *mut hefesto_filterpolicy_t {
+    Box::into_raw(Box::new(hefesto_filterpolicy_t {
+        policy: new_bloom_filter_policy(bits_per_key.max(0) as usize),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_filterpolicy_destroy(policy: *mut hefesto_filterpolicy_t) {
+    drop(Box::from_raw(policy))
+}
+
+#[no_mangle]
+pub extern "C" fn hefesto_readoptions_create() -> *mut hefesto_readoptions_t {
+    Box::into_raw(Box::new(hefesto_readoptions_t {
+        options: ReadOptions::default(),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_readoptions_destroy(options: *mut hefesto_readoptions_t) {
+    drop(Box::from_raw(options))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_readoptions_set_verify_checksums(options: *mut hefesto_readoptions_t, v: c_uchar) {
+    (*options).options.verify_checksums = v != 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_readoptions_set_fill_cache(options: *mut hefesto_readoptions_t, v: c_uchar) {
+    (*options).options.fill_cache = v != 0;
+}
+
+/// Read as of `snapshot`, or of the latest state if it is NULL.
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_readoptions_set_snapshot(
+    options: *mut hefesto_readoptions_t,
+    snapshot: *const hefesto_snapshot_t,
+) {
+    (*options).options.snapshot = (!snapshot.is_null()).then(|| (*snapshot).snapshot.clone());
+}
+
+#[no_mangle]
+pub extern "C" fn hefesto_writeoptions_create() -> *mut hefesto_writeoptions_t {
+    Box::into_raw(Box::new(hefesto_writeoptions_t {
+        options: WriteOptions::default(),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_writeoptions_destroy(options: *mut hefesto_writeoptions_t) {
+    drop(Box::from_raw(options))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_writeoptions_set_sync(options: *mut hefesto_writeoptions_t, v: c_uchar) {
+    (*options).options.sync = v != 0;
+}
+
+/// Free memory returned by this API, such as values and error messages.
+#[no_mangle]
+pub unsafe extern "C" fn hefesto_free(ptr: *mut c_void) { free(ptr) }
+
+#[no_mangle]
+pub extern "C" fn hefesto_major_version() -> c_int { HEFESTO_MAJOR_VERSION }
+
+#[no_mangle]
+pub extern "C" fn hefesto_minor_version() -> c_int { HEFESTO_MINOR_VERSION }
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+    use crate::util::testutil::tmp_dir;
+
+    unsafe fn take_string(ptr: *mut c_char, len: usize) -> Vec<u8> {
+        let result = bytes(ptr, len).to_vec();
+        hefesto_free(ptr as *mut c_void);
+        result
+    }
+
+    unsafe fn get(db: *mut hefesto_db_t, options: *const hefesto_readoptions_t, key: &[u8]) -> Option<Vec<u8>> {
+        let (mut len, mut err) = (0, ptr::null_mut());
+        let value = hefesto_get(db, options, key.as_ptr() as _, key.len(), &mut len, &mut err);
+        assert!(err.is_null());
+        (!value.is_null()).then(|| take_string(value, len))
+    }
+
+    #[test]
+    fn c_api() {
+        let name = CString::new(tmp_dir("hefesto_c")).unwrap();
+        unsafe {
+            let options = hefesto_options_create();
+            let policy = hefesto_filterpolicy_create_bloom(10);
+            let cache = hefesto_cache_create_lru(1 << 20);
+            hefesto_options_set_filter_policy(options, policy);
+            hefesto_options_set_cache(options, cache);
+            hefesto_options_set_compression(options, HEFESTO_ZSTD_COMPRESSION);
+            let ro = hefesto_readoptions_create();
+            let wo = hefesto_writeoptions_create();
+            hefesto_writeoptions_set_sync(wo, 1);
+
+            // Errors are reported as strings, and replace earlier ones.
+            let mut err = copy_out(b"earlier error");
+            assert!(hefesto_open(options, name.as_ptr(), &mut err).is_null());
+            let message = CStr::from_ptr(err).to_string_lossy().into_owned();
+            assert!(message.contains("does not exist"), "{}", message);
+            hefesto_free(err as *mut c_void);
+
+            let mut err = ptr::null_mut();
+            hefesto_options_set_create_if_missing(options, 1);
+            let db = hefesto_open(options, name.as_ptr(), &mut err);
+            assert!(err.is_null());
+
+            hefesto_put(db, wo, b"foo".as_ptr() as _, 3, b"hello".as_ptr() as _, 5, &mut err);
+            assert_eq!(get(db, ro, b"foo").unwrap(), b"hello");
+            let snapshot = hefesto_create_snapshot(db);
+
+            let batch = hefesto_writebatch_create();
+            hefesto_writebatch_put(batch, b"bar".as_ptr() as _, 3, b"b".as_ptr() as _, 1);
+            hefesto_writebatch_put(batch, b"box".as_ptr() as _, 3, b"c".as_ptr() as _, 1);
+            hefesto_writebatch_delete(batch, b"foo".as_ptr() as _, 3);
+            hefesto_write(db, wo, batch, &mut err);
+            hefesto_writebatch_destroy(batch);
+            hefesto_delete(db, wo, b"box".as_ptr() as _, 3, &mut err);
+            assert!(err.is_null());
+            assert_eq!(get(db, ro, b"foo"), None);
+            assert_eq!(get(db, ro, b"bar").unwrap(), b"b");
+
+            hefesto_readoptions_set_snapshot(ro, snapshot);
+            hefesto_release_snapshot(db, snapshot);
+            assert_eq!(get(db, ro, b"foo").unwrap(), b"hello");
+            hefesto_readoptions_set_snapshot(ro, ptr::null());
+
+            hefesto_compact_range(db, ptr::null(), 0, ptr::null(), 0, &mut err);
+            assert!(err.is_null());
+            let prop = CString::new("hefesto.num-files-at-level0").unwrap();
+            let value = hefesto_property_value(db, prop.as_ptr());
+            assert!(!value.is_null());
+            hefesto_free(value as *mut c_void);
+
+            let iter = hefesto_create_iterator(db, ro);
+            hefesto_iter_seek_to_first(iter);
+            let mut entries = Vec::new();
+            while hefesto_iter_valid(iter) != 0 {
+                let (mut klen, mut vlen) = (0, 0);
+                let key = hefesto_iter_key(iter, &mut klen);
+                let value = hefesto_iter_value(iter, &mut vlen);
+                entries.push((bytes(key, klen).to_vec(), bytes(value, vlen).to_vec()));
+                hefesto_iter_next(iter);
+            }
+            assert_eq!(entries, vec![(b"bar".to_vec(), b"b".to_vec())]);
+            hefesto_iter_get_error(iter, &mut err);
+            assert!(err.is_null());
+            hefesto_iter_destroy(iter);
+
+            hefesto_close(db);
+            hefesto_destroy_db(options, name.as_ptr(), &mut err);
+            assert!(err.is_null());
+
+            hefesto_writeoptions_destroy(wo);
+            hefesto_readoptions_destroy(ro);
+            hefesto_cache_destroy(cache);
+            hefesto_filterpolicy_destroy(policy);
+            hefesto_options_destroy(options);
+        }
+    }
+}
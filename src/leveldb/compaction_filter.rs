@@ -0,0 +1,61 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::leveldb::listener::CompactionJobInfo;
+
+/// What a `CompactionFilter` does with an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactionFilterDecision {
+    /// Keep the entry as it is.
+    Keep,
+    /// Remove the entry, as if the key had been deleted when it was written.
+    Remove,
+    /// Keep the key with a new value.
+    ChangeValue(Vec<u8>),
+}
+
+/// A CompactionFilter sees the values that compactions rewrite and may drop them or
+/// change them, e.g. to expire records by an application-level retention rule without
+/// issuing deletes.
+///
+/// The filter is only called for values that no snapshot can tell apart from their
+/// replacement, i.e. the newest value of a key written before the oldest live
+/// snapshot. Deletions, merge operands and entries hidden by newer ones are not
+/// passed to it, nor are entries written by flushes or moved by trivial moves, so a
+/// removed entry may still be read until a compaction reaches it.
+pub trait CompactionFilter: Send + Sync {
+    /// The name of the filter, for logging.
+    fn name(&self) -> &str;
+
+    /// Decide what to do with the `value` of `key`, which is being compacted from
+    /// `level` into the next level.
+    fn filter(&self, level: usize, key: &[u8], value: &[u8]) -> CompactionFilterDecision;
+}
+
+/// Creates a `CompactionFilter` for each compaction, for filters that keep state
+/// across the entries of one compaction or that depend on the compaction.
+pub trait CompactionFilterFactory: Send + Sync {
+    /// The name of the factory, for logging.
+    fn name(&self) -> &str;
+
+    /// Return the filter for the compaction described by `info`.
+    fn create_compaction_filter(&self, info: &CompactionJobInfo) -> Box<dyn CompactionFilter>;
+}
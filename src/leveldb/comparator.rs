@@ -0,0 +1,51 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::cmp::Ordering;
+
+/// A Comparator object provides a total order across slices that are used as keys in
+/// an sstable or a database. A Comparator implementation must be thread-safe since
+/// it may be invoked concurrently from multiple threads.
+pub trait Comparator: Send + Sync {
+    /// Three-way comparison. Returns value:
+    ///   `Ordering::Less`    iff `a` < `b`
+    ///   `Ordering::Equal`   iff `a` = `b`
+    ///   `Ordering::Greater` iff `a` > `b`
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+
+    /// The name of the comparator. Used to check for comparator mismatches (i.e., a DB
+    /// created with one comparator is accessed using a different comparator).
+    ///
+    /// The client of this package should switch to a new name whenever the comparator
+    /// implementation changes in a way that will cause the relative ordering of any two
+    /// keys to change.
+    fn name(&self) -> &'static str;
+
+    /// If `start < limit`, changes `start` to a short string in `[start, limit)`.
+    /// Simple comparator implementations may return with `start` unchanged, i.e., an
+    /// implementation of this method that does nothing is correct.
+    fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &[u8]);
+
+    /// Changes `key` to a short string >= `key`.
+    /// Simple comparator implementations may return with `key` unchanged, i.e., an
+    /// implementation of this method that does nothing is correct.
+    fn find_short_successor(&self, key: &mut Vec<u8>);
+}
@@ -0,0 +1,345 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{fmt, sync::Arc};
+
+use crate::{
+    leveldb::result::{Error, ErrorType, Result},
+    util::logger::FileLogger,
+};
+
+/// A file abstraction for reading sequentially through a file
+pub trait SequentialFile: Send {
+    /// Read up to `buf.len()` bytes from the file into `buf`. Returns the number of bytes
+    /// read; 0 means the end of the file has been reached.
+    ///
+    /// REQUIRES: External synchronization
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Skip `n` bytes from the file. This is guaranteed to be no slower that reading the
+    /// same data, but may be faster.
+    ///
+    /// If end of file is reached, skipping will stop at the end of the file, and skip
+    /// will return OK.
+    ///
+    /// REQUIRES: External synchronization
+    fn skip(&mut self, n: u64) -> Result<()>;
+}
+
+/// A file abstraction for randomly reading the contents of a file.
+pub trait RandomAccessFile: Send + Sync {
+    /// Read up to `buf.len()` bytes from the file starting at `offset` into `buf`.
+    /// Returns the number of bytes read, which is less than `buf.len()` only if the end
+    /// of the file is reached.
+    ///
+    /// Safe for concurrent use by multiple threads.
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize>;
+
+    /// Returns the whole contents of the file if they are already in memory, e.g.
+    /// because the file is memory mapped, so that callers can use them without a read
+    /// or a copy.
+    fn mapped(&self) -> Option<&[u8]> { None }
+
+    /// Advise that the `len` bytes from `offset` on will be read soon, so that they
+    /// can be read ahead of time. Only a hint: the default does nothing.
+    fn prefetch(&self, _offset: u64, _len: usize) {}
+}
+
+/// A file abstraction for sequential writing. The implementation must provide
+/// buffering since callers may append small fragments at a time to the file.
+pub trait WritableFile: Send {
+    /// Add `data` to the end of the file. The data may be held in a buffer and is not
+    /// guaranteed to reach the operating system until `flush()`, `sync()` or `close()`.
+    fn append(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Flush any buffered data and close the file. Dropping an unclosed file also
+    /// flushes it, but any error is lost.
+    fn close(&mut self) -> Result<()>;
+
+    /// Hand all buffered data to the operating system. The data survives a crash of
+    /// the process but not of the machine.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Flush, then force the file contents to durable storage. The data survives a
+    /// crash of the machine. Syncing a MANIFEST file also syncs its directory so the
+    /// new file's name is durable too.
+    fn sync(&mut self) -> Result<()>;
+
+    /// Reserve disk space for the file in blocks of `size` bytes as it grows, e.g. with
+    /// `fallocate()`, so that it is laid out contiguously. Space reserved past the end
+    /// of the file is released when it is closed. Zero turns preallocation off. Only a
+    /// hint: the default does nothing.
+    fn set_preallocation_block_size(&mut self, _size: usize) {}
+
+    /// Start writing the file back to disk, without waiting, each time another `bytes`
+    /// have been written to it, e.g. with `sync_file_range()`, so that a later `sync()`
+    /// or `close()` does not stall on flushing all of it at once. Zero turns this off.
+    /// Only a hint: the default does nothing.
+    fn set_bytes_per_sync(&mut self, _bytes: usize) {}
+}
+
+/// Identifies a locked file. Dropping it releases the lock.
+pub trait FileLock: Send {}
+
+/// The severity of an info log message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InfoLogLevel {
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl InfoLogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            InfoLogLevel::Info => "INFO",
+            InfoLogLevel::Warn => "WARN",
+            InfoLogLevel::Error => "ERROR",
+        }
+    }
+}
+
+/// An interface for writing log messages.
+pub trait Logger: Send + Sync {
+    /// Write an entry with the specified level to the log.
+    fn logv(&self, level: InfoLogLevel, args: fmt::Arguments<'_>);
+
+    fn info(&self, args: fmt::Arguments<'_>) { self.logv(InfoLogLevel::Info, args) }
+
+    fn warn(&self, args: fmt::Arguments<'_>) { self.logv(InfoLogLevel::Warn, args) }
+
+    fn error(&self, args: fmt::Arguments<'_>) { self.logv(InfoLogLevel::Error, args) }
+}
+
+/// A unit of background work.
+pub type Job = Box<dyn FnOnce() + Send>;
+
+/// Identifies one of the pools of background threads. Each pool has its own queue, so
+/// short, urgent jobs such as memtable flushes are not starved behind long
+/// compactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// For flushes and other work that writers may be waiting on.
+    High,
+    /// For compactions.
+    Low,
+}
+
+/// An Env is an interface used by the leveldb implementation to access operating system
+/// functionality like the filesystem etc. Callers may wish to provide a custom Env
+/// object when opening a database to get fine gain control; e.g., to rate limit file
+/// system operations.
+///
+/// All Env implementations are safe for concurrent access from multiple threads without
+/// any external synchronization.
+pub trait Env: Send + Sync {
+    /// Create an object that sequentially reads the file with the specified name.
+    /// On success, returns the new file. On failure returns an error; if the file does
+    /// not exist, the error is `ErrorType::NotFound`.
+    fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn SequentialFile>>;
+
+    /// Create an object supporting random-access reads from the file with the specified
+    /// name. If the file does not exist, returns an `ErrorType::NotFound` error.
+    fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn RandomAccessFile>>;
+
+    /// Create an object that writes to a new file with the specified name. Deletes any
+    /// existing file with the same name and creates a new file.
+    fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>>;
+
+    /// Returns true iff the named file exists.
+    fn file_exists(&self, fname: &str) -> bool;
+
+    /// Return the names of the children of the specified directory. The names are
+    /// relative to `dir`.
+    fn get_children(&self, dir: &str) -> Result<Vec<String>>;
+
+    /// Delete the named file.
+    fn remove_file(&self, fname: &str) -> Result<()>;
+
+    /// Create the specified directory.
+    fn create_dir(&self, dirname: &str) -> Result<()>;
+
+    /// Delete the specified directory.
+    fn remove_dir(&self, dirname: &str) -> Result<()>;
+
+    /// Return the size of `fname`.
+    fn get_file_size(&self, fname: &str) -> Result<u64>;
+
+    /// Rename file `src` to `target`.
+    fn rename_file(&self, src: &str, target: &str) -> Result<()>;
+
+    /// Create `target` as a hard link to the existing file `src`.
+    ///
+    /// The default implementation returns a `NotSupported` error, in which case
+    /// callers fall back to copying the file.
+    fn link_file(&self, _src: &str, _target: &str) -> Result<()> {
+        LEVELDB_ERR!(NotSupported, "hard links not supported")
+    }
+
+    /// Create and return a log file for storing informational messages. Any existing
+    /// file with the same name is replaced.
+    ///
+    /// The default implementation writes one timestamped line per message to a file
+    /// created with `new_writable_file`. The timestamps come from the system clock, so
+    /// an Env for a target without one should override this method, e.g. with a
+    /// `FileLogger::with_clock()`.
+    fn new_logger(&self, fname: &str) -> Result<Box<dyn Logger>> {
+        let file = self.new_writable_file(fname)?;
+        Ok(Box::new(FileLogger::new(file)))
+    }
+
+    /// Lock the specified file. Used to prevent concurrent access to the same db by
+    /// multiple processes. On failure, returns an error.
+    ///
+    /// On success, returns an object that represents the acquired lock. The caller
+    /// should call `unlock_file(lock)` to release the lock. If the process exits, the
+    /// lock will be automatically released.
+    ///
+    /// If somebody else already holds the lock, finishes immediately with a failure.
+    /// I.e., this call does not wait for existing locks to go away.
+    ///
+    /// May create the named file if it does not already exist.
+    fn lock_file(&self, fname: &str) -> Result<Box<dyn FileLock>>;
+
+    /// Release the lock acquired by a previous successful call to `lock_file`.
+    fn unlock_file(&self, lock: Box<dyn FileLock>) -> Result<()> {
+        drop(lock);
+        Ok(())
+    }
+
+    /// Arrange to run `job` once on a background thread of the pool for `priority`.
+    /// Jobs of the same pool start in the order they were scheduled, but may run
+    /// concurrently if the pool has several threads.
+    ///
+    /// The default implementation uses the thread pools of the default Env.
+    fn schedule(&self, job: Job, priority: Priority) { default_env().schedule(job, priority) }
+
+    /// Set the number of background threads of the pool for `priority`. A pool always
+    /// has at least one thread. Threads beyond a lowered count exit once they finish
+    /// their current job.
+    ///
+    /// The default implementation uses the thread pools of the default Env.
+    fn set_background_threads(&self, n: usize, priority: Priority) { default_env().set_background_threads(n, priority) }
+
+    /// Returns the number of micro-seconds since some fixed point in time. Only useful
+    /// for computing deltas of time.
+    fn now_micros(&self) -> u64;
+
+    /// Sleep or otherwise wait for `micros` micro-seconds. The database waits this
+    /// way when it throttles writes behind compactions.
+    ///
+    /// The default implementation puts the current thread to sleep.
+    fn sleep_for_microseconds(&self, micros: u64) { std::thread::sleep(std::time::Duration::from_micros(micros)) }
+}
+
+/// Return a default environment suitable for the current operating system. Without
+/// the `std` feature this is a process-wide `MemEnv`.
+#[cfg(feature = "std")]
+pub fn default_env() -> Arc<dyn Env> { crate::util::env_posix::default_env() }
+
+/// Return a default environment suitable for the current operating system. Without
+/// the `std` feature this is a process-wide `MemEnv`.
+#[cfg(not(feature = "std"))]
+pub fn default_env() -> Arc<dyn Env> {
+    static ENV: std::sync::OnceLock<Arc<dyn Env>> = std::sync::OnceLock::new();
+    ENV.get_or_init(|| Arc::new(crate::util::mem_env::MemEnv::new())).clone()
+}
+
+/// A utility routine: write `data` to the named file.
+pub fn write_string_to_file(env: &dyn Env, data: &[u8], fname: &str) -> Result<()> {
+    do_write_string_to_file(env, data, fname, false)
+}
+
+/// A utility routine: write `data` to the named file and sync it.
+pub fn write_string_to_file_sync(env: &dyn Env, data: &[u8], fname: &str) -> Result<()> {
+    do_write_string_to_file(env, data, fname, true)
+}
+
+fn do_write_string_to_file(env: &dyn Env, data: &[u8], fname: &str, should_sync: bool) -> Result<()> {
+    let mut file = env.new_writable_file(fname)?;
+    let mut s = file.append(data);
+    if s.is_ok() && should_sync {
+        s = file.sync();
+    }
+    if s.is_ok() {
+        s = file.close();
+    }
+    if s.is_err() {
+        let _ = env.remove_file(fname);
+    }
+    s
+}
+
+/// A utility routine: read contents of named file into a vector.
+pub fn read_file_to_string(env: &dyn Env, fname: &str) -> Result<Vec<u8>> {
+    const BUFFER_SIZE: usize = 8192;
+    let mut file = env.new_sequential_file(fname)?;
+    let mut data = Vec::new();
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    Ok(data)
+}
+
+/// A utility routine: copy the first `size` bytes of `src` to a new file `target` and
+/// sync it.
+pub fn copy_file(env: &dyn Env, src: &str, target: &str, size: u64) -> Result<()> {
+    const BUFFER_SIZE: usize = 64 * 1024;
+    let mut input = env.new_sequential_file(src)?;
+    let mut output = env.new_writable_file(target)?;
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    let mut remaining = size;
+    while remaining > 0 {
+        let want = remaining.min(BUFFER_SIZE as u64) as usize;
+        let n = input.read(&mut buf[..want])?;
+        if n == 0 {
+            return Err(Error::new(ErrorType::Corruption, "file shorter than expected").with_detail(src.to_string()));
+        }
+        output.append(&buf[..n])?;
+        remaining -= n as u64;
+    }
+    output.sync()?;
+    output.close()
+}
+
+/// A utility routine: make `target` a hard link to `src`, or a copy of its first
+/// `size` bytes if the Env or file system cannot link it.
+pub fn link_or_copy_file(env: &dyn Env, src: &str, target: &str, size: u64) -> Result<()> {
+    match env.link_file(src, target) {
+        Ok(()) => Ok(()),
+        Err(_) => copy_file(env, src, target, size),
+    }
+}
+
+/// Read exactly `buf.len()` bytes at `offset`, failing if the file is too short.
+pub fn read_exact_at(file: &dyn RandomAccessFile, offset: u64, buf: &mut [u8]) -> Result<()> {
+    let n = file.read(offset, buf)?;
+    if n != buf.len() {
+        return Err(Error::new(ErrorType::Corruption, "truncated read"));
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+/// A database can be configured with a custom FilterPolicy object. This object is
+/// responsible for creating a small filter from a set of keys. These filters are stored
+/// in leveldb and are consulted automatically by leveldb to decide whether or not to
+/// read some information from disk. In many cases, a filter can cut down the number of
+/// disk seeks form a handful to a single disk seek per `DB::get()` call.
+pub trait FilterPolicy: Send + Sync {
+    /// Return the name of this policy. Note that if the filter encoding changes in an
+    /// incompatible way, the name returned by this method must be changed. Otherwise,
+    /// old incompatible filters may be passed to methods of this type.
+    fn name(&self) -> &'static str;
+
+    /// `keys` contains a list of keys (potentially with duplicates) that are ordered
+    /// according to the user supplied comparator. Append a filter that summarizes `keys`
+    /// to `dst`.
+    fn create_filter(&self, keys: &[&[u8]], dst: &mut Vec<u8>);
+
+    /// `filter` contains the data appended by a preceding call to `create_filter()` on
+    /// this type. This method must return true if the key was in the list of keys passed
+    /// to `create_filter()`. This method may return true or false if the key was not on
+    /// the list, but it should aim to return false with a high probability.
+    fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool;
+
+    /// Batched form of `key_may_match()`: set `results[i]` to whether `keys[i]` may be
+    /// in `filter`. Policies can override this to decode the filter once and overlap
+    /// the memory accesses of many keys.
+    ///
+    /// REQUIRES: `keys.len() == results.len()`
+    fn keys_may_match(&self, keys: &[&[u8]], filter: &[u8], results: &mut [bool]) {
+        assert_eq!(keys.len(), results.len());
+        for (key, result) in keys.iter().zip(results.iter_mut()) {
+            *result = self.key_may_match(key, filter);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::leveldb::result::{Error, Result};
+
+/// An iterator yields a sequence of key/value pairs from a source.
+///
+/// Multiple threads can invoke const methods on an Iterator without external
+/// synchronization, but if any of the threads may call a non-const method, all threads
+/// accessing the same Iterator must use external synchronization.
+pub trait Iterator {
+    /// An iterator is either positioned at a key/value pair, or not valid. This method
+    /// returns true iff the iterator is valid.
+    fn valid(&self) -> bool;
+
+    /// Position at the first key in the source. The iterator is `valid()` after this
+    /// call iff the source is not empty.
+    fn seek_to_first(&mut self);
+
+    /// Position at the last key in the source. The iterator is `valid()` after this
+    /// call iff the source is not empty.
+    fn seek_to_last(&mut self);
+
+    /// Position at the first key in the source that is at or past `target`. The
+    /// iterator is `valid()` after this call iff the source contains an entry that comes
+    /// at or past `target`.
+    fn seek(&mut self, target: &[u8]);
+
+    /// Position at the last key in the source that is at or before `target`. The
+    /// iterator is `valid()` after this call iff the source contains an entry that comes
+    /// at or before `target`.
+    ///
+    /// The default implementation assumes that only identical keys compare equal.
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        self.seek(target);
+        if !self.valid() {
+            self.seek_to_last();
+        } else if self.key() != target {
+            self.prev();
+        }
+    }
+
+    /// Moves to the next entry in the source. After this call, `valid()` is true iff the
+    /// iterator was not positioned at the last entry in the source.
+    ///
+    /// REQUIRES: `valid()`
+    fn next(&mut self);
+
+    /// Moves to the previous entry in the source. After this call, `valid()` is true iff
+    /// the iterator was not positioned at the first entry in the source.
+    ///
+    /// REQUIRES: `valid()`
+    fn prev(&mut self);
+
+    /// Return the key for the current entry. The underlying storage for the returned
+    /// slice is valid only until the next modification of the iterator.
+    ///
+    /// REQUIRES: `valid()`
+    fn key(&self) -> &[u8];
+
+    /// Return the value for the current entry. The underlying storage for the returned
+    /// slice is valid only until the next modification of the iterator.
+    ///
+    /// REQUIRES: `valid()`
+    fn value(&self) -> &[u8];
+
+    /// If an error has occurred, return it. Else return an ok status.
+    fn status(&self) -> Result<()>;
+}
+
+/// An iterator over nothing, optionally carrying an error status.
+pub struct EmptyIterator {
+    status: Result<()>,
+}
+
+impl Iterator for EmptyIterator {
+    fn valid(&self) -> bool { false }
+
+    fn seek_to_first(&mut self) {}
+
+    fn seek_to_last(&mut self) {}
+
+    fn seek(&mut self, _target: &[u8]) {}
+
+    fn next(&mut self) { panic!("next() called on an empty iterator") }
+
+    fn prev(&mut self) { panic!("prev() called on an empty iterator") }
+
+    fn key(&self) -> &[u8] { panic!("key() called on an empty iterator") }
+
+    fn value(&self) -> &[u8] { panic!("value() called on an empty iterator") }
+
+    fn status(&self) -> Result<()> { self.status.clone() }
+}
+
+/// Return an empty iterator (yields nothing).
+pub fn new_empty_iterator() -> Box<dyn Iterator> { Box::new(EmptyIterator { status: Ok(()) }) }
+
+/// Return an empty iterator with the specified status.
+pub fn new_error_iterator(err: Error) -> Box<dyn Iterator> {
+    Box::new(EmptyIterator { status: Err(err) })
+}
@@ -0,0 +1,192 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::leveldb::result::Error;
+
+/// The kind of work a background job performs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackgroundJobKind {
+    /// Writing the contents of a memtable to a level-0 table.
+    Flush,
+    /// Merging the files of one level into the next one.
+    Compaction,
+}
+
+/// Describes a compaction that is about to run or that has just finished.
+#[derive(Debug, Clone)]
+pub struct CompactionJobInfo {
+    /// Identifier of the job, unique for the lifetime of the DB object.
+    pub job_id: u64,
+    /// Level whose files are being compacted.
+    pub level: usize,
+    /// Level the compaction writes its outputs to.
+    pub output_level: usize,
+    /// Numbers of the table files read by the compaction, from both levels.
+    pub input_files: Vec<u64>,
+    /// Total size of the input files.
+    pub input_bytes: u64,
+    /// True if the compaction was requested through `DB::compact_range()`.
+    pub manual: bool,
+    /// True if the compaction only moves a file to the next level.
+    pub trivial_move: bool,
+}
+
+/// Describes a flush that has just finished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlushJobInfo {
+    /// Identifier of the job, unique for the lifetime of the DB object.
+    pub job_id: u64,
+    /// Number of the table file written, and its size. The size is zero if the
+    /// memtable held nothing and no table was written.
+    pub file_number: u64,
+    pub file_size: u64,
+    /// Level the table was placed at.
+    pub level: usize,
+}
+
+/// Why a table file was added to the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableFileCreationReason {
+    Flush,
+    Compaction,
+    /// Added by `DB::ingest_external_file()`.
+    Ingestion,
+}
+
+/// Describes a table file that has just been added to the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableFileCreationInfo {
+    pub file_number: u64,
+    pub file_path: String,
+    pub file_size: u64,
+    /// Level the file was added to.
+    pub level: usize,
+    pub reason: TableFileCreationReason,
+}
+
+/// Describes a table file that has just been deleted because no version of the
+/// database uses it anymore.
+#[derive(Debug, Clone)]
+pub struct TableFileDeletionInfo {
+    pub file_number: u64,
+    pub file_path: String,
+    /// The outcome of deleting the file.
+    pub status: Result<(), Error>,
+}
+
+/// What to do with a compaction presented to `EventListener::on_compaction_begin()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionDecision {
+    /// Run the compaction now.
+    Proceed,
+    /// Do not run the compaction now. It is offered again the next time the DB looks
+    /// for compaction work.
+    Delay,
+    /// Never run this compaction, i.e. this exact set of input files at this level.
+    /// A compaction over a different set of files is offered again. Once level-0
+    /// reaches `Options::level0_stop_writes_trigger`, writes fail while no compaction is
+    /// allowed to run.
+    Veto,
+}
+
+/// A listener is notified of background work performed by a DB. All callbacks run
+/// on the thread that starts or installs the work, with DB internal locks held, so
+/// they must return quickly and must not call back into the DB.
+pub trait EventListener: Send + Sync {
+    /// Called before a compaction starts. The returned decision lets the listener
+    /// delay or veto the compaction, e.g. during maintenance freeze windows. If any
+    /// registered listener does not return `Proceed`, the compaction does not run.
+    fn on_compaction_begin(&self, _info: &CompactionJobInfo) -> CompactionDecision { CompactionDecision::Proceed }
+
+    /// Called after a compaction that was allowed to run has finished, successfully
+    /// or not.
+    fn on_compaction_completed(&self, _info: &CompactionJobInfo, _succeeded: bool) {}
+
+    /// Called after a memtable has been written to a table and the table installed.
+    fn on_flush_completed(&self, _info: &FlushJobInfo) {}
+
+    /// Called after a flush, compaction or ingestion added a table file to the
+    /// database.
+    fn on_table_file_created(&self, _info: &TableFileCreationInfo) {}
+
+    /// Called after an obsolete table file has been deleted.
+    fn on_table_file_deleted(&self, _info: &TableFileDeletionInfo) {}
+
+    /// Called when a background error stops the database from accepting writes. Only
+    /// the first such error is reported.
+    fn on_background_error(&self, _error: &Error) {}
+}
+
+/// A snapshot of the state of a background job, as returned by `DB::running_jobs()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackgroundJobInfo {
+    pub job_id: u64,
+    pub kind: BackgroundJobKind,
+    /// Level being flushed to or compacted from.
+    pub level: usize,
+    /// Bytes the job is expected to read in total.
+    pub total_bytes: u64,
+    /// Bytes of input processed so far.
+    pub bytes_read: u64,
+    /// Bytes of output written so far.
+    pub bytes_written: u64,
+}
+
+/// Progress counters of a running job. They are updated by the thread running the
+/// job and may be read concurrently without taking the DB lock.
+pub(crate) struct JobProgress {
+    job_id: u64,
+    kind: BackgroundJobKind,
+    level: usize,
+    total_bytes: u64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl JobProgress {
+    pub(crate) fn new(job_id: u64, kind: BackgroundJobKind, level: usize, total_bytes: u64) -> Self {
+        Self {
+            job_id,
+            kind,
+            level,
+            total_bytes,
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn add_bytes_read(&self, n: u64) { self.bytes_read.fetch_add(n, Ordering::Relaxed); }
+
+    pub(crate) fn add_bytes_written(&self, n: u64) { self.bytes_written.fetch_add(n, Ordering::Relaxed); }
+
+    pub(crate) fn info(&self) -> BackgroundJobInfo {
+        BackgroundJobInfo {
+            job_id: self.job_id,
+            kind: self.kind,
+            level: self.level,
+            total_bytes: self.total_bytes,
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+}
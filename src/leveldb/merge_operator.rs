@@ -0,0 +1,40 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+/// A MergeOperator combines the operands written with `DB::merge()` into a value, which
+/// lets read-modify-write updates such as counters or appends be issued as blind writes.
+/// Operands stack up in the memtable and tables; they are collapsed when the key is
+/// read and, where possible, when it is compacted.
+pub trait MergeOperator: Send + Sync {
+    /// The name of the operator. Merge operands written under one operator must only
+    /// be read back with a compatible operator of the same name.
+    fn name(&self) -> &str;
+
+    /// Combine `operands` (oldest first) with the `existing` value of `key`, which is
+    /// `None` when the key had no value or was deleted. Return `None` if the operands
+    /// cannot be merged, which reads of the key report as corruption.
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Option<Vec<u8>>;
+
+    /// Combine two adjacent operands into one, `left` being the older, without knowing
+    /// the existing value. Return `None` if they cannot be combined on their own; they
+    /// are then kept until a `full_merge` sees them.
+    fn partial_merge(&self, _key: &[u8], _left: &[u8], _right: &[u8]) -> Option<Vec<u8>> { None }
+}
@@ -18,5 +18,16 @@
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
+#[macro_use]
 pub mod result;
-pub mod slice;
\ No newline at end of file
+pub mod compaction_filter;
+pub mod comparator;
+pub mod env;
+pub mod filter_policy;
+pub mod iterator;
+pub mod listener;
+pub mod merge_operator;
+pub mod options;
+pub mod pinnable_slice;
+pub mod slice;
+pub mod slice_transform;
\ No newline at end of file
@@ -0,0 +1,657 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    db::{dbformat::config, metrics::MetricsRegistry, snapshot::Snapshot},
+    leveldb::{
+        compaction_filter::{CompactionFilter, CompactionFilterFactory},
+        comparator::Comparator,
+        env::{Env, InfoLogLevel, Logger},
+        filter_policy::FilterPolicy,
+        listener::EventListener,
+        merge_operator::MergeOperator,
+        result::Result,
+        slice_transform::SliceTransform,
+    },
+    table::{block::Block, format::LATEST_FORMAT_VERSION},
+    util::{
+        cache::{Cache, ConcurrentCache},
+        clock_cache::ClockCache,
+        comparator::bytewise_comparator,
+    },
+};
+
+/// DB contents are stored in a set of blocks, each of which holds a sequence of
+/// key,value pairs. Each block may be compressed before being stored in a file. The
+/// following enum describes which compression method (if any) is used to compress a
+/// block.
+///
+/// NOTE: do not change the values of existing entries, as these are part of the
+/// persistent format on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionType {
+    NoCompression,
+    /// Zstandard at the given level. Higher levels trade compression speed for smaller
+    /// blocks; decompression speed is about the same at every level.
+    Zstd { level: i32 },
+    /// LZ4, which decompresses faster than zstd at the cost of larger blocks.
+    Lz4,
+}
+
+/// The zstd level used when the level of a block is not otherwise known.
+pub const ZSTD_DEFAULT_LEVEL: i32 = 3;
+
+impl CompressionType {
+    /// Return the compression type recorded in a block trailer, if it is known. The
+    /// zstd level is not recorded, since decompression does not depend on it.
+    pub fn from_u8(v: u8) -> Option<CompressionType> {
+        match v {
+            0x0 => Some(CompressionType::NoCompression),
+            0x2 => Some(CompressionType::Zstd {
+                level: ZSTD_DEFAULT_LEVEL,
+            }),
+            0x3 => Some(CompressionType::Lz4),
+            _ => None,
+        }
+    }
+
+    /// Return the byte recorded in the trailer of blocks compressed with this type.
+    pub fn type_byte(&self) -> u8 {
+        match self {
+            CompressionType::NoCompression => 0x0,
+            // 0x1 is reserved for snappy, as in leveldb.
+            CompressionType::Zstd { .. } => 0x2,
+            CompressionType::Lz4 => 0x3,
+        }
+    }
+}
+
+/// The checksum stored in the trailer of every table block, covering the block
+/// contents and its compression type. The type used by a table is recorded in its
+/// footer, so tables written with different types can be read side by side.
+///
+/// NOTE: do not change the values of existing entries, as these are part of the
+/// persistent format on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChecksumType {
+    /// Masked crc32c, as in leveldb. Tables using it keep the leveldb footer.
+    #[default]
+    Crc32c,
+    /// The low 32 bits of xxHash64, which is faster than crc32c for large blocks on
+    /// CPUs without crc32c instructions.
+    XxHash64,
+}
+
+impl ChecksumType {
+    /// Return the checksum type recorded in a table footer, if it is known.
+    pub fn from_u8(v: u8) -> Option<ChecksumType> {
+        match v {
+            0x1 => Some(ChecksumType::Crc32c),
+            0x2 => Some(ChecksumType::XxHash64),
+            _ => None,
+        }
+    }
+
+    /// Return the byte recorded in the footer of tables using this type.
+    pub fn type_byte(&self) -> u8 {
+        match self {
+            ChecksumType::Crc32c => 0x1,
+            ChecksumType::XxHash64 => 0x2,
+        }
+    }
+}
+
+/// Tuning of block compression, see `Options::compression_opts`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionOptions {
+    /// If non-zero, each new zstd-compressed table gets a dictionary of up to this many
+    /// bytes, sampled from its first data blocks and stored in a meta block. The data
+    /// blocks that follow the sample are compressed against the dictionary, which
+    /// helps small blocks of similar records (e.g. JSON values) that share little
+    /// within a single block. Tables smaller than the dictionary get none. Requires
+    /// `Options::format_version` 3 or later; ignored otherwise.
+    /// Default: 0
+    pub max_dict_bytes: usize,
+}
+
+/// A cache of uncompressed data blocks, shared between all the tables of a database.
+pub type BlockCache = dyn ConcurrentCache<Arc<Block>>;
+
+/// Create an LRU block cache that holds up to `capacity` bytes of uncompressed blocks.
+pub fn new_block_cache(capacity: usize) -> Arc<BlockCache> {
+    Arc::new(Mutex::new(Cache::new(capacity)))
+}
+
+/// Create a block cache with CLOCK eviction that holds up to `capacity` bytes of
+/// uncompressed blocks, sized for blocks of about `block_size` bytes. Its lookups
+/// take no lock, which suits read-heavy workloads where many threads hit the cache.
+pub fn new_clock_block_cache(capacity: usize, block_size: usize) -> Arc<BlockCache> {
+    Arc::new(ClockCache::new(capacity, block_size))
+}
+
+/// Options to control the behavior of a database (passed to `DB::open`)
+#[derive(Clone)]
+pub struct Options {
+    // -------------------
+    // Parameters that affect behavior
+    /// Comparator used to define the order of keys in the table.
+    /// Default: a comparator that uses lexicographic byte-wise ordering
+    ///
+    /// REQUIRES: The client must ensure that the comparator supplied here has the same
+    /// name and orders keys *exactly* the same as the comparator provided to previous
+    /// open calls on the same DB.
+    pub comparator: Arc<dyn Comparator>,
+
+    /// If true, the database will be created if it is missing.
+    pub create_if_missing: bool,
+
+    /// If true, an error is raised if the database already exists.
+    pub error_if_exists: bool,
+
+    /// If true, the implementation will do aggressive checking of the data it is
+    /// processing and will stop early if it detects any errors: every block checksum is
+    /// verified on read, the manifest and logs must be free of damaged records, and a
+    /// corruption found while reading puts the database into an error state in which
+    /// writes fail. This may have unforeseen ramifications: for example, a corruption
+    /// of one DB entry may cause a large number of entries to become unreadable or for
+    /// the entire DB to become unopenable.
+    pub paranoid_checks: bool,
+
+    /// Use the specified object to interact with the environment, e.g. to read/write
+    /// files, schedule background work, etc.
+    /// Default: `env::default_env()`
+    pub env: Arc<dyn Env>,
+
+    /// Any internal progress/error information generated by the db will be written to
+    /// `info_log` if it is non-null, or to a LOG file stored in the same directory as
+    /// the DB contents if `info_log` is null. Opening the database moves an existing
+    /// LOG file to LOG.old. Without the `std` feature no LOG file is written.
+    /// Default: None
+    pub info_log: Option<Arc<dyn Logger>>,
+
+    /// Messages below this level are not written to the LOG file created by the DB.
+    /// Default: `InfoLogLevel::Info`
+    pub info_log_level: InfoLogLevel,
+
+    /// If non-zero, the LOG file created by the DB is moved to LOG.old once it grows
+    /// to about this many bytes, and a new LOG file is started.
+    /// Default: 0
+    pub max_log_file_size: u64,
+
+    // -------------------
+    // Parameters that affect performance
+    /// Amount of data to build up in memory (backed by an unsorted log on disk) before
+    /// converting to a sorted on-disk file.
+    ///
+    /// Larger values increase performance, especially during bulk loads. Up to two write
+    /// buffers may be held in memory at the same time, so you may wish to adjust this
+    /// parameter to control memory usage. Also, a larger write buffer will result in a
+    /// longer recovery time the next time the database is opened.
+    pub write_buffer_size: usize,
+
+    /// Write buffer size used instead of `write_buffer_size` between
+    /// `DB::prepare_for_bulk_load()` and `DB::finish_bulk_load()`. Smaller values
+    /// than `write_buffer_size` are ignored.
+    /// Default: 64MB
+    pub bulk_load_write_buffer_size: usize,
+
+    /// Size of the blocks that the memtable's arena carves its entries out of. Larger
+    /// blocks mean fewer allocations for write-heavy workloads, at the cost of more
+    /// memory left unused at the end of each block. The blocks of a flushed memtable
+    /// are reused by the next one.
+    /// Default: 4KB
+    pub arena_block_size: usize,
+
+    /// If true, the writers of a write group insert their own batches into the
+    /// memtable in parallel, instead of the group's leader inserting all of them.
+    /// Memtables then allocate from a concurrent arena, whose blocks are not reused.
+    /// Default: false
+    pub allow_concurrent_memtable_write: bool,
+
+    /// Writes with a longer key or value fail with an `InvalidArgument` error. Limits
+    /// above the format limit `config::MAX_ENTRY_SIZE` (for a key and its value
+    /// together) have no effect.
+    /// Default: `config::MAX_ENTRY_SIZE`
+    pub max_key_size: usize,
+    pub max_value_size: usize,
+
+    /// Number of open files that can be used by the DB. You may need to increase this if
+    /// your database has a large working set (budget one open file per 2MB of working
+    /// set). Ten of them are kept for files other than tables, and the least recently
+    /// used table is closed once the rest are open. Raised to at least 20. With -1,
+    /// every table is opened when the database is, and stays open along with its index
+    /// and filter blocks, including all the partitions of a partitioned filter.
+    /// Default: 1000
+    pub max_open_files: i32,
+
+    /// Control over blocks (user data is stored in a set of blocks, and a block is the
+    /// unit of reading from disk).
+    ///
+    /// If non-null, use the specified cache for blocks, e.g. one created with
+    /// `new_block_cache()` or `new_clock_block_cache()`. If null, leveldb will
+    /// automatically create and use an internal 8MB LRU cache.
+    pub block_cache: Option<Arc<BlockCache>>,
+
+    /// Approximate size of user data packed per block. Note that the block size
+    /// specified here corresponds to uncompressed data. The actual size of the unit read
+    /// from disk may be smaller if compression is enabled.
+    pub block_size: usize,
+
+    /// Number of keys between restart points for delta encoding of keys. This parameter
+    /// can be changed dynamically. Most clients should leave this parameter alone.
+    pub block_restart_interval: usize,
+
+    /// Leveldb will write up to this amount of bytes to a file before switching to a new
+    /// one.
+    pub max_file_size: usize,
+
+    /// Level-0 is compacted once it holds this many files.
+    /// Default: 4
+    pub level0_file_num_compaction_trigger: usize,
+
+    /// Once level-0 holds this many files, e.g. because a listener delays its
+    /// compaction, each write is delayed by a millisecond to let compactions catch up.
+    /// Raised to at least `level0_file_num_compaction_trigger`.
+    /// Default: 8
+    pub level0_slowdown_writes_trigger: usize,
+
+    /// Once level-0 holds this many files, writes stop until compactions bring the
+    /// count back down. Raised to at least `level0_slowdown_writes_trigger`. Neither
+    /// trigger applies during a bulk load.
+    /// Default: 12
+    pub level0_stop_writes_trigger: usize,
+
+    /// If true, the size limits of the levels are derived from the size of the last
+    /// level, each level holding a tenth of the next one, instead of being fixed at
+    /// 10MB for level-1, 100MB for level-2 and so on. Levels that would hold less than
+    /// 10MB stay empty. Most of the data then lives in the last level however large the
+    /// database is, which keeps the space taken by overwritten and deleted entries at
+    /// about a tenth of the live data.
+    /// Default: false
+    pub level_compaction_dynamic_level_bytes: bool,
+
+    /// Maximum number of threads that a compaction of level-0 files splits its work
+    /// across. The key range of the compaction is split at the boundaries of its input
+    /// files, and each part is compacted on its own thread into its own output files.
+    /// The compaction filter may then be called from several threads at once. Values
+    /// above 1 need a target that supports threads.
+    /// Default: 1
+    pub max_subcompactions: usize,
+
+    /// If non-zero, a table file that is older than this many seconds is compacted even
+    /// if no level is over its size limit, so that compaction filters eventually see
+    /// every entry. Files of the last level are rewritten in place. The age of files
+    /// written before their creation time was recorded counts from when the database
+    /// was opened. Expired files are looked for whenever the database looks for
+    /// compaction work, e.g. after a flush.
+    /// Default: 0
+    pub periodic_compaction_seconds: u64,
+
+    /// Compress blocks using the specified compression algorithm. This parameter can be
+    /// changed dynamically.
+    pub compression: CompressionType,
+
+    /// Compression used for the tables of each level, overriding `compression`. Level
+    /// `i` uses entry `i` and levels past the end of the list use its last entry.
+    /// Memtable flushes use the entry of level 0. For example `[NoCompression,
+    /// NoCompression, Zstd { level: 3 }]` spends no CPU on the frequently rewritten
+    /// upper levels and compresses the colder levels from 2 down.
+    /// Default: empty, every level uses `compression`
+    pub compression_per_level: Vec<CompressionType>,
+
+    /// Tuning of block compression, e.g. a dictionary for zstd.
+    /// Default: CompressionOptions::default()
+    pub compression_opts: CompressionOptions,
+
+    /// Checksum written to the trailer of the blocks of new tables. Tables written
+    /// with any type can be read regardless of this setting. Types other than crc32c
+    /// require `format_version` 2 or later.
+    /// Default: ChecksumType::Crc32c
+    pub checksum: ChecksumType,
+
+    /// Format version of new tables, which is recorded in their footer. Version 0 is
+    /// the leveldb format, version 1 shortens index keys, version 2 records the
+    /// checksum type in the footer and version 3 allows compression dictionaries. Tables of any version up to
+    /// `LATEST_FORMAT_VERSION` can be read regardless of this setting; older
+    /// versions keep new tables readable by older releases.
+    /// Default: LATEST_FORMAT_VERSION
+    pub format_version: u32,
+
+    /// If non-null, use the specified filter policy to reduce disk reads. Many
+    /// applications will benefit from passing the result of
+    /// `bloom::new_bloom_filter_policy()` here.
+    pub filter_policy: Option<Arc<dyn FilterPolicy>>,
+
+    /// If true, new tables get a single filter over all of their keys instead of one
+    /// filter per 2KB of data blocks, so that a point lookup probes one filter and can
+    /// skip the index block of a table that does not hold its key. The filter of a
+    /// table is loaded as a whole, which costs more memory for large tables. Tables
+    /// with either kind of filter can be read regardless of this setting.
+    /// Default: false
+    pub full_filter: bool,
+
+    /// If true, the filter of a new table is split into partitions, each a full filter
+    /// over the keys of consecutive data blocks. A point lookup reads only the
+    /// partition covering the data block it probes, and partitions are cached
+    /// individually in the block cache, so the memory held for filters stays bounded
+    /// however large tables are. Takes precedence over `full_filter`.
+    /// Default: false
+    pub partition_filters: bool,
+
+    /// Approximate size of the keys covered by each filter partition when
+    /// `partition_filters` is set. A partition ends at the first data block boundary
+    /// after this many bytes of keys.
+    /// Default: 4KB
+    pub metadata_block_size: usize,
+
+    /// If non-null, use the specified transform to extract key prefixes. Together with
+    /// a filter policy, each table then also stores a filter over its key prefixes,
+    /// which lets iterators opened with `ReadOptions::prefix_same_as_start` skip tables
+    /// that hold no key with the prefix they seek to.
+    /// Default: None
+    pub prefix_extractor: Option<Arc<dyn SliceTransform>>,
+
+    /// The operator that combines the operands written with `DB::merge()`. Without
+    /// one, `DB::merge()` fails with a `NotSupported` error. A database holding merge
+    /// operands must always be opened with the same operator.
+    /// Default: None
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+
+    /// If non-null, compactions pass the values they rewrite to this filter, which may
+    /// drop or change them. Takes precedence over `compaction_filter_factory`.
+    /// Default: None
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+
+    /// If non-null, each compaction creates its own filter with this factory.
+    /// Default: None
+    pub compaction_filter_factory: Option<Arc<dyn CompactionFilterFactory>>,
+
+    /// `DB::open()` checks that every table file of the database exists. If false, it
+    /// also checks that each has the size the MANIFEST records, which takes a file
+    /// system call per table. If true, it relies on the listing of the database
+    /// directory alone, which makes opening a database with many files much faster.
+    ///
+    /// Either way, tables are opened and their index and filter blocks loaded lazily
+    /// on first access, unless `max_open_files == -1`.
+    pub skip_stats_update_on_db_open: bool,
+
+    /// If non-zero, log files that are no longer needed for recovery are moved to the
+    /// "archive" subdirectory of the database instead of being deleted, so that
+    /// `DB::get_updates_since()` can still read them. The oldest archived logs are
+    /// deleted once the archive holds more than this many bytes.
+    /// Default: 0
+    pub wal_archive_size_limit: u64,
+
+    /// If true, writes are not flushed from the log's buffer to the operating system
+    /// one by one. They are flushed by `DB::flush_wal()` and `DB::sync_wal()`, by sync
+    /// writes, and whenever the buffer fills up. Writes still in the buffer are lost if
+    /// the process crashes.
+    /// Default: false
+    pub manual_wal_flush: bool,
+
+    /// If non-zero, disk space for log and table files is reserved in blocks of this
+    /// many bytes as they grow, with `fallocate()` where the env supports it, which
+    /// keeps files contiguous on disk and spares the file system from extending them
+    /// on every write. Space left unused is released when a file is closed. A size
+    /// around the write buffer size suits logs; larger tables take several blocks.
+    /// Default: 0
+    pub wal_preallocate_block_size: usize,
+
+    /// If non-zero, the env is asked to start writing a table file back to disk each
+    /// time another this many bytes have been written to it, e.g. with
+    /// `sync_file_range()`. Spreading the writeback over a large compaction output
+    /// avoids a long stall when the file is synced at the end. 1MB is a good value.
+    /// Default: 0
+    pub bytes_per_sync: usize,
+
+    /// Same as `bytes_per_sync`, for log files.
+    /// Default: 0
+    pub wal_bytes_per_sync: usize,
+
+    /// Listeners notified of flushes and compactions. A listener may also delay or
+    /// veto individual compactions.
+    /// Default: no listeners
+    pub listeners: Vec<Arc<dyn EventListener>>,
+
+    /// If non-null, the DB registers its statistics with this registry when opened,
+    /// labeled with `metrics_labels`, and leaves it when dropped. Pass
+    /// `MetricsRegistry::global()` to report all DBs of a process together.
+    /// Default: None
+    pub metrics_registry: Option<Arc<MetricsRegistry>>,
+    pub metrics_labels: Vec<(String, String)>,
+}
+
+impl Options {
+    /// Return the compression to use for tables written to `level`.
+    pub fn compression_for_level(&self, level: usize) -> CompressionType {
+        match self.compression_per_level.last() {
+            Some(&last) => self.compression_per_level.get(level).copied().unwrap_or(last),
+            None => self.compression,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            comparator: bytewise_comparator(),
+            create_if_missing: false,
+            error_if_exists: false,
+            paranoid_checks: false,
+            env: crate::leveldb::env::default_env(),
+            info_log: None,
+            info_log_level: InfoLogLevel::Info,
+            max_log_file_size: 0,
+            write_buffer_size: 4 * 1024 * 1024,
+            bulk_load_write_buffer_size: 64 * 1024 * 1024,
+            arena_block_size: 4 * 1024,
+            allow_concurrent_memtable_write: false,
+            max_key_size: config::MAX_ENTRY_SIZE,
+            max_value_size: config::MAX_ENTRY_SIZE,
+            max_open_files: 1000,
+            block_cache: None,
+            block_size: 4 * 1024,
+            block_restart_interval: 16,
+            max_file_size: 2 * 1024 * 1024,
+            level0_file_num_compaction_trigger: 4,
+            level0_slowdown_writes_trigger: 8,
+            level0_stop_writes_trigger: 12,
+            level_compaction_dynamic_level_bytes: false,
+            max_subcompactions: 1,
+            periodic_compaction_seconds: 0,
+            compression: CompressionType::NoCompression,
+            compression_per_level: Vec::new(),
+            compression_opts: CompressionOptions::default(),
+            checksum: ChecksumType::Crc32c,
+            format_version: LATEST_FORMAT_VERSION,
+            filter_policy: None,
+            full_filter: false,
+            partition_filters: false,
+            metadata_block_size: 4 * 1024,
+            prefix_extractor: None,
+            merge_operator: None,
+            compaction_filter: None,
+            compaction_filter_factory: None,
+            skip_stats_update_on_db_open: false,
+            wal_archive_size_limit: 0,
+            manual_wal_flush: false,
+            wal_preallocate_block_size: 0,
+            bytes_per_sync: 0,
+            wal_bytes_per_sync: 0,
+            listeners: Vec::new(),
+            metrics_registry: None,
+            metrics_labels: Vec::new(),
+        }
+    }
+}
+
+/// Options that control read operations
+#[derive(Clone)]
+pub struct ReadOptions {
+    /// Should the data read for this iteration be cached in memory? Callers may wish to
+    /// set this field to false for bulk scans.
+    pub fill_cache: bool,
+
+    /// If true, all data read from underlying storage will be verified against
+    /// corresponding checksums. A block that fails verification makes the read fail
+    /// with a `Corruption` error naming the table file and the offset of the block.
+    /// Blocks already in the block cache are not verified again.
+    pub verify_checksums: bool,
+
+    /// If `Some`, read as of the supplied snapshot (which must belong to the DB that is
+    /// being read and which must not have been released). If `None`, use an implicit
+    /// snapshot of the state at the beginning of this read operation.
+    pub snapshot: Option<Snapshot>,
+
+    /// If `Some`, the read fails with a `TimedOut` error if it still needs to read a
+    /// block from disk once this instant has passed. Blocks found in the block cache
+    /// and the memtable are still served.
+    pub deadline: Option<Instant>,
+
+    /// If `Some`, the read fails with an `Incomplete` error if a single block read
+    /// from disk takes longer than this. The slow read itself cannot be interrupted,
+    /// but its result is discarded rather than waiting on further slow reads.
+    pub io_timeout: Option<Duration>,
+
+    /// If true, an iterator positioned with `seek()` only yields keys that have the
+    /// same prefix as the seek target, according to `Options::prefix_extractor`, and
+    /// becomes invalid at the first key with another prefix. Has no effect without a
+    /// prefix extractor or for targets outside its domain.
+    pub prefix_same_as_start: bool,
+
+    /// If non-zero, iterators advise the env that this many bytes from the data block
+    /// they move to will be read soon, e.g. with `POSIX_FADV_WILLNEED`, so that the
+    /// operating system reads ahead while the iterator scans a long range instead of
+    /// waiting on the disk for every block. Compactions read their inputs with 2MB of
+    /// readahead.
+    /// Default: 0
+    pub readahead_size: usize,
+
+    /// If `Some`, long reads (`DB::multi_get()` over many keys, `DB::verify_checksums()`)
+    /// check the token periodically and fail with an `Aborted` error once it is
+    /// cancelled. Keys already looked up by `multi_get()` keep their results.
+    pub cancel: Option<CancellationToken>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            fill_cache: true,
+            verify_checksums: false,
+            snapshot: None,
+            deadline: None,
+            io_timeout: None,
+            prefix_same_as_start: false,
+            readahead_size: 0,
+            cancel: None,
+        }
+    }
+}
+
+/// Options that control `DB::compact_range_with_options()`
+#[derive(Clone, Default)]
+pub struct CompactRangeOptions {
+    /// If `Some`, the token is checked before the memtable flush and before each
+    /// compaction, and the call fails with an `Aborted` error once it is cancelled.
+    /// Compactions that already ran are kept, so the range is left partially compacted.
+    pub cancel: Option<CancellationToken>,
+}
+
+/// Options that control `DB::flush()`
+#[derive(Clone)]
+pub struct FlushOptions {
+    /// If true, the call returns once the memtable has been written to a level-0 table
+    /// and installed. Otherwise the flush runs in the background.
+    /// Default: true
+    pub wait: bool,
+}
+
+impl Default for FlushOptions {
+    fn default() -> Self { Self { wait: true } }
+}
+
+/// Options that control `DB::wait_for_compact()`
+#[derive(Clone, Default)]
+pub struct WaitForCompactOptions {
+    /// If true, the memtable is flushed first, so that its contents are compacted too.
+    /// Default: false
+    pub flush: bool,
+
+    /// If `Some`, the call fails with a `TimedOut` error once compactions are still
+    /// pending after this long.
+    /// Default: None
+    pub timeout: Option<Duration>,
+}
+
+/// Options that control `DB::ingest_external_file()`
+#[derive(Clone, Default)]
+pub struct IngestExternalFileOptions {
+    /// If true, the files are copied into the database. Otherwise they are hard-linked
+    /// where the Env and file system allow it, and must not be modified afterwards.
+    /// Default: false
+    pub copy_files: bool,
+}
+
+/// Lets an embedder abort long running DB calls, e.g. on shutdown or when a request
+/// times out. Clones share the same state, so one clone can be handed to the call and
+/// another cancelled from a different thread.
+#[derive(Clone, Default, Debug)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self { Self::default() }
+
+    /// Ask the calls using this token to stop. Cancellation cannot be undone.
+    pub fn cancel(&self) { self.cancelled.store(true, Ordering::Release) }
+
+    pub fn is_cancelled(&self) -> bool { self.cancelled.load(Ordering::Acquire) }
+
+    /// Return an `Aborted` error if the token has been cancelled.
+    pub(crate) fn check(&self) -> Result<()> {
+        match self.is_cancelled() {
+            true => LEVELDB_ERR!(Aborted, "operation cancelled"),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Options that control write operations
+#[derive(Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// If true, the write will be flushed from the operating system buffer cache (by
+    /// calling `WritableFile::sync()`) before the write is considered complete. If this
+    /// flag is true, writes will be slower.
+    ///
+    /// If this flag is false, and the machine crashes, some recent writes may be lost.
+    /// Note that if it is just the process that crashes (i.e., the machine does not
+    /// reboot), no writes will be lost even if `sync == false`.
+    pub sync: bool,
+}
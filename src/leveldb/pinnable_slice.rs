@@ -0,0 +1,103 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{fmt, ops::Deref, slice};
+
+/// A value read from the database by `DB::get_pinned()`. Instead of a copy, the value
+/// usually refers to the memtable entry or the data block holding it, which it keeps
+/// alive (and pinned in the block cache) until it is dropped.
+pub struct PinnableSlice {
+    data: *const u8,
+    len: usize,
+    pin: Pin,
+}
+
+enum Pin {
+    Owned(Vec<u8>),
+    // Keeps the memory the slice refers to alive and unchanged
+    Held { _pin: Box<dyn Send + Sync> },
+}
+
+// The referenced data is never modified, and `pin` is Send + Sync.
+unsafe impl Send for PinnableSlice {}
+unsafe impl Sync for PinnableSlice {}
+
+impl PinnableSlice {
+    /// Create a slice that owns `value`.
+    pub fn from_vec(value: Vec<u8>) -> Self {
+        Self {
+            data: value.as_ptr(),
+            len: value.len(),
+            pin: Pin::Owned(value),
+        }
+    }
+
+    /// Create a slice that refers to `data` without copying it.
+    ///
+    /// # Safety
+    /// `data` must stay valid and unchanged for as long as `pin` is alive.
+    pub(crate) unsafe fn pinned(data: &[u8], pin: Box<dyn Send + Sync>) -> Self {
+        Self {
+            data: data.as_ptr(),
+            len: data.len(),
+            pin: Pin::Held { _pin: pin },
+        }
+    }
+
+    /// Returns true iff the value refers to memory owned by the database rather than
+    /// to a copy of its own.
+    pub fn is_pinned(&self) -> bool { matches!(self.pin, Pin::Held { .. }) }
+
+    /// Return the value as a vector, copying it unless the slice owns it.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.pin {
+            Pin::Owned(value) => value,
+            Pin::Held { .. } => self.to_vec(),
+        }
+    }
+}
+
+impl Deref for PinnableSlice {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] { unsafe { slice::from_raw_parts(self.data, self.len) } }
+}
+
+impl AsRef<[u8]> for PinnableSlice {
+    fn as_ref(&self) -> &[u8] { self }
+}
+
+impl From<Vec<u8>> for PinnableSlice {
+    fn from(value: Vec<u8>) -> Self { Self::from_vec(value) }
+}
+
+impl PartialEq for PinnableSlice {
+    fn eq(&self, other: &Self) -> bool { **self == **other }
+}
+
+impl fmt::Debug for PinnableSlice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PinnableSlice")
+            .field("value", &&**self)
+            .field("pinned", &self.is_pinned())
+            .finish()
+    }
+}
@@ -19,13 +19,19 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-#[derive(Debug)]
+use std::{borrow::Cow, error::Error as StdError, io, sync::Arc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorType {
     NotFound,
     Corruption,
     NotSupported,
     InvalidArgument,
     IOError,
+    TimedOut,
+    Incomplete,
+    Aborted,
+    MergeInProgress,
 }
 
 impl ErrorType {
@@ -36,41 +42,169 @@ impl ErrorType {
             ErrorType::NotSupported => "NotSupportedError",
             ErrorType::InvalidArgument => "InvalidArgumentError",
             ErrorType::IOError => "IOError",
+            ErrorType::TimedOut => "TimedOutError",
+            ErrorType::Incomplete => "IncompleteError",
+            ErrorType::Aborted => "AbortedError",
+            ErrorType::MergeInProgress => "MergeInProgress",
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Error {
     ty: ErrorType,
-    msg: &'static str,
+    msg: Cow<'static, str>,
+    // What the error is about, e.g. a file name, when that is only known at runtime.
+    detail: Option<Arc<str>>,
+    // The lower-level error that caused this one, e.g. an `io::Error`.
+    source: Option<Arc<dyn StdError + Send + Sync>>,
 }
 
 impl Error {
-    pub fn new(ty: ErrorType, msg: &'static str) -> Error { Error { ty, msg } }
+    pub fn new(ty: ErrorType, msg: impl Into<Cow<'static, str>>) -> Error {
+        Error {
+            ty,
+            msg: msg.into(),
+            detail: None,
+            source: None,
+        }
+    }
+
+    pub fn not_found(msg: impl Into<Cow<'static, str>>) -> Error { Error::new(ErrorType::NotFound, msg) }
+
+    pub fn corruption(msg: impl Into<Cow<'static, str>>) -> Error { Error::new(ErrorType::Corruption, msg) }
+
+    pub fn not_supported(msg: impl Into<Cow<'static, str>>) -> Error { Error::new(ErrorType::NotSupported, msg) }
+
+    pub fn invalid_argument(msg: impl Into<Cow<'static, str>>) -> Error { Error::new(ErrorType::InvalidArgument, msg) }
+
+    pub fn io_error(msg: impl Into<Cow<'static, str>>) -> Error { Error::new(ErrorType::IOError, msg) }
+
+    pub fn timed_out(msg: impl Into<Cow<'static, str>>) -> Error { Error::new(ErrorType::TimedOut, msg) }
+
+    pub fn incomplete(msg: impl Into<Cow<'static, str>>) -> Error { Error::new(ErrorType::Incomplete, msg) }
+
+    pub fn aborted(msg: impl Into<Cow<'static, str>>) -> Error { Error::new(ErrorType::Aborted, msg) }
+
+    pub fn merge_in_progress(msg: impl Into<Cow<'static, str>>) -> Error { Error::new(ErrorType::MergeInProgress, msg) }
+
+    /// Attach `detail` to the error, e.g. the name of the file it is about.
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Error {
+        self.detail = Some(Arc::from(detail.into()));
+        self
+    }
+
+    /// Prefix the message with `context`, e.g. what was being done when the error
+    /// occurred: "reading block at offset 4096: bad block handle".
+    pub fn with_context(mut self, context: impl Into<String>) -> Error {
+        let context = context.into();
+        self.msg = match self.msg.is_empty() {
+            true => Cow::Owned(context),
+            false => Cow::Owned(format!("{}: {}", context, self.msg)),
+        };
+        self
+    }
+
+    /// Record `source` as the cause of the error, as returned by
+    /// `std::error::Error::source()`.
+    pub fn with_source(mut self, source: impl StdError + Send + Sync + 'static) -> Error {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    /// Return the type of this error
+    #[inline]
+    pub fn ty(&self) -> ErrorType { self.ty }
+
+    /// Return the message attached to this error
+    #[inline]
+    pub fn msg(&self) -> &str { &self.msg }
+
+    /// Return the detail attached with `with_detail()`, if any
+    #[inline]
+    pub fn detail(&self) -> Option<&str> { self.detail.as_deref() }
+
+    /// Return true iff this error indicates a NotFound error
+    #[inline]
+    pub fn is_not_found(&self) -> bool { self.ty == ErrorType::NotFound }
+
+    /// Return true iff this error indicates a Corruption error
+    #[inline]
+    pub fn is_corruption(&self) -> bool { self.ty == ErrorType::Corruption }
+
+    /// Return true iff this error indicates an IOError
+    #[inline]
+    pub fn is_io_error(&self) -> bool { self.ty == ErrorType::IOError }
+
+    /// Return true iff this error indicates a TimedOut error
+    #[inline]
+    pub fn is_timed_out(&self) -> bool { self.ty == ErrorType::TimedOut }
+
+    /// Return true iff this error indicates an Incomplete error
+    #[inline]
+    pub fn is_incomplete(&self) -> bool { self.ty == ErrorType::Incomplete }
+
+    /// Return true iff this error indicates an Aborted error
+    #[inline]
+    pub fn is_aborted(&self) -> bool { self.ty == ErrorType::Aborted }
+
+    /// Return true iff this error indicates that a lookup found merge operands that
+    /// still have to be combined with older entries for the key
+    #[inline]
+    pub fn is_merge_in_progress(&self) -> bool { self.ty == ErrorType::MergeInProgress }
 }
 
 impl ::std::fmt::Display for Error {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
         if self.msg.is_empty() {
-            write!(f, "LevelDB {}", self.ty.as_str())
+            write!(f, "LevelDB {}", self.ty.as_str())?;
         } else {
-            write!(f, "LevelDB {}: {}", self.ty.as_str(), self.msg)
+            write!(f, "LevelDB {}: {}", self.ty.as_str(), self.msg)?;
+        }
+        match self.detail.as_ref() {
+            Some(detail) => write!(f, ": {}", detail),
+            None => Ok(()),
         }
     }
 }
 
-impl ::std::error::Error for Error {
-    fn description(&self) -> &str { self.msg }
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn StdError + 'static))
+    }
+}
+
+impl From<io::Error> for Error {
+    /// A missing file is a NotFound error, anything else an IOError.
+    fn from(err: io::Error) -> Error {
+        let ty = match err.kind() {
+            io::ErrorKind::NotFound => ErrorType::NotFound,
+            _ => ErrorType::IOError,
+        };
+        Error::new(ty, err.to_string()).with_source(err)
+    }
+}
+
+/// Adds context to the error of a result, converting it to an `Error` first.
+pub trait ResultExt<T> {
+    /// Prefix the message of the error with the context returned by `f`, which is only
+    /// called on error. See `Error::with_context()`.
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, f: F) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for ::std::result::Result<T, E> {
+    fn with_context<C: Into<String>, F: FnOnce() -> C>(self, f: F) -> Result<T> {
+        self.map_err(|e| e.into().with_context(f()))
+    }
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
 macro_rules! LEVELDB_ERR {
     ($tp:tt) => {
-        Err(Error::new(ErrorType::$tp, ""))
+        Err($crate::leveldb::result::Error::new($crate::leveldb::result::ErrorType::$tp, ""))
     };
     ($tp:tt, $msg:expr) => {
-        Err(Error::new(ErrorType::$tp, $msg))
+        Err($crate::leveldb::result::Error::new($crate::leveldb::result::ErrorType::$tp, $msg))
     };
 }
\ No newline at end of file
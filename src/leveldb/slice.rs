@@ -26,7 +26,13 @@ use std::{
     ptr, slice,
 };
 
-use crate::util::bit;
+use crate::{
+    leveldb::result::Result,
+    util::{
+        bit,
+        coding::{decode_length_prefixed_slice, decode_varint_32_slice, decode_varint_64_slice},
+    },
+};
 
 /// Just like Rust's slice, except there's no borrowing. Instead, the user needs to
 /// guarantee that the instances of this struct should not live longer than the memory
@@ -55,11 +61,16 @@ impl Slice {
     /// Return a slice to the internal data.
     /// This should be preferred over `raw_data()`.
     #[inline]
-    pub fn data(&self) -> &[u8] { unsafe { slice::from_raw_parts(self.data, self.size) } }
+    pub fn data(&self) -> &[u8] {
+        if self.data.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.data, self.size) }
+    }
 
     /// Return true iff the length of the referenced data is zero
     #[inline]
-    pub fn empty(&self) -> bool { self.len() == 0 }
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
 
     /// Change this slice to refer to an empty array
     #[inline]
@@ -72,7 +83,7 @@ impl Slice {
     pub fn skip(&mut self, n: usize) {
         assert!(n <= self.len());
         unsafe {
-            self.data = self.data.offset(n as isize);
+            self.data = self.data.add(n);
         }
         self.size -= n;
     }
@@ -87,9 +98,6 @@ impl Slice {
         unsafe { ::std::str::from_utf8_unchecked(self.data()) }
     }
 
-    /// Returns a string from the slice data. Copying the contents.
-    pub fn to_string(&self) -> String { self.as_str().to_string() }
-
     /// Three-way comparison. Returns value:
     ///   `Ordering::Less`    iff `self` < `b`
     ///   `Ordering::Equal`   iff `self` = `b`
@@ -117,7 +125,7 @@ impl Index<usize> for Slice {
 
     /// Return the ith byte in the referenced data
     /// REQUIRES: index < self.len()
-    fn index(&self, index: usize) -> &u8 { unsafe { &*self.data.offset(index as isize) } }
+    fn index(&self, index: usize) -> &u8 { unsafe { &*self.data.add(index) } }
 }
 
 impl<'a> From<&'a [u8]> for Slice {
@@ -135,9 +143,16 @@ impl<'a> From<&'a str> for Slice {
     fn from(s: &'a str) -> Self { Slice::new(s.as_ptr(), s.len()) }
 }
 
-impl From<String> for Slice {
+impl<'a> From<BorrowedSlice<'a>> for Slice {
     #[inline]
-    fn from(s: String) -> Self { Slice::new(s.as_ptr(), s.len()) }
+    fn from(s: BorrowedSlice<'a>) -> Self { Slice::from(s.data()) }
+}
+
+impl ::std::fmt::Display for Slice {
+    /// Writes the slice data as a string. Copying the contents.
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 impl PartialEq for Slice {
@@ -148,4 +163,100 @@ impl Eq for Slice {}
 
 impl Hash for Slice {
     fn hash<H: Hasher>(&self, state: &mut H) { state.write(self.data()); }
-}
\ No newline at end of file
+}
+
+/// A slice that borrows the data it refers to, so that the compiler checks that it
+/// does not outlive it. Prefer it to `Slice`, which is kept for the data of
+/// arena-backed structures whose lifetime is managed by hand.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BorrowedSlice<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> BorrowedSlice<'a> {
+    /// Create a slice that refers to `data`
+    pub fn new(data: &'a [u8]) -> Self { Self { data } }
+
+    /// Create an empty slice
+    pub fn new_empty() -> Self { Self { data: &[] } }
+
+    /// Return the length (in bytes) of the referenced data
+    #[inline]
+    pub fn len(&self) -> usize { self.data.len() }
+
+    /// Return true iff the length of the referenced data is zero
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.data.is_empty() }
+
+    /// Return the referenced data, which may outlive this slice.
+    #[inline]
+    pub fn data(&self) -> &'a [u8] { self.data }
+
+    /// Change this slice to refer to an empty array
+    #[inline]
+    pub fn clear(&mut self) { self.data = &[]; }
+
+    /// Advance and drop the first `n` bytes from this slice.
+    pub fn skip(&mut self, n: usize) {
+        assert!(n <= self.len());
+        self.data = &self.data[n..];
+    }
+
+    /// Return true iff `x` is a prefix of `self`
+    pub fn starts_with(&self, x: &[u8]) -> bool { self.data.starts_with(x) }
+
+    /// Three-way comparison of the referenced data, as `Slice::compare()`.
+    #[inline]
+    pub fn compare(&self, b: &BorrowedSlice) -> Ordering { self.data.cmp(b.data) }
+
+    /// Drop all but the first `n` bytes from this slice.
+    pub fn truncate(&mut self, n: usize) {
+        assert!(n <= self.len());
+        self.data = &self.data[..n];
+    }
+
+    /// Divide the slice into the first `mid` bytes and the rest.
+    pub fn split_at(&self, mid: usize) -> (BorrowedSlice<'a>, BorrowedSlice<'a>) {
+        let (head, tail) = self.data.split_at(mid);
+        (Self::new(head), Self::new(tail))
+    }
+
+    /// Decode a varint32 from the start of the slice and advance past it.
+    pub fn get_varint32(&mut self) -> Result<u32> { decode_varint_32_slice(self) }
+
+    /// Decode a varint64 from the start of the slice and advance past it.
+    pub fn get_varint64(&mut self) -> Result<u64> { decode_varint_64_slice(self) }
+
+    /// Decode a length-prefixed value from the start of the slice and advance past it.
+    pub fn get_length_prefixed(&mut self) -> Result<BorrowedSlice<'a>> { decode_length_prefixed_slice(self) }
+}
+
+impl Index<usize> for BorrowedSlice<'_> {
+    type Output = u8;
+
+    /// Return the ith byte in the referenced data
+    /// REQUIRES: index < self.len()
+    fn index(&self, index: usize) -> &u8 { &self.data[index] }
+}
+
+impl<'a> From<&'a [u8]> for BorrowedSlice<'a> {
+    #[inline]
+    fn from(s: &'a [u8]) -> Self { Self::new(s) }
+}
+
+impl<'a> From<&'a Vec<u8>> for BorrowedSlice<'a> {
+    #[inline]
+    fn from(v: &'a Vec<u8>) -> Self { Self::new(v) }
+}
+
+impl<'a> From<&'a str> for BorrowedSlice<'a> {
+    #[inline]
+    fn from(s: &'a str) -> Self { Self::new(s.as_bytes()) }
+}
+
+impl ::std::fmt::Display for BorrowedSlice<'_> {
+    /// Writes the slice data as a string, replacing invalid UTF-8 sequences.
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.write_str(&String::from_utf8_lossy(self.data))
+    }
+}
@@ -0,0 +1,44 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::borrow::Cow;
+
+/// A SliceTransform maps a key to its prefix. With a prefix extractor configured, each
+/// table stores a filter over the prefixes of its keys (when a filter policy is set),
+/// and iterators opened with `ReadOptions::prefix_same_as_start` only visit keys that
+/// share the prefix of the seek target, skipping tables whose filter rules it out.
+///
+/// All keys with the same prefix must be adjacent in the order of the database's
+/// comparator, e.g. a fixed-length prefix with the default bytewise comparator.
+pub trait SliceTransform: Send + Sync {
+    /// The name of the transform. It is recorded with the prefix filters in each table,
+    /// so it must change if the transform changes in an incompatible way.
+    fn name(&self) -> &str;
+
+    /// Return the prefix of `key`.
+    ///
+    /// REQUIRES: `in_domain(key)`
+    fn transform<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]>;
+
+    /// Return true if `key` has a prefix. Keys outside the domain are never ruled out
+    /// by prefix filters.
+    fn in_domain(&self, key: &[u8]) -> bool;
+}
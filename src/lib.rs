@@ -0,0 +1,8 @@
+#[macro_use]
+pub mod leveldb;
+pub mod db;
+pub mod table;
+pub mod util;
+pub mod r#async;
+#[cfg(feature = "std")]
+pub mod hefesto_c;
@@ -1,6 +1,3 @@
-mod util;
-mod leveldb;
-
 fn main() {
     println!("Hello, world!");
 }
@@ -0,0 +1,367 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::{
+    leveldb::{
+        comparator::Comparator,
+        iterator::{new_error_iterator, Iterator},
+        result::{Error, ErrorType, Result},
+        slice::BorrowedSlice,
+    },
+    util::coding::decode_fixed_32,
+};
+
+/// An immutable, parsed data or index block. See block_builder.rs for the layout.
+pub struct Block {
+    data: Vec<u8>,
+    // Offset in data of restart array
+    restart_offset: usize,
+    // Block is malformed iff this is false
+    well_formed: bool,
+}
+
+impl Block {
+    /// Initialize the block with the specified contents.
+    pub fn new(contents: Vec<u8>) -> Self {
+        let mut block = Self {
+            data: contents,
+            restart_offset: 0,
+            well_formed: false,
+        };
+        let size = block.data.len();
+        if size >= 4 {
+            let max_restarts_allowed = (size - 4) / 4;
+            let num_restarts = block.num_restarts() as usize;
+            if num_restarts <= max_restarts_allowed {
+                block.restart_offset = size - (1 + num_restarts) * 4;
+                block.well_formed = true;
+            }
+        }
+        block
+    }
+
+    /// Return the size of the block contents in bytes.
+    pub fn size(&self) -> usize { self.data.len() }
+
+    /// Return the raw block contents, e.g. of a filter partition cached as a block.
+    pub fn data(&self) -> &[u8] { &self.data }
+
+    fn num_restarts(&self) -> u32 {
+        assert!(self.data.len() >= 4);
+        decode_fixed_32(&self.data[self.data.len() - 4..])
+    }
+
+    /// Return an iterator over the entries of the block, ordered by `comparator`.
+    pub fn new_iterator(self: &Arc<Self>, comparator: Arc<dyn Comparator>) -> Box<dyn Iterator> {
+        if !self.well_formed {
+            return new_error_iterator(Error::new(ErrorType::Corruption, "bad block contents"));
+        }
+        let num_restarts = self.num_restarts();
+        if num_restarts == 0 {
+            return crate::leveldb::iterator::new_empty_iterator();
+        }
+        Box::new(BlockIter {
+            block: self.clone(),
+            comparator,
+            restarts: self.restart_offset,
+            num_restarts,
+            current: self.restart_offset,
+            restart_index: num_restarts,
+            key: Vec::new(),
+            value: (0, 0),
+            status: Ok(()),
+        })
+    }
+}
+
+/// Helper routine: decode the next block entry starting at `p`, storing the number of
+/// shared key bytes, non_shared key bytes, and the length of the value. Returns the
+/// offset of the key delta, or `None` if the entry is malformed.
+fn decode_entry(data: &[u8], p: usize, limit: usize) -> Option<(usize, usize, usize, usize)> {
+    if limit < p + 3 {
+        return None;
+    }
+    let mut input = BorrowedSlice::new(&data[p..limit]);
+    let shared = input.get_varint32().ok()? as usize;
+    let non_shared = input.get_varint32().ok()? as usize;
+    let value_length = input.get_varint32().ok()? as usize;
+    if input.len() < non_shared + value_length {
+        return None;
+    }
+    Some((shared, non_shared, value_length, limit - input.len()))
+}
+
+struct BlockIter {
+    block: Arc<Block>,
+    comparator: Arc<dyn Comparator>,
+    // Offset of restart array (list of fixed32)
+    restarts: usize,
+    // Number of u32 entries in restart array
+    num_restarts: u32,
+
+    // current is offset in data of current entry. >= restarts if !valid
+    current: usize,
+    // Index of restart block in which current falls
+    restart_index: u32,
+    key: Vec<u8>,
+    // (offset, length) of the current value in data
+    value: (usize, usize),
+    status: Result<()>,
+}
+
+impl BlockIter {
+    #[inline]
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering { self.comparator.compare(a, b) }
+
+    /// Return the offset in data just past the end of the current entry.
+    #[inline]
+    fn next_entry_offset(&self) -> usize { self.value.0 + self.value.1 }
+
+    fn get_restart_point(&self, index: u32) -> usize {
+        assert!(index < self.num_restarts);
+        decode_fixed_32(&self.block.data[self.restarts + index as usize * 4..]) as usize
+    }
+
+    fn seek_to_restart_point(&mut self, index: u32) {
+        self.key.clear();
+        self.restart_index = index;
+        // current will be fixed by parse_next_key();
+
+        // parse_next_key() starts at the end of value, so set value accordingly
+        let offset = self.get_restart_point(index);
+        self.value = (offset, 0);
+    }
+
+    fn corruption_error(&mut self) {
+        self.current = self.restarts;
+        self.restart_index = self.num_restarts;
+        self.status = LEVELDB_ERR!(Corruption, "bad entry in block");
+        self.key.clear();
+        self.value = (0, 0);
+    }
+
+    fn parse_next_key(&mut self) -> bool {
+        self.current = self.next_entry_offset();
+        let p = self.current;
+        let limit = self.restarts; // Restarts come right after data
+        if p >= limit {
+            // No more entries to return. Mark as invalid.
+            self.current = self.restarts;
+            self.restart_index = self.num_restarts;
+            return false;
+        }
+
+        // Decode next entry
+        match decode_entry(&self.block.data, p, limit) {
+            Some((shared, non_shared, value_length, key_offset)) if self.key.len() >= shared => {
+                self.key.truncate(shared);
+                self.key
+                    .extend_from_slice(&self.block.data[key_offset..key_offset + non_shared]);
+                self.value = (key_offset + non_shared, value_length);
+                while self.restart_index + 1 < self.num_restarts
+                    && self.get_restart_point(self.restart_index + 1) < self.current
+                {
+                    self.restart_index += 1;
+                }
+                true
+            }
+            _ => {
+                self.corruption_error();
+                false
+            }
+        }
+    }
+}
+
+impl Iterator for BlockIter {
+    fn valid(&self) -> bool { self.current < self.restarts }
+
+    fn seek_to_first(&mut self) {
+        self.seek_to_restart_point(0);
+        self.parse_next_key();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.seek_to_restart_point(self.num_restarts - 1);
+        while self.parse_next_key() && self.next_entry_offset() < self.restarts {
+            // Keep skipping
+        }
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        // Binary search in restart array to find the last restart point with a key <
+        // target
+        let mut left = 0;
+        let mut right = self.num_restarts - 1;
+        while left < right {
+            let mid = (left + right).div_ceil(2);
+            let region_offset = self.get_restart_point(mid);
+            match decode_entry(&self.block.data, region_offset, self.restarts) {
+                Some((0, non_shared, _, key_offset)) => {
+                    let mid_key = &self.block.data[key_offset..key_offset + non_shared];
+                    if self.compare(mid_key, target) == Ordering::Less {
+                        // Key at "mid" is smaller than "target". Therefore all blocks
+                        // before "mid" are uninteresting.
+                        left = mid;
+                    } else {
+                        // Key at "mid" is >= "target". Therefore all blocks at or after
+                        // "mid" are uninteresting.
+                        right = mid - 1;
+                    }
+                }
+                _ => {
+                    self.corruption_error();
+                    return;
+                }
+            }
+        }
+
+        // Linear search (within restart block) for first key >= target
+        self.seek_to_restart_point(left);
+        loop {
+            if !self.parse_next_key() {
+                return;
+            }
+            if self.compare(&self.key, target) != Ordering::Less {
+                return;
+            }
+        }
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        self.parse_next_key();
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid());
+
+        // Scan backwards to a restart point before current
+        let original = self.current;
+        while self.get_restart_point(self.restart_index) >= original {
+            if self.restart_index == 0 {
+                // No more entries
+                self.current = self.restarts;
+                self.restart_index = self.num_restarts;
+                return;
+            }
+            self.restart_index -= 1;
+        }
+
+        self.seek_to_restart_point(self.restart_index);
+        // Loop until end of current entry hits the start of original entry
+        while self.parse_next_key() && self.next_entry_offset() < original {}
+    }
+
+    fn key(&self) -> &[u8] {
+        assert!(self.valid());
+        &self.key
+    }
+
+    fn value(&self) -> &[u8] {
+        assert!(self.valid());
+        &self.block.data[self.value.0..self.value.0 + self.value.1]
+    }
+
+    fn status(&self) -> Result<()> { self.status.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{table::block_builder::BlockBuilder, util::comparator::bytewise_comparator};
+
+    fn build(entries: &[(&[u8], &[u8])], restart_interval: usize) -> Arc<Block> {
+        let mut builder = BlockBuilder::new(restart_interval, bytewise_comparator());
+        for (k, v) in entries {
+            builder.add(k, v);
+        }
+        Arc::new(Block::new(builder.finish().to_vec()))
+    }
+
+    #[test]
+    fn empty_block() {
+        let block = build(&[], 16);
+        let mut iter = block.new_iterator(bytewise_comparator());
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        iter.seek(b"foo");
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+    }
+
+    #[test]
+    fn iterate_and_seek() {
+        let keys: Vec<Vec<u8>> = (0..200).map(|i| format!("key{:05}", i * 2).into_bytes()).collect();
+        let entries: Vec<(&[u8], &[u8])> = keys.iter().map(|k| (&k[..], &k[3..])).collect();
+        for interval in [1, 2, 16] {
+            let block = build(&entries, interval);
+            let mut iter = block.new_iterator(bytewise_comparator());
+            iter.seek_to_first();
+            for (k, v) in entries.iter() {
+                assert!(iter.valid());
+                assert_eq!(iter.key(), *k);
+                assert_eq!(iter.value(), *v);
+                iter.next();
+            }
+            assert!(!iter.valid());
+
+            // Seek to present and absent keys
+            iter.seek(b"key00100");
+            assert_eq!(iter.key(), b"key00100");
+            iter.seek(b"key00101");
+            assert_eq!(iter.key(), b"key00102");
+            iter.seek(b"a");
+            assert_eq!(iter.key(), b"key00000");
+            iter.seek(b"z");
+            assert!(!iter.valid());
+
+            // Iterate backwards
+            iter.seek_to_last();
+            for (k, v) in entries.iter().rev() {
+                assert!(iter.valid());
+                assert_eq!(iter.key(), *k);
+                assert_eq!(iter.value(), *v);
+                iter.prev();
+            }
+            assert!(!iter.valid());
+            iter.seek_for_prev(b"key00101");
+            assert_eq!(iter.key(), b"key00100");
+            iter.seek_for_prev(b"key00100");
+            assert_eq!(iter.key(), b"key00100");
+            iter.seek_for_prev(b"z");
+            assert_eq!(iter.key(), b"key00398");
+            iter.seek_for_prev(b"a");
+            assert!(!iter.valid());
+        }
+    }
+
+    #[test]
+    fn corrupted_block() {
+        let block = Arc::new(Block::new(vec![1, 2, 3]));
+        let mut iter = block.new_iterator(bytewise_comparator());
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        assert!(iter.status().unwrap_err().is_corruption());
+    }
+}
@@ -0,0 +1,151 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// BlockBuilder generates blocks where keys are prefix-compressed:
+//
+// When we store a key, we drop the prefix shared with the previous string. This helps
+// reduce the space requirement significantly. Furthermore, once every K keys, we do not
+// apply the prefix compression and store the entire key. We call this a "restart
+// point". The tail end of the block stores the offsets of all of the restart points,
+// and can be used to do a binary search when looking for a particular key. Values are
+// stored as-is (without compression) immediately following the corresponding key.
+//
+// An entry for a particular key-value pair has the form:
+//     shared_bytes: varint32
+//     unshared_bytes: varint32
+//     value_length: varint32
+//     key_delta: char[unshared_bytes]
+//     value: char[value_length]
+// shared_bytes == 0 for restart points.
+//
+// The trailer of the block has the form:
+//     restarts: uint32[num_restarts]
+//     num_restarts: uint32
+// restarts[i] contains the offset within the block of the ith restart point.
+
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::{
+    leveldb::comparator::Comparator,
+    util::coding::{encode_fixed_32_vec, encode_varint_32_vec},
+};
+
+pub struct BlockBuilder {
+    block_restart_interval: usize,
+    comparator: Arc<dyn Comparator>,
+    // Destination buffer
+    buffer: Vec<u8>,
+    // Restart points
+    restarts: Vec<u32>,
+    // Number of entries emitted since restart
+    counter: usize,
+    // Has finish() been called?
+    finished: bool,
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    pub fn new(block_restart_interval: usize, comparator: Arc<dyn Comparator>) -> Self {
+        assert!(block_restart_interval >= 1);
+        Self {
+            block_restart_interval,
+            comparator,
+            buffer: Vec::new(),
+            restarts: vec![0], // First restart point is at offset 0
+            counter: 0,
+            finished: false,
+            last_key: Vec::new(),
+        }
+    }
+
+    /// Reset the contents as if the BlockBuilder was just constructed.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.restarts.clear();
+        self.restarts.push(0); // First restart point is at offset 0
+        self.counter = 0;
+        self.finished = false;
+        self.last_key.clear();
+    }
+
+    /// REQUIRES: `finish()` has not been called since the last call to `reset()`.
+    /// REQUIRES: key is larger than any previously added key
+    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        assert!(!self.finished);
+        assert!(self.counter <= self.block_restart_interval);
+        assert!(
+            self.buffer.is_empty() // No values yet?
+                || self.comparator.compare(key, &self.last_key) == Ordering::Greater
+        );
+        let mut shared = 0;
+        if self.counter < self.block_restart_interval {
+            // See how much sharing to do with previous string
+            let min_length = self.last_key.len().min(key.len());
+            while shared < min_length && self.last_key[shared] == key[shared] {
+                shared += 1;
+            }
+        } else {
+            // Restart compression
+            self.restarts.push(self.buffer.len() as u32);
+            self.counter = 0;
+        }
+        let non_shared = key.len() - shared;
+
+        // Add "<shared><non_shared><value_size>" to buffer
+        encode_varint_32_vec(&mut self.buffer, shared as u32);
+        encode_varint_32_vec(&mut self.buffer, non_shared as u32);
+        encode_varint_32_vec(&mut self.buffer, value.len() as u32);
+
+        // Add string delta to buffer followed by value
+        self.buffer.extend_from_slice(&key[shared..]);
+        self.buffer.extend_from_slice(value);
+
+        // Update state
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(&key[shared..]);
+        assert_eq!(self.last_key, key);
+        self.counter += 1;
+    }
+
+    /// Finish building the block and return a slice that refers to the block contents.
+    /// The returned slice will remain valid for the lifetime of this builder or until
+    /// `reset()` is called.
+    pub fn finish(&mut self) -> &[u8] {
+        // Append restart array
+        for restart in self.restarts.iter() {
+            encode_fixed_32_vec(&mut self.buffer, *restart);
+        }
+        encode_fixed_32_vec(&mut self.buffer, self.restarts.len() as u32);
+        self.finished = true;
+        &self.buffer
+    }
+
+    /// Returns an estimate of the current (uncompressed) size of the block we are
+    /// building.
+    pub fn current_size_estimate(&self) -> usize {
+        self.buffer.len() // Raw data buffer
+            + self.restarts.len() * 4 // Restart array
+            + 4 // Restart array length
+    }
+
+    /// Return true iff no entries have been added since the last `reset()`
+    pub fn is_empty(&self) -> bool { self.buffer.is_empty() }
+}
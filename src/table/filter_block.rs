@@ -0,0 +1,507 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// A filter block is stored near the end of a Table file. It contains filters (e.g.,
+// bloom filters) for all data blocks in the table combined into a single filter block.
+// A full filter block instead holds one filter over all keys of the table, as produced
+// by the filter policy. A partitioned filter block splits such a filter into one full
+// filter per group of consecutive data blocks; each partition is stored as a block of
+// its own and the filter block only indexes them, see FilterPartitionIndex.
+
+use std::sync::Arc;
+
+use crate::{
+    leveldb::filter_policy::FilterPolicy,
+    table::format::BlockHandle,
+    util::coding::{decode_fixed_32, decode_fixed_64, encode_fixed_32_vec, encode_fixed_64_vec},
+};
+
+// Generate new filter every 2KB of data
+const FILTER_BASE_LG: u8 = 11;
+const FILTER_BASE: u64 = 1 << FILTER_BASE_LG;
+
+// Each entry of a partition index is the offset of the first data block covered by the
+// partition followed by the offset and size of the partition, all as fixed64.
+const PARTITION_ENTRY_SIZE: usize = 24;
+
+/// A FilterBlockBuilder is used to construct all of the filters for a particular Table.
+/// It generates a single string which is stored as a special block in the Table.
+///
+/// The sequence of calls to FilterBlockBuilder must match the regexp:
+///      (start_block add_key*)* finish
+pub struct FilterBlockBuilder {
+    policy: Arc<dyn FilterPolicy>,
+    // Build one filter over all keys, ignoring block boundaries
+    full: bool,
+    // Flattened key contents
+    keys: Vec<u8>,
+    // Starting index in keys of each key
+    start: Vec<usize>,
+    // Filter data computed so far
+    result: Vec<u8>,
+    // Offset in result of each filter
+    filter_offsets: Vec<u32>,
+    // Bytes of keys after which a new partition is started, or 0 if not partitioned
+    partition_size: usize,
+    // Offset of the first data block of the current partition
+    partition_start: u64,
+    // Finished partitions with the offset of their first data block
+    partitions: Vec<(u64, Vec<u8>)>,
+}
+
+impl FilterBlockBuilder {
+    pub fn new(policy: Arc<dyn FilterPolicy>) -> Self {
+        Self {
+            policy,
+            full: false,
+            keys: Vec::new(),
+            start: Vec::new(),
+            result: Vec::new(),
+            filter_offsets: Vec::new(),
+            partition_size: 0,
+            partition_start: 0,
+            partitions: Vec::new(),
+        }
+    }
+
+    /// Create a builder of a partitioned filter block: a full filter is cut at the
+    /// first block boundary after `partition_size` bytes of keys have been added to it.
+    /// Use `finish_partitions()` instead of `finish()`.
+    pub fn new_partitioned(policy: Arc<dyn FilterPolicy>, partition_size: usize) -> Self {
+        Self {
+            full: true,
+            partition_size: partition_size.max(1),
+            ..Self::new(policy)
+        }
+    }
+
+    pub fn is_partitioned(&self) -> bool { self.partition_size > 0 }
+
+    /// Create a builder of a full filter block, a single filter over all keys added.
+    pub fn new_full(policy: Arc<dyn FilterPolicy>) -> Self {
+        Self {
+            full: true,
+            ..Self::new(policy)
+        }
+    }
+
+    pub fn start_block(&mut self, block_offset: u64) {
+        if self.is_partitioned() && self.keys.len() >= self.partition_size {
+            self.finish_partition();
+            self.partition_start = block_offset;
+        }
+        if self.full {
+            return;
+        }
+        let filter_index = block_offset / FILTER_BASE;
+        assert!(filter_index >= self.filter_offsets.len() as u64);
+        while filter_index > self.filter_offsets.len() as u64 {
+            self.generate_filter();
+        }
+    }
+
+    pub fn add_key(&mut self, key: &[u8]) {
+        self.start.push(self.keys.len());
+        self.keys.extend_from_slice(key);
+    }
+
+    pub fn finish(&mut self) -> &[u8] {
+        if self.full {
+            self.generate_filter();
+            return &self.result;
+        }
+        if !self.start.is_empty() {
+            self.generate_filter();
+        }
+
+        // Append array of per-filter offsets
+        let array_offset = self.result.len() as u32;
+        for offset in self.filter_offsets.iter() {
+            encode_fixed_32_vec(&mut self.result, *offset);
+        }
+        encode_fixed_32_vec(&mut self.result, array_offset);
+        self.result.push(FILTER_BASE_LG); // Save encoding parameter in result
+        &self.result
+    }
+
+    /// Finish a partitioned filter block, returning each partition with the offset of
+    /// the first data block it covers.
+    pub fn finish_partitions(&mut self) -> Vec<(u64, Vec<u8>)> {
+        assert!(self.is_partitioned());
+        if !self.start.is_empty() {
+            self.finish_partition();
+        }
+        std::mem::take(&mut self.partitions)
+    }
+
+    fn finish_partition(&mut self) {
+        self.generate_filter();
+        self.filter_offsets.clear();
+        self.partitions.push((self.partition_start, std::mem::take(&mut self.result)));
+    }
+
+    fn generate_filter(&mut self) {
+        let num_keys = self.start.len();
+        if num_keys == 0 {
+            // Fast path if there are no keys for this filter
+            self.filter_offsets.push(self.result.len() as u32);
+            return;
+        }
+
+        // Make list of keys from flattened key structure
+        self.start.push(self.keys.len()); // Simplify length computation
+        let tmp_keys: Vec<&[u8]> = self
+            .start
+            .windows(2)
+            .map(|w| &self.keys[w[0]..w[1]])
+            .collect();
+
+        // Generate filter for current set of keys and append to result.
+        self.filter_offsets.push(self.result.len() as u32);
+        self.policy.create_filter(&tmp_keys, &mut self.result);
+
+        self.keys.clear();
+        self.start.clear();
+    }
+}
+
+pub struct FilterBlockReader {
+    policy: Arc<dyn FilterPolicy>,
+    data: Vec<u8>,
+    // The data is a single filter for the whole table
+    full: bool,
+    // Offset of beginning of offset array (at block-end)
+    offset: usize,
+    // Number of entries in offset array
+    num: usize,
+    // Encoding parameter (see FILTER_BASE_LG in filter_block.rs)
+    base_lg: u8,
+}
+
+impl FilterBlockReader {
+    /// REQUIRES: `contents` and `policy` must stay live while this reader is live.
+    pub fn new(policy: Arc<dyn FilterPolicy>, contents: Vec<u8>) -> Self {
+        let mut reader = Self {
+            policy,
+            data: contents,
+            full: false,
+            offset: 0,
+            num: 0,
+            base_lg: 0,
+        };
+        let n = reader.data.len();
+        if n < 5 {
+            return reader; // 1 byte for base_lg and 4 for start of offset array
+        }
+        reader.base_lg = reader.data[n - 1];
+        let last_word = decode_fixed_32(&reader.data[n - 5..]) as usize;
+        if last_word > n - 5 {
+            return reader;
+        }
+        reader.offset = last_word;
+        reader.num = (n - 5 - last_word) / 4;
+        reader
+    }
+
+    /// Create a reader of a full filter block, whose filter applies to every block.
+    pub fn new_full(policy: Arc<dyn FilterPolicy>, contents: Vec<u8>) -> Self {
+        Self {
+            policy,
+            data: contents,
+            full: true,
+            offset: 0,
+            num: 0,
+            base_lg: 0,
+        }
+    }
+
+    pub fn is_full(&self) -> bool { self.full }
+
+    pub fn key_may_match(&self, block_offset: u64, key: &[u8]) -> bool {
+        if self.full {
+            return self.policy.key_may_match(key, &self.data);
+        }
+        let index = (block_offset >> self.base_lg) as usize;
+        if index < self.num {
+            let start = decode_fixed_32(&self.data[self.offset + index * 4..]) as usize;
+            let limit = decode_fixed_32(&self.data[self.offset + index * 4 + 4..]) as usize;
+            if start <= limit && limit <= self.offset {
+                let filter = &self.data[start..limit];
+                return self.policy.key_may_match(key, filter);
+            } else if start == limit {
+                // Empty filters do not match any keys
+                return false;
+            }
+        }
+        true // Errors are treated as potential matches
+    }
+
+    /// Batched form of `key_may_match()` for keys that all fall in the data block
+    /// starting at `block_offset`. Sets `results[i]` to whether `keys[i]` may match.
+    pub fn keys_may_match(&self, block_offset: u64, keys: &[&[u8]], results: &mut [bool]) {
+        if self.full {
+            return self.policy.keys_may_match(keys, &self.data, results);
+        }
+        let index = (block_offset >> self.base_lg) as usize;
+        if index < self.num {
+            let start = decode_fixed_32(&self.data[self.offset + index * 4..]) as usize;
+            let limit = decode_fixed_32(&self.data[self.offset + index * 4 + 4..]) as usize;
+            if start <= limit && limit <= self.offset {
+                let filter = &self.data[start..limit];
+                return self.policy.keys_may_match(keys, filter, results);
+            } else if start == limit {
+                // Empty filters do not match any keys
+                return results.fill(false);
+            }
+        }
+        results.fill(true) // Errors are treated as potential matches
+    }
+}
+
+/// Encode the index of a partitioned filter block from the offset of the first data
+/// block of each partition and the location of the partition.
+pub fn encode_partition_index(partitions: &[(u64, BlockHandle)]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(partitions.len() * PARTITION_ENTRY_SIZE);
+    for (block_offset, handle) in partitions {
+        encode_fixed_64_vec(&mut result, *block_offset);
+        encode_fixed_64_vec(&mut result, handle.offset());
+        encode_fixed_64_vec(&mut result, handle.size());
+    }
+    result
+}
+
+/// The index of a partitioned filter block, which locates the partition holding the
+/// filter of a data block. The partitions themselves are read on demand.
+pub struct FilterPartitionIndex {
+    data: Vec<u8>,
+}
+
+impl FilterPartitionIndex {
+    pub fn new(contents: Vec<u8>) -> Self { Self { data: contents } }
+
+    pub fn num_partitions(&self) -> usize { self.data.len() / PARTITION_ENTRY_SIZE }
+
+    /// Return false if the index is not a whole number of entries.
+    pub fn is_well_formed(&self) -> bool { self.data.len().is_multiple_of(PARTITION_ENTRY_SIZE) }
+
+    /// Return the location of partition `i`.
+    ///
+    /// REQUIRES: `i < num_partitions()`
+    pub fn partition(&self, i: usize) -> BlockHandle {
+        let e = &self.data[i * PARTITION_ENTRY_SIZE..(i + 1) * PARTITION_ENTRY_SIZE];
+        BlockHandle::new(decode_fixed_64(&e[8..]), decode_fixed_64(&e[16..]))
+    }
+
+    /// Return the location of the partition with the filter of the data block starting
+    /// at `block_offset`, or `None` if no partition covers it.
+    pub fn find(&self, block_offset: u64) -> Option<BlockHandle> {
+        let entry = |i: usize| &self.data[i * PARTITION_ENTRY_SIZE..(i + 1) * PARTITION_ENTRY_SIZE];
+        // Number of partitions starting at or before block_offset
+        let (mut left, mut right) = (0, self.num_partitions());
+        while left < right {
+            let mid = (left + right) / 2;
+            if decode_fixed_64(entry(mid)) <= block_offset {
+                left = mid + 1;
+            } else {
+                right = mid;
+            }
+        }
+        Some(self.partition(left.checked_sub(1)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // For testing: emit an array with one hash value per key
+    struct TestHashFilter;
+
+    fn hash(key: &[u8]) -> u32 { crate::util::crc32c::value(key) }
+
+    impl FilterPolicy for TestHashFilter {
+        fn name(&self) -> &'static str { "TestHashFilter" }
+
+        fn create_filter(&self, keys: &[&[u8]], dst: &mut Vec<u8>) {
+            for key in keys {
+                encode_fixed_32_vec(dst, hash(key));
+            }
+        }
+
+        fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool {
+            let h = hash(key);
+            filter.chunks(4).any(|c| c.len() == 4 && decode_fixed_32(c) == h)
+        }
+    }
+
+    #[test]
+    fn empty_builder() {
+        let mut builder = FilterBlockBuilder::new(Arc::new(TestHashFilter));
+        let block = builder.finish().to_vec();
+        assert_eq!(block, [0, 0, 0, 0, 11]);
+        let reader = FilterBlockReader::new(Arc::new(TestHashFilter), block);
+        assert!(reader.key_may_match(0, b"foo"));
+        assert!(reader.key_may_match(100000, b"foo"));
+    }
+
+    #[test]
+    fn single_chunk() {
+        let mut builder = FilterBlockBuilder::new(Arc::new(TestHashFilter));
+        builder.start_block(100);
+        builder.add_key(b"foo");
+        builder.add_key(b"bar");
+        builder.add_key(b"box");
+        builder.start_block(200);
+        builder.add_key(b"box");
+        builder.start_block(300);
+        builder.add_key(b"hello");
+        let block = builder.finish().to_vec();
+        let reader = FilterBlockReader::new(Arc::new(TestHashFilter), block);
+        assert!(reader.key_may_match(100, b"foo"));
+        assert!(reader.key_may_match(100, b"bar"));
+        assert!(reader.key_may_match(100, b"box"));
+        assert!(reader.key_may_match(100, b"hello"));
+        assert!(!reader.key_may_match(100, b"missing"));
+        assert!(!reader.key_may_match(100, b"other"));
+    }
+
+    #[test]
+    fn multi_chunk() {
+        let mut builder = FilterBlockBuilder::new(Arc::new(TestHashFilter));
+
+        // First filter
+        builder.start_block(0);
+        builder.add_key(b"foo");
+        builder.start_block(2000);
+        builder.add_key(b"bar");
+
+        // Second filter
+        builder.start_block(3100);
+        builder.add_key(b"box");
+
+        // Third filter is empty
+
+        // Last filter
+        builder.start_block(9000);
+        builder.add_key(b"box");
+        builder.add_key(b"hello");
+
+        let block = builder.finish().to_vec();
+        let reader = FilterBlockReader::new(Arc::new(TestHashFilter), block);
+
+        // Check first filter
+        assert!(reader.key_may_match(0, b"foo"));
+        assert!(reader.key_may_match(2000, b"bar"));
+        assert!(!reader.key_may_match(0, b"box"));
+        assert!(!reader.key_may_match(0, b"hello"));
+
+        // Check second filter
+        assert!(reader.key_may_match(3100, b"box"));
+        assert!(!reader.key_may_match(3100, b"foo"));
+        assert!(!reader.key_may_match(3100, b"bar"));
+        assert!(!reader.key_may_match(3100, b"hello"));
+
+        // Check third filter (empty)
+        assert!(!reader.key_may_match(4100, b"foo"));
+        assert!(!reader.key_may_match(4100, b"bar"));
+        assert!(!reader.key_may_match(4100, b"box"));
+        assert!(!reader.key_may_match(4100, b"hello"));
+
+        // Check last filter
+        assert!(reader.key_may_match(9000, b"box"));
+        assert!(reader.key_may_match(9000, b"hello"));
+        assert!(!reader.key_may_match(9000, b"foo"));
+        assert!(!reader.key_may_match(9000, b"bar"));
+
+        // Batched probes agree with single probes
+        let keys: [&[u8]; 4] = [b"bar", b"box", b"foo", b"hello"];
+        let mut results = [false; 4];
+        for offset in [0, 2000, 3100, 4100, 9000, 100000] {
+            reader.keys_may_match(offset, &keys, &mut results);
+            for (key, result) in keys.iter().zip(results) {
+                assert_eq!(result, reader.key_may_match(offset, key));
+            }
+        }
+    }
+
+    #[test]
+    fn full_filter() {
+        let mut builder = FilterBlockBuilder::new_full(Arc::new(TestHashFilter));
+        builder.start_block(0);
+        builder.add_key(b"foo");
+        builder.start_block(3100);
+        builder.add_key(b"bar");
+        builder.start_block(9000);
+        builder.add_key(b"box");
+        let block = builder.finish().to_vec();
+        // A single filter with no offset array
+        assert_eq!(block.len(), 12);
+        let reader = FilterBlockReader::new_full(Arc::new(TestHashFilter), block);
+        for offset in [0, 3100, 100000] {
+            assert!(reader.key_may_match(offset, b"foo"));
+            assert!(reader.key_may_match(offset, b"bar"));
+            assert!(reader.key_may_match(offset, b"box"));
+            assert!(!reader.key_may_match(offset, b"hello"));
+        }
+        let keys: [&[u8]; 2] = [b"box", b"hello"];
+        let mut results = [false; 2];
+        reader.keys_may_match(0, &keys, &mut results);
+        assert_eq!(results, [true, false]);
+    }
+
+    #[test]
+    fn partitioned_filter() {
+        let mut builder = FilterBlockBuilder::new_partitioned(Arc::new(TestHashFilter), 6);
+        builder.start_block(0);
+        builder.add_key(b"foo");
+        builder.start_block(100);
+        builder.add_key(b"bar");
+        // Six bytes of keys close the first partition
+        builder.start_block(200);
+        builder.add_key(b"box");
+        builder.start_block(300);
+        builder.add_key(b"hello");
+        builder.start_block(400);
+        let partitions = builder.finish_partitions();
+        let starts: Vec<u64> = partitions.iter().map(|(start, _)| *start).collect();
+        // No partition is started for the block boundary after the last key
+        assert_eq!(starts, [0, 200]);
+
+        let handles: Vec<(u64, BlockHandle)> = partitions
+            .iter()
+            .enumerate()
+            .map(|(i, (start, _))| (*start, BlockHandle::new(1000 + i as u64 * 10, 8)))
+            .collect();
+        let index = FilterPartitionIndex::new(encode_partition_index(&handles));
+        assert_eq!(index.num_partitions(), 2);
+        assert_eq!(index.find(0).unwrap().offset(), 1000);
+        assert_eq!(index.find(100).unwrap().offset(), 1000);
+        assert_eq!(index.find(300).unwrap().offset(), 1010);
+        assert_eq!(index.find(5000).unwrap().offset(), 1010);
+        assert!(FilterPartitionIndex::new(Vec::new()).find(0).is_none());
+
+        let policy = TestHashFilter;
+        assert!(policy.key_may_match(b"bar", &partitions[0].1));
+        assert!(!policy.key_may_match(b"box", &partitions[0].1));
+        assert!(policy.key_may_match(b"hello", &partitions[1].1));
+        assert!(!policy.key_may_match(b"foo", &partitions[1].1));
+    }
+}
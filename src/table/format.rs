@@ -0,0 +1,528 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::time::Instant;
+
+use crate::{
+    leveldb::{
+        env::RandomAccessFile,
+        options::{ChecksumType, CompressionType, ReadOptions},
+        result::Result,
+        slice::BorrowedSlice,
+    },
+    util::{
+        coding::{decode_fixed_32, encode_fixed_32_vec, encode_varint_64_vec},
+        crc32c, lz4,
+        xxhash::XxHash64,
+        zstd,
+    },
+};
+
+/// BlockHandle is a pointer to the extent of a file that stores a data block or a meta
+/// block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockHandle {
+    offset: u64,
+    size: u64,
+}
+
+impl BlockHandle {
+    /// Maximum encoding length of a BlockHandle
+    pub const MAX_ENCODED_LENGTH: usize = 10 + 10;
+
+    pub fn new(offset: u64, size: u64) -> Self { Self { offset, size } }
+
+    /// The offset of the block in the file.
+    #[inline]
+    pub fn offset(&self) -> u64 { self.offset }
+
+    #[inline]
+    pub fn set_offset(&mut self, offset: u64) { self.offset = offset }
+
+    /// The size of the stored block
+    #[inline]
+    pub fn size(&self) -> u64 { self.size }
+
+    #[inline]
+    pub fn set_size(&mut self, size: u64) { self.size = size }
+
+    pub fn encode_to(&self, dst: &mut Vec<u8>) {
+        encode_varint_64_vec(dst, self.offset);
+        encode_varint_64_vec(dst, self.size);
+    }
+
+    /// Decode a handle from the start of `input`. Returns the handle and the number of
+    /// bytes consumed.
+    pub fn decode_from(input: &[u8]) -> Result<(BlockHandle, usize)> {
+        let mut slice = BorrowedSlice::new(input);
+        match (slice.get_varint64(), slice.get_varint64()) {
+            (Ok(offset), Ok(size)) => Ok((BlockHandle::new(offset, size), input.len() - slice.len())),
+            _ => LEVELDB_ERR!(Corruption, "bad block handle"),
+        }
+    }
+}
+
+/// Footer encapsulates the fixed information stored at the tail end of every table
+/// file.
+///
+/// Tables of format versions 0 and 1 use the leveldb footer: the two block handles,
+/// padding and `TABLE_MAGIC_NUMBER`. From version 2 on, a byte with the format version
+/// and a byte with the checksum type come before the handles and the footer ends with
+/// `VERSIONED_TABLE_MAGIC_NUMBER` instead, so that readers that predate the version
+/// reject the table rather than misread it. Tables ending with
+/// `CHECKSUMMED_TABLE_MAGIC_NUMBER` have only the checksum type byte and were written
+/// before versions were recorded; they read as version 2.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Footer {
+    metaindex_handle: BlockHandle,
+    index_handle: BlockHandle,
+    checksum_type: ChecksumType,
+    format_version: u32,
+}
+
+impl Footer {
+    /// Encoded length of a Footer. Note that the serialization of a Footer will always
+    /// occupy exactly this many bytes. It consists of two block handles and a magic
+    /// number.
+    pub const ENCODED_LENGTH: usize = 2 * BlockHandle::MAX_ENCODED_LENGTH + 8;
+
+    /// REQUIRES: `check_format_version(format_version, checksum_type)` is ok
+    pub fn new(
+        metaindex_handle: BlockHandle,
+        index_handle: BlockHandle,
+        checksum_type: ChecksumType,
+        format_version: u32,
+    ) -> Self {
+        Self {
+            metaindex_handle,
+            index_handle,
+            checksum_type,
+            format_version,
+        }
+    }
+
+    /// The block handle for the metaindex block of the table
+    pub fn metaindex_handle(&self) -> &BlockHandle { &self.metaindex_handle }
+
+    /// The block handle for the index block of the table
+    pub fn index_handle(&self) -> &BlockHandle { &self.index_handle }
+
+    /// The checksum type of the blocks of the table
+    pub fn checksum_type(&self) -> ChecksumType { self.checksum_type }
+
+    /// The format version of the table. Versions 0 and 1 share a footer, so tables of
+    /// either read as version 1.
+    pub fn format_version(&self) -> u32 { self.format_version }
+
+    pub fn encode_to(&self, dst: &mut Vec<u8>) {
+        let original_size = dst.len();
+        let magic = match self.format_version {
+            0 | 1 => {
+                assert_eq!(self.checksum_type, ChecksumType::Crc32c);
+                TABLE_MAGIC_NUMBER
+            }
+            format_version => {
+                // File offsets and sizes fit in 9 bytes, which leaves room for the
+                // version and the checksum type.
+                dst.push(format_version as u8);
+                dst.push(self.checksum_type.type_byte());
+                VERSIONED_TABLE_MAGIC_NUMBER
+            }
+        };
+        self.metaindex_handle.encode_to(dst);
+        self.index_handle.encode_to(dst);
+        assert!(dst.len() <= original_size + 2 * BlockHandle::MAX_ENCODED_LENGTH);
+        dst.resize(original_size + 2 * BlockHandle::MAX_ENCODED_LENGTH, 0); // Padding
+        encode_fixed_32_vec(dst, (magic & 0xffffffff) as u32);
+        encode_fixed_32_vec(dst, (magic >> 32) as u32);
+        assert_eq!(dst.len(), original_size + Self::ENCODED_LENGTH);
+    }
+
+    pub fn decode_from(input: &[u8]) -> Result<Footer> {
+        if input.len() < Self::ENCODED_LENGTH {
+            return LEVELDB_ERR!(Corruption, "not an sstable (footer too short)");
+        }
+        let magic_ptr = &input[Self::ENCODED_LENGTH - 8..];
+        let magic_lo = decode_fixed_32(magic_ptr) as u64;
+        let magic_hi = decode_fixed_32(&magic_ptr[4..]) as u64;
+        let magic = (magic_hi << 32) | magic_lo;
+        let (format_version, input) = match magic {
+            TABLE_MAGIC_NUMBER => (1, input),
+            CHECKSUMMED_TABLE_MAGIC_NUMBER => (2, input),
+            VERSIONED_TABLE_MAGIC_NUMBER if input[0] as u32 > LATEST_FORMAT_VERSION => {
+                return LEVELDB_ERR!(NotSupported, "unsupported table format version");
+            }
+            VERSIONED_TABLE_MAGIC_NUMBER => (input[0] as u32, &input[1..]),
+            _ => return LEVELDB_ERR!(Corruption, "not an sstable (bad magic number)"),
+        };
+        let (checksum_type, input) = match format_version {
+            0 | 1 => (ChecksumType::Crc32c, input),
+            _ => match ChecksumType::from_u8(input[0]) {
+                Some(checksum_type) => (checksum_type, &input[1..]),
+                None => return LEVELDB_ERR!(Corruption, "unknown checksum type"),
+            },
+        };
+
+        let (metaindex_handle, n) = BlockHandle::decode_from(input)?;
+        let (index_handle, _) = BlockHandle::decode_from(&input[n..])?;
+        Ok(Footer {
+            metaindex_handle,
+            index_handle,
+            checksum_type,
+            format_version,
+        })
+    }
+}
+
+/// TABLE_MAGIC_NUMBER was picked by running
+///    echo http://code.google.com/p/leveldb/ | sha1sum
+/// and taking the leading 64 bits.
+pub const TABLE_MAGIC_NUMBER: u64 = 0xdb4775248b80fb57;
+
+/// Magic number of tables whose footer records a checksum type other than crc32c but
+/// no format version. Only read, never written.
+pub const CHECKSUMMED_TABLE_MAGIC_NUMBER: u64 = 0x88e241b785f4cff7;
+
+/// Magic number of tables whose footer records the format version and checksum type.
+pub const VERSIONED_TABLE_MAGIC_NUMBER: u64 = 0x5c3a9e0d71b4f2e6;
+
+// Table format versions. Each version may use the encodings of all earlier ones:
+//   0 - The leveldb format. Index keys are the last key of each data block.
+//   1 - Index keys are shortened to a separator between adjacent data blocks.
+//   2 - The footer records the format version and the checksum type, which allows
+//       checksum types other than crc32c.
+//   3 - Zstd blocks may be compressed against a dictionary stored in a meta block.
+pub const LATEST_FORMAT_VERSION: u32 = 3;
+
+/// Metaindex key of the meta block holding the dictionary zstd data blocks may be
+/// compressed against.
+pub const COMPRESSION_DICT_KEY: &str = "compressiondict";
+
+/// Return an `InvalidArgument` error if tables of `format_version` cannot be written or
+/// cannot record `checksum_type`.
+pub fn check_format_version(format_version: u32, checksum_type: ChecksumType) -> Result<()> {
+    if format_version > LATEST_FORMAT_VERSION {
+        return LEVELDB_ERR!(InvalidArgument, "unsupported table format version");
+    }
+    if format_version < 2 && checksum_type != ChecksumType::Crc32c {
+        return LEVELDB_ERR!(InvalidArgument, "checksum type requires table format version 2");
+    }
+    Ok(())
+}
+
+/// 1-byte type + 32-bit checksum
+pub const BLOCK_TRAILER_SIZE: usize = 5;
+
+/// Return the checksum stored in the trailer of a block with the given contents and
+/// compression type byte.
+pub fn block_checksum(checksum_type: ChecksumType, contents: &[u8], type_byte: u8) -> u32 {
+    match checksum_type {
+        ChecksumType::Crc32c => {
+            let crc = crc32c::extend(crc32c::value(contents), &[type_byte]); // Extend crc to cover block type
+            crc32c::mask(crc)
+        }
+        ChecksumType::XxHash64 => {
+            let mut hasher = XxHash64::new(0);
+            hasher.update(contents);
+            hasher.update(&[type_byte]);
+            hasher.finish() as u32
+        }
+    }
+}
+
+/// Read the block identified by `handle` from `file`, a table whose blocks are
+/// checksummed with `checksum_type`. On success returns the block contents with the
+/// trailer stripped.
+///
+/// With `options.verify_checksums`, the checksum in the trailer is checked against the
+/// contents and the type byte. A mismatch, like any other damage found in the block,
+/// fails with a `Corruption` error naming the offset of the block.
+///
+/// Fails with `TimedOut` if `options.deadline` has passed before the read starts, and
+/// with `Incomplete` if the read takes longer than `options.io_timeout`.
+pub fn read_block(
+    file: &dyn RandomAccessFile,
+    options: &ReadOptions,
+    checksum_type: ChecksumType,
+    handle: &BlockHandle,
+) -> Result<Vec<u8>> {
+    read_block_with_dict(file, options, checksum_type, handle, &[])
+}
+
+/// Like `read_block()`, for a block of a table whose zstd blocks may be compressed
+/// against the dictionary `dict`.
+pub fn read_block_with_dict(
+    file: &dyn RandomAccessFile,
+    options: &ReadOptions,
+    checksum_type: ChecksumType,
+    handle: &BlockHandle,
+    dict: &[u8],
+) -> Result<Vec<u8>> {
+    read_block_contents(file, options, checksum_type, handle, dict).map_err(|e| match e.is_corruption() {
+        true => e.with_context(format!("reading block at offset {}", handle.offset())),
+        false => e,
+    })
+}
+
+fn read_block_contents(
+    file: &dyn RandomAccessFile,
+    options: &ReadOptions,
+    checksum_type: ChecksumType,
+    handle: &BlockHandle,
+    dict: &[u8],
+) -> Result<Vec<u8>> {
+    // Only look at the clock when asked to, as some targets do not have one.
+    let start = (options.deadline.is_some() || options.io_timeout.is_some()).then(Instant::now);
+    if let (Some(start), Some(deadline)) = (start, options.deadline) {
+        if start >= deadline {
+            return LEVELDB_ERR!(TimedOut, "read deadline exceeded");
+        }
+    }
+
+    // Read the block contents as well as the type/crc footer.
+    // See table_builder.rs for the code that built this structure.
+    let n = handle.size() as usize;
+    let mut owned = Vec::new();
+    let buf: &[u8] = match file.mapped() {
+        // A mapped file is used in place, so only the decoded contents are copied.
+        Some(data) => {
+            let start = handle.offset() as usize;
+            match data.get(start..start.saturating_add(n + BLOCK_TRAILER_SIZE)) {
+                Some(buf) => buf,
+                None => return LEVELDB_ERR!(Corruption, "truncated block read"),
+            }
+        }
+        None => {
+            owned.resize(n + BLOCK_TRAILER_SIZE, 0);
+            let read = file.read(handle.offset(), &mut owned)?;
+            if let (Some(start), Some(timeout)) = (start, options.io_timeout) {
+                if start.elapsed() > timeout {
+                    return LEVELDB_ERR!(Incomplete, "block read exceeded io_timeout");
+                }
+            }
+            if read != n + BLOCK_TRAILER_SIZE {
+                return LEVELDB_ERR!(Corruption, "truncated block read");
+            }
+            &owned
+        }
+    };
+
+    // Check the checksum of the type and the block contents
+    if options.verify_checksums {
+        let expected = decode_fixed_32(&buf[n + 1..]);
+        if block_checksum(checksum_type, &buf[..n], buf[n]) != expected {
+            return LEVELDB_ERR!(Corruption, "block checksum mismatch");
+        }
+    }
+
+    match CompressionType::from_u8(buf[n]) {
+        Some(CompressionType::NoCompression) => {
+            if owned.is_empty() {
+                return Ok(buf[..n].to_vec());
+            }
+            owned.truncate(n);
+            Ok(owned)
+        }
+        Some(CompressionType::Zstd { .. }) => uncompressed(zstd::decompress_with_dict(&buf[..n], dict)),
+        Some(CompressionType::Lz4) => uncompressed(lz4::decompress(&buf[..n])),
+        None => LEVELDB_ERR!(Corruption, "bad block type"),
+    }
+}
+
+fn uncompressed(result: Result<Vec<u8>>) -> Result<Vec<u8>> {
+    match result {
+        Ok(contents) => Ok(contents),
+        Err(_) => LEVELDB_ERR!(Corruption, "corrupted compressed block contents"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::leveldb::result::ErrorType;
+
+    #[test]
+    fn block_handle() {
+        let handle = BlockHandle::new(12345, 678);
+        let mut encoded = Vec::new();
+        handle.encode_to(&mut encoded);
+        let (decoded, n) = BlockHandle::decode_from(&encoded).expect("decode");
+        assert_eq!(decoded, handle);
+        assert_eq!(n, encoded.len());
+        assert!(BlockHandle::decode_from(&encoded[..1]).is_err());
+    }
+
+    #[test]
+    fn footer() {
+        for (checksum_type, format_version) in
+            [(ChecksumType::Crc32c, 1), (ChecksumType::Crc32c, 2), (ChecksumType::XxHash64, 2)]
+        {
+            let footer = Footer::new(BlockHandle::new(1, 2), BlockHandle::new(300, 4000), checksum_type, format_version);
+            let mut encoded = Vec::new();
+            footer.encode_to(&mut encoded);
+            assert_eq!(encoded.len(), Footer::ENCODED_LENGTH);
+            assert_eq!(Footer::decode_from(&encoded).expect("decode"), footer);
+
+            encoded[Footer::ENCODED_LENGTH - 1] ^= 0xff;
+            assert!(Footer::decode_from(&encoded).unwrap_err().is_corruption());
+        }
+
+        // The largest handles a file can hold still fit next to the checksum type.
+        let max = BlockHandle::new(u64::MAX >> 1, u64::MAX >> 1);
+        let footer = Footer::new(max, max, ChecksumType::XxHash64, LATEST_FORMAT_VERSION);
+        let mut encoded = Vec::new();
+        footer.encode_to(&mut encoded);
+        assert_eq!(Footer::decode_from(&encoded).expect("decode"), footer);
+        encoded[1] = 0x7f;
+        assert!(Footer::decode_from(&encoded).unwrap_err().is_corruption());
+        // Versions from the future are refused rather than misread
+        encoded[0] = LATEST_FORMAT_VERSION as u8 + 1;
+        assert_eq!(Footer::decode_from(&encoded).unwrap_err().ty(), ErrorType::NotSupported);
+
+        // Footers written before the version was recorded
+        let mut encoded = vec![ChecksumType::XxHash64.type_byte()];
+        BlockHandle::new(1, 2).encode_to(&mut encoded);
+        BlockHandle::new(300, 4000).encode_to(&mut encoded);
+        encoded.resize(2 * BlockHandle::MAX_ENCODED_LENGTH, 0);
+        encode_fixed_32_vec(&mut encoded, (CHECKSUMMED_TABLE_MAGIC_NUMBER & 0xffffffff) as u32);
+        encode_fixed_32_vec(&mut encoded, (CHECKSUMMED_TABLE_MAGIC_NUMBER >> 32) as u32);
+        let footer = Footer::decode_from(&encoded).expect("decode");
+        assert_eq!(footer.checksum_type(), ChecksumType::XxHash64);
+        assert_eq!(footer.format_version(), 2);
+        assert_eq!(footer.index_handle(), &BlockHandle::new(300, 4000));
+
+        assert!(check_format_version(0, ChecksumType::Crc32c).is_ok());
+        assert!(check_format_version(1, ChecksumType::XxHash64).is_err());
+        assert!(check_format_version(LATEST_FORMAT_VERSION + 1, ChecksumType::Crc32c).is_err());
+    }
+
+    /// A file holding one uncompressed block, whose reads take `delay`.
+    struct SlowFile {
+        contents: Vec<u8>,
+        delay: Duration,
+    }
+
+    impl RandomAccessFile for SlowFile {
+        fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+            std::thread::sleep(self.delay);
+            let data = &self.contents[offset as usize..];
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn read_block_deadlines() {
+        let mut contents = b"block".to_vec();
+        contents.push(CompressionType::NoCompression.type_byte());
+        encode_fixed_32_vec(&mut contents, 0);
+        let handle = BlockHandle::new(0, 5);
+        let file = SlowFile {
+            contents,
+            delay: Duration::from_millis(20),
+        };
+
+        assert_eq!(read_block(&file, &ReadOptions::default(), ChecksumType::Crc32c, &handle).unwrap(), b"block");
+
+        let options = ReadOptions {
+            deadline: Some(Instant::now()),
+            ..ReadOptions::default()
+        };
+        assert!(read_block(&file, &options, ChecksumType::Crc32c, &handle).unwrap_err().is_timed_out());
+
+        let options = ReadOptions {
+            deadline: Some(Instant::now() + Duration::from_secs(60)),
+            io_timeout: Some(Duration::from_millis(1)),
+            ..ReadOptions::default()
+        };
+        assert!(read_block(&file, &options, ChecksumType::Crc32c, &handle).unwrap_err().is_incomplete());
+
+        let options = ReadOptions {
+            io_timeout: Some(Duration::from_secs(60)),
+            ..ReadOptions::default()
+        };
+        assert_eq!(read_block(&file, &options, ChecksumType::Crc32c, &handle).unwrap(), b"block");
+    }
+
+    #[test]
+    fn compressed_block() {
+        let raw = b"hefestodb hefestodb hefestodb hefestodb hefestodb".repeat(10);
+        for (compression, compressed) in [
+            (CompressionType::Zstd { level: 5 }, zstd::compress(&raw, 5)),
+            (CompressionType::Lz4, lz4::compress(&raw)),
+        ] {
+            let mut contents = compressed;
+            let handle = BlockHandle::new(0, contents.len() as u64);
+            contents.push(compression.type_byte());
+            encode_fixed_32_vec(&mut contents, 0);
+
+            let mut file = SlowFile {
+                contents,
+                delay: Duration::ZERO,
+            };
+            assert_eq!(read_block(&file, &ReadOptions::default(), ChecksumType::Crc32c, &handle).unwrap(), raw);
+
+            file.contents[0] ^= 1;
+            assert!(read_block(&file, &ReadOptions::default(), ChecksumType::Crc32c, &handle).unwrap_err().is_corruption());
+        }
+        assert_eq!(CompressionType::from_u8(0x2), Some(CompressionType::Zstd { level: 3 }));
+        assert_eq!(CompressionType::from_u8(0x3), Some(CompressionType::Lz4));
+        assert_eq!(CompressionType::from_u8(0x1), None);
+    }
+
+    #[test]
+    fn verify_checksums() {
+        let mut contents = b"block".to_vec();
+        contents.push(CompressionType::NoCompression.type_byte());
+        let crc = crc32c::value(&contents);
+        encode_fixed_32_vec(&mut contents, crc32c::mask(crc));
+        let handle = BlockHandle::new(0, 5);
+        let mut file = SlowFile {
+            contents,
+            delay: Duration::ZERO,
+        };
+        let verify = ReadOptions {
+            verify_checksums: true,
+            ..ReadOptions::default()
+        };
+        assert_eq!(read_block(&file, &verify, ChecksumType::Crc32c, &handle).unwrap(), b"block");
+        assert!(read_block(&file, &verify, ChecksumType::XxHash64, &handle).unwrap_err().is_corruption());
+
+        file.contents[1] ^= 0x01;
+        assert_eq!(read_block(&file, &ReadOptions::default(), ChecksumType::Crc32c, &handle).unwrap(), b"bmock");
+        assert!(read_block(&file, &verify, ChecksumType::Crc32c, &handle).unwrap_err().is_corruption());
+
+        let mut contents = b"block".to_vec();
+        let type_byte = CompressionType::NoCompression.type_byte();
+        let checksum = block_checksum(ChecksumType::XxHash64, &contents, type_byte);
+        contents.push(type_byte);
+        encode_fixed_32_vec(&mut contents, checksum);
+        file.contents = contents;
+        assert_eq!(read_block(&file, &verify, ChecksumType::XxHash64, &handle).unwrap(), b"block");
+        file.contents[1] ^= 0x01;
+        let e = read_block(&file, &verify, ChecksumType::XxHash64, &handle).unwrap_err();
+        assert!(e.is_corruption());
+        assert_eq!(e.msg(), "reading block at offset 0: block checksum mismatch");
+    }
+}
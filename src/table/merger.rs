@@ -0,0 +1,262 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::leveldb::{
+    comparator::Comparator,
+    iterator::{new_empty_iterator, Iterator},
+    result::Result,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+struct MergingIterator {
+    comparator: Arc<dyn Comparator>,
+    children: Vec<Box<dyn Iterator>>,
+    current: Option<usize>,
+    // Which direction the children were last moved in. When moving forward, every
+    // child but the current one is positioned after key(); when moving backward,
+    // before it.
+    direction: Direction,
+}
+
+impl MergingIterator {
+    fn find_smallest(&mut self) {
+        let mut smallest: Option<usize> = None;
+        for (i, child) in self.children.iter().enumerate() {
+            if child.valid() {
+                match smallest {
+                    None => smallest = Some(i),
+                    Some(s) => {
+                        if self.comparator.compare(child.key(), self.children[s].key()) == Ordering::Less {
+                            smallest = Some(i);
+                        }
+                    }
+                }
+            }
+        }
+        self.current = smallest;
+    }
+
+    fn find_largest(&mut self) {
+        let mut largest: Option<usize> = None;
+        for (i, child) in self.children.iter().enumerate().rev() {
+            if child.valid() {
+                match largest {
+                    None => largest = Some(i),
+                    Some(l) => {
+                        if self.comparator.compare(child.key(), self.children[l].key()) == Ordering::Greater {
+                            largest = Some(i);
+                        }
+                    }
+                }
+            }
+        }
+        self.current = largest;
+    }
+}
+
+impl Iterator for MergingIterator {
+    fn valid(&self) -> bool { self.current.is_some() }
+
+    fn seek_to_first(&mut self) {
+        for child in self.children.iter_mut() {
+            child.seek_to_first();
+        }
+        self.find_smallest();
+        self.direction = Direction::Forward;
+    }
+
+    fn seek_to_last(&mut self) {
+        for child in self.children.iter_mut() {
+            child.seek_to_last();
+        }
+        self.find_largest();
+        self.direction = Direction::Reverse;
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        for child in self.children.iter_mut() {
+            child.seek(target);
+        }
+        self.find_smallest();
+        self.direction = Direction::Forward;
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        for child in self.children.iter_mut() {
+            child.seek_for_prev(target);
+        }
+        self.find_largest();
+        self.direction = Direction::Reverse;
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        let current = self.current.unwrap();
+
+        // Ensure that all children are positioned after key(). If we are moving in the
+        // forward direction, it is already true for all of the non-current children
+        // since current is the smallest child and key() == current.key(). Otherwise,
+        // we explicitly position the non-current children.
+        if self.direction != Direction::Forward {
+            let key = self.key().to_vec();
+            for (i, child) in self.children.iter_mut().enumerate() {
+                if i != current {
+                    child.seek(&key);
+                    if child.valid() && self.comparator.compare(&key, child.key()) == Ordering::Equal {
+                        child.next();
+                    }
+                }
+            }
+            self.direction = Direction::Forward;
+        }
+
+        self.children[current].next();
+        self.find_smallest();
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid());
+        let current = self.current.unwrap();
+
+        // Ensure that all children are positioned before key(). If we are moving in the
+        // reverse direction, it is already true for all of the non-current children
+        // since current is the largest child and key() == current.key(). Otherwise,
+        // we explicitly position the non-current children.
+        if self.direction != Direction::Reverse {
+            let key = self.key().to_vec();
+            for (i, child) in self.children.iter_mut().enumerate() {
+                if i != current {
+                    child.seek(&key);
+                    if child.valid() {
+                        // Child is at first entry >= key(). Step back one to be < key()
+                        child.prev();
+                    } else {
+                        // Child has no entries >= key(). Position at last entry.
+                        child.seek_to_last();
+                    }
+                }
+            }
+            self.direction = Direction::Reverse;
+        }
+
+        self.children[current].prev();
+        self.find_largest();
+    }
+
+    fn key(&self) -> &[u8] {
+        assert!(self.valid());
+        self.children[self.current.unwrap()].key()
+    }
+
+    fn value(&self) -> &[u8] {
+        assert!(self.valid());
+        self.children[self.current.unwrap()].value()
+    }
+
+    fn status(&self) -> Result<()> {
+        for child in self.children.iter() {
+            child.status()?;
+        }
+        Ok(())
+    }
+}
+
+/// Return an iterator that provided the union of the data in `children`. The result
+/// does no duplicate suppression. I.e., if a particular key is present in K child
+/// iterators, it will be yielded K times.
+pub fn new_merging_iterator(comparator: Arc<dyn Comparator>, mut children: Vec<Box<dyn Iterator>>) -> Box<dyn Iterator> {
+    match children.len() {
+        0 => new_empty_iterator(),
+        1 => children.pop().unwrap(),
+        _ => Box::new(MergingIterator {
+            comparator,
+            children,
+            current: None,
+            direction: Direction::Forward,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        table::{block::Block, block_builder::BlockBuilder},
+        util::comparator::bytewise_comparator,
+    };
+
+    fn block_iter(keys: &[&str]) -> Box<dyn Iterator> {
+        let mut builder = BlockBuilder::new(16, bytewise_comparator());
+        for k in keys {
+            builder.add(k.as_bytes(), b"");
+        }
+        Arc::new(Block::new(builder.finish().to_vec())).new_iterator(bytewise_comparator())
+    }
+
+    #[test]
+    fn merges_in_order() {
+        let mut iter = new_merging_iterator(
+            bytewise_comparator(),
+            vec![block_iter(&["a", "d", "g"]), block_iter(&[]), block_iter(&["b", "c", "h"])],
+        );
+        let mut seen = Vec::new();
+        iter.seek_to_first();
+        while iter.valid() {
+            seen.push(String::from_utf8(iter.key().to_vec()).unwrap());
+            iter.next();
+        }
+        assert_eq!(seen, ["a", "b", "c", "d", "g", "h"]);
+
+        iter.seek(b"e");
+        assert_eq!(iter.key(), b"g");
+        assert!(iter.status().is_ok());
+
+        seen.clear();
+        iter.seek_to_last();
+        while iter.valid() {
+            seen.push(String::from_utf8(iter.key().to_vec()).unwrap());
+            iter.prev();
+        }
+        assert_eq!(seen, ["h", "g", "d", "c", "b", "a"]);
+
+        // Switching directions
+        iter.seek(b"c");
+        iter.prev();
+        assert_eq!(iter.key(), b"b");
+        iter.prev();
+        assert_eq!(iter.key(), b"a");
+        iter.next();
+        assert_eq!(iter.key(), b"b");
+        iter.next();
+        assert_eq!(iter.key(), b"c");
+        iter.seek_for_prev(b"f");
+        assert_eq!(iter.key(), b"d");
+        iter.next();
+        assert_eq!(iter.key(), b"g");
+    }
+}
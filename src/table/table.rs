@@ -0,0 +1,1075 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{cell::Cell, collections::HashMap, sync::Arc};
+
+use crate::{
+    leveldb::{
+        env::{read_exact_at, RandomAccessFile},
+        iterator::{new_error_iterator, Iterator},
+        options::{BlockCache, ChecksumType, Options, ReadOptions},
+        pinnable_slice::PinnableSlice,
+        result::{Error, Result},
+    },
+    table::{
+        block::Block,
+        filter_block::{FilterBlockReader, FilterPartitionIndex},
+        format::{read_block, read_block_with_dict, BlockHandle, Footer, BLOCK_TRAILER_SIZE, COMPRESSION_DICT_KEY},
+        two_level_iterator::new_two_level_iterator,
+    },
+    util::{
+        cache::{CacheHandle, CacheKey},
+        coding::encode_fixed_64,
+    },
+};
+
+/// A key and value found by a point lookup.
+pub type KeyValue = (Vec<u8>, Vec<u8>);
+
+/// A Table is a sorted map from strings to strings. Tables are immutable and
+/// persistent. A Table may be safely accessed from multiple threads without external
+/// synchronization.
+pub struct Table {
+    options: Options,
+    // Name of the file, attached to the errors of block reads
+    file_name: String,
+    file: Box<dyn RandomAccessFile>,
+    cache_id: u64,
+    filter: Option<FilterBlockReader>,
+    // Index of the filter partitions, if the table has a partitioned filter. The
+    // partitions are read on demand.
+    filter_partitions: Option<FilterPartitionIndex>,
+    // Contents of the filter partitions by offset, if they are pinned in memory rather
+    // than read through the block cache, i.e. with `max_open_files == -1`.
+    pinned_filter_partitions: HashMap<u64, Vec<u8>>,
+    // Filter over the prefixes of all keys in the table, if it was built with one.
+    prefix_filter: Option<Vec<u8>>,
+    index_block: Arc<Block>,
+    // Offset of the metaindex block, which follows all of the data blocks.
+    metaindex_offset: u64,
+    checksum_type: ChecksumType,
+    // Dictionary zstd data blocks may be compressed against. Empty if the table has none.
+    compression_dict: Vec<u8>,
+}
+
+impl Table {
+    /// Attempt to open the table that is stored in bytes `[0..file_size)` of `file`, and
+    /// read the metadata entries necessary to allow retrieving data from the table.
+    /// `fname` names the file in the detail of corruption errors.
+    ///
+    /// If successful, returns the newly opened table. If there was an error while
+    /// initializing the table, returns a non-ok status.
+    pub fn open(options: &Options, fname: &str, file: Box<dyn RandomAccessFile>, size: u64) -> Result<Arc<Table>> {
+        Self::open_file(options, fname, file, size).map_err(|e| with_file_detail(e, fname))
+    }
+
+    fn open_file(options: &Options, fname: &str, file: Box<dyn RandomAccessFile>, size: u64) -> Result<Arc<Table>> {
+        if size < Footer::ENCODED_LENGTH as u64 {
+            return LEVELDB_ERR!(Corruption, "file is too short to be an sstable");
+        }
+
+        let mut footer_space = [0u8; Footer::ENCODED_LENGTH];
+        read_exact_at(
+            file.as_ref(),
+            size - Footer::ENCODED_LENGTH as u64,
+            &mut footer_space,
+        )?;
+        let footer = Footer::decode_from(&footer_space)?;
+
+        // Read the index block
+        let read_options = ReadOptions {
+            verify_checksums: options.paranoid_checks,
+            ..ReadOptions::default()
+        };
+        let index_block_contents = read_block(file.as_ref(), &read_options, footer.checksum_type(), footer.index_handle())?;
+
+        // We've successfully read the footer and the index block: we're ready to serve
+        // requests.
+        let cache_id = match options.block_cache.as_ref() {
+            Some(cache) => cache.new_cache_id(),
+            None => 0,
+        };
+        let mut table = Table {
+            options: options.clone(),
+            file_name: fname.to_string(),
+            file,
+            cache_id,
+            filter: None,
+            filter_partitions: None,
+            pinned_filter_partitions: HashMap::new(),
+            prefix_filter: None,
+            index_block: Arc::new(Block::new(index_block_contents)),
+            metaindex_offset: footer.metaindex_handle().offset(),
+            checksum_type: footer.checksum_type(),
+            compression_dict: Vec::new(),
+        };
+        table.read_meta(&footer)?;
+        Ok(Arc::new(table))
+    }
+
+    // Read the meta blocks. Only a failure to read the compression dictionary is an
+    // error, since the data blocks cannot be read without it.
+    fn read_meta(&mut self, footer: &Footer) -> Result<()> {
+        // TODO(sanjay): Skip this if footer.metaindex_handle() size indicates it is an
+        // empty block.
+        let opt = ReadOptions {
+            verify_checksums: self.options.paranoid_checks,
+            ..ReadOptions::default()
+        };
+        let contents = match read_block(self.file.as_ref(), &opt, self.checksum_type, footer.metaindex_handle()) {
+            Ok(contents) => contents,
+            // Do not propagate errors since meta info is not needed for operation
+            Err(_) => return Ok(()),
+        };
+        let meta = Arc::new(Block::new(contents));
+
+        let mut iter = meta.new_iterator(crate::util::comparator::bytewise_comparator());
+        iter.seek(COMPRESSION_DICT_KEY.as_bytes());
+        if iter.valid() && iter.key() == COMPRESSION_DICT_KEY.as_bytes() {
+            let (handle, _) = BlockHandle::decode_from(iter.value())?;
+            self.compression_dict = read_block(self.file.as_ref(), &opt, self.checksum_type, &handle)?;
+        }
+
+        let policy = match self.options.filter_policy.clone() {
+            Some(policy) => policy,
+            None => return Ok(()), // Do not need any filter
+        };
+        for kind in ["filter", "fullfilter", "partitionedfilter"] {
+            let key = format!("{}.{}", kind, policy.name());
+            iter.seek(key.as_bytes());
+            if iter.valid() && iter.key() == key.as_bytes() {
+                if let Ok((handle, _)) = BlockHandle::decode_from(iter.value()) {
+                    // We might want to unify with read_block() if we start requiring
+                    // checksum verification in Table::open.
+                    if let Ok(block) = read_block(self.file.as_ref(), &opt, self.checksum_type, &handle) {
+                        match kind {
+                            "filter" => self.filter = Some(FilterBlockReader::new(policy.clone(), block)),
+                            "fullfilter" => self.filter = Some(FilterBlockReader::new_full(policy.clone(), block)),
+                            _ => {
+                                let index = FilterPartitionIndex::new(block);
+                                if self.options.max_open_files == -1 {
+                                    self.pin_filter_partitions(&opt, &index);
+                                }
+                                self.filter_partitions = Some(index);
+                            }
+                        }
+                    }
+                }
+                break;
+            }
+        }
+
+        if let Some(transform) = self.options.prefix_extractor.as_ref() {
+            let key = format!("prefixfilter.{}.{}", policy.name(), transform.name());
+            iter.seek(key.as_bytes());
+            if iter.valid() && iter.key() == key.as_bytes() {
+                if let Ok((handle, _)) = BlockHandle::decode_from(iter.value()) {
+                    self.prefix_filter = read_block(self.file.as_ref(), &opt, self.checksum_type, &handle).ok();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Read all the filter partitions into memory. Partitions that cannot be read are
+    // left to the block cache.
+    fn pin_filter_partitions(&mut self, opt: &ReadOptions, index: &FilterPartitionIndex) {
+        if !index.is_well_formed() {
+            return;
+        }
+        for i in 0..index.num_partitions() {
+            let handle = index.partition(i);
+            if let Ok(partition) = read_block(self.file.as_ref(), opt, self.checksum_type, &handle) {
+                self.pinned_filter_partitions.insert(handle.offset(), partition);
+            }
+        }
+    }
+
+    /// Erase the blocks of this table from the block cache, e.g. once its file has been
+    /// deleted, rather than leaving them to be evicted.
+    pub fn evict_blocks(&self) {
+        if let Some(cache) = self.options.block_cache.as_ref() {
+            cache.erase_cache_id(self.cache_id);
+        }
+    }
+
+    /// Return false if the table certainly holds no key with the same prefix as `key`,
+    /// according to the table's prefix filter.
+    pub fn prefix_may_match(&self, key: &[u8]) -> bool {
+        match (self.prefix_filter.as_ref(), self.options.filter_policy.as_ref(), self.options.prefix_extractor.as_ref()) {
+            (Some(filter), Some(policy), Some(transform)) if transform.in_domain(key) => {
+                policy.key_may_match(&transform.transform(key), filter)
+            }
+            _ => true,
+        }
+    }
+
+    /// Convert an index iterator value (i.e., an encoded BlockHandle) into the
+    /// corresponding block, going through the block cache when there is one. A block
+    /// found in or added to the cache stays pinned there until the result is dropped.
+    fn block_reader(&self, options: &ReadOptions, index_value: &[u8]) -> Result<PinnedBlock> {
+        let (handle, _) = BlockHandle::decode_from(index_value)?;
+        // We intentionally allow extra stuff in index_value so that we can add more
+        // features in the future.
+        self.cached_block(options, &handle)
+    }
+
+    /// Read the block at `handle` through the block cache, if there is one.
+    fn cached_block(&self, options: &ReadOptions, handle: &BlockHandle) -> Result<PinnedBlock> {
+        match self.options.block_cache.as_ref() {
+            Some(block_cache) => {
+                let mut cache_key: CacheKey = [0; 16];
+                encode_fixed_64(&mut cache_key[..8], self.cache_id);
+                encode_fixed_64(&mut cache_key[8..], handle.offset());
+                if let Some(cache_handle) = block_cache.lookup(&cache_key) {
+                    return Ok(PinnedBlock {
+                        block: block_cache.value(&cache_handle),
+                        pin: Some((block_cache.clone(), cache_handle)),
+                    });
+                }
+                let block = Arc::new(Block::new(self.read_block(options, handle)?));
+                let pin = match options.fill_cache {
+                    true => block_cache
+                        .insert(&cache_key, block.clone(), block.size())
+                        .map(|cache_handle| (block_cache.clone(), cache_handle)),
+                    false => None,
+                };
+                Ok(PinnedBlock { block, pin })
+            }
+            None => Ok(PinnedBlock {
+                block: Arc::new(Block::new(self.read_block(options, handle)?)),
+                pin: None,
+            }),
+        }
+    }
+
+    /// Read the block at `handle`, always verifying its checksum if the table was opened
+    /// with `paranoid_checks`. Corruption errors name the file.
+    fn read_block(&self, options: &ReadOptions, handle: &BlockHandle) -> Result<Vec<u8>> {
+        let result = match self.options.paranoid_checks && !options.verify_checksums {
+            true => {
+                let options = ReadOptions {
+                    verify_checksums: true,
+                    ..options.clone()
+                };
+                read_block_with_dict(self.file.as_ref(), &options, self.checksum_type, handle, &self.compression_dict)
+            }
+            false => read_block_with_dict(self.file.as_ref(), options, self.checksum_type, handle, &self.compression_dict),
+        };
+        result.map_err(|e| with_file_detail(e, &self.file_name))
+    }
+
+    /// Set `results[i]` to whether `keys[i]` may be in the data block starting at
+    /// `block_offset`, according to the per-block filter or the filter partition
+    /// covering the block. The partition is read through the block cache unless it is
+    /// pinned.
+    fn block_keys_may_match(&self, options: &ReadOptions, block_offset: u64, keys: &[&[u8]], results: &mut [bool]) {
+        if let Some(filter) = self.filter.as_ref() {
+            return filter.keys_may_match(block_offset, keys, results);
+        }
+        if let (Some(partitions), Some(policy)) = (self.filter_partitions.as_ref(), self.options.filter_policy.as_ref()) {
+            let handle = partitions.find(block_offset);
+            if let Some(partition) = handle.and_then(|handle| self.pinned_filter_partitions.get(&handle.offset())) {
+                return policy.keys_may_match(keys, partition, results);
+            }
+            match handle.map(|handle| self.cached_block(options, &handle)) {
+                Some(Ok(partition)) => policy.keys_may_match(keys, partition.block.data(), results),
+                // Errors are treated as potential matches
+                _ => results.fill(true),
+            }
+        }
+    }
+
+    /// Convert an index iterator value (i.e., an encoded BlockHandle) into an iterator
+    /// over the contents of the corresponding block.
+    fn block_iterator(&self, options: &ReadOptions, index_value: &[u8]) -> Box<dyn Iterator> {
+        match self.block_reader(options, index_value) {
+            Ok(block) => Box::new(PinnedBlockIterator {
+                iter: block.block.new_iterator(self.options.comparator.clone()),
+                _block: block,
+            }),
+            Err(e) => new_error_iterator(e),
+        }
+    }
+
+    /// Advise the file that the `options.readahead_size` bytes from the block of the
+    /// index value on will be read soon, unless the block lies within `range`, which an
+    /// earlier call already advised.
+    fn readahead(&self, options: &ReadOptions, index_value: &[u8], range: &Cell<(u64, u64)>) {
+        if let Ok((handle, _)) = BlockHandle::decode_from(index_value) {
+            let block_end = handle.offset() + handle.size() + BLOCK_TRAILER_SIZE as u64;
+            let (start, end) = range.get();
+            if handle.offset() < start || block_end > end {
+                let end = block_end.max(handle.offset() + options.readahead_size as u64);
+                self.file.prefetch(handle.offset(), (end - handle.offset()) as usize);
+                range.set((handle.offset(), end));
+            }
+        }
+    }
+
+    /// Returns a new iterator over the table contents. The result of `new_iterator()` is
+    /// initially invalid (caller must call one of the seek methods on the iterator
+    /// before using it).
+    ///
+    /// With `options.prefix_same_as_start`, a `seek()` to a target whose prefix the
+    /// prefix filter rules out leaves the iterator invalid without reading any block.
+    pub fn new_iterator(self: &Arc<Self>, options: &ReadOptions) -> Box<dyn Iterator> {
+        let table = self.clone();
+        // Range of the file last advised for readahead
+        let readahead_range = Cell::new((0, 0));
+        let iter = new_two_level_iterator(
+            self.index_block.new_iterator(self.options.comparator.clone()),
+            Box::new(move |options, index_value| {
+                if options.readahead_size > 0 {
+                    table.readahead(options, index_value, &readahead_range);
+                }
+                table.block_iterator(options, index_value)
+            }),
+            options,
+        );
+        if options.prefix_same_as_start && self.prefix_filter.is_some() {
+            return Box::new(PrefixFilterIterator {
+                table: self.clone(),
+                iter,
+                filtered: false,
+            });
+        }
+        iter
+    }
+
+    /// Given a key, return an approximate byte offset in the file where the data for
+    /// that key begins (or would begin if the key were present in the file). The
+    /// returned value is in terms of file bytes, and so includes effects like
+    /// compression of the underlying data. E.g., the approximate offset of the last key
+    /// in the table will be close to the file length.
+    pub fn approximate_offset_of(&self, key: &[u8]) -> u64 {
+        let mut index_iter = self.index_block.new_iterator(self.options.comparator.clone());
+        index_iter.seek(key);
+        if index_iter.valid() {
+            if let Ok((handle, _)) = BlockHandle::decode_from(index_iter.value()) {
+                return handle.offset();
+            }
+            // Strange: we can't decode the block handle in the index block. We'll just
+            // return the offset of the metaindex block, which is close to the whole
+            // file size for this case.
+        }
+        // key is past the last key in the file. Approximate the offset by returning the
+        // offset of the metaindex block (which is right near the end of the file).
+        self.metaindex_offset
+    }
+
+    /// Seek to the first entry at or after `key` and, if the filter does not rule the
+    /// key out, return that entry. Returns `Ok(None)` if no such entry exists.
+    pub fn internal_get(&self, options: &ReadOptions, key: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.internal_get_with(options, key, |k, v, _| (k.to_vec(), v.to_vec()))
+    }
+
+    /// Like `internal_get()`, but the value refers to the data block holding it, which
+    /// stays pinned in the block cache while the value is alive.
+    pub fn internal_get_pinned(&self, options: &ReadOptions, key: &[u8]) -> Result<Option<(Vec<u8>, PinnableSlice)>> {
+        // The block iterator's values point into the block's data, which the pinned
+        // block keeps alive.
+        self.internal_get_with(options, key, |k, v, block| {
+            (k.to_vec(), unsafe { PinnableSlice::pinned(v, Box::new(block)) })
+        })
+    }
+
+    fn internal_get_with<T>(
+        &self,
+        options: &ReadOptions,
+        key: &[u8],
+        found: impl FnOnce(&[u8], &[u8], PinnedBlock) -> T,
+    ) -> Result<Option<T>> {
+        if let Some(filter) = self.filter.as_ref().filter(|f| f.is_full()) {
+            // A full filter rules the key out without a seek in the index block
+            if !filter.key_may_match(0, key) {
+                return Ok(None);
+            }
+        }
+        let mut iiter = self.index_block.new_iterator(self.options.comparator.clone());
+        iiter.seek(key);
+        if iiter.valid() {
+            let handle_value = iiter.value();
+            let full = self.filter.as_ref().is_some_and(|f| f.is_full());
+            if let (false, Ok((handle, _))) = (full, BlockHandle::decode_from(handle_value)) {
+                let mut may_match = [true];
+                self.block_keys_may_match(options, handle.offset(), &[key], &mut may_match);
+                if !may_match[0] {
+                    // Not found
+                    return Ok(None);
+                }
+            }
+            let block = self.block_reader(options, handle_value)?;
+            let mut block_iter = block.block.new_iterator(self.options.comparator.clone());
+            block_iter.seek(key);
+            if block_iter.valid() {
+                return Ok(Some(found(block_iter.key(), block_iter.value(), block)));
+            }
+            block_iter.status()?;
+        }
+        iiter.status()?;
+        Ok(None)
+    }
+
+    /// Batched form of `internal_get()`: `result[i]` is what `internal_get()` returns
+    /// for `keys[i]`. Keys that fall in the same data block are probed against its
+    /// filter together, and the block is read at most once for all of them.
+    ///
+    /// REQUIRES: `keys` is sorted by the table's comparator.
+    pub fn internal_multi_get(&self, options: &ReadOptions, keys: &[&[u8]]) -> Result<Vec<Option<KeyValue>>> {
+        let cmp = self.options.comparator.clone();
+        let mut result = vec![None; keys.len()];
+        let mut iiter = self.index_block.new_iterator(cmp.clone());
+        let mut may_match = Vec::new();
+        let mut i = 0;
+        while i < keys.len() {
+            iiter.seek(keys[i]);
+            if !iiter.valid() {
+                // keys[i..] are all past the last data block.
+                break;
+            }
+
+            // keys[i..j] all fall in the data block pointed to by iiter.
+            let j = i + keys[i..].partition_point(|k| cmp.compare(k, iiter.key()) != std::cmp::Ordering::Greater);
+            let group = &keys[i..j];
+            let handle_value = iiter.value();
+            may_match.clear();
+            may_match.resize(group.len(), true);
+            if let Ok((handle, _)) = BlockHandle::decode_from(handle_value) {
+                self.block_keys_may_match(options, handle.offset(), group, &mut may_match);
+            }
+            if may_match.contains(&true) {
+                let block = self.block_reader(options, handle_value)?;
+                let mut block_iter = block.block.new_iterator(cmp.clone());
+                for (k, key) in group.iter().enumerate().filter(|&(k, _)| may_match[k]) {
+                    block_iter.seek(key);
+                    if block_iter.valid() {
+                        result[i + k] = Some((block_iter.key().to_vec(), block_iter.value().to_vec()));
+                    }
+                    block_iter.status()?;
+                }
+            }
+            i = j;
+        }
+        iiter.status()?;
+        Ok(result)
+    }
+}
+
+/// Attach `fname` to corruption errors, which are otherwise hard to trace to a file.
+fn with_file_detail(e: Error, fname: &str) -> Error {
+    match e.is_corruption() && e.detail().is_none() {
+        true => e.with_detail(fname),
+        false => e,
+    }
+}
+
+/// A data block, pinned in the block cache if it came from there.
+struct PinnedBlock {
+    block: Arc<Block>,
+    pin: Option<(Arc<BlockCache>, CacheHandle)>,
+}
+
+impl Drop for PinnedBlock {
+    fn drop(&mut self) {
+        if let Some((cache, handle)) = self.pin.take() {
+            cache.release(handle);
+        }
+    }
+}
+
+/// Iterator over a data block that keeps the block pinned while it is alive.
+struct PinnedBlockIterator {
+    iter: Box<dyn Iterator>,
+    _block: PinnedBlock,
+}
+
+impl Iterator for PinnedBlockIterator {
+    fn valid(&self) -> bool { self.iter.valid() }
+
+    fn seek_to_first(&mut self) { self.iter.seek_to_first() }
+
+    fn seek_to_last(&mut self) { self.iter.seek_to_last() }
+
+    fn seek(&mut self, target: &[u8]) { self.iter.seek(target) }
+
+    fn seek_for_prev(&mut self, target: &[u8]) { self.iter.seek_for_prev(target) }
+
+    fn next(&mut self) { self.iter.next() }
+
+    fn prev(&mut self) { self.iter.prev() }
+
+    fn key(&self) -> &[u8] { self.iter.key() }
+
+    fn value(&self) -> &[u8] { self.iter.value() }
+
+    fn status(&self) -> Result<()> { self.iter.status() }
+}
+
+/// Table iterator that consults the prefix filter before seeking.
+struct PrefixFilterIterator {
+    table: Arc<Table>,
+    iter: Box<dyn Iterator>,
+    // The last seek target was ruled out by the prefix filter.
+    filtered: bool,
+}
+
+impl Iterator for PrefixFilterIterator {
+    fn valid(&self) -> bool { !self.filtered && self.iter.valid() }
+
+    fn seek_to_first(&mut self) {
+        self.filtered = false;
+        self.iter.seek_to_first();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.filtered = false;
+        self.iter.seek_to_last();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.filtered = !self.table.prefix_may_match(target);
+        if !self.filtered {
+            self.iter.seek(target);
+        }
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        self.filtered = !self.table.prefix_may_match(target);
+        if !self.filtered {
+            self.iter.seek_for_prev(target);
+        }
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        self.iter.next();
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid());
+        self.iter.prev();
+    }
+
+    fn key(&self) -> &[u8] {
+        assert!(self.valid());
+        self.iter.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        assert!(self.valid());
+        self.iter.value()
+    }
+
+    fn status(&self) -> Result<()> { self.iter.status() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        leveldb::{
+            env::Env,
+            options::{new_block_cache, new_clock_block_cache, CompressionType},
+        },
+        table::table_builder::TableBuilder,
+        util::{
+            bloom::new_bloom_filter_policy, env_posix::PosixEnv, slice_transform::new_fixed_prefix_transform,
+            testutil::tmp_dir,
+        },
+    };
+
+    fn build_table(options: &Options, fname: &str, n: usize) -> Arc<Table> {
+        let env = PosixEnv;
+        let file = env.new_writable_file(fname).expect("create");
+        let mut builder = TableBuilder::new(options, file);
+        for i in 0..n {
+            builder.add(format!("key{:06}", i).as_bytes(), format!("value{}", i).as_bytes());
+        }
+        builder.finish().expect("finish");
+        let size = builder.file_size();
+        builder.into_file().close().expect("close");
+        assert_eq!(env.get_file_size(fname).expect("size"), size);
+        Table::open(options, fname, env.new_random_access_file(fname).expect("open"), size).expect("table")
+    }
+
+    #[test]
+    fn empty_table() {
+        let dir = tmp_dir("table_empty");
+        let options = Options::default();
+        let table = build_table(&options, &format!("{}/000001.ldb", dir), 0);
+        let mut iter = table.new_iterator(&ReadOptions::default());
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+        assert!(table.internal_get(&ReadOptions::default(), b"key").unwrap().is_none());
+    }
+
+    #[test]
+    fn iterate_and_get() {
+        let dir = tmp_dir("table_iterate");
+        for (i, cache) in [new_block_cache(4096), new_clock_block_cache(4096, 256)].into_iter().enumerate() {
+            let options = Options {
+                block_size: 256,
+                block_cache: Some(cache),
+                filter_policy: Some(new_bloom_filter_policy(10)),
+                ..Options::default()
+            };
+            check_iterate_and_get(&options, &format!("{}/00000{}.ldb", dir, i + 1));
+        }
+    }
+
+    fn check_iterate_and_get(options: &Options, fname: &str) {
+        let n = 1000;
+        let table = build_table(options, fname, n);
+
+        let mut iter = table.new_iterator(&ReadOptions::default());
+        iter.seek_to_first();
+        for i in 0..n {
+            assert!(iter.valid());
+            assert_eq!(iter.key(), format!("key{:06}", i).as_bytes());
+            assert_eq!(iter.value(), format!("value{}", i).as_bytes());
+            iter.next();
+        }
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+
+        iter.seek(b"key000500x");
+        assert_eq!(iter.key(), b"key000501");
+        // The block under the iterator stays pinned in the cache until it moves on.
+        let cache = options.block_cache.as_ref().unwrap();
+        assert!(cache.pinned_usage() > 0);
+        drop(iter);
+        assert_eq!(cache.pinned_usage(), 0);
+
+        let (k, v) = table
+            .internal_get(&ReadOptions::default(), b"key000777")
+            .unwrap()
+            .expect("found");
+        assert_eq!(k, b"key000777");
+        assert_eq!(v, b"value777");
+        assert!(table.internal_get(&ReadOptions::default(), b"zzz").unwrap().is_none());
+        assert!(cache.usage() > 0);
+        table.evict_blocks();
+        assert_eq!(cache.usage(), 0);
+    }
+
+    #[test]
+    fn multi_get() {
+        let dir = tmp_dir("table_multi_get");
+        for full_filter in [false, true] {
+            let options = Options {
+                block_size: 256,
+                filter_policy: Some(new_bloom_filter_policy(10)),
+                full_filter,
+                ..Options::default()
+            };
+            let table = build_table(&options, &format!("{}/00000{}.ldb", dir, full_filter as u8 + 1), 1000);
+            assert_eq!(table.filter.as_ref().unwrap().is_full(), full_filter);
+            check_multi_get(&table);
+        }
+        let options = Options {
+            block_size: 256,
+            filter_policy: Some(new_bloom_filter_policy(10)),
+            partition_filters: true,
+            metadata_block_size: 1024,
+            ..Options::default()
+        };
+        check_multi_get(&build_table(&options, &format!("{}/000003.ldb", dir), 1000));
+    }
+
+    fn check_multi_get(table: &Table) {
+        let mut keys: Vec<String> = (0..1000).step_by(7).map(|i| format!("key{:06}", i)).collect();
+        keys.extend((0..1000).step_by(13).map(|i| format!("key{:06}x", i)));
+        keys.extend(["a".to_string(), "key000500".to_string(), "zzz".to_string()]);
+        keys.sort();
+        let refs: Vec<&[u8]> = keys.iter().map(|k| k.as_bytes()).collect();
+        let results = table.internal_multi_get(&ReadOptions::default(), &refs).unwrap();
+        assert_eq!(results.len(), refs.len());
+        for (key, result) in refs.iter().zip(results) {
+            assert_eq!(result, table.internal_get(&ReadOptions::default(), key).unwrap());
+        }
+        assert!(table.internal_multi_get(&ReadOptions::default(), &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn partitioned_filter() {
+        let dir = tmp_dir("table_partitioned_filter");
+        let cache = new_block_cache(1 << 20);
+        let options = Options {
+            block_size: 256,
+            block_cache: Some(cache.clone()),
+            filter_policy: Some(new_bloom_filter_policy(10)),
+            partition_filters: true,
+            metadata_block_size: 1024,
+            ..Options::default()
+        };
+        let table = build_table(&options, &format!("{}/000001.ldb", dir), 1000);
+        assert!(table.filter.is_none());
+        // 9 bytes of key per entry, so about 114 keys per partition
+        let partitions = table.filter_partitions.as_ref().unwrap().num_partitions();
+        assert!((8..=9).contains(&partitions), "{}", partitions);
+        assert_eq!(cache.usage(), 0);
+
+        let (_, v) = table.internal_get(&ReadOptions::default(), b"key000777").unwrap().unwrap();
+        assert_eq!(v, b"value777");
+        // One partition and one data block were cached
+        let usage = cache.usage();
+        assert!(usage > 0 && usage < 1024, "{}", usage);
+
+        // Keys missing from the table are ruled out by their partition, except for
+        // false positives.
+        let missing: Vec<String> = (0..1000).map(|i| format!("key{:06}x", i)).collect();
+        let not_ruled_out = missing
+            .iter()
+            .filter(|key| table.internal_get(&ReadOptions::default(), key.as_bytes()).unwrap().is_some())
+            .count();
+        assert!(not_ruled_out < 50, "{}", not_ruled_out);
+
+        table.evict_blocks();
+        assert_eq!(cache.usage(), 0);
+
+        // With max_open_files == -1 the partitions are read along with the table.
+        let pinned = Options {
+            max_open_files: -1,
+            ..options
+        };
+        let table = build_table(&pinned, &format!("{}/000002.ldb", dir), 1000);
+        assert_eq!(table.pinned_filter_partitions.len(), partitions);
+        let not_ruled_out = missing
+            .iter()
+            .filter(|key| table.internal_get(&ReadOptions::default(), key.as_bytes()).unwrap().is_some())
+            .count();
+        assert!(not_ruled_out < 50, "{}", not_ruled_out);
+        let (_, v) = table.internal_get(&ReadOptions::default(), b"key000777").unwrap().unwrap();
+        assert_eq!(v, b"value777");
+    }
+
+    // Records the ranges it is asked to prefetch.
+    struct PrefetchRecorder {
+        file: Box<dyn RandomAccessFile>,
+        prefetched: Arc<std::sync::Mutex<Vec<(u64, usize)>>>,
+    }
+
+    impl RandomAccessFile for PrefetchRecorder {
+        fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> { self.file.read(offset, buf) }
+
+        fn prefetch(&self, offset: u64, len: usize) { self.prefetched.lock().unwrap().push((offset, len)); }
+    }
+
+    #[test]
+    fn readahead() {
+        let dir = tmp_dir("table_readahead");
+        let fname = format!("{}/000001.ldb", dir);
+        let options = Options {
+            block_size: 256,
+            ..Options::default()
+        };
+        build_table(&options, &fname, 1000);
+        let size = PosixEnv.get_file_size(&fname).unwrap();
+        let prefetched = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let file = PrefetchRecorder {
+            file: PosixEnv.new_random_access_file(&fname).unwrap(),
+            prefetched: prefetched.clone(),
+        };
+        let table = Table::open(&options, &fname, Box::new(file), size).unwrap();
+
+        let count = |options: &ReadOptions| {
+            let mut iter = table.new_iterator(options);
+            iter.seek_to_first();
+            let mut n = 0;
+            while iter.valid() {
+                n += 1;
+                iter.next();
+            }
+            n
+        };
+        assert_eq!(count(&ReadOptions::default()), 1000);
+        assert!(prefetched.lock().unwrap().is_empty());
+
+        // A scan advises each 4KB of the file once, in order.
+        let options = ReadOptions {
+            readahead_size: 4096,
+            ..ReadOptions::default()
+        };
+        assert_eq!(count(&options), 1000);
+        let prefetched = prefetched.lock().unwrap().clone();
+        assert_eq!(prefetched[0], (0, 4096));
+        assert!(prefetched.windows(2).all(|w| w[1].0 >= w[0].0 + w[0].1 as u64 - 256));
+        let expected = size / 4096;
+        assert!((expected..=expected + 2).contains(&(prefetched.len() as u64)), "{}", prefetched.len());
+    }
+
+    #[test]
+    fn approximate_offset_of() {
+        let dir = tmp_dir("table_approximate_offset");
+        let options = Options {
+            block_size: 1024,
+            ..Options::default()
+        };
+        let fname = format!("{}/000001.ldb", dir);
+        let table = build_table(&options, &fname, 10000);
+        let size = PosixEnv.get_file_size(&fname).unwrap();
+
+        assert_eq!(table.approximate_offset_of(b"a"), 0);
+        assert_eq!(table.approximate_offset_of(b"key000000"), 0);
+        let end = table.approximate_offset_of(b"zzz");
+        assert!(end > size / 2 && end < size, "{} {}", end, size);
+
+        // Entries are all about the same size, so offsets grow linearly with the key.
+        let mut last = 0;
+        for i in (0..10000).step_by(1000) {
+            let offset = table.approximate_offset_of(format!("key{:06}", i).as_bytes());
+            let expected = end * i / 10000;
+            assert!(offset >= last);
+            assert!(offset + 2048 >= expected && offset <= expected + 2048, "{} {}", i, offset);
+            last = offset;
+        }
+    }
+
+    #[test]
+    fn zstd_compression() {
+        let dir = tmp_dir("table_zstd");
+        let plain = build_table(&Options::default(), &format!("{}/000001.ldb", dir), 1000);
+        let options = Options {
+            compression: CompressionType::Zstd { level: 3 },
+            ..Options::default()
+        };
+        let compressed = build_table(&options, &format!("{}/000002.ldb", dir), 1000);
+        let size = |name: &str| PosixEnv.get_file_size(&format!("{}/{}", dir, name)).unwrap();
+        assert!(size("000002.ldb") < size("000001.ldb") / 2);
+
+        for table in [plain, compressed] {
+            let mut iter = table.new_iterator(&ReadOptions::default());
+            iter.seek_to_first();
+            let mut n = 0;
+            while iter.valid() {
+                assert_eq!(iter.key(), format!("key{:06}", n).as_bytes());
+                n += 1;
+                iter.next();
+            }
+            assert_eq!(n, 1000);
+            let (_, v) = table.internal_get(&ReadOptions::default(), b"key000123").unwrap().unwrap();
+            assert_eq!(v, b"value123");
+        }
+    }
+
+    #[test]
+    fn compression_dictionary() {
+        let dir = tmp_dir("table_compression_dict");
+        let build = |options: &Options, fname: &str| {
+            let file = PosixEnv.new_writable_file(fname).unwrap();
+            let mut builder = TableBuilder::new(options, file);
+            for i in 0..2000 {
+                let value = format!(r#"{{"id": {}, "name": "user{}", "active": true, "tags": ["a", "b"]}}"#, i, i);
+                builder.add(format!("key{:06}", i).as_bytes(), value.as_bytes());
+            }
+            builder.finish().unwrap();
+            builder.into_file().close().unwrap();
+            crate::leveldb::env::read_file_to_string(&PosixEnv, fname).unwrap()
+        };
+        let mut options = Options {
+            block_size: 256,
+            compression: CompressionType::Zstd { level: 3 },
+            ..Options::default()
+        };
+        let plain = build(&options, &format!("{}/000001.ldb", dir));
+        options.compression_opts.max_dict_bytes = 4096;
+        let fname = format!("{}/000002.ldb", dir);
+        let data = build(&options, &fname);
+        assert!(data.len() < plain.len() * 3 / 4, "{} vs {}", data.len(), plain.len());
+
+        let summary = crate::table::verify::verify_table_bytes(&data).unwrap();
+        assert_eq!(summary.num_entries, 2000);
+        assert!(summary.meta_blocks.iter().any(|name| name == "compressiondict"));
+
+        let size = data.len() as u64;
+        let table = Table::open(&options, &fname, PosixEnv.new_random_access_file(&fname).unwrap(), size).unwrap();
+        let mut iter = table.new_iterator(&ReadOptions::default());
+        iter.seek_to_first();
+        let mut n = 0;
+        while iter.valid() {
+            assert_eq!(iter.key(), format!("key{:06}", n).as_bytes());
+            n += 1;
+            iter.next();
+        }
+        assert!(iter.status().is_ok());
+        assert_eq!(n, 2000);
+        let (_, v) = table.internal_get(&ReadOptions::default(), b"key001234").unwrap().unwrap();
+        assert!(v.starts_with(br#"{"id": 1234,"#));
+
+        // Older format versions cannot hold a dictionary
+        options.format_version = 2;
+        let data = build(&options, &format!("{}/000003.ldb", dir));
+        assert_eq!(data.len(), plain.len());
+    }
+
+    #[test]
+    fn xxhash_checksum() {
+        let dir = tmp_dir("table_xxhash");
+        let fname = format!("{}/000001.ldb", dir);
+        let options = Options {
+            block_size: 256,
+            checksum: ChecksumType::XxHash64,
+            ..Options::default()
+        };
+        build_table(&options, &fname, 1000);
+
+        // The checksum type comes from the footer, not from the options of the reader.
+        let mut data = crate::leveldb::env::read_file_to_string(&PosixEnv, &fname).unwrap();
+        let summary = crate::table::verify::verify_table_bytes(&data).unwrap();
+        assert_eq!(summary.checksum_type, ChecksumType::XxHash64);
+        let paranoid = Options {
+            paranoid_checks: true,
+            ..Options::default()
+        };
+        let size = data.len() as u64;
+        let table = Table::open(&paranoid, &fname, PosixEnv.new_random_access_file(&fname).unwrap(), size).unwrap();
+        let (_, v) = table.internal_get(&ReadOptions::default(), b"key000123").unwrap().unwrap();
+        assert_eq!(v, b"value123");
+
+        data[10] ^= 0x01;
+        assert!(crate::table::verify::verify_table_bytes(&data).unwrap_err().is_corruption());
+    }
+
+    #[test]
+    fn format_version() {
+        let dir = tmp_dir("table_format_version");
+        for format_version in 0..=crate::table::format::LATEST_FORMAT_VERSION {
+            let fname = format!("{}/00000{}.ldb", dir, format_version + 1);
+            let options = Options {
+                block_size: 256,
+                format_version,
+                ..Options::default()
+            };
+            let table = build_table(&options, &fname, 1000);
+            let data = crate::leveldb::env::read_file_to_string(&PosixEnv, &fname).unwrap();
+            let summary = crate::table::verify::verify_table_bytes(&data).unwrap();
+            // Versions 0 and 1 share the leveldb footer
+            assert_eq!(summary.format_version, format_version.max(1));
+
+            // Index keys are shortened from version 1 on
+            let mut index_iter = table.index_block.new_iterator(options.comparator.clone());
+            index_iter.seek_to_last();
+            let expected: &[u8] = if format_version == 0 { b"key000999" } else { b"l" };
+            assert_eq!(index_iter.key(), expected);
+
+            let (_, v) = table.internal_get(&ReadOptions::default(), b"key000123").unwrap().unwrap();
+            assert_eq!(v, b"value123");
+        }
+
+        // Only a footer with a version can record a checksum type other than crc32c
+        let options = Options {
+            checksum: ChecksumType::XxHash64,
+            format_version: 1,
+            ..Options::default()
+        };
+        let file = PosixEnv.new_writable_file(&format!("{}/000009.ldb", dir)).unwrap();
+        let mut builder = TableBuilder::new(&options, file);
+        builder.add(b"key", b"value");
+        assert_eq!(builder.finish().unwrap_err().ty(), crate::leveldb::result::ErrorType::InvalidArgument);
+    }
+
+    #[test]
+    fn verify_checksums() {
+        let dir = tmp_dir("table_verify_checksums");
+        let fname = format!("{}/000001.ldb", dir);
+        let options = Options {
+            block_size: 256,
+            ..Options::default()
+        };
+        build_table(&options, &fname, 1000);
+
+        // Damage a value in the first data block
+        let mut data = crate::leveldb::env::read_file_to_string(&PosixEnv, &fname).unwrap();
+        data[20] ^= 0x01;
+        crate::leveldb::env::write_string_to_file(&PosixEnv, &data, &fname).unwrap();
+        let size = data.len() as u64;
+        let table = Table::open(&options, &fname, PosixEnv.new_random_access_file(&fname).unwrap(), size).unwrap();
+
+        // Without verification the damaged contents are trusted
+        assert!(table.internal_get(&ReadOptions::default(), b"key000000").is_ok());
+        let verify = ReadOptions {
+            verify_checksums: true,
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+        let e = table.internal_get(&verify, b"key000000").unwrap_err();
+        assert!(e.is_corruption());
+        assert_eq!(e.msg(), "reading block at offset 0: block checksum mismatch");
+        assert_eq!(e.detail(), Some(fname.as_str()));
+        // Iterators skip the block and report the error in their status
+        let mut iter = table.new_iterator(&verify);
+        iter.seek_to_first();
+        assert_eq!(iter.status().unwrap_err().detail(), Some(fname.as_str()));
+
+        // Other blocks are still readable
+        let (_, v) = table.internal_get(&verify, b"key000777").unwrap().unwrap();
+        assert_eq!(v, b"value777");
+    }
+
+    #[test]
+    fn rejects_short_file() {
+        let dir = tmp_dir("table_short");
+        let fname = format!("{}/000001.ldb", dir);
+        let env = PosixEnv;
+        crate::leveldb::env::write_string_to_file(&env, b"tiny", &fname).unwrap();
+        let file = env.new_random_access_file(&fname).unwrap();
+        let e = Table::open(&Options::default(), &fname, file, 4).err().unwrap();
+        assert!(e.is_corruption());
+        assert_eq!(e.detail(), Some(fname.as_str()));
+    }
+
+    #[test]
+    fn prefix_filter() {
+        let dir = tmp_dir("table_prefix_filter");
+        let options = Options {
+            block_size: 256,
+            filter_policy: Some(new_bloom_filter_policy(10)),
+            prefix_extractor: Some(new_fixed_prefix_transform(7)),
+            ..Options::default()
+        };
+        // Prefixes "key0000" through "key0009"
+        let table = build_table(&options, &format!("{}/000001.ldb", dir), 1000);
+        assert!(table.prefix_may_match(b"key0005"));
+        assert!(table.prefix_may_match(b"key000999"));
+        assert!(!table.prefix_may_match(b"key0100"));
+        // Keys without a prefix are never ruled out
+        assert!(table.prefix_may_match(b"key"));
+
+        let read_options = ReadOptions {
+            prefix_same_as_start: true,
+            ..ReadOptions::default()
+        };
+        let mut iter = table.new_iterator(&read_options);
+        iter.seek(b"key0100");
+        assert!(!iter.valid());
+        iter.seek(b"key000500");
+        assert_eq!(iter.key(), b"key000500");
+        iter.seek_to_first();
+        assert_eq!(iter.key(), b"key000000");
+
+        // Without the read option the iterator still lands after the missing prefix
+        let mut iter = table.new_iterator(&ReadOptions::default());
+        iter.seek(b"key00099");
+        assert_eq!(iter.key(), b"key000990");
+    }
+}
@@ -0,0 +1,438 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// TableBuilder provides the interface used to build a Table (an immutable and sorted
+// map from keys to values).
+//
+// Multiple threads can invoke const methods on a TableBuilder without external
+// synchronization, but if any of the threads may call a non-const method, all threads
+// accessing the same TableBuilder must use external synchronization.
+
+use std::cmp::Ordering;
+
+use crate::{
+    leveldb::{
+        env::WritableFile,
+        options::{CompressionType, Options},
+        result::Result,
+    },
+    table::{
+        block_builder::BlockBuilder,
+        filter_block::{encode_partition_index, FilterBlockBuilder},
+        format::{
+            block_checksum, check_format_version, BlockHandle, Footer, BLOCK_TRAILER_SIZE, COMPRESSION_DICT_KEY,
+        },
+    },
+    util::{coding::encode_fixed_32, lz4, zstd},
+};
+
+pub struct TableBuilder {
+    options: Options,
+    index_block_options: Options,
+    file: Box<dyn WritableFile>,
+    offset: u64,
+    status: Result<()>,
+    data_block: BlockBuilder,
+    index_block: BlockBuilder,
+    last_key: Vec<u8>,
+    num_entries: u64,
+    // Either finish() or abandon() has been called.
+    closed: bool,
+    filter_block: Option<FilterBlockBuilder>,
+    // Distinct key prefixes seen so far, if the table gets a prefix filter.
+    prefixes: Option<Vec<Vec<u8>>>,
+    // Contents of the first data blocks, while they are sampled for a compression
+    // dictionary.
+    dict_sample: Option<Vec<u8>>,
+    // Dictionary the remaining data blocks are compressed against, once the sample is
+    // complete. Empty until then.
+    compression_dict: Vec<u8>,
+
+    // We do not emit the index entry for a block until we have seen the first key for
+    // the next data block. This allows us to use shorter keys in the index block. For
+    // example, consider a block boundary between the keys "the quick brown fox" and
+    // "the who". We can use "the r" as the key for the index block entry since it is >=
+    // all entries in the first block and < all entries in subsequent blocks.
+    //
+    // Invariant: pending_index_entry is true only if data_block is empty.
+    pending_index_entry: bool,
+    // Handle to add to index block
+    pending_handle: BlockHandle,
+}
+
+impl TableBuilder {
+    /// Create a builder that will store the contents of the table it is building in
+    /// `file`.
+    pub fn new(options: &Options, mut file: Box<dyn WritableFile>) -> Self {
+        file.set_preallocation_block_size(options.wal_preallocate_block_size);
+        file.set_bytes_per_sync(options.bytes_per_sync);
+        let mut index_block_options = options.clone();
+        index_block_options.block_restart_interval = 1;
+        let mut filter_block = options.filter_policy.clone().map(|policy| {
+            if options.partition_filters {
+                FilterBlockBuilder::new_partitioned(policy, options.metadata_block_size)
+            } else if options.full_filter {
+                FilterBlockBuilder::new_full(policy)
+            } else {
+                FilterBlockBuilder::new(policy)
+            }
+        });
+        if let Some(fb) = filter_block.as_mut() {
+            fb.start_block(0);
+        }
+        let prefixes = (options.filter_policy.is_some() && options.prefix_extractor.is_some()).then(Vec::new);
+        let dict_sample = (matches!(options.compression, CompressionType::Zstd { .. })
+            && options.compression_opts.max_dict_bytes > 0
+            && options.format_version >= 3)
+            .then(Vec::new);
+        Self {
+            data_block: BlockBuilder::new(options.block_restart_interval, options.comparator.clone()),
+            index_block: BlockBuilder::new(1, options.comparator.clone()),
+            options: options.clone(),
+            index_block_options,
+            file,
+            offset: 0,
+            // Nothing is written if the table cannot be written in the requested format
+            status: check_format_version(options.format_version, options.checksum),
+            last_key: Vec::new(),
+            num_entries: 0,
+            closed: false,
+            filter_block,
+            prefixes,
+            dict_sample,
+            compression_dict: Vec::new(),
+            pending_index_entry: false,
+            pending_handle: BlockHandle::default(),
+        }
+    }
+
+    /// Add key,value to the table being constructed.
+    ///
+    /// REQUIRES: key is after any previously added key according to comparator.
+    /// REQUIRES: `finish()`, `abandon()` have not been called
+    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        assert!(!self.closed);
+        if self.status.is_err() {
+            return;
+        }
+        if self.num_entries > 0 {
+            assert_eq!(
+                self.options.comparator.compare(key, &self.last_key),
+                Ordering::Greater
+            );
+        }
+
+        if self.pending_index_entry {
+            assert!(self.data_block.is_empty());
+            if self.options.format_version >= 1 {
+                self.options
+                    .comparator
+                    .find_shortest_separator(&mut self.last_key, key);
+            }
+            let mut handle_encoding = Vec::new();
+            self.pending_handle.encode_to(&mut handle_encoding);
+            self.index_block.add(&self.last_key, &handle_encoding);
+            self.pending_index_entry = false;
+        }
+
+        if let Some(fb) = self.filter_block.as_mut() {
+            fb.add_key(key);
+        }
+        if let (Some(prefixes), Some(transform)) = (self.prefixes.as_mut(), self.options.prefix_extractor.as_ref()) {
+            if transform.in_domain(key) {
+                // Keys with the same prefix are adjacent, so comparing with the last
+                // prefix is enough to keep the list free of duplicates.
+                let prefix = transform.transform(key);
+                if prefixes.last().map(|p| &p[..]) != Some(&prefix[..]) {
+                    prefixes.push(prefix.into_owned());
+                }
+            }
+        }
+
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.num_entries += 1;
+        self.data_block.add(key, value);
+
+        let estimated_block_size = self.data_block.current_size_estimate();
+        if estimated_block_size >= self.options.block_size {
+            self.flush();
+        }
+    }
+
+    /// Advanced operation: flush any buffered key/value pairs to file. Can be used to
+    /// ensure that two adjacent entries never live in the same data block. Most clients
+    /// should not need to use this method.
+    ///
+    /// REQUIRES: `finish()`, `abandon()` have not been called
+    pub fn flush(&mut self) {
+        assert!(!self.closed);
+        if self.status.is_err() || self.data_block.is_empty() {
+            return;
+        }
+        assert!(!self.pending_index_entry);
+        let contents = self.data_block.finish().to_vec();
+        self.data_block.reset();
+        match self.write_data_block(&contents) {
+            Ok(handle) => {
+                self.pending_handle = handle;
+                self.pending_index_entry = true;
+                self.status = self.file.flush();
+            }
+            Err(e) => self.status = Err(e),
+        }
+        self.sample_for_dict(&contents);
+        if let Some(fb) = self.filter_block.as_mut() {
+            fb.start_block(self.offset);
+        }
+    }
+
+    fn write_block(&mut self, raw: &[u8]) -> Result<BlockHandle> {
+        // File format contains a sequence of blocks where each block has:
+        //    block_data: uint8[n]
+        //    type: uint8
+        //    crc: uint32
+        let compression = self.options.compression;
+        match compression {
+            CompressionType::NoCompression => self.write_raw_block(raw, compression),
+            CompressionType::Zstd { level } => self.write_compressed_block(raw, zstd::compress(raw, level)),
+            CompressionType::Lz4 if raw.len() <= lz4::MAX_INPUT_SIZE => {
+                self.write_compressed_block(raw, lz4::compress(raw))
+            }
+            // Too large for an LZ4 block
+            CompressionType::Lz4 => self.write_raw_block(raw, CompressionType::NoCompression),
+        }
+    }
+
+    fn write_data_block(&mut self, raw: &[u8]) -> Result<BlockHandle> {
+        match self.options.compression {
+            CompressionType::Zstd { level } if !self.compression_dict.is_empty() => {
+                let compressed = zstd::compress_with_dict(raw, level, &self.compression_dict);
+                self.write_compressed_block(raw, compressed)
+            }
+            _ => self.write_block(raw),
+        }
+    }
+
+    // Add the contents of a data block to the dictionary sample. Blocks written before
+    // the sample is complete are compressed without a dictionary; the dictionary id in
+    // each zstd frame tells readers which blocks need it.
+    fn sample_for_dict(&mut self, raw: &[u8]) {
+        let Some(sample) = self.dict_sample.as_mut() else {
+            return;
+        };
+        let max_dict_bytes = self.options.compression_opts.max_dict_bytes;
+        let n = raw.len().min(max_dict_bytes - sample.len());
+        sample.extend_from_slice(&raw[..n]);
+        if sample.len() == max_dict_bytes {
+            self.compression_dict = self.dict_sample.take().unwrap_or_default();
+        }
+    }
+
+    fn write_compressed_block(&mut self, raw: &[u8], compressed: Vec<u8>) -> Result<BlockHandle> {
+        if compressed.len() < raw.len() - (raw.len() / 8) {
+            self.write_raw_block(&compressed, self.options.compression)
+        } else {
+            // Compressed less than 12.5%, so just store uncompressed form
+            self.write_raw_block(raw, CompressionType::NoCompression)
+        }
+    }
+
+    fn write_raw_block(&mut self, contents: &[u8], ty: CompressionType) -> Result<BlockHandle> {
+        let handle = BlockHandle::new(self.offset, contents.len() as u64);
+        self.file.append(contents)?;
+        let mut trailer = [0u8; BLOCK_TRAILER_SIZE];
+        trailer[0] = ty.type_byte();
+        let checksum = block_checksum(self.options.checksum, contents, trailer[0]);
+        encode_fixed_32(&mut trailer[1..], checksum);
+        self.file.append(&trailer)?;
+        self.offset += (contents.len() + BLOCK_TRAILER_SIZE) as u64;
+        Ok(handle)
+    }
+
+    /// Write the partitions of a partitioned filter, returning the index over them that
+    /// is stored as the filter block.
+    fn write_filter_partitions(&mut self, partitions: Vec<(u64, Vec<u8>)>) -> Result<Vec<u8>> {
+        let mut handles = Vec::with_capacity(partitions.len());
+        for (block_offset, filter) in partitions {
+            handles.push((block_offset, self.write_raw_block(&filter, CompressionType::NoCompression)?));
+        }
+        Ok(encode_partition_index(&handles))
+    }
+
+    /// Return non-ok iff some error has been detected.
+    pub fn status(&self) -> Result<()> { self.status.clone() }
+
+    /// Finish building the table. Stops using the file passed to the constructor after
+    /// this function returns.
+    ///
+    /// REQUIRES: `finish()`, `abandon()` have not been called
+    pub fn finish(&mut self) -> Result<()> {
+        self.flush();
+        assert!(!self.closed);
+        self.closed = true;
+
+        let mut filter_block_handle = BlockHandle::default();
+        let mut prefix_filter_handle = BlockHandle::default();
+        let mut metaindex_block_handle = BlockHandle::default();
+        let mut index_block_handle = BlockHandle::default();
+
+        // Write filter block
+        if self.status.is_ok() {
+            if let Some(mut fb) = self.filter_block.take() {
+                let contents = match fb.is_partitioned() {
+                    true => self.write_filter_partitions(fb.finish_partitions()),
+                    false => Ok(fb.finish().to_vec()),
+                };
+                match contents.and_then(|contents| self.write_raw_block(&contents, CompressionType::NoCompression)) {
+                    Ok(handle) => filter_block_handle = handle,
+                    Err(e) => self.status = Err(e),
+                }
+            }
+        }
+
+        // Write prefix filter block: a single filter over the prefixes of all keys
+        if self.status.is_ok() {
+            if let (Some(prefixes), Some(policy)) = (self.prefixes.as_ref(), self.options.filter_policy.as_ref()) {
+                let keys: Vec<&[u8]> = prefixes.iter().map(|p| &p[..]).collect();
+                let mut contents = Vec::new();
+                policy.create_filter(&keys, &mut contents);
+                match self.write_raw_block(&contents, CompressionType::NoCompression) {
+                    Ok(handle) => prefix_filter_handle = handle,
+                    Err(e) => self.status = Err(e),
+                }
+            }
+        }
+
+        // Write compression dictionary block
+        let mut compression_dict_handle = BlockHandle::default();
+        if self.status.is_ok() && !self.compression_dict.is_empty() {
+            let dict = std::mem::take(&mut self.compression_dict);
+            match self.write_raw_block(&dict, CompressionType::NoCompression) {
+                Ok(handle) => compression_dict_handle = handle,
+                Err(e) => self.status = Err(e),
+            }
+        }
+
+        // Write metaindex block
+        if self.status.is_ok() {
+            let mut meta_index_block = BlockBuilder::new(
+                self.index_block_options.block_restart_interval,
+                self.index_block_options.comparator.clone(),
+            );
+            if compression_dict_handle.size() > 0 {
+                // Add mapping from "compressiondict" to location of the dictionary, which
+                // sorts before the filter keys
+                let mut handle_encoding = Vec::new();
+                compression_dict_handle.encode_to(&mut handle_encoding);
+                meta_index_block.add(COMPRESSION_DICT_KEY.as_bytes(), &handle_encoding);
+            }
+            if let Some(policy) = self.options.filter_policy.as_ref() {
+                // Add mapping from "filter.Name" (or "fullfilter.Name" or
+                // "partitionedfilter.Name") to location of filter data
+                let kind = if self.options.partition_filters {
+                    "partitionedfilter"
+                } else if self.options.full_filter {
+                    "fullfilter"
+                } else {
+                    "filter"
+                };
+                let key = format!("{}.{}", kind, policy.name());
+                let mut handle_encoding = Vec::new();
+                filter_block_handle.encode_to(&mut handle_encoding);
+                meta_index_block.add(key.as_bytes(), &handle_encoding);
+
+                if let Some(transform) = self.options.prefix_extractor.as_ref() {
+                    // Add mapping from "prefixfilter.Name.Transform" to location of the
+                    // prefix filter
+                    let key = format!("prefixfilter.{}.{}", policy.name(), transform.name());
+                    let mut handle_encoding = Vec::new();
+                    prefix_filter_handle.encode_to(&mut handle_encoding);
+                    meta_index_block.add(key.as_bytes(), &handle_encoding);
+                }
+            }
+
+            // TODO(postrelease): Add stats and other meta blocks
+            let contents = meta_index_block.finish().to_vec();
+            match self.write_block(&contents) {
+                Ok(handle) => metaindex_block_handle = handle,
+                Err(e) => self.status = Err(e),
+            }
+        }
+
+        // Write index block
+        if self.status.is_ok() {
+            if self.pending_index_entry {
+                if self.options.format_version >= 1 {
+                    self.options
+                        .comparator
+                        .find_short_successor(&mut self.last_key);
+                }
+                let mut handle_encoding = Vec::new();
+                self.pending_handle.encode_to(&mut handle_encoding);
+                self.index_block.add(&self.last_key, &handle_encoding);
+                self.pending_index_entry = false;
+            }
+            let contents = self.index_block.finish().to_vec();
+            match self.write_block(&contents) {
+                Ok(handle) => index_block_handle = handle,
+                Err(e) => self.status = Err(e),
+            }
+        }
+
+        // Write footer
+        if self.status.is_ok() {
+            let footer = Footer::new(
+                metaindex_block_handle,
+                index_block_handle,
+                self.options.checksum,
+                self.options.format_version,
+            );
+            let mut footer_encoding = Vec::new();
+            footer.encode_to(&mut footer_encoding);
+            match self.file.append(&footer_encoding) {
+                Ok(()) => self.offset += footer_encoding.len() as u64,
+                Err(e) => self.status = Err(e),
+            }
+        }
+        self.status.clone()
+    }
+
+    /// Indicate that the contents of this builder should be abandoned. Stops using the
+    /// file passed to the constructor after this function returns. If the caller is not
+    /// going to call `finish()`, it must call `abandon()` before destroying this builder.
+    ///
+    /// REQUIRES: `finish()`, `abandon()` have not been called
+    pub fn abandon(&mut self) {
+        assert!(!self.closed);
+        self.closed = true;
+    }
+
+    /// Number of calls to `add()` so far.
+    pub fn num_entries(&self) -> u64 { self.num_entries }
+
+    /// Size of the file generated so far. If invoked after a successful `finish()` call,
+    /// returns the size of the final generated file.
+    pub fn file_size(&self) -> u64 { self.offset }
+
+    /// Give back the file the table was written to, e.g. to sync and close it.
+    pub fn into_file(self) -> Box<dyn WritableFile> { self.file }
+}
@@ -0,0 +1,290 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::leveldb::{
+    iterator::Iterator,
+    options::ReadOptions,
+    result::Result,
+};
+
+/// Converts an index iterator value (e.g. an encoded block handle) into an iterator
+/// over the contents of the corresponding block.
+pub type BlockFunction = Box<dyn Fn(&ReadOptions, &[u8]) -> Box<dyn Iterator>>;
+
+struct TwoLevelIterator {
+    block_function: BlockFunction,
+    options: ReadOptions,
+    status: Result<()>,
+    index_iter: Box<dyn Iterator>,
+    data_iter: Option<Box<dyn Iterator>>,
+    // If data_iter is set, then data_block_handle holds the "index_value" passed to
+    // block_function to create the data_iter.
+    data_block_handle: Vec<u8>,
+}
+
+impl TwoLevelIterator {
+    fn save_error(&mut self, status: Result<()>) {
+        if self.status.is_ok() && status.is_err() {
+            self.status = status;
+        }
+    }
+
+    fn set_data_iterator(&mut self, data_iter: Option<Box<dyn Iterator>>) {
+        if let Some(old) = self.data_iter.take() {
+            let status = old.status();
+            self.save_error(status);
+        }
+        self.data_iter = data_iter;
+    }
+
+    fn init_data_block(&mut self) {
+        if !self.index_iter.valid() {
+            self.set_data_iterator(None);
+            return;
+        }
+        let handle = self.index_iter.value();
+        if self.data_iter.is_some() && handle == &self.data_block_handle[..] {
+            // data_iter is already constructed with this iterator, so no need to change
+            // anything
+            return;
+        }
+        let iter = (self.block_function)(&self.options, handle);
+        self.data_block_handle = handle.to_vec();
+        self.set_data_iterator(Some(iter));
+    }
+
+    fn skip_empty_data_blocks_forward(&mut self) {
+        while self.data_iter.as_ref().is_none_or(|it| !it.valid()) {
+            // Move to next block
+            if !self.index_iter.valid() {
+                self.set_data_iterator(None);
+                return;
+            }
+            self.index_iter.next();
+            self.init_data_block();
+            if let Some(it) = self.data_iter.as_mut() {
+                it.seek_to_first();
+            }
+        }
+    }
+
+    fn skip_empty_data_blocks_backward(&mut self) {
+        while self.data_iter.as_ref().is_none_or(|it| !it.valid()) {
+            // Move to previous block
+            if !self.index_iter.valid() {
+                self.set_data_iterator(None);
+                return;
+            }
+            self.index_iter.prev();
+            self.init_data_block();
+            if let Some(it) = self.data_iter.as_mut() {
+                it.seek_to_last();
+            }
+        }
+    }
+}
+
+impl Iterator for TwoLevelIterator {
+    fn valid(&self) -> bool { self.data_iter.as_ref().is_some_and(|it| it.valid()) }
+
+    fn seek_to_first(&mut self) {
+        self.index_iter.seek_to_first();
+        self.init_data_block();
+        if let Some(it) = self.data_iter.as_mut() {
+            it.seek_to_first();
+        }
+        self.skip_empty_data_blocks_forward();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.index_iter.seek_to_last();
+        self.init_data_block();
+        if let Some(it) = self.data_iter.as_mut() {
+            it.seek_to_last();
+        }
+        self.skip_empty_data_blocks_backward();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.index_iter.seek(target);
+        self.init_data_block();
+        if let Some(it) = self.data_iter.as_mut() {
+            it.seek(target);
+        }
+        self.skip_empty_data_blocks_forward();
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        // The first block whose index key is at or past target holds the last entry
+        // at or before target, unless all of its entries come after target.
+        self.index_iter.seek(target);
+        if !self.index_iter.valid() && self.index_iter.status().is_ok() {
+            self.index_iter.seek_to_last();
+        }
+        self.init_data_block();
+        if let Some(it) = self.data_iter.as_mut() {
+            it.seek_for_prev(target);
+        }
+        self.skip_empty_data_blocks_backward();
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        self.data_iter.as_mut().unwrap().next();
+        self.skip_empty_data_blocks_forward();
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid());
+        self.data_iter.as_mut().unwrap().prev();
+        self.skip_empty_data_blocks_backward();
+    }
+
+    fn key(&self) -> &[u8] {
+        assert!(self.valid());
+        self.data_iter.as_ref().unwrap().key()
+    }
+
+    fn value(&self) -> &[u8] {
+        assert!(self.valid());
+        self.data_iter.as_ref().unwrap().value()
+    }
+
+    fn status(&self) -> Result<()> {
+        // It'd be nice if status() returned a const Status& instead of a Status
+        self.index_iter.status()?;
+        if let Some(it) = self.data_iter.as_ref() {
+            it.status()?;
+        }
+        self.status.clone()
+    }
+}
+
+/// Return a new two level iterator. A two-level iterator contains an index iterator
+/// whose values point to a sequence of blocks where each block is itself a sequence of
+/// key,value pairs. The returned two-level iterator yields the concatenation of all
+/// key/value pairs in the sequence of blocks. Takes ownership of `index_iter`.
+///
+/// Uses a supplied function to convert an index_iter value into an iterator over the
+/// contents of the corresponding block.
+pub fn new_two_level_iterator(index_iter: Box<dyn Iterator>, block_function: BlockFunction, options: &ReadOptions) -> Box<dyn Iterator> {
+    Box::new(TwoLevelIterator {
+        block_function,
+        options: options.clone(),
+        status: Ok(()),
+        index_iter,
+        data_iter: None,
+        data_block_handle: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    /// Iterates over a sorted in-memory map.
+    struct MapIterator {
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+        pos: usize,
+    }
+
+    impl MapIterator {
+        fn boxed(map: &BTreeMap<Vec<u8>, Vec<u8>>) -> Box<dyn Iterator> {
+            Box::new(MapIterator {
+                entries: map.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+                pos: map.len(),
+            })
+        }
+    }
+
+    impl Iterator for MapIterator {
+        fn valid(&self) -> bool { self.pos < self.entries.len() }
+
+        fn seek_to_first(&mut self) { self.pos = 0; }
+
+        fn seek_to_last(&mut self) { self.pos = self.entries.len().checked_sub(1).unwrap_or(self.entries.len()); }
+
+        fn seek(&mut self, target: &[u8]) { self.pos = self.entries.partition_point(|(k, _)| &k[..] < target); }
+
+        fn next(&mut self) { self.pos += 1; }
+
+        fn prev(&mut self) { self.pos = self.pos.checked_sub(1).unwrap_or(self.entries.len()); }
+
+        fn key(&self) -> &[u8] { &self.entries[self.pos].0 }
+
+        fn value(&self) -> &[u8] { &self.entries[self.pos].1 }
+
+        fn status(&self) -> Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn concatenates_blocks() {
+        // Blocks are indexed by their largest key; the index value names the block.
+        let blocks: Vec<BTreeMap<Vec<u8>, Vec<u8>>> = vec![
+            [(b"a".to_vec(), b"1".to_vec()), (b"c".to_vec(), b"2".to_vec())].into(),
+            BTreeMap::new(),
+            [(b"e".to_vec(), b"3".to_vec())].into(),
+        ];
+        let index: BTreeMap<Vec<u8>, Vec<u8>> =
+            [(b"c".to_vec(), vec![0]), (b"d".to_vec(), vec![1]), (b"e".to_vec(), vec![2])].into();
+        let mut iter = new_two_level_iterator(
+            MapIterator::boxed(&index),
+            Box::new(move |_, handle| MapIterator::boxed(&blocks[handle[0] as usize])),
+            &ReadOptions::default(),
+        );
+
+        iter.seek_to_first();
+        let mut keys = Vec::new();
+        while iter.valid() {
+            keys.push(iter.key().to_vec());
+            iter.next();
+        }
+        assert_eq!(keys, [b"a".to_vec(), b"c".to_vec(), b"e".to_vec()]);
+
+        iter.seek(b"b");
+        assert_eq!(iter.key(), b"c");
+        iter.seek(b"d");
+        assert_eq!(iter.key(), b"e");
+        assert_eq!(iter.value(), b"3");
+        iter.seek(b"f");
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+
+        iter.seek_to_last();
+        let mut keys = Vec::new();
+        while iter.valid() {
+            keys.push(iter.key().to_vec());
+            iter.prev();
+        }
+        assert_eq!(keys, [b"e".to_vec(), b"c".to_vec(), b"a".to_vec()]);
+
+        iter.seek_for_prev(b"d");
+        assert_eq!(iter.key(), b"c");
+        iter.seek_for_prev(b"b");
+        assert_eq!(iter.key(), b"a");
+        iter.seek_for_prev(b"z");
+        assert_eq!(iter.key(), b"e");
+        iter.seek_for_prev(b"0");
+        assert!(!iter.valid());
+    }
+}
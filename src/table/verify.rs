@@ -0,0 +1,354 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::{
+    leveldb::{
+        comparator::Comparator,
+        env::RandomAccessFile,
+        options::{ChecksumType, ReadOptions},
+        result::Result,
+    },
+    table::{
+        block::Block,
+        filter_block::FilterPartitionIndex,
+        format::{read_block, read_block_with_dict, BlockHandle, Footer, BLOCK_TRAILER_SIZE, COMPRESSION_DICT_KEY},
+    },
+    util::{coding::decode_fixed_32, comparator::bytewise_comparator},
+};
+
+/// What `verify_table_bytes()` learned about a table while checking it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableSummary {
+    /// Number of key/value entries across all data blocks.
+    pub num_entries: u64,
+    pub num_data_blocks: u64,
+    /// Uncompressed bytes of keys and values.
+    pub raw_key_size: u64,
+    pub raw_value_size: u64,
+    /// Stored (possibly compressed) bytes of the data, index and filter blocks.
+    pub data_size: u64,
+    pub index_size: u64,
+    pub filter_size: u64,
+    /// Location of each data block, in file order.
+    pub data_blocks: Vec<BlockHandle>,
+    /// Checksum type of the blocks, as recorded in the footer.
+    pub checksum_type: ChecksumType,
+    /// Format version of the table, as recorded in the footer.
+    pub format_version: u32,
+    /// Names of the entries of the metaindex block, e.g. "filter.<policy name>",
+    /// "fullfilter.<policy name>", "partitionedfilter.<policy name>" or
+    /// "compressiondict".
+    pub meta_blocks: Vec<String>,
+    /// First and last keys of the table. Both empty if the table has no entries.
+    pub smallest_key: Vec<u8>,
+    pub largest_key: Vec<u8>,
+}
+
+/// A table held in memory, so that blocks can be read with `read_block()` without an
+/// Env.
+struct BytesFile<'a>(&'a [u8]);
+
+impl RandomAccessFile for BytesFile<'_> {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let start = (offset as usize).min(self.0.len());
+        let n = buf.len().min(self.0.len() - start);
+        buf[..n].copy_from_slice(&self.0[start..start + n]);
+        Ok(n)
+    }
+
+    fn mapped(&self) -> Option<&[u8]> { Some(self.0) }
+}
+
+/// Fully parse the sstable held in `data`: the footer, the index and metaindex
+/// blocks, every data block and every filter block, verifying all block checksums.
+/// Returns a `Corruption` error describing the first problem found.
+///
+/// Key order is not checked since it depends on the comparator the table was built
+/// with; see `verify_table_bytes_with()`.
+pub fn verify_table_bytes(data: &[u8]) -> Result<TableSummary> { verify(data, None) }
+
+/// Like `verify_table_bytes()`, but also checks that the keys of the table are in
+/// strictly increasing order under `comparator` and that every index entry sorts at or
+/// after the last key of its data block.
+pub fn verify_table_bytes_with(comparator: &dyn Comparator, data: &[u8]) -> Result<TableSummary> {
+    verify(data, Some(comparator))
+}
+
+fn verify(data: &[u8], comparator: Option<&dyn Comparator>) -> Result<TableSummary> {
+    if data.len() < Footer::ENCODED_LENGTH {
+        return LEVELDB_ERR!(Corruption, "file is too short to be an sstable");
+    }
+    let footer_offset = data.len() - Footer::ENCODED_LENGTH;
+    let footer = Footer::decode_from(&data[footer_offset..])?;
+
+    let file = BytesFile(data);
+    let options = ReadOptions {
+        verify_checksums: true,
+        ..ReadOptions::default()
+    };
+    let mut summary = TableSummary::default();
+
+    // The metaindex and index blocks come last, right before the footer.
+    let metaindex_handle = footer.metaindex_handle();
+    let index_handle = footer.index_handle();
+    check_extent(metaindex_handle, index_handle.offset())?;
+    check_extent(index_handle, footer_offset as u64)?;
+    summary.index_size = index_handle.size();
+    summary.checksum_type = footer.checksum_type();
+    summary.format_version = footer.format_version();
+
+    // Ordering is only checked when asked to, but iterating a block does not depend
+    // on the comparator.
+    let iter_comparator = bytewise_comparator();
+
+    let metaindex = Arc::new(Block::new(read_block(&file, &options, footer.checksum_type(), metaindex_handle)?));
+    let mut meta_iter = metaindex.new_iterator(iter_comparator.clone());
+    let mut compression_dict = Vec::new();
+    meta_iter.seek_to_first();
+    while meta_iter.valid() {
+        let name = String::from_utf8_lossy(meta_iter.key()).into_owned();
+        if name == COMPRESSION_DICT_KEY {
+            // Needed to decompress the data blocks below
+            let (handle, _) = BlockHandle::decode_from(meta_iter.value())?;
+            check_extent(&handle, metaindex_handle.offset())?;
+            compression_dict = read_block(&file, &options, footer.checksum_type(), &handle)?;
+        } else if name.starts_with("filter.") {
+            let (handle, _) = BlockHandle::decode_from(meta_iter.value())?;
+            check_extent(&handle, metaindex_handle.offset())?;
+            check_filter_block(&read_block(&file, &options, footer.checksum_type(), &handle)?)?;
+            summary.filter_size += handle.size();
+        } else if name.starts_with("fullfilter.") {
+            // A single filter in the format of the policy, covered by the block checksum
+            let (handle, _) = BlockHandle::decode_from(meta_iter.value())?;
+            check_extent(&handle, metaindex_handle.offset())?;
+            read_block(&file, &options, footer.checksum_type(), &handle)?;
+            summary.filter_size += handle.size();
+        } else if name.starts_with("partitionedfilter.") {
+            // An index over full filters that are stored as blocks of their own
+            let (handle, _) = BlockHandle::decode_from(meta_iter.value())?;
+            check_extent(&handle, metaindex_handle.offset())?;
+            let index = FilterPartitionIndex::new(read_block(&file, &options, footer.checksum_type(), &handle)?);
+            if !index.is_well_formed() {
+                return LEVELDB_ERR!(Corruption, "bad filter partition index");
+            }
+            for i in 0..index.num_partitions() {
+                let partition = index.partition(i);
+                check_extent(&partition, handle.offset())?;
+                read_block(&file, &options, footer.checksum_type(), &partition)?;
+                summary.filter_size += partition.size();
+            }
+            summary.filter_size += handle.size();
+        }
+        summary.meta_blocks.push(name);
+        meta_iter.next();
+    }
+    meta_iter.status()?;
+
+    let index = Arc::new(Block::new(read_block(&file, &options, footer.checksum_type(), index_handle)?));
+    let mut index_iter = index.new_iterator(iter_comparator.clone());
+    let mut next_offset = 0;
+    index_iter.seek_to_first();
+    while index_iter.valid() {
+        let (handle, _) = BlockHandle::decode_from(index_iter.value())?;
+        // Data blocks are written back to back from the start of the file, and all of
+        // them precede the meta blocks.
+        if handle.offset() != next_offset {
+            return LEVELDB_ERR!(Corruption, "data block is not contiguous with the previous block");
+        }
+        check_extent(&handle, metaindex_handle.offset())?;
+        next_offset = handle.offset() + handle.size() + BLOCK_TRAILER_SIZE as u64;
+        summary.num_data_blocks += 1;
+        summary.data_blocks.push(handle);
+        summary.data_size += handle.size();
+
+        let contents = read_block_with_dict(&file, &options, footer.checksum_type(), &handle, &compression_dict)?;
+        let block = Arc::new(Block::new(contents));
+        let mut iter = block.new_iterator(iter_comparator.clone());
+        iter.seek_to_first();
+        while iter.valid() {
+            if let Some(cmp) = comparator {
+                if summary.num_entries > 0 && cmp.compare(&summary.largest_key, iter.key()) != Ordering::Less {
+                    return LEVELDB_ERR!(Corruption, "keys are not in increasing order");
+                }
+            }
+            if summary.num_entries == 0 {
+                summary.smallest_key = iter.key().to_vec();
+            }
+            summary.largest_key.clear();
+            summary.largest_key.extend_from_slice(iter.key());
+            summary.num_entries += 1;
+            summary.raw_key_size += iter.key().len() as u64;
+            summary.raw_value_size += iter.value().len() as u64;
+            iter.next();
+        }
+        iter.status()?;
+
+        if let Some(cmp) = comparator {
+            if summary.num_entries > 0 && cmp.compare(index_iter.key(), &summary.largest_key) == Ordering::Less {
+                return LEVELDB_ERR!(Corruption, "index key sorts before the end of its data block");
+            }
+        }
+        index_iter.next();
+    }
+    index_iter.status()?;
+    Ok(summary)
+}
+
+/// Check that the block at `handle`, including its trailer, ends at or before `limit`.
+fn check_extent(handle: &BlockHandle, limit: u64) -> Result<()> {
+    match handle
+        .offset()
+        .checked_add(handle.size())
+        .and_then(|end| end.checked_add(BLOCK_TRAILER_SIZE as u64))
+    {
+        Some(end) if end <= limit => Ok(()),
+        _ => LEVELDB_ERR!(Corruption, "block handle points outside of its region"),
+    }
+}
+
+/// Check the offset array of a filter block. See filter_block.rs for the layout.
+fn check_filter_block(contents: &[u8]) -> Result<()> {
+    let n = contents.len();
+    if n < 5 {
+        return LEVELDB_ERR!(Corruption, "filter block is too short");
+    }
+    let array_offset = decode_fixed_32(&contents[n - 5..]) as usize;
+    if array_offset > n - 5 || !(n - 5 - array_offset).is_multiple_of(4) {
+        return LEVELDB_ERR!(Corruption, "bad filter offset array");
+    }
+    let mut prev = 0;
+    for i in (array_offset..n - 5).step_by(4) {
+        let start = decode_fixed_32(&contents[i..]) as usize;
+        if start < prev || start > array_offset {
+            return LEVELDB_ERR!(Corruption, "bad filter offset");
+        }
+        prev = start;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        leveldb::{
+            env::Env,
+            options::{CompressionType, Options},
+        },
+        table::table_builder::TableBuilder,
+        util::{bloom::new_bloom_filter_policy, env_posix::PosixEnv, testutil::tmp_dir},
+    };
+
+    fn build_table_bytes(options: &Options, name: &str, keys: &[String]) -> Vec<u8> {
+        let fname = format!("{}/000001.ldb", tmp_dir(name));
+        let mut builder = TableBuilder::new(options, PosixEnv.new_writable_file(&fname).expect("create"));
+        for key in keys {
+            builder.add(key.as_bytes(), format!("value-of-{}", key).as_bytes());
+        }
+        builder.finish().expect("finish");
+        builder.into_file().close().expect("close");
+        std::fs::read(&fname).expect("read")
+    }
+
+    fn keys(n: usize) -> Vec<String> { (0..n).map(|i| format!("key{:06}", i)).collect() }
+
+    #[test]
+    fn valid_table() {
+        let options = Options {
+            block_size: 256,
+            compression: CompressionType::Lz4,
+            filter_policy: Some(new_bloom_filter_policy(10)),
+            ..Options::default()
+        };
+        let data = build_table_bytes(&options, "verify_valid", &keys(1000));
+        let summary = verify_table_bytes_with(bytewise_comparator().as_ref(), &data).expect("verify");
+        assert_eq!(summary.num_entries, 1000);
+        assert!(summary.num_data_blocks > 1);
+        assert_eq!(summary.raw_key_size, 9 * 1000);
+        assert_eq!(summary.smallest_key, b"key000000");
+        assert_eq!(summary.largest_key, b"key000999");
+        assert!(summary.filter_size > 0);
+        assert_eq!(summary.meta_blocks, vec!["filter.leveldb.BuiltinBloomFilter2".to_string()]);
+        assert_eq!(verify_table_bytes(&data).expect("verify"), summary);
+
+        let partitioned = Options {
+            partition_filters: true,
+            metadata_block_size: 1024,
+            ..options
+        };
+        let data = build_table_bytes(&partitioned, "verify_partitioned", &keys(1000));
+        let summary = verify_table_bytes(&data).expect("verify");
+        assert_eq!(summary.num_entries, 1000);
+        assert!(summary.filter_size > 0);
+        assert_eq!(summary.meta_blocks, vec!["partitionedfilter.leveldb.BuiltinBloomFilter2".to_string()]);
+
+        let empty = build_table_bytes(&Options::default(), "verify_empty", &[]);
+        let summary = verify_table_bytes(&empty).expect("verify");
+        assert_eq!(summary.num_entries, 0);
+        assert_eq!(summary.num_data_blocks, 0);
+    }
+
+    #[test]
+    fn corrupted_table() {
+        let options = Options {
+            block_size: 256,
+            ..Options::default()
+        };
+        let data = build_table_bytes(&options, "verify_corrupt", &keys(200));
+        assert!(verify_table_bytes(&data[..Footer::ENCODED_LENGTH - 1]).unwrap_err().is_corruption());
+        assert!(verify_table_bytes(&data[..data.len() - 1]).unwrap_err().is_corruption());
+
+        // A flipped bit anywhere before the footer is caught by a block checksum.
+        for offset in (0..data.len() - Footer::ENCODED_LENGTH).step_by(97) {
+            let mut corrupt = data.clone();
+            corrupt[offset] ^= 0x10;
+            assert!(verify_table_bytes(&corrupt).unwrap_err().is_corruption(), "offset {}", offset);
+        }
+    }
+
+    struct ReverseComparator;
+
+    impl Comparator for ReverseComparator {
+        fn compare(&self, a: &[u8], b: &[u8]) -> Ordering { b.cmp(a) }
+
+        fn name(&self) -> &'static str { "test.ReverseComparator" }
+
+        fn find_shortest_separator(&self, _start: &mut Vec<u8>, _limit: &[u8]) {}
+
+        fn find_short_successor(&self, _key: &mut Vec<u8>) {}
+    }
+
+    #[test]
+    fn key_order() {
+        let options = Options {
+            comparator: Arc::new(ReverseComparator),
+            ..Options::default()
+        };
+        let data = build_table_bytes(&options, "verify_order", &["b".to_string(), "a".to_string()]);
+        assert_eq!(verify_table_bytes(&data).expect("verify").num_entries, 2);
+        assert!(verify_table_bytes_with(&ReverseComparator, &data).is_ok());
+        assert!(verify_table_bytes_with(bytewise_comparator().as_ref(), &data)
+            .unwrap_err()
+            .is_corruption());
+    }
+}
@@ -19,9 +19,9 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::{cell::RefCell, mem, ptr, rc::Rc};
+use std::{alloc::Layout, cell::RefCell, mem, mem::MaybeUninit, ptr, rc::Rc};
 
-const K_BLOCK_SIZE: usize = 4096;
+pub const K_BLOCK_SIZE: usize = 4096;
 
 pub type ArenaRef = Rc<RefCell<Arena>>;
 
@@ -29,103 +29,191 @@ pub struct Arena {
     ptr: *mut u8,
     bytes_remaining: usize,
     memory_usage: i64,
-    blocks: Vec<Vec<u8>>,
+    // Bytes handed out to callers
+    useful_bytes: i64,
+    // Alignment padding and block tails left unused when a new block was started
+    wasted_bytes: i64,
+    block_size: usize,
+    blocks: Vec<Box<[MaybeUninit<u8>]>>,
+    // Blocks of `block_size` bytes retained by `reset()`, handed out again before any
+    // new block is allocated.
+    spare_blocks: Vec<Box<[MaybeUninit<u8>]>>,
 }
 
 impl Arena {
-    pub fn new() -> Self {
+    pub fn new() -> Self { Self::with_block_size(K_BLOCK_SIZE) }
+
+    /// Create an arena that carves small allocations out of blocks of `block_size`
+    /// bytes. Allocations of more than a quarter of a block get a block of their own.
+    pub fn with_block_size(block_size: usize) -> Self {
+        assert!(block_size >= 64);
         Self {
             ptr: ptr::null_mut(),
             bytes_remaining: 0,
             memory_usage: 0,
+            useful_bytes: 0,
+            wasted_bytes: 0,
+            block_size,
             blocks: Vec::new(),
+            spare_blocks: Vec::new(),
         }
     }
 
+    /// Forget every allocation, so that the arena can be used again from scratch, e.g.
+    /// by the next memtable. The regular blocks are retained for reuse rather than
+    /// freed; the blocks of large allocations are freed.
+    /// REQUIRES: nothing refers to memory handed out by the arena any more.
+    pub fn reset(&mut self) {
+        let block_size = self.block_size;
+        self.spare_blocks.extend(self.blocks.drain(..).filter(|block| block.len() == block_size));
+        self.ptr = ptr::null_mut();
+        self.bytes_remaining = 0;
+        self.memory_usage = 0;
+        self.useful_bytes = 0;
+        self.wasted_bytes = 0;
+    }
+
+    /// Return the size of the blocks that small allocations are carved out of.
+    pub fn block_size(&self) -> usize { self.block_size }
+
     /// Allocate a byte slice with length `bytes`.
-    /// Return a unique refrence to the slice allocated.
+    /// Return a unique refrence to the slice allocated, filled with zeros.
     pub fn alloc(&mut self, bytes: usize) -> *mut u8 {
+        let result = self.alloc_uninit(bytes);
+        unsafe { ptr::write_bytes(result, 0, bytes) };
+        result
+    }
+
+    /// Allocate a byte slice with length `bytes` without initializing it. The caller
+    /// must write every byte before reading any of them; this saves the cost of zeroing
+    /// memory that is about to be overwritten anyway, such as copies of keys and values.
+    pub fn alloc_uninit(&mut self, bytes: usize) -> *mut u8 {
         assert!(bytes > 0);
+        self.useful_bytes += bytes as i64;
         let bytes_remaning = self.bytes_remaining;
         if bytes <= bytes_remaning {
             assert!(!self.ptr.is_null());
             let result = self.ptr;
             unsafe {
-                self.ptr = self.ptr.offset(bytes as isize);
+                self.ptr = self.ptr.add(bytes);
                 self.bytes_remaining -= bytes;
                 return result;
             }
         }
-        return self.alloc_fallback(bytes);
+        self.alloc_fallback(bytes)
     }
 
     /// Allocate a byte slice with lenght `bytes` that is aligned to pointer
     /// address.
-    /// Return a unique reference to the slice allocated.
+    /// Return a unique reference to the slice allocated, filled with zeros.
     pub fn alloc_aliged(&mut self, bytes: usize) -> *mut u8 {
-        let ptr_size = mem::size_of::<usize>();
-        assert!(ptr_size <= 128);
-        let align = if ptr_size > 8 { ptr_size } else { 8 };
-        assert_eq!(align & (align - 1), 0);
-
-        let (bytes_remaining, slop) = {
-            let current_mod = self.ptr as usize & (align - 1);
-            let slop = if current_mod == 0 {
-                0
-            } else {
-              align - current_mod
-            };
-            (self.bytes_remaining, slop)
-        };
+        let align = mem::align_of::<usize>().max(8);
+        let result = self.alloc_layout(Layout::from_size_align(bytes, align).expect("invalid layout"));
+        unsafe { ptr::write_bytes(result, 0, bytes) };
+        result
+    }
+
+    /// Allocate memory that fits `layout`, without initializing it. A zero-sized layout
+    /// still takes one byte, so that every allocation has a distinct address.
+    pub fn alloc_layout(&mut self, layout: Layout) -> *mut u8 {
+        let (bytes, align) = (layout.size().max(1), layout.align());
+        self.useful_bytes += bytes as i64;
+
+        let slop = self.ptr.align_offset(align);
         let needed = bytes + slop;
-        let result : *mut u8;
-        if needed <= bytes_remaining {
+        let result = if needed <= self.bytes_remaining {
+            self.wasted_bytes += slop as i64;
             unsafe {
-                let p = self.ptr.offset(slop as isize);
-                self.ptr = self.ptr.offset(needed as isize);
+                let p = self.ptr.add(slop);
+                self.ptr = self.ptr.add(needed);
                 self.bytes_remaining -= needed;
-                result = p
+                p
             }
+        } else if bytes + align - 1 > self.block_size / 4 {
+            // Allocate it separately, with enough room to align it wherever the block
+            // starts.
+            self.wasted_bytes += align as i64 - 1;
+            let block = self.alloc_new(bytes + align - 1);
+            unsafe { block.add(block.align_offset(align)) }
         } else {
-            result = self.alloc_fallback(bytes);
-        }
+            // We waste the remaining space in the current block.
+            self.wasted_bytes += self.bytes_remaining as i64;
+            self.ptr = self.alloc_new(self.block_size);
+            self.bytes_remaining = self.block_size;
+            let slop = self.ptr.align_offset(align);
+            self.wasted_bytes += slop as i64;
+            unsafe {
+                let p = self.ptr.add(slop);
+                self.ptr = p.add(bytes);
+                self.bytes_remaining -= slop + bytes;
+                p
+            }
+        };
         assert_eq!(result as usize & (align - 1), 0);
         result
     }
 
+    /// Move `value` into the arena and return a reference to it. The arena frees the
+    /// memory of the object when it is dropped, but never runs its destructor.
+    pub fn alloc_obj<T>(&mut self, value: T) -> &mut T {
+        let result = self.alloc_layout(Layout::new::<T>()) as *mut T;
+        unsafe {
+            result.write(value);
+            &mut *result
+        }
+    }
+
     /// Return the memory usage for the memory pool, in number of bytes allocated.
     pub fn memory_usage(&self) -> i64 { self.memory_usage }
 
+    /// Return the number of bytes handed out by `alloc*()` calls.
+    pub fn useful_bytes(&self) -> i64 { self.useful_bytes }
+
+    /// Return the number of allocated bytes that can never be handed out: alignment
+    /// padding, and the tails of blocks that were too short for a later request.
+    pub fn wasted_bytes(&self) -> i64 { self.wasted_bytes }
+
     fn alloc_fallback(&mut self, bytes: usize) -> *mut u8 {
-        if bytes > K_BLOCK_SIZE / 4 {
+        if bytes > self.block_size / 4 {
+            // Object is more than a quarter of our block size. Allocate it separately
+            // to avoid wasting too much space in leftover bytes.
             return self.alloc_new(bytes);
         }
 
-        self.ptr = self.alloc_new(K_BLOCK_SIZE);
-        self.bytes_remaining = K_BLOCK_SIZE;
+        // We waste the remaining space in the current block.
+        self.wasted_bytes += self.bytes_remaining as i64;
+        self.ptr = self.alloc_new(self.block_size);
+        self.bytes_remaining = self.block_size;
 
         let result = self.ptr;
         unsafe {
-            self.ptr = self.ptr.offset(bytes as isize);
+            self.ptr = self.ptr.add(bytes);
             self.bytes_remaining -= bytes;
             result
         }
     }
 
     fn alloc_new(&mut self, bytes: usize) -> *mut u8 {
-        let mut v = Vec::with_capacity(bytes);
-        unsafe  {
-            v.set_len(bytes);
-            ptr::write_bytes(v.as_mut_ptr(), 0, bytes);
-        }
-        let result  = v.as_mut_ptr();
-        self.blocks.push(v);
+        let spare = match bytes == self.block_size {
+            true => self.spare_blocks.pop(),
+            false => None,
+        };
+        let mut block = spare.unwrap_or_else(|| Box::new_uninit_slice(bytes));
+        let result = block.as_mut_ptr() as *mut u8;
+        self.blocks.push(block);
         let memory_usage: i64 = self.memory_usage + bytes as i64;
         self.memory_usage = memory_usage;
-        unsafe { mem::transmute(result) }
+        result
     }
 }
 
+// The arena exclusively owns the blocks its pointers refer to.
+unsafe impl Send for Arena {}
+
+impl Default for Arena {
+    fn default() -> Self { Self::new() }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -173,6 +261,30 @@ mod tests {
         check_current_block(&arena, false, K_BLOCK_SIZE - 512 - ptr_size);
     }
 
+    #[test]
+    fn alloc_layout_and_obj() {
+        #[repr(align(64))]
+        struct CacheLine([u8; 64]);
+
+        let mut arena = Arena::new();
+        let _ = arena.alloc(1);
+        let line = arena.alloc_obj(CacheLine([7; 64]));
+        assert_eq!(line as *mut CacheLine as usize % 64, 0);
+        assert_eq!(line.0, [7; 64]);
+        let word = arena.alloc_obj(0x1234u64);
+        assert_eq!(word as *mut u64 as usize % mem::align_of::<u64>(), 0);
+        assert_eq!(*word, 0x1234);
+
+        // Too large to share a block, yet aligned wherever its block starts.
+        let p = arena.alloc_layout(Layout::from_size_align(2000, 256).unwrap());
+        assert_eq!(p as usize % 256, 0);
+        assert_eq!(arena.useful_bytes(), 1 + 64 + 8 + 2000);
+        assert_eq!(
+            arena.memory_usage(),
+            arena.useful_bytes() + arena.wasted_bytes() + arena.bytes_remaining as i64
+        );
+    }
+
     #[test]
     fn alloc() {
         let mut arena = Arena::new();
@@ -202,10 +314,73 @@ mod tests {
         assert_eq!(arena.memory_usage(), 16384); //12288 + 4096
     }
 
+    #[test]
+    fn alloc_uninit_and_accounting() {
+        let mut arena = Arena::new();
+        let p = arena.alloc_uninit(100);
+        unsafe {
+            ptr::write_bytes(p, 0xab, 100);
+            assert_eq!(*p.add(99), 0xab);
+        }
+        assert_eq!(arena.useful_bytes(), 100);
+        assert_eq!(arena.wasted_bytes(), 0);
+
+        // 100 is not a multiple of the alignment, so the next aligned block pays slop.
+        let q = arena.alloc_aliged(16);
+        assert_eq!(unsafe { std::slice::from_raw_parts(q, 16) }, [0u8; 16]);
+        let slop = arena.wasted_bytes();
+        assert!(slop < mem::size_of::<usize>().max(8) as i64);
+
+        // A request that does not fit in the current block abandons its tail.
+        let tail = arena.bytes_remaining as i64;
+        let _ = arena.alloc(1000);
+        let _ = arena.alloc(1000);
+        let _ = arena.alloc(1000);
+        let _ = arena.alloc(1000);
+        assert_eq!(arena.wasted_bytes(), slop + tail - 3000);
+        assert_eq!(arena.useful_bytes(), 100 + 16 + 4000);
+        assert_eq!(arena.memory_usage(), 2 * K_BLOCK_SIZE as i64);
+        assert_eq!(
+            arena.memory_usage(),
+            arena.useful_bytes() + arena.wasted_bytes() + arena.bytes_remaining as i64
+        );
+    }
+
+    #[test]
+    fn block_size_and_reset() {
+        let mut arena = Arena::with_block_size(1024);
+        assert_eq!(arena.block_size(), 1024);
+        for _ in 0..5 {
+            let _ = arena.alloc(200);
+        }
+        check_current_block(&arena, false, 1024 - 1000);
+        // More than a quarter of a block
+        let _ = arena.alloc(300);
+        // Does not fit in the rest of the current block
+        let _ = arena.alloc(200);
+        assert_eq!(arena.memory_usage(), 2 * 1024 + 300);
+
+        arena.reset();
+        check_current_block(&arena, true, 0);
+        assert_eq!(arena.memory_usage(), 0);
+        assert_eq!(arena.useful_bytes(), 0);
+        assert_eq!(arena.wasted_bytes(), 0);
+        assert_eq!(arena.spare_blocks.len(), 2);
+
+        // The retained blocks are handed out again, zeroed when asked to.
+        let p = arena.alloc_uninit(200);
+        unsafe { ptr::write_bytes(p, 0xff, 200) };
+        for _ in 0..5 {
+            let q = arena.alloc(200);
+            assert_eq!(unsafe { std::slice::from_raw_parts(q, 200) }, [0u8; 200]);
+        }
+        assert!(arena.spare_blocks.is_empty());
+        assert_eq!(arena.memory_usage(), 2 * 1024);
+    }
+
     #[test]
     fn arena_ref() {
        let arena_ref: ArenaRef = Rc::new(RefCell::new(Arena::new()));
-        assert_eq!(arena_ref.as_ptr().is_null(), false);
         assert_eq!(arena_ref.borrow_mut().memory_usage(), 0);
 
         let _ = arena_ref.borrow_mut().alloc(128);
@@ -26,6 +26,5 @@ pub fn memcpy(dst: &mut [u8], src: &[u8]) {
 }
 
 extern "C" {
-    #[inline]
     pub fn memcmp(s1: *const u8, s2: *const u8, n: usize) -> i32;
 }
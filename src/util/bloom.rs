@@ -0,0 +1,255 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::sync::Arc;
+
+use crate::{leveldb::filter_policy::FilterPolicy, util::hash::hash};
+
+fn bloom_hash(key: &[u8]) -> u32 { hash(key, 0xbc9f1d34) }
+
+/// A filter policy that uses a bloom filter with approximately the specified number
+/// of bits per key.
+pub struct BloomFilterPolicy {
+    bits_per_key: usize,
+    k: usize,
+}
+
+impl BloomFilterPolicy {
+    pub fn new(bits_per_key: usize) -> Self {
+        // We intentionally round down to reduce probing cost a little bit
+        let k = (bits_per_key as f64 * 0.69) as usize; // 0.69 =~ ln(2)
+        Self { bits_per_key, k: k.clamp(1, 30) }
+    }
+}
+
+impl FilterPolicy for BloomFilterPolicy {
+    fn name(&self) -> &'static str { "leveldb.BuiltinBloomFilter2" }
+
+    fn create_filter(&self, keys: &[&[u8]], dst: &mut Vec<u8>) {
+        // Compute bloom filter size (in both bits and bytes).
+        // For small n, we can see a very high false positive rate. Fix it by enforcing a
+        // minimum bloom filter length.
+        let bits = (keys.len() * self.bits_per_key).max(64);
+        let bytes = bits.div_ceil(8);
+        let bits = bytes * 8;
+
+        let init_size = dst.len();
+        dst.resize(init_size + bytes, 0);
+        dst.push(self.k as u8); // Remember # of probes in filter
+        let array = &mut dst[init_size..init_size + bytes];
+        for key in keys {
+            // Use double-hashing to generate a sequence of hash values.
+            // See analysis in [Kirsch,Mitzenmacher 2006].
+            let mut h = bloom_hash(key);
+            let delta = h.rotate_right(17);
+            for _ in 0..self.k {
+                let bitpos = h as usize % bits;
+                array[bitpos / 8] |= 1 << (bitpos % 8);
+                h = h.wrapping_add(delta);
+            }
+        }
+    }
+
+    fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool {
+        match decode_filter(filter) {
+            Ok((array, k)) => probe(array, k, bloom_hash(key)),
+            Err(matches) => matches,
+        }
+    }
+
+    fn keys_may_match(&self, keys: &[&[u8]], filter: &[u8], results: &mut [bool]) {
+        assert_eq!(keys.len(), results.len());
+        let (array, k) = match decode_filter(filter) {
+            Ok(decoded) => decoded,
+            Err(matches) => return results.fill(matches),
+        };
+
+        // Hash every key and prefetch the cache line of its first probe before testing
+        // any bits, so that the memory loads for different keys overlap.
+        let bits = array.len() * 8;
+        let hashes: Vec<u32> = keys
+            .iter()
+            .map(|key| {
+                let h = bloom_hash(key);
+                prefetch(&array[(h as usize % bits) / 8]);
+                h
+            })
+            .collect();
+        for (h, result) in hashes.into_iter().zip(results.iter_mut()) {
+            *result = probe(array, k, h);
+        }
+    }
+}
+
+/// Split `filter` into its bit array and number of probes. Returns `Err` with the
+/// answer for every key when the filter cannot be probed.
+fn decode_filter(filter: &[u8]) -> std::result::Result<(&[u8], usize), bool> {
+    let len = filter.len();
+    if len < 2 {
+        return Err(false);
+    }
+
+    // Use the encoded k so that we can read filters generated by bloom filters
+    // created using different parameters.
+    let k = filter[len - 1] as usize;
+    if k > 30 {
+        // Reserved for potentially new encodings for short bloom filters.
+        // Consider it a match.
+        return Err(true);
+    }
+    Ok((&filter[..len - 1], k))
+}
+
+/// Test the `k` bits selected by hash `h` in `array`.
+fn probe(array: &[u8], k: usize, mut h: u32) -> bool {
+    let bits = array.len() * 8;
+    let delta = h.rotate_right(17);
+    for _ in 0..k {
+        let bitpos = h as usize % bits;
+        if array[bitpos / 8] & (1 << (bitpos % 8)) == 0 {
+            return false;
+        }
+        h = h.wrapping_add(delta);
+    }
+    true
+}
+
+/// Hint the CPU to start loading the cache line holding `byte`.
+#[inline]
+fn prefetch(byte: &u8) {
+    #[cfg(target_arch = "x86_64")]
+    // SAFETY: SSE is part of the x86_64 baseline, and a prefetch is only a hint that
+    // never faults.
+    unsafe {
+        std::arch::x86_64::_mm_prefetch::<{ std::arch::x86_64::_MM_HINT_T0 }>(byte as *const u8 as *const i8)
+    };
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = byte;
+}
+
+/// Return a new filter policy that uses a bloom filter with approximately the
+/// specified number of bits per key. A good value for bits_per_key is 10, which yields
+/// a filter with ~ 1% false positive rate.
+pub fn new_bloom_filter_policy(bits_per_key: usize) -> Arc<dyn FilterPolicy> {
+    Arc::new(BloomFilterPolicy::new(bits_per_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::coding::encode_fixed_32;
+
+    fn key(i: u32, buf: &mut [u8; 4]) -> &[u8] {
+        encode_fixed_32(buf, i);
+        &buf[..]
+    }
+
+    fn build(policy: &BloomFilterPolicy, n: u32) -> Vec<u8> {
+        let keys: Vec<[u8; 4]> = (0..n).map(|i| i.to_le_bytes()).collect();
+        let refs: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+        let mut filter = Vec::new();
+        policy.create_filter(&refs, &mut filter);
+        filter
+    }
+
+    #[test]
+    fn empty_filter() {
+        let policy = BloomFilterPolicy::new(10);
+        let filter = build(&policy, 0);
+        assert!(!policy.key_may_match(b"hello", &filter));
+        assert!(!policy.key_may_match(b"world", &filter));
+    }
+
+    #[test]
+    fn small() {
+        let policy = BloomFilterPolicy::new(10);
+        let mut filter = Vec::new();
+        policy.create_filter(&[b"hello", b"world"], &mut filter);
+        assert!(policy.key_may_match(b"hello", &filter));
+        assert!(policy.key_may_match(b"world", &filter));
+        assert!(!policy.key_may_match(b"x", &filter));
+        assert!(!policy.key_may_match(b"foo", &filter));
+    }
+
+    #[test]
+    fn varying_lengths() {
+        let policy = BloomFilterPolicy::new(10);
+        let mut buf = [0u8; 4];
+        let mut mediocre_filters = 0;
+        let mut good_filters = 0;
+        let mut length = 1;
+        while length <= 10000 {
+            let filter = build(&policy, length);
+            assert!(filter.len() <= (length as usize * 10 / 8) + 40);
+
+            // All added keys must match
+            for i in 0..length {
+                assert!(policy.key_may_match(key(i, &mut buf), &filter));
+            }
+
+            // Check false positive rate
+            let mut hits = 0;
+            for i in 0..10000 {
+                if policy.key_may_match(key(i + 1_000_000_000, &mut buf), &filter) {
+                    hits += 1;
+                }
+            }
+            let rate = hits as f64 / 10000.0;
+            assert!(rate <= 0.02);
+            if rate > 0.0125 {
+                mediocre_filters += 1;
+            } else {
+                good_filters += 1;
+            }
+
+            length = if length < 10 {
+                length + 1
+            } else if length < 100 {
+                length + 10
+            } else if length < 1000 {
+                length + 100
+            } else {
+                length + 1000
+            };
+        }
+        assert!(mediocre_filters <= good_filters / 5);
+    }
+
+    #[test]
+    fn batch_matches_single() {
+        let policy = BloomFilterPolicy::new(10);
+        let filter = build(&policy, 1000);
+        let keys: Vec<[u8; 4]> = (0..2000u32).map(|i| (i * 7).to_le_bytes()).collect();
+        let refs: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+        let mut results = vec![false; refs.len()];
+        policy.keys_may_match(&refs, &filter, &mut results);
+        for (key, result) in refs.iter().zip(&results) {
+            assert_eq!(*result, policy.key_may_match(key, &filter));
+        }
+        assert!(results[..143].iter().all(|&r| r));
+
+        // Filters that cannot be probed give the same answer for every key.
+        policy.keys_may_match(&refs, &[], &mut results);
+        assert!(results.iter().all(|&r| !r));
+        policy.keys_may_match(&refs, &[0, 31], &mut results);
+        assert!(results.iter().all(|&r| r));
+    }
+}
@@ -19,190 +19,272 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::collections::HashMap;
-use std::mem::swap;
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::util::coding::decode_fixed_64;
 
 // Implementation taken from here
 // https://github.com/dermesser/leveldb-rs/blob/master/src/cache.rs
+//
+// The list nodes live in a slab and link to each other by index rather than by
+// pointer, so the list needs no unsafe code.
+
+/// Index of the sentinel node, which is never freed. Its `next` is the most recently
+/// used element and its `prev` the least recently used one.
+const HEAD: usize = 0;
 
 struct LRUNode<T> {
-    next: Option<Box<LRUNode<T>>>,
-    prev: Option<*mut LRUNode<T>>,
+    next: usize,
+    prev: usize,
     data: Option<T>,
 }
 
-/// No clone, no copy! That asserts that an LRUHandle exists only once.
-type LRUHandle<T> = *mut LRUNode<T>;
+/// Refers to an element of an `LRUList`. A handle is invalidated when its element is
+/// removed, after which its slot may be reused by another element.
+type LRUHandle = usize;
 
 struct LRUList<T> {
-    head: LRUNode<T>,
+    nodes: Vec<LRUNode<T>>,
+    // Slots of removed nodes, available for reuse
+    free: Vec<usize>,
     count: usize,
 }
 
-/// This is likely unstable; more investigation is needed into correct behavior!
 impl<T> LRUList<T> {
     fn new() -> LRUList<T> {
         LRUList {
-            head: LRUNode {
+            nodes: vec![LRUNode {
                 data: None,
-                next: None,
-                prev: None
-            },
+                next: HEAD,
+                prev: HEAD,
+            }],
+            free: Vec::new(),
             count: 0,
         }
     }
 
+    #[cfg(test)]
     fn count(&self) -> usize {
         self.count
     }
 
-    /// Inserts new element at front (least recently used element)
-    fn insert(&mut self, elem: T) -> LRUHandle<T> {
-        self.count += 1;
-
-        //Not first element
-        if self.head.next.is_some() {
-            let mut new_node = Box::new(LRUNode {
-                data: Some(elem),
-                next: None,
-                prev: Some(&mut self.head as *mut LRUNode<T>),
-            });
-            let new_pointer = new_node.as_mut() as *mut LRUNode<T>;
-
-            // Set up the node after the new node
-            self.head.next.as_mut().unwrap().prev = Some(new_pointer);
-            // Replace head.next with Node and set the new node's to that
-            new_node.next = self.head.next.take();
-            self.head.next = Some(new_node);
-
-            new_pointer
-        } else {
-            // First node; the only node right now is an empty head node
-            let mut new_node = Box::new(LRUNode{
-                data: Some(elem),
-                next: None,
-                prev: Some(&mut self.head as *mut LRUNode<T>),
-            });
-
-            let new_pointer = new_node.as_mut() as *mut LRUNode<T>;
-
-            //Set tail
-            self.head.prev = Some(new_pointer);
-            //Set First
-            self.head.next = Some(new_node);
+    /// Link the node at `index` right after the head.
+    fn link_front(&mut self, index: usize) {
+        let first = self.nodes[HEAD].next;
+        self.nodes[index].prev = HEAD;
+        self.nodes[index].next = first;
+        self.nodes[first].prev = index;
+        self.nodes[HEAD].next = index;
+    }
 
-            new_pointer
-        }
+    fn unlink(&mut self, index: usize) {
+        let LRUNode { prev, next, .. } = self.nodes[index];
+        self.nodes[prev].next = next;
+        self.nodes[next].prev = prev;
     }
 
-    fn remove_last(&mut self) -> Option<T> {
-       if self.count() == 0 as usize {
-           return None;
-       }
-
-       let mut lasto = unsafe { (*((*self.head.prev.unwrap()).prev.unwrap())).next.take() };
-
-       assert!(lasto.is_some());
-       if let Some(ref mut last) = lasto {
-           assert!(last.prev.is_some());
-           assert!(self.head.prev.is_some());
-           self.head.prev = last.prev;
-           self.count -= 1;
-           (*last).data.take()
-       } else {
-           None
-       }
-    }
-
-    fn remove(&mut self, node_handle: LRUHandle<T>) -> T {
-        unsafe {
-            let d = (*node_handle).data.take().unwrap();
-            // Take ownership of node to be removed
-            let mut current = (*(*node_handle).prev.unwrap()).next.take().unwrap();
-            let prev = current.prev.unwrap();
-            // Update previous node's sucessor
-            if current.next.is_some() {
-                // Update next node's predecessor.
-                current.next.as_mut().unwrap().prev = current.prev.take();
+    /// Inserts new element at front (most recently used element)
+    fn insert(&mut self, elem: T) -> LRUHandle {
+        let node = LRUNode {
+            data: Some(elem),
+            next: HEAD,
+            prev: HEAD,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
             }
-            (*prev).next = current.next.take();
-
-            self.count -= 1;
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.link_front(index);
+        self.count += 1;
+        index
+    }
 
-            d
+    /// Removes the least recently used element.
+    fn remove_last(&mut self) -> Option<T> {
+        match self.nodes[HEAD].prev {
+            HEAD => None,
+            last => Some(self.remove(last)),
         }
     }
-    
-    /// Reinserts the reference node at front
-    fn reinsert_front(&mut self, node_handle: LRUHandle<T>) {
-        unsafe {
-            let prevp = (*node_handle).prev.unwrap();
-
-            // If not last node, update following node's prev
-            if let Some(next) = (*node_handle).next.as_mut() {
-                next.prev = Some(prevp);
-            } else {
-                // If last node, update head
-                self.head.prev = Some(prevp);
-            }
 
-            // Swap this.next with prev.next. After that, this.next refers to this (!)
-            swap(&mut (*prevp).next, &mut (*node_handle).next);
-            // To reinsert at head, swap head's next with this.next
-            swap(&mut (*node_handle).next, &mut self.head.next);
-            // Update this prev reference to point to head
-
-            //Update the second node's prev reference
-            if let Some(ref mut newnext) = (*node_handle).next {
-                (*node_handle).prev = newnext.prev;
-                newnext.prev = Some(node_handle);
-            } else {
-                // Only one node, being the last one avoid head.prev pointing to head
-                self.head.prev = Some(node_handle);
-            }
+    fn remove(&mut self, node_handle: LRUHandle) -> T {
+        assert_ne!(node_handle, HEAD);
+        let data = self.nodes[node_handle].data.take().expect("removing a free LRU node");
+        self.unlink(node_handle);
+        self.free.push(node_handle);
+        self.count -= 1;
+        data
+    }
 
-            assert!(self.head.next.is_some());
-            assert!(self.head.prev.is_some());
-        }
+    /// Reinserts the reference node at front
+    fn reinsert_front(&mut self, node_handle: LRUHandle) {
+        assert!(self.nodes[node_handle].data.is_some());
+        self.unlink(node_handle);
+        self.link_front(node_handle);
     }
 
     fn _testing_head_ref(&self) -> Option<&T> {
-        if let Some(ref first) = self.head.next {
-            first.data.as_ref()
-        } else {
-            None
-        }
+        self.nodes[self.nodes[HEAD].next].data.as_ref()
     }
 }
 
 pub type CacheKey = [u8; 16];
 pub type CacheID = u64;
-type CacheEntry<T> = (T, LRUHandle<CacheKey>);
+
+/// Called with every element that leaves the cache for good, along with its key and
+/// charge: when it is evicted or erased (once no handle pins it any more), or when the
+/// cache is dropped. Elements taken back with `Cache::remove()` are not passed to it.
+pub type Deleter<T> = Box<dyn FnMut(&CacheKey, T, usize) + Send>;
+
+struct CacheEntry<T> {
+    key: CacheKey,
+    elem: T,
+    charge: usize,
+    // Number of handles that pin the entry
+    refs: usize,
+    // Set while the entry is unpinned, i.e. evictable
+    lru_handle: Option<LRUHandle>,
+    // Cleared when the entry is removed or replaced while pinned. It is then only
+    // kept alive for its handles, and freed by the last `release()`.
+    in_cache: bool,
+}
+
+/// A pinned reference to an element of a `Cache`, returned by `insert()` and
+/// `lookup()`. The element cannot be evicted while the handle exists; every handle
+/// must be given back to `release()` of the cache that returned it.
+#[must_use]
+#[derive(Debug)]
+pub struct CacheHandle {
+    pub(crate) slot: usize,
+}
+
+/// Counters of a cache's activity since it was created, and its current usage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups that found their key.
+    pub hits: u64,
+    /// Lookups that did not find their key.
+    pub misses: u64,
+    pub insertions: u64,
+    /// Elements removed to make room for others.
+    pub evictions: u64,
+    /// Total charge of the evicted elements.
+    pub evicted_charge: u64,
+    /// Total charge of the elements in the cache.
+    pub usage: usize,
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    /// The fraction of lookups that found their key, or 0 if there were none.
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+/// A cache that synchronizes internally, so that it can be shared between threads.
+/// Elements are charged against a capacity and pinned while handles to them exist,
+/// as in `Cache`. Implemented by `Mutex<Cache<T>>`, which evicts the least recently
+/// used elements, and by `ClockCache<T>`, whose lookups take no lock.
+pub trait ConcurrentCache<T>: Send + Sync {
+    /// Insert an element and return a handle that pins it, or `None` if the cache has
+    /// no room left for it.
+    fn insert(&self, key: &CacheKey, elem: T, charge: usize) -> Option<CacheHandle>;
+
+    /// Return a handle that pins the element for `key`, if it is in the cache.
+    fn lookup(&self, key: &CacheKey) -> Option<CacheHandle>;
+
+    /// The element pinned by `handle`.
+    fn value(&self, handle: &CacheHandle) -> T;
+
+    /// Give back a handle returned by `insert()` or `lookup()`.
+    fn release(&self, handle: CacheHandle);
+
+    /// Remove the element for `key`, once no handle pins it.
+    fn erase(&self, key: &CacheKey);
+
+    /// Remove every element that no handle pins.
+    fn prune(&self);
+
+    /// Erase every element whose key starts with `id`, encoded as a fixed64, e.g. the
+    /// blocks of a table whose file was deleted. Returns how many there were.
+    fn erase_cache_id(&self, id: CacheID) -> usize;
+
+    /// Returns an ID that is unique for this cache and that can be used to partition
+    /// the cache among several users.
+    fn new_cache_id(&self) -> CacheID;
+
+    /// The capacity of the cache, in the unit of the charges
+    fn capacity(&self) -> usize;
+
+    /// The total charge of the elements in the cache
+    fn usage(&self) -> usize;
+
+    /// The total charge of the elements in the cache that are pinned by a handle
+    fn pinned_usage(&self) -> usize;
+
+    /// Counters of the cache's activity and its current usage
+    fn stats(&self) -> CacheStats;
+
+    /// Approximate number of bytes of memory used by the cache: the charges of its
+    /// elements plus its own bookkeeping.
+    fn approximate_memory_usage(&self) -> usize;
+}
 
 /// Implementation of 'ShardedLRUCache'.
 /// See https://github.com/google/leveldb/blob/main/util/cache.cc
-/// Based on a HashMap; the elements are linked in order to support the LRU ordering
+/// Based on a HashMap; the elements are linked in order to support the LRU ordering.
+///
+/// Every element is inserted with a charge against the capacity of the cache, e.g.
+/// its size in bytes, or 1 to bound the number of elements. The least recently used
+/// elements are evicted to keep the total charge within the capacity. Elements pinned
+/// by a `CacheHandle` are not evictable, so while many are in use the usage may
+/// exceed the capacity.
 pub struct Cache<T> {
-    // note: CacheKeys (Vec<u8>) are duplicated between list and map. If this turns out to be a
-    // performance bottleneck, another layer of indirection™ can solve this by mapping the key
-    // to a numeric handle that keys both list and map.
-    list: LRUList<CacheKey>,
-    map: HashMap<CacheKey, CacheEntry<T>>,
+    // Entries live in a slab so that handles can refer to them by index. Only the
+    // unpinned entries are linked in the LRU list.
+    entries: Vec<Option<CacheEntry<T>>>,
+    free: Vec<usize>,
+    list: LRUList<usize>,
+    map: HashMap<CacheKey, usize>,
     cap: usize,
+    // Total charge of the elements in the cache
+    usage: usize,
     id: u64,
+    deleter: Option<Deleter<T>>,
+    stats: CacheStats,
 }
 
 impl <T> Cache<T> {
     pub fn new(capacity: usize) ->  Cache<T> {
         assert!(capacity > 0);
         Cache {
+            entries: Vec::new(),
+            free: Vec::new(),
             list: LRUList::new(),
             map: HashMap::with_capacity(1024),
             cap: capacity,
+            usage: 0,
             id: 0,
+            deleter: None,
+            stats: CacheStats::default(),
         }
     }
 
+    /// Pass every element that leaves the cache to `deleter` rather than dropping it.
+    pub fn set_deleter(&mut self, deleter: Deleter<T>) {
+        self.deleter = Some(deleter);
+    }
+
     /// Returns an ID that is unique for this cache and that can be used to partition the cache
     /// among several users.
     pub fn new_cache_id(&mut self) -> CacheID {
@@ -212,55 +294,279 @@ impl <T> Cache<T> {
 
     /// How many the cache currently contains
     pub fn count(&self) -> usize {
-        self.list.count()
+        self.map.len()
     }
 
-    /// The capacity of this cache
+    /// The capacity of this cache, in the unit of the charges
     pub fn cap(&self) -> usize {
         self.cap
     }
 
-    /// Insert a new element into the cache. The returned `CacheHandle` can be used for futher
-    /// operations on that element.
-    /// If the capacity has been reached, the last recently used element is removed from
-    /// the cache
-    pub fn insert(&mut self, key: &CacheKey, elem: T) {
-        if self.list.count() >= self.cap {
-            if let Some(removed_key) = self.list.remove_last() {
-                assert!(self.map.remove(&removed_key).is_some());
-            } else {
-                panic!("could not removed_last(); bug!");
+    /// The total charge of the elements in the cache
+    pub fn usage(&self) -> usize {
+        self.usage
+    }
+
+    /// The total charge of the elements in the cache that are pinned by a handle
+    pub fn pinned_usage(&self) -> usize {
+        self.map
+            .values()
+            .map(|&slot| self.entry(slot))
+            .filter(|entry| entry.refs > 0)
+            .map(|entry| entry.charge)
+            .sum()
+    }
+
+    /// Counters of the cache's activity since it was created, and its current usage.
+    /// Both `lookup()` and `get()` count as lookups.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            usage: self.usage,
+            capacity: self.cap,
+            ..self.stats
+        }
+    }
+
+    /// Approximate number of bytes of memory used by the cache: the charges of its
+    /// elements plus the memory of its slab, list and map.
+    pub fn approximate_memory_usage(&self) -> usize {
+        self.usage
+            + self.entries.capacity() * std::mem::size_of::<Option<CacheEntry<T>>>()
+            + self.list.nodes.capacity() * std::mem::size_of::<LRUNode<usize>>()
+            + self.map.capacity() * (std::mem::size_of::<CacheKey>() + std::mem::size_of::<usize>())
+    }
+
+    fn entry(&self, slot: usize) -> &CacheEntry<T> {
+        self.entries[slot].as_ref().expect("dangling cache slot")
+    }
+
+    fn entry_mut(&mut self, slot: usize) -> &mut CacheEntry<T> {
+        self.entries[slot].as_mut().expect("dangling cache slot")
+    }
+
+    /// Insert a new element into the cache, charging `charge` against its capacity,
+    /// and return a handle that pins it. An element with the same key is replaced.
+    /// The least recently used unpinned elements are removed from the cache until the
+    /// usage is back within the capacity.
+    pub fn insert(&mut self, key: &CacheKey, elem: T, charge: usize) -> CacheHandle {
+        self.erase(key);
+        let entry = CacheEntry {
+            key: *key,
+            elem,
+            charge,
+            refs: 1,
+            lru_handle: None,
+            in_cache: true,
+        };
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.entries[slot] = Some(entry);
+                slot
+            }
+            None => {
+                self.entries.push(Some(entry));
+                self.entries.len() - 1
+            }
+        };
+        self.map.insert(*key, slot);
+        self.usage += charge;
+        self.stats.insertions += 1;
+        self.evict();
+        CacheHandle { slot }
+    }
+
+    /// Return a handle that pins the element for `key`, if it is in the cache.
+    pub fn lookup(&mut self, key: &CacheKey) -> Option<CacheHandle> {
+        let slot = self.find(key)?;
+        let entry = self.entry_mut(slot);
+        entry.refs += 1;
+        if let Some(lru_handle) = entry.lru_handle.take() {
+            self.list.remove(lru_handle);
+        }
+        Some(CacheHandle { slot })
+    }
+
+    /// The element pinned by `handle`.
+    pub fn value(&self, handle: &CacheHandle) -> &T {
+        &self.entry(handle.slot).elem
+    }
+
+    /// Give back a handle returned by `insert()` or `lookup()`. Once no handle pins
+    /// the element it becomes evictable again, as the most recently used element, or
+    /// is dropped if it was removed from the cache in the meantime.
+    pub fn release(&mut self, handle: CacheHandle) {
+        let entry = self.entry_mut(handle.slot);
+        assert!(entry.refs > 0);
+        entry.refs -= 1;
+        if entry.refs > 0 {
+            return;
+        }
+        if entry.in_cache {
+            let lru_handle = self.list.insert(handle.slot);
+            self.entry_mut(handle.slot).lru_handle = Some(lru_handle);
+            self.evict();
+        } else {
+            let entry = self.free_slot(handle.slot);
+            self.delete(entry);
+        }
+    }
+
+    fn free_slot(&mut self, slot: usize) -> CacheEntry<T> {
+        self.free.push(slot);
+        self.entries[slot].take().expect("dangling cache slot")
+    }
+
+    fn delete(&mut self, entry: CacheEntry<T>) {
+        if let Some(deleter) = self.deleter.as_mut() {
+            deleter(&entry.key, entry.elem, entry.charge);
+        }
+    }
+
+    /// Remove unpinned elements, least recently used first, until the usage is within
+    /// the capacity or nothing is left to evict.
+    fn evict(&mut self) {
+        while self.usage > self.cap {
+            match self.list.remove_last() {
+                Some(slot) => {
+                    let entry = self.free_slot(slot);
+                    assert!(self.map.remove(&entry.key).is_some());
+                    self.usage -= entry.charge;
+                    self.stats.evictions += 1;
+                    self.stats.evicted_charge += entry.charge as u64;
+                    self.delete(entry);
+                }
+                None => break,
             }
         }
+    }
 
-        let lru_handle = self.list.insert(*key);
-        self.map.insert(*key, (elem, lru_handle));
+    /// Return the slot of the element for `key`, counting a hit or a miss.
+    fn find(&mut self, key: &CacheKey) -> Option<usize> {
+        let slot = self.map.get(key).copied();
+        match slot {
+            Some(_) => self.stats.hits += 1,
+            None => self.stats.misses += 1,
+        }
+        slot
     }
 
-    /// Retrieve an element from the cache.
+    /// Retrieve an element from the cache without pinning it.
     /// If the element has been preempted from the cache in the meantime, this returns None
     pub fn get(&mut self, key: &CacheKey) -> Option<&T> {
-        match self.map.get(key) {
-            None => None,
-            Some(&(ref elem, ref lru_handle)) => {
-                self.list.reinsert_front(*lru_handle);
-                Some(elem)
-            }
+        let slot = self.find(key)?;
+        if let Some(lru_handle) = self.entry(slot).lru_handle {
+            self.list.reinsert_front(lru_handle);
         }
+        Some(&self.entry(slot).elem)
     }
 
-    /// Remove  an element from the cache (for invalidation)
+    /// Remove  an element from the cache (for invalidation). Returns the element unless
+    /// a handle still pins it, in which case it stays alive until the handle is
+    /// released and `None` is returned.
     pub fn remove(&mut self, key: &CacheKey) -> Option<T> {
-        match self.map.remove(key) {
-            None => None,
-            Some((elem, lru_handle)) => {
-                self.list.remove(lru_handle);
-                Some(elem)
+        self.unlink(key).flatten().map(|entry| entry.elem)
+    }
+
+    /// Remove an element from the cache and pass it to the deleter, once no handle
+    /// pins it. Returns whether the cache held an element for `key`.
+    pub fn erase(&mut self, key: &CacheKey) -> bool {
+        match self.unlink(key) {
+            Some(entry) => {
+                if let Some(entry) = entry {
+                    self.delete(entry);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove every element that no handle pins and pass it to the deleter.
+    pub fn prune(&mut self) {
+        while let Some(slot) = self.list.remove_last() {
+            let entry = self.free_slot(slot);
+            assert!(self.map.remove(&entry.key).is_some());
+            self.usage -= entry.charge;
+            self.delete(entry);
+        }
+    }
+
+    /// Erase every element whose key starts with `id`, encoded as a fixed64, as the
+    /// keys of the users partitioned by `new_cache_id()` do. Returns how many there
+    /// were.
+    pub fn erase_cache_id(&mut self, id: CacheID) -> usize {
+        let keys: Vec<CacheKey> = self.map.keys().filter(|key| decode_fixed_64(&key[..8]) == id).copied().collect();
+        for key in keys.iter() {
+            self.erase(key);
+        }
+        keys.len()
+    }
+
+    /// Take the element for `key` out of the cache. Returns `Some(None)` if a handle
+    /// still pins it, in which case it is freed by the last `release()`.
+    fn unlink(&mut self, key: &CacheKey) -> Option<Option<CacheEntry<T>>> {
+        let slot = self.map.remove(key)?;
+        let entry = self.entry_mut(slot);
+        entry.in_cache = false;
+        let (charge, refs, lru_handle) = (entry.charge, entry.refs, entry.lru_handle.take());
+        self.usage -= charge;
+        if refs > 0 {
+            return Some(None);
+        }
+        if let Some(lru_handle) = lru_handle {
+            self.list.remove(lru_handle);
+        }
+        Some(Some(self.free_slot(slot)))
+    }
+}
+
+impl<T> Drop for Cache<T> {
+    fn drop(&mut self) {
+        if self.deleter.is_none() {
+            return;
+        }
+        let slots: Vec<usize> = self.map.values().copied().collect();
+        for slot in slots {
+            // Pinned elements are dropped with their slot: no handle can be released
+            // once the cache is gone.
+            if self.entry(slot).refs == 0 {
+                let entry = self.free_slot(slot);
+                self.delete(entry);
             }
         }
     }
 }
 
+impl<T: Clone + Send> ConcurrentCache<T> for Mutex<Cache<T>> {
+    fn insert(&self, key: &CacheKey, elem: T, charge: usize) -> Option<CacheHandle> {
+        Some(self.lock().unwrap().insert(key, elem, charge))
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<CacheHandle> { self.lock().unwrap().lookup(key) }
+
+    fn value(&self, handle: &CacheHandle) -> T { self.lock().unwrap().value(handle).clone() }
+
+    fn release(&self, handle: CacheHandle) { self.lock().unwrap().release(handle) }
+
+    fn erase(&self, key: &CacheKey) { self.lock().unwrap().erase(key); }
+
+    fn prune(&self) { self.lock().unwrap().prune() }
+
+    fn erase_cache_id(&self, id: CacheID) -> usize { self.lock().unwrap().erase_cache_id(id) }
+
+    fn new_cache_id(&self) -> CacheID { self.lock().unwrap().new_cache_id() }
+
+    fn capacity(&self) -> usize { self.lock().unwrap().cap() }
+
+    fn usage(&self) -> usize { self.lock().unwrap().usage() }
+
+    fn pinned_usage(&self) -> usize { self.lock().unwrap().pinned_usage() }
+
+    fn stats(&self) -> CacheStats { self.lock().unwrap().stats() }
+
+    fn approximate_memory_usage(&self) -> usize { self.lock().unwrap().approximate_memory_usage() }
+}
+
 #[cfg(test)]
 mod test {
     use super::LRUList;
@@ -270,6 +576,12 @@ mod test {
         [a, b, c, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
     }
 
+    /// Insert without keeping the element pinned.
+    fn put<T>(cache: &mut Cache<T>, key: &CacheKey, elem: T, charge: usize) {
+        let handle = cache.insert(key, elem, charge);
+        cache.release(handle);
+    }
+
     #[test]
     fn test_blockcache_cache_add_rm() {
         let mut cache = Cache::new(128);
@@ -280,11 +592,11 @@ mod test {
         let h_332 = make_key(6, 3, 1);
         let h_899 = make_key(8, 2, 1);
 
-        cache.insert(&h_123, 123);
-        cache.insert(&h_332, 332);
-        cache.insert(&h_521, 521);
-        cache.insert(&h_372, 372);
-        cache.insert(&h_899, 899);
+        put(&mut cache, &h_123, 123, 1);
+        put(&mut cache, &h_332, 332, 1);
+        put(&mut cache, &h_521, 521, 1);
+        put(&mut cache, &h_372, 372, 1);
+        put(&mut cache, &h_899, 899, 1);
 
         assert_eq!(cache.count(), 5);
 
@@ -297,7 +609,7 @@ mod test {
 
         assert_eq!(cache.count(), 4);
         assert_eq!(cache.cap(), 128);
-        assert_eq!(cache.new_cache_id(), 1 as u64);
+        assert_eq!(cache.new_cache_id(), 1_u64);
     }
 
     #[test]
@@ -332,19 +644,19 @@ mod test {
         let handle2 = lru.insert(22);
         let handle3 = lru.insert(244);
 
-        assert_eq!(lru._testing_head_ref().map(|r| (*r)).unwrap(), 244);
+        assert_eq!(lru._testing_head_ref().copied().unwrap(), 244);
 
         lru.reinsert_front(handle1);
 
-        assert_eq!(lru._testing_head_ref().map(|r| (*r)).unwrap(), 56);
+        assert_eq!(lru._testing_head_ref().copied().unwrap(), 56);
 
         lru.reinsert_front(handle3);
 
-        assert_eq!(lru._testing_head_ref().map(|r| (*r)).unwrap(), 244);
+        assert_eq!(lru._testing_head_ref().copied().unwrap(), 244);
 
         lru.reinsert_front(handle2);
 
-        assert_eq!(lru._testing_head_ref().map(|r| (*r)).unwrap(), 22);
+        assert_eq!(lru._testing_head_ref().copied().unwrap(), 22);
 
         assert_eq!(lru.remove_last(), Some(56));
         assert_eq!(lru.remove_last(), Some(244));
@@ -367,9 +679,9 @@ mod test {
             lru.insert(8),
         ];
 
-        for i in 0..9 {
-            lru.reinsert_front(handles[i]);
-            assert_eq!(lru._testing_head_ref().map(|x| *x), Some(i));
+        for (i, handle) in handles.into_iter().enumerate() {
+            lru.reinsert_front(handle);
+            assert_eq!(lru._testing_head_ref().copied(), Some(i));
         }
     }
 
@@ -380,10 +692,248 @@ mod test {
         let handle = lru.insert(3);
 
         lru.reinsert_front(handle);
-        assert_eq!(lru._testing_head_ref().map(|x| *x), Some(3));
+        assert_eq!(lru._testing_head_ref().copied(), Some(3));
         assert_eq!(lru.remove_last(), Some(3));
         assert_eq!(lru.remove_last(), None);
         assert_eq!(lru.remove_last(), None);
     }
-}
 
+    #[test]
+    fn test_blockcache_replace_key() {
+        let mut cache = Cache::new(2);
+        let (a, b, c) = (make_key(1, 0, 0), make_key(2, 0, 0), make_key(3, 0, 0));
+        put(&mut cache, &a, 1, 1);
+        put(&mut cache, &a, 2, 1);
+        assert_eq!(cache.count(), 1);
+        put(&mut cache, &b, 3, 1);
+        put(&mut cache, &c, 4, 1);
+        assert_eq!(cache.count(), 2);
+        assert_eq!(cache.get(&a), None);
+        assert_eq!(cache.get(&b), Some(&3));
+        assert_eq!(cache.get(&c), Some(&4));
+    }
+
+    #[test]
+    fn test_blockcache_stress_against_model() {
+        use crate::util::random::Random;
+
+        // The model keeps keys from least to most recently used.
+        let rnd = Random::new(301);
+        let cap = 16;
+        let mut cache = Cache::new(cap);
+        let mut model: Vec<(CacheKey, u32)> = Vec::new();
+        let ops = if cfg!(miri) { 500 } else { 20_000 };
+        for i in 0..ops {
+            let key = make_key(rnd.uniform(40) as u8, 0, 0);
+            let found = model.iter().position(|(k, _)| *k == key);
+            match rnd.uniform(3) {
+                0 => {
+                    if let Some(pos) = found {
+                        model.remove(pos);
+                    } else if model.len() == cap {
+                        model.remove(0);
+                    }
+                    model.push((key, i));
+                    put(&mut cache, &key, i, 1);
+                }
+                1 => {
+                    let expected = found.map(|pos| {
+                        let entry = model.remove(pos);
+                        model.push(entry);
+                        entry.1
+                    });
+                    assert_eq!(cache.get(&key).copied(), expected);
+                }
+                _ => {
+                    let expected = found.map(|pos| model.remove(pos).1);
+                    assert_eq!(cache.remove(&key), expected);
+                }
+            }
+            assert_eq!(cache.count(), model.len());
+        }
+        // The list slab never grows past the capacity plus its head.
+        assert!(cache.list.nodes.len() <= cap + 1);
+    }
+
+    #[test]
+    fn test_blockcache_charge() {
+        let mut cache = Cache::new(100);
+        let (a, b, c, d) = (make_key(1, 0, 0), make_key(2, 0, 0), make_key(3, 0, 0), make_key(4, 0, 0));
+        put(&mut cache, &a, 1, 40);
+        put(&mut cache, &b, 2, 10);
+        put(&mut cache, &c, 3, 40);
+        assert_eq!(cache.usage(), 90);
+        cache.get(&a);
+
+        // Making room for d evicts b and c, the least recently used elements.
+        put(&mut cache, &d, 4, 60);
+        assert_eq!(cache.get(&b), None);
+        assert_eq!(cache.get(&c), None);
+        assert_eq!(cache.get(&a), Some(&1));
+        assert_eq!(cache.usage(), 100);
+
+        // Replacing an element releases its old charge.
+        put(&mut cache, &d, 5, 20);
+        assert_eq!(cache.usage(), 60);
+        assert_eq!(cache.remove(&a), Some(1));
+        assert_eq!(cache.usage(), 20);
+
+        // An element larger than the whole cache does not stay cached.
+        put(&mut cache, &b, 6, 101);
+        assert_eq!(cache.get(&b), None);
+        assert_eq!(cache.usage(), 0);
+    }
+
+    #[test]
+    fn test_blockcache_pinning() {
+        let mut cache = Cache::new(2);
+        let (a, b, c) = (make_key(1, 0, 0), make_key(2, 0, 0), make_key(3, 0, 0));
+        let ha = cache.insert(&a, 1, 1);
+        put(&mut cache, &b, 2, 1);
+        let hb = cache.lookup(&b).unwrap();
+        assert_eq!(*cache.value(&hb), 2);
+        assert_eq!(cache.pinned_usage(), 2);
+
+        // Nothing is evictable while both are pinned, so the usage exceeds the capacity.
+        put(&mut cache, &c, 3, 1);
+        assert_eq!(cache.get(&c), None);
+        assert_eq!(cache.get(&a), Some(&1));
+        let hb2 = cache.lookup(&b).unwrap();
+        cache.release(hb);
+        assert_eq!(cache.pinned_usage(), 2);
+
+        // Once released, a is the only unpinned element and the first to go.
+        cache.release(ha);
+        put(&mut cache, &c, 3, 1);
+        assert_eq!(cache.lookup(&a).map(|h| cache.release(h)), None);
+        assert_eq!(cache.get(&c), Some(&3));
+
+        // A pinned element that is removed stays readable through its handle.
+        assert_eq!(cache.remove(&b), None);
+        assert_eq!(cache.get(&b), None);
+        assert_eq!(*cache.value(&hb2), 2);
+        assert_eq!(cache.usage(), 1);
+        let hc = cache.insert(&c, 4, 1);
+        cache.release(hb2);
+        assert_eq!(*cache.value(&hc), 4);
+        cache.release(hc);
+        assert_eq!(cache.remove(&c), Some(4));
+        assert_eq!(cache.count(), 0);
+        assert_eq!(cache.usage(), 0);
+    }
+
+    #[test]
+    fn test_blockcache_deleter() {
+        use std::sync::{Arc, Mutex};
+
+        let deleted = Arc::new(Mutex::new(Vec::new()));
+        let mut cache = Cache::new(10);
+        let log = deleted.clone();
+        cache.set_deleter(Box::new(move |key: &CacheKey, elem: u32, charge| log.lock().unwrap().push((key[0], elem, charge))));
+        let (a, b, c, d) = (make_key(1, 0, 0), make_key(2, 0, 0), make_key(3, 0, 0), make_key(4, 0, 0));
+        put(&mut cache, &a, 1, 6);
+        put(&mut cache, &b, 2, 4);
+        // Evicted
+        put(&mut cache, &c, 3, 4);
+        // Replaced
+        put(&mut cache, &c, 4, 4);
+        assert_eq!(*deleted.lock().unwrap(), vec![(1, 1, 6), (3, 3, 4)]);
+
+        // Erased while pinned: deleted on release.
+        let handle = cache.lookup(&b).unwrap();
+        assert!(cache.erase(&b));
+        assert!(!cache.erase(&b));
+        assert_eq!(deleted.lock().unwrap().len(), 2);
+        cache.release(handle);
+        assert_eq!(deleted.lock().unwrap()[2], (2, 2, 4));
+
+        // Taken back by the caller, so not deleted.
+        put(&mut cache, &d, 5, 1);
+        assert_eq!(cache.remove(&d), Some(5));
+        drop(cache);
+        assert_eq!(*deleted.lock().unwrap(), vec![(1, 1, 6), (3, 3, 4), (2, 2, 4), (3, 4, 4)]);
+    }
+
+    #[test]
+    fn test_blockcache_stats() {
+        let mut cache = Cache::new(10);
+        let (a, b, c) = (make_key(1, 0, 0), make_key(2, 0, 0), make_key(3, 0, 0));
+        let empty = cache.approximate_memory_usage();
+        put(&mut cache, &a, 1, 4);
+        put(&mut cache, &b, 2, 4);
+        assert_eq!(cache.get(&a), Some(&1));
+        assert!(cache.lookup(&c).is_none());
+        put(&mut cache, &c, 3, 4);
+        assert_eq!(cache.get(&b), None);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                insertions: 3,
+                evictions: 1,
+                evicted_charge: 4,
+                usage: 8,
+                capacity: 10,
+            }
+        );
+        assert_eq!(cache.stats().hit_ratio(), 1.0 / 3.0);
+        assert!(cache.approximate_memory_usage() >= empty + 8);
+    }
+
+    #[test]
+    fn test_blockcache_prune_and_erase_cache_id() {
+        let mut cache = Cache::new(100);
+        let (a, b, c) = (make_key(1, 0, 0), make_key(2, 0, 0), make_key(3, 0, 0));
+        put(&mut cache, &a, 1, 1);
+        let handle = cache.insert(&b, 2, 1);
+        put(&mut cache, &c, 3, 1);
+        cache.prune();
+        assert_eq!(cache.count(), 1);
+        assert_eq!(cache.usage(), 1);
+        assert_eq!(*cache.value(&handle), 2);
+        cache.release(handle);
+        assert_eq!(cache.get(&b), Some(&2));
+
+        let mut key = [0; 16];
+        for (id, offset) in [(1, 0), (1, 10), (5, 0), (1, 20)] {
+            crate::util::coding::encode_fixed_64(&mut key[..8], id);
+            crate::util::coding::encode_fixed_64(&mut key[8..], offset);
+            put(&mut cache, &key, offset as u32, 1);
+        }
+        assert_eq!(cache.erase_cache_id(1), 3);
+        assert_eq!(cache.erase_cache_id(1), 0);
+        assert_eq!(cache.get(&key), None);
+        assert_eq!(cache.count(), 2);
+    }
+
+    #[test]
+    fn test_blockcache_concurrent() {
+        use std::sync::{Arc, Mutex};
+
+        let cache = Arc::new(Mutex::new(Cache::<Arc<usize>>::new(64)));
+        let per_thread = if cfg!(miri) { 50 } else { 5000 };
+        let threads: Vec<_> = (0..4u8)
+            .map(|t| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for i in 0..per_thread {
+                        let key = make_key(t, (i % 100) as u8, 0);
+                        let mut cache = cache.lock().unwrap();
+                        if let Some(v) = cache.get(&key) {
+                            assert_eq!(**v % 100, i % 100);
+                        }
+                        put(&mut cache, &key, Arc::new(i), 1);
+                        if i % 7 == 0 {
+                            cache.remove(&make_key(t, (i % 13) as u8, 0));
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(cache.lock().unwrap().count() <= 64);
+    }
+}
@@ -0,0 +1,518 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// A cache that approximates LRU with the CLOCK algorithm, so that lookups only need
+// atomic operations on the slot they find: a hit sets the slot's reference bit
+// instead of moving the element to the front of a list under a lock.
+//
+// Elements live in a fixed-size open-addressing table, probed linearly from the hash
+// of their key. Every slot has a 64-bit `meta` word:
+//
+//    bits 0..30   number of handles pinning the element
+//    bit  30      CLOCK reference bit, set by lookups and cleared by the clock hand
+//    bits 61..64  state: EMPTY, TOMBSTONE, VISIBLE, INVISIBLE or BUSY
+//
+// A VISIBLE slot holds an element that lookups can pin, with a compare-and-swap that
+// only succeeds while the slot stays VISIBLE. Once pinned, its key and value cannot
+// change. An erased element that is still pinned is INVISIBLE, and freed by its last
+// release. A slot is BUSY while an element is written to it or dropped from it, by
+// whoever moved it there, and becomes a TOMBSTONE once free. Each slot also counts
+// the elements whose probe sequence passes over it, so that lookups can stop at the
+// first slot that no such element passes.
+//
+// Inserts, erases and evictions are serialized by a mutex, which also holds the
+// clock hand. Only they fill free slots, and evictions only free unpinned elements
+// that the hand has passed twice without a lookup in between.
+
+use std::{
+    cell::UnsafeCell,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::util::{
+    cache::{CacheHandle, CacheID, CacheKey, CacheStats, ConcurrentCache},
+    coding::decode_fixed_64,
+};
+
+const REFS_MASK: u64 = (1 << 30) - 1;
+const CLOCK_BIT: u64 = 1 << 30;
+const STATE_SHIFT: u32 = 61;
+
+const EMPTY: u64 = 0;
+const TOMBSTONE: u64 = 1;
+const VISIBLE: u64 = 2;
+const INVISIBLE: u64 = 3;
+const BUSY: u64 = 4;
+
+fn state(meta: u64) -> u64 { meta >> STATE_SHIFT }
+
+fn refs(meta: u64) -> u64 { meta & REFS_MASK }
+
+fn with_state(meta: u64, state: u64) -> u64 { (meta & !(7 << STATE_SHIFT)) | (state << STATE_SHIFT) }
+
+fn hash(key: &CacheKey) -> usize {
+    let h = decode_fixed_64(&key[..8]) ^ decode_fixed_64(&key[8..]).rotate_left(29);
+    (h.wrapping_mul(0x9e3779b97f4a7c15) >> 16) as usize
+}
+
+struct Slot<T> {
+    meta: AtomicU64,
+    // Number of elements stored past this slot in their probe sequence
+    displacements: AtomicU32,
+    charge: AtomicUsize,
+    // Written only while the slot is BUSY; read only while it is pinned.
+    key: UnsafeCell<CacheKey>,
+    value: UnsafeCell<Option<T>>,
+}
+
+/// A cache with CLOCK eviction whose lookups and releases take no lock. Elements are
+/// charged against the capacity and pinned by handles as in `Cache`.
+///
+/// The table has a fixed number of slots, sized from an estimate of the average
+/// charge of an element. If small elements fill every slot before the charges reach
+/// the capacity, older elements are evicted to make room.
+pub struct ClockCache<T> {
+    slots: Vec<Slot<T>>,
+    mask: usize,
+    cap: usize,
+    usage: AtomicUsize,
+    next_id: AtomicU64,
+    // Serializes the writers and holds the position of the clock hand.
+    hand: Mutex<usize>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    insertions: AtomicU64,
+    evictions: AtomicU64,
+    evicted_charge: AtomicU64,
+}
+
+// Elements are shared between the threads that pin them, and only dropped by the
+// thread that moved their slot to BUSY.
+unsafe impl<T: Send + Sync> Send for ClockCache<T> {}
+unsafe impl<T: Send + Sync> Sync for ClockCache<T> {}
+
+impl<T: Clone + Send + Sync> ClockCache<T> {
+    /// Create a cache for elements with a total charge of `capacity`, with enough slots
+    /// for elements whose charge averages `estimated_charge`.
+    pub fn new(capacity: usize, estimated_charge: usize) -> Self {
+        assert!(capacity > 0);
+        let n = (2 * capacity / estimated_charge.max(1)).clamp(16, 1 << 24).next_power_of_two();
+        Self {
+            slots: (0..n)
+                .map(|_| Slot {
+                    meta: AtomicU64::new(EMPTY << STATE_SHIFT),
+                    displacements: AtomicU32::new(0),
+                    charge: AtomicUsize::new(0),
+                    key: UnsafeCell::new([0; 16]),
+                    value: UnsafeCell::new(None),
+                })
+                .collect(),
+            mask: n - 1,
+            cap: capacity,
+            usage: AtomicUsize::new(0),
+            next_id: AtomicU64::new(0),
+            hand: Mutex::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            insertions: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            evicted_charge: AtomicU64::new(0),
+        }
+    }
+
+    /// Pin the element in slot `index` if it is VISIBLE.
+    fn try_pin(&self, index: usize) -> bool {
+        let meta = &self.slots[index].meta;
+        let mut current = meta.load(Ordering::Acquire);
+        while state(current) == VISIBLE {
+            match meta.compare_exchange_weak(current, (current + 1) | CLOCK_BIT, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return true,
+                Err(actual) => current = actual,
+            }
+        }
+        false
+    }
+
+    fn unpin(&self, index: usize) {
+        let old = self.slots[index].meta.fetch_sub(1, Ordering::AcqRel);
+        assert!(refs(old) > 0);
+        if refs(old) == 1 && state(old) == INVISIBLE {
+            // The element was erased while pinned, and this was its last handle.
+            self.try_free(index, old - 1);
+        }
+    }
+
+    /// Drop the element in slot `index` if its meta word is still `expected`. Returns
+    /// its charge on success.
+    fn try_free(&self, index: usize, expected: u64) -> Option<usize> {
+        let slot = &self.slots[index];
+        let busy = BUSY << STATE_SHIFT;
+        slot.meta.compare_exchange(expected, busy, Ordering::AcqRel, Ordering::Acquire).ok()?;
+        // SAFETY: the slot is BUSY, so no other thread reads or writes its contents.
+        let key = unsafe { *slot.key.get() };
+        unsafe { *slot.value.get() = None };
+        let mut i = hash(&key) & self.mask;
+        while i != index {
+            self.slots[i].displacements.fetch_sub(1, Ordering::AcqRel);
+            i = (i + 1) & self.mask;
+        }
+        let charge = slot.charge.load(Ordering::Relaxed);
+        slot.meta.store(TOMBSTONE << STATE_SHIFT, Ordering::Release);
+        Some(charge)
+    }
+
+    /// Find and pin the element for `key`.
+    fn find(&self, key: &CacheKey) -> Option<usize> {
+        let start = hash(key);
+        for probe in 0..self.slots.len() {
+            let index = (start + probe) & self.mask;
+            if self.try_pin(index) {
+                // SAFETY: the slot is pinned, so its key cannot change.
+                if unsafe { *self.slots[index].key.get() } == *key {
+                    return Some(index);
+                }
+                self.unpin(index);
+            }
+            if self.slots[index].displacements.load(Ordering::Acquire) == 0 {
+                break;
+            }
+        }
+        None
+    }
+
+    /// Make the element for `key` INVISIBLE. REQUIRES: the writer lock is held.
+    fn erase_locked(&self, key: &CacheKey) {
+        if let Some(index) = self.find(key) {
+            self.erase_pinned(index);
+        }
+    }
+
+    /// Make the element in slot `index` INVISIBLE and drop the pin of the caller on
+    /// it. REQUIRES: the writer lock is held.
+    fn erase_pinned(&self, index: usize) {
+        let meta = &self.slots[index].meta;
+        let mut current = meta.load(Ordering::Acquire);
+        while state(current) == VISIBLE {
+            match meta.compare_exchange_weak(current, with_state(current, INVISIBLE), Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    self.usage.fetch_sub(self.slots[index].charge.load(Ordering::Relaxed), Ordering::AcqRel);
+                    break;
+                }
+                Err(actual) => current = actual,
+            }
+        }
+        self.unpin(index);
+    }
+
+    /// Advance the clock hand until an element is evicted, clearing the reference bits
+    /// of the elements it passes. Returns false if no element is evictable.
+    /// REQUIRES: the writer lock is held, as `hand`.
+    fn evict_one(&self, hand: &mut usize) -> bool {
+        for _ in 0..2 * self.slots.len() {
+            let index = *hand;
+            *hand = (*hand + 1) & self.mask;
+            let meta = &self.slots[index].meta;
+            let current = meta.load(Ordering::Acquire);
+            if state(current) != VISIBLE || refs(current) > 0 {
+                continue;
+            }
+            if current & CLOCK_BIT != 0 {
+                let _ = meta.compare_exchange(current, current & !CLOCK_BIT, Ordering::AcqRel, Ordering::Relaxed);
+                continue;
+            }
+            if let Some(charge) = self.try_free(index, current) {
+                self.usage.fetch_sub(charge, Ordering::AcqRel);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+                self.evicted_charge.fetch_add(charge as u64, Ordering::Relaxed);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Return the first free slot in the probe sequence of `key`, counting the
+    /// displacement in the slots before it.
+    fn claim_slot(&self, key: &CacheKey) -> Option<usize> {
+        let start = hash(key);
+        let index = (0..self.slots.len()).map(|probe| (start + probe) & self.mask).find(|&i| {
+            matches!(state(self.slots[i].meta.load(Ordering::Acquire)), EMPTY | TOMBSTONE)
+        })?;
+        let mut i = start & self.mask;
+        while i != index {
+            self.slots[i].displacements.fetch_add(1, Ordering::AcqRel);
+            i = (i + 1) & self.mask;
+        }
+        Some(index)
+    }
+}
+
+impl<T: Clone + Send + Sync> ConcurrentCache<T> for ClockCache<T> {
+    fn insert(&self, key: &CacheKey, elem: T, charge: usize) -> Option<CacheHandle> {
+        let mut hand = self.hand.lock().unwrap();
+        self.erase_locked(key);
+        while self.usage.load(Ordering::Acquire) + charge > self.cap {
+            if !self.evict_one(&mut hand) {
+                break;
+            }
+        }
+        let index = match self.claim_slot(key) {
+            Some(index) => index,
+            None => {
+                // Every slot is taken by smaller elements than estimated.
+                if !self.evict_one(&mut hand) {
+                    return None;
+                }
+                self.claim_slot(key)?
+            }
+        };
+        let slot = &self.slots[index];
+        slot.meta.store(BUSY << STATE_SHIFT, Ordering::Release);
+        // SAFETY: the slot is BUSY, so no other thread reads or writes its contents.
+        unsafe {
+            *slot.key.get() = *key;
+            *slot.value.get() = Some(elem);
+        }
+        slot.charge.store(charge, Ordering::Relaxed);
+        self.usage.fetch_add(charge, Ordering::AcqRel);
+        self.insertions.fetch_add(1, Ordering::Relaxed);
+        slot.meta.store((VISIBLE << STATE_SHIFT) | 1, Ordering::Release);
+        Some(CacheHandle { slot: index })
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<CacheHandle> {
+        let slot = self.find(key);
+        let counter = if slot.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        slot.map(|slot| CacheHandle { slot })
+    }
+
+    fn value(&self, handle: &CacheHandle) -> T {
+        // SAFETY: the handle pins the slot, so its value cannot change.
+        unsafe { (*self.slots[handle.slot].value.get()).clone().expect("pinned slot without a value") }
+    }
+
+    fn release(&self, handle: CacheHandle) { self.unpin(handle.slot) }
+
+    fn erase(&self, key: &CacheKey) {
+        let _hand = self.hand.lock().unwrap();
+        self.erase_locked(key);
+    }
+
+    fn prune(&self) {
+        let _hand = self.hand.lock().unwrap();
+        for (index, slot) in self.slots.iter().enumerate() {
+            let current = slot.meta.load(Ordering::Acquire);
+            if state(current) == VISIBLE && refs(current) == 0 {
+                if let Some(charge) = self.try_free(index, current) {
+                    self.usage.fetch_sub(charge, Ordering::AcqRel);
+                }
+            }
+        }
+    }
+
+    fn erase_cache_id(&self, id: CacheID) -> usize {
+        let _hand = self.hand.lock().unwrap();
+        let mut erased = 0;
+        for index in 0..self.slots.len() {
+            if !self.try_pin(index) {
+                continue;
+            }
+            // SAFETY: the slot is pinned, so its key cannot change.
+            let key = unsafe { *self.slots[index].key.get() };
+            if decode_fixed_64(&key[..8]) == id {
+                self.erase_pinned(index);
+                erased += 1;
+            } else {
+                self.unpin(index);
+            }
+        }
+        erased
+    }
+
+    fn new_cache_id(&self) -> CacheID { self.next_id.fetch_add(1, Ordering::Relaxed) + 1 }
+
+    fn capacity(&self) -> usize { self.cap }
+
+    fn usage(&self) -> usize { self.usage.load(Ordering::Acquire) }
+
+    fn pinned_usage(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| {
+                let meta = slot.meta.load(Ordering::Acquire);
+                state(meta) == VISIBLE && refs(meta) > 0
+            })
+            .map(|slot| slot.charge.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            insertions: self.insertions.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            evicted_charge: self.evicted_charge.load(Ordering::Relaxed),
+            usage: self.usage(),
+            capacity: self.cap,
+        }
+    }
+
+    fn approximate_memory_usage(&self) -> usize { self.usage() + self.slots.len() * std::mem::size_of::<Slot<T>>() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::util::random::Random;
+
+    fn make_key(a: u8, b: u8) -> CacheKey {
+        let mut key = [0; 16];
+        key[0] = a;
+        key[8] = b;
+        key
+    }
+
+    fn put(cache: &ClockCache<u32>, key: &CacheKey, elem: u32, charge: usize) {
+        let handle = cache.insert(key, elem, charge).unwrap();
+        cache.release(handle);
+    }
+
+    fn get(cache: &ClockCache<u32>, key: &CacheKey) -> Option<u32> {
+        let handle = cache.lookup(key)?;
+        let value = cache.value(&handle);
+        cache.release(handle);
+        Some(value)
+    }
+
+    #[test]
+    fn insert_lookup_erase() {
+        let cache = ClockCache::new(100, 10);
+        for i in 0..10 {
+            put(&cache, &make_key(i, 0), i as u32, 10);
+        }
+        assert_eq!(cache.usage(), 100);
+        for i in 0..10 {
+            assert_eq!(get(&cache, &make_key(i, 0)), Some(i as u32));
+        }
+        assert_eq!(get(&cache, &make_key(10, 0)), None);
+
+        put(&cache, &make_key(3, 0), 33, 10);
+        assert_eq!(get(&cache, &make_key(3, 0)), Some(33));
+        cache.erase(&make_key(3, 0));
+        assert_eq!(get(&cache, &make_key(3, 0)), None);
+        assert_eq!(cache.usage(), 90);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.insertions, stats.evictions), (11, 2, 11, 0));
+        assert!(cache.approximate_memory_usage() > 90);
+    }
+
+    #[test]
+    fn eviction_spares_referenced_and_pinned() {
+        let cache = ClockCache::new(40, 10);
+        for i in 0..4 {
+            put(&cache, &make_key(i, 0), i as u32, 10);
+        }
+        // Looked up, so the hand passes over it once before it can be evicted.
+        assert_eq!(get(&cache, &make_key(0, 0)), Some(0));
+        put(&cache, &make_key(4, 0), 4, 10);
+        assert_eq!(get(&cache, &make_key(0, 0)), Some(0));
+        assert_eq!(cache.usage(), 40);
+        let pinned = cache.lookup(&make_key(4, 0)).unwrap();
+        for i in 5..20 {
+            put(&cache, &make_key(i, 0), i as u32, 10);
+        }
+        assert!(cache.usage() <= 40);
+        assert_eq!(cache.value(&pinned), 4);
+        assert_eq!(cache.pinned_usage(), 10);
+
+        // A pinned element that is erased stays readable until it is released.
+        cache.erase(&make_key(4, 0));
+        assert_eq!(get(&cache, &make_key(4, 0)), None);
+        assert_eq!(cache.value(&pinned), 4);
+        cache.release(pinned);
+        assert_eq!(cache.pinned_usage(), 0);
+    }
+
+    #[test]
+    fn prune_and_erase_cache_id() {
+        let cache = ClockCache::new(100, 10);
+        for (id, offset) in [(1, 0), (1, 1), (2, 0), (1, 2), (3, 0)] {
+            put(&cache, &make_key(id, offset), offset as u32, 10);
+        }
+        let pinned = cache.lookup(&make_key(1, 1)).unwrap();
+        assert_eq!(cache.erase_cache_id(1), 3);
+        assert_eq!(get(&cache, &make_key(1, 0)), None);
+        assert_eq!(get(&cache, &make_key(2, 0)), Some(0));
+        assert_eq!(cache.usage(), 20);
+        assert_eq!(cache.value(&pinned), 1);
+        cache.release(pinned);
+
+        let pinned = cache.lookup(&make_key(2, 0)).unwrap();
+        cache.prune();
+        assert_eq!(cache.usage(), 10);
+        assert_eq!(get(&cache, &make_key(3, 0)), None);
+        cache.release(pinned);
+        assert_eq!(get(&cache, &make_key(2, 0)), Some(0));
+    }
+
+    #[test]
+    fn concurrent() {
+        let cache = Arc::new(ClockCache::<Arc<u32>>::new(64, 1));
+        let per_thread = if cfg!(miri) { 50 } else { 5000 };
+        let threads: Vec<_> = (0..4u8)
+            .map(|t| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    let rnd = Random::new(t as u32 + 1);
+                    for i in 0..per_thread {
+                        let key = make_key(rnd.uniform(200) as u8, 0);
+                        match cache.lookup(&key) {
+                            Some(handle) => {
+                                assert_eq!(*cache.value(&handle) % 256, key[0] as u32);
+                                cache.release(handle);
+                            }
+                            None => {
+                                if let Some(handle) = cache.insert(&key, Arc::new(i * 256 + key[0] as u32), 1) {
+                                    cache.release(handle);
+                                }
+                            }
+                        }
+                        if i % 7 == 0 {
+                            cache.erase(&make_key(rnd.uniform(200) as u8, 0));
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(cache.usage() <= 64);
+        assert_eq!(cache.pinned_usage(), 0);
+    }
+}
@@ -19,25 +19,22 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::{mem::transmute, ptr::copy_nonoverlapping};
+use std::ptr::copy_nonoverlapping;
 use crate::{
     leveldb::result::{Error, ErrorType, Result},
-    leveldb::slice::Slice,
+    leveldb::slice::BorrowedSlice,
 };
 
-/// --------------------------------------------------------------------------------
-/// Encoding & Decoding which deal with primitive Rust slices
-/// --------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------
+// Encoding & Decoding which deal with primitive Rust slices
+// --------------------------------------------------------------------------------
 
 /// Encodes `value` in little-endian and puts it in the first 4-bytes of `dst`.
 ///
 /// Panics if `dst.len()` is less than 4.
 pub fn encode_fixed_32(dst: &mut [u8], value: u32) {
     assert!(dst.len() >= 4);
-    unsafe {
-        let bytes = transmute::<_, [u8; 4]>(value.to_le());
-        copy_nonoverlapping(bytes.as_ptr(), dst.as_mut_ptr(), 4);
-    }
+    dst[..4].copy_from_slice(&value.to_le_bytes());
 }
 
 /// Encodes 'value` in little-endian and appends it to `dst`. Extending the vector if
@@ -53,10 +50,7 @@ pub fn encode_fixed_32_vec(dst: &mut Vec<u8>, value: u32) {
 /// Panics if `dst.len()` is less than 8.
 pub fn encode_fixed_64(dst: &mut [u8], value: u64) {
     assert!(dst.len() >= 8);
-    unsafe {
-        let bytes = transmute::<_, [u8; 8]>(value.to_le());
-        copy_nonoverlapping(bytes.as_ptr(), dst.as_mut_ptr(), 8);
-    }
+    dst[..8].copy_from_slice(&value.to_le_bytes());
 }
 
 /// Encodes 'value` in little-endian and appends it to `dst`. Extending the vector if
@@ -133,10 +127,7 @@ pub fn encode_varint_32(dst: &mut [u8], value: u32) -> usize {
 pub fn encode_varint_32_vec(dst: &mut Vec<u8>, value: u32) -> usize {
     let enc_len = varint_length(value as u64);
     let old_len = dst.len();
-    unsafe {
-        dst.reserve(enc_len);
-        dst.set_len(old_len + enc_len);
-    }
+    dst.resize(old_len + enc_len, 0);
     encode_varint_32(&mut dst[old_len..], value)
 }
 
@@ -161,10 +152,7 @@ pub fn encode_varint_64(dst: &mut [u8], mut value: u64) -> usize {
 pub fn encode_varint_64_vec(dst: &mut Vec<u8>, value: u64) -> usize {
     let enc_len = varint_length(value);
     let old_len = dst.len();
-    unsafe {
-        dst.reserve(enc_len);
-        dst.set_len(old_len + enc_len);
-    }
+    dst.resize(old_len + enc_len, 0);
     encode_varint_64(&mut dst[old_len..], value)
 }
 
@@ -180,7 +168,7 @@ pub fn decode_varint_32(src: &[u8]) -> Result<(u32, usize)> {
 /// Decodes varint32 from the first `limit` bytes of `src`, and returns a tuple of which
 /// the first is the decoded value, and the second element is the number of bytes used to
 /// encode the result value.
-
+///
 /// Returns error if the first `limit` bytes of `src` doesn't contain a valid varint32.
 ///
 /// # Panics
@@ -253,28 +241,25 @@ pub fn varint_length(mut v: u64) -> usize {
     len
 }
 
-/// --------------------------------------------------------------------------------
-/// Encoding & Decoding which deal with LevelDB Slice type
-/// --------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------
+// Encoding & Decoding which deal with LevelDB slices
+// --------------------------------------------------------------------------------
 
 /// Encodes the slice `v` using length prefixed encoding, and appends the encoded value
 /// to `dst` .
-pub fn encode_length_prefixed_slice(dst: &mut Vec<u8>, v: &Slice) {
+pub fn encode_length_prefixed_slice(dst: &mut Vec<u8>, v: &[u8]) {
     let len = dst.len();
     let encoded_len = varint_length(v.len() as u64);
-    unsafe {
-        dst.reserve(encoded_len);
-        dst.set_len(len + encoded_len);
-    }
+    dst.resize(len + encoded_len, 0);
     encode_varint_32(&mut dst[len..], v.len() as u32);
-    dst.extend_from_slice(v.data());
+    dst.extend_from_slice(v);
 }
 
 /// Decodes the varint32 encoded u32 value from the `input`, and advances the slice past
 /// the decoded value.
 ///
 /// Returns a u32 value if the decoding is successful, otherwise returns error.
-pub fn decode_varint_32_slice(input: &mut Slice) -> Result<u32> {
+pub fn decode_varint_32_slice(input: &mut BorrowedSlice) -> Result<u32> {
     let (result, len) = decode_varint_32(input.data())?;
     input.skip(len);
     Ok(result)
@@ -284,7 +269,7 @@ pub fn decode_varint_32_slice(input: &mut Slice) -> Result<u32> {
 /// the decoded value.
 ///
 /// Returns a u64 value if the decoding is successful, otherwise returns error.
-pub fn decode_varint_64_slice(input: &mut Slice) -> Result<u64> {
+pub fn decode_varint_64_slice(input: &mut BorrowedSlice) -> Result<u64> {
     let (result, len) = decode_varint_64(input.data())?;
     input.skip(len);
     Ok(result)
@@ -294,10 +279,10 @@ pub fn decode_varint_64_slice(input: &mut Slice) -> Result<u64> {
 /// past the value.
 ///
 /// Returns a slice which contains the decoded value, or error if the input is malformed.
-pub fn decode_length_prefixed_slice(input: &mut Slice) -> Result<Slice> {
+pub fn decode_length_prefixed_slice<'a>(input: &mut BorrowedSlice<'a>) -> Result<BorrowedSlice<'a>> {
     let len = decode_varint_32_slice(input)? as usize;
     if input.len() >= len {
-        let result = Slice::new(input.raw_data(), len);
+        let result = BorrowedSlice::new(&input.data()[..len]);
         input.skip(len);
         return Ok(result);
     }
@@ -340,18 +325,18 @@ mod tests {
 
         for power in 0..64 {
             let v = 1 << power as u64;
-            encode_fixed_64(&mut data[(power * 3 + 0) * 8..], v - 1);
-            encode_fixed_64(&mut data[(power * 3 + 1) * 8..], v + 0);
+            encode_fixed_64(&mut data[power * 3 * 8..], v - 1);
+            encode_fixed_64(&mut data[(power * 3 + 1) * 8..], v);
             encode_fixed_64(&mut data[(power * 3 + 2) * 8..], v + 1);
         }
 
         for power in 0..64 {
             let v = 1 << power as u64;
-            let actual = decode_fixed_64(&data[(power * 3 + 0) * 8..]);
+            let actual = decode_fixed_64(&data[power * 3 * 8..]);
             assert_eq!(actual, v - 1);
 
             let actual = decode_fixed_64(&data[(power * 3 + 1) * 8..]);
-            assert_eq!(actual, v + 0);
+            assert_eq!(actual, v);
 
             let actual = decode_fixed_64(&data[(power * 3 + 2) * 8..]);
             assert_eq!(actual, v + 1);
@@ -398,7 +383,7 @@ mod tests {
             let v: u32 = (i / 32) << (i % 32);
             let limit = encode_varint_32(&mut data, v);
             assert!(limit <= 5);
-            let (actual, len) = decode_varint_32_limit(&mut data, limit).expect("OK");
+            let (actual, len) = decode_varint_32_limit(&data, limit).expect("OK");
             assert_eq!(actual, v);
             assert_eq!(len, limit);
         }
@@ -406,11 +391,7 @@ mod tests {
 
     #[test]
     fn varint_64() {
-        let mut values = Vec::new();
-        values.push(0);
-        values.push(100);
-        values.push(!0u64);
-        values.push(!0u64 - 1);
+        let mut values = vec![0, 100, !0u64, !0u64 - 1];
         for k in 0..64 {
             let power = 1u64 << k;
             values.push(power);
@@ -439,7 +420,7 @@ mod tests {
             let v: u64 = (i / 64) << (i % 64);
             let limit = encode_varint_64(&mut data, v);
             assert!(limit <= 10);
-            let (actual, len) = decode_varint_64_limit(&mut data, limit).expect("OK");
+            let (actual, len) = decode_varint_64_limit(&data, limit).expect("OK");
             assert_eq!(actual, v);
             assert_eq!(len, limit);
         }
@@ -495,7 +476,7 @@ mod tests {
     fn decode_varint_32_slice() {
         let mut v = vec![0; 4];
         let len = encode_varint_32(&mut v, 1000);
-        let mut s = Slice::from(&v[..]);
+        let mut s = BorrowedSlice::from(&v[..]);
         let v = super::decode_varint_32_slice(&mut s).expect("shouldn't be None");
         assert_eq!(v, 1000);
         assert_eq!(s.len(), 4 - len);
@@ -504,9 +485,9 @@ mod tests {
     #[test]
     fn decode_varint_64_slice() {
         let mut v = vec![0; 10];
-        let c = 1 << 60 as u64;
+        let c = 1_u64 << 60;
         let len = encode_varint_64(&mut v, c);
-        let mut s = Slice::from(&v[..]);
+        let mut s = BorrowedSlice::from(&v[..]);
         let v = super::decode_varint_64_slice(&mut s).expect("shouldn't be None");
         assert_eq!(v, c);
         assert_eq!(s.len(), 10 - len);
@@ -515,19 +496,38 @@ mod tests {
     #[test]
     fn prefix_length_slice() {
         let mut v: Vec<u8> = Vec::new();
-        encode_length_prefixed_slice(&mut v, &Slice::from(""));
-        encode_length_prefixed_slice(&mut v, &Slice::from("hello"));
-        encode_length_prefixed_slice(&mut v, &Slice::from("world"));
+        encode_length_prefixed_slice(&mut v, b"");
+        encode_length_prefixed_slice(&mut v, b"hello");
+        encode_length_prefixed_slice(&mut v, b"world");
 
-        let mut input = Slice::from(&v[..]);
+        let mut input = BorrowedSlice::from(&v[..]);
         let mut v;
         v = decode_length_prefixed_slice(&mut input).expect("shouldn't be None");
-        assert_eq!(v.as_str(), "");
+        assert_eq!(v.data(), b"");
         v = decode_length_prefixed_slice(&mut input).expect("shouldn't be None");
-        assert_eq!(v.as_str(), "hello");
+        assert_eq!(v.data(), b"hello");
         v = decode_length_prefixed_slice(&mut input).expect("shouldn't be None");
-        assert_eq!(v.as_str(), "world");
+        assert_eq!(v.data(), b"world");
 
         assert_eq!(input.len(), 0);
     }
+
+    #[test]
+    fn borrowed_slice_parsing() {
+        let mut v: Vec<u8> = Vec::new();
+        encode_varint_32_vec(&mut v, 300);
+        encode_varint_64_vec(&mut v, 1 << 40);
+        encode_length_prefixed_slice(&mut v, b"key");
+        v.extend_from_slice(b"tail");
+
+        let mut input = BorrowedSlice::from(&v);
+        assert_eq!(input.get_varint32().unwrap(), 300);
+        assert_eq!(input.get_varint64().unwrap(), 1 << 40);
+        assert_eq!(input.get_length_prefixed().unwrap().data(), b"key");
+        let (head, tail) = input.split_at(2);
+        assert_eq!((head.data(), tail.data()), (&b"ta"[..], &b"il"[..]));
+        input.truncate(3);
+        assert_eq!(input.data(), b"tai");
+        assert!(input.get_length_prefixed().is_err());
+    }
 }
\ No newline at end of file
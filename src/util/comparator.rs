@@ -0,0 +1,203 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::leveldb::comparator::Comparator;
+
+/// A comparator that uses lexicographic byte-wise ordering.
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    #[inline]
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering { a.cmp(b) }
+
+    fn name(&self) -> &'static str { "leveldb.BytewiseComparator" }
+
+    fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &[u8]) {
+        // Find length of common prefix
+        let min_length = start.len().min(limit.len());
+        let mut diff_index = 0;
+        while diff_index < min_length && start[diff_index] == limit[diff_index] {
+            diff_index += 1;
+        }
+
+        if diff_index >= min_length {
+            // Do not shorten if one string is a prefix of the other
+            return;
+        }
+
+        let diff_byte = start[diff_index];
+        if diff_byte < 0xff && diff_byte + 1 < limit[diff_index] {
+            start[diff_index] += 1;
+            start.truncate(diff_index + 1);
+            assert_eq!(self.compare(start, limit), Ordering::Less);
+        }
+    }
+
+    fn find_short_successor(&self, key: &mut Vec<u8>) {
+        // Find first character that can be incremented
+        for i in 0..key.len() {
+            if key[i] != 0xff {
+                key[i] += 1;
+                key.truncate(i + 1);
+                return;
+            }
+        }
+        // `key` is a run of 0xffs. Leave it alone.
+    }
+}
+
+/// Return a builtin comparator that uses lexicographic byte-wise ordering.
+pub fn bytewise_comparator() -> Arc<dyn Comparator> { Arc::new(BytewiseComparator) }
+
+/// A comparator that orders keys in decreasing lexicographic byte-wise order.
+pub struct ReverseBytewiseComparator;
+
+impl Comparator for ReverseBytewiseComparator {
+    #[inline]
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering { b.cmp(a) }
+
+    fn name(&self) -> &'static str { "hefesto.ReverseBytewiseComparator" }
+
+    // Index keys are left as they are; shortening them is only an optimization.
+    fn find_shortest_separator(&self, _start: &mut Vec<u8>, _limit: &[u8]) {}
+
+    fn find_short_successor(&self, _key: &mut Vec<u8>) {}
+}
+
+/// A comparator that orders keys lexicographically, ignoring the case of ASCII
+/// letters. Keys that differ only in case are the same key: writing "Foo" overwrites
+/// "foo".
+pub struct CaseInsensitiveComparator;
+
+impl Comparator for CaseInsensitiveComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.iter()
+            .map(u8::to_ascii_lowercase)
+            .cmp(b.iter().map(u8::to_ascii_lowercase))
+    }
+
+    fn name(&self) -> &'static str { "hefesto.CaseInsensitiveComparator" }
+
+    fn find_shortest_separator(&self, _start: &mut Vec<u8>, _limit: &[u8]) {}
+
+    fn find_short_successor(&self, _key: &mut Vec<u8>) {}
+}
+
+/// A comparator for keys that are unsigned 64-bit integers encoded as 8 big-endian
+/// bytes, ordering them numerically. Keys of any other length sort byte-wise, which
+/// for 8-byte keys agrees with the numeric order. Index keys are never shortened, so
+/// every key the comparator sees is a full integer.
+pub struct U64Comparator;
+
+impl Comparator for U64Comparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match (<[u8; 8]>::try_from(a), <[u8; 8]>::try_from(b)) {
+            (Ok(a), Ok(b)) => u64::from_be_bytes(a).cmp(&u64::from_be_bytes(b)),
+            _ => a.cmp(b),
+        }
+    }
+
+    fn name(&self) -> &'static str { "hefesto.U64Comparator" }
+
+    fn find_shortest_separator(&self, _start: &mut Vec<u8>, _limit: &[u8]) {}
+
+    fn find_short_successor(&self, _key: &mut Vec<u8>) {}
+}
+
+/// Return a builtin comparator that uses decreasing byte-wise ordering.
+pub fn reverse_bytewise_comparator() -> Arc<dyn Comparator> { Arc::new(ReverseBytewiseComparator) }
+
+/// Return a builtin comparator that ignores the case of ASCII letters.
+pub fn case_insensitive_comparator() -> Arc<dyn Comparator> { Arc::new(CaseInsensitiveComparator) }
+
+/// Return a builtin comparator for big-endian encoded u64 keys.
+pub fn u64_comparator() -> Arc<dyn Comparator> { Arc::new(U64Comparator) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortest_separator() {
+        let cmp = BytewiseComparator;
+        let mut start = b"abcdefg".to_vec();
+        cmp.find_shortest_separator(&mut start, b"abzzz");
+        assert_eq!(start, b"abd");
+
+        // One is a prefix of the other
+        let mut start = b"abc".to_vec();
+        cmp.find_shortest_separator(&mut start, b"abcdef");
+        assert_eq!(start, b"abc");
+
+        // Adjacent bytes cannot be shortened
+        let mut start = b"abc".to_vec();
+        cmp.find_shortest_separator(&mut start, b"abd");
+        assert_eq!(start, b"abc");
+    }
+
+    #[test]
+    fn short_successor() {
+        let cmp = BytewiseComparator;
+        let mut key = b"abc".to_vec();
+        cmp.find_short_successor(&mut key);
+        assert_eq!(key, b"b");
+
+        let mut key = vec![0xff, 0xff, 0x01];
+        cmp.find_short_successor(&mut key);
+        assert_eq!(key, vec![0xff, 0xff, 0x02]);
+
+        let mut key = vec![0xff, 0xff];
+        cmp.find_short_successor(&mut key);
+        assert_eq!(key, vec![0xff, 0xff]);
+    }
+
+    #[test]
+    fn builtin_comparators() {
+        let reverse = ReverseBytewiseComparator;
+        assert_eq!(reverse.compare(b"a", b"b"), Ordering::Greater);
+        assert_eq!(reverse.compare(b"ab", b"a"), Ordering::Less);
+        assert_eq!(reverse.compare(b"a", b"a"), Ordering::Equal);
+
+        let case = CaseInsensitiveComparator;
+        assert_eq!(case.compare(b"Foo", b"foo"), Ordering::Equal);
+        assert_eq!(case.compare(b"apple", b"Banana"), Ordering::Less);
+        assert_eq!(case.compare(b"FOOD", b"foo"), Ordering::Greater);
+        // Non-ASCII bytes are compared as they are.
+        assert_eq!(case.compare(&[0xc3, 0x89], &[0xc3, 0xa9]), Ordering::Less);
+
+        let ints = U64Comparator;
+        let key = |n: u64| n.to_be_bytes();
+        assert_eq!(ints.compare(&key(255), &key(256)), Ordering::Less);
+        assert_eq!(ints.compare(&key(u64::MAX), &key(1)), Ordering::Greater);
+        assert_eq!(ints.compare(&key(7), &key(7)), Ordering::Equal);
+        assert_eq!(ints.compare(b"", &key(0)), Ordering::Less);
+
+        for cmp in [reverse_bytewise_comparator(), case_insensitive_comparator(), u64_comparator()] {
+            let mut start = b"abcdefg".to_vec();
+            cmp.find_shortest_separator(&mut start, b"abzzz");
+            assert_eq!(start, b"abcdefg");
+            cmp.find_short_successor(&mut start);
+            assert_eq!(start, b"abcdefg");
+        }
+    }
+}
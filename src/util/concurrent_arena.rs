@@ -0,0 +1,259 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// An arena that several threads can allocate from at once, e.g. to build skiplist
+// nodes for concurrent memtable inserts.
+//
+// The arena is split into shards, and every thread allocates from the current block
+// of the shard it is assigned to. An allocation bumps the block's offset with a
+// compare-and-swap, so threads only contend when they share a shard, and never take a
+// lock unless the block is full. A full block is replaced under a mutex, which also
+// owns every block until the arena is dropped. Large allocations get a block of their
+// own, as in `Arena`.
+
+use std::{
+    cell::Cell,
+    mem::{self, MaybeUninit},
+    ptr,
+    sync::{
+        atomic::{AtomicI64, AtomicPtr, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+const SHARD_BLOCK_SIZE: usize = 4096;
+const MAX_SHARDS: usize = 64;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Shards are assigned to threads round-robin, on their first allocation.
+    static SHARD: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+fn shard_index() -> usize {
+    SHARD.with(|shard| match shard.get() {
+        Some(index) => index,
+        None => {
+            let index = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+            shard.set(Some(index));
+            index
+        }
+    })
+}
+
+struct Block {
+    // Points into `_data`, which is only kept to free the memory with the block.
+    base: *mut u8,
+    _data: Box<[MaybeUninit<u8>]>,
+    size: usize,
+    // Offset of the first free byte
+    used: AtomicUsize,
+}
+
+impl Block {
+    /// Bump-allocate `bytes` aligned to `align`, if the block has room for them.
+    fn alloc(&self, bytes: usize, align: usize) -> Option<*mut u8> {
+        let mut used = self.used.load(Ordering::Relaxed);
+        loop {
+            let start = (self.base as usize + used).next_multiple_of(align) - self.base as usize;
+            let end = start + bytes;
+            if end > self.size {
+                return None;
+            }
+            match self.used.compare_exchange_weak(used, end, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return Some(unsafe { self.base.add(start) }),
+                Err(actual) => used = actual,
+            }
+        }
+    }
+}
+
+pub struct ConcurrentArena {
+    // Current block of each shard, or null before its first allocation
+    shards: Vec<AtomicPtr<Block>>,
+    // Boxed so that the shards' pointers stay valid as the vector grows
+    #[allow(clippy::vec_box)]
+    blocks: Mutex<Vec<Box<Block>>>,
+    memory_usage: AtomicI64,
+    useful_bytes: AtomicI64,
+}
+
+// The arena exclusively owns the blocks its pointers refer to, and the blocks are only
+// carved up through atomic offsets.
+unsafe impl Send for ConcurrentArena {}
+unsafe impl Sync for ConcurrentArena {}
+
+impl ConcurrentArena {
+    /// Create an arena with a shard per available core.
+    pub fn new() -> Self {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_shards(cores)
+    }
+
+    /// Create an arena with `shards` shards, rounded up to a power of two.
+    pub fn with_shards(shards: usize) -> Self {
+        let shards = shards.clamp(1, MAX_SHARDS).next_power_of_two();
+        Self {
+            shards: (0..shards).map(|_| AtomicPtr::new(ptr::null_mut())).collect(),
+            blocks: Mutex::new(Vec::new()),
+            memory_usage: AtomicI64::new(0),
+            useful_bytes: AtomicI64::new(0),
+        }
+    }
+
+    /// Allocate a byte slice with length `bytes`.
+    /// Return a unique reference to the slice allocated, filled with zeros.
+    pub fn alloc(&self, bytes: usize) -> *mut u8 {
+        let result = self.alloc_uninit(bytes);
+        unsafe { ptr::write_bytes(result, 0, bytes) };
+        result
+    }
+
+    /// Allocate a byte slice with length `bytes` without initializing it. The caller
+    /// must write every byte before reading any of them.
+    pub fn alloc_uninit(&self, bytes: usize) -> *mut u8 { self.alloc_raw(bytes, 1) }
+
+    /// Allocate a byte slice with length `bytes` that is aligned to pointer address.
+    /// Return a unique reference to the slice allocated, filled with zeros.
+    pub fn alloc_aligned(&self, bytes: usize) -> *mut u8 {
+        let align = mem::size_of::<usize>().max(8);
+        let result = self.alloc_raw(bytes, align);
+        assert_eq!(result as usize & (align - 1), 0);
+        unsafe { ptr::write_bytes(result, 0, bytes) };
+        result
+    }
+
+    /// Return the memory usage for the memory pool, in number of bytes allocated.
+    pub fn memory_usage(&self) -> i64 { self.memory_usage.load(Ordering::Relaxed) }
+
+    /// Return the number of bytes handed out by `alloc*()` calls.
+    pub fn useful_bytes(&self) -> i64 { self.useful_bytes.load(Ordering::Relaxed) }
+
+    fn alloc_raw(&self, bytes: usize, align: usize) -> *mut u8 {
+        assert!(bytes > 0);
+        assert!(align.is_power_of_two());
+        self.useful_bytes.fetch_add(bytes as i64, Ordering::Relaxed);
+        if bytes > SHARD_BLOCK_SIZE / 4 {
+            // Allocate it separately to avoid wasting too much space in leftover bytes.
+            let mut blocks = self.blocks.lock().unwrap();
+            let block = self.new_block(&mut blocks, bytes + align - 1);
+            return unsafe { (*block).alloc(bytes, align).expect("block too small") };
+        }
+
+        let shard = &self.shards[shard_index() & (self.shards.len() - 1)];
+        loop {
+            let current = shard.load(Ordering::Acquire);
+            if !current.is_null() {
+                if let Some(result) = unsafe { (*current).alloc(bytes, align) } {
+                    return result;
+                }
+            }
+            // The remaining space in the current block is wasted. Another thread of
+            // the shard may have replaced the block already, in which case we retry.
+            let mut blocks = self.blocks.lock().unwrap();
+            if shard.load(Ordering::Acquire) == current {
+                let block = self.new_block(&mut blocks, SHARD_BLOCK_SIZE);
+                shard.store(block, Ordering::Release);
+            }
+        }
+    }
+
+    #[allow(clippy::vec_box)]
+    fn new_block(&self, blocks: &mut Vec<Box<Block>>, bytes: usize) -> *mut Block {
+        let mut data = Box::new_uninit_slice(bytes);
+        let mut block = Box::new(Block {
+            base: data.as_mut_ptr() as *mut u8,
+            _data: data,
+            size: bytes,
+            used: AtomicUsize::new(0),
+        });
+        let result = block.as_mut() as *mut Block;
+        blocks.push(block);
+        self.memory_usage.fetch_add(bytes as i64, Ordering::Relaxed);
+        result
+    }
+}
+
+impl Default for ConcurrentArena {
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn alloc() {
+        let arena = ConcurrentArena::with_shards(1);
+        assert_eq!(arena.memory_usage(), 0);
+        let p = arena.alloc(100);
+        assert_eq!(unsafe { std::slice::from_raw_parts(p, 100) }, [0u8; 100]);
+        assert_eq!(arena.memory_usage(), SHARD_BLOCK_SIZE as i64);
+
+        let q = arena.alloc_aligned(16);
+        assert_eq!(q as usize % mem::size_of::<usize>().max(8), 0);
+        assert!(q as usize >= p as usize + 100);
+
+        // Too large to share a block
+        let _ = arena.alloc_uninit(SHARD_BLOCK_SIZE);
+        assert_eq!(arena.memory_usage(), 2 * SHARD_BLOCK_SIZE as i64);
+
+        // Does not fit in the rest of the current block
+        let _ = arena.alloc(1000);
+        let _ = arena.alloc(1000);
+        let _ = arena.alloc(1000);
+        let _ = arena.alloc(1000);
+        assert_eq!(arena.memory_usage(), 3 * SHARD_BLOCK_SIZE as i64);
+        assert_eq!(arena.useful_bytes(), 100 + 16 + SHARD_BLOCK_SIZE as i64 + 4000);
+    }
+
+    #[test]
+    fn concurrent() {
+        let arena = Arc::new(ConcurrentArena::with_shards(2));
+        let per_thread = if cfg!(miri) { 50 } else { 2000 };
+        let threads: Vec<_> = (0..4u8)
+            .map(|t| {
+                let arena = arena.clone();
+                std::thread::spawn(move || {
+                    let mut allocated = Vec::new();
+                    for i in 0..per_thread {
+                        let bytes = 1 + (i * 7 + t as usize) % 300;
+                        let p = if i % 2 == 0 { arena.alloc_uninit(bytes) } else { arena.alloc_aligned(bytes) };
+                        unsafe { ptr::write_bytes(p, t, bytes) };
+                        allocated.push((p as usize, bytes));
+                    }
+                    // No other thread wrote over our allocations.
+                    for &(p, bytes) in allocated.iter() {
+                        let slice = unsafe { std::slice::from_raw_parts(p as *const u8, bytes) };
+                        assert!(slice.iter().all(|&b| b == t));
+                    }
+                    allocated.iter().map(|&(_, bytes)| bytes as i64).sum::<i64>()
+                })
+            })
+            .collect();
+        let total: i64 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(arena.useful_bytes(), total);
+        assert!(arena.memory_usage() >= total);
+    }
+}
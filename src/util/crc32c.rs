@@ -28,16 +28,25 @@ pub fn value(data: &[u8]) -> u32 {
     CASTAGNOLI.checksum(data)
 }
 
+/// Return the crc32c of concat(A, data) where `init_crc` is the crc32c of some string A.
+/// `extend()` is often used to maintain the crc32c of a stream of data.
+pub fn extend(init_crc: u32, data: &[u8]) -> u32 {
+    // The crate keeps its running state un-finalized and bit-reflected
+    let mut digest = CASTAGNOLI.digest_with_initial((init_crc ^ 0xffffffff).reverse_bits());
+    digest.update(data);
+    digest.finalize()
+}
+
 /// Return a masked representation of `crc`
 pub fn mask(crc: u32) -> u32 {
     // Rotate right by 15 bits and add a constant
-    ((crc >> 15) | (crc << 17)).wrapping_add(MASK_DELTA)
+    crc.rotate_right(15).wrapping_add(MASK_DELTA)
 }
 
 /// Return the crc whose masked representation is `masked_crc`.
 pub fn unmask(masked_crc: u32) -> u32 {
     let rot = masked_crc.wrapping_sub(MASK_DELTA);
-    (rot >> 17) | (rot << 15)
+    rot.rotate_left(15)
 }
 
 #[cfg(test)]
@@ -54,6 +63,12 @@ mod tests {
         assert_ne!(value("a".as_bytes()), value("foo".as_bytes()));
     }
 
+    #[test]
+    pub fn extend() {
+        assert_eq!(value(b"hello world"), super::extend(value(b"hello "), b"world"));
+        assert_eq!(value(b"hello"), super::extend(value(b""), b"hello"));
+    }
+
     #[test]
     pub fn mask() {
         let crc = value("foo".as_bytes());
@@ -0,0 +1,241 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Generators of skewed key indexes, to model realistic access patterns in benchmarks
+// and stress tests: a few keys are much more popular than the rest. The zipfian
+// generator follows "Quickly Generating Billion-Record Synthetic Databases" (Gray et
+// al., SIGMOD 1994), as used by YCSB.
+
+use std::cell::Cell;
+
+use crate::util::random::Random64;
+
+/// The skew of YCSB's zipfian workloads.
+pub const DEFAULT_ZIPFIAN_THETA: f64 = 0.99;
+
+/// Picks indexes of keys in `[0, n)` according to some distribution.
+pub trait KeyDistribution {
+    fn next(&self) -> u64;
+}
+
+fn zeta(from: u64, to: u64, theta: f64, initial: f64) -> f64 {
+    (from + 1..=to).fold(initial, |sum, i| sum + 1.0 / (i as f64).powf(theta))
+}
+
+/// Zipfian distribution over `[0, items)`: index `i` is picked with a probability
+/// proportional to `1 / (i + 1)^theta`, so 0 is the most popular index.
+pub struct Zipfian {
+    items: Cell<u64>,
+    theta: f64,
+    alpha: f64,
+    zeta2: f64,
+    zeta_n: Cell<f64>,
+    eta: Cell<f64>,
+    scrambled: bool,
+    rnd: Random64,
+}
+
+impl Zipfian {
+    /// REQUIRES: items > 0, 0 < theta < 1
+    pub fn new(items: u64, theta: f64, seed: u64) -> Self {
+        assert!(items > 0);
+        assert!(theta > 0.0 && theta < 1.0);
+        let zipfian = Self {
+            items: Cell::new(0),
+            theta,
+            alpha: 1.0 / (1.0 - theta),
+            zeta2: zeta(0, 2, theta, 0.0),
+            zeta_n: Cell::new(0.0),
+            eta: Cell::new(0.0),
+            scrambled: false,
+            rnd: Random64::new(seed),
+        };
+        zipfian.set_items(items);
+        zipfian
+    }
+
+    /// A zipfian distribution whose popular indexes are spread over `[0, items)`
+    /// instead of being the smallest ones, so that they do not cluster in the key space.
+    pub fn scrambled(items: u64, theta: f64, seed: u64) -> Self {
+        Self {
+            scrambled: true,
+            ..Self::new(items, theta, seed)
+        }
+    }
+
+    /// Change the number of items. Growing is incremental, so a key space that grows
+    /// as keys are inserted is cheap to follow.
+    pub fn set_items(&self, items: u64) {
+        assert!(items > 0);
+        let old = self.items.get();
+        let zeta_n = match items >= old {
+            true => zeta(old, items, self.theta, self.zeta_n.get()),
+            false => zeta(0, items, self.theta, 0.0),
+        };
+        self.items.set(items);
+        self.zeta_n.set(zeta_n);
+        let n = items as f64;
+        self.eta.set((1.0 - (2.0 / n).powf(1.0 - self.theta)) / (1.0 - self.zeta2 / zeta_n));
+    }
+
+    fn next_unscrambled(&self) -> u64 {
+        let items = self.items.get();
+        let u = self.rnd.next_f64();
+        let uz = u * self.zeta_n.get();
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 1.min(items - 1);
+        }
+        let eta = self.eta.get();
+        let index = (items as f64 * (eta * u - eta + 1.0).powf(self.alpha)) as u64;
+        index.min(items - 1)
+    }
+}
+
+impl KeyDistribution for Zipfian {
+    fn next(&self) -> u64 {
+        let index = self.next_unscrambled();
+        match self.scrambled {
+            // FNV-1a over the bytes of the index
+            true => {
+                let hash = index
+                    .to_le_bytes()
+                    .iter()
+                    .fold(0xcbf29ce484222325u64, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+                hash % self.items.get()
+            }
+            false => index,
+        }
+    }
+}
+
+/// Favors the most recently inserted keys: index `items - 1` is the most popular, with
+/// a zipfian decline towards older ones. Call `set_items()` as keys are inserted.
+pub struct Latest {
+    zipfian: Zipfian,
+}
+
+impl Latest {
+    pub fn new(items: u64, seed: u64) -> Self {
+        Self {
+            zipfian: Zipfian::new(items, DEFAULT_ZIPFIAN_THETA, seed),
+        }
+    }
+
+    pub fn set_items(&self, items: u64) { self.zipfian.set_items(items) }
+}
+
+impl KeyDistribution for Latest {
+    fn next(&self) -> u64 { self.zipfian.items.get() - 1 - self.zipfian.next_unscrambled() }
+}
+
+/// Picks an index of the hot set, the first `hot_fraction` of `[0, items)`, for
+/// `hot_op_fraction` of the operations, and an index of the rest for the others, each
+/// uniformly.
+pub struct Hotspot {
+    items: u64,
+    hot_items: u64,
+    hot_op_fraction: f64,
+    rnd: Random64,
+}
+
+impl Hotspot {
+    /// REQUIRES: items > 0, both fractions within `[0, 1]`
+    pub fn new(items: u64, hot_fraction: f64, hot_op_fraction: f64, seed: u64) -> Self {
+        assert!(items > 0);
+        assert!((0.0..=1.0).contains(&hot_fraction) && (0.0..=1.0).contains(&hot_op_fraction));
+        Self {
+            items,
+            hot_items: ((items as f64 * hot_fraction) as u64).clamp(1, items),
+            hot_op_fraction,
+            rnd: Random64::new(seed),
+        }
+    }
+}
+
+impl KeyDistribution for Hotspot {
+    fn next(&self) -> u64 {
+        let cold_items = self.items - self.hot_items;
+        if cold_items == 0 || self.rnd.next_f64() < self.hot_op_fraction {
+            self.rnd.uniform(self.hot_items)
+        } else {
+            self.hot_items + self.rnd.uniform(cold_items)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(dist: &dyn KeyDistribution, items: u64, samples: usize) -> Vec<usize> {
+        let mut counts = vec![0; items as usize];
+        for _ in 0..samples {
+            let index = dist.next();
+            assert!(index < items);
+            counts[index as usize] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn zipfian() {
+        let counts = histogram(&Zipfian::new(1000, DEFAULT_ZIPFIAN_THETA, 301), 1000, 100_000);
+        assert!(counts[0] > counts[1] && counts[1] > counts[10] && counts[10] > counts[500]);
+        // The 1% most popular keys get a large share of the accesses.
+        assert!(counts[..10].iter().sum::<usize>() > 30_000);
+
+        // Scrambling moves the popular keys but keeps the skew.
+        let mut counts = histogram(&Zipfian::scrambled(1000, DEFAULT_ZIPFIAN_THETA, 301), 1000, 100_000);
+        assert!(counts[0] < 5_000);
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        assert!(counts[..10].iter().sum::<usize>() > 30_000);
+
+        // Growing the key space keeps the indexes within it.
+        let zipfian = Zipfian::new(10, 0.5, 1);
+        zipfian.set_items(100);
+        histogram(&zipfian, 100, 1000);
+        zipfian.set_items(5);
+        histogram(&zipfian, 5, 1000);
+    }
+
+    #[test]
+    fn latest() {
+        let latest = Latest::new(100, 7);
+        let counts = histogram(&latest, 100, 10_000);
+        assert!(counts[99] > counts[98] && counts[98] > counts[50]);
+        latest.set_items(200);
+        assert!(histogram(&latest, 200, 10_000)[199] > 1000);
+    }
+
+    #[test]
+    fn hotspot() {
+        let counts = histogram(&Hotspot::new(1000, 0.1, 0.9, 5), 1000, 100_000);
+        let hot: usize = counts[..100].iter().sum();
+        assert!((88_000..92_000).contains(&hot));
+        assert!(counts[100..].iter().all(|&c| c > 0));
+
+        // Everything is hot.
+        histogram(&Hotspot::new(10, 1.0, 0.5, 5), 10, 1000);
+    }
+}
@@ -0,0 +1,943 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::FileExt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, OnceLock,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::leveldb::{
+    env::{Env, FileLock, Job, Priority, RandomAccessFile, SequentialFile, WritableFile},
+    result::{Error, Result},
+};
+
+pub(crate) fn posix_error(err: io::Error, msg: &'static str) -> Error { Error::from(err).with_context(msg) }
+
+struct PosixSequentialFile {
+    file: File,
+}
+
+impl SequentialFile for PosixSequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            match self.file.read(buf) {
+                Ok(n) => return Ok(n),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(posix_error(e, "error reading sequential file")),
+            }
+        }
+    }
+
+    fn skip(&mut self, n: u64) -> Result<()> {
+        self.file
+            .seek(SeekFrom::Current(n as i64))
+            .map(|_| ())
+            .map_err(|e| posix_error(e, "error skipping in sequential file"))
+    }
+}
+
+struct PosixRandomAccessFile {
+    file: File,
+}
+
+impl RandomAccessFile for PosixRandomAccessFile {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.file.read_at(&mut buf[read..], offset + read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(posix_error(e, "error reading random access file")),
+            }
+        }
+        Ok(read)
+    }
+
+    fn prefetch(&self, offset: u64, len: usize) { fadvise::will_need(&self.file, offset, len); }
+}
+
+pub(crate) mod fallocate {
+    use std::fs::File;
+
+    /// Reserve the `len` bytes from `offset` on for `file` without changing its size.
+    /// Does nothing where `fallocate()` is not available.
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    pub fn keep_size(file: &File, offset: u64, len: u64) {
+        use std::{ffi::c_int, os::fd::AsRawFd};
+
+        const FALLOC_FL_KEEP_SIZE: c_int = 1;
+
+        extern "C" {
+            fn fallocate(fd: c_int, mode: c_int, offset: i64, len: i64) -> c_int;
+        }
+
+        // SAFETY: Allocating blocks for an open file descriptor reads or writes no
+        // memory of the process. Failures, e.g. on file systems without support, are
+        // harmless.
+        unsafe {
+            fallocate(file.as_raw_fd(), FALLOC_FL_KEEP_SIZE, offset as i64, len as i64);
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+    pub fn keep_size(_file: &File, _offset: u64, _len: u64) {}
+}
+
+pub(crate) mod range_sync {
+    use std::fs::File;
+
+    /// Start writing the `len` bytes from `offset` on of `file` back to disk without
+    /// waiting for them. Does nothing where `sync_file_range()` is not available.
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    pub fn start_writeback(file: &File, offset: u64, len: u64) {
+        use std::{
+            ffi::{c_int, c_uint},
+            os::fd::AsRawFd,
+        };
+
+        const SYNC_FILE_RANGE_WRITE: c_uint = 2;
+
+        extern "C" {
+            fn sync_file_range(fd: c_int, offset: i64, len: i64, flags: c_uint) -> c_int;
+        }
+
+        // SAFETY: Starting writeback for an open file descriptor reads or writes no
+        // memory of the process. Failures are harmless.
+        unsafe {
+            sync_file_range(file.as_raw_fd(), offset as i64, len as i64, SYNC_FILE_RANGE_WRITE);
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+    pub fn start_writeback(_file: &File, _offset: u64, _len: u64) {}
+}
+
+mod fadvise {
+    use std::fs::File;
+
+    /// Ask the kernel to start reading the `len` bytes from `offset` on into the page
+    /// cache. Does nothing where `posix_fadvise()` is not available.
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    pub fn will_need(file: &File, offset: u64, len: usize) {
+        use std::{ffi::c_int, os::fd::AsRawFd};
+
+        const POSIX_FADV_WILLNEED: c_int = 3;
+
+        extern "C" {
+            fn posix_fadvise(fd: c_int, offset: i64, len: i64, advice: c_int) -> c_int;
+        }
+
+        // SAFETY: The advice only concerns the page cache of an open file descriptor;
+        // it reads or writes no memory of the process. Failures are harmless.
+        unsafe {
+            posix_fadvise(file.as_raw_fd(), offset as i64, len as i64, POSIX_FADV_WILLNEED);
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+    pub fn will_need(_file: &File, _offset: u64, _len: usize) {}
+}
+
+/// Default for the number of read-only files that may be mapped at once. Mapping is
+/// only used where the address space is large enough to map many tables.
+const DEFAULT_MMAP_LIMIT: usize = if cfg!(target_pointer_width = "64") { 1000 } else { 0 };
+
+/// Bounds the number of mapped files, since each one takes address space and a
+/// kernel mapping, which are more limited than file descriptors.
+struct Limiter {
+    max_acquires: AtomicUsize,
+    acquired: AtomicUsize,
+}
+
+impl Limiter {
+    /// If another resource is available, acquire it and return true. Else return
+    /// false.
+    fn acquire(&self) -> bool {
+        let max = self.max_acquires.load(Ordering::Relaxed);
+        self.acquired
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| (n < max).then_some(n + 1))
+            .is_ok()
+    }
+
+    /// Release a resource acquired by a previous call to `acquire()` that returned
+    /// true.
+    fn release(&self) { self.acquired.fetch_sub(1, Ordering::Relaxed); }
+}
+
+fn mmap_limiter() -> &'static Limiter {
+    static MMAP_LIMITER: Limiter = Limiter {
+        max_acquires: AtomicUsize::new(DEFAULT_MMAP_LIMIT),
+        acquired: AtomicUsize::new(0),
+    };
+    &MMAP_LIMITER
+}
+
+/// Set the number of files `PosixEnv::new_random_access_file()` may map into memory
+/// at once; files opened beyond the limit are read with `pread`. Zero disables
+/// mapping. Files that are already mapped stay mapped.
+pub fn set_mmap_limit(limit: usize) { mmap_limiter().max_acquires.store(limit, Ordering::Relaxed); }
+
+mod mmap {
+    use std::{
+        ffi::{c_int, c_void},
+        fs::File,
+        os::fd::AsRawFd,
+    };
+
+    const PROT_READ: c_int = 1;
+    const MAP_SHARED: c_int = 1;
+
+    extern "C" {
+        fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    /// A read-only mapping of a whole file.
+    pub struct Mapping {
+        base: *const u8,
+        len: usize,
+    }
+
+    // SAFETY: The mapping is read-only and owned by this value, so it may be shared
+    // and sent like a `&[u8]`.
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+
+    impl Mapping {
+        /// Map the first `len` bytes of `file`, which must not be empty. Returns `None`
+        /// if the file cannot be mapped.
+        pub fn new(file: &File, len: usize) -> Option<Mapping> {
+            assert!(len > 0);
+            // SAFETY: A fresh read-only shared mapping at an address of the kernel's
+            // choosing does not alias any memory owned by Rust code.
+            let base = unsafe { mmap(std::ptr::null_mut(), len, PROT_READ, MAP_SHARED, file.as_raw_fd(), 0) };
+            if base as isize == -1 {
+                return None;
+            }
+            Some(Mapping {
+                base: base as *const u8,
+                len,
+            })
+        }
+
+        pub fn as_slice(&self) -> &[u8] {
+            // SAFETY: `base` points to `len` readable bytes until `self` is dropped.
+            // The database never modifies or truncates a file it reads from.
+            unsafe { std::slice::from_raw_parts(self.base, self.len) }
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            // SAFETY: `base` and `len` describe a mapping created by `Mapping::new()`
+            // that is no longer borrowed.
+            unsafe {
+                munmap(self.base as *mut c_void, self.len);
+            }
+        }
+    }
+}
+
+/// A `RandomAccessFile` backed by a memory mapping of the whole file, which serves
+/// reads without a system call and exposes the contents through `mapped()`.
+struct PosixMmapReadableFile {
+    mapping: mmap::Mapping,
+}
+
+impl RandomAccessFile for PosixMmapReadableFile {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let data = self.mapping.as_slice();
+        let start = (offset as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn mapped(&self) -> Option<&[u8]> { Some(self.mapping.as_slice()) }
+}
+
+impl Drop for PosixMmapReadableFile {
+    fn drop(&mut self) { mmap_limiter().release(); }
+}
+
+/// Size of the buffer in which `PosixWritableFile` gathers small appends before
+/// writing them to the file.
+const WRITABLE_FILE_BUFFER_SIZE: usize = 65536;
+
+struct PosixWritableFile {
+    file: Option<File>,
+    // Data appended but not yet written to the file.
+    buf: Vec<u8>,
+    // The directory holding the file if it is a MANIFEST, which must be synced along
+    // with the file.
+    manifest_dir: Option<String>,
+    // Bytes appended so far, including those still in the buffer.
+    size: u64,
+    // Space is reserved in blocks of this size if it is not zero.
+    preallocation_block_size: u64,
+    // Bytes of the file reserved so far.
+    preallocated: u64,
+    // Bytes handed to the operating system so far.
+    written: u64,
+    // Writeback of the written bytes is started every this many bytes if it is not
+    // zero.
+    bytes_per_sync: u64,
+    // Bytes whose writeback was started so far.
+    range_synced: u64,
+}
+
+impl PosixWritableFile {
+    fn new(file: File, fname: &str) -> Self {
+        let (dirname, basename) = match fname.rfind('/') {
+            Some(i) => (&fname[..i], &fname[i + 1..]),
+            None => (".", fname),
+        };
+        Self {
+            file: Some(file),
+            buf: Vec::with_capacity(WRITABLE_FILE_BUFFER_SIZE),
+            manifest_dir: basename.starts_with("MANIFEST").then(|| dirname.to_string()),
+            size: 0,
+            preallocation_block_size: 0,
+            preallocated: 0,
+            written: 0,
+            bytes_per_sync: 0,
+            range_synced: 0,
+        }
+    }
+
+    // Start writing back the bytes written since the last range sync once there are
+    // `bytes_per_sync` of them, so that syncing or closing the file later does not have
+    // to flush all of it at once. Failures are ignored: a later sync still flushes.
+    fn range_sync(&mut self) {
+        if self.bytes_per_sync == 0 || self.written - self.range_synced < self.bytes_per_sync {
+            return;
+        }
+        if let Some(file) = self.file.as_ref() {
+            range_sync::start_writeback(file, self.range_synced, self.written - self.range_synced);
+        }
+        self.range_synced = self.written;
+    }
+
+    // Reserve space for the next `len` bytes, a whole number of blocks at a time.
+    // Failures are ignored: the writes still allocate what they need.
+    fn preallocate(&mut self, len: usize) {
+        self.size += len as u64;
+        let block_size = self.preallocation_block_size;
+        if block_size == 0 || self.size <= self.preallocated {
+            return;
+        }
+        let end = self.size.div_ceil(block_size) * block_size;
+        if let Some(file) = self.file.as_ref() {
+            fallocate::keep_size(file, self.preallocated, end - self.preallocated);
+        }
+        self.preallocated = end;
+    }
+
+    fn file(&mut self) -> Result<&mut File> {
+        match self.file.as_mut() {
+            Some(f) => Ok(f),
+            None => LEVELDB_ERR!(IOError, "file already closed"),
+        }
+    }
+
+    fn write_unbuffered(&mut self, data: &[u8]) -> Result<()> {
+        self.file()?
+            .write_all(data)
+            .map_err(|e| posix_error(e, "error appending to file"))?;
+        self.written += data.len() as u64;
+        self.range_sync();
+        Ok(())
+    }
+
+    fn flush_buffer(&mut self) -> Result<()> {
+        let s = match self.file.as_mut() {
+            Some(f) => f
+                .write_all(&self.buf)
+                .map_err(|e| posix_error(e, "error appending to file")),
+            None => LEVELDB_ERR!(IOError, "file already closed"),
+        };
+        if s.is_ok() {
+            self.written += self.buf.len() as u64;
+            self.range_sync();
+        }
+        self.buf.clear();
+        s
+    }
+}
+
+impl WritableFile for PosixWritableFile {
+    fn append(&mut self, mut data: &[u8]) -> Result<()> {
+        self.preallocate(data.len());
+        // Fit as much as possible into the buffer.
+        let copy = data.len().min(WRITABLE_FILE_BUFFER_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..copy]);
+        data = &data[copy..];
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        // Can't fit the rest of the data in the buffer, so a write is needed.
+        self.flush_buffer()?;
+
+        // Small writes go to the buffer, large writes are written directly.
+        if data.len() < WRITABLE_FILE_BUFFER_SIZE {
+            self.buf.extend_from_slice(data);
+            return Ok(());
+        }
+        self.write_unbuffered(data)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let mut s = if self.file.is_some() { self.flush_buffer() } else { Ok(()) };
+        if s.is_ok() && self.preallocated > self.size {
+            // Release the space reserved past the end of the file.
+            if let Some(file) = self.file.as_ref() {
+                s = file.set_len(self.size).map_err(|e| posix_error(e, "error truncating file"));
+            }
+        }
+        self.file = None;
+        s
+    }
+
+    fn flush(&mut self) -> Result<()> { self.flush_buffer() }
+
+    fn sync(&mut self) -> Result<()> {
+        // Ensure the new file's directory entry is on disk before the manifest it
+        // names is relied upon.
+        if let Some(dir) = self.manifest_dir.as_ref() {
+            File::open(dir)
+                .and_then(|d| d.sync_all())
+                .map_err(|e| posix_error(e, "error syncing directory"))?;
+        }
+        self.flush_buffer()?;
+        self.file()?
+            .sync_data()
+            .map_err(|e| posix_error(e, "error syncing file"))
+    }
+
+    fn set_preallocation_block_size(&mut self, size: usize) { self.preallocation_block_size = size as u64; }
+
+    fn set_bytes_per_sync(&mut self, bytes: usize) { self.bytes_per_sync = bytes as u64; }
+}
+
+impl Drop for PosixWritableFile {
+    fn drop(&mut self) { let _ = self.close(); }
+}
+
+/// The set of files locked by this process. File locks are only meant to exclude
+/// other processes, so a second lock of the same file from within this process is
+/// rejected here rather than left to the semantics of the underlying lock.
+fn locked_files() -> &'static Mutex<HashSet<String>> {
+    static LOCKED_FILES: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    LOCKED_FILES.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+/// An advisory lock on an open file, released when dropped.
+struct PosixFileLock {
+    file: File,
+    fname: String,
+}
+
+impl FileLock for PosixFileLock {}
+
+impl Drop for PosixFileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+        locked_files().lock().unwrap().remove(&self.fname);
+    }
+}
+
+/// A pool of threads running the jobs of one queue. Workers are started when the
+/// first job is scheduled.
+struct ThreadPool {
+    name: &'static str,
+    state: Mutex<PoolState>,
+    work_available: Condvar,
+}
+
+struct PoolState {
+    queue: VecDeque<Job>,
+    // Number of running worker threads and the number there should be.
+    threads: usize,
+    target_threads: usize,
+}
+
+impl ThreadPool {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            state: Mutex::new(PoolState {
+                queue: VecDeque::new(),
+                threads: 0,
+                target_threads: 1,
+            }),
+            work_available: Condvar::new(),
+        }
+    }
+
+    fn schedule(&'static self, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.push_back(job);
+        self.maybe_start_workers(&mut state);
+        self.work_available.notify_one();
+    }
+
+    fn set_threads(&'static self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.target_threads = n.max(1);
+        self.maybe_start_workers(&mut state);
+        // Wake idle workers so that surplus ones exit.
+        self.work_available.notify_all();
+    }
+
+    fn maybe_start_workers(&'static self, state: &mut PoolState) {
+        while state.threads < state.target_threads && !state.queue.is_empty() {
+            let started = thread::Builder::new()
+                .name(self.name.to_string())
+                .spawn(move || self.work());
+            if started.is_err() {
+                // Run with the threads we have; there is at least one unless none could
+                // ever be started.
+                break;
+            }
+            state.threads += 1;
+        }
+    }
+
+    fn work(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.threads > state.target_threads {
+                break;
+            }
+            match state.queue.pop_front() {
+                Some(job) => {
+                    drop(state);
+                    // A panicking job must not take the worker down with it.
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                    state = self.state.lock().unwrap();
+                }
+                None => state = self.work_available.wait(state).unwrap(),
+            }
+        }
+        state.threads -= 1;
+    }
+}
+
+fn thread_pool(priority: Priority) -> &'static ThreadPool {
+    static HIGH: ThreadPool = ThreadPool::new("hefesto-bg-high");
+    static LOW: ThreadPool = ThreadPool::new("hefesto-bg-low");
+    match priority {
+        Priority::High => &HIGH,
+        Priority::Low => &LOW,
+    }
+}
+
+/// The default `Env` that accesses the local filesystem through `std::fs`.
+pub struct PosixEnv;
+
+impl Env for PosixEnv {
+    fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn SequentialFile>> {
+        let file = File::open(fname).map_err(|e| posix_error(e, "error opening file for reading").with_detail(fname))?;
+        Ok(Box::new(PosixSequentialFile { file }))
+    }
+
+    fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn RandomAccessFile>> {
+        let file = File::open(fname).map_err(|e| posix_error(e, "error opening file for reading").with_detail(fname))?;
+        if !mmap_limiter().acquire() {
+            return Ok(Box::new(PosixRandomAccessFile { file }));
+        }
+        // Empty files cannot be mapped. The mapping outlives the file descriptor.
+        let size = file.metadata().map(|m| m.len() as usize).unwrap_or(0);
+        let mapping = if size > 0 { mmap::Mapping::new(&file, size) } else { None };
+        match mapping {
+            Some(mapping) => Ok(Box::new(PosixMmapReadableFile { mapping })),
+            None => {
+                mmap_limiter().release();
+                Ok(Box::new(PosixRandomAccessFile { file }))
+            }
+        }
+    }
+
+    fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(fname)
+            .map_err(|e| posix_error(e, "error opening file for writing").with_detail(fname))?;
+        Ok(Box::new(PosixWritableFile::new(file, fname)))
+    }
+
+    fn file_exists(&self, fname: &str) -> bool { fs::metadata(fname).is_ok() }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>> {
+        let entries = fs::read_dir(dir).map_err(|e| posix_error(e, "error listing directory").with_detail(dir))?;
+        let mut result = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| posix_error(e, "error listing directory").with_detail(dir))?;
+            result.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        Ok(result)
+    }
+
+    fn remove_file(&self, fname: &str) -> Result<()> {
+        fs::remove_file(fname).map_err(|e| posix_error(e, "error removing file").with_detail(fname))
+    }
+
+    fn create_dir(&self, dirname: &str) -> Result<()> {
+        fs::create_dir(dirname).map_err(|e| posix_error(e, "error creating directory").with_detail(dirname))
+    }
+
+    fn remove_dir(&self, dirname: &str) -> Result<()> {
+        fs::remove_dir(dirname).map_err(|e| posix_error(e, "error removing directory").with_detail(dirname))
+    }
+
+    fn get_file_size(&self, fname: &str) -> Result<u64> {
+        fs::metadata(fname)
+            .map(|m| m.len())
+            .map_err(|e| posix_error(e, "error getting file size").with_detail(fname))
+    }
+
+    fn rename_file(&self, src: &str, target: &str) -> Result<()> {
+        fs::rename(src, target).map_err(|e| posix_error(e, "error renaming file").with_detail(src))
+    }
+
+    fn link_file(&self, src: &str, target: &str) -> Result<()> {
+        fs::hard_link(src, target).map_err(|e| posix_error(e, "error linking file").with_detail(src))
+    }
+
+    fn lock_file(&self, fname: &str) -> Result<Box<dyn FileLock>> {
+        let mut locked = locked_files().lock().unwrap();
+        if locked.contains(fname) {
+            return LEVELDB_ERR!(IOError, "lock already held by this process");
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(fname)
+            .map_err(|e| posix_error(e, "error opening lock file").with_detail(fname))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return LEVELDB_ERR!(IOError, "lock already held by another process"),
+            Err(TryLockError::Error(e)) => return Err(posix_error(e, "error locking file").with_detail(fname)),
+        }
+        locked.insert(fname.to_string());
+        Ok(Box::new(PosixFileLock {
+            file,
+            fname: fname.to_string(),
+        }))
+    }
+
+    fn schedule(&self, job: Job, priority: Priority) { thread_pool(priority).schedule(job) }
+
+    fn set_background_threads(&self, n: usize, priority: Priority) { thread_pool(priority).set_threads(n) }
+
+    fn now_micros(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// Return the process-wide `PosixEnv` instance.
+pub fn default_env() -> Arc<dyn Env> {
+    static DEFAULT_ENV: OnceLock<Arc<dyn Env>> = OnceLock::new();
+    DEFAULT_ENV.get_or_init(|| Arc::new(PosixEnv)).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::leveldb::env::{read_file_to_string, write_string_to_file};
+    use crate::util::testutil::tmp_dir;
+    use std::{
+        sync::{mpsc, Barrier},
+        time::Duration,
+    };
+
+    #[test]
+    fn io_errors() {
+        let env = default_env();
+        let fname = format!("{}/missing", tmp_dir("env_io_errors"));
+        let e = env.new_sequential_file(&fname).err().expect("missing file");
+        assert!(e.is_not_found());
+        assert_eq!(e.detail(), Some(&fname[..]));
+        assert!(e.msg().starts_with("error opening file for reading: "));
+        let source = std::error::Error::source(&e).expect("io error");
+        assert_eq!(source.downcast_ref::<io::Error>().unwrap().kind(), io::ErrorKind::NotFound);
+
+        use crate::leveldb::result::ResultExt;
+        let e = File::open(&fname).with_context(|| format!("reading {}", fname)).unwrap_err();
+        assert!(e.is_not_found());
+        assert!(e.to_string().starts_with(&format!("LevelDB NotFoundError: reading {}: ", fname)));
+        assert_eq!(Error::corruption("bad block").with_context("offset 42").msg(), "offset 42: bad block");
+    }
+
+    #[test]
+    fn preallocation() {
+        use std::os::unix::fs::MetadataExt;
+
+        let env = default_env();
+        let fname = format!("{}/f", tmp_dir("env_preallocation"));
+        let allocated = || fs::metadata(&fname).unwrap().blocks() * 512;
+        let mut file = env.new_writable_file(&fname).expect("create");
+        file.set_preallocation_block_size(1 << 20);
+        for _ in 0..100 {
+            file.append(&[b'x'; 1000]).expect("append");
+        }
+        file.flush().expect("flush");
+        // The reserved space does not count towards the size of the file.
+        assert_eq!(env.get_file_size(&fname).expect("size"), 100_000);
+        if cfg!(target_os = "linux") {
+            assert!(allocated() >= 1 << 20, "{}", allocated());
+        }
+
+        file.close().expect("close");
+        assert_eq!(env.get_file_size(&fname).expect("size"), 100_000);
+        assert!(allocated() < 1 << 20, "{}", allocated());
+        assert_eq!(read_file_to_string(env.as_ref(), &fname).unwrap(), vec![b'x'; 100_000]);
+    }
+
+    #[test]
+    fn bytes_per_sync() {
+        let fname = format!("{}/f", tmp_dir("env_bytes_per_sync"));
+        let mut file = PosixWritableFile::new(File::create(&fname).unwrap(), &fname);
+        file.set_bytes_per_sync(100_000);
+        for _ in 0..250 {
+            file.append(&[b'x'; 1000]).expect("append");
+        }
+        // Writeback starts as whole buffers reach the file.
+        assert_eq!(file.written, 3 * WRITABLE_FILE_BUFFER_SIZE as u64);
+        assert_eq!(file.range_synced, 2 * WRITABLE_FILE_BUFFER_SIZE as u64);
+        file.flush().expect("flush");
+        assert_eq!(file.range_synced, 250_000);
+        file.append(&vec![b'x'; 150_000]).expect("append");
+        assert_eq!(file.range_synced, 400_000);
+        file.close().expect("close");
+        assert_eq!(read_file_to_string(&PosixEnv, &fname).unwrap(), vec![b'x'; 400_000]);
+    }
+
+    #[test]
+    fn writable_file_buffering() {
+        let env = default_env();
+        let fname = format!("{}/f", tmp_dir("env_writable_buffer"));
+        let mut file = env.new_writable_file(&fname).expect("create");
+
+        // Small appends stay in the buffer until flushed.
+        for _ in 0..100 {
+            file.append(b"0123456789").expect("append");
+        }
+        assert_eq!(env.get_file_size(&fname).expect("size"), 0);
+        file.flush().expect("flush");
+        assert_eq!(env.get_file_size(&fname).expect("size"), 1000);
+
+        // An append that overflows the buffer writes out one full buffer and keeps the
+        // remainder.
+        let large = vec![b'x'; WRITABLE_FILE_BUFFER_SIZE + 1];
+        file.append(b"y").expect("append");
+        file.append(&large).expect("append");
+        assert_eq!(env.get_file_size(&fname).expect("size"), 1000 + WRITABLE_FILE_BUFFER_SIZE as u64);
+        file.append(b"z").expect("append");
+        file.sync().expect("sync");
+        assert_eq!(env.get_file_size(&fname).expect("size"), 1003 + WRITABLE_FILE_BUFFER_SIZE as u64);
+
+        // What does not fit in the buffer after filling it is written directly.
+        file.append(&vec![b'x'; 3 * WRITABLE_FILE_BUFFER_SIZE]).expect("append");
+        assert_eq!(env.get_file_size(&fname).expect("size"), 1003 + 4 * WRITABLE_FILE_BUFFER_SIZE as u64);
+
+        // Dropping the file flushes what is left.
+        file.append(b"tail").expect("append");
+        drop(file);
+        let contents = read_file_to_string(env.as_ref(), &fname).expect("read");
+        assert_eq!(contents.len(), 1007 + 4 * WRITABLE_FILE_BUFFER_SIZE);
+        assert!(contents.ends_with(b"xtail"));
+
+        let mut file = env.new_writable_file(&fname).expect("create");
+        file.close().expect("close");
+        assert!(file.append(&large).is_err());
+        assert!(file.sync().is_err());
+    }
+
+    #[test]
+    fn mmap_readable_file() {
+        let env = default_env();
+        let dir = tmp_dir("env_mmap");
+        let fname = format!("{}/000001.ldb", dir);
+        write_string_to_file(env.as_ref(), b"hello world", &fname).expect("write");
+
+        let file = env.new_random_access_file(&fname).expect("open");
+        if cfg!(target_pointer_width = "64") {
+            assert_eq!(file.mapped(), Some(&b"hello world"[..]));
+        }
+        let mut buf = [0u8; 5];
+        assert_eq!(file.read(6, &mut buf).expect("read_at"), 5);
+        assert_eq!(&buf, b"world");
+        assert_eq!(file.read(9, &mut buf).expect("read_at"), 2);
+        assert_eq!(file.read(100, &mut buf).expect("read_at"), 0);
+
+        // Empty files are never mapped.
+        let empty = format!("{}/000002.ldb", dir);
+        write_string_to_file(env.as_ref(), b"", &empty).expect("write");
+        let file = env.new_random_access_file(&empty).expect("open");
+        assert!(file.mapped().is_none());
+        assert_eq!(file.read(0, &mut buf).expect("read_at"), 0);
+    }
+
+    #[test]
+    fn limiter() {
+        let limiter = Limiter {
+            max_acquires: AtomicUsize::new(2),
+            acquired: AtomicUsize::new(0),
+        };
+        assert!(limiter.acquire());
+        assert!(limiter.acquire());
+        assert!(!limiter.acquire());
+        limiter.release();
+        assert!(limiter.acquire());
+        limiter.max_acquires.store(0, Ordering::Relaxed);
+        limiter.release();
+        limiter.release();
+        assert!(!limiter.acquire());
+    }
+
+    #[test]
+    fn thread_pools() {
+        let env = default_env();
+        let (started_tx, started) = mpsc::channel();
+        let (release_tx, release) = mpsc::channel::<()>();
+        let release = Arc::new(Mutex::new(release));
+
+        // Jobs of the high priority pool run while the low priority one is busy.
+        let (tx, rx) = (started_tx.clone(), release.clone());
+        env.schedule(
+            Box::new(move || {
+                tx.send("low").unwrap();
+                let _ = rx.lock().unwrap().recv();
+            }),
+            Priority::Low,
+        );
+        let tx = started_tx.clone();
+        env.schedule(Box::new(move || tx.send("high").unwrap()), Priority::High);
+        let mut seen = vec![
+            started.recv_timeout(Duration::from_secs(10)).unwrap(),
+            started.recv_timeout(Duration::from_secs(10)).unwrap(),
+        ];
+        seen.sort();
+        assert_eq!(seen, vec!["high", "low"]);
+        release_tx.send(()).unwrap();
+
+        // With more threads, low priority jobs run concurrently.
+        let waiting = Arc::new(Barrier::new(3));
+        env.set_background_threads(3, Priority::Low);
+        for _ in 0..3 {
+            let (tx, waiting) = (started_tx.clone(), waiting.clone());
+            env.schedule(
+                Box::new(move || {
+                    waiting.wait();
+                    tx.send("done").unwrap();
+                }),
+                Priority::Low,
+            );
+        }
+        for _ in 0..3 {
+            assert_eq!(started.recv_timeout(Duration::from_secs(10)).unwrap(), "done");
+        }
+
+        // A panicking job does not stop the pool.
+        env.set_background_threads(1, Priority::Low);
+        env.schedule(Box::new(|| panic!("job failed")), Priority::Low);
+        let tx = started_tx.clone();
+        env.schedule(Box::new(move || tx.send("after panic").unwrap()), Priority::Low);
+        assert_eq!(started.recv_timeout(Duration::from_secs(10)).unwrap(), "after panic");
+    }
+
+    #[test]
+    fn read_write() {
+        let env = default_env();
+        let dir = tmp_dir("env_read_write");
+        let fname = format!("{}/f", dir);
+
+        write_string_to_file(env.as_ref(), b"hello world", &fname).expect("write");
+        assert!(env.file_exists(&fname));
+        assert_eq!(env.get_file_size(&fname).expect("size"), 11);
+        assert_eq!(read_file_to_string(env.as_ref(), &fname).expect("read"), b"hello world");
+
+        let file = env.new_random_access_file(&fname).expect("open");
+        let mut buf = [0u8; 5];
+        assert_eq!(file.read(6, &mut buf).expect("read_at"), 5);
+        assert_eq!(&buf, b"world");
+        assert_eq!(file.read(9, &mut buf).expect("read_at"), 2);
+
+        let mut seq = env.new_sequential_file(&fname).expect("open");
+        seq.skip(6).expect("skip");
+        let mut buf = [0u8; 16];
+        assert_eq!(seq.read(&mut buf).expect("read"), 5);
+        assert_eq!(&buf[..5], b"world");
+
+        assert_eq!(env.get_children(&dir).expect("children"), vec!["f".to_string()]);
+        env.rename_file(&fname, &format!("{}/g", dir)).expect("rename");
+        assert!(!env.file_exists(&fname));
+        env.remove_file(&format!("{}/g", dir)).expect("remove");
+    }
+
+    #[test]
+    fn lock_file() {
+        let env = default_env();
+        let dir = tmp_dir("env_lock_file");
+        let fname = format!("{}/LOCK", dir);
+
+        let lock = env.lock_file(&fname).expect("lock");
+        assert!(env.file_exists(&fname));
+        let err = env.lock_file(&fname).err().unwrap();
+        assert!(err.is_io_error());
+
+        // A separate open file description is excluded as another process would be.
+        let other = OpenOptions::new().write(true).open(&fname).unwrap();
+        assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+
+        env.unlock_file(lock).expect("unlock");
+        let lock = env.lock_file(&fname).expect("relock");
+        drop(lock);
+        other.try_lock().expect("lock released on drop");
+    }
+
+    #[test]
+    fn not_found() {
+        let env = default_env();
+        let dir = tmp_dir("env_not_found");
+        let err = env.new_sequential_file(&format!("{}/missing", dir)).err().unwrap();
+        assert!(err.is_not_found());
+    }
+}
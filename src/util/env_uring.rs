@@ -0,0 +1,796 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+// An Env that reads tables and appends to write-ahead logs through io_uring, using the
+// system calls directly. Random reads go through a ring per thread whose registered
+// buffer takes the data of block-sized reads, and each log file has its own ring with
+// its append buffer registered, so that a sync submits the write and the fdatasync()
+// together. Everything else is done by `PosixEnv`.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_long, c_uint, c_void},
+    fs::{File, OpenOptions},
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::fs::FileExt,
+    },
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::{
+    db::filename::{parse_file_name, FileType},
+    leveldb::{
+        env::{Env, FileLock, Job, Priority, RandomAccessFile, SequentialFile, WritableFile},
+        result::{Error, Result},
+    },
+    util::env_posix::{fallocate, posix_error, range_sync, PosixEnv},
+};
+
+/// Number of submission queue entries of each ring.
+const RING_ENTRIES: u32 = 64;
+
+/// Size of the registered buffer of the ring used for reads. Reads up to this size,
+/// which covers table blocks, land in it and are copied out.
+const READ_BUFFER_SIZE: usize = 256 << 10;
+
+/// Size of the registered append buffer of a log file.
+const WRITE_BUFFER_SIZE: usize = 64 << 10;
+
+mod sys {
+    use std::ffi::{c_int, c_long, c_uint, c_void};
+
+    pub const SYS_IO_URING_SETUP: c_long = 425;
+    pub const SYS_IO_URING_ENTER: c_long = 426;
+    pub const SYS_IO_URING_REGISTER: c_long = 427;
+
+    pub const IORING_OFF_SQ_RING: i64 = 0;
+    pub const IORING_OFF_CQ_RING: i64 = 0x8000000;
+    pub const IORING_OFF_SQES: i64 = 0x10000000;
+
+    pub const IORING_ENTER_GETEVENTS: c_uint = 1;
+    pub const IORING_REGISTER_BUFFERS: c_uint = 0;
+
+    pub const IORING_OP_FSYNC: u8 = 3;
+    pub const IORING_OP_READ_FIXED: u8 = 4;
+    pub const IORING_OP_WRITE_FIXED: u8 = 5;
+    pub const IORING_OP_READ: u8 = 22;
+
+    pub const IORING_FSYNC_DATASYNC: u32 = 1;
+
+    pub const EAGAIN: i32 = 11;
+    pub const EBUSY: i32 = 16;
+    pub const ECANCELED: i32 = 125;
+    pub const IOSQE_IO_LINK: u8 = 1 << 2;
+
+    pub const PROT_READ: c_int = 1;
+    pub const PROT_WRITE: c_int = 2;
+    pub const MAP_SHARED: c_int = 1;
+    pub const MAP_POPULATE: c_int = 0x8000;
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct SqringOffsets {
+        pub head: u32,
+        pub tail: u32,
+        pub ring_mask: u32,
+        pub ring_entries: u32,
+        pub flags: u32,
+        pub dropped: u32,
+        pub array: u32,
+        pub resv1: u32,
+        pub user_addr: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct CqringOffsets {
+        pub head: u32,
+        pub tail: u32,
+        pub ring_mask: u32,
+        pub ring_entries: u32,
+        pub overflow: u32,
+        pub cqes: u32,
+        pub flags: u32,
+        pub resv1: u32,
+        pub user_addr: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct Params {
+        pub sq_entries: u32,
+        pub cq_entries: u32,
+        pub flags: u32,
+        pub sq_thread_cpu: u32,
+        pub sq_thread_idle: u32,
+        pub features: u32,
+        pub wq_fd: u32,
+        pub resv: [u32; 3],
+        pub sq_off: SqringOffsets,
+        pub cq_off: CqringOffsets,
+    }
+
+    /// A submission queue entry.
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    pub struct Sqe {
+        pub opcode: u8,
+        pub flags: u8,
+        pub ioprio: u16,
+        pub fd: i32,
+        pub off: u64,
+        pub addr: u64,
+        pub len: u32,
+        pub op_flags: u32,
+        pub user_data: u64,
+        pub buf_index: u16,
+        pub personality: u16,
+        pub splice_fd_in: i32,
+        pub addr3: u64,
+        pub pad: u64,
+    }
+
+    /// A completion queue entry.
+    #[repr(C)]
+    pub struct Cqe {
+        pub user_data: u64,
+        pub res: i32,
+        pub flags: u32,
+    }
+
+    #[repr(C)]
+    pub struct Iovec {
+        pub base: *mut c_void,
+        pub len: usize,
+    }
+
+    extern "C" {
+        pub fn syscall(num: c_long, ...) -> c_long;
+        pub fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+/// A shared memory region of a ring.
+struct Region {
+    base: *mut u8,
+    len: usize,
+}
+
+impl Region {
+    fn map(fd: RawFd, len: usize, offset: i64) -> io::Result<Region> {
+        // SAFETY: A fresh shared mapping of the ring at an address of the kernel's
+        // choosing does not alias any memory owned by Rust code.
+        let base = unsafe {
+            sys::mmap(
+                ptr::null_mut(),
+                len,
+                sys::PROT_READ | sys::PROT_WRITE,
+                sys::MAP_SHARED | sys::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if base as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Region {
+            base: base as *mut u8,
+            len,
+        })
+    }
+
+    /// The u32 at byte `offset`, which the kernel may access concurrently.
+    fn atomic(&self, offset: u32) -> &AtomicU32 {
+        // SAFETY: The offsets come from the kernel, which aligns its ring fields, and
+        // lie within the region.
+        unsafe { &*(self.base.add(offset as usize) as *const AtomicU32) }
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        // SAFETY: `base` and `len` describe a mapping created by `Region::map()` that
+        // is no longer borrowed.
+        unsafe {
+            sys::munmap(self.base as *mut c_void, self.len);
+        }
+    }
+}
+
+/// An io_uring instance with one registered buffer. Operations are submitted in
+/// batches, and each batch is waited for before the next one.
+struct Ring {
+    // Field order matters: the mappings go before the ring is closed.
+    sq_ring: Region,
+    cq_ring: Region,
+    sqes: Region,
+    fd: OwnedFd,
+    params: sys::Params,
+    buffer: Box<[u8]>,
+}
+
+// SAFETY: A ring is only used through `&mut Ring`, so never by two threads at once,
+// and the kernel does not care which thread submits to it.
+unsafe impl Send for Ring {}
+
+fn syscall_result(ret: c_long) -> io::Result<usize> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+fn completion_result(res: i32) -> io::Result<usize> {
+    if res < 0 {
+        Err(io::Error::from_raw_os_error(-res))
+    } else {
+        Ok(res as usize)
+    }
+}
+
+impl Ring {
+    fn new(buffer_size: usize) -> io::Result<Ring> {
+        let mut params = sys::Params::default();
+        // SAFETY: `params` is a valid io_uring_params the kernel fills in.
+        let fd = syscall_result(unsafe {
+            sys::syscall(sys::SYS_IO_URING_SETUP, RING_ENTRIES as c_uint, &mut params as *mut sys::Params)
+        })?;
+        // SAFETY: The kernel just returned this descriptor, which nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let raw = fd.as_raw_fd();
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<sys::Cqe>();
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<sys::Sqe>();
+        let sq_ring = Region::map(raw, sq_len, sys::IORING_OFF_SQ_RING)?;
+        let cq_ring = Region::map(raw, cq_len, sys::IORING_OFF_CQ_RING)?;
+        let sqes = Region::map(raw, sqes_len, sys::IORING_OFF_SQES)?;
+
+        let mut buffer = vec![0; buffer_size].into_boxed_slice();
+        let iovec = sys::Iovec {
+            base: buffer.as_mut_ptr() as *mut c_void,
+            len: buffer.len(),
+        };
+        // SAFETY: The buffer lives on the heap as long as the ring, so the kernel may
+        // keep using it until the ring is closed.
+        syscall_result(unsafe {
+            sys::syscall(
+                sys::SYS_IO_URING_REGISTER,
+                raw,
+                sys::IORING_REGISTER_BUFFERS,
+                &iovec as *const sys::Iovec,
+                1 as c_uint,
+            )
+        })?;
+        Ok(Ring {
+            sq_ring,
+            cq_ring,
+            sqes,
+            fd,
+            params,
+            buffer,
+        })
+    }
+
+    /// Submit `ops` together and wait for all of them. Returns the result of each
+    /// operation: the number of bytes transferred or the error.
+    ///
+    /// # Safety
+    ///
+    /// The buffers that `ops` read from or write to must stay valid until this returns.
+    unsafe fn submit_and_wait(&mut self, ops: &[sys::Sqe]) -> Vec<io::Result<usize>> {
+        let mut results: Vec<Option<io::Result<usize>>> = (0..ops.len()).map(|_| None).collect();
+        for (batch, chunk) in ops.chunks(self.params.sq_entries as usize).enumerate() {
+            let first = batch * self.params.sq_entries as usize;
+            let sq = &self.params.sq_off;
+            let mask = self.sq_ring.atomic(sq.ring_mask).load(Ordering::Relaxed);
+            let tail = self.sq_ring.atomic(sq.tail).load(Ordering::Relaxed);
+            let array = self.sq_ring.base.add(sq.array as usize) as *mut u32;
+            for (i, op) in chunk.iter().enumerate() {
+                let index = tail.wrapping_add(i as u32) & mask;
+                let mut op = *op;
+                op.user_data = (first + i) as u64;
+                ptr::write((self.sqes.base as *mut sys::Sqe).add(index as usize), op);
+                ptr::write(array.add(index as usize), index);
+            }
+            self.sq_ring
+                .atomic(sq.tail)
+                .store(tail.wrapping_add(chunk.len() as u32), Ordering::Release);
+
+            let mut to_submit = chunk.len();
+            let mut pending = chunk.len();
+            while pending > 0 {
+                match self.enter(to_submit, 1) {
+                    Ok(submitted) => to_submit -= submitted.min(to_submit),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    // The completion queue is full: reap before submitting more.
+                    Err(e) if e.raw_os_error() == Some(sys::EBUSY) => {}
+                    Err(e) => {
+                        // Withdraw the operations the kernel has not taken. Those it
+                        // took may still use the buffers, so wait for them before the
+                        // buffers are handed back to the caller.
+                        let tail = self.sq_ring.atomic(sq.tail).load(Ordering::Relaxed);
+                        self.sq_ring
+                            .atomic(sq.tail)
+                            .store(tail.wrapping_sub(to_submit as u32), Ordering::Release);
+                        self.drain(pending - to_submit, &mut results);
+                        return results
+                            .into_iter()
+                            .map(|r| r.unwrap_or_else(|| Err(io::Error::new(e.kind(), e.to_string()))))
+                            .collect();
+                    }
+                }
+                pending -= self.reap(&mut results);
+            }
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    // Wait for the `in_flight` operations the kernel has taken to complete.
+    fn drain(&self, mut in_flight: usize, results: &mut [Option<io::Result<usize>>]) {
+        while in_flight > 0 {
+            match self.enter(0, 1) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if matches!(e.raw_os_error(), Some(sys::EBUSY) | Some(sys::EAGAIN)) => {}
+                // Returning would let the kernel write to memory the caller owns again.
+                Err(_) => std::process::abort(),
+            }
+            in_flight -= self.reap(results);
+        }
+    }
+
+    // Submit `to_submit` queued operations and wait for at least `min_complete`
+    // completions. Returns the number of operations submitted.
+    fn enter(&self, to_submit: usize, min_complete: usize) -> io::Result<usize> {
+        #[cfg(test)]
+        let to_submit = match ENTER_FAULT.get() {
+            Some(0) => {
+                ENTER_FAULT.set(None);
+                return Err(io::ErrorKind::OutOfMemory.into());
+            }
+            Some(n) => {
+                ENTER_FAULT.set(Some(n - 1));
+                to_submit.min(1)
+            }
+            None => to_submit,
+        };
+        // SAFETY: The call only reads the rings this Ring maps.
+        syscall_result(unsafe {
+            sys::syscall(
+                sys::SYS_IO_URING_ENTER,
+                self.fd.as_raw_fd(),
+                to_submit as c_uint,
+                min_complete as c_uint,
+                sys::IORING_ENTER_GETEVENTS,
+                ptr::null::<c_void>(),
+                0usize,
+            )
+        })
+    }
+
+    // Move the available completions into `results`. Returns their number.
+    fn reap(&self, results: &mut [Option<io::Result<usize>>]) -> usize {
+        let cq = &self.params.cq_off;
+        let mask = self.cq_ring.atomic(cq.ring_mask).load(Ordering::Relaxed);
+        let mut head = self.cq_ring.atomic(cq.head).load(Ordering::Relaxed);
+        let tail = self.cq_ring.atomic(cq.tail).load(Ordering::Acquire);
+        let mut reaped = 0;
+        while head != tail {
+            // SAFETY: Entries between head and tail were completed by the kernel and
+            // are not reused before head moves past them.
+            let cqe = unsafe {
+                let cqes = self.cq_ring.base.add(cq.cqes as usize) as *const sys::Cqe;
+                &*cqes.add((head & mask) as usize)
+            };
+            if let Some(result) = results.get_mut(cqe.user_data as usize) {
+                *result = Some(completion_result(cqe.res));
+            }
+            head = head.wrapping_add(1);
+            reaped += 1;
+        }
+        self.cq_ring.atomic(cq.head).store(head, Ordering::Release);
+        reaped
+    }
+
+    /// Read up to `buf.len()` bytes at `offset` of `fd`, through the registered
+    /// buffer if `buf` fits in it.
+    fn read(&mut self, fd: RawFd, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let fixed = buf.len() <= self.buffer.len();
+        let op = sys::Sqe {
+            opcode: if fixed { sys::IORING_OP_READ_FIXED } else { sys::IORING_OP_READ },
+            fd,
+            off: offset,
+            addr: if fixed { self.buffer.as_mut_ptr() } else { buf.as_mut_ptr() } as u64,
+            len: buf.len() as u32,
+            ..Default::default()
+        };
+        // SAFETY: Both the registered buffer and `buf` outlive the call.
+        let n = unsafe { self.submit_and_wait(&[op]) }.remove(0)?;
+        if fixed {
+            buf[..n].copy_from_slice(&self.buffer[..n]);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    // If set, the next this many io_uring_enter() calls of this thread submit at most
+    // one operation each, and the one after them fails.
+    static ENTER_FAULT: std::cell::Cell<Option<usize>> = const { std::cell::Cell::new(None) };
+}
+
+thread_local! {
+    // The ring of this thread for reads, or `None` if io_uring is not available.
+    static READ_RING: RefCell<Option<Ring>> = RefCell::new(Ring::new(READ_BUFFER_SIZE).ok());
+}
+
+struct UringRandomAccessFile {
+    file: File,
+}
+
+impl RandomAccessFile for UringRandomAccessFile {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            let pos = offset + read as u64;
+            let result = READ_RING.with(|ring| match ring.borrow_mut().as_mut() {
+                Some(ring) => ring.read(self.file.as_raw_fd(), pos, &mut buf[read..]),
+                None => self.file.read_at(&mut buf[read..], pos),
+            });
+            match result {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(posix_error(e, "error reading random access file")),
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// A write-ahead log file. Appends gather in the registered buffer of the file's own
+/// ring, which is written with `IORING_OP_WRITE_FIXED` once full or flushed.
+struct UringLogFile {
+    file: Option<File>,
+    ring: Ring,
+    // Bytes of the registered buffer holding appended data.
+    buffered: usize,
+    // Bytes handed to the operating system so far.
+    written: u64,
+    preallocation_block_size: u64,
+    preallocated: u64,
+    bytes_per_sync: u64,
+    range_synced: u64,
+}
+
+impl UringLogFile {
+    fn file(&self) -> Result<&File> {
+        match self.file.as_ref() {
+            Some(f) => Ok(f),
+            None => LEVELDB_ERR!(IOError, "file already closed"),
+        }
+    }
+
+    fn write_op(&mut self, start: usize) -> sys::Sqe {
+        sys::Sqe {
+            opcode: sys::IORING_OP_WRITE_FIXED,
+            fd: self.file.as_ref().map_or(-1, |f| f.as_raw_fd()),
+            off: self.written + start as u64,
+            // SAFETY: `start` is within the buffer.
+            addr: unsafe { self.ring.buffer.as_mut_ptr().add(start) } as u64,
+            len: (self.buffered - start) as u32,
+            buf_index: 0,
+            ..Default::default()
+        }
+    }
+
+    // Write the buffered data and, if `sync` is set, fdatasync() the file, with both
+    // operations submitted together. A short write is finished on its own before the
+    // sync is retried.
+    fn write_buffer(&mut self, sync: bool) -> Result<()> {
+        self.file()?;
+        let mut start = 0;
+        let mut need_sync = sync;
+        while start < self.buffered || need_sync {
+            let mut ops = Vec::with_capacity(2);
+            if start < self.buffered {
+                let mut write = self.write_op(start);
+                if need_sync {
+                    write.flags |= sys::IOSQE_IO_LINK;
+                }
+                ops.push(write);
+            }
+            if need_sync {
+                ops.push(sys::Sqe {
+                    opcode: sys::IORING_OP_FSYNC,
+                    fd: self.file()?.as_raw_fd(),
+                    op_flags: sys::IORING_FSYNC_DATASYNC,
+                    ..Default::default()
+                });
+            }
+            // SAFETY: The write reads from the registered buffer, which the ring owns.
+            let mut results = unsafe { self.ring.submit_and_wait(&ops) }.into_iter();
+            if start < self.buffered {
+                match results.next().unwrap() {
+                    Ok(n) => start += n,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(posix_error(e, "error appending to file")),
+                }
+            }
+            if need_sync {
+                match results.next().unwrap() {
+                    Ok(_) if start == self.buffered => need_sync = false,
+                    // Canceled along with a short write; retried once it is finished.
+                    Ok(_) => {}
+                    Err(ref e) if e.raw_os_error() == Some(sys::ECANCELED) => {}
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(posix_error(e, "error syncing file")),
+                }
+            }
+        }
+        self.written += self.buffered as u64;
+        self.buffered = 0;
+        if self.bytes_per_sync > 0 && self.written - self.range_synced >= self.bytes_per_sync {
+            range_sync::start_writeback(self.file()?, self.range_synced, self.written - self.range_synced);
+            self.range_synced = self.written;
+        }
+        Ok(())
+    }
+}
+
+impl WritableFile for UringLogFile {
+    fn append(&mut self, mut data: &[u8]) -> Result<()> {
+        let size = self.written + self.buffered as u64 + data.len() as u64;
+        if self.preallocation_block_size > 0 && size > self.preallocated {
+            let end = size.div_ceil(self.preallocation_block_size) * self.preallocation_block_size;
+            fallocate::keep_size(self.file()?, self.preallocated, end - self.preallocated);
+            self.preallocated = end;
+        }
+        while !data.is_empty() {
+            if self.buffered == self.ring.buffer.len() {
+                self.write_buffer(false)?;
+            }
+            let n = data.len().min(self.ring.buffer.len() - self.buffered);
+            self.ring.buffer[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let mut s = if self.file.is_some() { self.write_buffer(false) } else { Ok(()) };
+        if s.is_ok() && self.preallocated > self.written {
+            if let Some(file) = self.file.as_ref() {
+                s = file.set_len(self.written).map_err(|e| posix_error(e, "error truncating file"));
+            }
+        }
+        self.file = None;
+        s
+    }
+
+    fn flush(&mut self) -> Result<()> { self.write_buffer(false) }
+
+    fn sync(&mut self) -> Result<()> { self.write_buffer(true) }
+
+    fn set_preallocation_block_size(&mut self, size: usize) { self.preallocation_block_size = size as u64; }
+
+    fn set_bytes_per_sync(&mut self, bytes: usize) { self.bytes_per_sync = bytes as u64; }
+}
+
+impl Drop for UringLogFile {
+    fn drop(&mut self) { let _ = self.close(); }
+}
+
+/// An `Env` that does random reads and write-ahead log appends through io_uring, and
+/// everything else like `PosixEnv`. Tables are read with system calls rather than
+/// through memory mappings. Threads on which io_uring cannot be set up, e.g. because
+/// the process ran out of locked memory for the registered buffer, read with `pread()`.
+pub struct UringEnv;
+
+impl UringEnv {
+    /// Returns a `NotSupported` error if the kernel does not provide io_uring or does
+    /// not allow this process to use it.
+    pub fn new() -> Result<UringEnv> {
+        match Ring::new(READ_BUFFER_SIZE) {
+            Ok(_) => Ok(UringEnv),
+            Err(e) => Err(Error::from(e).with_context("io_uring not available")),
+        }
+    }
+}
+
+impl Env for UringEnv {
+    fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn SequentialFile>> {
+        PosixEnv.new_sequential_file(fname)
+    }
+
+    fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn RandomAccessFile>> {
+        let file = File::open(fname).map_err(|e| posix_error(e, "error opening file for reading").with_detail(fname))?;
+        Ok(Box::new(UringRandomAccessFile { file }))
+    }
+
+    fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        let basename = fname.rsplit('/').next().unwrap_or(fname);
+        if !matches!(parse_file_name(basename), Some((_, FileType::LogFile))) {
+            return PosixEnv.new_writable_file(fname);
+        }
+        let ring = Ring::new(WRITE_BUFFER_SIZE)
+            .map_err(|e| posix_error(e, "error setting up io_uring").with_detail(fname))?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(fname)
+            .map_err(|e| posix_error(e, "error opening file for writing").with_detail(fname))?;
+        Ok(Box::new(UringLogFile {
+            file: Some(file),
+            ring,
+            buffered: 0,
+            written: 0,
+            preallocation_block_size: 0,
+            preallocated: 0,
+            bytes_per_sync: 0,
+            range_synced: 0,
+        }))
+    }
+
+    fn file_exists(&self, fname: &str) -> bool { PosixEnv.file_exists(fname) }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>> { PosixEnv.get_children(dir) }
+
+    fn remove_file(&self, fname: &str) -> Result<()> { PosixEnv.remove_file(fname) }
+
+    fn create_dir(&self, dirname: &str) -> Result<()> { PosixEnv.create_dir(dirname) }
+
+    fn remove_dir(&self, dirname: &str) -> Result<()> { PosixEnv.remove_dir(dirname) }
+
+    fn get_file_size(&self, fname: &str) -> Result<u64> { PosixEnv.get_file_size(fname) }
+
+    fn rename_file(&self, src: &str, target: &str) -> Result<()> { PosixEnv.rename_file(src, target) }
+
+    fn link_file(&self, src: &str, target: &str) -> Result<()> { PosixEnv.link_file(src, target) }
+
+    fn lock_file(&self, fname: &str) -> Result<Box<dyn FileLock>> { PosixEnv.lock_file(fname) }
+
+    fn schedule(&self, job: Job, priority: Priority) { PosixEnv.schedule(job, priority) }
+
+    fn set_background_threads(&self, n: usize, priority: Priority) { PosixEnv.set_background_threads(n, priority) }
+
+    fn now_micros(&self) -> u64 { PosixEnv.now_micros() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        db::DB,
+        leveldb::{
+            env::{read_file_to_string, write_string_to_file},
+            options::{Options, ReadOptions, WriteOptions},
+        },
+        util::testutil::tmp_dir,
+    };
+
+    #[test]
+    fn reads_and_log_appends() {
+        let Ok(env) = UringEnv::new() else { return };
+        let dir = tmp_dir("uring_reads_and_log_appends");
+        let fname = format!("{}/000001.log", dir);
+        let data: Vec<u8> = (0..(READ_BUFFER_SIZE * 2 + 1000)).map(|i| (i % 251) as u8).collect();
+
+        let mut file = env.new_writable_file(&fname).unwrap();
+        file.set_preallocation_block_size(1 << 20);
+        for chunk in data.chunks(10_000) {
+            file.append(chunk).unwrap();
+        }
+        file.sync().unwrap();
+        assert_eq!(env.get_file_size(&fname).unwrap(), data.len() as u64);
+        file.append(b"tail").unwrap();
+        file.close().unwrap();
+        assert_eq!(env.get_file_size(&fname).unwrap(), data.len() as u64 + 4);
+
+        let file = env.new_random_access_file(&fname).unwrap();
+        // Through the registered buffer, and directly into a larger buffer.
+        let mut buf = vec![0; 4096];
+        assert_eq!(file.read(1000, &mut buf).unwrap(), 4096);
+        assert_eq!(buf, data[1000..5096]);
+        let mut buf = vec![0; data.len() + 100];
+        assert_eq!(file.read(0, &mut buf).unwrap(), data.len() + 4);
+        assert_eq!(buf[..data.len()], data[..]);
+        assert_eq!(&buf[data.len()..data.len() + 4], b"tail");
+
+        // Other files are written by PosixEnv.
+        write_string_to_file(&env, b"other", &format!("{}/CURRENT", dir)).unwrap();
+        assert_eq!(read_file_to_string(&env, &format!("{}/CURRENT", dir)).unwrap(), b"other");
+    }
+
+    #[test]
+    fn failed_submission() {
+        let Ok(mut ring) = Ring::new(4096) else { return };
+        let dir = tmp_dir("uring_failed_submission");
+        let fname = format!("{}/data", dir);
+        let data: Vec<u8> = (0..8192).map(|i| (i % 251) as u8).collect();
+        write_string_to_file(&PosixEnv, &data, &fname).unwrap();
+        let file = File::open(&fname).unwrap();
+
+        // The first operation is submitted, then submitting the others fails. They must
+        // be withdrawn, and the first one waited for, before the buffers are released.
+        let mut bufs = vec![vec![0u8; 1024]; 4];
+        let ops: Vec<sys::Sqe> = bufs
+            .iter_mut()
+            .enumerate()
+            .map(|(i, buf)| sys::Sqe {
+                opcode: sys::IORING_OP_READ,
+                fd: file.as_raw_fd(),
+                off: i as u64 * 1024,
+                addr: buf.as_mut_ptr() as u64,
+                len: buf.len() as u32,
+                ..Default::default()
+            })
+            .collect();
+        ENTER_FAULT.set(Some(1));
+        let results = unsafe { ring.submit_and_wait(&ops) };
+        assert_eq!(results[0].as_ref().unwrap(), &1024);
+        assert_eq!(bufs[0], data[..1024]);
+        assert!(results[1..].iter().all(|result| result.is_err()));
+        assert!(bufs[1..].iter().all(|buf| buf.iter().all(|&b| b == 0)));
+
+        // Nothing of the failed call is left in the queues to confuse the next one.
+        let mut buf = vec![0; 2048];
+        assert_eq!(ring.read(file.as_raw_fd(), 4096, &mut buf).unwrap(), 2048);
+        assert_eq!(buf, data[4096..6144]);
+        let mut buf = vec![0; 8192];
+        assert_eq!(ring.read(file.as_raw_fd(), 0, &mut buf).unwrap(), 8192);
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn db() {
+        let Ok(env) = UringEnv::new() else { return };
+        let dir = tmp_dir("uring_db");
+        let options = Options {
+            env: Arc::new(env),
+            create_if_missing: true,
+            write_buffer_size: 64 << 10,
+            ..Default::default()
+        };
+        let wo = WriteOptions { sync: true };
+        let db = DB::open(options.clone(), &dir).unwrap();
+        for i in 0..2000 {
+            db.put(&wo, format!("key{:05}", i).as_bytes(), &[b'v'; 100]).unwrap();
+        }
+        drop(db);
+        let db = DB::open(options, &dir).unwrap();
+        for i in (0..2000).step_by(7) {
+            let value = db.get(&ReadOptions::default(), format!("key{:05}", i).as_bytes()).unwrap();
+            assert_eq!(value.unwrap(), [b'v'; 100]);
+        }
+    }
+}
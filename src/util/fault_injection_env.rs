@@ -0,0 +1,459 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    db::filename::{parse_file_name, FileType},
+    leveldb::{
+        env::{Env, FileLock, Job, Logger, Priority, RandomAccessFile, SequentialFile, WritableFile},
+        result::{Error, ErrorType, Result},
+    },
+};
+
+/// The operations that `FaultInjectionEnv::fail_after()` can make fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultOperation {
+    /// Reads from sequential and random access files.
+    Read,
+    Append,
+    Sync,
+    /// Creating or opening a file.
+    Open,
+    Rename,
+    Remove,
+}
+
+struct Fault {
+    op: FaultOperation,
+    file_type: Option<FileType>,
+    // Matching calls that still succeed before the fault triggers
+    remaining: u64,
+}
+
+struct Tear {
+    file_type: Option<FileType>,
+    offset: u64,
+}
+
+/// What the env knows about a file written through it.
+#[derive(Debug, Clone, Copy, Default)]
+struct FileState {
+    // Number of bytes appended
+    pos: u64,
+    // Number of bytes that were appended before the last sync
+    pos_at_last_sync: u64,
+    // Set once a torn write hit the file; later appends are lost.
+    torn: bool,
+}
+
+struct Shared {
+    target: Arc<dyn Env>,
+    active: AtomicBool,
+    faults: Mutex<Vec<Fault>>,
+    tears: Mutex<Vec<Tear>>,
+    files: Mutex<HashMap<String, FileState>>,
+}
+
+fn file_type(fname: &str) -> Option<FileType> {
+    let basename = fname.rsplit('/').next().unwrap_or(fname);
+    parse_file_name(basename).map(|(_, file_type)| file_type)
+}
+
+fn injected(what: &str, fname: &str) -> Error {
+    Error::new(ErrorType::IOError, "injected fault").with_detail(format!("{} {}", what, fname))
+}
+
+impl Shared {
+    /// Return an error if `op` on `fname` should fail: the filesystem is inactive or a
+    /// fault registered for `op` has run out of successful calls.
+    fn check(&self, op: FaultOperation, fname: &str) -> Result<()> {
+        let is_write = !matches!(op, FaultOperation::Read);
+        if is_write && !self.active.load(Ordering::Acquire) {
+            return Err(injected("filesystem inactive", fname));
+        }
+        let ty = file_type(fname);
+        let mut faults = self.faults.lock().unwrap();
+        for fault in faults.iter_mut() {
+            if fault.op != op || fault.file_type.is_some_and(|t| Some(t) != ty) {
+                continue;
+            }
+            if fault.remaining == 0 {
+                return Err(injected(&format!("{:?}", op).to_lowercase(), fname));
+            }
+            fault.remaining -= 1;
+        }
+        Ok(())
+    }
+
+    /// Return the offset at which an append to `fname` must be torn, if any.
+    fn tear_offset(&self, fname: &str) -> Option<u64> {
+        let ty = file_type(fname);
+        self.tears
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|tear| tear.file_type.is_none() || tear.file_type == ty)
+            .map(|tear| tear.offset)
+            .min()
+    }
+}
+
+/// An Env that forwards to another Env and injects faults, so that error handling
+/// and crash recovery can be tested deterministically in-process. It can:
+///
+/// * make chosen operations fail after a number of successful calls (`fail_after`),
+/// * tear appends that cross a file offset, writing only the bytes before it
+///   (`tear_writes_at`),
+/// * stop all writes as if the process crashed (`set_filesystem_active`), and then
+///   drop everything that was not synced before the crash (`drop_unsynced_data`).
+///
+/// Only files written through this Env are tracked; files that existed before are
+/// assumed to be durable.
+pub struct FaultInjectionEnv {
+    shared: Arc<Shared>,
+}
+
+impl FaultInjectionEnv {
+    pub fn new(target: Arc<dyn Env>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                target,
+                active: AtomicBool::new(true),
+                faults: Mutex::new(Vec::new()),
+                tears: Mutex::new(Vec::new()),
+                files: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Let the next `n` calls of `op` on files of `file_type` (or on all files, if
+    /// `None`) succeed, and fail every later one with an `IOError`.
+    pub fn fail_after(&self, op: FaultOperation, file_type: Option<FileType>, n: u64) {
+        self.shared.faults.lock().unwrap().push(Fault {
+            op,
+            file_type,
+            remaining: n,
+        });
+    }
+
+    /// Tear the append that would extend a file of `file_type` (or any file, if
+    /// `None`) past `offset`: only the bytes before `offset` are written, the append
+    /// fails with an `IOError`, and later appends to the file are lost.
+    pub fn tear_writes_at(&self, file_type: Option<FileType>, offset: u64) {
+        self.shared.tears.lock().unwrap().push(Tear { file_type, offset });
+    }
+
+    /// Remove all faults and tears registered so far.
+    pub fn clear_faults(&self) {
+        self.shared.faults.lock().unwrap().clear();
+        self.shared.tears.lock().unwrap().clear();
+    }
+
+    /// While inactive, every write, sync, rename and removal fails, as if the process
+    /// had crashed. Reads still work.
+    pub fn set_filesystem_active(&self, active: bool) { self.shared.active.store(active, Ordering::Release) }
+
+    /// Truncate every file written through this Env to the size it had when it was
+    /// last synced, or to empty if it was never synced. Call after simulating a crash
+    /// and before reopening the database.
+    pub fn drop_unsynced_data(&self) -> Result<()> {
+        let mut files = self.shared.files.lock().unwrap();
+        for (fname, state) in files.iter_mut() {
+            if state.pos == state.pos_at_last_sync || !self.shared.target.file_exists(fname) {
+                continue;
+            }
+            let mut data = vec![0u8; state.pos_at_last_sync as usize];
+            if !data.is_empty() {
+                let file = self.shared.target.new_random_access_file(fname)?;
+                crate::leveldb::env::read_exact_at(file.as_ref(), 0, &mut data)?;
+            }
+            let mut file = self.shared.target.new_writable_file(fname)?;
+            file.append(&data)?;
+            file.sync()?;
+            file.close()?;
+            state.pos = state.pos_at_last_sync;
+            state.torn = false;
+        }
+        Ok(())
+    }
+}
+
+struct FaultSequentialFile {
+    fname: String,
+    file: Box<dyn SequentialFile>,
+    shared: Arc<Shared>,
+}
+
+impl SequentialFile for FaultSequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.shared.check(FaultOperation::Read, &self.fname)?;
+        self.file.read(buf)
+    }
+
+    fn skip(&mut self, n: u64) -> Result<()> { self.file.skip(n) }
+}
+
+struct FaultRandomAccessFile {
+    fname: String,
+    file: Box<dyn RandomAccessFile>,
+    shared: Arc<Shared>,
+}
+
+impl RandomAccessFile for FaultRandomAccessFile {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.shared.check(FaultOperation::Read, &self.fname)?;
+        self.file.read(offset, buf)
+    }
+
+    fn prefetch(&self, offset: u64, len: usize) { self.file.prefetch(offset, len) }
+}
+
+struct FaultWritableFile {
+    fname: String,
+    file: Box<dyn WritableFile>,
+    shared: Arc<Shared>,
+}
+
+impl FaultWritableFile {
+    fn update_state(&self, f: impl FnOnce(&mut FileState)) {
+        f(self.shared.files.lock().unwrap().entry(self.fname.clone()).or_default());
+    }
+
+    fn state(&self) -> FileState {
+        self.shared
+            .files
+            .lock()
+            .unwrap()
+            .get(&self.fname)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl WritableFile for FaultWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.shared.check(FaultOperation::Append, &self.fname)?;
+        let state = self.state();
+        if state.torn {
+            return Err(injected("append to torn file", &self.fname));
+        }
+        let end = state.pos + data.len() as u64;
+        if let Some(offset) = self.shared.tear_offset(&self.fname).filter(|&offset| offset < end) {
+            let keep = offset.saturating_sub(state.pos) as usize;
+            self.file.append(&data[..keep])?;
+            let _ = self.file.flush();
+            self.update_state(|s| {
+                s.pos += keep as u64;
+                s.torn = true;
+            });
+            return Err(injected("torn write", &self.fname));
+        }
+        self.file.append(data)?;
+        self.update_state(|s| s.pos = end);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> { self.file.close() }
+
+    fn flush(&mut self) -> Result<()> {
+        self.shared.check(FaultOperation::Append, &self.fname)?;
+        self.file.flush()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.shared.check(FaultOperation::Sync, &self.fname)?;
+        self.file.sync()?;
+        self.update_state(|s| s.pos_at_last_sync = s.pos);
+        Ok(())
+    }
+
+    fn set_preallocation_block_size(&mut self, size: usize) { self.file.set_preallocation_block_size(size) }
+
+    fn set_bytes_per_sync(&mut self, bytes: usize) { self.file.set_bytes_per_sync(bytes) }
+}
+
+impl Env for FaultInjectionEnv {
+    fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn SequentialFile>> {
+        self.shared.check(FaultOperation::Open, fname)?;
+        Ok(Box::new(FaultSequentialFile {
+            fname: fname.to_string(),
+            file: self.shared.target.new_sequential_file(fname)?,
+            shared: self.shared.clone(),
+        }))
+    }
+
+    fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn RandomAccessFile>> {
+        self.shared.check(FaultOperation::Open, fname)?;
+        Ok(Box::new(FaultRandomAccessFile {
+            fname: fname.to_string(),
+            file: self.shared.target.new_random_access_file(fname)?,
+            shared: self.shared.clone(),
+        }))
+    }
+
+    fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        self.shared.check(FaultOperation::Open, fname)?;
+        let file = self.shared.target.new_writable_file(fname)?;
+        self.shared
+            .files
+            .lock()
+            .unwrap()
+            .insert(fname.to_string(), FileState::default());
+        Ok(Box::new(FaultWritableFile {
+            fname: fname.to_string(),
+            file,
+            shared: self.shared.clone(),
+        }))
+    }
+
+    fn file_exists(&self, fname: &str) -> bool { self.shared.target.file_exists(fname) }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>> { self.shared.target.get_children(dir) }
+
+    fn remove_file(&self, fname: &str) -> Result<()> {
+        self.shared.check(FaultOperation::Remove, fname)?;
+        self.shared.target.remove_file(fname)?;
+        self.shared.files.lock().unwrap().remove(fname);
+        Ok(())
+    }
+
+    fn create_dir(&self, dirname: &str) -> Result<()> { self.shared.target.create_dir(dirname) }
+
+    fn remove_dir(&self, dirname: &str) -> Result<()> { self.shared.target.remove_dir(dirname) }
+
+    fn get_file_size(&self, fname: &str) -> Result<u64> { self.shared.target.get_file_size(fname) }
+
+    fn rename_file(&self, src: &str, target: &str) -> Result<()> {
+        self.shared.check(FaultOperation::Rename, src)?;
+        self.shared.target.rename_file(src, target)?;
+        let mut files = self.shared.files.lock().unwrap();
+        match files.remove(src) {
+            Some(state) => files.insert(target.to_string(), state),
+            None => files.remove(target),
+        };
+        Ok(())
+    }
+
+    fn link_file(&self, src: &str, target: &str) -> Result<()> { self.shared.target.link_file(src, target) }
+
+    fn lock_file(&self, fname: &str) -> Result<Box<dyn FileLock>> { self.shared.target.lock_file(fname) }
+
+    fn new_logger(&self, fname: &str) -> Result<Box<dyn Logger>> {
+        // The info log is not part of the database state under test.
+        self.shared.target.new_logger(fname)
+    }
+
+    fn schedule(&self, job: Job, priority: Priority) { self.shared.target.schedule(job, priority) }
+
+    fn set_background_threads(&self, n: usize, priority: Priority) {
+        self.shared.target.set_background_threads(n, priority)
+    }
+
+    fn now_micros(&self) -> u64 { self.shared.target.now_micros() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::DB,
+        leveldb::{
+            env::default_env,
+            options::{Options, ReadOptions, WriteOptions},
+        },
+        util::testutil::tmp_dir,
+    };
+
+    fn open(dir: &str, env: &Arc<FaultInjectionEnv>) -> Result<DB> {
+        let options = Options {
+            create_if_missing: true,
+            env: env.clone(),
+            ..Options::default()
+        };
+        DB::open(options, dir)
+    }
+
+    fn get(db: &DB, key: &str) -> Option<Vec<u8>> { db.get(&ReadOptions::default(), key.as_bytes()).unwrap() }
+
+    #[test]
+    fn drop_unsynced_writes() {
+        let dir = tmp_dir("fault_env_unsynced");
+        let env = Arc::new(FaultInjectionEnv::new(default_env()));
+        let db = open(&dir, &env).unwrap();
+        let sync = WriteOptions { sync: true };
+        db.put(&sync, b"synced", b"v1").unwrap();
+        db.put(&WriteOptions::default(), b"unsynced", b"v2").unwrap();
+        env.set_filesystem_active(false);
+        assert!(db.put(&WriteOptions::default(), b"failed", b"v3").is_err());
+        db.crash();
+
+        env.drop_unsynced_data().unwrap();
+        env.set_filesystem_active(true);
+        let db = open(&dir, &env).unwrap();
+        assert_eq!(get(&db, "synced").as_deref(), Some(&b"v1"[..]));
+        assert_eq!(get(&db, "unsynced"), None);
+        assert_eq!(get(&db, "failed"), None);
+    }
+
+    #[test]
+    fn fail_after() {
+        let dir = tmp_dir("fault_env_fail_after");
+        let env = Arc::new(FaultInjectionEnv::new(default_env()));
+        let db = open(&dir, &env).unwrap();
+        let sync = WriteOptions { sync: true };
+        env.fail_after(FaultOperation::Sync, Some(FileType::LogFile), 1);
+        db.put(&sync, b"k1", b"v1").unwrap();
+        let e = db.put(&sync, b"k2", b"v2").unwrap_err();
+        assert!(e.is_io_error());
+        // The DB stops accepting writes after a failed log write.
+        env.clear_faults();
+        assert!(db.put(&sync, b"k3", b"v3").is_err());
+        drop(db);
+
+        let db = open(&dir, &env).unwrap();
+        assert_eq!(get(&db, "k1").as_deref(), Some(&b"v1"[..]));
+    }
+
+    #[test]
+    fn torn_write() {
+        let dir = tmp_dir("fault_env_torn_write");
+        let env = Arc::new(FaultInjectionEnv::new(default_env()));
+        let db = open(&dir, &env).unwrap();
+        let sync = WriteOptions { sync: true };
+        db.put(&sync, b"k1", b"v1").unwrap();
+        // Tear the second record of the log in half.
+        env.tear_writes_at(Some(FileType::LogFile), 40);
+        assert!(db.put(&sync, b"k2", &[b'x'; 40]).unwrap_err().is_io_error());
+        db.crash();
+
+        env.clear_faults();
+        let db = open(&dir, &env).unwrap();
+        assert_eq!(get(&db, "k1").as_deref(), Some(&b"v1"[..]));
+        assert_eq!(get(&db, "k2"), None);
+    }
+}
@@ -0,0 +1,69 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::util::coding::decode_fixed_32;
+
+/// Similar to murmur hash. This is the hash of LevelDB's `util/hash.cc`, which its
+/// bloom filters are built with, so it must not change: filter blocks written with
+/// it are read by both implementations.
+pub fn hash(data: &[u8], seed: u32) -> u32 {
+    const M: u32 = 0xc6a4a793;
+    const R: u32 = 24;
+    let n = data.len();
+    let mut h = seed ^ (n as u32).wrapping_mul(M);
+
+    // Pick up four bytes at a time
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let w = decode_fixed_32(chunk);
+        h = h.wrapping_add(w).wrapping_mul(M);
+        h ^= h >> 16;
+    }
+
+    // Pick up remaining bytes
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        if rest.len() == 3 {
+            h = h.wrapping_add((rest[2] as u32) << 16);
+        }
+        if rest.len() >= 2 {
+            h = h.wrapping_add((rest[1] as u32) << 8);
+        }
+        h = h.wrapping_add(rest[0] as u32).wrapping_mul(M);
+        h ^= h >> R;
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_unsigned_issue() {
+        // Values from LevelDB's hash_test.cc: bytes above 0x7f are not sign extended.
+        assert_eq!(hash(&[], 0xbc9f1d34), 0xbc9f1d34);
+        assert_eq!(hash(&[0x62], 0xbc9f1d34), 0xef1345c4);
+        assert_eq!(hash(&[0xc3, 0x97], 0xbc9f1d34), 0x5b663814);
+        assert_eq!(hash(&[0xe2, 0x99, 0xa5], 0xbc9f1d34), 0x323c078f);
+        assert_eq!(hash(&[0xe1, 0x80, 0xb9, 0x32], 0xbc9f1d34), 0xed21633a);
+    }
+}
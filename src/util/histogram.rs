@@ -0,0 +1,253 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fmt;
+
+const NUM_BUCKETS: usize = 154;
+
+/// Upper limits of the buckets, the last one catching everything else.
+const BUCKET_LIMIT: [f64; NUM_BUCKETS] = [
+    1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 12.0, 14.0, 16.0, 18.0, 20.0, 25.0,
+    30.0, 35.0, 40.0, 45.0, 50.0, 60.0, 70.0, 80.0, 90.0, 100.0, 120.0, 140.0, 160.0,
+    180.0, 200.0, 250.0, 300.0, 350.0, 400.0, 450.0, 500.0, 600.0, 700.0, 800.0, 900.0,
+    1000.0, 1200.0, 1400.0, 1600.0, 1800.0, 2000.0, 2500.0, 3000.0, 3500.0, 4000.0, 4500.0,
+    5000.0, 6000.0, 7000.0, 8000.0, 9000.0, 10000.0, 12000.0, 14000.0, 16000.0, 18000.0,
+    20000.0, 25000.0, 30000.0, 35000.0, 40000.0, 45000.0, 50000.0, 60000.0, 70000.0,
+    80000.0, 90000.0, 100000.0, 120000.0, 140000.0, 160000.0, 180000.0, 200000.0, 250000.0,
+    300000.0, 350000.0, 400000.0, 450000.0, 500000.0, 600000.0, 700000.0, 800000.0,
+    900000.0, 1.0e+06, 1.2e+06, 1.4e+06, 1.6e+06, 1.8e+06, 2.0e+06, 2.5e+06, 3.0e+06,
+    3.5e+06, 4.0e+06, 4.5e+06, 5.0e+06, 6.0e+06, 7.0e+06, 8.0e+06, 9.0e+06, 1.0e+07,
+    1.2e+07, 1.4e+07, 1.6e+07, 1.8e+07, 2.0e+07, 2.5e+07, 3.0e+07, 3.5e+07, 4.0e+07,
+    4.5e+07, 5.0e+07, 6.0e+07, 7.0e+07, 8.0e+07, 9.0e+07, 1.0e+08, 1.2e+08, 1.4e+08,
+    1.6e+08, 1.8e+08, 2.0e+08, 2.5e+08, 3.0e+08, 3.5e+08, 4.0e+08, 4.5e+08, 5.0e+08,
+    6.0e+08, 7.0e+08, 8.0e+08, 9.0e+08, 1.0e+09, 1.2e+09, 1.4e+09, 1.6e+09, 1.8e+09,
+    2.0e+09, 2.5e+09, 3.0e+09, 3.5e+09, 4.0e+09, 4.5e+09, 5.0e+09, 6.0e+09, 7.0e+09,
+    8.0e+09, 9.0e+09, 1e200,
+];
+
+/// Distribution of a series of values, typically latencies in microseconds, bucketed
+/// on a roughly logarithmic scale so that percentiles can be estimated in constant
+/// space.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    min: f64,
+    max: f64,
+    num: f64,
+    sum: f64,
+    sum_squares: f64,
+    buckets: [f64; NUM_BUCKETS],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            min: BUCKET_LIMIT[NUM_BUCKETS - 1],
+            max: 0.0,
+            num: 0.0,
+            sum: 0.0,
+            sum_squares: 0.0,
+            buckets: [0.0; NUM_BUCKETS],
+        }
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn clear(&mut self) { *self = Self::default(); }
+
+    pub fn add(&mut self, value: f64) {
+        // Linear search is fast enough for our usage in db_bench
+        let mut b = 0;
+        while b < NUM_BUCKETS - 1 && BUCKET_LIMIT[b] <= value {
+            b += 1;
+        }
+        self.buckets[b] += 1.0;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.num += 1.0;
+        self.sum += value;
+        self.sum_squares += value * value;
+    }
+
+    /// Add the values recorded by `other`, e.g. to combine the histograms of several
+    /// benchmark threads.
+    pub fn merge(&mut self, other: &Histogram) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.num += other.num;
+        self.sum += other.sum;
+        self.sum_squares += other.sum_squares;
+        for (b, n) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *b += n;
+        }
+    }
+
+    /// Number of values added.
+    pub fn count(&self) -> u64 { self.num as u64 }
+
+    pub fn min(&self) -> f64 {
+        match self.num == 0.0 {
+            true => 0.0,
+            false => self.min,
+        }
+    }
+
+    pub fn max(&self) -> f64 { self.max }
+
+    pub fn median(&self) -> f64 { self.percentile(50.0) }
+
+    pub fn p95(&self) -> f64 { self.percentile(95.0) }
+
+    pub fn p99(&self) -> f64 { self.percentile(99.0) }
+
+    /// Estimate the value below which `p` percent of the values fall, interpolating
+    /// within the bucket that holds it.
+    pub fn percentile(&self, p: f64) -> f64 {
+        let threshold = self.num * (p / 100.0);
+        let mut sum = 0.0;
+        for (b, &n) in self.buckets.iter().enumerate() {
+            sum += n;
+            if sum >= threshold && n > 0.0 {
+                // Scale linearly within this bucket
+                let left_point = if b == 0 { 0.0 } else { BUCKET_LIMIT[b - 1] };
+                let right_point = BUCKET_LIMIT[b];
+                let left_sum = sum - n;
+                let pos = (threshold - left_sum) / n;
+                let r = left_point + (right_point - left_point) * pos;
+                return r.clamp(self.min(), self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn average(&self) -> f64 {
+        match self.num == 0.0 {
+            true => 0.0,
+            false => self.sum / self.num,
+        }
+    }
+
+    pub fn standard_deviation(&self) -> f64 {
+        if self.num == 0.0 {
+            return 0.0;
+        }
+        let variance = (self.sum_squares * self.num - self.sum * self.sum) / (self.num * self.num);
+        variance.max(0.0).sqrt()
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Count: {:.0}  Average: {:.4}  StdDev: {:.2}",
+            self.num,
+            self.average(),
+            self.standard_deviation()
+        )?;
+        writeln!(f, "Min: {:.4}  Median: {:.4}  Max: {:.4}", self.min(), self.median(), self.max)?;
+        writeln!(f, "------------------------------------------------------")?;
+        let mult = 100.0 / self.num;
+        let mut sum = 0.0;
+        for (b, &n) in self.buckets.iter().enumerate() {
+            if n <= 0.0 {
+                continue;
+            }
+            sum += n;
+            let left = if b == 0 { 0.0 } else { BUCKET_LIMIT[b - 1] };
+            write!(
+                f,
+                "[ {:7.0}, {:7.0} ) {:7.0} {:7.3}% {:7.3}% ",
+                left,
+                BUCKET_LIMIT[b],
+                n,
+                mult * n,
+                mult * sum
+            )?;
+            // Add hash marks based on percentage; 20 marks for 100%.
+            let marks = (20.0 * (n / self.num) + 0.5) as usize;
+            writeln!(f, "{}", "#".repeat(marks))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let h = Histogram::new();
+        assert_eq!(h.count(), 0);
+        assert_eq!(h.min(), 0.0);
+        assert_eq!(h.median(), 0.0);
+        assert_eq!(h.average(), 0.0);
+        assert_eq!(h.standard_deviation(), 0.0);
+        assert!(h.to_string().starts_with("Count: 0  Average: 0.0000"));
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut h = Histogram::new();
+        for i in 1..=100 {
+            h.add(i as f64);
+        }
+        assert_eq!(h.count(), 100);
+        assert_eq!(h.min(), 1.0);
+        assert_eq!(h.max(), 100.0);
+        assert_eq!(h.average(), 50.5);
+        assert!((h.standard_deviation() - 28.866).abs() < 0.01);
+        // Estimates are exact up to the width of the buckets.
+        assert!((h.median() - 50.0).abs() <= 5.0);
+        assert!((h.p95() - 95.0).abs() <= 5.0);
+        assert!((h.p99() - 99.0).abs() <= 10.0);
+        assert!(h.median() <= h.p95() && h.p95() <= h.p99() && h.p99() <= h.max());
+
+        let text = h.to_string();
+        assert!(text.starts_with("Count: 100  Average: 50.5000  StdDev: 28.87\nMin: 1.0000"));
+        assert!(text.contains("\n[       1,       2 )       1   1.000%   1.000% \n"));
+        assert!(text.contains("[      10,      12 )       2   2.000%  11.000% \n"));
+        assert!(text.ends_with("[     100,     120 )       1   1.000% 100.000% \n"));
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = Histogram::new();
+        let mut b = Histogram::new();
+        for _ in 0..90 {
+            a.add(10.0);
+        }
+        for _ in 0..10 {
+            b.add(10_000.0);
+        }
+        a.merge(&b);
+        assert_eq!(a.count(), 100);
+        assert_eq!(a.min(), 10.0);
+        assert_eq!(a.max(), 10_000.0);
+        assert!(a.median() < 12.0);
+        assert!(a.p95() > 9_000.0);
+
+        a.clear();
+        assert_eq!(a, Histogram::new());
+    }
+}
@@ -0,0 +1,226 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    fmt::{self, Write},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::leveldb::{
+    env::{Env, InfoLogLevel, Logger, WritableFile},
+    result::Result,
+};
+
+#[cfg(test)]
+thread_local! {
+    static SYSTEM_CLOCK_READS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Number of times this thread read the system clock for a timestamp.
+#[cfg(test)]
+pub(crate) fn system_clock_reads() -> usize { SYSTEM_CLOCK_READS.get() }
+
+/// Microseconds since the Unix epoch according to the system clock.
+fn system_now_micros() -> u64 {
+    #[cfg(test)]
+    SYSTEM_CLOCK_READS.set(SYSTEM_CLOCK_READS.get() + 1);
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Format `micros` since the Unix epoch as `yyyy/mm/dd-hh:mm:ss.uuuuuu` (UTC).
+fn append_timestamp(buf: &mut String, micros: u64) {
+    let secs = micros / 1_000_000;
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Convert days since the epoch to a civil date.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let _ = write!(
+        buf,
+        "{:04}/{:02}/{:02}-{:02}:{:02}:{:02}.{:06}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60,
+        micros % 1_000_000
+    );
+}
+
+/// Format a log line: timestamp, level and message, terminated by a newline.
+fn format_line(level: InfoLogLevel, micros: u64, args: fmt::Arguments<'_>) -> String {
+    let mut line = String::new();
+    append_timestamp(&mut line, micros);
+    let _ = write!(line, " {} {}", level.as_str(), args);
+    if !line.ends_with('\n') {
+        line.push('\n');
+    }
+    line
+}
+
+/// A logger that appends one line per message to a file. Each line is flushed so
+/// that the log is readable while the DB is running.
+pub struct FileLogger {
+    file: Mutex<Box<dyn WritableFile>>,
+    // Returns the time of a message in micro-seconds since the Unix epoch.
+    clock: Box<dyn Fn() -> u64 + Send + Sync>,
+}
+
+impl FileLogger {
+    /// A logger that timestamps its lines with the system clock.
+    pub fn new(file: Box<dyn WritableFile>) -> Self { Self::with_clock(file, system_now_micros) }
+
+    /// A logger that timestamps its lines with `clock`, which returns micro-seconds
+    /// since the Unix epoch.
+    pub fn with_clock(file: Box<dyn WritableFile>, clock: impl Fn() -> u64 + Send + Sync + 'static) -> Self {
+        Self {
+            file: Mutex::new(file),
+            clock: Box::new(clock),
+        }
+    }
+}
+
+impl Logger for FileLogger {
+    fn logv(&self, level: InfoLogLevel, args: fmt::Arguments<'_>) {
+        let line = format_line(level, (self.clock)(), args);
+        let mut file = self.file.lock().unwrap();
+        // Logging is best effort; a failed write must not fail the operation logged.
+        if file.append(line.as_bytes()).is_ok() {
+            let _ = file.flush();
+        }
+    }
+}
+
+impl Drop for FileLogger {
+    fn drop(&mut self) { let _ = self.file.get_mut().unwrap().close(); }
+}
+
+struct RollingState {
+    logger: Box<dyn Logger>,
+    // Approximate number of bytes written to the current file
+    size: u64,
+}
+
+/// A logger that writes to `fname` through loggers created by `env`, filtering out
+/// messages below `level`. Once the current file holds about `max_size` bytes, it is
+/// renamed to `old_fname`, replacing the previous one, and a new file is started.
+/// A `max_size` of zero never rolls the file.
+pub struct RollingLogger {
+    env: Arc<dyn Env>,
+    fname: String,
+    old_fname: String,
+    max_size: u64,
+    level: InfoLogLevel,
+    state: Mutex<RollingState>,
+}
+
+impl RollingLogger {
+    /// Move an existing `fname` to `old_fname` and start logging to a new `fname`.
+    pub fn open(env: Arc<dyn Env>, fname: &str, old_fname: &str, max_size: u64, level: InfoLogLevel) -> Result<Self> {
+        let _ = env.rename_file(fname, old_fname);
+        let logger = env.new_logger(fname)?;
+        Ok(Self {
+            env,
+            fname: fname.to_string(),
+            old_fname: old_fname.to_string(),
+            max_size,
+            level,
+            state: Mutex::new(RollingState { logger, size: 0 }),
+        })
+    }
+
+    fn roll(&self, state: &mut RollingState) -> Result<()> {
+        // Close the current file before renaming it.
+        let _ = std::mem::replace(&mut state.logger, Box::new(NullLogger));
+        self.env.rename_file(&self.fname, &self.old_fname)?;
+        state.logger = self.env.new_logger(&self.fname)?;
+        state.size = 0;
+        Ok(())
+    }
+}
+
+impl Logger for RollingLogger {
+    fn logv(&self, level: InfoLogLevel, args: fmt::Arguments<'_>) {
+        if level < self.level {
+            return;
+        }
+        let msg = args.to_string();
+        let mut state = self.state.lock().unwrap();
+        if self.max_size > 0 && state.size >= self.max_size && self.roll(&mut state).is_err() {
+            // Keep appending to whatever file is open rather than losing messages.
+            state.size = 0;
+        }
+        // Count the timestamp and level that the logger adds to each line.
+        state.size += msg.len() as u64 + 34;
+        state.logger.logv(level, format_args!("{}", msg));
+    }
+}
+
+/// A logger that discards all messages.
+pub struct NullLogger;
+
+impl Logger for NullLogger {
+    fn logv(&self, _level: InfoLogLevel, _args: fmt::Arguments<'_>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{leveldb::env::default_env, util::testutil::tmp_dir};
+
+    #[test]
+    fn rolling() {
+        let dir = tmp_dir("logger_rolling");
+        let env = default_env();
+        let fname = format!("{}/LOG", dir);
+        let old_fname = format!("{}/LOG.old", dir);
+        env.new_logger(&fname).unwrap().info(format_args!("first run"));
+
+        let logger = RollingLogger::open(env.clone(), &fname, &old_fname, 1000, InfoLogLevel::Warn).unwrap();
+        let old = String::from_utf8(crate::leveldb::env::read_file_to_string(env.as_ref(), &old_fname).unwrap()).unwrap();
+        assert!(old.ends_with(" INFO first run\n"));
+
+        logger.info(format_args!("filtered"));
+        logger.warn(format_args!("value {}", 42));
+        let log = String::from_utf8(crate::leveldb::env::read_file_to_string(env.as_ref(), &fname).unwrap()).unwrap();
+        assert_eq!(log.lines().count(), 1);
+        assert!(log.ends_with(" WARN value 42\n"));
+
+        for i in 0..100 {
+            logger.error(format_args!("message {}", i));
+        }
+        assert!(env.get_file_size(&fname).unwrap() < 1100);
+        let old = String::from_utf8(crate::leveldb::env::read_file_to_string(env.as_ref(), &old_fname).unwrap()).unwrap();
+        assert!(old.contains(" ERROR message "));
+    }
+}
@@ -0,0 +1,282 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// An implementation of the LZ4 block format, used for table blocks compressed with
+// `CompressionType::Lz4`.
+//
+// A compressed buffer starts with the varint32 length of the uncompressed data,
+// followed by a single LZ4 block. The block is a series of sequences, each made of a
+// token, literals, and a match copied from earlier output:
+//
+//    token: u8           high nibble = literal length, low nibble = match length - 4
+//    literal length extra bytes, if the high nibble is 15
+//    literals: u8[literal length]
+//    offset: u16         distance back to the match (little endian)
+//    match length extra bytes, if the low nibble is 15
+//
+// The last sequence has literals only. The block carries no length of its own, which
+// is why the uncompressed length is stored in front of it.
+
+use crate::{
+    leveldb::result::Result,
+    util::coding::{decode_varint_32, encode_varint_32_vec},
+};
+
+const MIN_MATCH: usize = 4;
+// The last match must start at least this many bytes before the end of the input...
+const MF_LIMIT: usize = 12;
+// ... and the block must end with at least this many literals.
+const LAST_LITERALS: usize = 5;
+const MAX_OFFSET: usize = 65535;
+
+const HASH_LOG: u32 = 14;
+
+/// The largest input that `compress` accepts, as in the reference implementation.
+/// Larger declared lengths mark a block as corrupt.
+pub const MAX_INPUT_SIZE: usize = 0x7E00_0000;
+
+/// The most `decompress` allocates up front. Larger outputs grow as the block actually
+/// regenerates them, so that a damaged length cannot make it allocate on its own.
+const MAX_PREALLOCATION: usize = 1 << 20;
+
+fn hash(v: u32) -> usize { (v.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize }
+
+fn read_u32(input: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]])
+}
+
+/// Append `len` in the length extension format: bytes of 255 followed by the
+/// remainder.
+fn write_length(dst: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        dst.push(255);
+        len -= 255;
+    }
+    dst.push(len as u8);
+}
+
+fn write_sequence(dst: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let literal_nibble = literals.len().min(15);
+    let match_nibble = matched.map_or(0, |(_, len)| (len - MIN_MATCH).min(15));
+    dst.push(((literal_nibble << 4) | match_nibble) as u8);
+    if literal_nibble == 15 {
+        write_length(dst, literals.len() - 15);
+    }
+    dst.extend_from_slice(literals);
+    if let Some((offset, len)) = matched {
+        dst.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_nibble == 15 {
+            write_length(dst, len - MIN_MATCH - 15);
+        }
+    }
+}
+
+/// Compress `input` into an LZ4 block preceded by the input length.
+/// REQUIRES: `input.len() <= MAX_INPUT_SIZE`
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    assert!(input.len() <= MAX_INPUT_SIZE, "lz4: input too large");
+    let mut dst = Vec::with_capacity(input.len() / 2 + 16);
+    encode_varint_32_vec(&mut dst, input.len() as u32);
+
+    let mut table = vec![usize::MAX; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut pos = 0;
+    if input.len() >= MF_LIMIT {
+        let match_limit = input.len() - LAST_LITERALS;
+        while pos + MF_LIMIT <= input.len() {
+            let h = hash(read_u32(input, pos));
+            let candidate = table[h];
+            table[h] = pos;
+            if candidate == usize::MAX || pos - candidate > MAX_OFFSET || read_u32(input, candidate) != read_u32(input, pos) {
+                pos += 1;
+                continue;
+            }
+            let len = MIN_MATCH
+                + input[candidate + MIN_MATCH..]
+                    .iter()
+                    .zip(&input[pos + MIN_MATCH..match_limit])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+            write_sequence(&mut dst, &input[anchor..pos], Some((pos - candidate, len)));
+            // Index a position inside the match so that runs keep finding matches.
+            let end = pos + len;
+            if end >= 2 && end + MF_LIMIT <= input.len() + 2 {
+                table[hash(read_u32(input, end - 2))] = end - 2;
+            }
+            pos = end;
+            anchor = pos;
+        }
+    }
+    write_sequence(&mut dst, &input[anchor..], None);
+    dst
+}
+
+/// Read a length extension starting at `*pos` and add it to `len`.
+fn read_length(input: &[u8], pos: &mut usize, mut len: usize) -> Result<usize> {
+    loop {
+        let b = match input.get(*pos) {
+            Some(&b) => b,
+            None => return LEVELDB_ERR!(Corruption, "lz4: truncated length"),
+        };
+        *pos += 1;
+        len += b as usize;
+        if b != 255 {
+            return Ok(len);
+        }
+    }
+}
+
+/// Upper bound on how much larger the output of a block is than the block itself: a
+/// byte of the block regenerates at most 255 bytes, as part of a length extension.
+const MAX_EXPANSION: usize = 255;
+
+/// Decompress a buffer produced by `compress`.
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>> {
+    let (expected, n) = decode_varint_32(input)?;
+    let expected = expected as usize;
+    // The length comes from the block, so check it before allocating for it.
+    if expected > MAX_INPUT_SIZE || expected > (input.len() - n).saturating_mul(MAX_EXPANSION) {
+        return LEVELDB_ERR!(Corruption, "lz4: decompressed length out of range");
+    }
+    let mut out = Vec::with_capacity(expected.min(MAX_PREALLOCATION));
+    decompress_block(&input[n..], expected, &mut out)?;
+    Ok(out)
+}
+
+/// Decode the LZ4 block `input`, which must regenerate exactly `expected` bytes.
+fn decompress_block(input: &[u8], expected: usize, out: &mut Vec<u8>) -> Result<()> {
+    let start = out.len();
+    let mut pos = 0;
+    loop {
+        let token = match input.get(pos) {
+            Some(&t) => t as usize,
+            None => return LEVELDB_ERR!(Corruption, "lz4: truncated block"),
+        };
+        pos += 1;
+
+        let mut literal_length = token >> 4;
+        if literal_length == 15 {
+            literal_length = read_length(input, &mut pos, literal_length)?;
+        }
+        match input.get(pos..pos + literal_length) {
+            Some(literals) if out.len() - start + literal_length <= expected => out.extend_from_slice(literals),
+            _ => return LEVELDB_ERR!(Corruption, "lz4: literals out of range"),
+        }
+        pos += literal_length;
+        if pos == input.len() {
+            // The last sequence has no match.
+            break;
+        }
+
+        let offset = match input.get(pos..pos + 2) {
+            Some(b) => u16::from_le_bytes([b[0], b[1]]) as usize,
+            None => return LEVELDB_ERR!(Corruption, "lz4: truncated offset"),
+        };
+        pos += 2;
+        let mut match_length = token & 15;
+        if match_length == 15 {
+            match_length = read_length(input, &mut pos, match_length)?;
+        }
+        match_length += MIN_MATCH;
+        if offset == 0 || offset > out.len() - start || out.len() - start + match_length > expected {
+            return LEVELDB_ERR!(Corruption, "lz4: match out of range");
+        }
+        let from = out.len() - offset;
+        for i in 0..match_length {
+            let b = out[from + i];
+            out.push(b);
+        }
+    }
+    if out.len() - start != expected {
+        return LEVELDB_ERR!(Corruption, "lz4: bad uncompressed length");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::random::Random;
+
+    #[test]
+    fn round_trip() {
+        let rnd = Random::new(301);
+        let mut inputs: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcdefghijkl".to_vec(),
+            vec![b'x'; 1000],
+            b"abcabcabcabcabcabcabcabcabcabcabcabc".to_vec(),
+            (0..=255u8).cycle().take(300_000).collect(),
+        ];
+        for len in [100, 4096, 100_000] {
+            inputs.push((0..len).map(|_| rnd.uniform(256) as u8).collect());
+            inputs.push((0..len).map(|_| b"0123456789abcdef"[rnd.skewed(4) as usize % 16]).collect());
+        }
+        for input in inputs.iter() {
+            let compressed = compress(input);
+            assert_eq!(&decompress(&compressed).unwrap(), input, "len {}", input.len());
+        }
+        assert!(compress(&vec![0u8; 10_000]).len() < 100);
+    }
+
+    #[test]
+    fn decodes_reference_block() {
+        // The block of `lz4 -l` (legacy frame format) applied to the text below.
+        let text = b"Hello hefestodb! Hello hefestodb! LZ4 blocks, please. LZ4 blocks, please.\n";
+        let mut out = Vec::new();
+        decompress_block(REFERENCE_BLOCK, text.len(), &mut out).unwrap();
+        assert_eq!(out, &text[..]);
+    }
+
+    #[test]
+    fn rejects_corruption() {
+        let rnd = Random::new(17);
+        let input: Vec<u8> = (0..10_000).map(|_| b"abcd"[rnd.skewed(2) as usize % 4]).collect();
+        let compressed = compress(&input);
+        assert!(decompress(&[]).is_err());
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+        // A length far beyond what the block can regenerate is rejected up front.
+        let mut huge = Vec::new();
+        encode_varint_32_vec(&mut huge, u32::MAX);
+        huge.extend_from_slice(&compressed[compressed.len() - 8..]);
+        assert!(decompress(&huge).unwrap_err().is_corruption());
+        // So is one beyond what compress() accepts, even from a block large enough to
+        // regenerate it.
+        let mut huge = Vec::new();
+        encode_varint_32_vec(&mut huge, MAX_INPUT_SIZE as u32 + 1);
+        huge.resize(huge.len() + MAX_INPUT_SIZE / MAX_EXPANSION + 1, 0);
+        assert!(decompress(&huge).unwrap_err().is_corruption());
+        // Flipping bits must never panic, whatever the result.
+        for i in 0..compressed.len() {
+            let mut damaged = compressed.clone();
+            damaged[i] ^= 1 << (i % 8);
+            let _ = decompress(&damaged);
+        }
+    }
+
+    const REFERENCE_BLOCK: &[u8] = &[
+        0xfd, 0x02, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x68, 0x65, 0x66, 0x65, 0x73, 0x74, 0x6f, 0x64,
+        0x62, 0x21, 0x20, 0x11, 0x00, 0xfc, 0x04, 0x4c, 0x5a, 0x34, 0x20, 0x62, 0x6c, 0x6f, 0x63, 0x6b,
+        0x73, 0x2c, 0x20, 0x70, 0x6c, 0x65, 0x61, 0x73, 0x65, 0x2e, 0x14, 0x00, 0x50, 0x61, 0x73, 0x65,
+        0x2e, 0x0a,
+    ];
+}
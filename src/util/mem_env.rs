@@ -0,0 +1,341 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// An Env that keeps all files in memory and needs no operating system support: no
+// files, threads or clock. It is the default Env where the `std` feature is disabled,
+// e.g. when building for `wasm32-unknown-unknown`, and a starting point for an Env over
+// some other storage, such as the storage of a browser.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+use crate::{
+    leveldb::{
+        env::{Env, FileLock, Job, Logger, Priority, RandomAccessFile, SequentialFile, WritableFile},
+        result::{Error, Result},
+    },
+    util::logger::FileLogger,
+};
+
+type FileData = Arc<RwLock<Vec<u8>>>;
+
+fn not_found(fname: &str) -> Error { Error::not_found("file not found").with_detail(fname) }
+
+struct MemSequentialFile {
+    data: FileData,
+    pos: usize,
+}
+
+impl SequentialFile for MemSequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let data = self.data.read().unwrap();
+        let start = self.pos.min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        self.pos = start + n;
+        Ok(n)
+    }
+
+    fn skip(&mut self, n: u64) -> Result<()> {
+        let len = self.data.read().unwrap().len();
+        self.pos = (self.pos as u64).saturating_add(n).min(len as u64) as usize;
+        Ok(())
+    }
+}
+
+struct MemRandomAccessFile {
+    data: FileData,
+}
+
+impl RandomAccessFile for MemRandomAccessFile {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let data = self.data.read().unwrap();
+        let start = (offset as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+}
+
+struct MemWritableFile {
+    data: Option<FileData>,
+}
+
+impl MemWritableFile {
+    fn data(&self) -> Result<&FileData> {
+        match self.data.as_ref() {
+            Some(data) => Ok(data),
+            None => LEVELDB_ERR!(IOError, "file already closed"),
+        }
+    }
+}
+
+impl WritableFile for MemWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.data()?.write().unwrap().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.data = None;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> { self.data().map(|_| ()) }
+
+    fn sync(&mut self) -> Result<()> { self.data().map(|_| ()) }
+}
+
+/// A lock taken with `MemEnv::lock_file()`, released when dropped.
+struct MemFileLock {
+    locked: Arc<Mutex<HashSet<String>>>,
+    fname: String,
+}
+
+impl FileLock for MemFileLock {}
+
+impl Drop for MemFileLock {
+    fn drop(&mut self) { self.locked.lock().unwrap().remove(&self.fname); }
+}
+
+/// An Env holding its files in memory. Directories exist implicitly: a file can be
+/// created under any name, and the children of a directory are the files whose names
+/// start with the directory name and a `/`. Background jobs run right away on the
+/// thread that schedules them, and the clock only advances when the database sleeps,
+/// so a `MemEnv` behaves deterministically.
+#[derive(Default)]
+pub struct MemEnv {
+    files: Mutex<HashMap<String, FileData>>,
+    locked: Arc<Mutex<HashSet<String>>>,
+    now_micros: Arc<AtomicU64>,
+}
+
+impl MemEnv {
+    pub fn new() -> Self { Self::default() }
+
+    fn file(&self, fname: &str) -> Result<FileData> {
+        match self.files.lock().unwrap().get(fname) {
+            Some(data) => Ok(data.clone()),
+            None => Err(not_found(fname)),
+        }
+    }
+}
+
+impl Env for MemEnv {
+    fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn SequentialFile>> {
+        Ok(Box::new(MemSequentialFile {
+            data: self.file(fname)?,
+            pos: 0,
+        }))
+    }
+
+    fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn RandomAccessFile>> {
+        Ok(Box::new(MemRandomAccessFile { data: self.file(fname)? }))
+    }
+
+    fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        // Readers of a replaced file keep its old contents.
+        let data = FileData::default();
+        self.files.lock().unwrap().insert(fname.to_string(), data.clone());
+        Ok(Box::new(MemWritableFile { data: Some(data) }))
+    }
+
+    fn file_exists(&self, fname: &str) -> bool { self.files.lock().unwrap().contains_key(fname) }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>> {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        let mut children: Vec<String> = self
+            .files
+            .lock()
+            .unwrap()
+            .keys()
+            .filter_map(|fname| fname.strip_prefix(&prefix))
+            .map(|rest| rest.split('/').next().unwrap_or(rest).to_string())
+            .collect();
+        children.sort();
+        children.dedup();
+        Ok(children)
+    }
+
+    fn remove_file(&self, fname: &str) -> Result<()> {
+        match self.files.lock().unwrap().remove(fname) {
+            Some(_) => Ok(()),
+            None => Err(not_found(fname)),
+        }
+    }
+
+    fn create_dir(&self, _dirname: &str) -> Result<()> { Ok(()) }
+
+    fn remove_dir(&self, _dirname: &str) -> Result<()> { Ok(()) }
+
+    fn get_file_size(&self, fname: &str) -> Result<u64> { Ok(self.file(fname)?.read().unwrap().len() as u64) }
+
+    fn rename_file(&self, src: &str, target: &str) -> Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let data = files.remove(src).ok_or_else(|| not_found(src))?;
+        files.insert(target.to_string(), data);
+        Ok(())
+    }
+
+    fn link_file(&self, src: &str, target: &str) -> Result<()> {
+        let mut files = self.files.lock().unwrap();
+        if files.contains_key(target) {
+            return Err(Error::io_error("file already exists").with_detail(target));
+        }
+        let data = files.get(src).ok_or_else(|| not_found(src))?.clone();
+        files.insert(target.to_string(), data);
+        Ok(())
+    }
+
+    fn new_logger(&self, fname: &str) -> Result<Box<dyn Logger>> {
+        // Timestamps come from the virtual clock rather than the system clock, which
+        // some targets do not have.
+        let file = self.new_writable_file(fname)?;
+        let now_micros = self.now_micros.clone();
+        Ok(Box::new(FileLogger::with_clock(file, move || now_micros.load(Ordering::Relaxed))))
+    }
+
+    fn lock_file(&self, fname: &str) -> Result<Box<dyn FileLock>> {
+        if !self.locked.lock().unwrap().insert(fname.to_string()) {
+            return LEVELDB_ERR!(IOError, "lock already held");
+        }
+        self.files.lock().unwrap().entry(fname.to_string()).or_default();
+        Ok(Box::new(MemFileLock {
+            locked: self.locked.clone(),
+            fname: fname.to_string(),
+        }))
+    }
+
+    fn schedule(&self, job: Job, _priority: Priority) { job() }
+
+    fn set_background_threads(&self, _n: usize, _priority: Priority) {}
+
+    fn now_micros(&self) -> u64 { self.now_micros.load(Ordering::Relaxed) }
+
+    fn sleep_for_microseconds(&self, micros: u64) { self.now_micros.fetch_add(micros, Ordering::Relaxed); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{KeyRange, DB},
+        leveldb::{
+            env::{read_file_to_string, write_string_to_file},
+            options::{FlushOptions, Options, ReadOptions, WriteOptions},
+        },
+        util::logger::system_clock_reads,
+    };
+
+    #[test]
+    fn files() {
+        let env = MemEnv::new();
+        assert!(env.new_sequential_file("/dir/f").err().unwrap().is_not_found());
+        write_string_to_file(&env, b"hello world", "/dir/f").unwrap();
+        assert!(env.file_exists("/dir/f"));
+        assert_eq!(env.get_file_size("/dir/f").unwrap(), 11);
+        assert_eq!(read_file_to_string(&env, "/dir/f").unwrap(), b"hello world");
+
+        let file = env.new_random_access_file("/dir/f").unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(file.read(6, &mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"world");
+
+        env.link_file("/dir/f", "/dir/sub/g").unwrap();
+        env.rename_file("/dir/f", "/dir/h").unwrap();
+        assert_eq!(env.get_children("/dir").unwrap(), vec!["h", "sub"]);
+        env.remove_file("/dir/h").unwrap();
+        assert_eq!(read_file_to_string(&env, "/dir/sub/g").unwrap(), b"hello world");
+        assert!(env.remove_file("/dir/h").unwrap_err().is_not_found());
+
+        let lock = env.lock_file("/dir/LOCK").unwrap();
+        assert!(env.lock_file("/dir/LOCK").is_err());
+        env.unlock_file(lock).unwrap();
+        env.lock_file("/dir/LOCK").unwrap();
+
+        env.sleep_for_microseconds(250);
+        assert_eq!(env.now_micros(), 250);
+    }
+
+    #[test]
+    fn db_round_trip() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let options = Options {
+            env: env.clone(),
+            create_if_missing: true,
+            ..Default::default()
+        };
+        let db = DB::open(options.clone(), "/db").unwrap();
+        for i in 0..1000 {
+            db.put(&WriteOptions::default(), format!("key{:04}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        db.flush(&FlushOptions::default()).unwrap();
+        db.put(&WriteOptions::default(), b"key0500", b"updated").unwrap();
+        drop(db);
+        assert!(env.get_children("/db").unwrap().iter().any(|f| f.ends_with(".ldb")));
+
+        let db = DB::open(options, "/db").unwrap();
+        assert_eq!(db.get(&ReadOptions::default(), b"key0500").unwrap().unwrap(), b"updated");
+        assert_eq!(db.get(&ReadOptions::default(), b"key0999").unwrap().unwrap(), b"value");
+        let mut iter = db.new_iterator(&ReadOptions::default());
+        iter.seek_to_first();
+        let mut n = 0;
+        while iter.valid() {
+            n += 1;
+            iter.next();
+        }
+        assert_eq!(n, 1000);
+    }
+
+    #[test]
+    fn no_system_clock() {
+        let env = Arc::new(MemEnv::new());
+        env.sleep_for_microseconds(1_500_000);
+        let options = Options {
+            env: env.clone(),
+            create_if_missing: true,
+            write_buffer_size: 16 << 10,
+            ..Default::default()
+        };
+        let reads = system_clock_reads();
+        let db = DB::open(options.clone(), "/db").unwrap();
+        for i in 0..2000 {
+            db.put(&WriteOptions::default(), format!("key{:05}", i).as_bytes(), &[b'v'; 100])
+                .unwrap();
+        }
+        db.flush(&FlushOptions::default()).unwrap();
+        db.compact_range(&KeyRange::all()).unwrap();
+        drop(db);
+        DB::open(options, "/db").unwrap();
+        assert_eq!(system_clock_reads(), reads);
+
+        // The LOG is timestamped with the virtual clock.
+        let log = String::from_utf8(read_file_to_string(env.as_ref(), "/db/LOG.old").unwrap()).unwrap();
+        assert!(log.lines().count() > 1);
+        assert!(log.lines().all(|line| line.starts_with("1970/01/01-00:00:01.5")), "{}", log);
+    }
+}
@@ -0,0 +1,115 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::sync::Arc;
+
+use crate::leveldb::merge_operator::MergeOperator;
+
+struct U64AddOperator;
+
+impl U64AddOperator {
+    fn decode(v: &[u8]) -> Option<u64> { Some(u64::from_le_bytes(v.try_into().ok()?)) }
+}
+
+impl MergeOperator for U64AddOperator {
+    fn name(&self) -> &str { "hefesto.U64AddOperator" }
+
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Option<Vec<u8>> {
+        let mut sum = match existing {
+            Some(v) => Self::decode(v)?,
+            None => 0,
+        };
+        for op in operands {
+            sum = sum.wrapping_add(Self::decode(op)?);
+        }
+        Some(sum.to_le_bytes().to_vec())
+    }
+
+    fn partial_merge(&self, _key: &[u8], left: &[u8], right: &[u8]) -> Option<Vec<u8>> {
+        let sum = Self::decode(left)?.wrapping_add(Self::decode(right)?);
+        Some(sum.to_le_bytes().to_vec())
+    }
+}
+
+/// Return a merge operator that treats values and operands as 8-byte little-endian
+/// unsigned integers and adds them up, wrapping on overflow. A missing value counts as
+/// zero; a value or operand of any other length fails the merge.
+pub fn new_u64_add_operator() -> Arc<dyn MergeOperator> { Arc::new(U64AddOperator) }
+
+struct StringAppendOperator {
+    delimiter: u8,
+}
+
+impl MergeOperator for StringAppendOperator {
+    fn name(&self) -> &str { "hefesto.StringAppendOperator" }
+
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Option<Vec<u8>> {
+        let mut result = Vec::new();
+        let parts = existing.into_iter().chain(operands.iter().copied());
+        for (i, part) in parts.enumerate() {
+            if i > 0 {
+                result.push(self.delimiter);
+            }
+            result.extend_from_slice(part);
+        }
+        Some(result)
+    }
+
+    fn partial_merge(&self, _key: &[u8], left: &[u8], right: &[u8]) -> Option<Vec<u8>> {
+        let mut result = Vec::with_capacity(left.len() + 1 + right.len());
+        result.extend_from_slice(left);
+        result.push(self.delimiter);
+        result.extend_from_slice(right);
+        Some(result)
+    }
+}
+
+/// Return a merge operator that appends each operand to the existing value, separated
+/// by `delimiter`.
+pub fn new_string_append_operator(delimiter: u8) -> Arc<dyn MergeOperator> {
+    Arc::new(StringAppendOperator { delimiter })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u64_add() {
+        let op = new_u64_add_operator();
+        let one = 1u64.to_le_bytes();
+        let five = 5u64.to_le_bytes();
+        assert_eq!(op.full_merge(b"k", None, &[&one, &five]), Some(6u64.to_le_bytes().to_vec()));
+        assert_eq!(op.full_merge(b"k", Some(&five), &[&one]), Some(6u64.to_le_bytes().to_vec()));
+        assert_eq!(op.partial_merge(b"k", &one, &five), Some(6u64.to_le_bytes().to_vec()));
+        assert_eq!(op.full_merge(b"k", Some(b"bad"), &[&one]), None);
+        assert_eq!(op.partial_merge(b"k", &one, b"bad"), None);
+    }
+
+    #[test]
+    fn string_append() {
+        let op = new_string_append_operator(b',');
+        assert_eq!(op.full_merge(b"k", None, &[b"a", b"b"]), Some(b"a,b".to_vec()));
+        assert_eq!(op.full_merge(b"k", Some(b"x"), &[b"a"]), Some(b"x,a".to_vec()));
+        assert_eq!(op.full_merge(b"k", Some(b"x"), &[]), Some(b"x".to_vec()));
+        assert_eq!(op.partial_merge(b"k", b"a", b"b"), Some(b"a,b".to_vec()));
+    }
+}
@@ -20,9 +20,32 @@
 // SOFTWARE.
 
 pub mod random;
+pub mod distribution;
+pub mod histogram;
 pub mod arena;
+pub mod concurrent_arena;
 pub mod crc32c;
+pub mod hash;
 pub mod atomic;
 pub mod bit;
 pub mod cache;
+pub mod clock_cache;
 pub mod coding;
+pub mod bloom;
+pub mod comparator;
+pub mod merge_operators;
+pub mod slice_transform;
+#[cfg(feature = "std")]
+pub mod env_posix;
+#[cfg(all(feature = "io_uring", target_os = "linux", target_pointer_width = "64"))]
+pub mod env_uring;
+pub mod mem_env;
+pub mod encrypted_env;
+pub mod fault_injection_env;
+pub mod logger;
+pub mod slow_env;
+pub mod lz4;
+pub mod zstd;
+pub mod xxhash;
+#[cfg(test)]
+pub mod testutil;
@@ -70,7 +70,7 @@ impl Random {
 
     /// Randomly returns true ~ "1/n" of the time. False otherwise.
     #[inline(always)]
-    pub fn one_in(&self, n: u32) -> bool { self.next() % n == 0 }
+    pub fn one_in(&self, n: u32) -> bool { self.next().is_multiple_of(n) }
 
     /// Skewed: this first pick "base" uniformly from range `[0, max_log]`,
     /// and then return "base" random bits. The effect is to pick a random
@@ -81,6 +81,79 @@ impl Random {
         let r = 1 << self.uniform(max_log + 1);
         self.uniform(r)
     }
+
+    /// Fill `buf` with random bytes, three per generated number since the generator
+    /// only yields 31 bits.
+    pub fn fill_bytes(&self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(3) {
+            let bytes = self.next().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// A random number generator with 64-bit output and a 2^64-1 period, based on
+/// xorshift64*. Unlike `Random`, it can produce large values such as 64-bit sequence
+/// numbers or offsets, and it is fast enough to fill big buffers.
+pub struct Random64 {
+    state: Cell<u64>,
+}
+
+impl Random64 {
+    pub fn new(seed: u64) -> Self {
+        // Scramble the seed with splitmix64, so that similar seeds give unrelated
+        // sequences and no seed leads to the all-zero state.
+        let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        Self {
+            state: Cell::new(if z == 0 { 1 } else { z }),
+        }
+    }
+
+    /// Return the next random number in this generator
+    pub fn next(&self) -> u64 {
+        let mut x = self.state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state.set(x);
+        x.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Returns a uniformly distributed value in the range `[0..n)`
+    #[inline(always)]
+    pub fn uniform(&self, n: u64) -> u64 {
+        assert!(n > 0);
+        ((self.next() as u128 * n as u128) >> 64) as u64
+    }
+
+    /// Returns a uniformly distributed value in the range `[0, 1)`
+    #[inline(always)]
+    pub fn next_f64(&self) -> f64 { (self.next() >> 11) as f64 / (1u64 << 53) as f64 }
+
+    /// Randomly returns true ~ "1/n" of the time. False otherwise.
+    #[inline(always)]
+    pub fn one_in(&self, n: u64) -> bool { self.uniform(n) == 0 }
+
+    /// Pick a random number in the range `[0, 2^max_log)` with exponential bias towards
+    /// smaller numbers, as `Random::skewed()`.
+    /// REQUIRES: max_log < 64
+    #[inline(always)]
+    pub fn skewed(&self, max_log: u32) -> u64 {
+        assert!(max_log < 64);
+        let r = 1 << self.uniform(max_log as u64 + 1);
+        self.uniform(r)
+    }
+
+    /// Fill `buf` with random bytes.
+    pub fn fill_bytes(&self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -99,4 +172,41 @@ mod tests {
         assert_eq!(rnd.uniform(10), 7);
         assert_eq!(rnd.skewed(2), 1);
     }
+
+    #[test]
+    fn random64() {
+        let a = Random64::new(0);
+        let b = Random64::new(0);
+        let c = Random64::new(1);
+        let first: Vec<u64> = (0..10).map(|_| a.next()).collect();
+        assert_eq!(first, (0..10).map(|_| b.next()).collect::<Vec<_>>());
+        assert_ne!(first, (0..10).map(|_| c.next()).collect::<Vec<_>>());
+        // Values span the whole 64-bit range.
+        assert!((0..100).any(|_| a.next() > u32::MAX as u64));
+
+        for _ in 0..1000 {
+            assert!(a.uniform(1 << 40) < 1 << 40);
+            assert!(a.skewed(50) < 1 << 50);
+            let f = a.next_f64();
+            assert!((0.0..1.0).contains(&f));
+        }
+        let hits = (0..10000).filter(|_| a.one_in(10)).count();
+        assert!((800..1200).contains(&hits));
+    }
+
+    #[test]
+    fn fill_bytes() {
+        for len in [0, 1, 7, 8, 9, 1000] {
+            let mut buf = vec![0u8; len];
+            Random64::new(len as u64).fill_bytes(&mut buf);
+            if len >= 8 {
+                assert!(buf.iter().filter(|&&b| b == 0).count() < len / 8 + 2);
+            }
+            let mut buf = vec![0u8; len];
+            Random::new(len as u32 + 1).fill_bytes(&mut buf);
+            if len >= 8 {
+                assert!(buf.iter().filter(|&&b| b == 0).count() < len / 8 + 2);
+            }
+        }
+    }
 }
@@ -0,0 +1,66 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{borrow::Cow, sync::Arc};
+
+use crate::leveldb::slice_transform::SliceTransform;
+
+/// Maps keys to their first `len` bytes. Shorter keys have no prefix.
+struct FixedPrefixTransform {
+    len: usize,
+    name: String,
+}
+
+impl SliceTransform for FixedPrefixTransform {
+    fn name(&self) -> &str { &self.name }
+
+    fn transform<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        assert!(self.in_domain(key));
+        Cow::Borrowed(&key[..self.len])
+    }
+
+    fn in_domain(&self, key: &[u8]) -> bool { key.len() >= self.len }
+}
+
+/// Return a prefix extractor that uses the first `len` bytes of each key as its
+/// prefix, e.g. a fixed-width tenant id at the start of every key.
+pub fn new_fixed_prefix_transform(len: usize) -> Arc<dyn SliceTransform> {
+    Arc::new(FixedPrefixTransform {
+        len,
+        name: format!("hefesto.FixedPrefix.{}", len),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_prefix() {
+        let t = new_fixed_prefix_transform(3);
+        assert_eq!(t.name(), "hefesto.FixedPrefix.3");
+        assert!(t.in_domain(b"abc"));
+        assert!(t.in_domain(b"abcdef"));
+        assert!(!t.in_domain(b"ab"));
+        assert_eq!(&*t.transform(b"abcdef"), b"abc");
+        assert_eq!(&*t.transform(b"abc"), b"abc");
+    }
+}
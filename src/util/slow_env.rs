@@ -0,0 +1,419 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    db::filename::{parse_file_name, FileType},
+    leveldb::{
+        env::{Env, FileLock, Job, Priority, RandomAccessFile, SequentialFile, WritableFile},
+        result::Result,
+    },
+    util::random::Random,
+};
+
+/// The delay added to each read, append or sync of a file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Latency {
+    #[default]
+    None,
+    Fixed(Duration),
+    /// Uniformly distributed in `[min, max]`.
+    Uniform { min: Duration, max: Duration },
+    /// Usually `base`, but `spike` about once in `one_in` operations, like a disk that
+    /// stalls now and then.
+    Spiky { base: Duration, spike: Duration, one_in: u32 },
+}
+
+impl Latency {
+    fn sample(&self, rnd: &Random) -> Duration {
+        match *self {
+            Latency::None => Duration::ZERO,
+            Latency::Fixed(d) => d,
+            Latency::Uniform { min, max } => {
+                let range = max.saturating_sub(min).as_micros().min(u32::MAX as u128 - 1) as u32;
+                min + Duration::from_micros(rnd.uniform(range + 1) as u64)
+            }
+            Latency::Spiky { base, spike, one_in } => {
+                if one_in > 0 && rnd.one_in(one_in) {
+                    spike
+                } else {
+                    base
+                }
+            }
+        }
+    }
+}
+
+/// How I/O on one kind of file is slowed down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoProfile {
+    pub latency: Latency,
+    /// If set, reads and writes of this kind of file together proceed at no more than
+    /// this many bytes per second.
+    pub bytes_per_second: Option<u64>,
+}
+
+/// Options for `SlowEnv`. Files are classified by name, so they only apply to files
+/// named the way the database names them.
+#[derive(Debug, Clone, Default)]
+pub struct SlowEnvOptions {
+    /// Write-ahead log files ("*.log").
+    pub wal: IoProfile,
+    /// Table files ("*.ldb" and "*.sst").
+    pub table: IoProfile,
+    /// Descriptor files ("MANIFEST-*").
+    pub manifest: IoProfile,
+    /// Everything else, e.g. CURRENT and temporary files.
+    pub other: IoProfile,
+    /// Seed for the latency distributions, so that runs are reproducible.
+    pub seed: u32,
+}
+
+/// The throughput budget of one kind of file.
+struct Throttle {
+    bytes_per_second: Option<u64>,
+    // When the I/O admitted so far will have completed.
+    busy_until: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    /// Reserve time for `bytes` and return how long the caller must wait for them.
+    fn reserve(&self, bytes: usize) -> Duration {
+        let rate = match self.bytes_per_second {
+            Some(rate) if rate > 0 && bytes > 0 => rate,
+            _ => return Duration::ZERO,
+        };
+        let now = Instant::now();
+        let mut busy_until = self.busy_until.lock().unwrap();
+        let start = busy_until.filter(|t| *t > now).unwrap_or(now);
+        let done = start + Duration::from_micros(bytes as u64 * 1_000_000 / rate);
+        *busy_until = Some(done);
+        done - now
+    }
+}
+
+struct Shared {
+    profiles: [(IoProfile, Throttle); 4],
+    rnd: Mutex<Random>,
+    injected_micros: AtomicU64,
+}
+
+impl Shared {
+    /// Sleep for the latency of one operation that moves `bytes` bytes of a file of
+    /// class `class`.
+    fn delay(&self, class: usize, bytes: usize) {
+        let (profile, throttle) = &self.profiles[class];
+        let mut delay = throttle.reserve(bytes);
+        if profile.latency != Latency::None {
+            delay += profile.latency.sample(&self.rnd.lock().unwrap());
+        }
+        if !delay.is_zero() {
+            self.injected_micros
+                .fetch_add(delay.as_micros() as u64, Ordering::Relaxed);
+            thread::sleep(delay);
+        }
+    }
+}
+
+const WAL: usize = 0;
+const TABLE: usize = 1;
+const MANIFEST: usize = 2;
+const OTHER: usize = 3;
+
+fn classify(fname: &str) -> usize {
+    let basename = fname.rsplit('/').next().unwrap_or(fname);
+    match parse_file_name(basename) {
+        Some((_, FileType::LogFile)) => WAL,
+        Some((_, FileType::TableFile)) => TABLE,
+        Some((_, FileType::DescriptorFile)) => MANIFEST,
+        _ => OTHER,
+    }
+}
+
+/// An Env that forwards to another Env but makes file I/O slower, to reproduce slow
+/// disk pathologies such as write stalls and compaction debt in tests. Reads, appends
+/// and syncs each pay the configured latency of the file's kind, and the bytes they
+/// move count against its throughput cap. Other operations are not slowed down.
+pub struct SlowEnv {
+    target: Arc<dyn Env>,
+    shared: Arc<Shared>,
+}
+
+impl SlowEnv {
+    pub fn new(target: Arc<dyn Env>, options: SlowEnvOptions) -> Self {
+        let entry = |profile: IoProfile| {
+            (
+                profile,
+                Throttle {
+                    bytes_per_second: profile.bytes_per_second,
+                    busy_until: Mutex::new(None),
+                },
+            )
+        };
+        Self {
+            target,
+            shared: Arc::new(Shared {
+                profiles: [
+                    entry(options.wal),
+                    entry(options.table),
+                    entry(options.manifest),
+                    entry(options.other),
+                ],
+                rnd: Mutex::new(Random::new(options.seed)),
+                injected_micros: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Total time spent sleeping in injected delays so far.
+    pub fn injected_delay(&self) -> Duration {
+        Duration::from_micros(self.shared.injected_micros.load(Ordering::Relaxed))
+    }
+}
+
+struct SlowSequentialFile {
+    file: Box<dyn SequentialFile>,
+    shared: Arc<Shared>,
+    class: usize,
+}
+
+impl SequentialFile for SlowSequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.file.read(buf)?;
+        self.shared.delay(self.class, n);
+        Ok(n)
+    }
+
+    fn skip(&mut self, n: u64) -> Result<()> { self.file.skip(n) }
+}
+
+struct SlowRandomAccessFile {
+    file: Box<dyn RandomAccessFile>,
+    shared: Arc<Shared>,
+    class: usize,
+}
+
+impl RandomAccessFile for SlowRandomAccessFile {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let n = self.file.read(offset, buf)?;
+        self.shared.delay(self.class, n);
+        Ok(n)
+    }
+
+    fn prefetch(&self, offset: u64, len: usize) { self.file.prefetch(offset, len) }
+}
+
+struct SlowWritableFile {
+    file: Box<dyn WritableFile>,
+    shared: Arc<Shared>,
+    class: usize,
+}
+
+impl WritableFile for SlowWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.shared.delay(self.class, data.len());
+        self.file.append(data)
+    }
+
+    fn close(&mut self) -> Result<()> { self.file.close() }
+
+    fn flush(&mut self) -> Result<()> { self.file.flush() }
+
+    fn sync(&mut self) -> Result<()> {
+        self.shared.delay(self.class, 0);
+        self.file.sync()
+    }
+
+    fn set_preallocation_block_size(&mut self, size: usize) { self.file.set_preallocation_block_size(size) }
+
+    fn set_bytes_per_sync(&mut self, bytes: usize) { self.file.set_bytes_per_sync(bytes) }
+}
+
+impl Env for SlowEnv {
+    fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn SequentialFile>> {
+        Ok(Box::new(SlowSequentialFile {
+            file: self.target.new_sequential_file(fname)?,
+            shared: self.shared.clone(),
+            class: classify(fname),
+        }))
+    }
+
+    fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn RandomAccessFile>> {
+        Ok(Box::new(SlowRandomAccessFile {
+            file: self.target.new_random_access_file(fname)?,
+            shared: self.shared.clone(),
+            class: classify(fname),
+        }))
+    }
+
+    fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        Ok(Box::new(SlowWritableFile {
+            file: self.target.new_writable_file(fname)?,
+            shared: self.shared.clone(),
+            class: classify(fname),
+        }))
+    }
+
+    fn file_exists(&self, fname: &str) -> bool { self.target.file_exists(fname) }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>> { self.target.get_children(dir) }
+
+    fn remove_file(&self, fname: &str) -> Result<()> { self.target.remove_file(fname) }
+
+    fn create_dir(&self, dirname: &str) -> Result<()> { self.target.create_dir(dirname) }
+
+    fn remove_dir(&self, dirname: &str) -> Result<()> { self.target.remove_dir(dirname) }
+
+    fn get_file_size(&self, fname: &str) -> Result<u64> { self.target.get_file_size(fname) }
+
+    fn rename_file(&self, src: &str, target: &str) -> Result<()> { self.target.rename_file(src, target) }
+
+    fn link_file(&self, src: &str, target: &str) -> Result<()> { self.target.link_file(src, target) }
+
+    fn lock_file(&self, fname: &str) -> Result<Box<dyn FileLock>> { self.target.lock_file(fname) }
+
+    fn schedule(&self, job: Job, priority: Priority) { self.target.schedule(job, priority) }
+
+    fn set_background_threads(&self, n: usize, priority: Priority) { self.target.set_background_threads(n, priority) }
+
+    fn now_micros(&self) -> u64 { self.target.now_micros() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::DB,
+        leveldb::{
+            env::default_env,
+            options::{Options, WriteOptions},
+        },
+        util::testutil::tmp_dir,
+    };
+
+    #[test]
+    fn latency_per_file_type() {
+        let dir = tmp_dir("slow_env_latency");
+        let env = SlowEnv::new(
+            default_env(),
+            SlowEnvOptions {
+                table: IoProfile {
+                    latency: Latency::Fixed(Duration::from_millis(2)),
+                    bytes_per_second: None,
+                },
+                ..SlowEnvOptions::default()
+            },
+        );
+
+        let mut wal = env.new_writable_file(&format!("{}/000003.log", dir)).expect("create");
+        wal.append(b"record").expect("append");
+        wal.sync().expect("sync");
+        assert_eq!(env.injected_delay(), Duration::ZERO);
+
+        let fname = format!("{}/000004.ldb", dir);
+        let mut table = env.new_writable_file(&fname).expect("create");
+        table.append(b"block").expect("append");
+        table.sync().expect("sync");
+        table.close().expect("close");
+        let mut buf = [0u8; 5];
+        assert_eq!(env.new_random_access_file(&fname).expect("open").read(0, &mut buf).expect("read"), 5);
+        assert_eq!(&buf, b"block");
+        assert_eq!(env.injected_delay(), Duration::from_millis(6));
+    }
+
+    #[test]
+    fn throughput_cap() {
+        let dir = tmp_dir("slow_env_throughput");
+        let env = SlowEnv::new(
+            default_env(),
+            SlowEnvOptions {
+                manifest: IoProfile {
+                    latency: Latency::None,
+                    bytes_per_second: Some(1 << 20),
+                },
+                ..SlowEnvOptions::default()
+            },
+        );
+        let start = Instant::now();
+        let mut file = env.new_writable_file(&format!("{}/MANIFEST-000002", dir)).expect("create");
+        for _ in 0..10 {
+            file.append(&[0u8; 1024]).expect("append");
+        }
+        // 10KB at 1MB/s takes about 10ms.
+        assert!(start.elapsed() >= Duration::from_micros(9000));
+        assert!(env.injected_delay() >= Duration::from_micros(9000));
+    }
+
+    #[test]
+    fn latency_distributions() {
+        let rnd = Random::new(301);
+        let (min, max) = (Duration::from_micros(100), Duration::from_micros(200));
+        for _ in 0..100 {
+            let d = Latency::Uniform { min, max }.sample(&rnd);
+            assert!(d >= min && d <= max);
+        }
+        let spiky = Latency::Spiky {
+            base: Duration::ZERO,
+            spike: Duration::from_secs(1),
+            one_in: 10,
+        };
+        let spikes = (0..1000).filter(|_| !spiky.sample(&rnd).is_zero()).count();
+        assert!(spikes > 50 && spikes < 200, "{}", spikes);
+    }
+
+    #[test]
+    fn slow_wal() {
+        let dir = tmp_dir("slow_env_db");
+        let env = Arc::new(SlowEnv::new(
+            default_env(),
+            SlowEnvOptions {
+                wal: IoProfile {
+                    latency: Latency::Fixed(Duration::from_millis(1)),
+                    bytes_per_second: None,
+                },
+                ..SlowEnvOptions::default()
+            },
+        ));
+        let options = Options {
+            env: env.clone(),
+            create_if_missing: true,
+            ..Options::default()
+        };
+        let db = DB::open(options, &dir).expect("open");
+        let delay_of = |options: &WriteOptions| {
+            let before = env.injected_delay();
+            db.put(options, b"key", b"value").expect("put");
+            env.injected_delay() - before
+        };
+        let unsynced = delay_of(&WriteOptions::default());
+        assert!(unsynced > Duration::ZERO);
+        // A synced write also pays for the sync.
+        assert_eq!(delay_of(&WriteOptions { sync: true }), unsynced + Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,173 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// xxHash64, a fast non-cryptographic hash by Yann Collet, used to checksum table
+// blocks when `Options::checksum` is `ChecksumType::XxHash64`. The output matches
+// the reference implementation (XXH64) for every seed.
+
+const PRIME_1: u64 = 0x9e3779b185ebca87;
+const PRIME_2: u64 = 0xc2b2ae3d27d4eb4f;
+const PRIME_3: u64 = 0x165667b19e3779f9;
+const PRIME_4: u64 = 0x85ebca77c2b2ae63;
+const PRIME_5: u64 = 0x27d4eb2f165667c5;
+
+const STRIPE: usize = 32;
+
+fn read_u64(data: &[u8]) -> u64 { u64::from_le_bytes(data[..8].try_into().unwrap()) }
+
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME_2)).rotate_left(31).wrapping_mul(PRIME_1)
+}
+
+fn merge_round(acc: u64, val: u64) -> u64 { (acc ^ round(0, val)).wrapping_mul(PRIME_1).wrapping_add(PRIME_4) }
+
+/// Incremental xxHash64 of the concatenation of everything passed to `update()`.
+#[derive(Clone)]
+pub struct XxHash64 {
+    seed: u64,
+    total_len: u64,
+    acc: [u64; 4],
+    // Input not yet consumed by a full stripe
+    buf: [u8; STRIPE],
+    buf_len: usize,
+}
+
+impl XxHash64 {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            total_len: 0,
+            acc: [
+                seed.wrapping_add(PRIME_1).wrapping_add(PRIME_2),
+                seed.wrapping_add(PRIME_2),
+                seed,
+                seed.wrapping_sub(PRIME_1),
+            ],
+            buf: [0; STRIPE],
+            buf_len: 0,
+        }
+    }
+
+    fn consume_stripe(acc: &mut [u64; 4], stripe: &[u8]) {
+        for (i, a) in acc.iter_mut().enumerate() {
+            *a = round(*a, read_u64(&stripe[8 * i..]));
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        if self.buf_len > 0 {
+            let n = (STRIPE - self.buf_len).min(data.len());
+            self.buf[self.buf_len..self.buf_len + n].copy_from_slice(&data[..n]);
+            self.buf_len += n;
+            data = &data[n..];
+            if self.buf_len < STRIPE {
+                return;
+            }
+            Self::consume_stripe(&mut self.acc, &self.buf);
+            self.buf_len = 0;
+        }
+        let mut stripes = data.chunks_exact(STRIPE);
+        for stripe in &mut stripes {
+            Self::consume_stripe(&mut self.acc, stripe);
+        }
+        let rest = stripes.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+
+    pub fn finish(&self) -> u64 {
+        let mut h = if self.total_len >= STRIPE as u64 {
+            let [v1, v2, v3, v4] = self.acc;
+            let mut h = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for v in self.acc {
+                h = merge_round(h, v);
+            }
+            h
+        } else {
+            self.seed.wrapping_add(PRIME_5)
+        };
+        h = h.wrapping_add(self.total_len);
+
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 8 {
+            h ^= round(0, read_u64(rest));
+            h = h.rotate_left(27).wrapping_mul(PRIME_1).wrapping_add(PRIME_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let k = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+            h ^= k.wrapping_mul(PRIME_1);
+            h = h.rotate_left(23).wrapping_mul(PRIME_2).wrapping_add(PRIME_3);
+            rest = &rest[4..];
+        }
+        for &b in rest {
+            h ^= (b as u64).wrapping_mul(PRIME_5);
+            h = h.rotate_left(11).wrapping_mul(PRIME_1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(PRIME_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(PRIME_3);
+        h ^ (h >> 32)
+    }
+}
+
+/// Return the xxHash64 of `data` with the given seed.
+pub fn hash64(data: &[u8], seed: u64) -> u64 {
+    let mut hasher = XxHash64::new(seed);
+    hasher.update(data);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_values() {
+        assert_eq!(hash64(b"", 0), 0xef46db3751d8e999);
+        assert_eq!(hash64(b"a", 0), 0xd24ec4f1a98c6e5b);
+        assert_eq!(hash64(b"abc", 0), 0x44bc2cf5ad770999);
+        assert_eq!(hash64(b"Nobody inspects the spammish repetition", 0), 0xfbcea83c8a378bf1);
+    }
+
+    #[test]
+    fn incremental() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 + i / 13) as u8).collect();
+        for len in [0, 3, 31, 32, 33, 100, 1000] {
+            let expected = hash64(&data[..len], 42);
+            for split in [1, 5, 17, 32] {
+                let mut hasher = XxHash64::new(42);
+                for chunk in data[..len].chunks(split) {
+                    hasher.update(chunk);
+                }
+                assert_eq!(hasher.finish(), expected, "len {} split {}", len, split);
+            }
+        }
+        assert_ne!(hash64(b"abc", 0), hash64(b"abc", 1));
+    }
+}
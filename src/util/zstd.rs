@@ -0,0 +1,1743 @@
+// MIT License
+//
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// A self-contained implementation of the Zstandard compression format (RFC 8878),
+// used for table blocks compressed with `CompressionType::Zstd`.
+//
+// The decoder handles every feature of single frames: raw, RLE and compressed blocks,
+// Huffman-coded literals with one or four streams, and all sequence table modes.
+// Dictionaries are supported in their raw content form only, where the dictionary is
+// content that matches may refer to as if it preceded the frame. The encoder is
+// simpler: it finds matches with a hash chain whose search depth grows with the
+// compression level, and higher levels also defer a match by a byte when that gives
+// a longer one. Literals are Huffman coded when that makes them smaller, and
+// sequences are coded with the predefined FSE tables. Its output is a standard frame
+// that any Zstandard decoder accepts, given the same dictionary.
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use crate::leveldb::result::Result;
+
+const MAGIC_NUMBER: u32 = 0xFD2F_B528;
+const SKIPPABLE_MAGIC_MASK: u32 = 0xFFFF_FFF0;
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+
+const MAX_BLOCK_SIZE: usize = 128 * 1024;
+const MIN_MATCH: usize = 4;
+
+const BLOCK_RAW: u32 = 0;
+const BLOCK_RLE: u32 = 1;
+const BLOCK_COMPRESSED: u32 = 2;
+
+const LITERALS_RAW: u8 = 0;
+const LITERALS_RLE: u8 = 1;
+const LITERALS_COMPRESSED: u8 = 2;
+
+const MODE_PREDEFINED: u8 = 0;
+const MODE_RLE: u8 = 1;
+const MODE_FSE: u8 = 2;
+
+const HUFFMAN_MAX_BITS: u32 = 11;
+
+// Baselines and number of extra bits of the literal length codes.
+const LL_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64, 128, 256, 512,
+    1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LL_BITS: [u8; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+
+// Baselines and number of extra bits of the match length codes.
+const ML_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32,
+    33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027, 2051, 4099, 8195, 16387, 32771, 65539,
+];
+const ML_BITS: [u8; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3,
+    3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+
+// Predefined distributions of the sequence codes.
+const LL_DEFAULT_LOG: u32 = 6;
+const LL_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1, -1, -1, -1, -1,
+];
+const ML_DEFAULT_LOG: u32 = 6;
+const ML_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OF_DEFAULT_LOG: u32 = 5;
+const OF_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+// Maximum accuracy logs of the FSE tables.
+const LL_MAX_LOG: u32 = 9;
+const ML_MAX_LOG: u32 = 9;
+const OF_MAX_LOG: u32 = 8;
+const HUFFMAN_WEIGHTS_MAX_LOG: u32 = 6;
+
+fn highest_bit(v: u32) -> u32 {
+    debug_assert!(v > 0);
+    31 - v.leading_zeros()
+}
+
+/// Return the code whose baseline is the largest one not exceeding `value`.
+fn code_for(base: &[u32], value: u32) -> usize { base.partition_point(|&b| b <= value) - 1 }
+
+// ---------------------------------------------------------------------------------
+// Bit streams
+
+/// Reads a bit stream from its start, least significant bits first.
+struct ForwardBitReader<'a> {
+    data: &'a [u8],
+    bit_pos: usize,
+}
+
+impl<'a> ForwardBitReader<'a> {
+    fn new(data: &'a [u8]) -> Self { Self { data, bit_pos: 0 } }
+
+    fn read(&mut self, n: u32) -> Result<u32> {
+        let mut value = 0u32;
+        for i in 0..n {
+            let byte = match self.data.get(self.bit_pos / 8) {
+                Some(byte) => *byte,
+                None => return LEVELDB_ERR!(Corruption, "zstd: truncated table description"),
+            };
+            value |= (((byte >> (self.bit_pos % 8)) & 1) as u32) << i;
+            self.bit_pos += 1;
+        }
+        Ok(value)
+    }
+
+    fn rewind(&mut self, n: usize) { self.bit_pos -= n; }
+
+    /// Number of whole bytes touched so far.
+    fn bytes_consumed(&self) -> usize { self.bit_pos.div_ceil(8) }
+}
+
+/// Reads a bit stream from its end. The last byte carries a marker bit above the
+/// final bit written. Bits read past the start of the stream are zeros.
+struct BackwardBitReader<'a> {
+    data: &'a [u8],
+    // Position of the lowest bit not read yet; negative once the stream is overread.
+    bit_pos: i64,
+}
+
+impl<'a> BackwardBitReader<'a> {
+    fn new(data: &'a [u8]) -> Result<Self> {
+        match data.last() {
+            Some(&last) if last != 0 => Ok(Self {
+                data,
+                bit_pos: (data.len() as i64 - 1) * 8 + highest_bit(last as u32) as i64,
+            }),
+            _ => LEVELDB_ERR!(Corruption, "zstd: bad bit stream end marker"),
+        }
+    }
+
+    /// Return the `n` bits starting at bit `pos`, with bits before the stream start
+    /// reading as zeros.
+    fn bits_at(&self, pos: i64, n: u32) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        if pos < 0 {
+            let shift = (-pos) as u32;
+            if shift >= n {
+                return 0;
+            }
+            return self.bits_at(0, n - shift) << shift;
+        }
+        let start = (pos / 8) as usize;
+        let mut word = [0u8; 8];
+        let end = (start + 8).min(self.data.len());
+        word[..end - start].copy_from_slice(&self.data[start..end]);
+        (u64::from_le_bytes(word) >> (pos % 8)) & ((1u64 << n) - 1)
+    }
+
+    fn read(&mut self, n: u32) -> u64 {
+        self.bit_pos -= n as i64;
+        self.bits_at(self.bit_pos, n)
+    }
+
+    fn peek(&self, n: u32) -> u64 { self.bits_at(self.bit_pos - n as i64, n) }
+
+    fn consume(&mut self, n: u32) { self.bit_pos -= n as i64; }
+
+    /// Returns true if more bits were read than the stream holds.
+    fn overread(&self) -> bool { self.bit_pos < 0 }
+
+    fn finished(&self) -> bool { self.bit_pos == 0 }
+}
+
+/// Writes a bit stream that is read back by `BackwardBitReader`.
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            out: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    fn add(&mut self, value: u64, n: u32) {
+        debug_assert!(n <= 56);
+        if n == 0 {
+            return;
+        }
+        self.acc |= (value & ((1u64 << n) - 1)) << self.bits;
+        self.bits += n;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    /// Append the end marker and return the stream.
+    fn finish(mut self) -> Vec<u8> {
+        self.add(1, 1);
+        self.into_bytes()
+    }
+
+    /// Return the bits written so far padded to whole bytes, for streams that are read
+    /// forwards and need no end marker.
+    fn into_bytes(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+// ---------------------------------------------------------------------------------
+// Finite State Entropy tables
+
+#[derive(Clone, Copy, Default)]
+struct FseEntry {
+    symbol: u8,
+    nb_bits: u8,
+    baseline: u16,
+}
+
+#[derive(Clone)]
+struct FseTable {
+    log: u32,
+    entries: Vec<FseEntry>,
+}
+
+/// Lay out the symbols of a normalized distribution in a table of `1 << log` cells,
+/// as both the encoder and the decoder do. Returns the symbol of each cell.
+fn spread_symbols(norm: &[i16], log: u32) -> Result<Vec<u8>> {
+    let size = 1usize << log;
+    let mut symbols = vec![0u8; size];
+    let mut high = size as i64 - 1;
+    for (s, &p) in norm.iter().enumerate() {
+        if p == -1 {
+            if high < 0 {
+                return LEVELDB_ERR!(Corruption, "zstd: bad FSE distribution");
+            }
+            symbols[high as usize] = s as u8;
+            high -= 1;
+        }
+    }
+    let step = (size >> 1) + (size >> 3) + 3;
+    let mask = size - 1;
+    let mut pos = 0;
+    for (s, &p) in norm.iter().enumerate() {
+        for _ in 0..p.max(0) {
+            symbols[pos] = s as u8;
+            loop {
+                pos = (pos + step) & mask;
+                if pos as i64 <= high {
+                    break;
+                }
+            }
+        }
+    }
+    if pos != 0 {
+        return LEVELDB_ERR!(Corruption, "zstd: bad FSE distribution");
+    }
+    Ok(symbols)
+}
+
+impl FseTable {
+    fn from_distribution(norm: &[i16], log: u32) -> Result<FseTable> {
+        let size = 1usize << log;
+        let total: i64 = norm.iter().map(|&p| (p as i64).abs()).sum();
+        if total != size as i64 || norm.len() > 256 {
+            return LEVELDB_ERR!(Corruption, "zstd: bad FSE distribution");
+        }
+        let symbols = spread_symbols(norm, log)?;
+        let mut next: Vec<u32> = norm.iter().map(|&p| if p == -1 { 1 } else { p.max(0) as u32 }).collect();
+        let entries = symbols
+            .iter()
+            .map(|&s| {
+                let state = next[s as usize];
+                next[s as usize] += 1;
+                let nb_bits = log - highest_bit(state);
+                FseEntry {
+                    symbol: s,
+                    nb_bits: nb_bits as u8,
+                    baseline: ((state << nb_bits) - size as u32) as u16,
+                }
+            })
+            .collect();
+        Ok(FseTable { log, entries })
+    }
+
+    fn rle(symbol: u8) -> FseTable {
+        FseTable {
+            log: 0,
+            entries: vec![FseEntry {
+                symbol,
+                nb_bits: 0,
+                baseline: 0,
+            }],
+        }
+    }
+
+    /// Read a table description (RFC 8878 section 4.1.1) from the start of `data`.
+    /// Returns the table and the number of bytes used by the description.
+    fn read_description(data: &[u8], max_log: u32, max_symbol: usize) -> Result<(FseTable, usize)> {
+        let mut reader = ForwardBitReader::new(data);
+        let log = reader.read(4)? + 5;
+        if log > max_log {
+            return LEVELDB_ERR!(Corruption, "zstd: FSE accuracy log too large");
+        }
+        let mut remaining = (1i32 << log) + 1;
+        let mut norm: Vec<i16> = Vec::new();
+        while remaining > 1 {
+            if norm.len() > max_symbol {
+                return LEVELDB_ERR!(Corruption, "zstd: too many FSE symbols");
+            }
+            let bits = highest_bit(remaining as u32) + 1;
+            let lower_mask = (1u32 << (bits - 1)) - 1;
+            let threshold = (1u32 << bits) - 1 - remaining as u32;
+            let mut value = reader.read(bits)?;
+            if (value & lower_mask) < threshold {
+                reader.rewind(1);
+                value &= lower_mask;
+            } else if value > lower_mask {
+                value -= threshold;
+            }
+            let proba = value as i32 - 1;
+            remaining -= proba.abs();
+            norm.push(proba as i16);
+            if proba == 0 {
+                // Zero probabilities are followed by 2-bit repeat flags.
+                loop {
+                    let repeat = reader.read(2)?;
+                    norm.extend(std::iter::repeat_n(0, repeat as usize));
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+        }
+        if remaining != 1 || norm.len() > max_symbol + 1 {
+            return LEVELDB_ERR!(Corruption, "zstd: bad FSE table description");
+        }
+        Ok((FseTable::from_distribution(&norm, log)?, reader.bytes_consumed()))
+    }
+}
+
+/// Encoder side of an FSE table.
+struct FseEncoder {
+    log: u32,
+    // Next state for each (symbol, cumulated frequency) cell.
+    next_state: Vec<u16>,
+    // Per symbol: (delta_nb_bits, delta_find_state)
+    transforms: Vec<(i64, i64)>,
+}
+
+impl FseEncoder {
+    fn new(norm: &[i16], log: u32) -> FseEncoder {
+        let size = 1usize << log;
+        let symbols = spread_symbols(norm, log).expect("valid predefined distribution");
+        let mut cumul = vec![0usize; norm.len() + 1];
+        for (s, &p) in norm.iter().enumerate() {
+            cumul[s + 1] = cumul[s] + if p == -1 { 1 } else { p.max(0) as usize };
+        }
+        let mut next_state = vec![0u16; size];
+        let mut fill = cumul.clone();
+        for (u, &s) in symbols.iter().enumerate() {
+            next_state[fill[s as usize]] = (size + u) as u16;
+            fill[s as usize] += 1;
+        }
+        let mut total = 0i64;
+        let transforms = norm
+            .iter()
+            .map(|&p| match p {
+                0 => (((log as i64 + 1) << 16) - size as i64, 0),
+                -1 | 1 => {
+                    let t = ((log as i64) << 16) - size as i64;
+                    total += 1;
+                    (t, total - 2)
+                }
+                p => {
+                    let p = p as i64;
+                    let max_bits_out = log as i64 - highest_bit(p as u32 - 1) as i64;
+                    let min_state_plus = p << max_bits_out;
+                    let t = ((max_bits_out << 16) - min_state_plus, total - p);
+                    total += p;
+                    t
+                }
+            })
+            .collect();
+        FseEncoder {
+            log,
+            next_state,
+            transforms,
+        }
+    }
+
+    fn init_state(&self, symbol: usize) -> u32 {
+        let (delta_nb_bits, delta_find_state) = self.transforms[symbol];
+        let nb_bits_out = (delta_nb_bits + (1 << 15)) >> 16;
+        let value = (nb_bits_out << 16) - delta_nb_bits;
+        self.next_state[((value >> nb_bits_out) + delta_find_state) as usize] as u32
+    }
+
+    fn encode(&self, writer: &mut BitWriter, state: &mut u32, symbol: usize) {
+        let (delta_nb_bits, delta_find_state) = self.transforms[symbol];
+        let nb_bits_out = ((*state as i64 + delta_nb_bits) >> 16) as u32;
+        writer.add(*state as u64, nb_bits_out);
+        *state = self.next_state[((*state >> nb_bits_out) as i64 + delta_find_state) as usize] as u32;
+    }
+
+    fn flush(&self, writer: &mut BitWriter, state: u32) { writer.add(state as u64, self.log); }
+}
+
+// ---------------------------------------------------------------------------------
+// Huffman tables
+
+#[derive(Clone)]
+struct HuffmanTable {
+    max_bits: u32,
+    // Indexed by the next max_bits bits of the stream.
+    symbols: Vec<u8>,
+    nb_bits: Vec<u8>,
+}
+
+impl HuffmanTable {
+    /// Build the decoding table from the weights of all symbols but the last one.
+    fn from_weights(mut weights: Vec<u8>) -> Result<HuffmanTable> {
+        if weights.is_empty() || weights.len() > 255 {
+            return LEVELDB_ERR!(Corruption, "zstd: bad Huffman weights");
+        }
+        let mut total: u32 = 0;
+        for &w in weights.iter() {
+            if w as u32 > HUFFMAN_MAX_BITS {
+                return LEVELDB_ERR!(Corruption, "zstd: bad Huffman weight");
+            }
+            if w > 0 {
+                total += 1 << (w - 1);
+            }
+        }
+        if total == 0 {
+            return LEVELDB_ERR!(Corruption, "zstd: bad Huffman weights");
+        }
+        // The weight of the last symbol is implied: it completes the sum to the next
+        // power of two.
+        let max_bits = highest_bit(total) + 1;
+        let left = (1u32 << max_bits) - total;
+        if !left.is_power_of_two() || max_bits > HUFFMAN_MAX_BITS {
+            return LEVELDB_ERR!(Corruption, "zstd: bad Huffman weights");
+        }
+        weights.push((highest_bit(left) + 1) as u8);
+
+        let size = 1usize << max_bits;
+        let bits: Vec<u32> = weights
+            .iter()
+            .map(|&w| if w > 0 { max_bits + 1 - w as u32 } else { 0 })
+            .collect();
+        let mut rank_count = vec![0usize; max_bits as usize + 2];
+        for &b in bits.iter() {
+            rank_count[b as usize] += 1;
+        }
+        // Longer codes come first in the table.
+        let mut rank_start = vec![0usize; max_bits as usize + 2];
+        let mut next = 0;
+        for b in (1..=max_bits as usize).rev() {
+            rank_start[b] = next;
+            next += rank_count[b] << (max_bits as usize - b);
+        }
+        if next != size {
+            return LEVELDB_ERR!(Corruption, "zstd: bad Huffman weights");
+        }
+        let mut symbols = vec![0u8; size];
+        let mut nb_bits = vec![0u8; size];
+        for (s, &b) in bits.iter().enumerate() {
+            if b == 0 {
+                continue;
+            }
+            let len = 1usize << (max_bits - b);
+            let start = rank_start[b as usize];
+            symbols[start..start + len].fill(s as u8);
+            nb_bits[start..start + len].fill(b as u8);
+            rank_start[b as usize] += len;
+        }
+        Ok(HuffmanTable {
+            max_bits,
+            symbols,
+            nb_bits,
+        })
+    }
+
+    /// Read a Huffman tree description. Returns the table and the number of bytes
+    /// used by the description.
+    fn read_description(data: &[u8]) -> Result<(HuffmanTable, usize)> {
+        let header = match data.first() {
+            Some(&h) => h as usize,
+            None => return LEVELDB_ERR!(Corruption, "zstd: missing Huffman tree description"),
+        };
+        if header < 128 {
+            // FSE compressed weights
+            let compressed = match data.get(1..1 + header) {
+                Some(c) => c,
+                None => return LEVELDB_ERR!(Corruption, "zstd: truncated Huffman weights"),
+            };
+            let (table, n) = FseTable::read_description(compressed, HUFFMAN_WEIGHTS_MAX_LOG, 255)?;
+            if n >= compressed.len() {
+                return LEVELDB_ERR!(Corruption, "zstd: truncated Huffman weights");
+            }
+            let mut reader = BackwardBitReader::new(&compressed[n..])?;
+            let mut weights = Vec::new();
+            let mut state1 = reader.read(table.log) as usize;
+            let mut state2 = reader.read(table.log) as usize;
+            // Decode with two interleaved states until the stream is exhausted.
+            loop {
+                let e = table.entries[state1];
+                weights.push(e.symbol);
+                state1 = e.baseline as usize + reader.read(e.nb_bits as u32) as usize;
+                if reader.overread() {
+                    weights.push(table.entries[state2].symbol);
+                    break;
+                }
+                let e = table.entries[state2];
+                weights.push(e.symbol);
+                state2 = e.baseline as usize + reader.read(e.nb_bits as u32) as usize;
+                if reader.overread() {
+                    weights.push(table.entries[state1].symbol);
+                    break;
+                }
+                if weights.len() > 255 {
+                    return LEVELDB_ERR!(Corruption, "zstd: too many Huffman weights");
+                }
+            }
+            Ok((HuffmanTable::from_weights(weights)?, 1 + header))
+        } else {
+            // Weights stored directly, 4 bits each
+            let count = header - 127;
+            let bytes = match data.get(1..1 + count.div_ceil(2)) {
+                Some(b) => b,
+                None => return LEVELDB_ERR!(Corruption, "zstd: truncated Huffman weights"),
+            };
+            let weights = (0..count)
+                .map(|i| if i % 2 == 0 { bytes[i / 2] >> 4 } else { bytes[i / 2] & 0xf })
+                .collect();
+            Ok((HuffmanTable::from_weights(weights)?, 1 + bytes.len()))
+        }
+    }
+
+    fn decode_stream(&self, data: &[u8], count: usize, out: &mut Vec<u8>) -> Result<()> {
+        let mut reader = BackwardBitReader::new(data)?;
+        for _ in 0..count {
+            let index = reader.peek(self.max_bits) as usize;
+            out.push(self.symbols[index]);
+            reader.consume(self.nb_bits[index] as u32);
+        }
+        if !reader.finished() {
+            return LEVELDB_ERR!(Corruption, "zstd: bad Huffman stream length");
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------------
+// Decompression
+
+/// State carried from block to block within a frame.
+struct FrameDecoder {
+    huffman: Option<HuffmanTable>,
+    ll_table: Option<FseTable>,
+    of_table: Option<FseTable>,
+    ml_table: Option<FseTable>,
+    rep: [usize; 3],
+}
+
+impl FrameDecoder {
+    fn new() -> Self {
+        Self {
+            huffman: None,
+            ll_table: None,
+            of_table: None,
+            ml_table: None,
+            rep: [1, 4, 8],
+        }
+    }
+
+    /// Decode the literals section at the start of `block`. Returns the literals and
+    /// the size of the section.
+    fn decode_literals(&mut self, block: &[u8]) -> Result<(Vec<u8>, usize)> {
+        let header = match block.first() {
+            Some(&h) => h,
+            None => return LEVELDB_ERR!(Corruption, "zstd: missing literals section"),
+        };
+        let block_type = header & 3;
+        let size_format = (header >> 2) & 3;
+        let byte = |i: usize| -> Result<usize> {
+            match block.get(i) {
+                Some(&b) => Ok(b as usize),
+                None => LEVELDB_ERR!(Corruption, "zstd: truncated literals header"),
+            }
+        };
+        match block_type {
+            LITERALS_RAW | LITERALS_RLE => {
+                let (size, header_len) = match size_format {
+                    0 | 2 => (header as usize >> 3, 1),
+                    1 => ((header as usize >> 4) + (byte(1)? << 4), 2),
+                    _ => ((header as usize >> 4) + (byte(1)? << 4) + (byte(2)? << 12), 3),
+                };
+                if block_type == LITERALS_RAW {
+                    match block.get(header_len..header_len + size) {
+                        Some(literals) => Ok((literals.to_vec(), header_len + size)),
+                        None => LEVELDB_ERR!(Corruption, "zstd: truncated raw literals"),
+                    }
+                } else {
+                    Ok((vec![byte(header_len)? as u8; size], header_len + 1))
+                }
+            }
+            _ => {
+                let (header_len, size_bits, streams) = match size_format {
+                    0 => (3, 10, 1),
+                    1 => (3, 10, 4),
+                    2 => (4, 14, 4),
+                    _ => (5, 18, 4),
+                };
+                let mut h: u64 = 0;
+                for i in 0..header_len {
+                    h |= (byte(i)? as u64) << (8 * i);
+                }
+                let mask = (1u64 << size_bits) - 1;
+                let regenerated = ((h >> 4) & mask) as usize;
+                let compressed = ((h >> (4 + size_bits)) & mask) as usize;
+                let mut data = match block.get(header_len..header_len + compressed) {
+                    Some(data) => data,
+                    None => return LEVELDB_ERR!(Corruption, "zstd: truncated compressed literals"),
+                };
+                if block_type == LITERALS_COMPRESSED {
+                    let (table, n) = HuffmanTable::read_description(data)?;
+                    self.huffman = Some(table);
+                    data = &data[n..];
+                }
+                let table = match self.huffman.as_ref() {
+                    Some(table) => table,
+                    None => return LEVELDB_ERR!(Corruption, "zstd: treeless literals without a table"),
+                };
+                let mut literals = Vec::with_capacity(regenerated);
+                if streams == 1 {
+                    table.decode_stream(data, regenerated, &mut literals)?;
+                } else {
+                    if data.len() < 6 {
+                        return LEVELDB_ERR!(Corruption, "zstd: truncated jump table");
+                    }
+                    let sizes = [
+                        u16::from_le_bytes([data[0], data[1]]) as usize,
+                        u16::from_le_bytes([data[2], data[3]]) as usize,
+                        u16::from_le_bytes([data[4], data[5]]) as usize,
+                    ];
+                    let mut rest = &data[6..];
+                    let per_stream = regenerated.div_ceil(4);
+                    for (i, &size) in sizes.iter().enumerate() {
+                        if size > rest.len() {
+                            return LEVELDB_ERR!(Corruption, "zstd: bad jump table");
+                        }
+                        let count = per_stream.min(regenerated.saturating_sub(i * per_stream));
+                        table.decode_stream(&rest[..size], count, &mut literals)?;
+                        rest = &rest[size..];
+                    }
+                    let count = regenerated.saturating_sub(3 * per_stream);
+                    table.decode_stream(rest, count, &mut literals)?;
+                }
+                if literals.len() != regenerated {
+                    return LEVELDB_ERR!(Corruption, "zstd: bad literals size");
+                }
+                Ok((literals, header_len + compressed))
+            }
+        }
+    }
+
+    /// Read the table used for one kind of sequence code. Returns the number of bytes
+    /// of `data` used.
+    fn read_sequence_table(
+        slot: &mut Option<FseTable>,
+        mode: u8,
+        data: &[u8],
+        default: (&[i16], u32),
+        max_log: u32,
+        max_symbol: usize,
+    ) -> Result<usize> {
+        match mode {
+            MODE_PREDEFINED => {
+                *slot = Some(FseTable::from_distribution(default.0, default.1)?);
+                Ok(0)
+            }
+            MODE_RLE => match data.first() {
+                Some(&symbol) if symbol as usize <= max_symbol => {
+                    *slot = Some(FseTable::rle(symbol));
+                    Ok(1)
+                }
+                _ => LEVELDB_ERR!(Corruption, "zstd: bad RLE sequence table"),
+            },
+            MODE_FSE => {
+                let (table, n) = FseTable::read_description(data, max_log, max_symbol)?;
+                *slot = Some(table);
+                Ok(n)
+            }
+            _ => {
+                if slot.is_none() {
+                    return LEVELDB_ERR!(Corruption, "zstd: repeated sequence table missing");
+                }
+                Ok(0)
+            }
+        }
+    }
+
+    fn decode_block(&mut self, block: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let (literals, mut pos) = self.decode_literals(block)?;
+
+        // Sequences section header
+        let byte = |i: usize| -> Result<usize> {
+            match block.get(i) {
+                Some(&b) => Ok(b as usize),
+                None => LEVELDB_ERR!(Corruption, "zstd: truncated sequences header"),
+            }
+        };
+        let b0 = byte(pos)?;
+        let num_sequences = if b0 < 128 {
+            pos += 1;
+            b0
+        } else if b0 < 255 {
+            pos += 2;
+            ((b0 - 128) << 8) + byte(pos - 1)?
+        } else {
+            pos += 3;
+            byte(pos - 2)? + (byte(pos - 1)? << 8) + 0x7F00
+        };
+        if num_sequences == 0 {
+            if pos != block.len() {
+                return LEVELDB_ERR!(Corruption, "zstd: trailing bytes in block");
+            }
+            out.extend_from_slice(&literals);
+            return Ok(());
+        }
+
+        let modes = byte(pos)? as u8;
+        pos += 1;
+        if modes & 3 != 0 {
+            return LEVELDB_ERR!(Corruption, "zstd: reserved sequence mode bits set");
+        }
+        pos += Self::read_sequence_table(
+            &mut self.ll_table,
+            modes >> 6,
+            &block[pos..],
+            (&LL_DEFAULT, LL_DEFAULT_LOG),
+            LL_MAX_LOG,
+            35,
+        )?;
+        pos += Self::read_sequence_table(
+            &mut self.of_table,
+            (modes >> 4) & 3,
+            &block[pos..],
+            (&OF_DEFAULT, OF_DEFAULT_LOG),
+            OF_MAX_LOG,
+            31,
+        )?;
+        pos += Self::read_sequence_table(
+            &mut self.ml_table,
+            (modes >> 2) & 3,
+            &block[pos..],
+            (&ML_DEFAULT, ML_DEFAULT_LOG),
+            ML_MAX_LOG,
+            52,
+        )?;
+        if pos >= block.len() {
+            return LEVELDB_ERR!(Corruption, "zstd: missing sequences bit stream");
+        }
+
+        let (ll_table, of_table, ml_table) = (
+            self.ll_table.as_ref().unwrap(),
+            self.of_table.as_ref().unwrap(),
+            self.ml_table.as_ref().unwrap(),
+        );
+        let mut reader = BackwardBitReader::new(&block[pos..])?;
+        let mut ll_state = reader.read(ll_table.log) as usize;
+        let mut of_state = reader.read(of_table.log) as usize;
+        let mut ml_state = reader.read(ml_table.log) as usize;
+        let mut lit_pos = 0;
+        for i in 0..num_sequences {
+            let ll_code = ll_table.entries[ll_state].symbol as usize;
+            let of_code = of_table.entries[of_state].symbol as u32;
+            let ml_code = ml_table.entries[ml_state].symbol as usize;
+            if ll_code > 35 || ml_code > 52 || of_code > 31 {
+                return LEVELDB_ERR!(Corruption, "zstd: bad sequence code");
+            }
+            let offset_value = (1u64 << of_code) + reader.read(of_code);
+            let match_length = ML_BASE[ml_code] as usize + reader.read(ML_BITS[ml_code] as u32) as usize;
+            let literal_length = LL_BASE[ll_code] as usize + reader.read(LL_BITS[ll_code] as u32) as usize;
+
+            // Resolve repeat offsets
+            let offset = if offset_value > 3 {
+                let offset = (offset_value - 3) as usize;
+                self.rep = [offset, self.rep[0], self.rep[1]];
+                offset
+            } else {
+                let mut index = offset_value as usize - 1;
+                if literal_length == 0 {
+                    index += 1;
+                }
+                match index {
+                    0 => self.rep[0],
+                    1 => {
+                        let offset = self.rep[1];
+                        self.rep = [offset, self.rep[0], self.rep[2]];
+                        offset
+                    }
+                    _ => {
+                        let offset = if index == 2 { self.rep[2] } else { self.rep[0].wrapping_sub(1) };
+                        self.rep = [offset, self.rep[0], self.rep[1]];
+                        offset
+                    }
+                }
+            };
+
+            if i + 1 < num_sequences {
+                let e = ll_table.entries[ll_state];
+                ll_state = e.baseline as usize + reader.read(e.nb_bits as u32) as usize;
+                let e = ml_table.entries[ml_state];
+                ml_state = e.baseline as usize + reader.read(e.nb_bits as u32) as usize;
+                let e = of_table.entries[of_state];
+                of_state = e.baseline as usize + reader.read(e.nb_bits as u32) as usize;
+            }
+            if reader.overread() {
+                return LEVELDB_ERR!(Corruption, "zstd: sequences bit stream overread");
+            }
+
+            // Execute the sequence
+            match literals.get(lit_pos..lit_pos + literal_length) {
+                Some(lits) => out.extend_from_slice(lits),
+                None => return LEVELDB_ERR!(Corruption, "zstd: sequence literals out of range"),
+            }
+            lit_pos += literal_length;
+            if offset == 0 || offset > out.len() {
+                return LEVELDB_ERR!(Corruption, "zstd: match offset out of range");
+            }
+            let start = out.len() - offset;
+            for j in 0..match_length {
+                let b = out[start + j];
+                out.push(b);
+            }
+        }
+        if !reader.finished() {
+            return LEVELDB_ERR!(Corruption, "zstd: bad sequences bit stream length");
+        }
+        out.extend_from_slice(&literals[lit_pos..]);
+        Ok(())
+    }
+}
+
+fn read_u32_le(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Return the ID that frames compressed with the raw content dictionary `dict` carry
+/// in their header. IDs below 32768 are reserved by the format.
+pub fn dictionary_id(dict: &[u8]) -> u32 { (crate::util::crc32c::value(dict) % (u32::MAX - 32768)) + 32768 }
+
+/// Decompress the Zstandard frames in `input`.
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>> { decompress_with_dict(input, &[]) }
+
+/// Decompress the Zstandard frames in `input`, using `dict` as the raw content
+/// dictionary of the frames that name one. Frames without a dictionary ID are decoded
+/// without it.
+pub fn decompress_with_dict(input: &[u8], dict: &[u8]) -> Result<Vec<u8>> {
+    // The dictionary is decoded output as far as matches are concerned.
+    let mut out = dict.to_vec();
+    let mut pos = 0;
+    if input.is_empty() {
+        return LEVELDB_ERR!(Corruption, "zstd: empty input");
+    }
+    while pos < input.len() {
+        let magic = match read_u32_le(input, pos) {
+            Some(m) => m,
+            None => return LEVELDB_ERR!(Corruption, "zstd: truncated frame"),
+        };
+        pos += 4;
+        if magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC {
+            match read_u32_le(input, pos) {
+                Some(size) => match (pos + 4).checked_add(size as usize) {
+                    Some(end) if end <= input.len() => pos = end,
+                    _ => return LEVELDB_ERR!(Corruption, "zstd: truncated skippable frame"),
+                },
+                _ => return LEVELDB_ERR!(Corruption, "zstd: truncated skippable frame"),
+            }
+            continue;
+        }
+        if magic != MAGIC_NUMBER {
+            return LEVELDB_ERR!(Corruption, "zstd: bad magic number");
+        }
+        pos = decompress_frame(input, pos, dict, &mut out)?;
+    }
+    out.drain(..dict.len());
+    Ok(out)
+}
+
+/// Decode the frame whose header starts at `pos` (just past the magic number).
+/// Returns the position following the frame.
+fn decompress_frame(input: &[u8], mut pos: usize, dict: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+    let descriptor = match input.get(pos) {
+        Some(&d) => d,
+        None => return LEVELDB_ERR!(Corruption, "zstd: truncated frame header"),
+    };
+    pos += 1;
+    let fcs_flag = descriptor >> 6;
+    let single_segment = descriptor & 0x20 != 0;
+    let has_checksum = descriptor & 0x04 != 0;
+    if descriptor & 0x08 != 0 {
+        return LEVELDB_ERR!(Corruption, "zstd: reserved frame header bit set");
+    }
+    if !single_segment {
+        pos += 1; // Window descriptor
+    }
+    let dict_id_size = [0, 1, 2, 4][(descriptor & 3) as usize];
+    if input.len() < pos + dict_id_size {
+        return LEVELDB_ERR!(Corruption, "zstd: truncated frame header");
+    }
+    let mut id = [0u8; 4];
+    id[..dict_id_size].copy_from_slice(&input[pos..pos + dict_id_size]);
+    match u32::from_le_bytes(id) {
+        0 => {}
+        _ if dict.is_empty() => return LEVELDB_ERR!(NotSupported, "zstd: frame needs a dictionary"),
+        id if id != dictionary_id(dict) => return LEVELDB_ERR!(Corruption, "zstd: wrong dictionary"),
+        _ => {}
+    }
+    pos += dict_id_size;
+    let fcs_size = match fcs_flag {
+        0 if single_segment => 1,
+        0 => 0,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    let content_size = match input.get(pos..pos + fcs_size) {
+        Some(bytes) => {
+            let mut word = [0u8; 8];
+            word[..fcs_size].copy_from_slice(bytes);
+            let size = u64::from_le_bytes(word);
+            if fcs_size == 2 {
+                Some(size + 256)
+            } else if fcs_size > 0 {
+                Some(size)
+            } else {
+                None
+            }
+        }
+        None => return LEVELDB_ERR!(Corruption, "zstd: truncated frame header"),
+    };
+    pos += fcs_size;
+
+    let frame_start = out.len();
+    let mut decoder = FrameDecoder::new();
+    loop {
+        let header = match input.get(pos..pos + 3) {
+            Some(b) => b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16,
+            None => return LEVELDB_ERR!(Corruption, "zstd: truncated block header"),
+        };
+        pos += 3;
+        let last = header & 1 != 0;
+        let block_type = (header >> 1) & 3;
+        let size = (header >> 3) as usize;
+        if size > MAX_BLOCK_SIZE {
+            return LEVELDB_ERR!(Corruption, "zstd: block too large");
+        }
+        match block_type {
+            BLOCK_RAW => match input.get(pos..pos + size) {
+                Some(data) => {
+                    out.extend_from_slice(data);
+                    pos += size;
+                }
+                None => return LEVELDB_ERR!(Corruption, "zstd: truncated raw block"),
+            },
+            BLOCK_RLE => match input.get(pos) {
+                Some(&b) => {
+                    out.resize(out.len() + size, b);
+                    pos += 1;
+                }
+                None => return LEVELDB_ERR!(Corruption, "zstd: truncated RLE block"),
+            },
+            BLOCK_COMPRESSED => match input.get(pos..pos + size) {
+                Some(data) => {
+                    decoder.decode_block(data, out)?;
+                    pos += size;
+                }
+                None => return LEVELDB_ERR!(Corruption, "zstd: truncated compressed block"),
+            },
+            _ => return LEVELDB_ERR!(Corruption, "zstd: reserved block type"),
+        }
+        if last {
+            break;
+        }
+    }
+    if content_size.is_some_and(|size| size != (out.len() - frame_start) as u64) {
+        return LEVELDB_ERR!(Corruption, "zstd: frame content size mismatch");
+    }
+    if has_checksum {
+        // The checksum is not verified: table blocks carry their own crc.
+        if input.len() < pos + 4 {
+            return LEVELDB_ERR!(Corruption, "zstd: truncated frame checksum");
+        }
+        pos += 4;
+    }
+    Ok(pos)
+}
+
+// ---------------------------------------------------------------------------------
+// Compression
+
+struct Sequence {
+    literal_length: u32,
+    match_length: u32,
+    offset: u32,
+}
+
+/// Finds matches in the input with hash chains over 4-byte prefixes.
+struct MatchFinder {
+    head: Vec<u32>,
+    chain: Vec<u32>,
+    max_attempts: usize,
+    // Whether to look a byte ahead for a longer match before taking one.
+    lazy: bool,
+}
+
+const HASH_LOG: u32 = 15;
+const NO_POSITION: u32 = u32::MAX;
+
+impl MatchFinder {
+    fn new(input_len: usize, level: i32) -> Self {
+        let max_attempts = match level {
+            i32::MIN..=1 => 1,
+            2..=3 => 4,
+            4..=6 => 16,
+            7..=12 => 64,
+            _ => 256,
+        };
+        Self {
+            head: vec![NO_POSITION; 1 << HASH_LOG],
+            chain: vec![NO_POSITION; input_len],
+            max_attempts,
+            lazy: level >= 4,
+        }
+    }
+
+    fn hash(input: &[u8], pos: usize) -> usize {
+        let v = u32::from_le_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]]);
+        (v.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+    }
+
+    fn insert(&mut self, input: &[u8], pos: usize) {
+        if pos + MIN_MATCH <= input.len() {
+            let h = Self::hash(input, pos);
+            self.chain[pos] = self.head[h];
+            self.head[h] = pos as u32;
+        }
+    }
+
+    /// Return the longest match (offset, length) for `pos` that ends before `limit`.
+    fn find(&self, input: &[u8], pos: usize, limit: usize) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > limit {
+            return None;
+        }
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[Self::hash(input, pos)];
+        for _ in 0..self.max_attempts {
+            if candidate == NO_POSITION {
+                break;
+            }
+            let c = candidate as usize;
+            let len = input[c..]
+                .iter()
+                .zip(&input[pos..limit])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len >= MIN_MATCH && best.is_none_or(|(_, best_len)| len > best_len) {
+                best = Some((pos - c, len));
+            }
+            candidate = self.chain[c];
+        }
+        best
+    }
+}
+
+/// Return the Huffman code length of each symbol given its number of occurrences, with
+/// no code longer than `HUFFMAN_MAX_BITS`. Symbols that do not occur get length 0.
+/// REQUIRES: at least two symbols occur.
+fn huffman_code_lengths(counts: &[u32]) -> Vec<u32> {
+    let mut counts = counts.to_vec();
+    loop {
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &c)| c > 0)
+            .map(|(s, &c)| Reverse((c as u64, s)))
+            .collect();
+        // Nodes past the symbols are the inner nodes of the tree, each created after
+        // its children.
+        let mut parent = vec![usize::MAX; counts.len()];
+        while heap.len() > 1 {
+            let Reverse((a, x)) = heap.pop().unwrap();
+            let Reverse((b, y)) = heap.pop().unwrap();
+            parent[x] = parent.len();
+            parent[y] = parent.len();
+            heap.push(Reverse((a + b, parent.len())));
+            parent.push(usize::MAX);
+        }
+        let mut depth = vec![0u32; parent.len()];
+        for node in (0..parent.len()).rev() {
+            if parent[node] != usize::MAX {
+                depth[node] = depth[parent[node]] + 1;
+            }
+        }
+        depth.truncate(counts.len());
+        if depth.iter().all(|&d| d <= HUFFMAN_MAX_BITS) {
+            return depth;
+        }
+        // Flatten the distribution until the tree is shallow enough.
+        for c in counts.iter_mut() {
+            *c = c.div_ceil(2);
+        }
+    }
+}
+
+/// Scale `counts`, which sum to `total`, to a distribution over `1 << log` cells in
+/// which every symbol that occurs keeps at least one cell.
+fn normalize_counts(counts: &[u32], total: usize, log: u32) -> Vec<i16> {
+    let size = 1i64 << log;
+    let mut norm: Vec<i16> = counts
+        .iter()
+        .map(|&c| if c == 0 { 0 } else { (c as i64 * size / total as i64).max(1) as i16 })
+        .collect();
+    let mut sum: i64 = norm.iter().map(|&p| p as i64).sum();
+    // The rounding error goes to the most probable symbol.
+    while sum != size {
+        let s = (0..norm.len()).max_by_key(|&s| norm[s]).unwrap();
+        let delta = if sum < size { 1 } else { -1 };
+        norm[s] += delta;
+        sum += delta as i64;
+    }
+    norm
+}
+
+/// Write the description of a distribution, as `FseTable::read_description()` reads
+/// it back.
+fn write_fse_description(dst: &mut Vec<u8>, norm: &[i16], log: u32) {
+    let mut writer = BitWriter::new();
+    writer.add((log - 5) as u64, 4);
+    let mut remaining = (1i32 << log) + 1;
+    let mut s = 0;
+    while remaining > 1 {
+        let proba = norm[s];
+        s += 1;
+        let bits = highest_bit(remaining as u32) + 1;
+        let lower_mask = (1u32 << (bits - 1)) - 1;
+        let threshold = (1u32 << bits) - 1 - remaining as u32;
+        let value = (proba + 1) as u32;
+        if value < threshold {
+            writer.add(value as u64, bits - 1);
+        } else if value <= lower_mask {
+            writer.add(value as u64, bits);
+        } else {
+            writer.add((value + threshold) as u64, bits);
+        }
+        remaining -= proba.abs() as i32;
+        if proba == 0 {
+            let mut zeros = norm[s..].iter().take_while(|&&p| p == 0).count();
+            s += zeros;
+            loop {
+                let repeat = zeros.min(3);
+                writer.add(repeat as u64, 2);
+                if repeat != 3 {
+                    break;
+                }
+                zeros -= 3;
+            }
+        }
+    }
+    dst.extend_from_slice(&writer.into_bytes());
+}
+
+/// FSE-compress Huffman weights with two interleaved states, as
+/// `HuffmanTable::read_description()` decodes them. Returns None if the weights take
+/// a single value, which such a stream cannot encode.
+fn compress_weights(weights: &[u8]) -> Option<Vec<u8>> {
+    let mut counts = [0u32; HUFFMAN_MAX_BITS as usize + 1];
+    for &w in weights.iter() {
+        counts[w as usize] += 1;
+    }
+    if weights.len() < 2 || counts.iter().filter(|&&c| c > 0).count() < 2 {
+        return None;
+    }
+    let max_weight = counts.iter().rposition(|&c| c > 0).unwrap();
+    let norm = normalize_counts(&counts[..=max_weight], weights.len(), HUFFMAN_WEIGHTS_MAX_LOG);
+    let mut dst = Vec::new();
+    write_fse_description(&mut dst, &norm, HUFFMAN_WEIGHTS_MAX_LOG);
+
+    // Weights at even positions go through the first state, odd ones through the
+    // second; the stream is written backwards so the decoder reads the first first.
+    let encoder = FseEncoder::new(&norm, HUFFMAN_WEIGHTS_MAX_LOG);
+    let n = weights.len();
+    let mut states = [0u32; 2];
+    states[(n - 1) % 2] = encoder.init_state(weights[n - 1] as usize);
+    states[(n - 2) % 2] = encoder.init_state(weights[n - 2] as usize);
+    let mut writer = BitWriter::new();
+    for i in (0..n - 2).rev() {
+        encoder.encode(&mut writer, &mut states[i % 2], weights[i] as usize);
+    }
+    encoder.flush(&mut writer, states[1]);
+    encoder.flush(&mut writer, states[0]);
+    dst.extend_from_slice(&writer.finish());
+    Some(dst)
+}
+
+/// Encoder side of a Huffman table.
+struct HuffmanEncoder {
+    max_bits: u32,
+    codes: Vec<u32>,
+    lengths: Vec<u32>,
+}
+
+impl HuffmanEncoder {
+    /// Assign codes to the symbols the way `HuffmanTable::from_weights()` lays out its
+    /// table: longer codes first, then by symbol.
+    fn new(lengths: Vec<u32>) -> HuffmanEncoder {
+        let max_bits = lengths.iter().copied().max().unwrap_or(0);
+        let mut rank_count = vec![0u32; max_bits as usize + 1];
+        for &l in lengths.iter() {
+            rank_count[l as usize] += 1;
+        }
+        let mut rank_start = vec![0u32; max_bits as usize + 1];
+        let mut next = 0;
+        for l in (1..=max_bits).rev() {
+            rank_start[l as usize] = next;
+            next += rank_count[l as usize] << (max_bits - l);
+        }
+        let codes = lengths
+            .iter()
+            .map(|&l| {
+                if l == 0 {
+                    return 0;
+                }
+                let start = rank_start[l as usize];
+                rank_start[l as usize] += 1 << (max_bits - l);
+                start >> (max_bits - l)
+            })
+            .collect();
+        HuffmanEncoder {
+            max_bits,
+            codes,
+            lengths,
+        }
+    }
+
+    /// Write the tree description: the weights of the symbols before the last one that
+    /// occurs, FSE compressed or 4 bits each, whichever is smaller. Returns false if
+    /// neither form can hold them.
+    fn write_description(&self, dst: &mut Vec<u8>) -> bool {
+        let last = self.lengths.iter().rposition(|&l| l > 0).unwrap();
+        let weights: Vec<u8> = self.lengths[..last]
+            .iter()
+            .map(|&l| if l > 0 { (self.max_bits + 1 - l) as u8 } else { 0 })
+            .collect();
+        let compressed = compress_weights(&weights).filter(|c| c.len() < 128);
+        match compressed {
+            Some(c) if weights.len() > 128 || c.len() < weights.len().div_ceil(2) => {
+                dst.push(c.len() as u8);
+                dst.extend_from_slice(&c);
+            }
+            _ if weights.len() <= 128 => {
+                dst.push((127 + weights.len()) as u8);
+                for pair in weights.chunks(2) {
+                    dst.push(pair[0] << 4 | pair.get(1).copied().unwrap_or(0));
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn encode_stream(&self, literals: &[u8]) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        // Written backwards so that the decoder reads the first literal first.
+        for &b in literals.iter().rev() {
+            writer.add(self.codes[b as usize] as u64, self.lengths[b as usize]);
+        }
+        writer.finish()
+    }
+}
+
+/// Build a literals section with Huffman-coded literals, in a single stream for short
+/// runs of literals and in four otherwise. Returns None if the literals use fewer than
+/// two distinct bytes or their tree cannot be described.
+fn compress_literals(literals: &[u8]) -> Option<Vec<u8>> {
+    let mut counts = [0u32; 256];
+    for &b in literals.iter() {
+        counts[b as usize] += 1;
+    }
+    if counts.iter().filter(|&&c| c > 0).count() < 2 {
+        return None;
+    }
+    let encoder = HuffmanEncoder::new(huffman_code_lengths(&counts));
+    let mut body = Vec::new();
+    if !encoder.write_description(&mut body) {
+        return None;
+    }
+    let size_format = if literals.len() < 256 {
+        body.extend_from_slice(&encoder.encode_stream(literals));
+        0
+    } else {
+        let streams: Vec<Vec<u8>> = literals
+            .chunks(literals.len().div_ceil(4))
+            .map(|chunk| encoder.encode_stream(chunk))
+            .collect();
+        for stream in &streams[..3] {
+            body.extend_from_slice(&(stream.len() as u16).to_le_bytes());
+        }
+        for stream in streams.iter() {
+            body.extend_from_slice(stream);
+        }
+        match literals.len().max(body.len()) {
+            0..1024 => 1,
+            1024..16384 => 2,
+            _ => 3,
+        }
+    };
+    let (header_len, size_bits) = [(3, 10), (3, 10), (4, 14), (5, 18)][size_format];
+    if body.len() >= 1 << size_bits {
+        return None;
+    }
+    let header = LITERALS_COMPRESSED as u64
+        | (size_format as u64) << 2
+        | (literals.len() as u64) << 4
+        | (body.len() as u64) << (4 + size_bits);
+    let mut section = header.to_le_bytes()[..header_len].to_vec();
+    section.extend_from_slice(&body);
+    Some(section)
+}
+
+fn write_literals_header(dst: &mut Vec<u8>, size: usize, block_type: u8) {
+    if size < 32 {
+        dst.push((size << 3) as u8 | block_type);
+    } else if size < 4096 {
+        dst.push((size << 4) as u8 | 0b0100 | block_type);
+        dst.push((size >> 4) as u8);
+    } else {
+        dst.push((size << 4) as u8 | 0b1100 | block_type);
+        dst.push((size >> 4) as u8);
+        dst.push((size >> 12) as u8);
+    }
+}
+
+/// Encode the sequences with the predefined tables.
+fn encode_sequences(dst: &mut Vec<u8>, sequences: &[Sequence]) {
+    let n = sequences.len();
+    if n < 128 {
+        dst.push(n as u8);
+    } else if n < 0x7F00 {
+        dst.push(((n >> 8) + 128) as u8);
+        dst.push(n as u8);
+    } else {
+        dst.push(255);
+        dst.extend_from_slice(&((n - 0x7F00) as u16).to_le_bytes());
+    }
+    if n == 0 {
+        return;
+    }
+    dst.push(0); // Predefined mode for all three tables
+
+    let ll_encoder = FseEncoder::new(&LL_DEFAULT, LL_DEFAULT_LOG);
+    let ml_encoder = FseEncoder::new(&ML_DEFAULT, ML_DEFAULT_LOG);
+    let of_encoder = FseEncoder::new(&OF_DEFAULT, OF_DEFAULT_LOG);
+    let codes: Vec<(usize, usize, u32, u64)> = sequences
+        .iter()
+        .map(|s| {
+            let offset_value = s.offset as u64 + 3;
+            (
+                code_for(&LL_BASE, s.literal_length),
+                code_for(&ML_BASE, s.match_length),
+                highest_bit(offset_value as u32),
+                offset_value,
+            )
+        })
+        .collect();
+
+    let mut writer = BitWriter::new();
+    let add_extra_bits = |writer: &mut BitWriter, s: &Sequence, &(ll, ml, of, offset_value): &(usize, usize, u32, u64)| {
+        writer.add((s.literal_length - LL_BASE[ll]) as u64, LL_BITS[ll] as u32);
+        writer.add((s.match_length - ML_BASE[ml]) as u64, ML_BITS[ml] as u32);
+        writer.add(offset_value - (1u64 << of), of);
+    };
+
+    // Sequences are written last to first so that the decoder reads them in order.
+    let last = &codes[n - 1];
+    let mut ll_state = ll_encoder.init_state(last.0);
+    let mut ml_state = ml_encoder.init_state(last.1);
+    let mut of_state = of_encoder.init_state(last.2 as usize);
+    add_extra_bits(&mut writer, &sequences[n - 1], last);
+    for i in (0..n - 1).rev() {
+        let c = &codes[i];
+        of_encoder.encode(&mut writer, &mut of_state, c.2 as usize);
+        ml_encoder.encode(&mut writer, &mut ml_state, c.1);
+        ll_encoder.encode(&mut writer, &mut ll_state, c.0);
+        add_extra_bits(&mut writer, &sequences[i], c);
+    }
+    ml_encoder.flush(&mut writer, ml_state);
+    of_encoder.flush(&mut writer, of_state);
+    ll_encoder.flush(&mut writer, ll_state);
+    dst.extend_from_slice(&writer.finish());
+}
+
+/// Build the compressed representation of `input[start..end]`, where earlier input
+/// may be referenced by matches.
+fn compress_block(input: &[u8], start: usize, end: usize, finder: &mut MatchFinder) -> Vec<u8> {
+    let mut literals = Vec::new();
+    let mut sequences = Vec::new();
+    let mut literal_start = start;
+    let mut pos = start;
+    while pos < end {
+        match finder.find(input, pos, end) {
+            Some((mut offset, mut length)) => {
+                finder.insert(input, pos);
+                while finder.lazy {
+                    match finder.find(input, pos + 1, end) {
+                        Some((o, l)) if l > length => {
+                            pos += 1;
+                            finder.insert(input, pos);
+                            (offset, length) = (o, l);
+                        }
+                        _ => break,
+                    }
+                }
+                literals.extend_from_slice(&input[literal_start..pos]);
+                sequences.push(Sequence {
+                    literal_length: (pos - literal_start) as u32,
+                    match_length: length as u32,
+                    offset: offset as u32,
+                });
+                for p in pos + 1..pos + length {
+                    finder.insert(input, p);
+                }
+                pos += length;
+                literal_start = pos;
+            }
+            None => {
+                finder.insert(input, pos);
+                pos += 1;
+            }
+        }
+    }
+    literals.extend_from_slice(&input[literal_start..end]);
+
+    let mut block = Vec::new();
+    if !literals.is_empty() && literals.iter().all(|&b| b == literals[0]) {
+        write_literals_header(&mut block, literals.len(), LITERALS_RLE);
+        block.push(literals[0]);
+    } else {
+        match compress_literals(&literals) {
+            Some(section) if section.len() < literals.len() => block.extend_from_slice(&section),
+            _ => {
+                write_literals_header(&mut block, literals.len(), LITERALS_RAW);
+                block.extend_from_slice(&literals);
+            }
+        }
+    }
+    encode_sequences(&mut block, &sequences);
+    block
+}
+
+fn write_block_header(dst: &mut Vec<u8>, last: bool, block_type: u32, size: usize) {
+    let header = last as u32 | (block_type << 1) | ((size as u32) << 3);
+    dst.extend_from_slice(&header.to_le_bytes()[..3]);
+}
+
+/// Compress `input` into a single Zstandard frame. Higher levels search harder for
+/// matches; levels at or below 1 favour speed.
+pub fn compress(input: &[u8], level: i32) -> Vec<u8> { compress_with_dict(input, level, &[]) }
+
+/// Like `compress()`, but matches may also refer to `dict`, a raw content dictionary
+/// that `decompress_with_dict()` must be given back. Small inputs that resemble the
+/// dictionary compress much better than on their own. An empty `dict` is no
+/// dictionary.
+pub fn compress_with_dict(input: &[u8], level: i32, dict: &[u8]) -> Vec<u8> {
+    let mut dst = Vec::with_capacity(input.len() / 2 + 16);
+    dst.extend_from_slice(&MAGIC_NUMBER.to_le_bytes());
+
+    // Single segment frame with the content size and no checksum.
+    let size = input.len() as u64;
+    let (fcs_flag, fcs): (u8, Vec<u8>) = if size < 256 {
+        (0, vec![size as u8])
+    } else if size < 65536 + 256 {
+        (1, ((size - 256) as u16).to_le_bytes().to_vec())
+    } else if size <= u32::MAX as u64 {
+        (2, (size as u32).to_le_bytes().to_vec())
+    } else {
+        (3, size.to_le_bytes().to_vec())
+    };
+    let dict_id_flag = if dict.is_empty() { 0 } else { 3 };
+    dst.push((fcs_flag << 6) | 0x20 | dict_id_flag);
+    if !dict.is_empty() {
+        dst.extend_from_slice(&dictionary_id(dict).to_le_bytes());
+    }
+    dst.extend_from_slice(&fcs);
+
+    if input.is_empty() {
+        write_block_header(&mut dst, true, BLOCK_RAW, 0);
+        return dst;
+    }
+
+    // Matches are found in the dictionary followed by the input.
+    let mut buf = Vec::new();
+    let input = match dict.is_empty() {
+        true => input,
+        false => {
+            buf.reserve(dict.len() + input.len());
+            buf.extend_from_slice(dict);
+            buf.extend_from_slice(input);
+            &buf[..]
+        }
+    };
+    let mut finder = MatchFinder::new(input.len(), level);
+    for p in 0..dict.len() {
+        finder.insert(input, p);
+    }
+    let mut start = dict.len();
+    while start < input.len() {
+        let end = (start + MAX_BLOCK_SIZE).min(input.len());
+        let last = end == input.len();
+        let raw = &input[start..end];
+        if raw.iter().all(|&b| b == raw[0]) {
+            write_block_header(&mut dst, last, BLOCK_RLE, raw.len());
+            dst.push(raw[0]);
+            for p in start..end {
+                finder.insert(input, p);
+            }
+        } else {
+            let block = compress_block(input, start, end, &mut finder);
+            if block.len() < raw.len() {
+                write_block_header(&mut dst, last, BLOCK_COMPRESSED, block.len());
+                dst.extend_from_slice(&block);
+            } else {
+                write_block_header(&mut dst, last, BLOCK_RAW, raw.len());
+                dst.extend_from_slice(raw);
+            }
+        }
+        start = end;
+    }
+    dst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{leveldb::result::ErrorType, util::random::Random};
+
+    fn compressible(rnd: &Random, len: usize) -> Vec<u8> {
+        // Words drawn from a small vocabulary compress well but not trivially.
+        let words: [&[u8]; 8] = [b"alpha ", b"beta ", b"gamma ", b"delta ", b"key", b"value", b"0123", b"\n"];
+        let mut data = Vec::new();
+        while data.len() < len {
+            if rnd.one_in(10) {
+                data.push(rnd.uniform(256) as u8);
+            } else {
+                data.extend_from_slice(words[rnd.uniform(8) as usize]);
+            }
+        }
+        data.truncate(len);
+        data
+    }
+
+    #[test]
+    fn predefined_distributions() {
+        for (norm, log) in [
+            (&LL_DEFAULT[..], LL_DEFAULT_LOG),
+            (&ML_DEFAULT[..], ML_DEFAULT_LOG),
+            (&OF_DEFAULT[..], OF_DEFAULT_LOG),
+        ] {
+            assert!(FseTable::from_distribution(norm, log).is_ok());
+        }
+        assert_eq!(code_for(&LL_BASE, 15), 15);
+        assert_eq!(code_for(&LL_BASE, 17), 16);
+        assert_eq!(code_for(&LL_BASE, 100_000), 35);
+        assert_eq!(code_for(&ML_BASE, 3), 0);
+        assert_eq!(code_for(&ML_BASE, 130), 42);
+        assert_eq!(code_for(&ML_BASE, 131), 43);
+    }
+
+    #[test]
+    fn round_trip() {
+        let rnd = Random::new(301);
+        let mut inputs: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"a".to_vec(),
+            vec![b'x'; 1000],
+            b"abcabcabcabcabcabcabcabcabcabcabcabc".to_vec(),
+            (0..=255u8).collect(),
+        ];
+        for len in [100, 4096, 50_000, 300_000] {
+            inputs.push(compressible(&rnd, len));
+            inputs.push((0..len).map(|_| rnd.uniform(256) as u8).collect());
+        }
+        for input in inputs.iter() {
+            for level in [-5, 1, 3, 9, 19] {
+                let compressed = compress(input, level);
+                assert_eq!(&decompress(&compressed).unwrap(), input, "len {} level {}", input.len(), level);
+            }
+        }
+
+        let text = compressible(&rnd, 4096);
+        let fast = compress(&text, 1).len();
+        let strong = compress(&text, 19).len();
+        assert!(strong <= fast);
+        assert!(fast < text.len() / 2);
+    }
+
+    #[test]
+    fn compression_ratio() {
+        let rnd = Random::new(7);
+        let text = compressible(&rnd, 65536);
+        let lz4 = crate::util::lz4::compress(&text).len();
+        let sizes: Vec<usize> = [1, 3, 9, 19].iter().map(|&level| compress(&text, level).len()).collect();
+        assert!(sizes[1] < lz4 * 4 / 5, "{:?} {}", sizes, lz4);
+        assert!(sizes.windows(2).all(|w| w[1] <= w[0]), "{:?}", sizes);
+        assert!(sizes[3] < sizes[0] * 4 / 5, "{:?}", sizes);
+
+        // Few repeats, but a skewed byte distribution that only entropy coding exploits
+        let skewed: Vec<u8> = (0..65536).map(|_| b'a' + rnd.skewed(4) as u8).collect();
+        let lz4 = crate::util::lz4::compress(&skewed).len();
+        let size = compress(&skewed, 3).len();
+        assert!(size < lz4 && size < skewed.len() * 2 / 3, "{} {}", size, lz4);
+        assert_eq!(decompress(&compress(&skewed, 3)).unwrap(), skewed);
+    }
+
+    #[test]
+    fn huffman_descriptions() {
+        let rnd = Random::new(99);
+        let mut cases: Vec<Vec<u32>> = vec![
+            vec![1, 1],
+            vec![0, 5, 0, 0, 7, 1000],
+            (0..100).map(|_| rnd.skewed(10)).collect(),
+            (0..256).map(|_| rnd.skewed(12)).collect(),
+            (0..256).map(|s| if s % 3 == 0 { 0 } else { 1 << (s % 11) }).collect(),
+        ];
+        // Very unequal counts whose tree needs to be flattened.
+        cases.push((0..40).map(|s| 1 << (s % 31)).collect());
+        for counts in cases.iter() {
+            let lengths = huffman_code_lengths(counts);
+            assert!(lengths.iter().all(|&l| l <= HUFFMAN_MAX_BITS));
+            assert!(lengths.iter().zip(counts).all(|(&l, &c)| (l == 0) == (c == 0)));
+            let encoder = HuffmanEncoder::new(lengths);
+            let mut description = Vec::new();
+            assert!(encoder.write_description(&mut description));
+            let (table, n) = HuffmanTable::read_description(&description).unwrap();
+            assert_eq!(n, description.len());
+            let literals: Vec<u8> = (0..counts.len()).filter(|&s| counts[s] > 0).map(|s| s as u8).collect();
+            let mut decoded = Vec::new();
+            table.decode_stream(&encoder.encode_stream(&literals), literals.len(), &mut decoded).unwrap();
+            assert_eq!(decoded, literals);
+        }
+        // 255 equal weights fit neither form of description
+        let encoder = HuffmanEncoder::new(huffman_code_lengths(&[1; 256]));
+        assert!(!encoder.write_description(&mut Vec::new()));
+    }
+
+    #[test]
+    fn dictionary() {
+        let rnd = Random::new(42);
+        let dict = compressible(&rnd, 8192);
+        // Small inputs that share content with the dictionary
+        let input: Vec<u8> = dict[1000..1200].iter().chain(&dict[5000..5100]).copied().collect();
+        let plain = compress(&input, 3);
+        let with_dict = compress_with_dict(&input, 3, &dict);
+        assert!(with_dict.len() < plain.len() / 4, "{} {}", with_dict.len(), plain.len());
+        assert_eq!(decompress_with_dict(&with_dict, &dict).unwrap(), input);
+
+        // Frames name the dictionary they need
+        assert_eq!(decompress(&with_dict).unwrap_err().ty(), ErrorType::NotSupported);
+        assert!(decompress_with_dict(&with_dict, &dict[1..]).unwrap_err().is_corruption());
+        assert_eq!(decompress_with_dict(&plain, &dict).unwrap(), input);
+        assert_eq!(decompress_with_dict(&compress_with_dict(&[], 3, &dict), &dict).unwrap(), b"");
+    }
+
+    #[test]
+    fn decodes_reference_frames() {
+        // Produced by the reference implementation: `zstd -19 --no-check` of the
+        // text below, which uses Huffman-coded literals and FSE-coded sequences.
+        let text = b"Hello hefestodb! Hello hefestodb! Zstandard blocks, please. \
+                     Zstandard blocks, please. The quick brown fox jumps over the lazy dog.\n";
+        assert_eq!(decompress(REFERENCE_FRAME).unwrap(), &text[..]);
+    }
+
+    #[test]
+    fn rejects_corruption() {
+        let rnd = Random::new(17);
+        let input = compressible(&rnd, 10_000);
+        let compressed = compress(&input, 3);
+        assert!(decompress(&[]).is_err());
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+        assert!(decompress(&compressed[1..]).is_err());
+        let mut skippable = SKIPPABLE_MAGIC.to_le_bytes().to_vec();
+        skippable.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decompress(&skippable).unwrap_err().is_corruption());
+        // Flipping bits must never panic, whatever the result.
+        for i in 4..compressed.len() {
+            let mut damaged = compressed.clone();
+            damaged[i] ^= 1 << (i % 8);
+            let _ = decompress(&damaged);
+        }
+    }
+
+    const REFERENCE_FRAME: &[u8] = &[
+        0x28, 0xb5, 0x2f, 0xfd, 0x20, 0x83, 0xbd, 0x02, 0x00, 0x72, 0x05, 0x13, 0x18, 0x80, 0xab, 0x0e,
+        0x40, 0xc2, 0x52, 0xb5, 0x21, 0x62, 0xa2, 0x81, 0xb1, 0xb2, 0xec, 0xf2, 0x68, 0x32, 0x78, 0x4a,
+        0x67, 0x89, 0x26, 0x75, 0x02, 0x40, 0x32, 0xb8, 0x1d, 0x8a, 0xd4, 0x5e, 0xdf, 0xbc, 0xd4, 0x86,
+        0xdf, 0x4a, 0xc2, 0x20, 0x7f, 0xd8, 0x3d, 0x3a, 0x2c, 0xd7, 0x21, 0x3b, 0x98, 0xe5, 0xfa, 0x88,
+        0xa4, 0xad, 0x74, 0x95, 0x0b, 0x0d, 0x19, 0xef, 0x7a, 0x93, 0xaa, 0x45, 0x35, 0xbb, 0x78, 0xb0,
+        0x1a, 0xcf, 0x56, 0xa7, 0xef, 0xbc, 0xab, 0x43, 0x02, 0x00, 0xf5, 0x0c, 0x47, 0x9a, 0x27, 0x18,
+    ];
+}